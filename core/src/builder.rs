@@ -0,0 +1,224 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::dns_class::DnsClass;
+use crate::header::Opcode;
+use crate::packet::DnsPacket;
+use crate::query_type::QueryType;
+use crate::question::DnsQuestion;
+use crate::record::DnsRecord;
+use crate::result_code::ResultCode;
+
+// Names are kept without the root's trailing dot
+fn name(domain: &str) -> String {
+    domain.trim_end_matches('.').to_string()
+}
+
+// Shorthands for the records most often built by hand, `DnsRecord::a("example.com", addr, 300)`
+impl DnsRecord {
+    pub fn a(domain: &str, addr: Ipv4Addr, ttl: u32) -> DnsRecord {
+        DnsRecord::A { domain: name(domain), addr, ttl, class: DnsClass::IN }
+    }
+
+    pub fn aaaa(domain: &str, addr: Ipv6Addr, ttl: u32) -> DnsRecord {
+        DnsRecord::AAAA { domain: name(domain), addr, ttl, class: DnsClass::IN }
+    }
+
+    pub fn ns(domain: &str, ns: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::NS { domain: name(domain), ns: name(ns), ttl, class: DnsClass::IN }
+    }
+
+    pub fn cname(domain: &str, cname: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::CNAME { domain: name(domain), cname: name(cname), ttl, class: DnsClass::IN }
+    }
+
+    pub fn ptr(domain: &str, host: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::PTR { domain: name(domain), host: name(host), ttl, class: DnsClass::IN }
+    }
+
+    pub fn mx(domain: &str, preference: u16, exchange: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::MX { domain: name(domain), preference, exchange: name(exchange), ttl, class: DnsClass::IN }
+    }
+
+    pub fn srv(domain: &str, priority: u16, weight: u16, port: u16, target: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::SRV { domain: name(domain), priority, weight, port, target: name(target), ttl, class: DnsClass::IN }
+    }
+
+    pub fn dname(domain: &str, target: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::DNAME { domain: name(domain), target: name(target), ttl, class: DnsClass::IN }
+    }
+
+    pub fn hinfo(domain: &str, cpu: &str, os: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::HINFO { domain: name(domain), cpu: cpu.as_bytes().to_vec(), os: os.as_bytes().to_vec(), ttl, class: DnsClass::IN }
+    }
+
+    pub fn uri(domain: &str, priority: u16, weight: u16, target: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::URI { domain: name(domain), priority, weight, target: target.as_bytes().to_vec(), ttl, class: DnsClass::IN }
+    }
+
+    // Text over 255 bytes is split over several character strings
+    pub fn txt(domain: &str, text: &str, ttl: u32) -> DnsRecord {
+        let data = match text.is_empty() {
+            true => alloc::vec![Vec::new()],
+            false => text.as_bytes().chunks(255).map(<[u8]>::to_vec).collect(),
+        };
+        DnsRecord::TXT { domain: name(domain), data, ttl, class: DnsClass::IN }
+    }
+}
+
+/**
+Builds a `DnsPacket` one call at a time, starting from a query or a response preset. The header's
+section counts are filled in from the records by `build`, so they can't disagree with them, e.g.
+`DnsPacketBuilder::response_to(&query).answer(DnsRecord::a("example.com", addr, 300)).build()`.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct DnsPacketBuilder {
+    packet: DnsPacket,
+}
+
+impl DnsPacketBuilder {
+    // An empty message with ID 0
+    pub fn new() -> DnsPacketBuilder {
+        DnsPacketBuilder::default()
+    }
+
+    // A recursive query for `name` in class IN
+    pub fn query(domain: &str, qtype: QueryType) -> DnsPacketBuilder {
+        DnsPacketBuilder::new().recursion_desired(true).question(DnsQuestion::new(name(domain), qtype))
+    }
+
+    // An empty NOERROR response to `request`, with its ID, opcode, RD and CD bits and questions
+    pub fn response_to(request: &DnsPacket) -> DnsPacketBuilder {
+        let mut builder = DnsPacketBuilder::new();
+        let header = &mut builder.packet.header;
+        header.id = request.header.id;
+        header.response = true;
+        header.opcode = request.header.opcode;
+        header.recursion_desired = request.header.recursion_desired;
+        header.checking_disabled = request.header.checking_disabled;
+        builder.packet.questions = request.questions.clone();
+        builder
+    }
+
+    pub fn id(mut self, id: u16) -> DnsPacketBuilder {
+        self.packet.header.id = id;
+        self
+    }
+
+    pub fn opcode(mut self, opcode: Opcode) -> DnsPacketBuilder {
+        self.packet.header.opcode = opcode;
+        self
+    }
+
+    pub fn rcode(mut self, rcode: ResultCode) -> DnsPacketBuilder {
+        self.packet.header.rescode = rcode;
+        self
+    }
+
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> DnsPacketBuilder {
+        self.packet.header.recursion_desired = recursion_desired;
+        self
+    }
+
+    pub fn recursion_available(mut self, recursion_available: bool) -> DnsPacketBuilder {
+        self.packet.header.recursion_available = recursion_available;
+        self
+    }
+
+    pub fn authoritative(mut self, authoritative: bool) -> DnsPacketBuilder {
+        self.packet.header.authoritative_answer = authoritative;
+        self
+    }
+
+    pub fn truncated(mut self, truncated: bool) -> DnsPacketBuilder {
+        self.packet.header.truncated_message = truncated;
+        self
+    }
+
+    pub fn checking_disabled(mut self, checking_disabled: bool) -> DnsPacketBuilder {
+        self.packet.header.checking_disabled = checking_disabled;
+        self
+    }
+
+    pub fn authed_data(mut self, authed_data: bool) -> DnsPacketBuilder {
+        self.packet.header.authed_data = authed_data;
+        self
+    }
+
+    pub fn question(mut self, question: DnsQuestion) -> DnsPacketBuilder {
+        self.packet.questions.push(question);
+        self
+    }
+
+    pub fn answer(mut self, record: DnsRecord) -> DnsPacketBuilder {
+        self.packet.answers.push(record);
+        self
+    }
+
+    pub fn answers(mut self, records: impl IntoIterator<Item = DnsRecord>) -> DnsPacketBuilder {
+        self.packet.answers.extend(records);
+        self
+    }
+
+    pub fn authority(mut self, record: DnsRecord) -> DnsPacketBuilder {
+        self.packet.authorities.push(record);
+        self
+    }
+
+    pub fn additional(mut self, record: DnsRecord) -> DnsPacketBuilder {
+        self.packet.resources.push(record);
+        self
+    }
+
+    pub fn build(mut self) -> DnsPacket {
+        let packet = &mut self.packet;
+        packet.header.questions = packet.questions.len() as u16;
+        packet.header.answers = packet.answers.len() as u16;
+        packet.header.authoritative_entries = packet.authorities.len() as u16;
+        packet.header.resource_entries = packet.resources.len() as u16;
+        self.packet
+    }
+}
+
+impl DnsPacket {
+    pub fn builder() -> DnsPacketBuilder {
+        DnsPacketBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::byte_buffer::ByteBuffer;
+
+    #[test]
+    fn test_counts_follow_records() {
+        let query = DnsPacketBuilder::query("example.com.", QueryType::A).id(4321).build();
+        assert_eq!(query.header.questions, 1);
+        assert!(query.header.recursion_desired && !query.header.response);
+        assert_eq!(query.questions[0].name, "example.com");
+
+        let response = DnsPacketBuilder::response_to(&query)
+            .recursion_available(true)
+            .answers([DnsRecord::cname("example.com", "cdn.example.net.", 300), DnsRecord::a("cdn.example.net", Ipv4Addr::new(192, 0, 2, 1), 60)])
+            .authority(DnsRecord::ns("example.net", "ns1.example.net", 3600))
+            .build();
+        assert_eq!(response.header.id, 4321);
+        assert!(response.header.response && response.header.recursion_desired);
+        assert_eq!((response.header.questions, response.header.answers, response.header.authoritative_entries, response.header.resource_entries), (1, 2, 1, 0));
+
+        // What is built reads back the same, counts included
+        let mut buffer = ByteBuffer::new();
+        response.write(&mut buffer).unwrap();
+        assert_eq!(DnsPacket::from_bytes(&buffer.buffer[..buffer.position()]).unwrap(), response);
+    }
+
+    #[test]
+    fn test_txt_splits_long_text() {
+        let text = "x".repeat(300);
+        let DnsRecord::TXT { data, .. } = DnsRecord::txt("example.com", &text, 60) else { unreachable!() };
+        assert_eq!(data.iter().map(Vec::len).collect::<Vec<_>>(), [255, 45]);
+        assert_eq!(DnsRecord::txt("example.com", "", 60), DnsRecord::TXT { domain: "example.com".to_string(), data: alloc::vec![Vec::new()], ttl: 60, class: DnsClass::IN });
+    }
+}
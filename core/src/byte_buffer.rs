@@ -0,0 +1,486 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::error::{DnsError, Result, MAX_LABEL_LEN, MAX_NAME_LEN};
+use crate::name::{parse_labels, push_label};
+
+pub struct ByteBuffer {
+    pub buffer: [u8; 512],
+    pub position: usize,
+    pub strict: bool, // reject compression pointers that don't point backwards, see `ParseMode`
+}
+
+impl Default for ByteBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteBuffer {
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; 512],
+            position: 0,
+            strict: false,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn step(&mut self, steps: usize) -> Result<()>{
+        self.position += steps;
+
+        Ok(())
+    }
+
+    pub fn seek(&mut self, position: usize) -> Result<()> {
+        self.position = position;
+
+        Ok(())
+    }
+
+    pub fn read(&mut self) -> Result<u8> {
+        if self.position >= 512 {
+            return Err(DnsError::BufferOverflow);
+        }
+
+        let res = self.buffer[self.position];
+        self.step(1)?;
+        Ok(res)
+    }
+
+    pub fn get(&self, position: usize) -> Result<u8> {
+        if position >= 512 {
+            return Err(DnsError::BufferOverflow);
+        }
+
+        Ok(self.buffer[position])
+    }
+
+    pub fn get_range_(&self, start: usize, end: usize) -> Result<&[u8]> {
+        if start >= 512 || end > 512 {
+            return Err(DnsError::BufferOverflow);
+        }
+
+        Ok(&self.buffer[start..end])
+    }
+
+    pub fn get_range(&self, start: usize, len: usize) -> Result<&[u8]> {
+        self.get_range_(start, start+len)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let res = ((self.read()? as u16) << 8) | (self.read()? as u16);
+        Ok(res)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let res = ((self.read()? as u32) << 24)
+        | ((self.read()? as u32) << 16)
+        | ((self.read()? as u32) << 8)
+        | (self.read()? as u32);
+        Ok(res)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        let bytes = self.get_range(self.position, len)?.to_vec();
+        self.step(len)?;
+        Ok(bytes)
+    }
+
+    // Record owner names and targets are compared everywhere, so they are lowercased on the way in
+    pub fn read_qname(&mut self, out : &mut String) -> Result<()> {
+        self.read_qname_with_case(out, false)
+    }
+
+    // Keeps the octets as sent, for question names that have to be echoed back verbatim
+    pub fn read_qname_preserve_case(&mut self, out : &mut String) -> Result<()> {
+        self.read_qname_with_case(out, true)
+    }
+
+    fn read_qname_with_case(&mut self, out : &mut String, preserve_case: bool) -> Result<()> {
+        let mut position = self.position;
+        let mut jump = false;
+        let max_jumps = 5;
+        let mut jumps = 0;
+        let mut delim = "";
+        let mut name_len = 0;
+    
+        loop {
+            let len = self.get(position)?;
+            if jumps > max_jumps {
+                return Err(DnsError::TooManyJumps(max_jumps));
+            }
+
+            if len & 0xC0 == 0xC0 {
+                if !jump {
+                    self.seek(position + 2)?;
+                }
+
+                let new_jump = ((len as u16) ^ 0xC0) << 8 | self.get(position+1)? as u16;
+                let offset = new_jump as usize;
+                // Encoders only ever point back at a name they already wrote
+                if self.strict && offset >= position {
+                    return Err(DnsError::BadPointer(offset));
+                }
+                position = offset;
+
+                jump = true;
+                jumps += 1;
+            } else {
+                position += 1;
+                name_len += len as usize + 1;
+                if name_len > MAX_NAME_LEN {
+                    return Err(DnsError::NameTooLong(name_len));
+                }
+                if len == 0 {
+                    break;
+                }
+                if len as usize > MAX_LABEL_LEN {
+                    return Err(DnsError::LabelTooLong(len as usize));
+                }
+                out.push_str(delim);
+                let label = self.get_range(position, len as usize)?;
+                if preserve_case {
+                    push_label(out, label);
+                } else {
+                    push_label(out, &label.to_ascii_lowercase());
+                }
+                delim = ".";
+                position += len as usize;
+            }
+        };
+
+        if !jump {
+            self.seek(position)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, val: u8) -> Result<()> {
+        if self.position >= 512 {
+            return Err(DnsError::BufferOverflow);
+        }
+
+        self.buffer[self.position] = val;
+        self.step(1)?;
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, val: u8) -> Result<()> {
+        self.write(val)
+    }
+
+    pub fn write_u16(&mut self, val: u16) -> Result<()> {
+        self.write((val >> 8) as u8)?;
+        self.write((val & 0xFF) as u8)?; // & 0xFF extracts the last 8 bits
+        Ok(())
+    }
+
+    pub fn write_u32(&mut self, val: u32) -> Result<()> {
+        self.write(((val >> 24) & 0xFF) as u8)?;
+        self.write(((val >> 16) & 0xFF) as u8)?;
+        self.write(((val >> 8) & 0xFF) as u8)?;
+        self.write((val & 0xFF) as u8)?;
+        Ok(())
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        for &byte in bytes {
+            self.write(byte)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_qname(&mut self, qname: &str) -> Result<()> {
+        // Validate up front so an invalid name never leaves a partial label in the buffer
+        let labels = parse_labels(qname)?;
+        let mut name_len = 1;
+        for label in &labels {
+            if label.len() > MAX_LABEL_LEN {
+                return Err(DnsError::LabelTooLong(label.len()));
+            }
+            name_len += label.len() + 1;
+        }
+        if name_len > MAX_NAME_LEN {
+            return Err(DnsError::NameTooLong(name_len));
+        }
+
+        for label in labels {
+            self.write_u8(label.len() as u8)?;
+            self.write_bytes(&label)?;
+        }
+        self.write_u8(0)?;
+        Ok(())
+    }
+
+    pub fn set(&mut self, position: usize, val: u8) -> Result<()> {
+        if position >= 512 {
+            return Err(DnsError::BufferOverflow);
+        }
+
+        self.buffer[position] = val;
+        Ok(())
+    }
+
+    pub fn set_u16(&mut self, position: usize, val: u16) -> Result<()> {
+        self.set(position, (val >> 8) as u8)?;
+        self.set(position+1, (val & 0xFF) as u8)?;
+        Ok(())
+    }
+
+    pub fn from_buffer(buffer: &[u8]) -> Self {
+        let mut new_buffer = ByteBuffer::new();
+        for (i, &val) in buffer.iter().enumerate() {
+            new_buffer.set(i, val).unwrap();
+        }
+        new_buffer.seek(0).unwrap();
+        new_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_byte_buffer() {
+        let buffer = ByteBuffer::new();
+        assert_eq!(buffer.position, 0);
+        assert_eq!(buffer.buffer, [0; 512]);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut buffer = ByteBuffer::new();
+        buffer.step(5).unwrap();
+        assert_eq!(buffer.position, 5);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut buffer = ByteBuffer::new();
+        buffer.seek(10).unwrap();
+        assert_eq!(buffer.position, 10);
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write(42).unwrap();
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_get() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write(42).unwrap();
+        assert_eq!(buffer.get(0).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_get_range() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write(42).unwrap();
+        buffer.write(43).unwrap();
+        assert_eq!(buffer.get_range(0, 2).unwrap(), &[42, 43]);
+    }
+
+    #[test]
+    fn test_read_u16() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_u16(0x1234).unwrap();
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read_u16().unwrap(), 0x1234);
+    }
+
+    #[test]
+    fn test_read_u32() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_u32(0x12345678).unwrap();
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read_u32().unwrap(), 0x12345678);
+    }
+
+    #[test]
+    fn test_read_qname() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        buffer.seek(0).unwrap();
+        let mut qname = String::new();
+        buffer.read_qname(&mut qname).unwrap();
+        assert_eq!(qname, "example.com");
+    }
+
+    #[test]
+    fn test_read_write_bytes() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_bytes(&[1, 2, 3]).unwrap();
+        assert_eq!(buffer.position(), 3);
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read_bytes(3).unwrap(), vec![1, 2, 3]);
+        assert_eq!(buffer.position(), 3);
+
+        buffer.seek(510).unwrap();
+        assert_eq!(buffer.read_bytes(2).unwrap(), vec![0, 0]);
+        assert!(buffer.read_bytes(1).is_err());
+    }
+
+    #[test]
+    fn test_write_u8() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_u8(0x12).unwrap();
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read().unwrap(), 0x12);
+    }
+
+    #[test]
+    fn test_write_u16() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_u16(0x1234).unwrap();
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read_u16().unwrap(), 0x1234);
+    }
+
+    #[test]
+    fn test_write_u32() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_u32(0x12345678).unwrap();
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read_u32().unwrap(), 0x12345678);
+    }
+
+    #[test]
+    fn test_write_qname() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        buffer.seek(0).unwrap();
+        let mut qname = String::new();
+        buffer.read_qname(&mut qname).unwrap();
+        assert_eq!(qname, "example.com");
+    }
+
+    #[test]
+    fn test_write_qname_root_and_trailing_dot() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("").unwrap();
+        assert_eq!(buffer.position(), 1);
+        buffer.write_qname(".").unwrap();
+        assert_eq!(buffer.position(), 2);
+
+        buffer.write_qname("example.com.").unwrap();
+        buffer.seek(2).unwrap();
+        let mut qname = String::new();
+        buffer.read_qname(&mut qname).unwrap();
+        assert_eq!(qname, "example.com");
+        assert_eq!(buffer.position(), 15);
+    }
+
+    #[test]
+    fn test_qname_bytes_round_trip() {
+        // Labels are bytes, not text: what isn't printable ASCII is escaped rather than mangled
+        let mut buffer = ByteBuffer::new();
+        buffer.write_u8(4).unwrap();
+        buffer.write_bytes(&[b'C', 0xC3, 0xA9, b'.']).unwrap();
+        buffer.write_u8(0).unwrap();
+        buffer.seek(0).unwrap();
+        let mut name = String::new();
+        buffer.read_qname_preserve_case(&mut name).unwrap();
+        assert_eq!(name, "C\\195\\169\\046");
+        let mut lowered = String::new();
+        buffer.seek(0).unwrap();
+        buffer.read_qname(&mut lowered).unwrap();
+        assert_eq!(lowered, "c\\195\\169\\046");
+
+        let mut written = ByteBuffer::new();
+        written.write_qname(&name).unwrap();
+        assert_eq!(written.buffer[..7], buffer.buffer[..7]);
+        // Unescaped UTF-8 goes out as its bytes
+        let mut written = ByteBuffer::new();
+        written.write_qname("Cé\\.").unwrap();
+        assert_eq!(written.buffer[..7], buffer.buffer[..7]);
+    }
+
+    #[test]
+    fn test_write_qname_empty_label() {
+        let mut buffer = ByteBuffer::new();
+        assert_eq!(buffer.write_qname("a..b"), Err(DnsError::EmptyLabel));
+        assert_eq!(buffer.write_qname(".example.com"), Err(DnsError::EmptyLabel));
+        assert_eq!(buffer.write_qname("example.com.."), Err(DnsError::EmptyLabel));
+        assert_eq!(buffer.position(), 0);
+    }
+
+    #[test]
+    fn test_write_qname_label_too_long() {
+        let mut buffer = ByteBuffer::new();
+        let qname = format!("{}.com", "a".repeat(64));
+        let err = buffer.write_qname(&qname).unwrap_err();
+        assert_eq!(err, DnsError::LabelTooLong(64));
+        assert_eq!(buffer.position(), 0);
+    }
+
+    #[test]
+    fn test_write_qname_name_too_long() {
+        let mut buffer = ByteBuffer::new();
+        let label = "a".repeat(63);
+        let qname = format!("{0}.{0}.{0}.{0}", label);
+        let err = buffer.write_qname(&qname).unwrap_err();
+        assert_eq!(err, DnsError::NameTooLong(257));
+        assert_eq!(buffer.position(), 0);
+    }
+
+    #[test]
+    fn test_write_qname_max_lengths() {
+        let mut buffer = ByteBuffer::new();
+        let label = "a".repeat(63);
+        let qname = format!("{0}.{0}.{0}.{1}", label, "a".repeat(61));
+        buffer.write_qname(&qname).unwrap();
+        assert_eq!(buffer.position(), 255);
+        buffer.seek(0).unwrap();
+        let mut name = String::new();
+        buffer.read_qname(&mut name).unwrap();
+        assert_eq!(name, qname);
+    }
+
+    #[test]
+    fn test_read_qname_label_too_long() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_u8(64).unwrap();
+        buffer.seek(0).unwrap();
+        let mut name = String::new();
+        let err = buffer.read_qname(&mut name).unwrap_err();
+        assert_eq!(err, DnsError::LabelTooLong(64));
+    }
+
+    #[test]
+    fn test_read_qname_name_too_long() {
+        let mut buffer = ByteBuffer::new();
+        for _ in 0..5 {
+            buffer.write_u8(63).unwrap();
+            buffer.step(63).unwrap();
+        }
+        buffer.write_u8(0).unwrap();
+        buffer.seek(0).unwrap();
+        let mut name = String::new();
+        let err = buffer.read_qname(&mut name).unwrap_err();
+        assert_eq!(err, DnsError::NameTooLong(256));
+    }
+
+    #[test]
+    fn test_set() {
+        let mut buffer = ByteBuffer::new();
+        buffer.set(0, 42).unwrap();
+        assert_eq!(buffer.get(0).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_set_u16() {
+        let mut buffer = ByteBuffer::new();
+        buffer.set_u16(0, 0x1234).unwrap();
+        assert_eq!(buffer.read_u16().unwrap(), 0x1234);
+    }
+}
@@ -0,0 +1,70 @@
+use alloc::format;
+use alloc::string::String;
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy, PartialOrd, Ord, Default)]
+pub enum DnsClass {
+    UNKNOWN(u16),
+    #[default]
+    IN,   // 1
+    CH,   // 3
+    HS,   // 4
+    NONE, // 254
+    ANY,  // 255
+}
+
+impl DnsClass {
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            DnsClass::UNKNOWN(x) => x,
+            DnsClass::IN => 1,
+            DnsClass::CH => 3,
+            DnsClass::HS => 4,
+            DnsClass::NONE => 254,
+            DnsClass::ANY => 255,
+        }
+    }
+
+    pub fn from_num(num: u16) -> DnsClass {
+        match num {
+            1 => DnsClass::IN,
+            3 => DnsClass::CH,
+            4 => DnsClass::HS,
+            254 => DnsClass::NONE,
+            255 => DnsClass::ANY,
+            _ => DnsClass::UNKNOWN(num),
+        }
+    }
+
+    // The mnemonic, or CLASS1234 for classes without one (RFC 3597)
+    pub fn name(&self) -> String {
+        match self {
+            DnsClass::UNKNOWN(num) => format!("CLASS{}", num),
+            known => format!("{:?}", known),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DnsClass> {
+        let name = name.to_ascii_uppercase();
+        if let Some(num) = name.strip_prefix("CLASS") {
+            return num.parse().ok().map(DnsClass::from_num);
+        }
+        [DnsClass::IN, DnsClass::CH, DnsClass::HS, DnsClass::NONE, DnsClass::ANY].into_iter().find(|class| class.name() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for num in [1, 3, 4, 254, 255, 42] {
+            assert_eq!(DnsClass::from_num(num).to_num(), num);
+        }
+        assert_eq!(DnsClass::from_num(3), DnsClass::CH);
+        assert_eq!(DnsClass::from_name("ch"), Some(DnsClass::CH));
+        assert_eq!(DnsClass::from_name("CLASS42"), Some(DnsClass::UNKNOWN(42)));
+        assert_eq!(DnsClass::UNKNOWN(42).name(), "CLASS42");
+        assert_eq!(DnsClass::from_name("A"), None);
+    }
+}
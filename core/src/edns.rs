@@ -0,0 +1,74 @@
+use alloc::vec::Vec;
+
+use crate::record::DnsRecord;
+
+pub const OPTION_ECS: u16 = 8;
+pub const OPTION_COOKIE: u16 = 10;
+pub const OPTION_KEEPALIVE: u16 = 11;
+pub const OPTION_PADDING: u16 = 12;
+
+// The DO bit in the flags of an OPT record (RFC 3225)
+pub const FLAG_DO: u32 = 0x8000;
+
+// What we tell upstreams and clients we can take over UDP, our buffers hold 512 bytes
+pub const UDP_PAYLOAD_SIZE: u16 = 512;
+
+// One option from the data of an OPT record (RFC 6891 section 6.1.2)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl EdnsOption {
+    pub fn new(code: u16, data: Vec<u8>) -> EdnsOption {
+        EdnsOption { code, data }
+    }
+}
+
+pub fn parse_options(mut data: &[u8]) -> Option<Vec<EdnsOption>> {
+    let mut options = Vec::new();
+    while !data.is_empty() {
+        let code = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+        let len = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as usize;
+        options.push(EdnsOption::new(code, data.get(4..4 + len)?.to_vec()));
+        data = &data[4 + len..];
+    }
+    Some(options)
+}
+
+pub fn encode_options(options: &[EdnsOption]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for option in options {
+        data.extend_from_slice(&option.code.to_be_bytes());
+        data.extend_from_slice(&(option.data.len() as u16).to_be_bytes());
+        data.extend_from_slice(&option.data);
+    }
+    data
+}
+
+// An OPT record with just our payload size and the DO bit, for answers that have no options to relay
+pub fn opt_record(dnssec_ok: bool) -> DnsRecord {
+    DnsRecord::OPT {
+        udp_size: UDP_PAYLOAD_SIZE,
+        flags: if dnssec_ok { FLAG_DO } else { 0 },
+        options: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_round_trip() {
+        let options = vec![
+            EdnsOption::new(OPTION_COOKIE, vec![1, 2, 3, 4, 5, 6, 7, 8]),
+            EdnsOption::new(OPTION_PADDING, Vec::new()),
+        ];
+        let data = encode_options(&options);
+        assert_eq!(data.len(), 4 + 8 + 4);
+        assert_eq!(parse_options(&data), Some(options));
+        assert_eq!(parse_options(&data[..6]), None);
+    }
+}
@@ -0,0 +1,86 @@
+use core::{error, fmt};
+#[cfg(feature = "std")]
+use std::io;
+
+/**
+Errors raised while encoding or decoding DNS messages. With the `std` feature they convert into
+`std::io::Error` (kind `InvalidData`, `Other` for running out of buffer) so `?` works in code
+built on `io::Result`; use `DnsError::from_io` to get them back.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsError {
+    LabelTooLong(usize), // a single label exceeds 63 bytes
+    EmptyLabel,          // two dots in a row or a leading dot, only the root label is empty
+    NameTooLong(usize),  // the encoded name exceeds 255 bytes
+    BadPointer(usize),   // a compression pointer that doesn't point back into the message
+    RdataLength { expected: u16, actual: usize }, // RDLENGTH disagrees with the record data
+    CountMismatch,       // the message ends before the records its header counts
+    TrailingBytes(usize),
+    MessageTooLarge(usize), // more than the 512 bytes a plain DNS message may have
+    Malformed(&'static str), // record data that doesn't parse, e.g. an EDNS option list
+    TooManyJumps(usize), // a chain of compression pointers longer than any real name needs
+    BufferOverflow,      // reading or writing past the end of the buffer
+    UnassignedType(u16), // a record type number IANA hasn't assigned
+}
+
+pub const MAX_LABEL_LEN: usize = 63;
+pub const MAX_NAME_LEN: usize = 255;
+
+pub type Result<T> = core::result::Result<T, DnsError>;
+
+#[cfg(feature = "std")]
+impl DnsError {
+    pub fn from_io(err: &io::Error) -> Option<&DnsError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<DnsError>())
+    }
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::LabelTooLong(len) => write!(f, "Label of {} bytes exceeds the limit of {}", len, MAX_LABEL_LEN),
+            DnsError::EmptyLabel => write!(f, "Empty label in a name"),
+            DnsError::NameTooLong(len) => write!(f, "Name of {} bytes exceeds the limit of {}", len, MAX_NAME_LEN),
+            DnsError::BadPointer(offset) => write!(f, "Compression pointer to offset {} doesn't point backwards", offset),
+            DnsError::RdataLength { expected, actual } => write!(f, "Record data of {} bytes doesn't match its length of {}", actual, expected),
+            DnsError::CountMismatch => write!(f, "Message ends before the records its header counts"),
+            DnsError::TrailingBytes(len) => write!(f, "{} bytes of trailing data after the last record", len),
+            DnsError::MessageTooLarge(len) => write!(f, "Message of {} bytes is too large", len),
+            DnsError::Malformed(what) => write!(f, "Malformed {}", what),
+            DnsError::TooManyJumps(max) => write!(f, "Limit of {} jumps exceeded", max),
+            DnsError::BufferOverflow => write!(f, "Buffer overflow"),
+            DnsError::UnassignedType(num) => write!(f, "Record type {} is not assigned", num),
+        }
+    }
+}
+
+impl error::Error for DnsError {}
+
+#[cfg(feature = "std")]
+impl From<DnsError> for io::Error {
+    fn from(err: DnsError) -> io::Error {
+        let kind = match err {
+            DnsError::BufferOverflow | DnsError::TooManyJumps(_) => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_through_io_error() {
+        let err: io::Error = DnsError::LabelTooLong(64).into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(DnsError::from_io(&err), Some(&DnsError::LabelTooLong(64)));
+    }
+
+    #[test]
+    fn test_from_io_other_error() {
+        let err = io::Error::other("Buffer overflow");
+        assert_eq!(DnsError::from_io(&err), None);
+    }
+}
@@ -0,0 +1,275 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+use crate::byte_buffer::ByteBuffer;
+use crate::dns_class::DnsClass;
+use crate::header::{HeaderFlags, Opcode};
+use crate::packet::DnsPacket;
+use crate::presentation::{fqdn, record_data};
+use crate::query_type::QueryType;
+use crate::record::DnsRecord;
+use crate::result_code::ResultCode;
+
+// Compression pointers followed for one name before giving up, same as `read_qname`
+const MAX_JUMPS: usize = 5;
+
+// Bytes of hex on one line of the breakdown
+const BYTES_PER_LINE: usize = 16;
+
+fn rcode_name(rcode: u16) -> String {
+    match ResultCode::from_num(rcode) {
+        ResultCode::UNKNOWN(num) => format!("{}", num),
+        known => format!("{:?}", known),
+    }
+}
+
+fn flags(flags: HeaderFlags) -> String {
+    let bit = |flag: HeaderFlags| flags.contains(flag) as u8;
+    format!(
+        "flags QR={} OPCODE={} AA={} TC={} RD={} RA={} Z={} AD={} CD={} RCODE={}",
+        bit(HeaderFlags::QR), Opcode::from_num(flags.opcode()), bit(HeaderFlags::AA), bit(HeaderFlags::TC), bit(HeaderFlags::RD),
+        bit(HeaderFlags::RA), bit(HeaderFlags::Z), bit(HeaderFlags::AD), bit(HeaderFlags::CD), rcode_name(flags.rcode() as u16),
+    )
+}
+
+// Where a name on the wire ends and what it says, following compression pointers like the parser does
+struct WireName {
+    text: String,
+    len: usize,              // bytes of the name at its own offset, up to and including the first pointer
+    pointer: Option<usize>,  // where the first pointer sends the rest of the name
+}
+
+fn read_name(message: &[u8], start: usize) -> Result<WireName, String> {
+    let mut text = String::new();
+    let (mut pos, mut len, mut pointer, mut jumps) = (start, None, None, 0);
+    loop {
+        let &label = message.get(pos).ok_or("the name runs past the end of the message")?;
+        match label {
+            0 => {
+                len.get_or_insert_with(|| pos + 1 - start);
+                break;
+            }
+            0xC0..=0xFF => {
+                let &low = message.get(pos + 1).ok_or("the pointer runs past the end of the message")?;
+                let target = ((label as usize & 0x3F) << 8) | low as usize;
+                if target >= pos {
+                    return Err(format!("pointer at {:04x} to {:04x} doesn't point backwards", pos, target));
+                }
+                jumps += 1;
+                if jumps > MAX_JUMPS {
+                    return Err(format!("more than {} pointers in one name", MAX_JUMPS));
+                }
+                len.get_or_insert_with(|| pos + 2 - start);
+                pointer.get_or_insert(target);
+                pos = target;
+            }
+            0x40..=0xBF => return Err(format!("label type {:#04x} at {:04x} is not defined", label, pos)),
+            _ => {
+                let data = message.get(pos + 1..pos + 1 + label as usize).ok_or("the label runs past the end of the message")?;
+                if !text.is_empty() {
+                    text.push('.');
+                }
+                text.push_str(&String::from_utf8_lossy(data).to_ascii_lowercase());
+                pos += 1 + label as usize;
+            }
+        }
+    }
+    Ok(WireName { text, len: len.unwrap_or(0), pointer })
+}
+
+struct Explainer<'a> {
+    message: &'a [u8],
+    pos: usize,
+    out: String,
+}
+
+impl Explainer<'_> {
+    fn line(&mut self, offset: usize, len: usize, text: &str) {
+        let bytes = &self.message[offset..offset + len];
+        let mut chunks = bytes.chunks(BYTES_PER_LINE);
+        let first: String = chunks.next().unwrap_or_default().iter().map(|byte| format!("{:02x}", byte)).collect();
+        let _ = writeln!(self.out, "{:04x}  {:<32}  {}", offset, first, text);
+        for (i, chunk) in chunks.enumerate() {
+            let hex: String = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let _ = writeln!(self.out, "{:04x}  {}", offset + (i + 1) * BYTES_PER_LINE, hex);
+        }
+    }
+
+    fn field(&self, len: usize, what: &str) -> Result<u32, String> {
+        let bytes = self.message.get(self.pos..self.pos + len).ok_or_else(|| format!("the message ends before the {}", what))?;
+        let value = bytes.iter().fold(0u32, |value, &byte| (value << 8) | byte as u32);
+        Ok(value)
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, String> {
+        let value = self.field(2, what)? as u16;
+        self.line(self.pos, 2, &format!("{} {}", what, value));
+        self.pos += 2;
+        Ok(value)
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let name = read_name(self.message, self.pos)?;
+        let text = match name.pointer {
+            Some(target) => format!("name {} (pointer to {:04x})", fqdn(&name.text), target),
+            None => format!("name {}", fqdn(&name.text)),
+        };
+        self.line(self.pos, name.len, &text);
+        self.pos += name.len;
+        Ok(name.text)
+    }
+
+    fn header(&mut self) -> Result<[u16; 4], String> {
+        self.u16("ID")?;
+        let value = HeaderFlags::from_bits_retain(self.field(2, "flags")? as u16);
+        self.line(self.pos, 2, &flags(value));
+        self.pos += 2;
+        Ok([self.u16("QDCOUNT")?, self.u16("ANCOUNT")?, self.u16("NSCOUNT")?, self.u16("ARCOUNT")?])
+    }
+
+    fn question(&mut self) -> Result<(), String> {
+        self.name()?;
+        let qtype = self.field(2, "type")? as u16;
+        self.line(self.pos, 2, &format!("type {}", QueryType::from_num(qtype).name()));
+        self.pos += 2;
+        let class = self.field(2, "class")? as u16;
+        self.line(self.pos, 2, &format!("class {:?}", DnsClass::from_num(class)));
+        self.pos += 2;
+        Ok(())
+    }
+
+    fn record(&mut self) -> Result<(), String> {
+        let start = self.pos;
+        self.name()?;
+        let qtype = QueryType::from_num(self.field(2, "type")? as u16);
+        self.line(self.pos, 2, &format!("type {}", qtype.name()));
+        self.pos += 2;
+
+        // An OPT record (RFC 6891) keeps the UDP payload size in CLASS and the extended RCODE, version and flags in TTL
+        let class = self.field(2, "class")? as u16;
+        match qtype {
+            QueryType::OPT => self.line(self.pos, 2, &format!("UDP payload size {}", class)),
+            _ => self.line(self.pos, 2, &format!("class {:?}", DnsClass::from_num(class))),
+        }
+        self.pos += 2;
+        let ttl = self.field(4, "TTL")?;
+        match qtype {
+            QueryType::OPT => self.line(self.pos, 4, &format!("extended RCODE {} version {} flags {:#06x}", ttl >> 24, (ttl >> 16) & 0xFF, ttl & 0xFFFF)),
+            _ => self.line(self.pos, 4, &format!("TTL {}", ttl)),
+        }
+        self.pos += 4;
+
+        let rdlength = self.u16("RDLENGTH")? as usize;
+        if self.pos + rdlength > self.message.len() {
+            return Err(format!("RDLENGTH {} runs past the end of the message", rdlength));
+        }
+        if rdlength > 0 {
+            let text = match self.decode_record(start) {
+                Some(data) => format!("RDATA {}", data),
+                None => String::from("RDATA"),
+            };
+            self.line(self.pos, rdlength, &text);
+        }
+        self.pos += rdlength;
+        Ok(())
+    }
+
+    // The record data in presentation format, where the parser can read the record
+    fn decode_record(&self, start: usize) -> Option<String> {
+        if self.message.len() > 512 {
+            return None;
+        }
+        let mut buffer = ByteBuffer::from_buffer(self.message);
+        buffer.seek(start).ok()?;
+        let record = DnsRecord::read(&mut buffer).ok()?;
+        record_data(&record)
+    }
+
+    fn explain(&mut self) -> Result<(), String> {
+        let [questions, answers, authorities, additionals] = self.header()?;
+        for (section, count) in [("QUESTION", questions), ("ANSWER", answers), ("AUTHORITY", authorities), ("ADDITIONAL", additionals)] {
+            if count > 0 {
+                let _ = writeln!(self.out, ";; {} SECTION", section);
+            }
+            for _ in 0..count {
+                match section {
+                    "QUESTION" => self.question()?,
+                    _ => self.record()?,
+                }
+            }
+        }
+        if self.pos < self.message.len() {
+            let trailing = self.message.len() - self.pos;
+            self.line(self.pos, trailing, &format!("{} trailing bytes", trailing));
+        }
+        Ok(())
+    }
+}
+
+impl DnsPacket {
+    /**
+    A byte by byte breakdown of a message as it is on the wire: one line per field with its offset,
+    its bytes in hex and what they mean, e.g. the header flags one by one, where compression pointers
+    point and each record's data in presentation format. Breaks off with an error line where the
+    message stops making sense, so it works best on exactly the messages the parser rejects.
+    */
+    pub fn explain(message: &[u8]) -> String {
+        let mut explainer = Explainer { message, pos: 0, out: String::new() };
+        if let Err(e) = explainer.explain() {
+            let _ = writeln!(explainer.out, "{:04x}  error: {}", explainer.pos, e);
+        }
+        explainer.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::question::DnsQuestion;
+    use core::net::Ipv4Addr;
+
+    fn wire(packet: &DnsPacket) -> Vec<u8> {
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.position()].to_vec()
+    }
+
+    #[test]
+    fn test_explain_response() {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 0x1234;
+        packet.header.response = true;
+        packet.header.recursion_desired = true;
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300, class: DnsClass::IN });
+        let mut message = wire(&packet);
+        // Point the answer's name at the question's, the way compressing writers do
+        let answer = 12 + 13 + 4;
+        message.splice(answer..answer + 13, [0xC0, 0x0C]);
+
+        let explained = DnsPacket::explain(&message);
+        let lines: Vec<&str> = explained.lines().collect();
+        assert_eq!(lines[0], "0000  1234                              ID 4660");
+        assert_eq!(lines[1], "0002  8100                              flags QR=1 OPCODE=QUERY AA=0 TC=0 RD=1 RA=0 Z=0 AD=0 CD=0 RCODE=NOERROR");
+        assert_eq!(lines[6], ";; QUESTION SECTION");
+        assert_eq!(lines[7], "000c  076578616d706c6503636f6d00        name example.com.");
+        assert!(explained.contains("001d  c00c                              name example.com. (pointer to 000c)\n"));
+        assert!(explained.contains("0029  c0000201                          RDATA 192.0.2.1\n"));
+        assert!(!explained.contains("error"));
+    }
+
+    #[test]
+    fn test_explain_broken_message() {
+        let mut packet = DnsPacket::new();
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        let mut message = wire(&packet);
+        message.truncate(18);
+        assert!(DnsPacket::explain(&message).ends_with("000c  error: the label runs past the end of the message\n"));
+
+        // A pointer to itself would loop forever in a careless parser
+        message.truncate(12);
+        message.extend([0xC0, 0x0C, 0, 1, 0, 1]);
+        assert!(DnsPacket::explain(&message).contains("error: pointer at 000c to 000c doesn't point backwards"));
+    }
+}
@@ -0,0 +1,370 @@
+use alloc::format;
+use core::fmt;
+
+use bitflags::bitflags;
+
+use crate::error::Result;
+use crate::{byte_buffer::ByteBuffer, result_code::ResultCode};
+
+// What a message asks the server to do, see the IANA "DNS OpCodes" registry
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    #[default]
+    Query,  // 0
+    IQuery, // 1, obsolete (RFC 3425)
+    Status, // 2
+    Notify, // 4, a zone changed (RFC 1996)
+    Update, // 5, dynamic update (RFC 2136)
+    Dso,    // 6, DNS stateful operations (RFC 8490)
+    Unassigned(u8),
+}
+
+impl Opcode {
+    pub fn to_num(self) -> u8 {
+        match self {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::Dso => 6,
+            Opcode::Unassigned(num) => num,
+        }
+    }
+
+    pub fn from_num(num: u8) -> Opcode {
+        match num {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            6 => Opcode::Dso,
+            _ => Opcode::Unassigned(num),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Opcode::Unassigned(num) => write!(f, "{}", num),
+            known => f.write_str(&format!("{:?}", known).to_ascii_uppercase()),
+        }
+    }
+}
+
+bitflags! {
+    /**
+    The second 16 bits of the header. The single bit flags are named, OPCODE and RCODE are the
+    4 bit fields around them: `opcode`/`set_opcode` and `rcode`/`set_rcode` read and write those,
+    and any value of the 16 bits survives a round trip, reserved ones included.
+    */
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct HeaderFlags: u16 {
+        const QR = 1 << 15; // response
+        const AA = 1 << 10; // authoritative answer
+        const TC = 1 << 9;  // truncated
+        const RD = 1 << 8;  // recursion desired
+        const RA = 1 << 7;  // recursion available
+        const Z = 1 << 6;   // reserved, must be zero
+        const AD = 1 << 5;  // authentic data
+        const CD = 1 << 4;  // checking disabled
+    }
+}
+
+const OPCODE_SHIFT: u16 = 11;
+const OPCODE_MASK: u16 = 0x0F << OPCODE_SHIFT;
+const RCODE_MASK: u16 = 0x0F;
+
+impl HeaderFlags {
+    pub fn response(self) -> bool {
+        self.contains(HeaderFlags::QR)
+    }
+
+    pub fn set_response(&mut self, value: bool) {
+        self.set(HeaderFlags::QR, value);
+    }
+
+    pub fn authoritative_answer(self) -> bool {
+        self.contains(HeaderFlags::AA)
+    }
+
+    pub fn set_authoritative_answer(&mut self, value: bool) {
+        self.set(HeaderFlags::AA, value);
+    }
+
+    pub fn truncated_message(self) -> bool {
+        self.contains(HeaderFlags::TC)
+    }
+
+    pub fn set_truncated_message(&mut self, value: bool) {
+        self.set(HeaderFlags::TC, value);
+    }
+
+    pub fn recursion_desired(self) -> bool {
+        self.contains(HeaderFlags::RD)
+    }
+
+    pub fn set_recursion_desired(&mut self, value: bool) {
+        self.set(HeaderFlags::RD, value);
+    }
+
+    pub fn recursion_available(self) -> bool {
+        self.contains(HeaderFlags::RA)
+    }
+
+    pub fn set_recursion_available(&mut self, value: bool) {
+        self.set(HeaderFlags::RA, value);
+    }
+
+    pub fn z(self) -> bool {
+        self.contains(HeaderFlags::Z)
+    }
+
+    pub fn set_z(&mut self, value: bool) {
+        self.set(HeaderFlags::Z, value);
+    }
+
+    pub fn authed_data(self) -> bool {
+        self.contains(HeaderFlags::AD)
+    }
+
+    pub fn set_authed_data(&mut self, value: bool) {
+        self.set(HeaderFlags::AD, value);
+    }
+
+    pub fn checking_disabled(self) -> bool {
+        self.contains(HeaderFlags::CD)
+    }
+
+    pub fn set_checking_disabled(&mut self, value: bool) {
+        self.set(HeaderFlags::CD, value);
+    }
+
+    pub fn opcode(self) -> u8 {
+        ((self.bits() & OPCODE_MASK) >> OPCODE_SHIFT) as u8
+    }
+
+    // Only the low 4 bits of `opcode` fit, anything above them would spill into QR
+    pub fn set_opcode(&mut self, opcode: u8) {
+        *self = HeaderFlags::from_bits_retain((self.bits() & !OPCODE_MASK) | ((opcode as u16 & 0x0F) << OPCODE_SHIFT));
+    }
+
+    pub fn rcode(self) -> u8 {
+        (self.bits() & RCODE_MASK) as u8
+    }
+
+    pub fn set_rcode(&mut self, rcode: u8) {
+        *self = HeaderFlags::from_bits_retain((self.bits() & !RCODE_MASK) | (rcode as u16 & RCODE_MASK));
+    }
+}
+
+/**
+ID -- Packet Identifier -- 16 bits
+QR -- Query Response -- 1 bit
+OPCODE -- Operation Code -- 4 bits
+AA -- Authoritative Answer -- 1 bit
+TC -- Truncated Message -- 1 bit
+RD -- Recursion Desired -- 1 bit
+RA -- Recursion Available -- 1 bit
+Z -- Reserved -- 3 bits (Split into Z, response, and authed_data)
+RCODE -- Response Code -- 4 bits
+QDCOUNT -- Question Count -- 16 bits
+ANCOUNT -- Answer Count -- 16 bits
+NSCOUNT -- Authority Count -- 16 bits
+ARCOUNT -- Additional Count -- 16 bits
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct DnsHeader {
+    pub id: u16, // 16 bits
+
+    pub recursion_desired: bool,    // 1 bit
+    pub truncated_message: bool,    // 1 bit
+    pub authoritative_answer: bool, // 1 bit
+    pub opcode: Opcode,             // 4 bits
+    pub response: bool,             // 1 bit
+
+    pub rescode: ResultCode,       // 4 bits
+    pub checking_disabled: bool,   // 1 bit
+    pub authed_data: bool,         // 1 bit
+    pub z: bool,                   // 1 bit
+    pub recursion_available: bool, // 1 bit
+
+    pub questions: u16,             // 16 bits
+    pub answers: u16,               // 16 bits
+    pub authoritative_entries: u16, // 16 bits
+    pub resource_entries: u16,      // 16 bits
+}
+
+impl Default for DnsHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsHeader {
+    pub fn new() -> DnsHeader {
+        DnsHeader {
+            id: 0,
+
+            recursion_desired: false,
+            truncated_message: false,
+            authoritative_answer: false,
+            opcode: Opcode::Query,
+            response: false,
+
+            rescode: ResultCode::NOERROR,
+            checking_disabled: false,
+            authed_data: false,
+            z: false,
+            recursion_available: false,
+
+            questions: 0,
+            answers: 0,
+            authoritative_entries: 0,
+            resource_entries: 0,
+        }
+    }
+
+    pub fn flags(&self) -> HeaderFlags {
+        let mut flags = HeaderFlags::empty();
+        flags.set_response(self.response);
+        flags.set_opcode(self.opcode.to_num());
+        flags.set_authoritative_answer(self.authoritative_answer);
+        flags.set_truncated_message(self.truncated_message);
+        flags.set_recursion_desired(self.recursion_desired);
+        flags.set_recursion_available(self.recursion_available);
+        flags.set_z(self.z);
+        flags.set_authed_data(self.authed_data);
+        flags.set_checking_disabled(self.checking_disabled);
+        flags.set_rcode(self.rescode.low_bits());
+        flags
+    }
+
+    pub fn set_flags(&mut self, flags: HeaderFlags) {
+        self.response = flags.response();
+        self.opcode = Opcode::from_num(flags.opcode());
+        self.authoritative_answer = flags.authoritative_answer();
+        self.truncated_message = flags.truncated_message();
+        self.recursion_desired = flags.recursion_desired();
+        self.recursion_available = flags.recursion_available();
+        self.z = flags.z();
+        self.authed_data = flags.authed_data();
+        self.checking_disabled = flags.checking_disabled();
+        self.rescode = ResultCode::from_num(flags.rcode() as u16);
+    }
+
+    pub fn read(&mut self, buffer: &mut ByteBuffer) -> Result<()> {
+        self.id = buffer.read_u16()?;
+
+        self.set_flags(HeaderFlags::from_bits_retain(buffer.read_u16()?));
+
+        self.questions = buffer.read_u16()?;
+        self.answers = buffer.read_u16()?;
+        self.authoritative_entries = buffer.read_u16()?;
+        self.resource_entries = buffer.read_u16()?;
+
+        Ok(())
+    }
+
+    pub fn write(&self, buffer: &mut ByteBuffer) -> Result<()>{
+        let _ = buffer.write_u16(self.id);
+
+        let _ = buffer.write_u16(self.flags().bits());
+        let _ = buffer.write_u16(self.questions);
+        let _ = buffer.write_u16(self.answers);
+        let _ = buffer.write_u16(self.authoritative_entries);
+        let _ = buffer.write_u16(self.resource_entries);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_write_header() {
+        let mut header = DnsHeader::new();
+        header.id = 6666;
+        header.recursion_desired = true;
+        header.questions = 1;
+
+        let mut buffer = ByteBuffer::new();
+        header.write(&mut buffer).unwrap();
+
+        let mut read_header = DnsHeader::new();
+        buffer.seek(0).unwrap();
+        read_header.read(&mut buffer).unwrap();
+
+        assert_eq!(header.id, read_header.id);
+        assert_eq!(header.recursion_desired, read_header.recursion_desired);
+        assert_eq!(header.questions, read_header.questions);
+    }
+
+    #[test]
+    fn test_opcode_round_trip() {
+        let mut header = DnsHeader::new();
+        header.opcode = Opcode::Update;
+        header.recursion_desired = true;
+
+        let mut buffer = ByteBuffer::new();
+        header.write(&mut buffer).unwrap();
+        let mut read_header = DnsHeader::new();
+        buffer.seek(0).unwrap();
+        read_header.read(&mut buffer).unwrap();
+
+        assert_eq!(read_header.opcode, Opcode::Update);
+        assert!(read_header.recursion_desired);
+        assert!(!read_header.response);
+    }
+
+    #[test]
+    fn test_flags_round_trip() {
+        for bits in 0..=u16::MAX {
+            let flags = HeaderFlags::from_bits_retain(bits);
+            let mut rebuilt = HeaderFlags::empty();
+            rebuilt.set_response(flags.response());
+            rebuilt.set_opcode(flags.opcode());
+            rebuilt.set_authoritative_answer(flags.authoritative_answer());
+            rebuilt.set_truncated_message(flags.truncated_message());
+            rebuilt.set_recursion_desired(flags.recursion_desired());
+            rebuilt.set_recursion_available(flags.recursion_available());
+            rebuilt.set_z(flags.z());
+            rebuilt.set_authed_data(flags.authed_data());
+            rebuilt.set_checking_disabled(flags.checking_disabled());
+            rebuilt.set_rcode(flags.rcode());
+            assert_eq!(rebuilt, flags, "{:#06x}", bits);
+
+            // Through the header and the wire
+            let mut header = DnsHeader::new();
+            header.set_flags(flags);
+            let mut buffer = ByteBuffer::new();
+            header.write(&mut buffer).unwrap();
+            buffer.seek(0).unwrap();
+            let mut read_header = DnsHeader::new();
+            read_header.read(&mut buffer).unwrap();
+            assert_eq!(read_header.flags(), flags, "{:#06x}", bits);
+            assert_eq!(read_header, header);
+        }
+    }
+
+    #[test]
+    fn test_reserved_bits_stay_apart() {
+        let mut header = DnsHeader::new();
+        header.z = true;
+        header.authed_data = true;
+        header.checking_disabled = true;
+        assert_eq!(header.flags().bits(), 0x0070);
+
+        // An opcode too large for its 4 bits used to spill into QR
+        let mut flags = header.flags();
+        flags.set_opcode(0x1F);
+        assert!(!flags.response());
+        assert_eq!(flags.opcode(), 0x0F);
+        assert_eq!(Opcode::from_num(flags.opcode()), Opcode::Unassigned(15));
+        assert_eq!((Opcode::Dso.to_string(), Opcode::Unassigned(15).to_string()), ("DSO".to_string(), "15".to_string()));
+    }
+}
@@ -0,0 +1,168 @@
+/*!
+Conversions to and from hickory-proto's `Message` and `Record`, behind the `hickory` feature.
+They go through the wire format, so every record type either side knows, unknown ones and EDNS
+included, comes across exactly as it would over the network. Messages are held to the 512 bytes
+`DnsPacket` works with.
+*/
+use alloc::vec::Vec;
+use core::fmt;
+
+use hickory_proto::error::ProtoError;
+use hickory_proto::op::Message;
+use hickory_proto::rr::Record;
+use hickory_proto::serialize::binary::{BinDecodable, BinDecoder, BinEncodable, BinEncoder};
+
+use crate::byte_buffer::ByteBuffer;
+use crate::error::DnsError;
+use crate::packet::DnsPacket;
+use crate::record::DnsRecord;
+
+// Why a conversion failed, on our side or on hickory's
+#[derive(Debug)]
+pub enum ConversionError {
+    Dns(DnsError),
+    Hickory(ProtoError),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::Dns(e) => write!(f, "{}", e),
+            ConversionError::Hickory(e) => write!(f, "hickory-proto: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl From<DnsError> for ConversionError {
+    fn from(e: DnsError) -> Self {
+        ConversionError::Dns(e)
+    }
+}
+
+impl From<ProtoError> for ConversionError {
+    fn from(e: ProtoError) -> Self {
+        ConversionError::Hickory(e)
+    }
+}
+
+// What `write` put in the buffer, nothing after it
+fn written(write: impl FnOnce(&mut ByteBuffer) -> Result<(), DnsError>) -> Result<Vec<u8>, DnsError> {
+    let mut buffer = ByteBuffer::new();
+    write(&mut buffer)?;
+    Ok(buffer.buffer[..buffer.position()].to_vec())
+}
+
+impl TryFrom<&Message> for DnsPacket {
+    type Error = ConversionError;
+
+    fn try_from(message: &Message) -> Result<Self, Self::Error> {
+        Ok(DnsPacket::from_bytes(&message.to_vec()?)?)
+    }
+}
+
+impl TryFrom<Message> for DnsPacket {
+    type Error = ConversionError;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        DnsPacket::try_from(&message)
+    }
+}
+
+impl TryFrom<&DnsPacket> for Message {
+    type Error = ConversionError;
+
+    fn try_from(packet: &DnsPacket) -> Result<Self, Self::Error> {
+        Ok(Message::from_vec(&written(|buffer| packet.write(buffer))?)?)
+    }
+}
+
+impl TryFrom<DnsPacket> for Message {
+    type Error = ConversionError;
+
+    fn try_from(packet: DnsPacket) -> Result<Self, Self::Error> {
+        Message::try_from(&packet)
+    }
+}
+
+impl TryFrom<&Record> for DnsRecord {
+    type Error = ConversionError;
+
+    fn try_from(record: &Record) -> Result<Self, Self::Error> {
+        let mut bytes = Vec::new();
+        record.emit(&mut BinEncoder::new(&mut bytes))?;
+        if bytes.len() > 512 {
+            return Err(DnsError::MessageTooLarge(bytes.len()).into());
+        }
+        Ok(DnsRecord::read(&mut ByteBuffer::from_buffer(&bytes))?)
+    }
+}
+
+impl TryFrom<Record> for DnsRecord {
+    type Error = ConversionError;
+
+    fn try_from(record: Record) -> Result<Self, Self::Error> {
+        DnsRecord::try_from(&record)
+    }
+}
+
+impl TryFrom<&DnsRecord> for Record {
+    type Error = ConversionError;
+
+    fn try_from(record: &DnsRecord) -> Result<Self, Self::Error> {
+        let bytes = written(|buffer| record.write(buffer))?;
+        Ok(Record::read(&mut BinDecoder::new(&bytes))?)
+    }
+}
+
+impl TryFrom<DnsRecord> for Record {
+    type Error = ConversionError;
+
+    fn try_from(record: DnsRecord) -> Result<Self, Self::Error> {
+        <Record as TryFrom<&DnsRecord>>::try_from(&record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use core::net::Ipv4Addr;
+    use hickory_proto::op::{MessageType, Query};
+    use hickory_proto::rr::{Name, RData, RecordType};
+    use crate::query_type::QueryType;
+    use crate::question::DnsQuestion;
+
+    #[test]
+    fn test_packet_round_trip() {
+        let packet = DnsPacket::builder()
+            .id(4321)
+            .question(DnsQuestion::new("example.com".to_string(), QueryType::A))
+            .answer(DnsRecord::cname("example.com", "www.example.net", 300))
+            .answer(DnsRecord::a("www.example.net", Ipv4Addr::new(192, 0, 2, 1), 60))
+            .build();
+
+        let message = Message::try_from(&packet).unwrap();
+        assert_eq!(message.id(), 4321);
+        assert_eq!(message.queries()[0].query_type(), RecordType::A);
+        assert_eq!(message.answers()[1].data(), Some(&RData::A(Ipv4Addr::new(192, 0, 2, 1).into())));
+        assert_eq!(DnsPacket::try_from(&message).unwrap().answers, packet.answers);
+    }
+
+    #[test]
+    fn test_from_hickory() {
+        let name = Name::from_ascii("example.com.").unwrap();
+        let mut message = Message::new();
+        message.set_id(7).set_message_type(MessageType::Response).add_query(Query::query(name.clone(), RecordType::MX));
+        message.add_answer(Record::from_rdata(name, 300, RData::A(Ipv4Addr::new(192, 0, 2, 7).into())));
+
+        let packet = DnsPacket::try_from(message).unwrap();
+        assert!(packet.header.response);
+        assert_eq!(packet.questions[0].qtype, QueryType::MX);
+        assert_eq!(packet.answers, vec![DnsRecord::a("example.com", Ipv4Addr::new(192, 0, 2, 7), 300)]);
+
+        let record: Record = (&packet.answers[0]).try_into().unwrap();
+        assert_eq!(DnsRecord::try_from(record).unwrap(), packet.answers[0]);
+    }
+}
@@ -0,0 +1,26 @@
+/*!
+The DNS wire format: byte buffer, header, question, record and packet parsing and writing. Needs
+only `alloc`, build it with `default-features = false` for targets without `std`.
+*/
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod builder;
+pub mod byte_buffer;
+pub mod dns_class;
+pub mod edns;
+pub mod error;
+pub mod explain;
+pub mod header;
+#[cfg(feature = "hickory")]
+pub mod hickory;
+pub mod name;
+pub mod nsec;
+pub mod packet;
+pub mod presentation;
+pub mod query_type;
+pub mod question;
+pub mod record;
+pub mod result_code;
+pub mod svcb;
@@ -0,0 +1,245 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt::Write;
+use core::mem;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::error::{DnsError, Result};
+
+// Label-aware domain name helpers. Names are compared case-insensitively and
+// without the trailing root dot, matching what `read_qname` produces.
+
+fn trim_root(name: &str) -> &str {
+    name.strip_suffix('.').unwrap_or(name)
+}
+
+// True when `name` equals `zone` or sits below it ("www.example.com" is in "example.com",
+// "badexample.com" is not). The root zone ("") contains every name.
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    let name = trim_root(name);
+    let zone = trim_root(zone);
+
+    if zone.is_empty() {
+        return true;
+    }
+    if name.len() < zone.len() {
+        return false;
+    }
+
+    let split = name.len() - zone.len();
+    if !name.is_char_boundary(split) || !name[split..].eq_ignore_ascii_case(zone) {
+        return false;
+    }
+    split == 0 || name.as_bytes()[split - 1] == b'.'
+}
+
+// Appends a label from the wire in presentation format. Dots, backslashes and every byte that isn't
+// printable ASCII become `\DDD`, so a label that isn't ASCII comes back unchanged from `parse_labels`.
+pub fn push_label(out: &mut String, label: &[u8]) {
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' | ..=b' ' | 0x7F.. => {
+                let _ = write!(out, "\\{:03}", byte);
+            }
+            _ => out.push(byte as char),
+        }
+    }
+}
+
+// The raw bytes of each label of a name in presentation format, `\DDD` and `\X` escapes decoded.
+// The root is "" or ".", any other empty label is an error.
+pub fn parse_labels(name: &str) -> Result<Vec<Vec<u8>>> {
+    let mut labels = Vec::new();
+    if name.is_empty() || name == "." {
+        return Ok(labels);
+    }
+    let mut label = Vec::new();
+    let mut bytes = name.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'.' => labels.push(mem::take(&mut label)),
+            b'\\' => label.push(match bytes.next() {
+                Some(digit @ b'0'..=b'9') => {
+                    let mut value = (digit - b'0') as u16;
+                    for _ in 0..2 {
+                        match bytes.next() {
+                            Some(digit @ b'0'..=b'9') => value = value * 10 + (digit - b'0') as u16,
+                            _ => return Err(DnsError::Malformed("escape in a name")),
+                        }
+                    }
+                    u8::try_from(value).map_err(|_| DnsError::Malformed("escape in a name"))?
+                }
+                Some(escaped) => escaped,
+                None => return Err(DnsError::Malformed("escape in a name")),
+            }),
+            _ => label.push(byte),
+        }
+    }
+    // A trailing dot only closes the last label
+    if !label.is_empty() {
+        labels.push(label);
+    }
+    if labels.iter().any(Vec::is_empty) {
+        return Err(DnsError::EmptyLabel);
+    }
+    Ok(labels)
+}
+
+// Where a DNAME from `owner` to `target` sends `name` (RFC 6672 2.2): the owner suffix is replaced
+// by the target, "www.example.org" under example.org -> example.net becomes "www.example.net". None
+// when the DNAME doesn't cover the name (the owner itself isn't redirected) or the result is too long.
+pub fn substitute_dname(name: &str, owner: &str, target: &str) -> Option<String> {
+    let (name, owner, target) = (trim_root(name), trim_root(owner), trim_root(target));
+    if !is_subdomain(name, owner) || name.len() == owner.len() {
+        return None;
+    }
+    let prefix = name[..name.len() - owner.len()].trim_end_matches('.');
+    let substituted = if target.is_empty() { prefix.to_string() } else { format!("{}.{}", prefix, target) };
+    (to_wire(&substituted).len() <= 255).then_some(substituted)
+}
+
+// DNSSEC's canonical order (RFC 4034 section 6.1): label by label from the right, case-insensitively,
+// so "example.com" sorts before "a.example.com", which sorts before "b.example.com"
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name: &str| name.trim_end_matches('.').to_ascii_lowercase();
+    let (a, b) = (labels(a), labels(b));
+    let a = a.split('.').rev().filter(|label| !label.is_empty());
+    let b = b.split('.').rev().filter(|label| !label.is_empty());
+    a.map(str::as_bytes).cmp(b.map(str::as_bytes))
+}
+
+// The uncompressed, lowercased wire format of a name, the form DNSSEC hashes and signs
+pub fn to_wire(name: &str) -> Vec<u8> {
+    let mut wire = Vec::new();
+    // A name that doesn't parse can't have been on the wire, its labels are taken as they are
+    let labels = parse_labels(name).unwrap_or_else(|_| {
+        trim_root(name).split('.').filter(|label| !label.is_empty()).map(|label| label.as_bytes().to_vec()).collect()
+    });
+    for label in labels {
+        wire.push(label.len() as u8);
+        wire.extend(label.iter().map(|byte| byte.to_ascii_lowercase()));
+    }
+    wire.push(0);
+    wire
+}
+
+// "192.0.2.1" becomes "1.2.0.192.in-addr.arpa", IPv6 addresses get one label per nibble under ip6.arpa
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            let mut labels: Vec<String> = Vec::with_capacity(33);
+            for octet in v6.octets().iter().rev() {
+                labels.push(format!("{:x}", octet & 0x0F));
+                labels.push(format!("{:x}", octet >> 4));
+            }
+            labels.push("ip6.arpa".to_string());
+            labels.join(".")
+        }
+    }
+}
+
+// Inverse of `reverse_name`; only complete addresses are accepted, not partial reverse zones
+pub fn parse_reverse_name(name: &str) -> Option<IpAddr> {
+    let name = trim_root(name).to_ascii_lowercase();
+
+    if let Some(rest) = name.strip_suffix(".in-addr.arpa") {
+        let labels: Vec<u8> = rest.split('.').map(|label| label.parse().ok()).collect::<Option<_>>()?;
+        if labels.len() != 4 {
+            return None;
+        }
+        return Some(IpAddr::V4(Ipv4Addr::new(labels[3], labels[2], labels[1], labels[0])));
+    }
+
+    if let Some(rest) = name.strip_suffix(".ip6.arpa") {
+        let nibbles: Vec<u8> = rest.split('.')
+            .map(|label| if label.len() == 1 { u8::from_str_radix(label, 16).ok() } else { None })
+            .collect::<Option<_>>()?;
+        if nibbles.len() != 32 {
+            return None;
+        }
+        let mut octets = [0u8; 16];
+        for (i, pair) in nibbles.rchunks(2).enumerate() {
+            octets[i] = (pair[1] << 4) | pair[0];
+        }
+        return Some(IpAddr::V6(Ipv6Addr::from(octets)));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels_round_trip() {
+        let mut name = String::new();
+        push_label(&mut name, "münchen".as_bytes());
+        assert_eq!(name, "m\\195\\188nchen");
+        name.push('.');
+        push_label(&mut name, b"a.b\\c d");
+        assert_eq!(name, "m\\195\\188nchen.a\\046b\\092c\\032d");
+        assert_eq!(parse_labels(&name), Ok(vec!["münchen".as_bytes().to_vec(), b"a.b\\c d".to_vec()]));
+
+        // Unescaped UTF-8 is taken byte for byte, `\X` stands for X
+        assert_eq!(parse_labels("münchen.de."), Ok(vec!["münchen".as_bytes().to_vec(), b"de".to_vec()]));
+        assert_eq!(parse_labels("a\\.b"), Ok(vec![b"a.b".to_vec()]));
+        assert_eq!(parse_labels("."), Ok(Vec::new()));
+        assert_eq!(parse_labels("a..b"), Err(DnsError::EmptyLabel));
+        assert!(parse_labels("a\\256").is_err());
+        assert!(parse_labels("a\\").is_err());
+    }
+
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("example.com", "example.com"));
+        assert!(is_subdomain("www.example.com", "example.com"));
+        assert!(is_subdomain("WWW.Example.com.", "example.COM"));
+        assert!(is_subdomain("example.com", ""));
+        assert!(!is_subdomain("badexample.com", "example.com"));
+        assert!(!is_subdomain("com", "example.com"));
+        assert!(!is_subdomain("example.org", "example.com"));
+    }
+
+    #[test]
+    fn test_substitute_dname() {
+        assert_eq!(substitute_dname("WWW.Example.org", "example.org", "example.net").as_deref(), Some("WWW.example.net"));
+        assert_eq!(substitute_dname("a.b.example.org.", "example.org.", "c.example.net.").as_deref(), Some("a.b.c.example.net"));
+        assert_eq!(substitute_dname("example.org", "example.org", "example.net"), None);
+        assert_eq!(substitute_dname("badexample.org", "example.org", "example.net"), None);
+        assert_eq!(substitute_dname(&format!("{}.example.org", ["a"; 120].join(".")), "example.org", "long-name.example.net"), None);
+    }
+
+    #[test]
+    fn test_canonical_cmp() {
+        let mut names = vec!["z.example", "zABC.a.EXAMPLE", "example", "*.z.example", "a.example", "yljkjljk.a.example"];
+        names.sort_by(|a, b| canonical_cmp(a, b));
+        assert_eq!(names, vec!["example", "a.example", "yljkjljk.a.example", "zABC.a.EXAMPLE", "z.example", "*.z.example"]);
+        assert_eq!(canonical_cmp("Example.com.", "example.com"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_reverse_name_round_trip() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(reverse_name(v4), "1.2.0.192.in-addr.arpa");
+        assert_eq!(parse_reverse_name("1.2.0.192.IN-ADDR.ARPA."), Some(v4));
+
+        let v6: IpAddr = "2001:db8::567:89ab".parse().unwrap();
+        let name = reverse_name(v6);
+        assert_eq!(name, "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa");
+        assert_eq!(parse_reverse_name(&name), Some(v6));
+    }
+
+    #[test]
+    fn test_parse_reverse_name_rejects_partial() {
+        assert_eq!(parse_reverse_name("2.0.192.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("256.2.0.192.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("example.com"), None);
+    }
+}
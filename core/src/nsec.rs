@@ -0,0 +1,121 @@
+use sha1::{Digest, Sha1};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::name::to_wire;
+use crate::query_type::QueryType;
+
+// NSEC3 hash algorithm 1, the only one defined (RFC 5155 section 11)
+pub const NSEC3_SHA1: u8 = 1;
+
+const BASE32HEX: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+
+// The type bitmap of NSEC and NSEC3 records (RFC 4034 section 4.1.2): one window per 256 types,
+// each a bit per type, trailing zero bytes left out
+pub fn encode_types(types: &[QueryType]) -> Vec<u8> {
+    let mut nums: Vec<u16> = types.iter().map(QueryType::to_num).collect();
+    nums.sort_unstable();
+    nums.dedup();
+    let mut data = Vec::new();
+    for window in 0..=255u8 {
+        let mut bitmap = [0u8; 32];
+        let mut len = 0;
+        for num in nums.iter().filter(|num| (*num >> 8) as u8 == window) {
+            let bit = (*num & 0xFF) as usize;
+            bitmap[bit / 8] |= 0x80 >> (bit % 8);
+            len = bit / 8 + 1;
+        }
+        if len > 0 {
+            data.extend_from_slice(&[window, len as u8]);
+            data.extend_from_slice(&bitmap[..len]);
+        }
+    }
+    data
+}
+
+pub fn parse_types(mut data: &[u8]) -> Option<Vec<QueryType>> {
+    let mut types = Vec::new();
+    while !data.is_empty() {
+        let (window, len) = (*data.first()? as u16, *data.get(1)? as usize);
+        let bitmap = data.get(2..2 + len).filter(|_| (1..=32).contains(&len))?;
+        for (i, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(QueryType::from_num(window << 8 | (i * 8 + bit) as u16));
+                }
+            }
+        }
+        data = &data[2 + len..];
+    }
+    Some(types)
+}
+
+// Base32 with the extended hex alphabet and no padding (RFC 4648 section 7), as NSEC3 owner names use it
+pub fn to_base32hex(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let bits = block.iter().fold(0u64, |bits, byte| bits << 8 | *byte as u64);
+        for i in 0..(chunk.len() * 8).div_ceil(5) {
+            out.push(BASE32HEX[(bits >> (35 - i * 5) & 0x1F) as usize] as char);
+        }
+    }
+    out
+}
+
+pub fn from_base32hex(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.trim_end_matches('=').chars() {
+        let value = BASE32HEX.iter().position(|&digit| digit as char == c.to_ascii_lowercase())?;
+        bits = bits << 5 | value as u32;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// The hashed owner name of NSEC3 (RFC 5155 section 5), iterated SHA-1 over the canonical wire format
+pub fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut hash = Sha1::new().chain_update(to_wire(name)).chain_update(salt).finalize();
+    for _ in 0..iterations {
+        hash = Sha1::new().chain_update(hash).chain_update(salt).finalize();
+    }
+    hash.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_bitmap() {
+        // The example bitmap of RFC 4034 section 4.3
+        let types = [QueryType::A, QueryType::MX, QueryType::RRSIG, QueryType::NSEC, QueryType::UNKNOWN(1234)];
+        let data = encode_types(&types);
+        assert_eq!(data, [
+            0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03,
+            0x04, 0x1b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x20,
+        ]);
+        assert_eq!(parse_types(&data), Some(types.to_vec()));
+        assert_eq!(parse_types(&[0, 0]), None);
+    }
+
+    #[test]
+    fn test_nsec3_hash() {
+        // From the example zone of RFC 5155 appendix A
+        let hash = nsec3_hash("example", &[0xaa, 0xbb, 0xcc, 0xdd], 12);
+        assert_eq!(to_base32hex(&hash), "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom");
+        assert_eq!(from_base32hex("0P9MHAVEQVM6T7VBL5LOP2U3T2RP3TOM"), Some(hash));
+        assert_eq!(to_base32hex(&nsec3_hash("a.example", &[0xaa, 0xbb, 0xcc, 0xdd], 12)), "35mthgpgcu1qg68fab165klnsnk3dpvl");
+    }
+}
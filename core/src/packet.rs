@@ -0,0 +1,695 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use log::debug;
+use serde::Deserialize;
+
+use crate::error::{DnsError, Result};
+use crate::{byte_buffer::ByteBuffer, header::DnsHeader, name::is_subdomain, query_type::QueryType, question::DnsQuestion, record::DnsRecord};
+use crate::{edns::{EdnsOption, FLAG_DO}, result_code::ResultCode};
+
+/**
+How much of a broken message is accepted. Strict parsing rejects inconsistent header counts,
+compression pointers that don't point backwards, record lengths that disagree with the data and
+trailing bytes, and the client gets a FORMERR. Permissive parsing salvages what it can: record
+lengths win over the data, and a message that breaks off keeps the records before the break.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    Strict,
+    #[default]
+    Permissive,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ParsingConfig {
+    pub mode: ParseMode,
+}
+
+// An atomic rather than a lock so it works without `std` too
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+// Set once at startup from `[parsing]`, permissive until then
+pub fn set_parse_mode(mode: ParseMode) {
+    STRICT.store(mode == ParseMode::Strict, Ordering::Relaxed);
+}
+
+fn current_parse_mode() -> ParseMode {
+    match STRICT.load(Ordering::Relaxed) {
+        true => ParseMode::Strict,
+        false => ParseMode::Permissive,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DnsPacket {
+    pub header: DnsHeader,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub resources: Vec<DnsRecord>,
+}
+
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsPacket {
+    pub fn new() -> DnsPacket {
+        DnsPacket {
+            header: DnsHeader::new(),
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            resources: Vec::new(),
+        }
+    }
+
+    // For messages of unknown length, like the zero padded ones in the cache
+    pub fn from_buffer(buffer: &mut ByteBuffer) -> Result<DnsPacket> {
+        DnsPacket::read(buffer, None, current_parse_mode())
+    }
+
+    // For messages straight off the wire, strict parsing also rejects anything after the last record
+    pub fn from_bytes(message: &[u8]) -> Result<DnsPacket> {
+        DnsPacket::parse(message, current_parse_mode())
+    }
+
+    pub fn parse(message: &[u8], mode: ParseMode) -> Result<DnsPacket> {
+        if message.len() > 512 {
+            return Err(DnsError::MessageTooLarge(message.len()));
+        }
+        DnsPacket::read(&mut ByteBuffer::from_buffer(message), Some(message.len()), mode)
+    }
+
+    fn read(buffer: &mut ByteBuffer, len: Option<usize>, mode: ParseMode) -> Result<DnsPacket> {
+        let strict = mode == ParseMode::Strict;
+        let end = len.unwrap_or(buffer.buffer.len());
+        buffer.strict = strict;
+        let mut packet = DnsPacket::new();
+        packet.header.read(buffer)?;
+
+        // Without its question a message can't be answered, even permissive parsing gives up
+        for _ in 0..packet.header.questions {
+            let mut question = DnsQuestion::new(String::new(), QueryType::UNKNOWN(0));
+            question.read(buffer)?;
+            packet.questions.push(question);
+        }
+        if buffer.position() > end {
+            return Err(DnsError::CountMismatch);
+        }
+
+        let counts = [packet.header.answers, packet.header.authoritative_entries, packet.header.resource_entries];
+        'sections: for (section, count) in counts.into_iter().enumerate() {
+            for _ in 0..count {
+                let record = match DnsRecord::read(buffer) {
+                    Ok(_) if buffer.position() > end => Err(DnsError::CountMismatch),
+                    result => result,
+                };
+                match (record, strict) {
+                    (Ok(record), _) => match section {
+                        0 => packet.answers.push(record),
+                        1 => packet.authorities.push(record),
+                        _ => packet.resources.push(record),
+                    },
+                    (Err(e), true) => return Err(e),
+                    // Keep the records that made sense, the counts follow what was kept
+                    (Err(e), false) => {
+                        debug!("Dropping the rest of a malformed message: {}", e);
+                        packet.header.answers = packet.answers.len() as u16;
+                        packet.header.authoritative_entries = packet.authorities.len() as u16;
+                        packet.header.resource_entries = packet.resources.len() as u16;
+                        break 'sections;
+                    }
+                }
+            }
+        }
+
+        if let (true, Some(len)) = (strict, len) {
+            if buffer.position() < len {
+                return Err(DnsError::TrailingBytes(len - buffer.position()));
+            }
+        }
+
+        // The header only has the low 4 bits of an extended RCODE, the OPT record the rest
+        if let Some(&DnsRecord::OPT { flags, .. }) = packet.opt() {
+            packet.header.rescode = ResultCode::from_parts(packet.header.rescode.low_bits(), (flags >> 24) as u8);
+        }
+        Ok(packet)
+    }
+
+    // The FORMERR answer to a query that didn't parse. Without a full header there is nothing
+    // to answer, and answering responses could start a loop with another server.
+    pub fn format_error(message: &[u8]) -> Option<DnsPacket> {
+        if message.len() < 12 {
+            return None;
+        }
+        let mut request = DnsHeader::new();
+        request.read(&mut ByteBuffer::from_buffer(&message[..12])).ok()?;
+        if request.response {
+            return None;
+        }
+
+        let mut response = DnsPacket::new();
+        response.header.id = request.id;
+        response.header.opcode = request.opcode;
+        response.header.recursion_desired = request.recursion_desired;
+        response.header.recursion_available = true;
+        response.header.response = true;
+        response.header.rescode = ResultCode::FORMERR;
+        Some(response)
+    }
+
+    pub fn write(&self, buffer: &mut ByteBuffer) -> Result<()>{
+        // The section counts always follow the records actually present
+        let mut header = self.header.clone();
+        header.questions = self.questions.len() as u16;
+        header.answers = self.answers.len() as u16;
+        header.authoritative_entries = self.authorities.len() as u16;
+        header.resource_entries = self.resources.len() as u16;
+        header.write(buffer)?;
+
+        for q in &self.questions {
+            q.write(buffer)?;
+        }
+
+        for a in &self.answers {
+            a.write(buffer)?;
+        }
+
+        for a in &self.authorities {
+            a.write(buffer)?;
+        }
+
+        // The upper 8 bits of an extended RCODE go in the OPT record, without one there is no room
+        let extended = self.header.rescode.extended_bits();
+        if extended != 0 && self.opt().is_none() {
+            return Err(DnsError::Malformed("extended RCODE without an OPT record"));
+        }
+        for a in &self.resources {
+            match a {
+                DnsRecord::OPT { udp_size, flags, options } if (flags >> 24) as u8 != extended => {
+                    let flags = (flags & 0x00FF_FFFF) | (extended as u32) << 24;
+                    DnsRecord::OPT { udp_size: *udp_size, flags, options: options.clone() }.write(buffer)?;
+                }
+                _ => a.write(buffer)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn opt(&self) -> Option<&DnsRecord> {
+        self.resources.iter().find(|record| matches!(record, DnsRecord::OPT { .. }))
+    }
+
+    // Upstream options are for the client that asked, they are taken out before anything is cached
+    pub fn take_opt(&mut self) -> Option<DnsRecord> {
+        let index = self.resources.iter().position(|record| matches!(record, DnsRecord::OPT { .. }))?;
+        Some(self.resources.remove(index))
+    }
+
+    fn opt_options(&mut self) -> Option<&mut Vec<EdnsOption>> {
+        self.resources.iter_mut().find_map(|record| match record {
+            DnsRecord::OPT { options, .. } => Some(options),
+            _ => None,
+        })
+    }
+
+    pub fn option(&self, code: u16) -> Option<&[u8]> {
+        match self.opt()? {
+            DnsRecord::OPT { options, .. } => options.iter().find(|option| option.code == code).map(|option| option.data.as_slice()),
+            _ => None,
+        }
+    }
+
+    pub fn take_option(&mut self, code: u16) -> Option<Vec<u8>> {
+        let options = self.opt_options()?;
+        let index = options.iter().position(|option| option.code == code)?;
+        Some(options.remove(index).data)
+    }
+
+    // Replaces an option of the same code, does nothing without an OPT record
+    pub fn set_option(&mut self, option: EdnsOption) {
+        if let Some(options) = self.opt_options() {
+            options.retain(|existing| existing.code != option.code);
+            options.push(option);
+        }
+    }
+
+    // The DO bit lives in the TTL field of the EDNS OPT pseudo-record (RFC 6891)
+    pub fn dnssec_ok(&self) -> bool {
+        self.opt().is_some_and(|opt| matches!(opt, DnsRecord::OPT { flags, .. } if flags & FLAG_DO != 0))
+    }
+
+    // RRSIG, NSEC and NSEC3 are only for clients that set DO, unless they asked for them by type
+    pub fn remove_dnssec_records(&mut self, qtype: QueryType) {
+        let keep = |record: &DnsRecord| {
+            let rtype = record.query_type();
+            rtype == qtype || !matches!(rtype, QueryType::RRSIG | QueryType::NSEC | QueryType::NSEC3)
+        };
+        self.answers.retain(keep);
+        self.authorities.retain(keep);
+        self.resources.retain(keep);
+    }
+
+    // Every record except OPT, whose TTL field holds EDNS flags
+    pub fn set_ttl(&mut self, ttl: u32) {
+        for record in self.answers.iter_mut().chain(self.authorities.iter_mut()).chain(self.resources.iter_mut()) {
+            if record.query_type().to_num() != 41 {
+                record.set_ttl(ttl);
+            }
+        }
+    }
+
+    // How long NXDOMAIN or NODATA may be cached, the lower of the SOA's TTL and minimum (RFC 2308 section 5)
+    pub fn negative_ttl(&self) -> Option<u32> {
+        if !self.answers.is_empty() || !matches!(self.header.rescode, ResultCode::NOERROR | ResultCode::NXDOMAIN) {
+            return None;
+        }
+        self.authorities.iter().find_map(|record| match record {
+            DnsRecord::SOA { ttl, minimum, .. } => Some((*ttl).min(*minimum)),
+            _ => None,
+        })
+    }
+
+    // How long the whole response may be cached: the lowest TTL among its answer and authority
+    // records, so no RRset outlives its own TTL, and the negative TTL when there are no answers
+    pub fn cache_ttl(&self) -> Option<u32> {
+        if self.answers.is_empty() {
+            return self.negative_ttl();
+        }
+        self.answers.iter().chain(&self.authorities)
+            .filter(|record| record.query_type() != QueryType::OPT)
+            .map(DnsRecord::ttl)
+            .min()
+    }
+
+    // The SOA of a negative answer carries the time the answer may still be cached
+    pub fn set_negative_ttl(&mut self, ttl: u32) {
+        for record in &mut self.authorities {
+            if let DnsRecord::SOA { .. } = record {
+                record.set_ttl(ttl);
+            }
+        }
+    }
+
+    pub fn get_random_a(&self) -> Option<Ipv4Addr> {
+        for a in &self.answers {
+            if let DnsRecord::A { addr, .. } = a {
+                return Some(*addr);
+            }
+        }
+        None
+    }
+
+    pub fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.authorities.iter().filter_map(|record| match record {
+            DnsRecord::NS { domain, ns, ..} => Some((domain.as_str(), ns.as_str())),
+            _ => None,
+        }).filter(move |(domain, _)| is_subdomain(qname, domain))
+    }
+
+    // Only glue for nameservers inside `bailiwick` (the zone of the server that sent
+    // this referral) is trusted; anything else has to be resolved independently.
+    pub fn get_resolved_ns(&self, qname: &str, bailiwick: &str) -> Option<IpAddr> {
+        self.get_glue_addrs(qname, bailiwick).into_iter().next()
+    }
+
+    // Every trusted glue address, an IPv4 and an IPv6 one per nameserver, in the order the NS records appeared
+    pub fn get_glue_addrs(&self, qname: &str, bailiwick: &str) -> Vec<IpAddr> {
+        self.get_glue(qname, bailiwick).concat()
+    }
+
+    // The same addresses one nameserver at a time, the ones without trusted glue left out
+    pub fn get_glue(&self, qname: &str, bailiwick: &str) -> Vec<Vec<IpAddr>> {
+        self.get_ns(qname)
+            .filter(|(_, ns)| is_subdomain(ns, bailiwick))
+            .map(|(_, ns)| {
+                let glue = |v6: bool| self.resources.iter().find_map(|record| match record {
+                    DnsRecord::A { domain, addr, .. } if !v6 && domain.eq_ignore_ascii_case(ns) => Some(IpAddr::V4(*addr)),
+                    DnsRecord::AAAA { domain, addr, .. } if v6 && domain.eq_ignore_ascii_case(ns) => Some(IpAddr::V6(*addr)),
+                    _ => None,
+                });
+                glue(false).into_iter().chain(glue(true)).collect::<Vec<_>>()
+            })
+            .filter(|addrs| !addrs.is_empty())
+            .collect()
+    }
+
+    pub fn get_referral_zone<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
+        self.get_ns(qname).map(|(domain, _)| domain).next()
+    }
+
+    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
+        self.get_ns(qname).map(|(_, ns)| ns).next()
+    }
+
+    pub fn write_to_bytes(&self) -> Result<[u8; 512]> {
+        let mut buffer = ByteBuffer::new();
+        self.write(&mut buffer)?;
+        Ok(buffer.buffer)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::dns_class::DnsClass;
+
+    #[test]
+    fn test_new_dns_packet() {
+        let packet = DnsPacket::new();
+        assert_eq!(packet.header, DnsHeader::new());
+        assert!(packet.questions.is_empty());
+        assert!(packet.answers.is_empty());
+        assert!(packet.authorities.is_empty());
+        assert!(packet.resources.is_empty());
+    }
+
+    #[test]
+    fn test_from_buffer() {
+        let mut buffer = ByteBuffer::new();
+        let header = DnsHeader::new();
+        header.write(&mut buffer).unwrap();
+
+        let question = DnsQuestion::new("example.com".to_string(), QueryType::A);
+        question.write(&mut buffer).unwrap();
+
+        let answer = DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: Ipv4Addr::new(127, 0, 0, 1),
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        answer.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+
+        let mut packet = DnsPacket::new();
+        packet.header = header;
+        packet.header.questions = 1;
+        packet.header.answers = 1;
+        packet.questions.push(question);
+        packet.answers.push(answer);
+
+        let deserialized_packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+        for (i, q) in deserialized_packet.questions.iter().enumerate() {
+            assert_eq!(q, &packet.questions[i]);
+        }
+        for (i, a) in deserialized_packet.answers.iter().enumerate() {
+            assert_eq!(a, &packet.answers[i]);
+        }
+    }
+
+    fn create_referral(ns: &str, glue: Ipv4Addr) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.authorities.push(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            ns: ns.to_string(),
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+        packet.resources.push(DnsRecord::A {
+            domain: ns.to_string(),
+            addr: glue,
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+        packet
+    }
+
+    #[test]
+    fn test_get_resolved_ns_in_bailiwick() {
+        let glue = Ipv4Addr::new(192, 0, 2, 1);
+        let packet = create_referral("ns1.example.com", glue);
+
+        assert_eq!(packet.get_resolved_ns("www.example.com", "com"), Some(IpAddr::V4(glue)));
+        assert_eq!(packet.get_resolved_ns("www.example.com", ""), Some(IpAddr::V4(glue)));
+        assert_eq!(packet.get_referral_zone("www.example.com"), Some("example.com"));
+    }
+
+    #[test]
+    fn test_get_resolved_ns_out_of_bailiwick() {
+        let packet = create_referral("ns1.victim.org", Ipv4Addr::new(192, 0, 2, 66));
+
+        // A .com server has no authority to hand out addresses for victim.org
+        assert_eq!(packet.get_resolved_ns("www.example.com", "com"), None);
+        assert_eq!(packet.get_unresolved_ns("www.example.com"), Some("ns1.victim.org"));
+    }
+
+    #[test]
+    fn test_get_glue_addrs_all_nameservers() {
+        let mut packet = create_referral("ns1.example.com", Ipv4Addr::new(192, 0, 2, 1));
+        packet.authorities.push(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            ns: "ns2.example.com".to_string(),
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+        packet.resources.push(DnsRecord::A {
+            domain: "ns2.example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 2),
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+        packet.resources.push(DnsRecord::AAAA {
+            domain: "ns1.example.com".to_string(),
+            addr: "2001:db8::1".parse().unwrap(),
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+
+        assert_eq!(
+            packet.get_glue_addrs("www.example.com", "com"),
+            vec![IpAddr::from([192, 0, 2, 1]), "2001:db8::1".parse().unwrap(), IpAddr::from([192, 0, 2, 2])]
+        );
+    }
+
+    #[test]
+    fn test_get_ns_matches_whole_labels() {
+        let packet = create_referral("ns1.example.com", Ipv4Addr::new(192, 0, 2, 1));
+
+        assert_eq!(packet.get_ns("badexample.com").count(), 0);
+        assert_eq!(packet.get_resolved_ns("badexample.com", ""), None);
+    }
+
+    #[test]
+    fn test_cache_ttl() {
+        let mut packet = DnsPacket::new();
+        packet.answers.push(DnsRecord::cname("www.example.com", "example.com", 3600));
+        packet.answers.push(DnsRecord::a("example.com", Ipv4Addr::new(192, 0, 2, 1), 300));
+        packet.authorities.push(DnsRecord::ns("example.com", "ns1.example.com", 600));
+        assert_eq!(packet.cache_ttl(), Some(300));
+        packet.authorities[0].set_ttl(60);
+        assert_eq!(packet.cache_ttl(), Some(60));
+
+        // Negative answers go by the SOA, whichever of its TTL and minimum is lower
+        packet.answers.clear();
+        packet.authorities = vec![DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "admin.example.com".to_string(),
+            serial: 1,
+            refresh: 7200,
+            retry: 900,
+            expire: 86400,
+            minimum: 120,
+            ttl: 3600,
+            class: DnsClass::IN,
+        }];
+        packet.header.rescode = ResultCode::NXDOMAIN;
+        assert_eq!(packet.cache_ttl(), Some(120));
+    }
+
+    #[test]
+    fn test_write_sets_section_counts() {
+        let mut packet = DnsPacket::new();
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        packet.answers.push(DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: Ipv4Addr::new(127, 0, 0, 1),
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.seek(0).unwrap();
+
+        let deserialized_packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(deserialized_packet.header.questions, 1);
+        assert_eq!(deserialized_packet.header.answers, 1);
+        assert_eq!(deserialized_packet.answers, packet.answers);
+    }
+
+    #[test]
+    fn test_write_dns_packet() {
+        let mut buffer = ByteBuffer::new();
+        
+        let mut packet = DnsPacket::new();
+        packet.header.questions = 1;
+        packet.header.answers = 1;
+
+        let question = DnsQuestion::new("example.com".to_string(), QueryType::A);
+        packet.questions.push(question.clone());
+
+        let answer = DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: Ipv4Addr::new(127, 0, 0, 1),
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        packet.answers.push(answer.clone());
+
+        packet.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+
+        let deserialized_packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(deserialized_packet.header.questions, packet.header.questions);
+        assert_eq!(deserialized_packet.header.answers, packet.header.answers);
+        assert_eq!(deserialized_packet.questions, packet.questions);
+        assert_eq!(deserialized_packet.answers, packet.answers);
+    }
+
+    #[test]
+    fn test_extended_rcode() {
+        let mut packet = DnsPacket::new();
+        packet.header.response = true;
+        packet.header.rescode = ResultCode::BADCOOKIE;
+        let mut buffer = ByteBuffer::new();
+        assert_eq!(packet.write(&mut buffer), Err(DnsError::Malformed("extended RCODE without an OPT record")));
+
+        // 23 is split into 7 in the header and 1 at the top of the OPT record's TTL
+        packet.resources.push(DnsRecord::OPT { udp_size: 512, flags: FLAG_DO, options: Vec::new() });
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        let message = &buffer.buffer[..buffer.position()];
+        assert_eq!(message[3] & 0x0F, 7);
+        let read = DnsPacket::from_bytes(message).unwrap();
+        assert_eq!(read.header.rescode, ResultCode::BADCOOKIE);
+        assert_eq!(read.opt(), Some(&DnsRecord::OPT { udp_size: 512, flags: 0x0100_0000 | FLAG_DO, options: Vec::new() }));
+        assert!(read.dnssec_ok());
+    }
+
+    #[test]
+    fn test_dnssec_records() {
+        let mut packet = DnsPacket::new();
+        packet.resources.push(DnsRecord::OPT { udp_size: 512, flags: 0x8000, options: Vec::new() });
+        assert!(packet.dnssec_ok());
+        packet.resources[0] = DnsRecord::OPT { udp_size: 512, flags: 0, options: Vec::new() };
+        assert!(!packet.dnssec_ok());
+
+        packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 60, class: DnsClass::IN });
+        packet.answers.push(DnsRecord::UNKNOWN { domain: "example.com".to_string(), qtype: 46, data_len: 0, ttl: 60, class: DnsClass::IN });
+        packet.authorities.push(DnsRecord::UNKNOWN { domain: "example.com".to_string(), qtype: 47, data_len: 0, ttl: 60, class: DnsClass::IN });
+
+        let mut asked_for_rrsig = packet.clone();
+        asked_for_rrsig.remove_dnssec_records(QueryType::RRSIG);
+        assert_eq!(asked_for_rrsig.answers.len(), 2);
+
+        packet.remove_dnssec_records(QueryType::A);
+        assert_eq!(packet.answers.len(), 1);
+        assert!(packet.authorities.is_empty());
+        assert_eq!(packet.resources.len(), 1);
+    }
+
+    #[test]
+    fn test_negative_ttl() {
+        let mut packet = DnsPacket::new();
+        packet.header.rescode = ResultCode::NXDOMAIN;
+        assert_eq!(packet.negative_ttl(), None);
+        packet.authorities.push(DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 1,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 900,
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+        assert_eq!(packet.negative_ttl(), Some(900));
+        packet.set_negative_ttl(120);
+        assert_eq!(packet.negative_ttl(), Some(120));
+
+        packet.header.rescode = ResultCode::SERVFAIL;
+        assert_eq!(packet.negative_ttl(), None);
+    }
+
+    fn create_message() -> Vec<u8> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 99;
+        packet.header.response = true;
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        for last in [1, 2] {
+            packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, last), ttl: 60, class: DnsClass::IN });
+        }
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.position()].to_vec()
+    }
+
+    fn parse_error(message: &[u8]) -> DnsError {
+        DnsPacket::parse(message, ParseMode::Strict).unwrap_err()
+    }
+
+    #[test]
+    fn test_strict_and_permissive_parsing() {
+        let message = create_message();
+        assert_eq!(DnsPacket::parse(&message, ParseMode::Strict).unwrap().answers.len(), 2);
+
+        // The header counts a third answer that isn't there
+        let mut overcounted = message.clone();
+        overcounted[7] = 3;
+        assert_eq!(parse_error(&overcounted), DnsError::CountMismatch);
+        let salvaged = DnsPacket::parse(&overcounted, ParseMode::Permissive).unwrap();
+        assert_eq!(salvaged.answers.len(), 2);
+        assert_eq!(salvaged.header.answers, 2);
+
+        let mut trailing = message.clone();
+        trailing.extend_from_slice(b"junk");
+        assert_eq!(parse_error(&trailing), DnsError::TrailingBytes(4));
+        assert_eq!(DnsPacket::parse(&trailing, ParseMode::Permissive).unwrap().answers.len(), 2);
+
+        // The first answer's owner name points forward at the second answer's, 14 bytes on
+        let mut forward = message[..29].to_vec();
+        forward.extend_from_slice(&(0xC000u16 | 45).to_be_bytes());
+        forward.extend_from_slice(&message[42..]);
+        assert_eq!(parse_error(&forward), DnsError::BadPointer(45));
+        assert_eq!(DnsPacket::parse(&forward, ParseMode::Permissive).unwrap().answers.len(), 2);
+
+        // An RDLENGTH of 5 for an A record, the length wins and the extra byte is skipped
+        let mut long_rdata = message.clone();
+        long_rdata[51] = 5;
+        long_rdata.insert(56, 0);
+        assert_eq!(parse_error(&long_rdata), DnsError::RdataLength { expected: 5, actual: 4 });
+        let salvaged = DnsPacket::parse(&long_rdata, ParseMode::Permissive).unwrap();
+        assert_eq!(salvaged.answers[1], DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 2), ttl: 60, class: DnsClass::IN });
+    }
+
+    #[test]
+    fn test_format_error() {
+        let mut query = create_message();
+        assert!(DnsPacket::format_error(&query).is_none());
+        query[2] &= 0x7F;
+        query[2] |= 0x01;
+        let response = DnsPacket::format_error(&query).unwrap();
+        assert_eq!(response.header.id, 99);
+        assert!(response.header.recursion_desired);
+        assert_eq!(response.header.rescode, ResultCode::FORMERR);
+        assert!(DnsPacket::format_error(&query[..11]).is_none());
+    }
+}
@@ -0,0 +1,203 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::nsec::to_base32hex;
+use crate::query_type::QueryType;
+use crate::record::DnsRecord;
+use crate::svcb::{self, key_name, SvcParam};
+
+// Names and record data the way zone files and the DoH JSON API write them
+
+pub fn fqdn(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+// Presentation format of the record data, None for records we don't keep the data of
+pub fn record_data(record: &DnsRecord) -> Option<String> {
+    match record {
+        DnsRecord::UNKNOWN { .. } | DnsRecord::OPT { .. } => None,
+        DnsRecord::A { addr, .. } => Some(addr.to_string()),
+        DnsRecord::AAAA { addr, .. } => Some(addr.to_string()),
+        DnsRecord::NS { ns: name, .. }
+        | DnsRecord::CNAME { cname: name, .. }
+        | DnsRecord::DNAME { target: name, .. }
+        | DnsRecord::PTR { host: name, .. } => Some(fqdn(name)),
+        DnsRecord::HINFO { cpu, os, .. } => Some(format!("{} {}", txt_string(cpu), txt_string(os))),
+        DnsRecord::LOC { size, horizontal_precision, vertical_precision, latitude, longitude, altitude, .. } => Some(format!(
+            "{} {} {} {} {} {}", loc_angle(*latitude, 'N', 'S'), loc_angle(*longitude, 'E', 'W'), loc_altitude(*altitude),
+            loc_size(*size), loc_size(*horizontal_precision), loc_size(*vertical_precision)
+        )),
+        DnsRecord::ZONEMD { serial, scheme, algorithm, digest, .. } => {
+            Some(format!("{} {} {} {}", serial, scheme, algorithm, digest.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()))
+        }
+        DnsRecord::SVCB { priority, target, params, .. } | DnsRecord::HTTPS { priority, target, params, .. } => {
+            let params = params.iter().map(svc_param).collect::<Vec<_>>().join(" ");
+            Some(format!("{} {} {}", priority, fqdn(target), params).trim_end().to_string())
+        }
+        DnsRecord::DHCID { data, .. } => Some(to_base64(data)),
+        DnsRecord::EUI48 { addr, .. } => Some(eui(addr)),
+        DnsRecord::EUI64 { addr, .. } => Some(eui(addr)),
+        DnsRecord::URI { priority, weight, target, .. } => Some(format!("{} {} {}", priority, weight, txt_string(target))),
+        DnsRecord::MX { preference, exchange, .. } => Some(format!("{} {}", preference, fqdn(exchange))),
+        DnsRecord::SRV { priority, weight, port, target, .. } => Some(format!("{} {} {} {}", priority, weight, port, fqdn(target))),
+        DnsRecord::SOA { mname, rname, serial, refresh, retry, expire, minimum, .. } => Some(format!(
+            "{} {} {} {} {} {} {}", fqdn(mname), fqdn(rname), serial, refresh, retry, expire, minimum
+        )),
+        DnsRecord::TXT { data, .. } => Some(data.iter().map(|string| txt_string(string)).collect::<Vec<_>>().join(" ")),
+        DnsRecord::DNSKEY { flags, protocol, algorithm, public_key, .. } => Some(format!("{} {} {} {}", flags, protocol, algorithm, to_base64(public_key))),
+        // Times as seconds since 1970, which RFC 4034 3.2 allows next to the date form
+        DnsRecord::RRSIG { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature, .. } => Some(format!(
+            "{} {} {} {} {} {} {} {} {}", type_covered.name(), algorithm, labels, original_ttl, expiration, inception, key_tag, fqdn(signer), to_base64(signature)
+        )),
+        DnsRecord::NSEC { next, types, .. } => Some(format!("{} {}", fqdn(next), type_list(types)).trim_end().to_string()),
+        DnsRecord::NSEC3 { algorithm, flags, iterations, salt, next, types, .. } => {
+            let salt = if salt.is_empty() { "-".to_string() } else { salt.iter().map(|byte| format!("{:02x}", byte)).collect() };
+            Some(format!("{} {} {} {} {} {}", algorithm, flags, iterations, salt, to_base32hex(next), type_list(types)).trim_end().to_string())
+        }
+    }
+}
+
+// "00-00-5e-00-53-2a", lowercase hex pairs joined by hyphens (RFC 7043 3)
+fn eui(addr: &[u8]) -> String {
+    addr.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join("-")
+}
+
+// "alpn=h2,h3", "port=8443" or "key65000=\001x" for keys without a known form (RFC 9460 2.1)
+fn svc_param(param: &SvcParam) -> String {
+    let known = match param.key {
+        svcb::KEY_MANDATORY => svcb::u16_list(&param.value).map(|keys| keys.into_iter().map(key_name).collect::<Vec<_>>().join(",")),
+        svcb::KEY_ALPN => svcb::alpn_ids(&param.value)
+            .filter(|ids| ids.iter().all(|id| id.iter().all(|byte| byte.is_ascii_graphic() && !matches!(byte, b',' | b'\\' | b'"'))))
+            .map(|ids| ids.iter().map(|id| String::from_utf8_lossy(id)).collect::<Vec<_>>().join(",")),
+        svcb::KEY_NO_DEFAULT_ALPN if param.value.is_empty() => return key_name(param.key),
+        svcb::KEY_PORT => <[u8; 2]>::try_from(param.value.as_slice()).ok().map(|port| u16::from_be_bytes(port).to_string()),
+        svcb::KEY_IPV4HINT => svcb::ipv4_list(&param.value).map(|addrs| addrs.iter().map(ToString::to_string).collect::<Vec<_>>().join(",")),
+        svcb::KEY_ECH => Some(to_base64(&param.value)),
+        svcb::KEY_IPV6HINT => svcb::ipv6_list(&param.value).map(|addrs| addrs.iter().map(ToString::to_string).collect::<Vec<_>>().join(",")),
+        _ => None,
+    };
+    match known {
+        Some(value) => format!("{}={}", key_name(param.key), value),
+        // Spaces escaped too, so the value stays one field without quotes
+        None => {
+            let quoted = txt_string(&param.value);
+            format!("key{}={}", param.key, quoted[1..quoted.len() - 1].replace(' ', "\\032"))
+        }
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Standard base64 with padding (RFC 4648 4), as zone files write DHCID, keys and signatures
+fn to_base64(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | (*byte as u32) << (16 - i * 8));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[(bits >> (18 - i * 6) & 0x3F) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+fn type_list(types: &[QueryType]) -> String {
+    types.iter().map(QueryType::name).collect::<Vec<_>>().join(" ")
+}
+
+// "42 21 54.000 N", from thousandths of an arc second offset by 2^31
+fn loc_angle(value: u32, positive: char, negative: char) -> String {
+    let offset = value as i64 - (1 << 31);
+    let hemisphere = if offset < 0 { negative } else { positive };
+    let ms = offset.unsigned_abs();
+    format!("{} {} {}.{:03} {}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000, hemisphere)
+}
+
+// Metres from centimetres above 100 km below the ellipsoid, "-24.00m"
+fn loc_altitude(value: u32) -> String {
+    let cm = value as i64 - 10_000_000;
+    let sign = if cm < 0 { "-" } else { "" };
+    format!("{}{}.{:02}m", sign, cm.unsigned_abs() / 100, cm.unsigned_abs() % 100)
+}
+
+// A size or precision, the mantissa in the high nibble and the power of ten in the low one, in metres
+// without decimals when there are no centimetres, like BIND writes them
+fn loc_size(value: u8) -> String {
+    let cm = (value >> 4) as u64 * 10u64.pow((value & 0x0F).min(9) as u32);
+    match cm % 100 {
+        0 => format!("{}m", cm / 100),
+        rest => format!("{}.{:02}m", cm / 100, rest),
+    }
+}
+
+// Quoted like zone files do, with \DDD escapes for anything that isn't printable ASCII
+pub fn txt_string(string: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x20..=0x7E => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_class::DnsClass;
+
+    #[test]
+    fn test_record_data() {
+        let mx = DnsRecord::MX {
+            domain: "example.com".to_string(),
+            preference: 10,
+            exchange: "mail.example.com".to_string(),
+            ttl: 300,
+            class: DnsClass::IN,
+        };
+        assert_eq!(record_data(&mx), Some("10 mail.example.com.".to_string()));
+
+        let txt = DnsRecord::TXT {
+            domain: "example.com".to_string(),
+            data: vec![b"say \"hi\"".to_vec(), vec![7]],
+            ttl: 300,
+            class: DnsClass::IN,
+        };
+        assert_eq!(record_data(&txt), Some(r#""say \"hi\"" "\007""#.to_string()));
+
+        let hinfo = DnsRecord::HINFO { domain: "example.com".to_string(), cpu: b"x86_64".to_vec(), os: b"Linux".to_vec(), ttl: 300, class: DnsClass::IN };
+        assert_eq!(record_data(&hinfo), Some(r#""x86_64" "Linux""#.to_string()));
+
+        let uri = DnsRecord::URI { domain: "_ftp._tcp.example.com".to_string(), priority: 10, weight: 1, target: b"ftp://ftp1.example.com/public".to_vec(), ttl: 300, class: DnsClass::IN };
+        assert_eq!(record_data(&uri), Some(r#"10 1 "ftp://ftp1.example.com/public""#.to_string()));
+
+        let loc = DnsRecord::LOC {
+            domain: "caida.org".to_string(),
+            size: 0x33,
+            horizontal_precision: 0x13,
+            vertical_precision: 0x13,
+            latitude: 2265864648,
+            longitude: 1725418648,
+            altitude: 10010700,
+            ttl: 300,
+            class: DnsClass::IN,
+        };
+        assert_eq!(record_data(&loc), Some("32 53 1.000 N 117 14 25.000 W 107.00m 30m 10m 10m".to_string()));
+
+        let unknown = DnsRecord::UNKNOWN { domain: "example.com".to_string(), qtype: 99, data_len: 4, ttl: 300, class: DnsClass::IN };
+        assert_eq!(record_data(&unknown), None);
+    }
+}
@@ -0,0 +1,246 @@
+use alloc::string::{String, ToString};
+use core::cmp::Ordering;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::error::DnsError;
+
+// One variant per assigned value of the IANA "Resource Record (RR) TYPEs" registry, named by its
+// mnemonic (NSAP-PTR as NSAP_PTR, * as ANY)
+macro_rules! query_types {
+    ($($name:ident = $num:literal,)*) => {
+        #[allow(non_camel_case_types)]
+        #[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
+        pub enum QueryType {
+            UNKNOWN(u16), // an unassigned or reserved value
+            $($name,)*
+        }
+
+        impl QueryType {
+            pub fn to_num(&self) -> u16 {
+                match *self {
+                    QueryType::UNKNOWN(x) => x,
+                    $(QueryType::$name => $num,)*
+                }
+            }
+
+            pub fn from_num(num: u16) -> QueryType {
+                match num {
+                    $($num => QueryType::$name,)*
+                    _ => QueryType::UNKNOWN(num),
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn mnemonic(&self) -> Option<&'static str> {
+                match *self {
+                    QueryType::UNKNOWN(_) => None,
+                    QueryType::NSAP_PTR => Some("NSAP-PTR"),
+                    $(QueryType::$name => Some(stringify!($name)),)*
+                }
+            }
+
+            fn from_mnemonic(name: &str) -> Option<QueryType> {
+                match name {
+                    "NSAP-PTR" => Some(QueryType::NSAP_PTR),
+                    "*" => Some(QueryType::ANY),
+                    $(stringify!($name) => Some(QueryType::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+query_types! {
+    A = 1,
+    NS = 2,
+    MD = 3,
+    MF = 4,
+    CNAME = 5,
+    SOA = 6,
+    MB = 7,
+    MG = 8,
+    MR = 9,
+    NULL = 10,
+    WKS = 11,
+    PTR = 12,
+    HINFO = 13,
+    MINFO = 14,
+    MX = 15,
+    TXT = 16,
+    RP = 17,
+    AFSDB = 18,
+    X25 = 19,
+    ISDN = 20,
+    RT = 21,
+    NSAP = 22,
+    NSAP_PTR = 23,
+    SIG = 24,
+    KEY = 25,
+    PX = 26,
+    GPOS = 27,
+    AAAA = 28,
+    LOC = 29,
+    NXT = 30,
+    EID = 31,
+    NIMLOC = 32,
+    SRV = 33,
+    ATMA = 34,
+    NAPTR = 35,
+    KX = 36,
+    CERT = 37,
+    A6 = 38,
+    DNAME = 39,
+    SINK = 40,
+    OPT = 41,
+    APL = 42,
+    DS = 43,
+    SSHFP = 44,
+    IPSECKEY = 45,
+    RRSIG = 46,
+    NSEC = 47,
+    DNSKEY = 48,
+    DHCID = 49,
+    NSEC3 = 50,
+    NSEC3PARAM = 51,
+    TLSA = 52,
+    SMIMEA = 53,
+    HIP = 55,
+    NINFO = 56,
+    RKEY = 57,
+    TALINK = 58,
+    CDS = 59,
+    CDNSKEY = 60,
+    OPENPGPKEY = 61,
+    CSYNC = 62,
+    ZONEMD = 63,
+    SVCB = 64,
+    HTTPS = 65,
+    DSYNC = 66,
+    HHIT = 67,
+    BRID = 68,
+    SPF = 99,
+    UINFO = 100,
+    UID = 101,
+    GID = 102,
+    UNSPEC = 103,
+    NID = 104,
+    L32 = 105,
+    L64 = 106,
+    LP = 107,
+    EUI48 = 108,
+    EUI64 = 109,
+    NXNAME = 128,
+    TKEY = 249,
+    TSIG = 250,
+    IXFR = 251,
+    AXFR = 252,
+    MAILB = 253,
+    MAILA = 254,
+    ANY = 255,
+    URI = 256,
+    CAA = 257,
+    AVC = 258,
+    DOA = 259,
+    AMTRELAY = 260,
+    RESINFO = 261,
+    WALLET = 262,
+    CLA = 263,
+    IPN = 264,
+    TA = 32768,
+    DLV = 32769,
+}
+
+impl QueryType {
+    // Accepts mnemonics ("AAAA", case-insensitive) as well as plain numbers ("28") and TYPE28 (RFC 3597)
+    pub fn from_name(name: &str) -> Option<QueryType> {
+        if let Ok(num) = name.parse::<u16>() {
+            return Some(QueryType::from_num(num));
+        }
+        let name = name.to_ascii_uppercase();
+        match name.strip_prefix("TYPE").map(str::parse::<u16>) {
+            Some(Ok(num)) => Some(QueryType::from_num(num)),
+            _ => QueryType::from_mnemonic(&name),
+        }
+    }
+
+    // The mnemonic, or TYPE1234 for types without one (RFC 3597)
+    pub fn name(&self) -> String {
+        self.to_string()
+    }
+}
+
+// By number, the order of NSEC type bitmaps and of RRsets in a canonical zone
+impl Ord for QueryType {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_num().cmp(&other.to_num())
+    }
+}
+
+impl PartialOrd for QueryType {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Only assigned types, `from_num` is the one that takes any value
+impl TryFrom<u16> for QueryType {
+    type Error = DnsError;
+
+    fn try_from(num: u16) -> Result<QueryType, DnsError> {
+        match QueryType::from_num(num) {
+            QueryType::UNKNOWN(num) => Err(DnsError::UnassignedType(num)),
+            known => Ok(known),
+        }
+    }
+}
+
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mnemonic() {
+            Some(mnemonic) => f.write_str(mnemonic),
+            None => write!(f, "TYPE{}", self.to_num()),
+        }
+    }
+}
+
+impl FromStr for QueryType {
+    type Err = DnsError;
+
+    fn from_str(name: &str) -> Result<QueryType, DnsError> {
+        QueryType::from_name(name).ok_or(DnsError::Malformed("record type"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(QueryType::from_name("aaaa"), Some(QueryType::AAAA));
+        assert_eq!(QueryType::from_name("15"), Some(QueryType::MX));
+        assert_eq!(QueryType::from_name("65"), Some(QueryType::HTTPS));
+        assert_eq!(QueryType::from_name("TYPE65"), Some(QueryType::HTTPS));
+        assert_eq!(QueryType::from_name("type1234"), Some(QueryType::UNKNOWN(1234)));
+        assert_eq!(QueryType::from_name("nsap-ptr"), Some(QueryType::NSAP_PTR));
+        assert_eq!(QueryType::from_name("BOGUS"), None);
+        assert_eq!("srv".parse::<QueryType>(), Ok(QueryType::SRV));
+        assert!("TYPE".parse::<QueryType>().is_err());
+    }
+
+    #[test]
+    fn test_registry_round_trip() {
+        for num in 0..=u16::MAX {
+            let qtype = QueryType::from_num(num);
+            assert_eq!(qtype.to_num(), num);
+            assert_eq!(qtype.to_string().parse::<QueryType>(), Ok(qtype));
+            assert_eq!(QueryType::try_from(num).is_ok(), !matches!(qtype, QueryType::UNKNOWN(_)));
+        }
+        assert_eq!(QueryType::try_from(54), Err(DnsError::UnassignedType(54)));
+        assert_eq!(QueryType::NSAP_PTR.to_string(), "NSAP-PTR");
+        assert_eq!(QueryType::UNKNOWN(65280).name(), "TYPE65280");
+        assert!(QueryType::UNKNOWN(54) < QueryType::HIP && QueryType::ANY < QueryType::URI);
+    }
+}
@@ -0,0 +1,114 @@
+use alloc::string::String;
+
+use crate::error::Result;
+use crate::{byte_buffer::ByteBuffer, dns_class::DnsClass, query_type::QueryType};
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: QueryType,
+    pub qclass: DnsClass,
+}
+
+impl DnsQuestion {
+    pub fn new(name: String, qtype: QueryType) -> DnsQuestion {
+        DnsQuestion {
+            name,
+            qtype,
+            qclass: DnsClass::IN,
+        }
+    }
+
+    // The name keeps the case it was sent in (clients may use it as 0x20 entropy), compare it case-insensitively
+    pub fn read(&mut self, buffer: &mut ByteBuffer) -> Result<()> {
+        buffer.read_qname_preserve_case(&mut self.name)?;
+        let t = buffer.read_u16()?;
+        self.qtype = QueryType::from_num(t);
+        self.qclass = DnsClass::from_num(buffer.read_u16()?);
+
+        Ok(())
+    }
+
+    pub fn write(&self, buffer: &mut ByteBuffer) -> Result<()> {
+        buffer.write_qname(&self.name)?;
+        buffer.write_u16(self.qtype.to_num())?;
+        buffer.write_u16(self.qclass.to_num())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_dns_question() {
+        let name = "example.com".to_string();
+        let qtype = QueryType::A;
+        let question = DnsQuestion::new(name.clone(), qtype);
+
+        assert_eq!(question.name, name);
+        assert_eq!(question.qtype, qtype);
+    }
+
+    #[test]
+    fn test_read_dns_question() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        buffer.write_u16(QueryType::A.to_num()).unwrap();
+        buffer.write_u16(1).unwrap();
+        buffer.seek(0).unwrap();
+
+        let mut question = DnsQuestion::new(String::new(), QueryType::UNKNOWN(0));
+        question.read(&mut buffer).unwrap();
+
+        assert_eq!(question.name, "example.com");
+        assert_eq!(question.qtype, QueryType::A);
+        assert_eq!(question.qclass, DnsClass::IN);
+    }
+
+    #[test]
+    fn test_chaos_question() {
+        let mut question = DnsQuestion::new("version.bind".to_string(), QueryType::TXT);
+        question.qclass = DnsClass::CH;
+        let mut buffer = ByteBuffer::new();
+        question.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        let mut read_question = DnsQuestion::new(String::new(), QueryType::UNKNOWN(0));
+        read_question.read(&mut buffer).unwrap();
+        assert_eq!(read_question, question);
+    }
+
+    #[test]
+    fn test_write_dns_question() {
+        let question = DnsQuestion::new("example.com".to_string(), QueryType::A);
+        let mut buffer = ByteBuffer::new();
+        question.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        let mut name = String::new();
+        buffer.read_qname(&mut name).unwrap();
+        assert_eq!(name, "example.com");
+        assert_eq!(buffer.read_u16().unwrap(), QueryType::A.to_num());
+        assert_eq!(buffer.read_u16().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_read_preserves_case() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("wWw.ExAmPle.com").unwrap();
+        buffer.write_u16(QueryType::A.to_num()).unwrap();
+        buffer.write_u16(1).unwrap();
+        buffer.seek(0).unwrap();
+
+        let mut question = DnsQuestion::new(String::new(), QueryType::UNKNOWN(0));
+        question.read(&mut buffer).unwrap();
+        assert_eq!(question.name, "wWw.ExAmPle.com");
+
+        buffer.seek(0).unwrap();
+        let mut name = String::new();
+        buffer.read_qname(&mut name).unwrap();
+        assert_eq!(name, "www.example.com");
+    }
+}
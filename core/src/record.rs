@@ -0,0 +1,1611 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use log::debug;
+
+use crate::byte_buffer::ByteBuffer;
+use crate::dns_class::DnsClass;
+use crate::edns::{encode_options, parse_options, EdnsOption};
+use crate::error::{DnsError, Result};
+use crate::nsec::{encode_types, parse_types};
+use crate::query_type::QueryType;
+use crate::svcb::{encode_params, parse_params, SvcParam};
+
+/*
+Name -- Label Sequence
+Type -- 2-byte Integer
+Class -- 2-byte Integer
+TTL -- 4-byte Integer
+Len -- 2-byte Integer
+*/
+
+/*
+A: Indicates the IP address for the domain. Holds a 32-bit IPv4 address. E.g. "cloudfare.come" gives 104.17.210.9
+    default TTL is 14,400 seconds so if the record gets updated, it will take 4 hours to propagate.
+
+NS: Indicates the authoritative name server for the domain. Holds a domain name. E.g. "cloudfare.com" gives "ns1.cloudflare.com".
+    NS records cannot point to CNAME records.
+
+CNAME: Indicates the canonical name for an alias. Holds a domain name. E.g. "www.cloudflare.com" gives "www.cloudflare.com.cdn.cloudflare.net"
+    e.g. "www.cloudflare.com" gives "www.cloudflare.com.cdn.cloudflare.net" which  resolves to an A record.
+
+SOA: Start of authority, one per zone at its apex. Names the primary server and the zone's contact, and holds the
+    serial and timers secondaries use, plus the TTL for negative answers (RFC 2308).
+
+PTR: Maps an address back to a name. Holds a domain name. E.g. "1.1.1.1.in-addr.arpa" gives "one.one.one.one".
+
+HINFO: The host's CPU and operating system, as two character strings (RFC 1035). Mostly seen as the answer
+    to ANY queries servers no longer want to answer in full (RFC 8482).
+
+MX: Indicates the mail server for the domain. Holds a domain name. E.g. "cloudfare.com" gives "mail.cloudfare.com".
+
+TXT: Free-form text attached to a name. Holds one or more character strings of up to 255 bytes each,
+    which are not necessarily valid UTF-8 (e.g. DNSCrypt certificates).
+
+AAAA: Indicates the IP address for the domain. Holds a 128-bit IPv6 address.
+
+LOC: The geographical location of the name (RFC 1876): latitude and longitude in thousandths of an arc second
+    offset by 2^31, altitude in centimetres above 100 km below the WGS 84 ellipsoid, and the size and precisions
+    as a mantissa and power of ten in centimetres. Only version 0 is defined, others are kept as unknown records.
+
+SRV: Where a service is offered (RFC 2782), e.g. "_ipp._tcp.example.com" or a DNS-SD instance name. Holds the
+    target host and port, with a priority (lower first) and a weight for spreading load among equal priorities.
+
+DNAME: Redirects a whole subtree to another (RFC 6672), e.g. "example.org" to "example.net" makes
+    "www.example.org" an alias of "www.example.net". The owner name itself is not redirected.
+
+OPT: The EDNS pseudo record (RFC 6891), only ever in the additional section with the root as its owner. The
+    class field holds the sender's UDP payload size, the TTL field the extended RCODE, version and flags.
+
+RRSIG: A signature over the records of one name and type (RFC 4034), made with the DNSKEY of the signer's
+    zone with the key tag given and valid between the inception and expiration times (seconds since 1970).
+
+NSEC: Proves a name or type doesn't exist (RFC 4034). Names the next name of the zone in canonical order and
+    lists the types present at its owner, anything between the two names doesn't exist.
+
+DNSKEY: A public key of the zone at its apex (RFC 4034), flags 256 for a zone key and 257 when it is also a
+    key signing key. The protocol is always 3, the algorithm says how the key is encoded (15 is Ed25519).
+
+NSEC3: The same proof over hashed names (RFC 5155), so the zone's names can't be walked. The owner's first
+    label and the next name are salted, iterated SHA-1 hashes.
+
+DHCID: Which DHCP client a name was registered for (RFC 4701), so DHCP servers updating the zone don't take
+    over each other's names. Holds an identifier type, a digest type and a SHA-256 digest of the client's
+    identifier and the name, kept as opaque bytes.
+
+ZONEMD: A digest of the whole zone at its apex (RFC 8976), for checking a zone that came through a transfer
+    or a file. Holds the SOA serial it was computed for, the scheme (1 is SIMPLE), the hash algorithm (1 is
+    SHA-384, 2 SHA-512) and the digest.
+
+SVCB, HTTPS: How to reach a service (RFC 9460). Priority 0 is AliasMode, pointing the whole name at
+    another one; other priorities are ServiceMode endpoints with a target ("." for the owner itself) and
+    parameters such as the ALPN protocols, port and address hints. HTTPS is SVCB for https:// origins.
+
+EUI48, EUI64: A 48-bit or 64-bit hardware address (RFC 7043), e.g. a DHCP client's MAC address.
+
+URI: A URI a service is offered at (RFC 7553), with a priority and weight like SRV. The target fills the rest
+    of the data, it isn't a length-prefixed character string.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DnsRecord {
+    UNKNOWN {
+        domain: String,
+        qtype: u16,
+        data_len: u16,
+        ttl: u32,
+        class: DnsClass,
+    }, // 0
+    A {
+        domain: String,
+        addr: Ipv4Addr,
+        ttl: u32,
+        class: DnsClass,
+    }, // 1
+    NS {
+        domain: String,
+        ns: String,
+        ttl: u32,
+        class: DnsClass,
+    }, // 2
+    CNAME {
+        domain: String,
+        cname: String,
+        ttl: u32,
+        class: DnsClass,
+    }, // 5
+    SOA {
+        domain: String,
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+        class: DnsClass,
+    }, // 6
+    PTR {
+        domain: String,
+        host: String,
+        ttl: u32,
+        class: DnsClass,
+    }, // 12
+    HINFO {
+        domain: String,
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 13
+    MX {
+        domain: String,
+        preference: u16,
+        exchange: String,
+        ttl: u32,
+        class: DnsClass,
+    }, // 15
+    TXT {
+        domain: String,
+        data: Vec<Vec<u8>>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 16
+    AAAA {
+        domain: String,
+        addr: Ipv6Addr,
+        ttl: u32,
+        class: DnsClass,
+    }, // 28
+    LOC {
+        domain: String,
+        size: u8,
+        horizontal_precision: u8,
+        vertical_precision: u8,
+        latitude: u32,
+        longitude: u32,
+        altitude: u32,
+        ttl: u32,
+        class: DnsClass,
+    }, // 29
+    SRV {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+        ttl: u32,
+        class: DnsClass,
+    }, // 33
+    DNAME {
+        domain: String,
+        target: String,
+        ttl: u32,
+        class: DnsClass,
+    }, // 39
+    OPT {
+        udp_size: u16,
+        flags: u32,
+        options: Vec<EdnsOption>,
+    }, // 41
+    RRSIG {
+        domain: String,
+        type_covered: QueryType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: String,
+        signature: Vec<u8>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 46
+    NSEC {
+        domain: String,
+        next: String,
+        types: Vec<QueryType>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 47
+    DNSKEY {
+        domain: String,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 48
+    DHCID {
+        domain: String,
+        data: Vec<u8>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 49
+    NSEC3 {
+        domain: String,
+        algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next: Vec<u8>, // the hash, not a name
+        types: Vec<QueryType>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 50
+    ZONEMD {
+        domain: String,
+        serial: u32,
+        scheme: u8,
+        algorithm: u8,
+        digest: Vec<u8>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 63
+    SVCB {
+        domain: String,
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 64
+    HTTPS {
+        domain: String,
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 65
+    EUI48 {
+        domain: String,
+        addr: [u8; 6],
+        ttl: u32,
+        class: DnsClass,
+    }, // 108
+    EUI64 {
+        domain: String,
+        addr: [u8; 8],
+        ttl: u32,
+        class: DnsClass,
+    }, // 109
+    URI {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        target: Vec<u8>,
+        ttl: u32,
+        class: DnsClass,
+    }, // 256
+}
+
+impl DnsRecord {
+    pub fn read(buffer: &mut ByteBuffer) -> Result<DnsRecord> {
+        let mut domain = String::new();
+        buffer.read_qname(&mut domain)?;
+        let qtype = buffer.read_u16()?;
+        let class_num = buffer.read_u16()?;
+        let class = DnsClass::from_num(class_num);
+        let ttl = buffer.read_u32()?;
+        let data_len = buffer.read_u16()?;
+        let start = buffer.position();
+
+        let record: Result<DnsRecord> = match qtype {
+            1 => {
+                let addr = Ipv4Addr::from(buffer.read_u32()?);
+                Ok(DnsRecord::A {
+                    domain,
+                    addr,
+                    ttl,
+                    class,
+                })
+            },
+            2 => {
+                let mut ns = String::new();
+                buffer.read_qname(&mut ns)?;
+                Ok(DnsRecord::NS {
+                    domain,
+                    ns,
+                    ttl,
+                    class,
+                })
+            },
+            5 => {
+                let mut cname = String::new();
+                buffer.read_qname(&mut cname)?;
+                Ok(DnsRecord::CNAME {
+                    domain,
+                    cname,
+                    ttl,
+                    class,
+                })
+            },
+            6 => {
+                let mut mname = String::new();
+                buffer.read_qname(&mut mname)?;
+                let mut rname = String::new();
+                buffer.read_qname(&mut rname)?;
+                Ok(DnsRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial: buffer.read_u32()?,
+                    refresh: buffer.read_u32()?,
+                    retry: buffer.read_u32()?,
+                    expire: buffer.read_u32()?,
+                    minimum: buffer.read_u32()?,
+                    ttl,
+                    class,
+                })
+            },
+            12 => {
+                let mut host = String::new();
+                buffer.read_qname(&mut host)?;
+                Ok(DnsRecord::PTR {
+                    domain,
+                    host,
+                    ttl,
+                    class,
+                })
+            },
+            13 => {
+                let len = buffer.read()?;
+                let cpu = buffer.read_bytes(len as usize)?;
+                let len = buffer.read()?;
+                let os = buffer.read_bytes(len as usize)?;
+                Ok(DnsRecord::HINFO {
+                    domain,
+                    cpu,
+                    os,
+                    ttl,
+                    class,
+                })
+            },
+            15 => {
+                let preference = buffer.read_u16()?;
+                let mut exchange = String::new();
+                buffer.read_qname(&mut exchange)?;
+                Ok(DnsRecord::MX {
+                    domain,
+                    preference,
+                    exchange,
+                    ttl,
+                    class,
+                })
+            },
+            16 => {
+                let end = buffer.position() + data_len as usize;
+                let mut data = Vec::new();
+                while buffer.position() < end {
+                    let len = buffer.read()?;
+                    data.push(buffer.read_bytes(len as usize)?);
+                }
+                Ok(DnsRecord::TXT {
+                    domain,
+                    data,
+                    ttl,
+                    class,
+                })
+            },
+            28 => {
+                let mut addr = [0u8; 16];
+                for octet in addr.iter_mut() {
+                    *octet = buffer.read()?;
+                }
+                let addr = Ipv6Addr::from(addr);
+                Ok(DnsRecord::AAAA {
+                    domain,
+                    addr,
+                    ttl,
+                    class,
+                })
+            },
+            29 if data_len == 16 && buffer.get(buffer.position())? == 0 => {
+                buffer.step(1)?; // the version
+                let size = buffer.read()?;
+                let horizontal_precision = buffer.read()?;
+                let vertical_precision = buffer.read()?;
+                Ok(DnsRecord::LOC {
+                    domain,
+                    size,
+                    horizontal_precision,
+                    vertical_precision,
+                    latitude: buffer.read_u32()?,
+                    longitude: buffer.read_u32()?,
+                    altitude: buffer.read_u32()?,
+                    ttl,
+                    class,
+                })
+            },
+            33 => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let mut target = String::new();
+                buffer.read_qname(&mut target)?;
+                Ok(DnsRecord::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    target,
+                    ttl,
+                    class,
+                })
+            },
+            39 => {
+                let mut target = String::new();
+                buffer.read_qname(&mut target)?;
+                Ok(DnsRecord::DNAME {
+                    domain,
+                    target,
+                    ttl,
+                    class,
+                })
+            },
+            41 => {
+                let data = buffer.read_bytes(data_len as usize)?;
+                let options = parse_options(&data)
+                    .ok_or(DnsError::Malformed("EDNS options"))?;
+                Ok(DnsRecord::OPT {
+                    udp_size: class_num,
+                    flags: ttl,
+                    options,
+                })
+            },
+            46 if data_len >= 18 => {
+                let type_covered = QueryType::from_num(buffer.read_u16()?);
+                let algorithm = buffer.read()?;
+                let labels = buffer.read()?;
+                let original_ttl = buffer.read_u32()?;
+                let expiration = buffer.read_u32()?;
+                let inception = buffer.read_u32()?;
+                let key_tag = buffer.read_u16()?;
+                let mut signer = String::new();
+                buffer.read_qname(&mut signer)?;
+                let signature = buffer.read_bytes((start + data_len as usize).saturating_sub(buffer.position()))?;
+                Ok(DnsRecord::RRSIG {
+                    domain,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer,
+                    signature,
+                    ttl,
+                    class,
+                })
+            },
+            47 => {
+                let mut next = String::new();
+                buffer.read_qname(&mut next)?;
+                let bitmap = buffer.read_bytes((start + data_len as usize).saturating_sub(buffer.position()))?;
+                Ok(DnsRecord::NSEC {
+                    domain,
+                    next,
+                    types: parse_types(&bitmap).ok_or(DnsError::Malformed("NSEC type bitmap"))?,
+                    ttl,
+                    class,
+                })
+            },
+            50 => {
+                let algorithm = buffer.read()?;
+                let flags = buffer.read()?;
+                let iterations = buffer.read_u16()?;
+                let salt_len = buffer.read()?;
+                let salt = buffer.read_bytes(salt_len as usize)?;
+                let hash_len = buffer.read()?;
+                let next = buffer.read_bytes(hash_len as usize)?;
+                let bitmap = buffer.read_bytes((start + data_len as usize).saturating_sub(buffer.position()))?;
+                Ok(DnsRecord::NSEC3 {
+                    domain,
+                    algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next,
+                    types: parse_types(&bitmap).ok_or(DnsError::Malformed("NSEC3 type bitmap"))?,
+                    ttl,
+                    class,
+                })
+            },
+            48 if data_len >= 4 => {
+                let flags = buffer.read_u16()?;
+                let protocol = buffer.read()?;
+                let algorithm = buffer.read()?;
+                let public_key = buffer.read_bytes(data_len as usize - 4)?;
+                Ok(DnsRecord::DNSKEY {
+                    domain,
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                    ttl,
+                    class,
+                })
+            },
+            49 => {
+                let data = buffer.read_bytes(data_len as usize)?;
+                Ok(DnsRecord::DHCID {
+                    domain,
+                    data,
+                    ttl,
+                    class,
+                })
+            },
+            63 if data_len >= 6 => {
+                let serial = buffer.read_u32()?;
+                let scheme = buffer.read()?;
+                let algorithm = buffer.read()?;
+                let digest = buffer.read_bytes(data_len as usize - 6)?;
+                Ok(DnsRecord::ZONEMD {
+                    domain,
+                    serial,
+                    scheme,
+                    algorithm,
+                    digest,
+                    ttl,
+                    class,
+                })
+            },
+            64 | 65 => {
+                let priority = buffer.read_u16()?;
+                let mut target = String::new();
+                buffer.read_qname(&mut target)?;
+                let data = buffer.read_bytes((start + data_len as usize).saturating_sub(buffer.position()))?;
+                let params = parse_params(&data).ok_or(DnsError::Malformed("SvcParams"))?;
+                match qtype {
+                    64 => Ok(DnsRecord::SVCB { domain, priority, target, params, ttl, class }),
+                    _ => Ok(DnsRecord::HTTPS { domain, priority, target, params, ttl, class }),
+                }
+            },
+            108 if data_len == 6 => {
+                let mut addr = [0; 6];
+                addr.copy_from_slice(&buffer.read_bytes(6)?);
+                Ok(DnsRecord::EUI48 {
+                    domain,
+                    addr,
+                    ttl,
+                    class,
+                })
+            },
+            109 if data_len == 8 => {
+                let mut addr = [0; 8];
+                addr.copy_from_slice(&buffer.read_bytes(8)?);
+                Ok(DnsRecord::EUI64 {
+                    domain,
+                    addr,
+                    ttl,
+                    class,
+                })
+            },
+            256 if data_len >= 4 => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let target = buffer.read_bytes(data_len as usize - 4)?;
+                Ok(DnsRecord::URI {
+                    domain,
+                    priority,
+                    weight,
+                    target,
+                    ttl,
+                    class,
+                })
+            },
+            _ => {
+                // The data isn't kept, but has to be skipped to get to the next record
+                buffer.step(data_len as usize)?;
+                Ok(DnsRecord::UNKNOWN {
+                    domain,
+                    qtype,
+                    data_len,
+                    ttl,
+                    class,
+                })
+            }
+        };
+
+        // Strict parsing rejects a length that disagrees with the data, otherwise the length wins
+        let actual = buffer.position() - start;
+        if actual != data_len as usize {
+            if buffer.strict {
+                return Err(DnsError::RdataLength { expected: data_len, actual });
+            }
+            buffer.seek(start + data_len as usize)?;
+        }
+        record
+    }
+
+    pub fn domain(&self) -> &str {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::LOC { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::DNAME { domain, .. }
+            | DnsRecord::URI { domain, .. }
+            | DnsRecord::ZONEMD { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::DHCID { domain, .. }
+            | DnsRecord::EUI48 { domain, .. }
+            | DnsRecord::EUI64 { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. } => domain,
+            DnsRecord::OPT { .. } => "",
+        }
+    }
+
+    pub fn set_domain(&mut self, new_domain: String) {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::LOC { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::DNAME { domain, .. }
+            | DnsRecord::URI { domain, .. }
+            | DnsRecord::ZONEMD { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::DHCID { domain, .. }
+            | DnsRecord::EUI48 { domain, .. }
+            | DnsRecord::EUI64 { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. } => *domain = new_domain,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    pub fn query_type(&self) -> QueryType {
+        match self {
+            DnsRecord::UNKNOWN { qtype, .. } => QueryType::from_num(*qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::HINFO { .. } => QueryType::HINFO,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::LOC { .. } => QueryType::LOC,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::DNAME { .. } => QueryType::DNAME,
+            DnsRecord::URI { .. } => QueryType::URI,
+            DnsRecord::ZONEMD { .. } => QueryType::ZONEMD,
+            DnsRecord::SVCB { .. } => QueryType::SVCB,
+            DnsRecord::HTTPS { .. } => QueryType::HTTPS,
+            DnsRecord::DHCID { .. } => QueryType::DHCID,
+            DnsRecord::EUI48 { .. } => QueryType::EUI48,
+            DnsRecord::EUI64 { .. } => QueryType::EUI64,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+            DnsRecord::RRSIG { .. } => QueryType::RRSIG,
+            DnsRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DnsRecord::NSEC { .. } => QueryType::NSEC,
+            DnsRecord::NSEC3 { .. } => QueryType::NSEC3,
+        }
+    }
+
+    pub fn ttl(&self) -> u32 {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::LOC { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::URI { ttl, .. }
+            | DnsRecord::ZONEMD { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::DHCID { ttl, .. }
+            | DnsRecord::EUI48 { ttl, .. }
+            | DnsRecord::EUI64 { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. } => *ttl,
+            // Not a TTL, and no reason to hold an answer for a shorter time
+            DnsRecord::OPT { .. } => u32::MAX,
+        }
+    }
+
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::LOC { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::URI { ttl, .. }
+            | DnsRecord::ZONEMD { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::DHCID { ttl, .. }
+            | DnsRecord::EUI48 { ttl, .. }
+            | DnsRecord::EUI64 { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. } => *ttl = new_ttl,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    // OPT uses the CLASS field for its payload size, it has no class
+    pub fn class(&self) -> DnsClass {
+        match self {
+            DnsRecord::UNKNOWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::HINFO { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::LOC { class, .. }
+            | DnsRecord::SRV { class, .. }
+            | DnsRecord::DNAME { class, .. }
+            | DnsRecord::URI { class, .. }
+            | DnsRecord::ZONEMD { class, .. }
+            | DnsRecord::SVCB { class, .. }
+            | DnsRecord::HTTPS { class, .. }
+            | DnsRecord::DHCID { class, .. }
+            | DnsRecord::EUI48 { class, .. }
+            | DnsRecord::EUI64 { class, .. }
+            | DnsRecord::RRSIG { class, .. }
+            | DnsRecord::DNSKEY { class, .. }
+            | DnsRecord::NSEC { class, .. }
+            | DnsRecord::NSEC3 { class, .. } => *class,
+            DnsRecord::OPT { .. } => DnsClass::IN,
+        }
+    }
+
+    pub fn set_class(&mut self, new_class: DnsClass) {
+        match self {
+            DnsRecord::UNKNOWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::HINFO { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::LOC { class, .. }
+            | DnsRecord::SRV { class, .. }
+            | DnsRecord::DNAME { class, .. }
+            | DnsRecord::URI { class, .. }
+            | DnsRecord::ZONEMD { class, .. }
+            | DnsRecord::SVCB { class, .. }
+            | DnsRecord::HTTPS { class, .. }
+            | DnsRecord::DHCID { class, .. }
+            | DnsRecord::EUI48 { class, .. }
+            | DnsRecord::EUI64 { class, .. }
+            | DnsRecord::RRSIG { class, .. }
+            | DnsRecord::DNSKEY { class, .. }
+            | DnsRecord::NSEC { class, .. }
+            | DnsRecord::NSEC3 { class, .. } => *class = new_class,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    pub fn write(&self, buffer: &mut ByteBuffer) -> Result<()> {
+        match self {
+            DnsRecord::UNKNOWN { domain, qtype, ttl, .. } => {
+                debug!("Skipping unknown record: {} {} {}", domain, qtype, ttl)
+            },
+            DnsRecord::A { domain, addr, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::A.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                buffer.write_u16(4)?;
+                buffer.write_u32(u32::from(*addr))?;
+            },
+            DnsRecord::NS { domain, ns, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NS.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?; // sets initial length to 0
+                buffer.write_qname(ns)?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?; // sets the length to the actual length
+            },
+            DnsRecord::CNAME { domain, cname, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::CNAME.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_qname(cname)?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::SOA { domain, mname, rname, serial, refresh, retry, expire, minimum, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SOA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_qname(mname)?;
+                buffer.write_qname(rname)?;
+                for value in [serial, refresh, retry, expire, minimum] {
+                    buffer.write_u32(*value)?;
+                }
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::PTR { domain, host, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::PTR.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_qname(host)?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::HINFO { domain, cpu, os, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::HINFO.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                for string in [cpu, os] {
+                    let string = &string[..string.len().min(255)];
+                    buffer.write_u8(string.len() as u8)?;
+                    buffer.write_bytes(string)?;
+                }
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::MX { domain, preference, exchange, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::MX.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_u16(*preference)?;
+                buffer.write_qname(exchange)?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::TXT { domain, data, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TXT.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                for string in data {
+                    let string = &string[..string.len().min(255)];
+                    buffer.write_u8(string.len() as u8)?;
+                    buffer.write_bytes(string)?;
+                }
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::AAAA { domain, addr, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::AAAA.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+                buffer.write_u16(16)?;
+
+                let addr = addr.octets();
+                for octet in addr {
+                    buffer.write_u8(octet)?;
+                }
+            },
+            DnsRecord::LOC { domain, size, horizontal_precision, vertical_precision, latitude, longitude, altitude, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::LOC.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                buffer.write_u16(16)?;
+                for value in [0, *size, *horizontal_precision, *vertical_precision] {
+                    buffer.write_u8(value)?;
+                }
+                for value in [latitude, longitude, altitude] {
+                    buffer.write_u32(*value)?;
+                }
+            },
+            DnsRecord::SRV { domain, priority, weight, port, target, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SRV.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                for value in [priority, weight, port] {
+                    buffer.write_u16(*value)?;
+                }
+                buffer.write_qname(target)?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::DNAME { domain, target, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNAME.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_qname(target)?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::URI { domain, priority, weight, target, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::URI.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                buffer.write_u16(4 + target.len() as u16)?;
+                buffer.write_u16(*priority)?;
+                buffer.write_u16(*weight)?;
+                buffer.write_bytes(target)?;
+            },
+            DnsRecord::DHCID { domain, data, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DHCID.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                buffer.write_u16(data.len() as u16)?;
+                buffer.write_bytes(data)?;
+            },
+            DnsRecord::SVCB { domain, priority, target, params, ttl, class }
+            | DnsRecord::HTTPS { domain, priority, target, params, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(self.query_type().to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_u16(*priority)?;
+                buffer.write_qname(target)?;
+                buffer.write_bytes(&encode_params(params))?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::EUI48 { domain, addr, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::EUI48.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                buffer.write_u16(6)?;
+                buffer.write_bytes(addr)?;
+            },
+            DnsRecord::EUI64 { domain, addr, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::EUI64.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                buffer.write_u16(8)?;
+                buffer.write_bytes(addr)?;
+            },
+            DnsRecord::ZONEMD { domain, serial, scheme, algorithm, digest, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::ZONEMD.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                buffer.write_u16(6 + digest.len() as u16)?;
+                buffer.write_u32(*serial)?;
+                buffer.write_u8(*scheme)?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_bytes(digest)?;
+            },
+            DnsRecord::OPT { udp_size, flags, options } => {
+                buffer.write_u8(0)?;
+                buffer.write_u16(QueryType::OPT.to_num())?;
+                buffer.write_u16(*udp_size)?;
+                buffer.write_u32(*flags)?;
+
+                let data = encode_options(options);
+                buffer.write_u16(data.len() as u16)?;
+                for byte in data {
+                    buffer.write_u8(byte)?;
+                }
+            },
+            DnsRecord::RRSIG { domain, type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::RRSIG.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_u16(type_covered.to_num())?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_u8(*labels)?;
+                buffer.write_u32(*original_ttl)?;
+                buffer.write_u32(*expiration)?;
+                buffer.write_u32(*inception)?;
+                buffer.write_u16(*key_tag)?;
+                buffer.write_qname(signer)?; // never compressed (RFC 4034 3.1.7)
+                buffer.write_bytes(signature)?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::DNSKEY { domain, flags, protocol, algorithm, public_key, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::DNSKEY.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                buffer.write_u16(4 + public_key.len() as u16)?;
+                buffer.write_u16(*flags)?;
+                buffer.write_u8(*protocol)?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_bytes(public_key)?;
+            },
+            DnsRecord::NSEC { domain, next, types, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_qname(next)?; // never compressed (RFC 4034 4.1.1)
+                buffer.write_bytes(&encode_types(types))?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+            DnsRecord::NSEC3 { domain, algorithm, flags, iterations, salt, next, types, ttl, class } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::NSEC3.to_num())?;
+                buffer.write_u16(class.to_num())?;
+                buffer.write_u32(*ttl)?;
+
+                let start = buffer.position();
+                buffer.write_u16(0_u16)?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_u8(*flags)?;
+                buffer.write_u16(*iterations)?;
+                buffer.write_u8(salt.len() as u8)?;
+                buffer.write_bytes(salt)?;
+                buffer.write_u8(next.len() as u8)?;
+                buffer.write_bytes(next)?;
+                buffer.write_bytes(&encode_types(types))?;
+                let len = buffer.position() - (start+2);
+                buffer.set_u16(start, len as u16)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_to_hex_int(ip: Ipv4Addr) -> u32 {
+        let octets = ip.octets();
+        ((octets[0] as u32) << 24) |
+        ((octets[1] as u32) << 16) |
+        ((octets[2] as u32) << 8)  |
+        (octets[3] as u32)
+    }
+    
+
+    #[test]
+    fn test_read_a_record() {
+        let mut buffer = ByteBuffer::new();
+        let addr = Ipv4Addr::new(127, 0, 0, 1);
+        buffer.write_qname("example.com").unwrap();
+        buffer.write_u16(1).unwrap();
+        buffer.write_u16(1).unwrap();
+        buffer.write_u32(3600).unwrap();
+        buffer.write_u16(4).unwrap();
+        buffer.write_u32(ipv4_to_hex_int(addr)).unwrap();
+        buffer.seek(0).unwrap();
+
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(
+            record,
+            DnsRecord::A {
+                domain: "example.com".to_string(),
+                addr,
+                ttl: 3600,
+                class: DnsClass::IN,
+            }
+        );
+    }
+
+    #[test]
+    fn test_class_round_trip() {
+        for class in [DnsClass::IN, DnsClass::CH, DnsClass::NONE, DnsClass::UNKNOWN(42)] {
+            let record = DnsRecord::TXT { domain: "version.bind".to_string(), data: vec![b"1.0".to_vec()], ttl: 0, class };
+            let mut buffer = ByteBuffer::new();
+            record.write(&mut buffer).unwrap();
+            buffer.seek(0).unwrap();
+            let read = DnsRecord::read(&mut buffer).unwrap();
+            assert_eq!(read.class(), class);
+            assert_eq!(read, record);
+        }
+    }
+
+    #[test]
+    fn test_read_ns_record() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        buffer.write_u16(2).unwrap();
+        buffer.write_u16(1).unwrap();
+        buffer.write_u32(3600).unwrap();
+        buffer.write_u16(13).unwrap();
+        buffer.write_qname("ns1.example.com").unwrap();
+        buffer.seek(0).unwrap();
+
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(
+            record,
+            DnsRecord::NS {
+                domain: "example.com".to_string(),
+                ns: "ns1.example.com".to_string(),
+                ttl: 3600,
+                class: DnsClass::IN,
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_cname_record() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        buffer.write_u16(5).unwrap();
+        buffer.write_u16(1).unwrap();
+        buffer.write_u32(3600).unwrap();
+        buffer.write_u16(13).unwrap();
+        buffer.write_qname("cname.example.com").unwrap();
+        buffer.seek(0).unwrap();
+
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(
+            record,
+            DnsRecord::CNAME {
+                domain: "example.com".to_string(),
+                cname: "cname.example.com".to_string(),
+                ttl: 3600,
+                class: DnsClass::IN,
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_mx_record() {
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        buffer.write_u16(15).unwrap();
+        buffer.write_u16(1).unwrap();
+        buffer.write_u32(3600).unwrap();
+        buffer.write_u16(15).unwrap();
+        buffer.write_u16(10).unwrap();
+        buffer.write_qname("mx.example.com").unwrap();
+        buffer.seek(0).unwrap();
+
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(
+            record,
+            DnsRecord::MX {
+                domain: "example.com".to_string(),
+                preference: 10,
+                exchange: "mx.example.com".to_string(),
+                ttl: 3600,
+                class: DnsClass::IN,
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_aaaa_record() {
+        let mut buffer = ByteBuffer::new();
+        let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        buffer.write_qname("example.com").unwrap();
+        buffer.write_u16(28).unwrap();
+        buffer.write_u16(1).unwrap();
+        buffer.write_u32(3600).unwrap();
+        buffer.write_u16(16).unwrap();
+        buffer.write_u8(0x20).unwrap();
+        buffer.write_u8(0x01).unwrap();
+        buffer.write_u8(0x0d).unwrap();
+        buffer.write_u8(0xb8).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x00).unwrap();
+        buffer.write_u8(0x01).unwrap();
+        buffer.seek(0).unwrap();
+
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(
+            record,
+            DnsRecord::AAAA {
+                domain: "example.com".to_string(),
+                addr,
+                ttl: 3600,
+                class: DnsClass::IN,
+            }
+        );
+
+    }
+
+    #[test]
+    fn test_write_a_record() {
+        let mut buffer = ByteBuffer::new();
+        let record = DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: Ipv4Addr::new(127, 0, 0, 1),
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        record.write(&mut buffer).unwrap();
+        buffer.seek(0).unwrap();
+
+        let mut str = String::new();
+        buffer.read_qname(&mut str).unwrap();
+        assert_eq!(str, "example.com");
+        assert_eq!(buffer.read_u16().unwrap(), 1);
+        assert_eq!(buffer.read_u16().unwrap(), 1);
+        assert_eq!(buffer.read_u32().unwrap(), 3600);
+        assert_eq!(buffer.read_u16().unwrap(), 4);
+        assert_eq!(buffer.read_u32().unwrap(), ipv4_to_hex_int(Ipv4Addr::new(127, 0, 0, 1)));
+    }
+
+    #[test]
+    fn test_write_ns_record() {
+        let record = DnsRecord::NS {
+            domain: "example.com".to_string(),
+            ns: "ns.example.com".to_string(),
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        let mut domain = String::new();
+        buffer.read_qname(&mut domain).unwrap();
+        assert_eq!(domain, "example.com");
+        assert_eq!(buffer.read_u16().unwrap(), 2); // QueryType::NS
+        assert_eq!(buffer.read_u16().unwrap(), 1); // Class
+        assert_eq!(buffer.read_u32().unwrap(), 3600); // TTL
+        let data_len = buffer.read_u16().unwrap();
+        assert!(data_len > 0); // Ensure data length is set correctly
+        let mut ns = String::new();
+        buffer.read_qname(&mut ns).unwrap();
+        assert_eq!(ns, "ns.example.com");
+    }
+
+    #[test]
+    fn test_write_cname_record() {
+        let record = DnsRecord::CNAME {
+            domain: "example.com".to_string(),
+            cname: "cname.example.com".to_string(),
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        let mut domain = String::new();
+        buffer.read_qname(&mut domain).unwrap();
+        assert_eq!(domain, "example.com");
+        assert_eq!(buffer.read_u16().unwrap(), 5); // QueryType::CNAME
+        assert_eq!(buffer.read_u16().unwrap(), 1); // Class
+        assert_eq!(buffer.read_u32().unwrap(), 3600); // TTL
+        let data_len = buffer.read_u16().unwrap();
+        assert!(data_len > 0); // Ensure data length is set correctly
+        let mut cname = String::new();
+        buffer.read_qname(&mut cname).unwrap();
+        assert_eq!(cname, "cname.example.com");
+    }
+
+    #[test]
+    fn test_read_write_ptr_record() {
+        let record = DnsRecord::PTR {
+            domain: "1.2.0.192.in-addr.arpa".to_string(),
+            host: "host.example.com".to_string(),
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+        assert_eq!(record.ttl(), 3600);
+    }
+
+    #[test]
+    fn test_read_write_txt_record() {
+        let record = DnsRecord::TXT {
+            domain: "example.com".to_string(),
+            data: vec![b"v=spf1 -all".to_vec(), vec![0, 255, 10]],
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+    }
+
+    #[test]
+    fn test_write_mx_record() {
+        let record = DnsRecord::MX {
+            domain: "example.com".to_string(),
+            preference: 10,
+            exchange: "mx.example.com".to_string(),
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        let mut domain = String::new();
+        buffer.read_qname(&mut domain).unwrap();
+        assert_eq!(domain, "example.com");
+        assert_eq!(buffer.read_u16().unwrap(), 15); // QueryType::MX
+        assert_eq!(buffer.read_u16().unwrap(), 1); // Class
+        assert_eq!(buffer.read_u32().unwrap(), 3600); // TTL
+        let data_len = buffer.read_u16().unwrap();
+        assert!(data_len > 0); // Ensure data length is set correctly
+        assert_eq!(buffer.read_u16().unwrap(), 10); // Preference
+        let mut exchange = String::new();
+        buffer.read_qname(&mut exchange).unwrap();
+        assert_eq!(exchange, "mx.example.com");
+    }
+
+    #[test]
+    fn test_write_aaaa_record() {
+        let record = DnsRecord::AAAA {
+            domain: "example.com".to_string(),
+            addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).unwrap();
+
+        buffer.seek(0).unwrap();
+        let mut domain = String::new();
+        buffer.read_qname(&mut domain).unwrap();
+        assert_eq!(domain, "example.com");
+        assert_eq!(buffer.read_u16().unwrap(), 28); // QueryType::AAAA
+        assert_eq!(buffer.read_u16().unwrap(), 1); // Class
+        assert_eq!(buffer.read_u32().unwrap(), 3600); // TTL
+        let data_len = buffer.read_u16().unwrap();
+        assert_eq!(data_len, 16); // Ensure data length is set correctly
+        let mut addr = [0u8; 16];
+        for octet in addr.iter_mut() {
+            *octet = buffer.read().unwrap();
+        }
+        assert_eq!(addr, Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+    }
+
+    #[test]
+    fn test_soa_round_trip() {
+        let record = DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024061801,
+            refresh: 7200,
+            retry: 900,
+            expire: 1209600,
+            minimum: 300,
+            ttl: 3600,
+            class: DnsClass::IN,
+        };
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).unwrap();
+        let end = buffer.position();
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+        assert_eq!(buffer.position(), end);
+    }
+
+    #[test]
+    fn test_srv_round_trip() {
+        let record = DnsRecord::SRV {
+            domain: "_ipp._tcp.example.com".to_string(),
+            priority: 10,
+            weight: 60,
+            port: 631,
+            target: "printer.example.com".to_string(),
+            ttl: 120,
+            class: DnsClass::IN,
+        };
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).unwrap();
+        let end = buffer.position();
+
+        buffer.seek(0).unwrap();
+        assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+        assert_eq!(buffer.position(), end);
+    }
+
+    #[test]
+    fn test_hinfo_loc_and_uri_round_trip() {
+        let loc = DnsRecord::LOC {
+            domain: "caida.org".to_string(),
+            size: 0x33,
+            horizontal_precision: 0x13,
+            vertical_precision: 0x13,
+            latitude: 2265864648,
+            longitude: 1725418648,
+            altitude: 10010700,
+            ttl: 300,
+            class: DnsClass::IN,
+        };
+        for record in [DnsRecord::hinfo("example.com", "x86_64", "Linux", 300), loc, DnsRecord::uri("_ftp._tcp.example.com", 10, 1, "ftp://ftp1.example.com/public", 300)] {
+            let mut buffer = ByteBuffer::new();
+            record.write(&mut buffer).unwrap();
+            let end = buffer.position();
+
+            buffer.seek(0).unwrap();
+            buffer.strict = true;
+            assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+            assert_eq!(buffer.position(), end);
+        }
+
+        // Only version 0 of LOC is defined
+        let mut buffer = ByteBuffer::new();
+        buffer.write_qname("example.com").unwrap();
+        for value in [29, 1, 0, 300, 16] {
+            buffer.write_u16(value).unwrap();
+        }
+        buffer.write_bytes(&[1; 16]).unwrap();
+        buffer.seek(0).unwrap();
+        assert!(matches!(DnsRecord::read(&mut buffer).unwrap(), DnsRecord::UNKNOWN { qtype: 29, .. }));
+    }
+
+    #[test]
+    fn test_svcb_round_trip() {
+        let params = vec![SvcParam::alpn(&["h2", "h3"]), SvcParam::port(8443), SvcParam::ipv4hint(&[Ipv4Addr::new(192, 0, 2, 1)])];
+        let records = [
+            DnsRecord::HTTPS { domain: "example.com".to_string(), priority: 1, target: "".to_string(), params, ttl: 300, class: DnsClass::IN },
+            DnsRecord::SVCB { domain: "_dns.resolver.arpa".to_string(), priority: 0, target: "dns.example.net".to_string(), params: Vec::new(), ttl: 300, class: DnsClass::IN },
+        ];
+        for record in records {
+            let mut buffer = ByteBuffer::new();
+            record.write(&mut buffer).unwrap();
+            let end = buffer.position();
+
+            buffer.seek(0).unwrap();
+            buffer.strict = true;
+            assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+            assert_eq!(buffer.position(), end);
+        }
+    }
+
+    #[test]
+    fn test_eui_and_dhcid_round_trip() {
+        let records = [
+            DnsRecord::EUI48 { domain: "host.example".to_string(), addr: [0x00, 0x00, 0x5e, 0x00, 0x53, 0x2a], ttl: 300, class: DnsClass::IN },
+            DnsRecord::EUI64 { domain: "host.example".to_string(), addr: [0x00, 0x00, 0x5e, 0xef, 0x10, 0x00, 0x00, 0x2a], ttl: 300, class: DnsClass::IN },
+            DnsRecord::DHCID { domain: "client.example.com".to_string(), data: vec![0, 1, 1, 0xab, 0xcd, 0xef], ttl: 86400, class: DnsClass::IN },
+        ];
+        for record in records {
+            let mut buffer = ByteBuffer::new();
+            record.write(&mut buffer).unwrap();
+            let end = buffer.position();
+
+            buffer.seek(0).unwrap();
+            buffer.strict = true;
+            assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+            assert_eq!(buffer.position(), end);
+        }
+    }
+
+    #[test]
+    fn test_dnskey_and_rrsig_round_trip() {
+        let records = [
+            DnsRecord::DNSKEY { domain: "example.com".to_string(), flags: 257, protocol: 3, algorithm: 15, public_key: vec![7; 32], ttl: 3600, class: DnsClass::IN },
+            DnsRecord::RRSIG {
+                domain: "example.com".to_string(),
+                type_covered: QueryType::MX,
+                algorithm: 15,
+                labels: 2,
+                original_ttl: 3600,
+                expiration: 1440021600,
+                inception: 1438207200,
+                key_tag: 3613,
+                signer: "example.com".to_string(),
+                signature: vec![9; 64],
+                ttl: 3600,
+                class: DnsClass::IN,
+            },
+        ];
+        for record in records {
+            let mut buffer = ByteBuffer::new();
+            record.write(&mut buffer).unwrap();
+            let end = buffer.position();
+
+            buffer.seek(0).unwrap();
+            buffer.strict = true;
+            assert_eq!(DnsRecord::read(&mut buffer).unwrap(), record);
+            assert_eq!(buffer.position(), end);
+        }
+    }
+}
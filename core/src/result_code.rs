@@ -0,0 +1,124 @@
+/**
+The RCODE of a message, see the IANA "DNS RCODEs" registry. The header only has room for 4 bits,
+the values from 16 up are extended RCODEs (RFC 6891 section 6.1.3) whose upper 8 bits travel in the
+TTL field of the OPT record: `DnsPacket` merges the two when reading and splits them when writing.
+*/
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ResultCode {
+    #[default]
+    NOERROR,
+    FORMERR,
+    SERVFAIL,
+    NXDOMAIN,
+    NOTIMP,
+    REFUSED,
+    YXDOMAIN,  // 6, RFC 2136
+    YXRRSET,   // 7
+    NXRRSET,   // 8
+    NOTAUTH,   // 9
+    NOTZONE,   // 10
+    DSOTYPENI, // 11, RFC 8490
+    BADVERS,   // 16, an EDNS version the server doesn't do
+    BADKEY,    // 17, from here on for TSIG and TKEY (RFC 8945, RFC 2930)
+    BADTIME,   // 18
+    BADMODE,   // 19
+    BADNAME,   // 20
+    BADALG,    // 21
+    BADTRUNC,  // 22
+    BADCOOKIE, // 23, RFC 7873
+    UNKNOWN(u16), // unassigned, only the low 12 bits can be sent
+}
+
+// The largest RCODE the header and an OPT record can carry between them
+pub const MAX_RCODE: u16 = 0x0FFF;
+
+impl ResultCode {
+    // BADSIG shares 16 with BADVERS, it only appears in TSIG records
+    pub const BADSIG: ResultCode = ResultCode::BADVERS;
+
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            ResultCode::NOERROR => 0,
+            ResultCode::FORMERR => 1,
+            ResultCode::SERVFAIL => 2,
+            ResultCode::NXDOMAIN => 3,
+            ResultCode::NOTIMP => 4,
+            ResultCode::REFUSED => 5,
+            ResultCode::YXDOMAIN => 6,
+            ResultCode::YXRRSET => 7,
+            ResultCode::NXRRSET => 8,
+            ResultCode::NOTAUTH => 9,
+            ResultCode::NOTZONE => 10,
+            ResultCode::DSOTYPENI => 11,
+            ResultCode::BADVERS => 16,
+            ResultCode::BADKEY => 17,
+            ResultCode::BADTIME => 18,
+            ResultCode::BADMODE => 19,
+            ResultCode::BADNAME => 20,
+            ResultCode::BADALG => 21,
+            ResultCode::BADTRUNC => 22,
+            ResultCode::BADCOOKIE => 23,
+            ResultCode::UNKNOWN(num) => num,
+        }
+    }
+
+    pub fn from_num(num: u16) -> ResultCode {
+        match num {
+            0 => ResultCode::NOERROR,
+            1 => ResultCode::FORMERR,
+            2 => ResultCode::SERVFAIL,
+            3 => ResultCode::NXDOMAIN,
+            4 => ResultCode::NOTIMP,
+            5 => ResultCode::REFUSED,
+            6 => ResultCode::YXDOMAIN,
+            7 => ResultCode::YXRRSET,
+            8 => ResultCode::NXRRSET,
+            9 => ResultCode::NOTAUTH,
+            10 => ResultCode::NOTZONE,
+            11 => ResultCode::DSOTYPENI,
+            16 => ResultCode::BADVERS,
+            17 => ResultCode::BADKEY,
+            18 => ResultCode::BADTIME,
+            19 => ResultCode::BADMODE,
+            20 => ResultCode::BADNAME,
+            21 => ResultCode::BADALG,
+            22 => ResultCode::BADTRUNC,
+            23 => ResultCode::BADCOOKIE,
+            _ => ResultCode::UNKNOWN(num),
+        }
+    }
+
+    // The 4 bits that go in the header
+    pub fn low_bits(&self) -> u8 {
+        (self.to_num() & 0x0F) as u8
+    }
+
+    // The 8 bits that go at the top of an OPT record's TTL, 0 for RCODEs that fit the header
+    pub fn extended_bits(&self) -> u8 {
+        ((self.to_num() & MAX_RCODE) >> 4) as u8
+    }
+
+    pub fn from_parts(low_bits: u8, extended_bits: u8) -> ResultCode {
+        ResultCode::from_num((extended_bits as u16) << 4 | (low_bits & 0x0F) as u16)
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.extended_bits() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_merge() {
+        for num in 0..=MAX_RCODE {
+            let rcode = ResultCode::from_num(num);
+            assert_eq!(rcode.to_num(), num);
+            assert_eq!(ResultCode::from_parts(rcode.low_bits(), rcode.extended_bits()), rcode);
+        }
+        assert_eq!((ResultCode::BADCOOKIE.low_bits(), ResultCode::BADCOOKIE.extended_bits()), (7, 1));
+        assert!(!ResultCode::REFUSED.is_extended() && ResultCode::BADSIG.is_extended());
+    }
+}
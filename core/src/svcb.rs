@@ -0,0 +1,320 @@
+/*!
+SVCB and HTTPS records (RFC 9460): their parameters, and a helper that turns a name's records
+into the endpoints to try, in order, so clients can adopt them without knowing the record format.
+*/
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::query_type::QueryType;
+use crate::record::DnsRecord;
+
+pub const KEY_MANDATORY: u16 = 0;
+pub const KEY_ALPN: u16 = 1;
+pub const KEY_NO_DEFAULT_ALPN: u16 = 2;
+pub const KEY_PORT: u16 = 3;
+pub const KEY_IPV4HINT: u16 = 4;
+pub const KEY_ECH: u16 = 5;
+pub const KEY_IPV6HINT: u16 = 6;
+
+// The names of the keys above in presentation format, others are written keyNNNNN
+const KEY_NAMES: [&str; 7] = ["mandatory", "alpn", "no-default-alpn", "port", "ipv4hint", "ech", "ipv6hint"];
+
+// AliasMode records followed before giving up, like CNAME chains
+pub const MAX_ALIASES: usize = 8;
+
+// What HTTPS clients speak without an alpn saying otherwise (RFC 9460 7.1.1)
+const DEFAULT_HTTPS_ALPN: &str = "http/1.1";
+
+pub fn key_name(key: u16) -> String {
+    KEY_NAMES.get(key as usize).map_or_else(|| format!("key{}", key), |name| name.to_string())
+}
+
+pub fn key_from_name(name: &str) -> Option<u16> {
+    match KEY_NAMES.iter().position(|known| known.eq_ignore_ascii_case(name)) {
+        Some(key) => Some(key as u16),
+        None => name.strip_prefix("key")?.parse().ok(),
+    }
+}
+
+// One SvcParam, the value in wire format (RFC 9460 2.2)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SvcParam {
+    pub key: u16,
+    pub value: Vec<u8>,
+}
+
+impl SvcParam {
+    pub fn new(key: u16, value: Vec<u8>) -> SvcParam {
+        SvcParam { key, value }
+    }
+
+    pub fn alpn(ids: &[&str]) -> SvcParam {
+        let mut value = Vec::new();
+        for id in ids {
+            value.push(id.len() as u8);
+            value.extend_from_slice(id.as_bytes());
+        }
+        SvcParam::new(KEY_ALPN, value)
+    }
+
+    pub fn port(port: u16) -> SvcParam {
+        SvcParam::new(KEY_PORT, port.to_be_bytes().to_vec())
+    }
+
+    pub fn ipv4hint(addrs: &[Ipv4Addr]) -> SvcParam {
+        SvcParam::new(KEY_IPV4HINT, addrs.iter().flat_map(|addr| addr.octets()).collect())
+    }
+
+    pub fn ipv6hint(addrs: &[Ipv6Addr]) -> SvcParam {
+        SvcParam::new(KEY_IPV6HINT, addrs.iter().flat_map(|addr| addr.octets()).collect())
+    }
+}
+
+// Keys have to come in increasing order without repeats, a record breaking that is malformed
+pub fn parse_params(mut data: &[u8]) -> Option<Vec<SvcParam>> {
+    let mut params: Vec<SvcParam> = Vec::new();
+    while !data.is_empty() {
+        let key = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+        let len = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as usize;
+        if params.last().is_some_and(|last| last.key >= key) {
+            return None;
+        }
+        params.push(SvcParam::new(key, data.get(4..4 + len)?.to_vec()));
+        data = &data[4 + len..];
+    }
+    Some(params)
+}
+
+pub fn encode_params(params: &[SvcParam]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for param in params {
+        data.extend_from_slice(&param.key.to_be_bytes());
+        data.extend_from_slice(&(param.value.len() as u16).to_be_bytes());
+        data.extend_from_slice(&param.value);
+    }
+    data
+}
+
+// The length-prefixed protocol ids of an alpn value, None when they don't fill it exactly
+pub fn alpn_ids(mut value: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut ids = Vec::new();
+    while let Some((&len, rest)) = value.split_first() {
+        ids.push(rest.get(..len as usize).filter(|id| !id.is_empty())?);
+        value = &rest[len as usize..];
+    }
+    (!ids.is_empty()).then_some(ids)
+}
+
+// The value split into N-byte items, None when it is empty or doesn't split evenly
+fn items<const N: usize, T>(value: &[u8], item: impl Fn([u8; N]) -> T) -> Option<Vec<T>> {
+    (!value.is_empty() && value.len().is_multiple_of(N)).then(|| value.chunks(N).map(|chunk| item(chunk.try_into().unwrap())).collect())
+}
+
+pub fn u16_list(value: &[u8]) -> Option<Vec<u16>> {
+    items(value, u16::from_be_bytes)
+}
+
+pub fn ipv4_list(value: &[u8]) -> Option<Vec<Ipv4Addr>> {
+    items(value, Ipv4Addr::from)
+}
+
+pub fn ipv6_list(value: &[u8]) -> Option<Vec<Ipv6Addr>> {
+    items(value, Ipv6Addr::from)
+}
+
+/**
+One place to connect to, from a ServiceMode record. `alpn` has the protocols to offer in the
+TLS handshake, the HTTPS default included unless the record left it out, and the hints are
+addresses to try while the target's A and AAAA records are looked up.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub priority: u16,
+    pub target: String,
+    pub port: u16,
+    pub alpn: Vec<String>,
+    pub ipv4hint: Vec<Ipv4Addr>,
+    pub ipv6hint: Vec<Ipv6Addr>,
+    pub ech: Option<Vec<u8>>, // an ECHConfigList, for clients doing Encrypted Client Hello
+}
+
+impl Endpoint {
+    // The origin itself, what clients connect to when a name has no usable records
+    pub fn fallback(host: &str, port: u16) -> Endpoint {
+        Endpoint { priority: 0, target: host.to_string(), port, alpn: Vec::new(), ipv4hint: Vec::new(), ipv6hint: Vec::new(), ech: None }
+    }
+
+    // None when a key is mandatory that we don't know or a value we use is malformed
+    fn from_record(owner: &str, priority: u16, target: &str, params: &[SvcParam], https: bool, port: u16) -> Option<Endpoint> {
+        let value = |key: u16| params.iter().find(|param| param.key == key).map(|param| param.value.as_slice());
+        if let Some(mandatory) = value(KEY_MANDATORY) {
+            if u16_list(mandatory)?.iter().any(|key| !(KEY_ALPN..=KEY_IPV6HINT).contains(key)) {
+                return None;
+            }
+        }
+        let mut alpn: Vec<String> = match value(KEY_ALPN) {
+            Some(alpn) => alpn_ids(alpn)?.into_iter().map(|id| String::from_utf8_lossy(id).into_owned()).collect(),
+            None => Vec::new(),
+        };
+        if https && value(KEY_NO_DEFAULT_ALPN).is_none() && !alpn.iter().any(|id| id == DEFAULT_HTTPS_ALPN) {
+            alpn.push(DEFAULT_HTTPS_ALPN.to_string());
+        }
+        Some(Endpoint {
+            priority,
+            target: if target.is_empty() || target == "." { owner.to_string() } else { target.to_string() },
+            port: value(KEY_PORT).map_or(Some(port), |port| Some(u16::from_be_bytes(port.try_into().ok()?)))?,
+            alpn,
+            ipv4hint: value(KEY_IPV4HINT).map_or(Some(Vec::new()), ipv4_list)?,
+            ipv6hint: value(KEY_IPV6HINT).map_or(Some(Vec::new()), ipv6_list)?,
+            ech: value(KEY_ECH).map(<[u8]>::to_vec),
+        })
+    }
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+// (priority, target, params, is HTTPS) of an SVCB or HTTPS record
+fn service(record: &DnsRecord) -> Option<(u16, &str, &[SvcParam], bool)> {
+    match record {
+        DnsRecord::SVCB { priority, target, params, .. } => Some((*priority, target, params, false)),
+        DnsRecord::HTTPS { priority, target, params, .. } => Some((*priority, target, params, true)),
+        _ => None,
+    }
+}
+
+/**
+The endpoints the ServiceMode records of `owner` offer, lowest priority first, records of equal
+priority in the order given. `port` is used where a record has no port of its own. Records that
+need a parameter we don't understand are skipped, as RFC 9460 8 requires.
+*/
+pub fn endpoints(owner: &str, records: &[DnsRecord], port: u16) -> Vec<Endpoint> {
+    let mut endpoints: Vec<Endpoint> = records.iter()
+        .filter(|record| same_name(record.domain(), owner))
+        .filter_map(service)
+        .filter(|(priority, ..)| *priority > 0)
+        .filter_map(|(priority, target, params, https)| Endpoint::from_record(owner, priority, target, params, https, port))
+        .collect();
+    endpoints.sort_by_key(|endpoint| endpoint.priority);
+    endpoints
+}
+
+// Where `name` ends up after the CNAMEs among the records
+fn follow_cnames<'a>(mut name: &'a str, records: &'a [DnsRecord]) -> &'a str {
+    for _ in 0..records.len() {
+        match records.iter().find_map(|record| match record {
+            DnsRecord::CNAME { domain, cname, .. } if same_name(domain, name) => Some(cname.as_str()),
+            _ => None,
+        }) {
+            Some(cname) => name = cname,
+            None => break,
+        }
+    }
+    name
+}
+
+/**
+Resolves `qname`'s SVCB or HTTPS records (`qtype`) into the endpoints to try, in order, with
+`lookup` doing the queries: it gets a name and type and returns the answer records, so any
+transport works. AliasMode records are followed to the name they point to. When nothing usable
+is found, the origin `host` and `port` come back as the only endpoint, so the list is never
+empty and callers can always just go through it.
+*/
+pub fn resolve<E>(
+    qname: &str,
+    qtype: QueryType,
+    host: &str,
+    port: u16,
+    mut lookup: impl FnMut(&str, QueryType) -> Result<Vec<DnsRecord>, E>,
+) -> Result<Vec<Endpoint>, E> {
+    let mut name = qname.to_string();
+    for _ in 0..=MAX_ALIASES {
+        let records = lookup(&name, qtype)?;
+        let owner = follow_cnames(&name, &records).to_string();
+        let found = endpoints(&owner, &records, port);
+        if !found.is_empty() {
+            return Ok(found);
+        }
+        let alias = records.iter()
+            .filter(|record| same_name(record.domain(), &owner))
+            .filter_map(service)
+            .find(|(priority, ..)| *priority == 0);
+        match alias {
+            // "." as the target says the service doesn't exist (RFC 9460 2.5.1)
+            Some((_, target, ..)) if !target.is_empty() && target != "." && !same_name(target, &owner) => name = target.to_string(),
+            _ => break,
+        }
+    }
+    Ok(alloc::vec![Endpoint::fallback(host, port)])
+}
+
+// The name HTTPS records of an origin are at, "_8443._https.example.com" for ports other than 443 (RFC 9460 9.1)
+pub fn https_name(host: &str, port: u16) -> String {
+    match port {
+        443 => host.to_string(),
+        _ => format!("_{}._https.{}", port, host),
+    }
+}
+
+// `resolve` for the HTTPS records of an https:// origin
+pub fn resolve_https<E>(host: &str, port: u16, lookup: impl FnMut(&str, QueryType) -> Result<Vec<DnsRecord>, E>) -> Result<Vec<Endpoint>, E> {
+    resolve(&https_name(host, port), QueryType::HTTPS, host, port, lookup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use crate::dns_class::DnsClass;
+
+    fn https(domain: &str, priority: u16, target: &str, params: Vec<SvcParam>) -> DnsRecord {
+        DnsRecord::HTTPS { domain: domain.to_string(), priority, target: target.to_string(), params, ttl: 300, class: DnsClass::IN }
+    }
+
+    #[test]
+    fn test_params_round_trip() {
+        let params = vec![SvcParam::alpn(&["h2", "h3"]), SvcParam::port(8443), SvcParam::ipv4hint(&[Ipv4Addr::new(192, 0, 2, 1)])];
+        assert_eq!(parse_params(&encode_params(&params)), Some(params.clone()));
+
+        // Keys out of order make the list malformed
+        let reversed: Vec<SvcParam> = params.into_iter().rev().collect();
+        assert_eq!(parse_params(&encode_params(&reversed)), None);
+        assert_eq!(key_from_name("key65000"), Some(65000));
+        assert_eq!(key_name(KEY_NO_DEFAULT_ALPN), "no-default-alpn");
+    }
+
+    #[test]
+    fn test_resolve_https() {
+        let records = |name: &str| match name {
+            "example.com" => vec![https("example.com", 0, "svc.example.net", Vec::new())],
+            "svc.example.net" => vec![
+                https("svc.example.net", 2, "backup.example.net", vec![SvcParam::alpn(&["h2"])]),
+                https("svc.example.net", 1, ".", vec![SvcParam::alpn(&["h3"]), SvcParam::port(8443), SvcParam::ipv6hint(&["2001:db8::1".parse().unwrap()])]),
+                https("svc.example.net", 1, "future.example.net", vec![SvcParam::new(KEY_MANDATORY, vec![0, 9]), SvcParam::new(9, vec![1])]),
+            ],
+            _ => Vec::new(),
+        };
+        let mut queried = Vec::new();
+        let endpoints = resolve_https("example.com", 443, |name, qtype| {
+            queried.push((name.to_string(), qtype));
+            Ok::<_, ()>(records(name))
+        }).unwrap();
+
+        assert_eq!(queried, vec![("example.com".to_string(), QueryType::HTTPS), ("svc.example.net".to_string(), QueryType::HTTPS)]);
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!((endpoints[0].target.as_str(), endpoints[0].port), ("svc.example.net", 8443));
+        assert_eq!(endpoints[0].alpn, vec!["h3", "http/1.1"]);
+        assert_eq!(endpoints[0].ipv6hint, vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]);
+        assert_eq!((endpoints[1].target.as_str(), endpoints[1].port, endpoints[1].priority), ("backup.example.net", 443, 2));
+
+        // Without records, or with an alias to ".", the origin is what's left
+        let fallback = resolve_https("example.org", 8443, |name, _| {
+            assert_eq!(name, "_8443._https.example.org");
+            Ok::<_, ()>(vec![https(name, 0, ".", Vec::new())])
+        }).unwrap();
+        assert_eq!(fallback, vec![Endpoint::fallback("example.org", 8443)]);
+    }
+}
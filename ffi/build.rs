@@ -0,0 +1,18 @@
+use std::env;
+use std::path::Path;
+
+// Regenerates include/r_dns.h from the extern "C" functions in src/lib.rs
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let crate_dir = Path::new(&crate_dir);
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).expect("Failed to read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(crate_dir.join("src/lib.rs"))
+        .generate()
+        .expect("Failed to generate the C header")
+        .write_to_file(crate_dir.join("include/r_dns.h"));
+}
@@ -0,0 +1,82 @@
+/* Generated by cbindgen from ffi/src/lib.rs, don't edit by hand */
+
+#ifndef R_DNS_H
+#define R_DNS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define RDNS_ERR_INVALID -1
+
+#define RDNS_ERR_TOO_SMALL -2
+
+#define RDNS_ERR_ENCODE -3
+
+#define RDNS_ERR_NO_DATA -4
+
+#define RDNS_SECTION_ANSWER 0
+
+#define RDNS_SECTION_AUTHORITY 1
+
+#define RDNS_SECTION_ADDITIONAL 2
+
+typedef struct RDnsPacket RDnsPacket;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct RDnsPacket *rdns_packet_new(void);
+
+struct RDnsPacket *rdns_packet_parse(const uint8_t *data, uintptr_t len);
+
+void rdns_packet_free(struct RDnsPacket *packet);
+
+intptr_t rdns_packet_serialize(const struct RDnsPacket *packet, uint8_t *out, uintptr_t out_len);
+
+uint16_t rdns_packet_id(const struct RDnsPacket *packet);
+
+void rdns_packet_set_id(struct RDnsPacket *packet, uint16_t id);
+
+bool rdns_packet_is_response(const struct RDnsPacket *packet);
+
+uint16_t rdns_packet_rcode(const struct RDnsPacket *packet);
+
+void rdns_packet_set_recursion_desired(struct RDnsPacket *packet, bool recursion_desired);
+
+uintptr_t rdns_packet_question_count(const struct RDnsPacket *packet);
+
+intptr_t rdns_packet_record_count(const struct RDnsPacket *packet, uint32_t section);
+
+intptr_t rdns_packet_add_question(struct RDnsPacket *packet, const char *name, uint16_t qtype);
+
+intptr_t rdns_question_name(const struct RDnsPacket *packet,
+                            uintptr_t index,
+                            char *out,
+                            uintptr_t out_len);
+
+uint16_t rdns_question_type(const struct RDnsPacket *packet, uintptr_t index);
+
+intptr_t rdns_record_name(const struct RDnsPacket *packet,
+                          uint32_t section,
+                          uintptr_t index,
+                          char *out,
+                          uintptr_t out_len);
+
+uint16_t rdns_record_type(const struct RDnsPacket *packet, uint32_t section, uintptr_t index);
+
+uint32_t rdns_record_ttl(const struct RDnsPacket *packet, uint32_t section, uintptr_t index);
+
+intptr_t rdns_record_data(const struct RDnsPacket *packet,
+                          uint32_t section,
+                          uintptr_t index,
+                          char *out,
+                          uintptr_t out_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* R_DNS_H */
@@ -0,0 +1,273 @@
+/*!
+C bindings for the DNS message code in `r_dns_core`, declared in `include/r_dns.h`.
+
+A message is parsed into (or created as) an opaque `RDnsPacket` handle that the caller releases
+with `rdns_packet_free`. Every pointer passed in has to be valid for the length given with it,
+strings are NUL-terminated. Functions that fill a caller's buffer return the number of bytes
+written (without the terminating NUL for strings) or one of the negative `RDNS_ERR_` codes.
+*/
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, CStr};
+use std::{ptr, slice};
+
+use r_dns_core::byte_buffer::ByteBuffer;
+use r_dns_core::packet::DnsPacket;
+use r_dns_core::presentation::record_data;
+use r_dns_core::query_type::QueryType;
+use r_dns_core::question::DnsQuestion;
+use r_dns_core::record::DnsRecord;
+
+// A null pointer, a string that isn't UTF-8, an unknown section or an index past the end of the section
+pub const RDNS_ERR_INVALID: isize = -1;
+// The output buffer can't hold the result
+pub const RDNS_ERR_TOO_SMALL: isize = -2;
+// The message can't be encoded, e.g. a name with a label over 63 bytes
+pub const RDNS_ERR_ENCODE: isize = -3;
+// The record has no presentation format, OPT and types the parser doesn't know
+pub const RDNS_ERR_NO_DATA: isize = -4;
+
+pub struct RDnsPacket {
+    packet: DnsPacket,
+}
+
+// The `section` of the record accessors. A plain integer rather than an enum, since C can pass any value.
+pub const RDNS_SECTION_ANSWER: u32 = 0;
+pub const RDNS_SECTION_AUTHORITY: u32 = 1;
+pub const RDNS_SECTION_ADDITIONAL: u32 = 2;
+
+impl RDnsPacket {
+    fn records(&self, section: u32) -> Option<&[DnsRecord]> {
+        match section {
+            RDNS_SECTION_ANSWER => Some(&self.packet.answers),
+            RDNS_SECTION_AUTHORITY => Some(&self.packet.authorities),
+            RDNS_SECTION_ADDITIONAL => Some(&self.packet.resources),
+            _ => None,
+        }
+    }
+}
+
+unsafe fn copy_bytes(bytes: &[u8], out: *mut u8, out_len: usize) -> isize {
+    if out.is_null() {
+        return RDNS_ERR_INVALID;
+    }
+    if bytes.len() > out_len {
+        return RDNS_ERR_TOO_SMALL;
+    }
+    ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+    bytes.len() as isize
+}
+
+unsafe fn copy_string(string: &str, out: *mut c_char, out_len: usize) -> isize {
+    if out.is_null() {
+        return RDNS_ERR_INVALID;
+    }
+    if string.len() >= out_len {
+        return RDNS_ERR_TOO_SMALL;
+    }
+    ptr::copy_nonoverlapping(string.as_ptr(), out as *mut u8, string.len());
+    *out.add(string.len()) = 0;
+    string.len() as isize
+}
+
+unsafe fn record<'a>(packet: *const RDnsPacket, section: u32, index: usize) -> Option<&'a DnsRecord> {
+    packet.as_ref()?.records(section)?.get(index)
+}
+
+// An empty message with ID 0, give it one with `rdns_packet_set_id`
+#[no_mangle]
+pub extern "C" fn rdns_packet_new() -> *mut RDnsPacket {
+    Box::into_raw(Box::new(RDnsPacket { packet: DnsPacket::new() }))
+}
+
+// Parses `len` bytes of a DNS message, NULL if they aren't one
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_parse(data: *const u8, len: usize) -> *mut RDnsPacket {
+    if data.is_null() {
+        return ptr::null_mut();
+    }
+    match DnsPacket::from_bytes(slice::from_raw_parts(data, len)) {
+        Ok(packet) => Box::into_raw(Box::new(RDnsPacket { packet })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_free(packet: *mut RDnsPacket) {
+    if !packet.is_null() {
+        drop(Box::from_raw(packet));
+    }
+}
+
+// Writes the message in wire format to `out`
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_serialize(packet: *const RDnsPacket, out: *mut u8, out_len: usize) -> isize {
+    let Some(packet) = packet.as_ref() else {
+        return RDNS_ERR_INVALID;
+    };
+    let mut buffer = ByteBuffer::new();
+    if packet.packet.write(&mut buffer).is_err() {
+        return RDNS_ERR_ENCODE;
+    }
+    copy_bytes(&buffer.buffer[..buffer.position()], out, out_len)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_id(packet: *const RDnsPacket) -> u16 {
+    packet.as_ref().map_or(0, |packet| packet.packet.header.id)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_set_id(packet: *mut RDnsPacket, id: u16) {
+    if let Some(packet) = packet.as_mut() {
+        packet.packet.header.id = id;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_is_response(packet: *const RDnsPacket) -> bool {
+    packet.as_ref().is_some_and(|packet| packet.packet.header.response)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_rcode(packet: *const RDnsPacket) -> u16 {
+    packet.as_ref().map_or(0, |packet| packet.packet.header.rescode.to_num())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_set_recursion_desired(packet: *mut RDnsPacket, recursion_desired: bool) {
+    if let Some(packet) = packet.as_mut() {
+        packet.packet.header.recursion_desired = recursion_desired;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_question_count(packet: *const RDnsPacket) -> usize {
+    packet.as_ref().map_or(0, |packet| packet.packet.questions.len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_record_count(packet: *const RDnsPacket, section: u32) -> isize {
+    match packet.as_ref().and_then(|packet| packet.records(section)) {
+        Some(records) => records.len() as isize,
+        None => RDNS_ERR_INVALID,
+    }
+}
+
+// Adds a class IN question for the NUL-terminated `name`, 0 on success
+#[no_mangle]
+pub unsafe extern "C" fn rdns_packet_add_question(packet: *mut RDnsPacket, name: *const c_char, qtype: u16) -> isize {
+    let (Some(packet), false) = (packet.as_mut(), name.is_null()) else {
+        return RDNS_ERR_INVALID;
+    };
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return RDNS_ERR_INVALID;
+    };
+    packet.packet.questions.push(DnsQuestion::new(name.trim_end_matches('.').to_ascii_lowercase(), QueryType::from_num(qtype)));
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_question_name(packet: *const RDnsPacket, index: usize, out: *mut c_char, out_len: usize) -> isize {
+    match packet.as_ref().and_then(|packet| packet.packet.questions.get(index)) {
+        Some(question) => copy_string(&question.name, out, out_len),
+        None => RDNS_ERR_INVALID,
+    }
+}
+
+// The type of question `index`, 0 when there is no such question
+#[no_mangle]
+pub unsafe extern "C" fn rdns_question_type(packet: *const RDnsPacket, index: usize) -> u16 {
+    packet.as_ref().and_then(|packet| packet.packet.questions.get(index)).map_or(0, |question| question.qtype.to_num())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_record_name(packet: *const RDnsPacket, section: u32, index: usize, out: *mut c_char, out_len: usize) -> isize {
+    match record(packet, section, index) {
+        Some(record) => copy_string(record.domain(), out, out_len),
+        None => RDNS_ERR_INVALID,
+    }
+}
+
+// The type of record `index` of `section`, 0 when there is no such record
+#[no_mangle]
+pub unsafe extern "C" fn rdns_record_type(packet: *const RDnsPacket, section: u32, index: usize) -> u16 {
+    record(packet, section, index).map_or(0, |record| record.query_type().to_num())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rdns_record_ttl(packet: *const RDnsPacket, section: u32, index: usize) -> u32 {
+    record(packet, section, index).map_or(0, DnsRecord::ttl)
+}
+
+// The record data as zone files write it, "10 mail.example.com." for an MX
+#[no_mangle]
+pub unsafe extern "C" fn rdns_record_data(packet: *const RDnsPacket, section: u32, index: usize, out: *mut c_char, out_len: usize) -> isize {
+    match record(packet, section, index).map(record_data) {
+        Some(Some(data)) => copy_string(&data, out, out_len),
+        Some(None) => RDNS_ERR_NO_DATA,
+        None => RDNS_ERR_INVALID,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r_dns_core::dns_class::DnsClass;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_query_round_trip() {
+        unsafe {
+            let query = rdns_packet_new();
+            rdns_packet_set_id(query, 4321);
+            rdns_packet_set_recursion_desired(query, true);
+            assert_eq!(rdns_packet_add_question(query, c"Example.com.".as_ptr(), 28), 0);
+
+            let mut wire = [0u8; 512];
+            let len = rdns_packet_serialize(query, wire.as_mut_ptr(), wire.len());
+            assert!(len > 0);
+            assert_eq!(rdns_packet_serialize(query, wire.as_mut_ptr(), 4), RDNS_ERR_TOO_SMALL);
+            rdns_packet_free(query);
+
+            let parsed = rdns_packet_parse(wire.as_ptr(), len as usize);
+            assert!(!parsed.is_null());
+            assert_eq!(rdns_packet_id(parsed), 4321);
+            assert_eq!(rdns_packet_question_count(parsed), 1);
+            assert_eq!(rdns_question_type(parsed, 0), 28);
+            let mut name = [0 as c_char; 64];
+            assert_eq!(rdns_question_name(parsed, 0, name.as_mut_ptr(), name.len()), 11);
+            assert_eq!(CStr::from_ptr(name.as_ptr()).to_str(), Ok("example.com"));
+            assert_eq!(rdns_question_name(parsed, 0, name.as_mut_ptr(), 11), RDNS_ERR_TOO_SMALL);
+            assert_eq!(rdns_question_name(parsed, 1, name.as_mut_ptr(), name.len()), RDNS_ERR_INVALID);
+            rdns_packet_free(parsed);
+        }
+    }
+
+    #[test]
+    fn test_record_accessors() {
+        let mut packet = DnsPacket::new();
+        packet.header.response = true;
+        packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300, class: DnsClass::IN });
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        unsafe {
+            let parsed = rdns_packet_parse(buffer.buffer.as_ptr(), buffer.position());
+            assert!(rdns_packet_is_response(parsed));
+            assert_eq!(rdns_packet_record_count(parsed, RDNS_SECTION_ANSWER), 1);
+            assert_eq!(rdns_packet_record_count(parsed, RDNS_SECTION_AUTHORITY), 0);
+            assert_eq!(rdns_record_type(parsed, RDNS_SECTION_ANSWER, 0), 1);
+            assert_eq!(rdns_record_ttl(parsed, RDNS_SECTION_ANSWER, 0), 300);
+            let mut data = [0 as c_char; 64];
+            assert_eq!(rdns_record_data(parsed, RDNS_SECTION_ANSWER, 0, data.as_mut_ptr(), data.len()), 9);
+            assert_eq!(CStr::from_ptr(data.as_ptr()).to_str(), Ok("192.0.2.1"));
+            // Sections are checked, not trusted
+            assert_eq!(rdns_packet_record_count(parsed, 3), RDNS_ERR_INVALID);
+            assert_eq!(rdns_record_data(parsed, u32::MAX, 0, data.as_mut_ptr(), data.len()), RDNS_ERR_INVALID);
+            assert_eq!(rdns_record_ttl(parsed, 3, 0), 0);
+            rdns_packet_free(parsed);
+        }
+        assert!(unsafe { rdns_packet_parse([0u8; 3].as_ptr(), 3) }.is_null());
+    }
+}
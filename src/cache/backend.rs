@@ -0,0 +1,382 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+
+use crate::cache::cache::{CacheConfig, DnsCache, DnsCacheEntry};
+use crate::cache::clock::{Clock, SystemClock};
+use crate::cache::key::CacheKey;
+use crate::cache::policy::DomainRules;
+use crate::io::Result;
+use crate::utils::builder::DnsPacketBuilder;
+use crate::utils::memory::MemoryUsage;
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+
+// Which `CacheBackend` the server keeps its cache in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackendKind {
+    #[default]
+    Mutex,
+    Sharded,
+}
+
+/**
+Where `ThreadSafeDnsCache` keeps its entries, together with their eviction order and refresh
+timers. `Mutex<DnsCache>` is the plain one where every call takes the one lock, `ShardedDnsCache`
+spreads the keys over shards so a call only locks the shard of its key. Both evict in insertion
+order (FIFO), lookups don't move an entry back.
+*/
+pub trait CacheBackend: Send + Sync {
+    // Expired entries are still handed out within their serve_stale window
+    fn get(&self, key: &CacheKey) -> Option<DnsCacheEntry>;
+    // The entry however long expired, left where it is
+    fn peek(&self, key: &CacheKey) -> Option<DnsCacheEntry>;
+    fn insert(&self, key: CacheKey, entry: DnsCacheEntry) -> Result<()>;
+    fn update(&self, key: &CacheKey, packet: &DnsPacket, ttl: u32) -> Result<()>;
+    fn ttl_override(&self, name: &str) -> Option<u32>;
+    fn is_pinned(&self, key: &CacheKey) -> bool;
+    fn schedule_retry(&self, key: &CacheKey, delay: u64);
+    fn due_keys(&self) -> Vec<CacheKey>;
+    fn next_due(&self) -> Option<u64>;
+    fn clock(&self) -> Arc<dyn Clock>;
+    // Everything in one `DnsCache`, for saving
+    fn snapshot(&self) -> DnsCache;
+    fn memory(&self) -> MemoryUsage;
+}
+
+// A worker that panicked while holding the lock leaves the cache poisoned. The data is still
+// consistent (every change is a single insert or update), so keep using it.
+fn lock(cache: &Mutex<DnsCache>) -> MutexGuard<'_, DnsCache> {
+    cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl CacheBackend for Mutex<DnsCache> {
+    fn get(&self, key: &CacheKey) -> Option<DnsCacheEntry> {
+        lock(self).get(key).cloned()
+    }
+
+    fn peek(&self, key: &CacheKey) -> Option<DnsCacheEntry> {
+        lock(self).peek(key).cloned()
+    }
+
+    fn insert(&self, key: CacheKey, entry: DnsCacheEntry) -> Result<()> {
+        lock(self).insert(key, entry)
+    }
+
+    fn update(&self, key: &CacheKey, packet: &DnsPacket, ttl: u32) -> Result<()> {
+        lock(self).update(key, packet, ttl)
+    }
+
+    fn ttl_override(&self, name: &str) -> Option<u32> {
+        lock(self).ttl_override(name)
+    }
+
+    fn is_pinned(&self, key: &CacheKey) -> bool {
+        lock(self).is_pinned(key)
+    }
+
+    fn schedule_retry(&self, key: &CacheKey, delay: u64) {
+        lock(self).schedule_retry(key, delay)
+    }
+
+    fn due_keys(&self) -> Vec<CacheKey> {
+        lock(self).due_keys()
+    }
+
+    fn next_due(&self) -> Option<u64> {
+        lock(self).next_due()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        lock(self).shared_clock()
+    }
+
+    fn snapshot(&self) -> DnsCache {
+        lock(self).clone()
+    }
+
+    fn memory(&self) -> MemoryUsage {
+        lock(self).memory()
+    }
+}
+
+/**
+A cache split over shards by the hash of the key, each a `DnsCache` with its own lock, eviction
+order and refresh timers. Changes lock only the shard of their key, so with enough shards they
+rarely wait on each other. Lookups take no lock at all: every shard also keeps its entries in a
+persistent map that changes swap out whole, a change shares everything but the path to its key
+with the map before it. Each shard holds its share of `max_size` and evicts in insertion order
+like `DnsCache`, so the first entry cached in the shard goes rather than the first overall.
+*/
+pub struct ShardedDnsCache {
+    shards: Vec<Shard>,
+    rules: DomainRules,
+    config: CacheConfig,
+    shard_size: usize,
+    clock: Arc<dyn Clock>,
+}
+
+struct Shard {
+    cache: Mutex<DnsCache>,
+    // What lookups read, the entries of `cache` as of its last change
+    entries: ArcSwap<im::HashMap<CacheKey, DnsCacheEntry>>,
+}
+
+impl Shard {
+    // Brings `entries` in line with `cache` for the keys a change touched, under the shard's lock
+    fn publish<'a>(&self, cache: &DnsCache, keys: impl IntoIterator<Item = &'a CacheKey>) {
+        let mut entries = im::HashMap::clone(&self.entries.load());
+        for key in keys {
+            match cache.peek(key) {
+                Some(entry) => entries.insert(key.clone(), entry.clone()),
+                None => entries.remove(key),
+            };
+        }
+        self.entries.store(Arc::new(entries));
+    }
+}
+
+impl ShardedDnsCache {
+    pub fn new(max_size: usize, shards: usize, config: &CacheConfig) -> ShardedDnsCache {
+        ShardedDnsCache::with_clock(max_size, shards, config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(max_size: usize, shards: usize, config: &CacheConfig, clock: Arc<dyn Clock>) -> ShardedDnsCache {
+        let shards = shards.max(1);
+        let shard_size = max_size.div_ceil(shards).max(1);
+        let shards = (0..shards).map(|_| {
+            let mut cache = DnsCache::with_clock(shard_size, Arc::clone(&clock));
+            cache.set_policy(config);
+            Shard { cache: Mutex::new(cache), entries: ArcSwap::from_pointee(im::HashMap::new()) }
+        }).collect();
+        ShardedDnsCache {
+            shards,
+            rules: DomainRules::new(&config.ttl_override, &config.policy),
+            config: config.clone(),
+            shard_size,
+            clock,
+        }
+    }
+
+    // Takes over the entries of a cache loaded from file, oldest first
+    pub fn from_cache(cache: &DnsCache, shards: usize, config: &CacheConfig) -> ShardedDnsCache {
+        let sharded = ShardedDnsCache::new(cache.max_size(), shards, config);
+        for key in &cache.order {
+            if let Some(entry) = cache.cache.get(key) {
+                let _ = sharded.insert(key.clone(), entry.clone());
+            }
+        }
+        sharded
+    }
+
+    fn shard(&self, key: &CacheKey) -> &Shard {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+impl CacheBackend for ShardedDnsCache {
+    // Entries past their stale window are left for the next insert of the key or eviction to remove
+    fn get(&self, key: &CacheKey) -> Option<DnsCacheEntry> {
+        let serve_stale = self.rules.policy(&key.name).serve_stale();
+        let entries = self.shard(key).entries.load();
+        entries.get(key).filter(|entry| entry.expiry + serve_stale >= self.clock.now()).cloned()
+    }
+
+    fn peek(&self, key: &CacheKey) -> Option<DnsCacheEntry> {
+        self.shard(key).entries.load().get(key).cloned()
+    }
+
+    fn insert(&self, key: CacheKey, entry: DnsCacheEntry) -> Result<()> {
+        let shard = self.shard(&key);
+        let mut cache = lock(&shard.cache);
+        // An entry past its stale window is still in the shard, it would keep the new one out
+        if cache.get(&key).is_some() {
+            return Ok(());
+        }
+        // The entry a full shard would evict, checked again once the new one is in
+        let oldest = cache.order.iter().find(|key| !cache.is_pinned(key)).cloned();
+        cache.insert(key.clone(), entry)?;
+        shard.publish(&cache, [Some(&key), oldest.as_ref()].into_iter().flatten());
+        Ok(())
+    }
+
+    fn update(&self, key: &CacheKey, packet: &DnsPacket, ttl: u32) -> Result<()> {
+        let shard = self.shard(key);
+        let mut cache = lock(&shard.cache);
+        cache.update(key, packet, ttl)?;
+        shard.publish(&cache, [key]);
+        Ok(())
+    }
+
+    fn ttl_override(&self, name: &str) -> Option<u32> {
+        self.rules.ttl(name)
+    }
+
+    fn is_pinned(&self, key: &CacheKey) -> bool {
+        lock(&self.shard(key).cache).is_pinned(key)
+    }
+
+    fn schedule_retry(&self, key: &CacheKey, delay: u64) {
+        lock(&self.shard(key).cache).schedule_retry(key, delay)
+    }
+
+    fn due_keys(&self) -> Vec<CacheKey> {
+        self.shards.iter().flat_map(|shard| lock(&shard.cache).due_keys()).collect()
+    }
+
+    fn next_due(&self) -> Option<u64> {
+        self.shards.iter().filter_map(|shard| lock(&shard.cache).next_due()).min()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
+    }
+
+    fn snapshot(&self) -> DnsCache {
+        // Room for every shard full, so nothing is evicted on the way
+        let mut merged = DnsCache::with_clock(self.shard_size * self.shards.len(), Arc::clone(&self.clock));
+        merged.set_policy(&self.config);
+        for shard in &self.shards {
+            let cache = lock(&shard.cache);
+            for key in &cache.order {
+                if let Some(entry) = cache.cache.get(key) {
+                    let _ = merged.insert(key.clone(), entry.clone());
+                }
+            }
+        }
+        merged
+    }
+
+    fn memory(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for shard in &self.shards {
+            usage += lock(&shard.cache).memory();
+        }
+        usage
+    }
+}
+
+pub fn new_backend(cache: DnsCache, config: &CacheConfig) -> Arc<dyn CacheBackend> {
+    match config.backend {
+        CacheBackendKind::Mutex => Arc::new(Mutex::new(cache)),
+        CacheBackendKind::Sharded => Arc::new(ShardedDnsCache::from_cache(&cache, config.shards, config)),
+    }
+}
+
+/**
+Times `ops` operations on each of `threads` threads over a set of 4096 names, nine lookups for
+every insert, the mix of a warm cache. Inserts of names that were evicted evict others in turn
+when the cache is smaller. Used by `cache benchmark` to compare the backends.
+*/
+pub fn benchmark(backend: &dyn CacheBackend, threads: usize, ops: usize) -> Duration {
+    let keys: Vec<CacheKey> = (0..4096).map(|i| CacheKey::new(&format!("host{}.example.com", i), QueryType::A)).collect();
+    let packet = DnsPacketBuilder::query("example.com", QueryType::A).answer(DnsRecord::a("example.com", Ipv4Addr::new(192, 0, 2, 1), 300)).build();
+    let entry = DnsCacheEntry::from_packet(&packet, 300, &*backend.clock()).unwrap();
+    for key in &keys {
+        let _ = backend.insert(key.clone(), entry.clone());
+    }
+    let next = AtomicUsize::new(0);
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                for _ in 0..ops {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let key = &keys[i.wrapping_mul(7919) % keys.len()];
+                    if i.is_multiple_of(10) {
+                        let _ = backend.insert(key.clone(), entry.clone());
+                    } else {
+                        backend.get(key);
+                    }
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::clock::ManualClock;
+    use crate::cache::policy::DomainPolicy;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn entry(clock: &dyn Clock, ttl: u32) -> DnsCacheEntry {
+        let packet = DnsPacketBuilder::query("example.com", QueryType::A).build();
+        DnsCacheEntry::from_packet(&packet, ttl, clock).unwrap()
+    }
+
+    #[test]
+    fn test_sharded_matches_mutex() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let mut config = CacheConfig::default();
+        config.policy.insert("stale.example".to_string(), DomainPolicy { serve_stale: Some(60), ..Default::default() });
+        let mut plain = DnsCache::with_clock(64, clock.clone());
+        plain.set_policy(&config);
+        let backends: [Box<dyn CacheBackend>; 2] = [
+            Box::new(Mutex::new(plain)),
+            Box::new(ShardedDnsCache::with_clock(64, 8, &config, clock.clone())),
+        ];
+        for backend in &backends {
+            backend.insert(CacheKey::new("fresh.example", QueryType::A), entry(&*clock, 300)).unwrap();
+            backend.insert(CacheKey::new("stale.example", QueryType::A), entry(&*clock, 10)).unwrap();
+            backend.insert(CacheKey::new("gone.example", QueryType::A), entry(&*clock, 10)).unwrap();
+        }
+        clock.advance(30);
+        for backend in &backends {
+            assert!(backend.get(&CacheKey::new("fresh.example", QueryType::A)).is_some());
+            assert!(backend.get(&CacheKey::new("stale.example", QueryType::A)).is_some_and(|entry| entry.is_expired(&*clock)));
+            assert!(backend.get(&CacheKey::new("gone.example", QueryType::A)).is_none());
+
+            // Once gone an entry can be cached again
+            backend.insert(CacheKey::new("gone.example", QueryType::A), entry(&*clock, 10)).unwrap();
+            assert!(backend.get(&CacheKey::new("gone.example", QueryType::A)).is_some());
+            assert_eq!(backend.snapshot().cache.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_shards_share_max_size() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let sharded = ShardedDnsCache::with_clock(16, 4, &CacheConfig::default(), clock.clone());
+        for i in 0..100 {
+            sharded.insert(CacheKey::new(&format!("host{}.example", i), QueryType::A), entry(&*clock, 300)).unwrap();
+        }
+        // Each shard holds 4, the snapshot has them all
+        assert_eq!(sharded.snapshot().cache.len(), 16);
+        assert_eq!((0..100).filter(|i| sharded.get(&CacheKey::new(&format!("host{}.example", i), QueryType::A)).is_some()).count(), 16);
+    }
+
+    #[test]
+    fn test_lookups_take_no_lock() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let sharded = ShardedDnsCache::with_clock(16, 1, &CacheConfig::default(), clock.clone());
+        let key = CacheKey::new("www.example", QueryType::A);
+        sharded.insert(key.clone(), entry(&*clock, 300)).unwrap();
+
+        // With the shard locked by a change, lookups still get through
+        let _change = lock(&sharded.shard(&key).cache);
+        assert!(sharded.get(&key).is_some());
+        assert!(sharded.peek(&CacheKey::new("other.example", QueryType::A)).is_none());
+    }
+
+    #[test]
+    fn test_concurrent_access() {
+        let sharded = ShardedDnsCache::new(8192, 16, &CacheConfig::default());
+        benchmark(&sharded, 4, 1000);
+        assert_eq!(sharded.snapshot().cache.len(), 4096);
+    }
+}
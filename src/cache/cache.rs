@@ -1,73 +1,201 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::io::{Read, Write};
+use std::mem::size_of;
 use std::{fs, io, thread};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
+use crate::utils::dns_class::DnsClass;
 use crate::utils::query_type::QueryType;
-use crate::utils::record::DnsRecord;
-use crate::recursive_lookup;
+use crate::resolver::resolve_with;
 use crate::utils::byte_buffer::ByteBuffer;
+use crate::utils::memory::{map_heap_size, HeapSize, MemoryUsage};
 use crate::utils::packet::DnsPacket;
-
-use log::{info, warn};
+use crate::utils::wire::{read_large_message, write_large_message};
+use crate::cache::backend::{new_backend, CacheBackend, CacheBackendKind};
+use crate::cache::clock::{Clock, SystemClock};
+use crate::cache::key::{CacheKey, Provenance};
+use crate::cache::policy::{DomainPolicy, DomainRules};
+use crate::cache::refresh_pool::RefreshPool;
+use crate::cache::supervisor::{supervise, RESTART_DELAY};
+use crate::logging::telemetry::{self, SpanKind};
+use crate::server::stats::{counters, STATS_PATH};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, info, warn};
+use serde::Deserialize;
 use toml::Value;
 use crate::io::Result;
 
+const MAX_REFRESH_DELAY: Duration = Duration::from_secs(1);
+const PINNED_RETRY_DELAY: u64 = 10;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub size: Option<usize>,        // entries, the <max_size> argument wins when given
+    pub refresh_jitter: u64,        // seconds an entry may be refreshed before it expires
+    pub refresh_concurrency: usize, // background lookups (refreshes and stale entries) run at once
+    pub refresh_per_zone: usize,    // of those, at most this many for names in the same zone
+    pub refresh_queue: usize,       // refreshes waiting for a worker, more are put off
+    pub pinned: Vec<String>,        // names kept cached (A and AAAA) and never evicted
+    pub ttl_override: HashMap<String, u32>,     // "*.internal.lan" = 30
+    pub policy: HashMap<String, DomainPolicy>,  // prefetch and serve_stale per name pattern
+    pub prewarm: String,            // file of names resolved at startup, one per line
+    pub prewarm_concurrency: usize, // lookups the warm-up runs at once
+    pub compression: SnapshotCompression, // of the saved cache file
+    pub backend: CacheBackendKind,  // mutex or sharded, see `CacheBackend`
+    pub shards: usize,              // of the sharded backend
+    pub partition: bool,            // keep clients that set AD from answers learned in the clear
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            size: None,
+            refresh_jitter: 10,
+            refresh_concurrency: 4,
+            refresh_per_zone: 2,
+            refresh_queue: 1024,
+            pinned: Vec::new(),
+            ttl_override: HashMap::new(),
+            policy: HashMap::new(),
+            prewarm: String::new(),
+            prewarm_concurrency: 2,
+            compression: SnapshotCompression::None,
+            backend: CacheBackendKind::Mutex,
+            shards: 16,
+            partition: false,
+        }
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// How the saved cache is compressed. Loading recognises every format by its first bytes, so
+// changing this only takes effect on the next save.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl SnapshotCompression {
+    fn detect(data: &[u8]) -> SnapshotCompression {
+        if data.starts_with(&GZIP_MAGIC) {
+            SnapshotCompression::Gzip
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            SnapshotCompression::Zstd
+        } else {
+            SnapshotCompression::None
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            SnapshotCompression::None => Ok(data.to_vec()),
+            SnapshotCompression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            SnapshotCompression::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            SnapshotCompression::None => Ok(data.to_vec()),
+            SnapshotCompression::Gzip => {
+                let mut out = Vec::new();
+                GzDecoder::new(data).read_to_end(&mut out)?;
+                Ok(out)
+            }
+            SnapshotCompression::Zstd => zstd::decode_all(data),
+        }
+    }
+}
+
+// The names of a warm-up list: one per line, `#` starts a comment. Top site lists such as
+// Tranco's come as "rank,name", only the last field is taken.
+pub fn prewarm_names(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let name = line.rsplit(',').next().unwrap_or_default().trim().trim_end_matches('.').to_ascii_lowercase();
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/**
+A cached response, kept parsed so a hit costs a clone of the records (or, for relayed answers, a
+single write) rather than a parse of the stored message. The packet is shared, handing an entry
+out of the cache doesn't copy it. It is only turned back into wire format when the cache is saved.
+*/
 #[derive(Clone, Debug, PartialEq)]
 pub struct DnsCacheEntry {
-    pub response: [u8; 512],
+    pub packet: Arc<DnsPacket>,
     pub expiry: u64,
     pub ttl: u32,
 }
 
 impl DnsCacheEntry {
-    pub fn new(response: [u8; 512], expiry: u64, ttl: u64) -> DnsCacheEntry {
+    pub fn new(packet: DnsPacket, expiry: u64, ttl: u64) -> DnsCacheEntry {
         DnsCacheEntry {
-            response,
+            packet: Arc::new(packet),
             expiry,
             ttl: ttl as u32,
         }
     }
 
-    pub fn from_packet(packet: &DnsPacket, ttl: u32) -> Result<DnsCacheEntry> {
-        let mut buffer = ByteBuffer::new();
-        packet.write(&mut buffer).unwrap();
-        Ok(DnsCacheEntry {
-            response: buffer.buffer,
-            expiry: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + ttl as u64,
-            ttl,
-        })
+    // Fails if the packet doesn't fit in a message, it could never be answered from the cache
+    pub fn from_packet(packet: &DnsPacket, ttl: u32, clock: &dyn Clock) -> Result<DnsCacheEntry> {
+        write_large_message(packet)?;
+        Ok(DnsCacheEntry::new(packet.clone(), clock.now() + ttl as u64, ttl as u64))
     }
 
-    pub fn is_expired(&self) -> bool {
-        self.expiry < SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    pub fn remaining_ttl(&self, clock: &dyn Clock) -> u32 {
+        self.expiry.saturating_sub(clock.now()) as u32
     }
 
-    pub fn update(&mut self, packet: &DnsPacket, ttl: u32) -> Result<()>{
-        let mut buffer = ByteBuffer::new();
-        packet.write(&mut buffer).unwrap();
-        self.response = buffer.buffer;
-        self.expiry = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + ttl as u64;
-        Ok(())
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        self.expiry < clock.now()
     }
 
-    pub fn get_packet(&self) -> Result<DnsPacket> {
-        let mut buffer = ByteBuffer::from_buffer(&self.response);
-        DnsPacket::from_buffer(&mut buffer)
+    pub fn update(&mut self, packet: &DnsPacket, ttl: u32, clock: &dyn Clock) -> Result<()>{
+        *self = DnsCacheEntry::from_packet(packet, ttl, clock)?;
+        Ok(())
     }
 
-    pub fn to_toml(&self) -> Value {
+    // The saved form is still the 512-byte message, caches saved before entries were kept parsed load as they are
+    pub fn to_toml(&self) -> Option<Value> {
         let mut map = toml::map::Map::new();
         
-        // Convert `[u8; 512]` to an array of integers (u32) for TOML serialization
-        let response_array = self.response.iter().map(|&x| Value::Integer(x as i64)).collect();
+        // Convert `[u8; 512]` to an array of integers (u32) for TOML serialization, larger answers are saved at their length
+        let response = match self.packet.write_to_bytes() {
+            Ok(buffer) => buffer.to_vec(),
+            Err(_) => write_large_message(&self.packet).ok()?,
+        };
+        let response_array = response.iter().map(|&x| Value::Integer(x as i64)).collect();
         
         map.insert("response".into(), Value::Array(response_array));
         map.insert("expiry".into(), Value::Integer(self.expiry as i64));
         map.insert("ttl".into(), Value::Integer(self.ttl as i64));
         
-        Value::Table(map)
+        Some(Value::Table(map))
     }
 
     pub fn from_toml(value: &toml::Value) -> Option<DnsCacheEntry> {
@@ -79,18 +207,20 @@ impl DnsCacheEntry {
                 .map(|v| v.as_integer().and_then(|x| x.try_into().ok()).unwrap_or(0))
                 .collect();
     
-            if response.len() != 512 {
+            if response.len() < 512 {
                 return None; // Handle error if response size doesn't match expected length
             }
     
             let expiry = table.get("expiry")?.as_integer()?.try_into().ok()?;
             let ttl = table.get("ttl")?.as_integer()?.try_into().ok()?;
     
-            let mut response_array: [u8; 512] = [0; 512];
-            response_array.copy_from_slice(&response);
+            let packet = match response.len() {
+                512 => DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(&response)).ok()?,
+                _ => read_large_message(&response).ok()?,
+            };
     
             Some(DnsCacheEntry {
-                response: response_array,
+                packet: Arc::new(packet),
                 expiry,
                 ttl,
             })
@@ -100,31 +230,142 @@ impl DnsCacheEntry {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Entries are refreshed up to `jitter` seconds (at most half their TTL) before they expire, so
+// entries cached together don't all go upstream in the same second. The offset is derived from
+// the key and expiry so the timer can be found again without storing it. Pinned entries are
+// refreshed halfway through their TTL, leaving time for a retry before they would expire.
+fn refresh_at(jitter: u64, pinned: bool, key: &CacheKey, entry: &DnsCacheEntry) -> u64 {
+    if pinned {
+        return entry.expiry - entry.ttl as u64 / 2;
+    }
+    let window = jitter.min(entry.ttl as u64 / 2);
+    if window == 0 {
+        return entry.expiry;
+    }
+    let mut hasher = DefaultHasher::new();
+    (key, entry.expiry).hash(&mut hasher);
+    entry.expiry - hasher.finish() % (window + 1)
+}
+
+/**
+Entries in insertion order for eviction, plus a timer queue of (refresh time, key) sorted by
+time so the refresh thread only ever looks at the entries that are actually due. Entries for
+pinned names are never evicted, entries whose policy turns prefetch off never get a timer.
+Expiry and refresh times all come from `clock`, the system clock outside of tests.
+*/
+#[derive(Clone, Debug)]
 pub struct DnsCache {
-    pub cache: HashMap<String, DnsCacheEntry>,
-    pub order: VecDeque<String>,
+    pub cache: HashMap<CacheKey, DnsCacheEntry>,
+    pub order: VecDeque<CacheKey>,
+    timers: BTreeSet<(u64, CacheKey)>,
+    refresh_jitter: u64,
+    pinned: HashSet<String>,
+    rules: DomainRules,
     max_size: usize,
+    compression: SnapshotCompression,
+    clock: Arc<dyn Clock>,
 }
 
 impl DnsCache {
     pub fn new(max_size: usize) -> DnsCache {
+        DnsCache::with_clock(max_size, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(max_size: usize, clock: Arc<dyn Clock>) -> DnsCache {
         DnsCache {
             cache: HashMap::new(),
             order: VecDeque::new(),
+            timers: BTreeSet::new(),
+            refresh_jitter: 0,
+            pinned: HashSet::new(),
+            rules: DomainRules::default(),
             max_size,
+            compression: SnapshotCompression::None,
+            clock,
         }
     }
 
-    pub fn insert(&mut self, key: String, entry: DnsCacheEntry) -> Result<()>{
-        if (self.cache.get(key.as_str())).is_some() {
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    pub fn shared_clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    // The entries and the keys the eviction order and the timer queue keep of them
+    pub fn memory(&self) -> MemoryUsage {
+        let entries = map_heap_size(&self.cache, |entry| size_of::<DnsPacket>() + entry.packet.heap_size());
+        let order = self.order.capacity() * size_of::<CacheKey>() + self.order.iter().map(CacheKey::heap_size).sum::<usize>();
+        let timers = self.timers.iter().map(|(_, key)| size_of::<(u64, CacheKey)>() + key.heap_size()).sum::<usize>();
+        MemoryUsage { entries: self.cache.len(), bytes: entries + order + timers }
+    }
+
+    // Changes when entries are due, so the timer queue is rebuilt
+    pub fn set_policy(&mut self, config: &CacheConfig) {
+        self.refresh_jitter = config.refresh_jitter;
+        self.compression = config.compression;
+        self.pinned = config.pinned.iter().map(|name| name.trim_end_matches('.').to_ascii_lowercase()).collect();
+        self.rules = DomainRules::new(&config.ttl_override, &config.policy);
+        self.timers = self.cache.iter()
+            .filter(|(key, _)| self.prefetches(key))
+            .map(|(key, entry)| (self.refresh_at(key, entry), key.clone()))
+            .collect();
+    }
+
+    pub fn ttl_override(&self, name: &str) -> Option<u32> {
+        self.rules.ttl(name)
+    }
+
+    fn policy(&self, key: &CacheKey) -> DomainPolicy {
+        self.rules.policy(&key.name)
+    }
+
+    fn prefetches(&self, key: &CacheKey) -> bool {
+        self.is_pinned(key) || self.policy(key).prefetch()
+    }
+
+    pub fn is_pinned(&self, key: &CacheKey) -> bool {
+        self.pinned.contains(&key.name)
+    }
+
+    fn refresh_at(&self, key: &CacheKey, entry: &DnsCacheEntry) -> u64 {
+        refresh_at(self.refresh_jitter, self.is_pinned(key), key, entry)
+    }
+
+    pub fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.cache.remove(key) {
+            self.timers.remove(&(self.refresh_at(key, &entry), key.clone()));
+            self.order.retain(|x| x != key);
+        }
+    }
+
+    // A failed refresh of a pinned entry is tried again until the entry expires
+    pub fn schedule_retry(&mut self, key: &CacheKey, delay: u64) {
+        let now = self.clock.now();
+        if self.cache.get(key).is_some_and(|entry| entry.expiry > now + delay) {
+            self.timers.insert((now + delay, key.clone()));
+        }
+    }
+
+    pub fn insert(&mut self, key: CacheKey, entry: DnsCacheEntry) -> Result<()>{
+        if self.cache.contains_key(&key) {
             return Ok(()); // Already exists
         }
 
+        // With nothing but pinned entries left the cache grows past max_size instead
         if self.cache.len() >= self.max_size {
-            let oldest = self.order.pop_front().unwrap();
-            self.cache.remove(&oldest);
-            warn!("Evicting oldest entry: {}", oldest)
+            if let Some(oldest) = self.order.iter().find(|key| !self.is_pinned(key)).cloned() {
+                self.remove(&oldest);
+                warn!("Evicting oldest entry: {}", oldest)
+            }
+        }
+        if self.prefetches(&key) {
+            self.timers.insert((self.refresh_at(&key, &entry), key.clone()));
         }
         self.cache.insert(key.clone(), entry);
         self.order.push_back(key);
@@ -132,13 +373,15 @@ impl DnsCache {
         Ok(())
     }
     
-    pub fn get(&mut self, key: &str) -> Option<&DnsCacheEntry> {
+    pub fn get(&mut self, key: &CacheKey) -> Option<&DnsCacheEntry> {
         // First, perform an immutable lookup to check if the entry exists
+        let serve_stale = self.policy(key).serve_stale();
         if let Some(entry) = self.cache.get(key) {
-            if entry.is_expired() {
+            // Expired entries are still handed out for `serve_stale` seconds, see `is_expired`
+            let now = self.clock.now();
+            if entry.expiry + serve_stale < now {
                 // If the entry is expired, perform mutable operations to remove it
-                self.cache.remove(key);
-                self.order.retain(|x| x != key);
+                self.remove(key);
                 return None;
             }
             // If the entry is valid, convert to a mutable reference
@@ -148,53 +391,40 @@ impl DnsCache {
         None
     }
 
+    // Unlike `get`, expired entries stay put and come back however old they are
+    pub fn peek(&self, key: &CacheKey) -> Option<&DnsCacheEntry> {
+        self.cache.get(key)
+    }
 
-    pub fn update(&mut self, key: &str, packet: &DnsPacket, ttl: u32) -> Result<()>{
+    pub fn update(&mut self, key: &CacheKey, packet: &DnsPacket, ttl: u32) -> Result<()>{
+        let pinned = self.is_pinned(key);
+        let prefetch = self.prefetches(key);
         if let Some(entry) = self.cache.get_mut(key) {
-            entry.update(packet, ttl)?;
+            self.timers.remove(&(refresh_at(self.refresh_jitter, pinned, key, entry), key.clone()));
+            entry.update(packet, ttl, &*self.clock)?;
+            if prefetch {
+                self.timers.insert((refresh_at(self.refresh_jitter, pinned, key, entry), key.clone()));
+            }
         }
         Ok(())
     }
 
-    pub fn update_expired(&mut self) -> Result<()> {
-        let mut expired_keys = Vec::new();
-    
-        // First collect all keys with expired entries to avoid mutating the cache while iterating
-        for (key, entry) in self.cache.iter() {
-            if entry.is_expired() {
-                expired_keys.push(key.clone());
+    // Takes the due entries off the timer queue, they are scheduled again by `update`. An
+    // entry that isn't updated stays in the cache until it is looked up or evicted.
+    pub fn due_keys(&mut self) -> Vec<CacheKey> {
+        let now = self.clock.now();
+        let mut keys = Vec::new();
+        while let Some((due, _)) = self.timers.first() {
+            if *due >= now {
+                break;
             }
+            keys.push(self.timers.pop_first().unwrap().1);
         }
-    
-        // Update each expired entry
-        for key in expired_keys {
-            if let Some(entry) = self.cache.get_mut(&key) {
-
-                let name = key.split("-").next().unwrap();
-                let qtype = QueryType::from_num(key.split("-").last().unwrap().parse::<u16>().unwrap());
-                let res_packet = match recursive_lookup(&name, qtype) {
-                    Ok(packet) => packet,
-                    Err(_) => continue, // Skip if the recursive lookup fails
-                };
-    
-                if res_packet.answers.is_empty() {
-                    continue;
-                }
-    
-                let ttl = match res_packet.answers.get(0).unwrap() {
-                    DnsRecord::A { ttl, .. } => *ttl,
-                    DnsRecord::AAAA { ttl, .. } => *ttl,
-                    DnsRecord::CNAME { ttl, .. } => *ttl,
-                    DnsRecord::NS { ttl, .. } => *ttl,
-                    DnsRecord::MX { ttl, .. } => *ttl,
-                    DnsRecord::UNKNOWN { ttl, .. } => *ttl,
-                };
-    
-                entry.update(&res_packet, ttl)?;
-            }
-        }
-    
-        Ok(())
+        keys
+    }
+
+    pub fn next_due(&self) -> Option<u64> {
+        self.timers.first().map(|(due, _)| *due)
     }
 
     pub fn to_toml(&self) -> Value {
@@ -202,13 +432,13 @@ impl DnsCache {
 
         // Convert cache entries to TOML format
         let entries: toml::map::Map<String, Value> = self.cache.iter()
-            .map(|(key, entry)| (key.clone(), entry.to_toml()))
+            .filter_map(|(key, entry)| Some((key.to_string(), entry.to_toml()?)))
             .collect();
 
         map.insert("cache".to_string(), Value::Table(entries));
 
         // Convert order to TOML format
-        let order_array: Vec<Value> = self.order.iter().map(|s| Value::String(s.clone())).collect();
+        let order_array: Vec<Value> = self.order.iter().map(|key| Value::String(key.to_string())).collect();
         map.insert("order".to_string(), Value::Array(order_array));
 
         // Insert max_size
@@ -221,7 +451,7 @@ impl DnsCache {
         let toml_content = self.to_toml();
         let toml_string = toml::to_string(&toml_content).unwrap();
 
-        fs::write(path, toml_string)?;
+        fs::write(path, self.compression.compress(toml_string.as_bytes())?)?;
 
         Ok(())
     }
@@ -230,20 +460,36 @@ impl DnsCache {
         if let Some(table) = value.as_table() {
             let cache_table = table.get("cache")?.as_table()?;
             let cache = cache_table.iter().filter_map(|(key, value)| {
-                DnsCacheEntry::from_toml(value).map(|entry| (key.clone(), entry))
-            }).collect::<HashMap<String, DnsCacheEntry>>();
-            let order = table.get("order")?.as_array()?.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect::<VecDeque<String>>();
+                Some((key.parse().ok()?, DnsCacheEntry::from_toml(value)?))
+            }).collect::<HashMap<CacheKey, DnsCacheEntry>>();
+            let order = table.get("order")?.as_array()?.iter().filter_map(|v| v.as_str()?.parse().ok()).collect::<VecDeque<CacheKey>>();
             let max_size = table.get("max_size")?.as_integer()?.try_into().ok()?;
-            Some(DnsCache { cache, order, max_size })
+            let timers = cache.iter().map(|(key, entry)| (entry.expiry, key.clone())).collect();
+            Some(DnsCache {
+                cache,
+                order,
+                timers,
+                refresh_jitter: 0,
+                pinned: HashSet::new(),
+                rules: DomainRules::default(),
+                max_size,
+                compression: SnapshotCompression::None,
+                clock: Arc::new(SystemClock),
+            })
         } else {
             None
         }
     }
 
+    // Saving again keeps the file's compression unless `set_policy` picks another
     pub fn load_from_toml(path: impl AsRef<Path>) -> Result<DnsCache> {
-        let toml_string = fs::read_to_string(path)?;
+        let data = fs::read(path)?;
+        let compression = SnapshotCompression::detect(&data);
+        let toml_string = String::from_utf8(compression.decompress(&data)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         let value = toml::from_str(&toml_string)?;
-        DnsCache::from_toml(&value).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid TOML format"))
+        let mut cache = DnsCache::from_toml(&value).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid TOML format"))?;
+        cache.compression = compression;
+        Ok(cache)
         
     }
     
@@ -252,94 +498,285 @@ impl DnsCache {
 // Thread-safe DnsCache with automatic expiration update thread
 #[derive(Clone)]
 pub struct ThreadSafeDnsCache {
-    pub cache: Arc<Mutex<DnsCache>>,
+    pub backend: Arc<dyn CacheBackend>,
+    refresh_pool: Arc<OnceLock<RefreshPool>>,
+    persistent: bool, // saved to disk when dropped
 }
 
 impl ThreadSafeDnsCache {
-    pub fn new(max_size: usize, update_interval: Duration, cache_store_interval: Duration, path: impl AsRef<Path>) -> ThreadSafeDnsCache {
-        let cache = Arc::new(Mutex::new(match DnsCache::load_from_toml(path) {
+    pub fn new(max_size: usize, update_interval: Duration, cache_store_interval: Duration, path: impl AsRef<Path>, config: &CacheConfig) -> ThreadSafeDnsCache {
+        let mut cache = match DnsCache::load_from_toml(path) {
             Ok(cache) => {
                 println!("Cache loaded from file");
                 cache
             },
             Err(_) => DnsCache::new(max_size),
-        }));
+        };
 
-        cache.lock().unwrap().max_size = max_size;
+        // A first run has no totals yet
+        if let Err(e) = counters().load(STATS_PATH) {
+            debug!("No statistics loaded: {}", e);
+        }
 
-        let cache_clone = Arc::clone(&cache);
+        cache.max_size = max_size;
+        cache.set_policy(config);
+        let res = ThreadSafeDnsCache { backend: new_backend(cache, config), refresh_pool: Arc::new(OnceLock::new()), persistent: true };
 
-        let cache_clone_2 = Arc::clone(&cache);
-        // Spawn a thread to periodically update expired entries
-        thread::spawn(move || {
+        let refresher = res.clone();
+        let pool = RefreshPool::new(config.refresh_concurrency, config.refresh_per_zone, config.refresh_queue, move |key| {
+            if let Err(e) = refresher.refresh(key) {
+                warn!("Failed to refresh {}: {:?}", key, e);
+            }
+        });
+        let _ = res.refresh_pool.set(pool);
+
+        // Hands expired entries to the refresh pool, see `update_expired`
+        let refresher = res.clone();
+        supervise("cache-refresh", RESTART_DELAY, move || {
             println!("Starting cache update thread");
             loop {
-                {
-                    let mut cache = cache_clone.lock().unwrap();
-                    if let Err(e) = cache.update_expired() {
-                        eprintln!("Failed to update expired entries: {:?}", e);
-                    }
+                if let Err(e) = refresher.update_expired() {
+                    eprintln!("Failed to update expired entries: {:?}", e);
                 }
-                thread::sleep(update_interval);
+                thread::sleep(refresher.refresh_delay(update_interval));
             }
         });
 
-        thread::spawn(move || {
+        let saver = res.clone();
+        supervise("cache-save", RESTART_DELAY, move || {
             loop {
-                {
-                    let cache = cache_clone_2.lock().unwrap();
-                    info!("Saving cache to file");
-                    if let Err(e) = cache.save_to_toml("dns_cache.toml") {
-                        eprintln!("Failed to save cache to file: {:?}", e);
-                    }
+                info!("Saving cache to file");
+                if let Err(e) = saver.backend.snapshot().save_to_toml("dns_cache.toml") {
+                    eprintln!("Failed to save cache to file: {:?}", e);
+                }
+                if let Err(e) = counters().save(STATS_PATH) {
+                    eprintln!("Failed to save statistics to file: {:?}", e);
                 }
                 thread::sleep(cache_store_interval);
             }
         });
 
-        let res = ThreadSafeDnsCache { cache };
+        if !config.pinned.is_empty() {
+            let prefetcher = res.clone();
+            let names = config.pinned.clone();
+            thread::spawn(move || {
+                if let Err(e) = prefetcher.prefetch_pinned(&names) {
+                    eprintln!("Failed to prefetch pinned names: {:?}", e);
+                }
+            });
+        }
+
+        if !config.prewarm.is_empty() {
+            let warmer = res.clone();
+            let (path, concurrency) = (config.prewarm.clone(), config.prewarm_concurrency);
+            thread::spawn(move || match fs::read_to_string(&path) {
+                Ok(text) => {
+                    // Each name takes an A and an AAAA entry, more than fit would only evict each other
+                    let mut names = prewarm_names(&text);
+                    names.truncate(max_size / 2);
+                    warmer.prewarm(&names, concurrency);
+                }
+                Err(e) => warn!("Failed to read prewarm list {}: {}", path, e),
+            });
+        }
+
         info!("Cache successfully initialized with max size: {} and update interval: {:?}", max_size, update_interval);
 
         res
     }
 
-    pub fn insert(&self, key: String, entry: DnsCacheEntry) -> Result<()> {
-        let mut cache = self.cache.lock().unwrap();
-        cache.insert(key, entry)
+    // Only in memory: no refresh or save threads, expired entries stay until they are replaced
+    pub fn in_memory(cache: DnsCache) -> ThreadSafeDnsCache {
+        let config = CacheConfig::default();
+        ThreadSafeDnsCache { backend: new_backend(cache, &config), refresh_pool: Arc::new(OnceLock::new()), persistent: false }
+    }
+
+    pub fn insert(&self, key: CacheKey, entry: DnsCacheEntry) -> Result<()> {
+        let mut span = telemetry::span("cache.insert", SpanKind::Internal);
+        span.attr("cache.key", &key);
+        self.backend.insert(key, entry)
+    }
+
+    pub fn memory(&self) -> MemoryUsage {
+        self.backend.memory()
     }
 
-    pub fn get(&self, key: &str) -> Option<DnsCacheEntry> {
-        let mut cache = self.cache.lock().unwrap();
-        cache.get(key).cloned()
+    // Shared with the cache, entries handed out are checked against the same time
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.backend.clock()
     }
 
-    pub fn update(&self, key: &str, packet: &DnsPacket, ttl: u32) -> Result<()> {
-        let mut cache = self.cache.lock().unwrap();
-        cache.update(key, packet, ttl)
+    pub fn get(&self, key: &CacheKey) -> Option<DnsCacheEntry> {
+        let mut span = telemetry::span("cache.get", SpanKind::Internal);
+        span.attr("cache.key", key);
+        let entry = self.backend.get(key);
+        span.attr("cache.hit", entry.is_some());
+        entry
+    }
+
+    // An entry even past its serve_stale window, for when there is nothing better to answer with
+    pub fn peek(&self, key: &CacheKey) -> Option<DnsCacheEntry> {
+        self.backend.peek(key)
+    }
+
+    pub fn update(&self, key: &CacheKey, packet: &DnsPacket, ttl: u32) -> Result<()> {
+        self.backend.update(key, packet, ttl)
+    }
+
+    // Rewrites the records to the configured TTL for `name`, if there is one, and returns it
+    pub fn override_ttl(&self, name: &str, packet: &mut DnsPacket) -> Option<u32> {
+        let ttl = self.backend.ttl_override(name)?;
+        packet.set_ttl(ttl);
+        Some(ttl)
+    }
+
+    // Resolves and caches `name` unless it is cached already, however it was learned
+    fn prefetch(&self, name: &str, qtype: QueryType) -> Result<()> {
+        let key = CacheKey::new(name, qtype);
+        if Provenance::BEST_FIRST.iter().any(|provenance| self.get(&key.clone().learned(*provenance)).is_some()) {
+            return Ok(());
+        }
+        let (mut packet, provenance) = resolve_with(name, qtype, false, None)?;
+        let ttl = self.override_ttl(name, &mut packet)
+            .unwrap_or_else(|| packet.cache_ttl().unwrap_or(60));
+        self.insert(key.learned(provenance), DnsCacheEntry::from_packet(&packet, ttl, &*self.clock())?)
+    }
+
+    // Resolves the pinned names that aren't cached yet so they are answered from the cache from the start
+    pub fn prefetch_pinned(&self, names: &[String]) -> Result<()> {
+        for name in names {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            for qtype in [QueryType::A, QueryType::AAAA] {
+                if let Err(e) = self.prefetch(&name, qtype) {
+                    warn!("Failed to prefetch pinned name {}: {}", name, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Fills the cache with `names` after a restart, with at most `concurrency` lookups in flight
+    // so a long list doesn't flood the upstreams while clients are already being answered
+    pub fn prewarm(&self, names: &[String], concurrency: usize) {
+        let next = AtomicUsize::new(0);
+        let failed = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..concurrency.clamp(1, names.len().max(1)) {
+                scope.spawn(|| {
+                    while let Some(name) = names.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        for qtype in [QueryType::A, QueryType::AAAA] {
+                            if let Err(e) = self.prefetch(name, qtype) {
+                                debug!("Failed to prewarm {} {:?}: {}", name, qtype, e);
+                                failed.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        info!("Prewarmed the cache with {} names, {} lookups failed", names.len(), failed.into_inner());
+    }
+
+    // Sleeps until the next entry is due, rechecking at least every MAX_REFRESH_DELAY for entries
+    // inserted meanwhile. `update_interval` is the shortest nap, expiry only has second precision.
+    pub fn refresh_delay(&self, update_interval: Duration) -> Duration {
+        let now = self.backend.clock().now();
+        let until_due = match self.backend.next_due() {
+            Some(due) => Duration::from_secs((due + 1).saturating_sub(now)),
+            None => MAX_REFRESH_DELAY,
+        };
+        until_due.clamp(update_interval, MAX_REFRESH_DELAY.max(update_interval))
+    }
+
+    // The lookups happen on the refresh pool without the lock held, queries keep being answered
+    // from the cache meanwhile. Entries that don't fit in its queue come due again a second later.
+    pub fn update_expired(&self) -> Result<()> {
+        let Some(pool) = self.refresh_pool.get() else {
+            return Ok(());
+        };
+        for key in self.backend.due_keys() {
+            if !pool.submit(&key) {
+                self.backend.schedule_retry(&key, 1);
+            }
+        }
+        Ok(())
+    }
+
+    // After a stale entry was served, so the next client gets a fresh answer (RFC 8767 section 5)
+    pub fn refresh_stale(&self, key: &CacheKey) {
+        if let Some(pool) = self.refresh_pool.get() {
+            if !pool.submit(key) {
+                debug!("Refresh queue is full, not refreshing stale entry {}", key);
+            }
+        }
+    }
+
+    // Only answers of class IN that hold for every client can be looked up again on our own, and
+    // only an answer learned at least as safely as the entry's replaces it
+    fn refresh(&self, key: &CacheKey) -> Result<()> {
+        if key.qclass != DnsClass::IN || key.ecs_scope.is_some() {
+            return Ok(());
+        }
+        let name = &key.name;
+        let mut res_packet = match resolve_with(name, key.qtype, false, None) {
+            Ok((packet, provenance)) if !packet.answers.is_empty() && provenance >= key.provenance => packet,
+            // Skip if the lookup fails or learned the answer less safely than the entry was
+            _ => {
+                if self.backend.is_pinned(key) {
+                    self.backend.schedule_retry(key, PINNED_RETRY_DELAY);
+                }
+                return Ok(());
+            }
+        };
+
+        let ttl = match self.override_ttl(name, &mut res_packet) {
+            Some(ttl) => ttl,
+            None => res_packet.cache_ttl().unwrap_or(60),
+        };
+
+        self.update(key, &res_packet, ttl)
     }
 }
 
 impl Drop for ThreadSafeDnsCache {
     fn drop(&mut self) {
+        if !self.persistent {
+            return;
+        }
         info!("Saving cache to file");
-        self.cache.lock().unwrap().save_to_toml("dns_cache.toml").unwrap();
+        // Also runs while a panicking worker unwinds, where a second panic would abort
+        if let Err(e) = self.backend.snapshot().save_to_toml("dns_cache.toml") {
+            eprintln!("Failed to save cache to file: {:?}", e);
+        }
+        if let Err(e) = counters().save(STATS_PATH) {
+            eprintln!("Failed to save statistics to file: {:?}", e);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::{packet::DnsPacket, query_type::QueryType, question::DnsQuestion};
-    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use crate::utils::dns_class::DnsClass;
+    use crate::utils::{packet::DnsPacket, query_type::QueryType, question::DnsQuestion, record::DnsRecord};
+    use crate::cache::clock::ManualClock;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn entry_key(name: &str) -> CacheKey {
+        CacheKey::new(name, QueryType::A)
+    }
+
+    fn manual_cache(max_size: usize) -> (DnsCache, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(NOW));
+        (DnsCache::with_clock(max_size, clock.clone()), clock)
+    }
 
     fn create_test_packet() -> DnsPacket {
         // Create a mock DNS packet for testing
         DnsPacket {
             header: Default::default(),
-            questions: vec![DnsQuestion {
-                name: "google.com".to_string(),
-                qtype: QueryType::A,
-            }],
+            questions: vec![DnsQuestion::new("google.com".to_string(), QueryType::A)],
             answers: vec![],
             authorities: vec![],
             resources: vec![],
@@ -348,16 +785,18 @@ mod tests {
 
     fn create_test_entry(ttl: u32) -> DnsCacheEntry {
         let packet = create_test_packet();
-        DnsCacheEntry::from_packet(&packet, ttl).unwrap()
+        DnsCacheEntry::from_packet(&packet, ttl, &SystemClock).unwrap()
     }
 
     #[test]
     fn test_create_entry() {
-        let ttl = 60;
-        let entry = create_test_entry(ttl);
+        let clock = ManualClock::new(NOW);
+        let entry = DnsCacheEntry::from_packet(&create_test_packet(), 60, &clock).unwrap();
 
-        assert_eq!(entry.response.len(), 512);
-        assert_eq!(entry.expiry, SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + ttl as u64);
+        assert_eq!(*entry.packet, create_test_packet());
+        assert_eq!(entry.expiry, NOW + 60);
+        clock.advance(45);
+        assert_eq!(entry.remaining_ttl(&clock), 15);
     }
 
     #[test]
@@ -366,23 +805,25 @@ mod tests {
         let ttl = 60;
         let entry = create_test_entry(ttl);
 
-        cache.insert("example.com".to_string(), entry.clone()).unwrap();
+        cache.insert(entry_key("example.com"), entry.clone()).unwrap();
 
-        let cached_entry = cache.get("example.com").unwrap();
+        let cached_entry = cache.get(&entry_key("example.com")).unwrap();
         assert_eq!(cached_entry, &entry);
     }
 
     #[test]
     fn test_expired_entry() {
-        let mut cache = DnsCache::new(2);
-        let ttl = 1; // 1 second TTL for quick expiry
-        let entry = create_test_entry(ttl);
+        let (mut cache, clock) = manual_cache(2);
+        let entry = DnsCacheEntry::from_packet(&create_test_packet(), 1, &*clock).unwrap();
 
-        cache.insert("example.com".to_string(), entry.clone()).unwrap();
+        cache.insert(entry_key("example.com"), entry.clone()).unwrap();
+        clock.advance(1);
+        assert!(!entry.is_expired(&*clock));
+        assert!(cache.get(&entry_key("example.com")).is_some());
 
-        std::thread::sleep(Duration::from_secs(2)); // Wait for entry to expire
-
-        assert!(cache.get("example.com").is_none());
+        clock.advance(1);
+        assert!(entry.is_expired(&*clock));
+        assert!(cache.get(&entry_key("example.com")).is_none());
     }
 
     #[test]
@@ -391,13 +832,48 @@ mod tests {
         let ttl = 60;
         let entry = create_test_entry(ttl);
         let mut packet = create_test_packet();
-        packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: [127, 0, 0, 1].into(), ttl });
+        packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: [127, 0, 0, 1].into(), ttl, class: DnsClass::IN });
+
+        cache.insert(entry_key("example.com"), entry.clone()).unwrap();
+        cache.update(&entry_key("example.com"), &packet, ttl).unwrap();
 
-        cache.insert("example.com".to_string(), entry.clone()).unwrap();
-        cache.update("example.com", &packet, ttl).unwrap();
+        let cached_entry = cache.get(&entry_key("example.com")).unwrap();
+        assert_eq!(*cached_entry.packet, packet);
+    }
 
-        let cached_entry = cache.get("example.com").unwrap();
-        assert_eq!(cached_entry.response[..], packet.write_to_bytes().unwrap()[..]);
+    #[test]
+    fn test_entry_toml_round_trip() {
+        let mut packet = create_test_packet();
+        packet.answers.push(DnsRecord::A { domain: "google.com".to_string(), addr: [192, 0, 2, 1].into(), ttl: 60, class: DnsClass::IN });
+        let entry = DnsCacheEntry::from_packet(&packet, 60, &SystemClock).unwrap();
+
+        let restored = DnsCacheEntry::from_toml(&entry.to_toml().unwrap()).unwrap();
+        assert_eq!(restored.packet.answers, packet.answers);
+        assert_eq!((restored.expiry, restored.ttl), (entry.expiry, entry.ttl));
+
+        // Answers larger than the packet buffer are saved at their own length
+        packet.answers = (0..60).map(|i| DnsRecord::A { domain: "google.com".to_string(), addr: [192, 0, 2, i].into(), ttl: 60, class: DnsClass::IN }).collect();
+        let entry = DnsCacheEntry::from_packet(&packet, 60, &SystemClock).unwrap();
+        let restored = DnsCacheEntry::from_toml(&entry.to_toml().unwrap()).unwrap();
+        assert_eq!(restored.packet.answers, packet.answers);
+    }
+
+    #[test]
+    fn test_compressed_snapshot() {
+        let dir = std::env::temp_dir();
+        for compression in [SnapshotCompression::None, SnapshotCompression::Gzip, SnapshotCompression::Zstd] {
+            let mut cache = DnsCache::new(2);
+            cache.compression = compression;
+            cache.insert(entry_key("google.com"), create_test_entry(60)).unwrap();
+            let path = dir.join(format!("r_dns_snapshot_{:?}_{}.toml", compression, std::process::id()));
+            cache.save_to_toml(&path).unwrap();
+
+            assert_eq!(SnapshotCompression::detect(&fs::read(&path).unwrap()), compression);
+            let loaded = DnsCache::load_from_toml(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.compression, compression);
+            assert_eq!(loaded.order, cache.order);
+        }
     }
 
     #[test]
@@ -408,13 +884,102 @@ mod tests {
         let entry2 = create_test_entry(ttl);
         let entry3 = create_test_entry(ttl);
 
-        cache.insert("example1.com".to_string(), entry1).unwrap();
-        cache.insert("example2.com".to_string(), entry2).unwrap();
-        cache.insert("example3.com".to_string(), entry3).unwrap(); // This should evict the oldest entry (example1.com)
+        cache.insert(entry_key("example1.com"), entry1).unwrap();
+        cache.insert(entry_key("example2.com"), entry2).unwrap();
+        cache.insert(entry_key("example3.com"), entry3).unwrap(); // This should evict the oldest entry (example1.com)
+
+        assert!(cache.get(&entry_key("example1.com")).is_none());
+        assert!(cache.get(&entry_key("example2.com")).is_some());
+        assert!(cache.get(&entry_key("example3.com")).is_some());
+    }
+
+    #[test]
+    fn test_timer_queue() {
+        let (mut cache, _) = manual_cache(4);
+        let now = NOW;
+        let response = create_test_packet();
+        cache.insert(entry_key("late.com"), DnsCacheEntry::new(response.clone(), now - 10, 60)).unwrap();
+        cache.insert(entry_key("fresh.com"), DnsCacheEntry::new(response.clone(), now + 60, 60)).unwrap();
+        cache.insert(entry_key("early.com"), DnsCacheEntry::new(response.clone(), now - 20, 60)).unwrap();
+
+        assert_eq!(cache.next_due(), Some(now - 20));
+        assert_eq!(cache.due_keys(), vec![entry_key("early.com"), entry_key("late.com")]);
+        assert!(cache.due_keys().is_empty());
+
+        // Updating an entry puts it back in the queue at its new expiry
+        cache.update(&entry_key("early.com"), &create_test_packet(), 120).unwrap();
+        assert_eq!(cache.next_due(), Some(now + 60));
+        cache.remove(&entry_key("fresh.com"));
+        assert!(cache.next_due().unwrap() >= now + 120);
+    }
+
+    #[test]
+    fn test_refresh_jitter() {
+        let (mut cache, _) = manual_cache(64);
+        let now = NOW;
+        let response = create_test_packet();
+        for i in 0..32 {
+            cache.insert(entry_key(&format!("host{}.com", i)), DnsCacheEntry::new(response.clone(), now + 300, 300)).unwrap();
+        }
+        cache.insert(entry_key("short.com"), DnsCacheEntry::new(response.clone(), now + 4, 4)).unwrap();
+        cache.set_policy(&CacheConfig { refresh_jitter: 30, ..Default::default() });
+
+        // Spread over the window instead of all at once, short TTLs only move by half
+        let due: BTreeSet<u64> = cache.timers.iter().filter(|(_, key)| key.name.starts_with("host")).map(|(due, _)| *due).collect();
+        assert!(due.len() > 1);
+        assert!(due.iter().all(|due| (now + 270..=now + 300).contains(due)));
+        assert!(cache.timers.iter().any(|(due, key)| *key == entry_key("short.com") && *due >= now + 2));
+
+        cache.remove(&entry_key("short.com"));
+        assert_eq!(cache.timers.len(), 32);
+    }
+
+    #[test]
+    fn test_pinned_entries() {
+        let (mut cache, clock) = manual_cache(2);
+        cache.set_policy(&CacheConfig { refresh_jitter: 0, pinned: vec!["VPN.example.com.".to_string()], ..Default::default() });
+        let now = NOW;
+        let response = create_test_packet();
+
+        cache.insert(entry_key("vpn.example.com"), DnsCacheEntry::new(response.clone(), now + 300, 300)).unwrap();
+        assert!(cache.is_pinned(&entry_key("vpn.example.com")));
+        assert!(!cache.is_pinned(&entry_key("example.com")));
+        // Refreshed halfway through instead of at expiry
+        assert_eq!(cache.next_due(), Some(now + 150));
+
+        cache.insert(entry_key("example1.com"), create_test_entry(60)).unwrap();
+        cache.insert(entry_key("example2.com"), create_test_entry(60)).unwrap();
+        assert!(cache.get(&entry_key("vpn.example.com")).is_some());
+        assert!(cache.get(&entry_key("example1.com")).is_none());
+
+        clock.advance(100);
+        cache.schedule_retry(&entry_key("vpn.example.com"), 10);
+        assert_eq!(cache.next_due(), Some(now + 110));
+    }
+
+    #[test]
+    fn test_prewarm_names() {
+        let names = prewarm_names("# top sites\nexample.com\n\n1,Google.com.\n2,example.com\nwiki.lan # internal\n");
+        assert_eq!(names, vec!["example.com", "google.com", "wiki.lan"]);
+    }
+
+    #[test]
+    fn test_domain_policy() {
+        let (mut cache, clock) = manual_cache(4);
+        cache.set_policy(&CacheConfig {
+            policy: HashMap::from([("*.stale.lan".to_string(), DomainPolicy { prefetch: Some(false), serve_stale: Some(600) })]),
+            ..Default::default()
+        });
+        let now = NOW;
+        let response = create_test_packet();
+
+        cache.insert(entry_key("www.stale.lan"), DnsCacheEntry::new(response.clone(), now - 60, 60)).unwrap();
+        cache.insert(entry_key("www.example.com"), DnsCacheEntry::new(response.clone(), now - 60, 60)).unwrap();
 
-        assert!(cache.get("example1.com").is_none());
-        assert!(cache.get("example2.com").is_some());
-        assert!(cache.get("example3.com").is_some());
+        // No refresh timer, but still answered from within the stale window
+        assert_eq!(cache.due_keys(), vec![entry_key("www.example.com")]);
+        assert!(cache.get(&entry_key("www.stale.lan")).unwrap().is_expired(&*clock));
+        assert!(cache.get(&entry_key("www.example.com")).is_none());
     }
 
     // todo: test update_expired
@@ -424,8 +989,8 @@ mod tests {
     //     let ttl = 1; // 1 second TTL for quick expiry
     //     let entry = create_test_entry(ttl);
 
-    //     cache.insert("google.com".to_string(), entry.clone()).unwrap();
-    //     assert!(cache.get("google.com").is_some());
+    //     cache.insert(entry_key("google.com"), entry.clone()).unwrap();
+    //     assert!(cache.get(&entry_key("google.com")).is_some());
 
     //     std::thread::sleep(Duration::from_secs(2)); // Wait for entry to expire
 
@@ -0,0 +1,42 @@
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Where the cache gets the time from. Seconds since the Unix epoch, the only precision expiry has.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> u64;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+}
+
+// Only moves when a test tells it to, expiry can be tested without sleeping
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ManualClock(AtomicU64);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(now: u64) -> ManualClock {
+        ManualClock(AtomicU64::new(now))
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.0.fetch_add(secs, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
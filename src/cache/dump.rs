@@ -0,0 +1,554 @@
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::cache::cache::{DnsCache, DnsCacheEntry};
+use crate::cache::key::CacheKey;
+use crate::utils::dns_class::DnsClass;
+use crate::utils::key_file::from_hex;
+use crate::utils::nsec::from_base32hex;
+use crate::utils::packet::DnsPacket;
+use crate::utils::presentation::{fqdn, record_data};
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+use crate::utils::svcb::{self, key_from_name, SvcParam};
+use crate::utils::result_code::ResultCode;
+
+// A comment line a dump for a standby starts with, followed by the time it was taken
+pub const TIME_PREFIX: &str = ";; at ";
+
+// Negative entries in a dump have no SOA to take a TTL from, the handler caches those for as long
+const NEGATIVE_TTL: u32 = 60;
+
+/*
+Cache dumps use zone file presentation format, the way dig prints answers. Every entry starts
+with its question as a comment, followed by the answer records with their remaining TTL:
+
+;www.example.com.	IN	CNAME	; NOERROR
+www.example.com.	284	IN	CNAME	example.com.
+
+Record lines without a question in front (a plain zone file) become an entry of their own.
+*/
+
+fn parse_type(name: &str) -> Option<QueryType> {
+    name.parse().ok()
+}
+
+fn rcode_name(rescode: ResultCode) -> String {
+    format!("{:?}", rescode)
+}
+
+fn parse_rcode(name: &str) -> Option<ResultCode> {
+    (0..=ResultCode::BADCOOKIE.to_num()).map(ResultCode::from_num).find(|rescode| rcode_name(*rescode).eq_ignore_ascii_case(name))
+}
+
+pub fn export(cache: &DnsCache, out: &mut impl Write) -> io::Result<usize> {
+    export_since(cache, 0, out)
+}
+
+// Only the entries stored or refreshed at `since` (cache clock seconds) or later
+pub fn export_since(cache: &DnsCache, since: u64, out: &mut impl Write) -> io::Result<usize> {
+    let now = cache.clock().now();
+    let mut count = 0;
+    for key in &cache.order {
+        // The dump format has class IN only, and answers for one client subnet don't belong in it
+        if key.qclass != DnsClass::IN || key.ecs_scope.is_some() {
+            continue;
+        }
+        let stored = |entry: &DnsCacheEntry| entry.expiry.saturating_sub(entry.ttl.into());
+        let Some(entry) = cache.cache.get(key).filter(|entry| !entry.is_expired(cache.clock()) && stored(entry) >= since) else {
+            continue;
+        };
+        let packet = &entry.packet;
+        let Some(question) = packet.questions.first() else {
+            continue;
+        };
+        let remaining = (entry.expiry - now) as u32;
+
+        writeln!(out, ";{}\tIN\t{}\t; {}", fqdn(&question.name), question.qtype.name(), rcode_name(packet.header.rescode))?;
+        for record in &packet.answers {
+            if let Some(data) = record_data(record) {
+                let ttl = record.ttl().min(remaining);
+                writeln!(out, "{}\t{}\t{}\t{}\t{}", fqdn(record.domain()), ttl, record.class().name(), record.query_type().name(), data)?;
+            }
+        }
+        writeln!(out)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn invalid(line: usize, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", line, msg))
+}
+
+fn parse_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// Splits on whitespace, keeping quoted strings (with their escapes) in one piece
+fn tokens(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            let mut token = String::from(chars.next().unwrap());
+            while let Some(c) = chars.next() {
+                token.push(c);
+                if c == '\\' {
+                    token.extend(chars.next());
+                } else if c == '"' {
+                    break;
+                }
+            }
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+    tokens
+}
+
+fn parse_txt(token: &str) -> Option<Vec<u8>> {
+    let inner = token.strip_prefix('"')?.strip_suffix('"')?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.to_string().as_bytes());
+            continue;
+        }
+        let next = chars.next()?;
+        if next.is_ascii_digit() {
+            let digits: String = [Some(next), chars.next(), chars.next()].into_iter().collect::<Option<_>>()?;
+            bytes.push(digits.parse().ok()?);
+        } else {
+            bytes.push(next as u8);
+        }
+    }
+    Some(bytes)
+}
+
+// `key=value`, `key="value"` or a bare key, each value in the form RFC 9460 appendix A gives for its key
+fn parse_svc_param(field: &str) -> Option<SvcParam> {
+    let (name, value) = field.split_once('=').unwrap_or((field, ""));
+    let key = key_from_name(name)?;
+    let value = parse_txt(&if value.starts_with('"') { value.to_string() } else { format!("\"{}\"", value) })?;
+    let text = String::from_utf8(value.clone()).ok();
+    let list = || text.as_deref().filter(|text| !text.is_empty()).map(|text| text.split(','));
+    let value = match key {
+        svcb::KEY_MANDATORY => list()?.map(|name| key_from_name(name).map(u16::to_be_bytes)).collect::<Option<Vec<_>>>()?.concat(),
+        svcb::KEY_ALPN => SvcParam::alpn(&list()?.collect::<Vec<_>>()).value,
+        svcb::KEY_NO_DEFAULT_ALPN if value.is_empty() => value,
+        svcb::KEY_NO_DEFAULT_ALPN => return None,
+        svcb::KEY_PORT => text?.parse::<u16>().ok()?.to_be_bytes().to_vec(),
+        svcb::KEY_IPV4HINT => SvcParam::ipv4hint(&list()?.map(|addr| addr.parse().ok()).collect::<Option<Vec<_>>>()?).value,
+        svcb::KEY_ECH => STANDARD.decode(text?).ok()?,
+        svcb::KEY_IPV6HINT => SvcParam::ipv6hint(&list()?.map(|addr| addr.parse().ok()).collect::<Option<Vec<_>>>()?).value,
+        _ => value,
+    };
+    Some(SvcParam::new(key, value))
+}
+
+// Hex pairs joined by hyphens, exactly N of them
+fn parse_eui<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let mut addr = [0; N];
+    let mut pairs = text.split('-');
+    for byte in addr.iter_mut() {
+        let pair = pairs.next().filter(|pair| pair.len() == 2)?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    pairs.next().is_none().then_some(addr)
+}
+
+// Degrees, then optional minutes and seconds, then the hemisphere, as thousandths of an arc second offset by 2^31
+fn parse_loc_angle(fields: &mut dyn Iterator<Item = &&str>, positive: &str, negative: &str, max: u64) -> Option<u32> {
+    let mut parts = Vec::new();
+    let sign = loop {
+        match *fields.next()? {
+            field if field.eq_ignore_ascii_case(positive) => break 1,
+            field if field.eq_ignore_ascii_case(negative) => break -1,
+            field if parts.len() < 3 => parts.push(field),
+            _ => return None,
+        }
+    };
+    let degrees: u64 = parts.first()?.parse().ok()?;
+    let minutes: u64 = parts.get(1).map_or(Some(0), |minutes| minutes.parse().ok().filter(|&minutes| minutes < 60))?;
+    let seconds: f64 = parts.get(2).map_or(Some(0.0), |seconds| seconds.parse().ok().filter(|&seconds| (0.0..60.0).contains(&seconds)))?;
+    let thousandths = (degrees * 3600 + minutes * 60) * 1000 + (seconds * 1000.0).round() as u64;
+    if thousandths > max * 3_600_000 {
+        return None;
+    }
+    Some(((1i64 << 31) + sign * thousandths as i64) as u32)
+}
+
+// "-24.00m" or "30", in centimetres
+fn parse_centimetres(field: &str) -> Option<i64> {
+    let metres: f64 = field.strip_suffix(['m', 'M']).unwrap_or(field).parse().ok()?;
+    metres.is_finite().then(|| (metres * 100.0).round() as i64)
+}
+
+// A size or precision as a mantissa and power of ten, rounded down like RFC 1876 does
+fn loc_size(field: &str) -> Option<u8> {
+    let (mut mantissa, mut exponent) = (u64::try_from(parse_centimetres(field)?).ok()?, 0);
+    while mantissa > 9 {
+        mantissa /= 10;
+        exponent += 1;
+    }
+    (exponent <= 9).then_some((mantissa << 4) as u8 | exponent)
+}
+
+// `d [m [s]] N|S d [m [s]] E|W alt[m] [size[m] [hp[m] [vp[m]]]]` (RFC 1876 3), sizes default to 1m, 10km and 10m
+fn parse_loc(domain: String, data: &[&str], ttl: u32, class: DnsClass) -> Option<DnsRecord> {
+    let mut fields = data.iter();
+    let latitude = parse_loc_angle(&mut fields, "N", "S", 90)?;
+    let longitude = parse_loc_angle(&mut fields, "E", "W", 180)?;
+    let altitude = u32::try_from(parse_centimetres(fields.next()?)? + 10_000_000).ok()?;
+    let mut sizes = [0x12, 0x16, 0x13];
+    for size in sizes.iter_mut() {
+        if let Some(field) = fields.next() {
+            *size = loc_size(field)?;
+        }
+    }
+    if fields.next().is_some() {
+        return None;
+    }
+    let [size, horizontal_precision, vertical_precision] = sizes;
+    Some(DnsRecord::LOC { domain, size, horizontal_precision, vertical_precision, latitude, longitude, altitude, ttl, class })
+}
+
+// `name ttl [IN] type data`, the form `export` writes
+pub fn parse_record(line: &str) -> Option<DnsRecord> {
+    let tokens = tokens(line);
+    let mut fields = tokens.iter().map(String::as_str);
+    let domain = parse_name(fields.next()?);
+    let ttl = fields.next()?.parse().ok()?;
+    let mut qtype = fields.next()?;
+    // ANY is a type as well, records only have that class in UPDATE messages
+    let class = match DnsClass::from_name(qtype).filter(|class| *class != DnsClass::ANY) {
+        Some(class) => {
+            qtype = fields.next()?;
+            class
+        }
+        None => DnsClass::IN,
+    };
+    let data: Vec<&str> = fields.collect();
+
+    let record = match (parse_type(qtype)?, data.as_slice()) {
+        (QueryType::A, [addr]) => DnsRecord::A { domain, addr: addr.parse::<Ipv4Addr>().ok()?, ttl, class },
+        (QueryType::AAAA, [addr]) => DnsRecord::AAAA { domain, addr: addr.parse::<Ipv6Addr>().ok()?, ttl, class },
+        (QueryType::NS, [ns]) => DnsRecord::NS { domain, ns: parse_name(ns), ttl, class },
+        (QueryType::CNAME, [cname]) => DnsRecord::CNAME { domain, cname: parse_name(cname), ttl, class },
+        (QueryType::DNAME, [target]) => DnsRecord::DNAME { domain, target: parse_name(target), ttl, class },
+        (QueryType::PTR, [host]) => DnsRecord::PTR { domain, host: parse_name(host), ttl, class },
+        (QueryType::MX, [preference, exchange]) => DnsRecord::MX { domain, preference: preference.parse().ok()?, exchange: parse_name(exchange), ttl, class },
+        (QueryType::SRV, [priority, weight, port, target]) => DnsRecord::SRV {
+            domain,
+            priority: priority.parse().ok()?,
+            weight: weight.parse().ok()?,
+            port: port.parse().ok()?,
+            target: parse_name(target),
+            ttl,
+            class,
+        },
+        (QueryType::SOA, [mname, rname, serial, refresh, retry, expire, minimum]) => DnsRecord::SOA {
+            domain,
+            mname: parse_name(mname),
+            rname: parse_name(rname),
+            serial: serial.parse().ok()?,
+            refresh: refresh.parse().ok()?,
+            retry: retry.parse().ok()?,
+            expire: expire.parse().ok()?,
+            minimum: minimum.parse().ok()?,
+            ttl,
+            class,
+        },
+        (QueryType::NSEC, [next, types @ ..]) => DnsRecord::NSEC {
+            domain,
+            next: parse_name(next),
+            types: types.iter().map(|name| parse_type(name)).collect::<Option<_>>()?,
+            ttl,
+            class,
+        },
+        (QueryType::NSEC3, [algorithm, flags, iterations, salt, next, types @ ..]) => DnsRecord::NSEC3 {
+            domain,
+            algorithm: algorithm.parse().ok()?,
+            flags: flags.parse().ok()?,
+            iterations: iterations.parse().ok()?,
+            salt: if *salt == "-" { Vec::new() } else { from_hex(salt)? },
+            next: from_base32hex(next)?,
+            types: types.iter().map(|name| parse_type(name)).collect::<Option<_>>()?,
+            ttl,
+            class,
+        },
+        (QueryType::HINFO, [cpu, os]) => DnsRecord::HINFO { domain, cpu: parse_txt(cpu)?, os: parse_txt(os)?, ttl, class },
+        (QueryType::URI, [priority, weight, target]) => DnsRecord::URI {
+            domain,
+            priority: priority.parse().ok()?,
+            weight: weight.parse().ok()?,
+            target: parse_txt(target)?,
+            ttl,
+            class,
+        },
+        (QueryType::ZONEMD, [serial, scheme, algorithm, digest @ ..]) if !digest.is_empty() => DnsRecord::ZONEMD {
+            domain,
+            serial: serial.parse().ok()?,
+            scheme: scheme.parse().ok()?,
+            algorithm: algorithm.parse().ok()?,
+            digest: from_hex(&digest.concat())?,
+            ttl,
+            class,
+        },
+        (QueryType::LOC, data) => parse_loc(domain, data, ttl, class)?,
+        (qtype @ (QueryType::SVCB | QueryType::HTTPS), [priority, target, params @ ..]) => {
+            let (priority, target) = (priority.parse().ok()?, parse_name(target));
+            let mut params = params.iter().map(|field| parse_svc_param(field)).collect::<Option<Vec<_>>>()?;
+            params.sort_by_key(|param| param.key);
+            if params.windows(2).any(|pair| pair[0].key == pair[1].key) {
+                return None;
+            }
+            match qtype {
+                QueryType::SVCB => DnsRecord::SVCB { domain, priority, target, params, ttl, class },
+                _ => DnsRecord::HTTPS { domain, priority, target, params, ttl, class },
+            }
+        }
+        (QueryType::DNSKEY, [flags, protocol, algorithm, key @ ..]) if !key.is_empty() => DnsRecord::DNSKEY {
+            domain,
+            flags: flags.parse().ok()?,
+            protocol: protocol.parse().ok()?,
+            algorithm: algorithm.parse().ok()?,
+            public_key: STANDARD.decode(key.concat()).ok()?,
+            ttl,
+            class,
+        },
+        (QueryType::RRSIG, [covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature @ ..]) if !signature.is_empty() => DnsRecord::RRSIG {
+            domain,
+            type_covered: parse_type(covered)?,
+            algorithm: algorithm.parse().ok()?,
+            labels: labels.parse().ok()?,
+            original_ttl: original_ttl.parse().ok()?,
+            expiration: expiration.parse().ok()?,
+            inception: inception.parse().ok()?,
+            key_tag: key_tag.parse().ok()?,
+            signer: parse_name(signer),
+            signature: STANDARD.decode(signature.concat()).ok()?,
+            ttl,
+            class,
+        },
+        (QueryType::DHCID, data) if !data.is_empty() => DnsRecord::DHCID { domain, data: STANDARD.decode(data.concat()).ok()?, ttl, class },
+        (QueryType::EUI48, [addr]) => DnsRecord::EUI48 { domain, addr: parse_eui(addr)?, ttl, class },
+        (QueryType::EUI64, [addr]) => DnsRecord::EUI64 { domain, addr: parse_eui(addr)?, ttl, class },
+        (QueryType::TXT, strings) if !strings.is_empty() => DnsRecord::TXT {
+            domain,
+            data: strings.iter().map(|string| parse_txt(string)).collect::<Option<_>>()?,
+            ttl,
+            class,
+        },
+        _ => return None,
+    };
+    Some(record)
+}
+
+fn parse_question(line: &str) -> Option<(DnsQuestion, ResultCode)> {
+    let (question, rcode) = line.strip_prefix(';')?.split_once(';')?;
+    let fields: Vec<&str> = question.split_whitespace().collect();
+    let [name, class, qtype] = fields.as_slice() else {
+        return None;
+    };
+    if !class.eq_ignore_ascii_case("IN") {
+        return None;
+    }
+    Some((DnsQuestion::new(parse_name(name), parse_type(qtype)?), parse_rcode(rcode.trim())?))
+}
+
+fn insert(cache: &mut DnsCache, question: DnsQuestion, rescode: ResultCode, answers: Vec<DnsRecord>) -> io::Result<()> {
+    let ttl = answers.iter().map(DnsRecord::ttl).min().unwrap_or(NEGATIVE_TTL);
+    let mut packet = DnsPacket::new();
+    packet.header.response = true;
+    packet.header.recursion_available = true;
+    packet.header.rescode = rescode;
+    packet.questions.push(question.clone());
+    packet.answers = answers;
+    cache.insert(CacheKey::for_question(&question), DnsCacheEntry::from_packet(&packet, ttl, cache.clock())?)
+}
+
+// Returns how many entries were added. Entries already in the cache are kept as they are.
+pub fn import(cache: &mut DnsCache, text: &str) -> io::Result<usize> {
+    let mut count = 0;
+    let mut current: Option<(DnsQuestion, ResultCode, Vec<DnsRecord>)> = None;
+
+    for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() || line.starts_with(";;") {
+            continue;
+        }
+        if line.starts_with(';') {
+            if let Some((question, rescode, answers)) = current.take() {
+                insert(cache, question, rescode, answers)?;
+                count += 1;
+            }
+            current = Some(parse_question(line).map(|(question, rescode)| (question, rescode, Vec::new()))
+                .ok_or_else(|| invalid(number, "expected ;name IN type ; status"))?);
+            continue;
+        }
+
+        let record = parse_record(line).ok_or_else(|| invalid(number, "unsupported or malformed record"))?;
+        match &mut current {
+            Some((_, _, answers)) => answers.push(record),
+            None => {
+                let question = DnsQuestion::new(record.domain().to_string(), record.query_type());
+                insert(cache, question, ResultCode::NOERROR, vec![record])?;
+                count += 1;
+            }
+        }
+    }
+    if let Some((question, rescode, answers)) = current {
+        insert(cache, question, rescode, answers)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::clock::ManualClock;
+    use std::sync::Arc;
+
+    const DUMP: &str = ";www.example.com.\tIN\tA\t; NOERROR
+www.example.com.\t300\tIN\tCNAME\texample.com.
+example.com.\t300\tIN\tA\t192.0.2.1
+
+;missing.example.com.\tIN\tAAAA\t; NXDOMAIN
+
+;example.com.\tIN\tTXT\t; NOERROR
+example.com.\t60\tIN\tTXT\t\"v=spf1 -all\" \"a\\\"b\\255\"
+";
+
+    #[test]
+    fn test_import_export_round_trip() {
+        let mut cache = DnsCache::new(16);
+        assert_eq!(import(&mut cache, DUMP).unwrap(), 3);
+
+        let packet = cache.get(&CacheKey::new("missing.example.com", QueryType::AAAA)).unwrap().packet.clone();
+        assert_eq!(packet.header.rescode, ResultCode::NXDOMAIN);
+        let packet = cache.get(&CacheKey::new("www.example.com", QueryType::A)).unwrap().packet.clone();
+        assert_eq!(packet.answers.len(), 2);
+        let packet = cache.get(&CacheKey::new("example.com", QueryType::TXT)).unwrap().packet.clone();
+        assert_eq!(packet.answers[0], DnsRecord::TXT {
+            domain: "example.com".to_string(),
+            data: vec![b"v=spf1 -all".to_vec(), b"a\"b\xff".to_vec()],
+            ttl: 60,
+            class: DnsClass::IN,
+        });
+
+        let mut out = Vec::new();
+        assert_eq!(export(&cache, &mut out).unwrap(), 3);
+        let mut again = DnsCache::new(16);
+        assert_eq!(import(&mut again, &String::from_utf8(out).unwrap()).unwrap(), 3);
+        assert_eq!(again.order, cache.order);
+    }
+
+    #[test]
+    fn test_export_since() {
+        let clock = Arc::new(ManualClock::new(1000));
+        let mut cache = DnsCache::with_clock(16, clock.clone());
+        import(&mut cache, DUMP).unwrap();
+        clock.advance(10);
+        import(&mut cache, "new.example.com. 300 IN A 192.0.2.2\n").unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(export_since(&cache, 1010, &mut out).unwrap(), 1);
+        assert!(String::from_utf8(out).unwrap().contains("new.example.com."));
+        assert_eq!(export_since(&cache, 1000, &mut Vec::new()).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_import_plain_zone_lines() {
+        let mut cache = DnsCache::new(16);
+        assert_eq!(import(&mut cache, "mail.example.com. 3600 IN MX 10 mx.example.com.\nexample.com. 3600 TYPE28 2001:db8::1\n_sip._udp.example.com. 3600 IN SRV 10 60 5060 sip.example.com.\n").unwrap(), 3);
+        assert!(cache.get(&CacheKey::new("mail.example.com", QueryType::MX)).is_some());
+        assert!(cache.get(&CacheKey::new("example.com", QueryType::AAAA)).is_some());
+        assert!(cache.get(&CacheKey::new("_sip._udp.example.com", QueryType::SRV)).is_some());
+
+        let err = import(&mut cache, "example.com. 60 IN A not-an-address\n").unwrap_err();
+        assert!(err.to_string().starts_with("Line 1"));
+    }
+
+    #[test]
+    fn test_parse_hinfo_uri_and_loc() {
+        for line in [
+            "example.com.\t300\tIN\tHINFO\t\"x86_64\" \"Linux\"",
+            "_ftp._tcp.example.com.\t300\tIN\tURI\t10 1 \"ftp://ftp1.example.com/public\"",
+            "caida.org.\t300\tIN\tLOC\t32 53 1.000 N 117 14 25.000 W 107.00m 30m 10m 10m",
+        ] {
+            let record = parse_record(line).unwrap();
+            assert_eq!(format!("{}.\t300\tIN\t{}\t{}", record.domain(), record.query_type().name(), record_data(&record).unwrap()), line);
+        }
+
+        // Minutes, seconds and sizes can be left out
+        let record = parse_record("example.com. 300 IN LOC 52 S 4 30 E 10").unwrap();
+        assert_eq!(record_data(&record).unwrap(), "52 0 0.000 S 4 30 0.000 E 10.00m 1m 10000m 10m");
+        assert!(parse_record("example.com. 300 IN LOC 91 N 4 E 10m").is_none());
+    }
+
+    #[test]
+    fn test_parse_svcb() {
+        for line in [
+            "example.com.\t300\tIN\tHTTPS\t1 . alpn=h2,h3 port=8443 ipv4hint=192.0.2.1,192.0.2.2 ipv6hint=2001:db8::1",
+            "example.com.\t300\tIN\tHTTPS\t0 svc.example.net.",
+            "_dns.resolver.arpa.\t300\tIN\tSVCB\t1 dns.example.net. mandatory=alpn alpn=dot no-default-alpn key65000=a\\032b",
+        ] {
+            let record = parse_record(line).unwrap();
+            assert_eq!(format!("{}.\t300\tIN\t{}\t{}", record.domain(), record.query_type().name(), record_data(&record).unwrap()), line);
+        }
+
+        // Parameters may come in any order and quoted, but only once each
+        let record = parse_record(r#"example.com. 300 IN HTTPS 1 . port="443" alpn="h2""#).unwrap();
+        assert_eq!(record_data(&record).unwrap(), "1 . alpn=h2 port=443");
+        assert!(parse_record("example.com. 300 IN HTTPS 1 . port=443 port=8443").is_none());
+        assert!(parse_record("example.com. 300 IN HTTPS 1 . ipv4hint=2001:db8::1").is_none());
+    }
+
+    #[test]
+    fn test_parse_eui_and_dhcid() {
+        for line in [
+            "host.example.\t300\tIN\tEUI48\t00-00-5e-00-53-2a",
+            "host.example.\t300\tIN\tEUI64\t00-00-5e-ef-10-00-00-2a",
+            "client.example.com.\t300\tIN\tDHCID\tAAIBY2/AuCccgoJbsaxcQc9TUapptP69lOjxfNuVAA2kjEA=",
+        ] {
+            let record = parse_record(line).unwrap();
+            assert_eq!(format!("{}.\t300\tIN\t{}\t{}", record.domain(), record.query_type().name(), record_data(&record).unwrap()), line);
+        }
+
+        // Base64 may be split over several fields, addresses need every pair
+        let record = parse_record("client.example.com. 300 IN DHCID AAIBY2/AuCccgoJbsaxcQc9TUapptP69l OjxfNuVAA2kjEA=").unwrap();
+        assert!(matches!(record, DnsRecord::DHCID { ref data, .. } if data.len() == 35));
+        assert!(parse_record("host.example. 300 IN EUI48 00-00-5e-00-53").is_none());
+        assert!(parse_record("host.example. 300 IN EUI48 00:00:5e:00:53:2a").is_none());
+    }
+
+    #[test]
+    fn test_parse_dnskey_and_rrsig() {
+        // The Ed25519 example of RFC 8080 6.1
+        for line in [
+            "example.com.\t3600\tIN\tDNSKEY\t257 3 15 l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=",
+            "example.com.\t3600\tIN\tRRSIG\tMX 15 2 3600 1440021600 1438207200 3613 example.com. oL9krJun7xfBOIWcGHi7mag5/hdZrKWw15jPGrHpjQeRAvTdszaPD+QLs3fx8A4M3e23mRZ9VrbpMngwcrqNAg==",
+        ] {
+            let record = parse_record(line).unwrap();
+            assert_eq!(format!("{}.\t3600\tIN\t{}\t{}", record.domain(), record.query_type().name(), record_data(&record).unwrap()), line);
+        }
+        assert!(parse_record("example.com. 3600 IN RRSIG MX 15 2 3600 1440021600 1438207200 3613 example.com.").is_none());
+    }
+}
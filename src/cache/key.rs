@@ -0,0 +1,182 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::utils::dns_class::DnsClass;
+use crate::utils::memory::HeapSize;
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::subnet::Subnet;
+
+/**
+The path an answer was learned on, from the least to the most trustworthy. Answers learned one
+way are filed apart from those learned another, so a client that asks for authenticated data
+can be kept from answers that crossed the network in the clear.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provenance {
+    #[default]
+    Plain,     // UDP or TCP in the clear, from upstreams or authoritative servers
+    Encrypted, // from an upstream over an encrypted transport
+    Validated, // checked by a DNSSEC validator of our own
+}
+
+impl Provenance {
+    // The order cached answers are looked for in
+    pub const BEST_FIRST: [Provenance; 3] = [Provenance::Validated, Provenance::Encrypted, Provenance::Plain];
+
+    fn name(self) -> &'static str {
+        match self {
+            Provenance::Plain => "plain",
+            Provenance::Encrypted => "encrypted",
+            Provenance::Validated => "validated",
+        }
+    }
+}
+
+/**
+What a cached answer is filed under. The name is kept canonical, lowercase and without the
+trailing dot, so "WWW.Example.com." and "www.example.com" share one entry, while an answer for
+another class or for another client subnet (RFC 7871) never stands in for this one. `ecs_scope`
+is the subnet an upstream said its answer holds for, masked to its prefix; None for answers that
+hold for every client.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub name: String,
+    pub qtype: QueryType,
+    pub qclass: DnsClass,
+    pub ecs_scope: Option<Subnet>,
+    pub provenance: Provenance,
+}
+
+pub fn canonical_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl CacheKey {
+    // Class IN, for every client, learned in the clear
+    pub fn new(name: &str, qtype: QueryType) -> CacheKey {
+        CacheKey { name: canonical_name(name), qtype, qclass: DnsClass::IN, ecs_scope: None, provenance: Provenance::Plain }
+    }
+
+    pub fn for_question(question: &DnsQuestion) -> CacheKey {
+        CacheKey { qclass: question.qclass, ..CacheKey::new(&question.name, question.qtype) }
+    }
+
+    pub fn with_scope(self, scope: Subnet) -> CacheKey {
+        CacheKey { ecs_scope: Some(scope.network()), ..self }
+    }
+
+    pub fn learned(self, provenance: Provenance) -> CacheKey {
+        CacheKey { provenance, ..self }
+    }
+
+    fn sort_key(&self) -> (&str, u16, DnsClass, Option<(IpAddr, u8)>, Provenance) {
+        (&self.name, self.qtype.to_num(), self.qclass, self.ecs_scope.map(|scope| (scope.addr, scope.prefix)), self.provenance)
+    }
+}
+
+// Only so keys can sit in the timer queue next to their refresh time
+impl Ord for CacheKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl PartialOrd for CacheKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl HeapSize for CacheKey {
+    fn heap_size(&self) -> usize {
+        self.name.heap_size()
+    }
+}
+
+// "name-qtype", then "/class" for classes other than IN, "@subnet" for a scope and "#provenance"
+// for answers not learned in the clear. Keys of class IN without a scope learned in the clear look
+// like the plain strings caches were saved with before, so those still load.
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.name, self.qtype.to_num())?;
+        if self.qclass != DnsClass::IN {
+            write!(f, "/{}", self.qclass.to_num())?;
+        }
+        if let Some(scope) = self.ecs_scope {
+            write!(f, "@{}/{}", scope.addr, scope.prefix)?;
+        }
+        if self.provenance != Provenance::Plain {
+            write!(f, "#{}", self.provenance.name())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CacheKey {
+    type Err = String;
+
+    // Names may contain dashes and even '@' themselves, so everything is split off from the right
+    fn from_str(s: &str) -> Result<CacheKey, String> {
+        let (rest, provenance) = match s.rsplit_once('#') {
+            Some((rest, "encrypted")) => (rest, Provenance::Encrypted),
+            Some((rest, "validated")) => (rest, Provenance::Validated),
+            _ => (s, Provenance::Plain),
+        };
+        let (rest, ecs_scope) = match rest.rsplit_once('@') {
+            Some((name, scope)) => match scope.parse::<Subnet>() {
+                Ok(scope) => (name, Some(scope.network())),
+                Err(_) => (rest, None),
+            },
+            None => (rest, None),
+        };
+        let (name, kind) = rest.rsplit_once('-').ok_or_else(|| format!("Invalid cache key {:?}", s))?;
+        let (qtype, qclass) = kind.split_once('/').map(|(qtype, qclass)| (qtype, Some(qclass))).unwrap_or((kind, None));
+        let qtype = qtype.parse().map_err(|_| format!("Invalid type in cache key {:?}", s))?;
+        let qclass = match qclass {
+            Some(qclass) => DnsClass::from_num(qclass.parse().map_err(|_| format!("Invalid class in cache key {:?}", s))?),
+            None => DnsClass::IN,
+        };
+        Ok(CacheKey { name: canonical_name(name), qtype: QueryType::from_num(qtype), qclass, ecs_scope, provenance })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_keys() {
+        assert_eq!(CacheKey::new("WWW.Example.com.", QueryType::A), CacheKey::new("www.example.com", QueryType::A));
+        let chaos = DnsQuestion { name: "www.example.com".to_string(), qtype: QueryType::A, qclass: DnsClass::CH };
+        assert_ne!(CacheKey::for_question(&chaos), CacheKey::new("www.example.com", QueryType::A));
+
+        let scoped = CacheKey::new("www.example.com", QueryType::A).with_scope("192.0.2.77/24".parse().unwrap());
+        assert_eq!(scoped.ecs_scope, Some("192.0.2.0/24".parse().unwrap()));
+        assert_ne!(scoped, CacheKey::new("www.example.com", QueryType::A));
+        assert_ne!(CacheKey::new("www.example.com", QueryType::A).learned(Provenance::Encrypted), CacheKey::new("www.example.com", QueryType::A));
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        // The plain keys caches used to be saved with
+        let key: CacheKey = "my-vpn.example.com-28".parse().unwrap();
+        assert_eq!(key, CacheKey::new("my-vpn.example.com", QueryType::AAAA));
+        assert_eq!(key.to_string(), "my-vpn.example.com-28");
+        assert!("example.com".parse::<CacheKey>().is_err());
+
+        let key = CacheKey { qclass: DnsClass::CH, ..CacheKey::new("a@b-c.example", QueryType::TXT) }.with_scope("2001:db8::1/48".parse().unwrap());
+        assert_eq!(key.to_string(), "a@b-c.example-16/3@2001:db8::/48");
+        assert_eq!(key.to_string().parse::<CacheKey>(), Ok(key.clone()));
+
+        let key = key.learned(Provenance::Validated);
+        assert_eq!(key.to_string(), "a@b-c.example-16/3@2001:db8::/48#validated");
+        assert_eq!(key.to_string().parse::<CacheKey>(), Ok(key));
+    }
+}
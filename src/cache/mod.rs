@@ -1 +1,9 @@
-pub mod cache;
\ No newline at end of file
+pub mod backend;
+#[allow(clippy::module_inception)]
+pub mod cache;
+pub mod clock;
+pub mod dump;
+pub mod key;
+pub mod policy;
+pub mod refresh_pool;
+pub mod supervisor;
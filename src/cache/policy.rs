@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::utils::name::is_subdomain;
+
+// TTL of answers served from an expired entry, as RFC 8767 recommends
+pub const STALE_TTL: u32 = 30;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DomainPolicy {
+    pub prefetch: Option<bool>,   // refresh entries before they expire, on unless set to false
+    pub serve_stale: Option<u64>, // seconds an expired entry may still be answered from
+}
+
+impl DomainPolicy {
+    pub fn prefetch(&self) -> bool {
+        self.prefetch.unwrap_or(true)
+    }
+
+    pub fn serve_stale(&self) -> u64 {
+        self.serve_stale.unwrap_or(0)
+    }
+}
+
+// "example.com" only matches the name itself, "*.example.com" every name below it
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(zone) => is_subdomain(name, zone) && !name.eq_ignore_ascii_case(zone),
+        None => name.eq_ignore_ascii_case(pattern),
+    }
+}
+
+fn normalize(pattern: &str) -> String {
+    pattern.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// The longest matching pattern is the most specific one
+fn most_specific<'a, T>(rules: &'a [(String, T)], name: &str) -> Option<&'a T> {
+    rules.iter()
+        .filter(|(pattern, _)| matches(pattern, name))
+        .max_by_key(|(pattern, _)| pattern.len())
+        .map(|(_, value)| value)
+}
+
+/**
+Per-domain overrides of how entries are cached, from the `ttl_override` and `policy` tables of
+the `[cache]` section. TTL overrides rewrite the records themselves, so clients see the same TTL
+the cache uses.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DomainRules {
+    ttl_override: Vec<(String, u32)>,
+    policy: Vec<(String, DomainPolicy)>,
+}
+
+impl DomainRules {
+    pub fn new(ttl_override: &HashMap<String, u32>, policy: &HashMap<String, DomainPolicy>) -> DomainRules {
+        DomainRules {
+            ttl_override: ttl_override.iter().map(|(pattern, ttl)| (normalize(pattern), *ttl)).collect(),
+            policy: policy.iter().map(|(pattern, policy)| (normalize(pattern), policy.clone())).collect(),
+        }
+    }
+
+    pub fn ttl(&self, name: &str) -> Option<u32> {
+        most_specific(&self.ttl_override, name).copied()
+    }
+
+    pub fn policy(&self, name: &str) -> DomainPolicy {
+        most_specific(&self.policy, name).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_specific_rule_wins() {
+        let ttl_override = HashMap::from([
+            ("*.internal.lan".to_string(), 30),
+            ("*.db.internal.lan.".to_string(), 5),
+            ("Internal.lan".to_string(), 600),
+        ]);
+        let rules = DomainRules::new(&ttl_override, &HashMap::new());
+
+        assert_eq!(rules.ttl("www.internal.lan"), Some(30));
+        assert_eq!(rules.ttl("primary.db.internal.lan"), Some(5));
+        assert_eq!(rules.ttl("internal.lan"), Some(600));
+        assert_eq!(rules.ttl("notinternal.lan"), None);
+    }
+
+    #[test]
+    fn test_policy_defaults() {
+        let policy = HashMap::from([("*.example.com".to_string(), DomainPolicy { prefetch: Some(false), serve_stale: Some(3600) })]);
+        let rules = DomainRules::new(&HashMap::new(), &policy);
+
+        assert!(!rules.policy("www.example.com").prefetch());
+        assert_eq!(rules.policy("www.example.com").serve_stale(), 3600);
+        assert!(rules.policy("example.org").prefetch());
+        assert_eq!(rules.policy("example.org").serve_stale(), 0);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use log::error;
+
+use crate::cache::key::CacheKey;
+
+// Without a public suffix list the last two labels stand in for the zone a name belongs to
+fn zone_of(name: &str) -> String {
+    let mut labels: Vec<&str> = name.rsplitn(3, '.').take(2).collect();
+    labels.reverse();
+    labels.join(".")
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    queue: VecDeque<CacheKey>,
+    pending: HashSet<CacheKey>,     // queued or running, a key is never looked up twice at once
+    running: HashMap<String, usize>, // lookups in flight per zone
+}
+
+/**
+Runs the cache's background lookups (refreshes of due entries and of stale entries that were just
+served) on a fixed set of worker threads, so however many entries come due at once they never
+take more than `workers` sockets and threads away from foreground queries. A zone gets at most
+`per_zone` of them, a zone with thousands of cached names can't occupy every worker or hammer its
+servers. The queue holds `capacity` keys, submitting more fails and the caller decides when to
+try again.
+*/
+#[derive(Clone)]
+pub struct RefreshPool {
+    state: Arc<(Mutex<PoolState>, Condvar)>,
+    per_zone: usize,
+    capacity: usize,
+}
+
+impl RefreshPool {
+    pub fn new<F>(workers: usize, per_zone: usize, capacity: usize, refresh: F) -> RefreshPool
+    where
+        F: Fn(&CacheKey) + Send + Sync + 'static,
+    {
+        let pool = RefreshPool { state: Arc::default(), per_zone: per_zone.max(1), capacity: capacity.max(1) };
+        let refresh = Arc::new(refresh);
+        for i in 0..workers.max(1) {
+            let (pool, refresh) = (pool.clone(), Arc::clone(&refresh));
+            thread::Builder::new()
+                .name(format!("cache-refresh-{}", i))
+                .spawn(move || loop {
+                    let (key, zone) = pool.next();
+                    // A panicking lookup costs one refresh, not a worker
+                    if catch_unwind(AssertUnwindSafe(|| refresh(&key))).is_err() {
+                        error!("Refreshing {} panicked", key);
+                    }
+                    pool.done(&key, &zone);
+                })
+                .expect("Failed to start cache refresh worker");
+        }
+        pool
+    }
+
+    // Queues a lookup of `key`, false if the queue is full. A key already queued counts as queued.
+    pub fn submit(&self, key: &CacheKey) -> bool {
+        let (state, wakeup) = &*self.state;
+        let mut state = state.lock().unwrap();
+        if state.pending.contains(key) {
+            return true;
+        }
+        if state.queue.len() >= self.capacity {
+            return false;
+        }
+        state.pending.insert(key.clone());
+        state.queue.push_back(key.clone());
+        wakeup.notify_one();
+        true
+    }
+
+    // Waits for the oldest queued key whose zone is below its limit
+    fn next(&self) -> (CacheKey, String) {
+        let (state, wakeup) = &*self.state;
+        let mut state = state.lock().unwrap();
+        loop {
+            let ready = state.queue.iter().position(|key| state.running.get(&zone_of(&key.name)).copied().unwrap_or(0) < self.per_zone);
+            if let Some(key) = ready.and_then(|i| state.queue.remove(i)) {
+                let zone = zone_of(&key.name);
+                *state.running.entry(zone.clone()).or_default() += 1;
+                return (key, zone);
+            }
+            state = wakeup.wait(state).unwrap();
+        }
+    }
+
+    fn done(&self, key: &CacheKey, zone: &str) {
+        let (state, wakeup) = &*self.state;
+        let mut state = state.lock().unwrap();
+        state.pending.remove(key);
+        if let Some(running) = state.running.get_mut(zone) {
+            *running -= 1;
+            if *running == 0 {
+                state.running.remove(zone);
+            }
+        }
+        // A worker waiting for this zone can go on now
+        wakeup.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn key(key: &str) -> CacheKey {
+        key.parse().unwrap()
+    }
+
+    #[test]
+    fn test_zone_of() {
+        assert_eq!(zone_of("www.a.example.com"), "example.com");
+        assert_eq!(zone_of("example.com"), "example.com");
+        assert_eq!(zone_of("lan"), "lan");
+    }
+
+    #[test]
+    fn test_per_zone_limit() {
+        let (started, lookups) = mpsc::channel();
+        let release = Arc::new((Mutex::new(false), Condvar::new()));
+        let gate = Arc::clone(&release);
+        let pool = RefreshPool::new(3, 1, 2, move |key: &CacheKey| {
+            started.send(key.to_string()).unwrap();
+            let (released, wakeup) = &*gate;
+            let _guard = wakeup.wait_while(released.lock().unwrap(), |released| !*released).unwrap();
+        });
+
+        assert!(pool.submit(&key("a.example.com-1")));
+        assert!(pool.submit(&key("b.example.com-1")));
+        assert!(pool.submit(&key("a.example.com-1")));
+        assert_eq!(lookups.recv_timeout(Duration::from_secs(1)).unwrap(), "a.example.com-1");
+        // Free workers don't take the second name of a zone that is at its limit
+        assert!(lookups.recv_timeout(Duration::from_millis(100)).is_err());
+        assert!(pool.submit(&key("example.org-1")));
+        assert_eq!(lookups.recv_timeout(Duration::from_secs(1)).unwrap(), "example.org-1");
+        assert!(pool.submit(&key("c.example.net-1")));
+        assert_eq!(lookups.recv_timeout(Duration::from_secs(1)).unwrap(), "c.example.net-1");
+        // Every worker is busy, b.example.com and d.example.net fill the queue
+        assert!(pool.submit(&key("d.example.net-1")));
+        assert!(!pool.submit(&key("e.example.io-1")));
+
+        *release.0.lock().unwrap() = true;
+        release.1.notify_all();
+        let mut rest: Vec<String> = (0..2).map(|_| lookups.recv_timeout(Duration::from_secs(1)).unwrap()).collect();
+        rest.sort();
+        assert_eq!(rest, vec!["b.example.com-1", "d.example.net-1"]);
+    }
+}
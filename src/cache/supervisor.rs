@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info};
+
+// Waited before restarting a worker so one that panics straight away doesn't spin
+pub const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/**
+Runs `work` on its own thread and starts it again whenever it panics, so a bad entry or a bug
+in a background job doesn't silently stop it for the rest of the process. The returned handle
+finishes with the number of restarts once `work` returns normally.
+*/
+pub fn supervise<F>(name: &str, restart_delay: Duration, work: F) -> thread::JoinHandle<usize>
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let name = name.to_string();
+    let work = Arc::new(work);
+    thread::spawn(move || {
+        let mut restarts = 0;
+        loop {
+            let worker = Arc::clone(&work);
+            let result = thread::Builder::new()
+                .name(name.clone())
+                .spawn(move || worker())
+                .and_then(|handle| handle.join().map_err(|_| std::io::Error::other("panicked")));
+            match result {
+                Ok(()) => {
+                    info!("{} worker finished", name);
+                    return restarts;
+                }
+                Err(e) => {
+                    error!("{} worker died ({}), restarting in {:?}", name, e, restart_delay);
+                    restarts += 1;
+                    thread::sleep(restart_delay);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_restarts_panicking_worker() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let supervisor = supervise("test", Duration::from_millis(1), move || {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                panic!("worker failure");
+            }
+        });
+
+        assert_eq!(supervisor.join().unwrap(), 2);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+}
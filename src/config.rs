@@ -0,0 +1,274 @@
+use std::path::Path;
+use std::{env, fs, io};
+
+use flexi_logger::LogSpecification;
+use serde::Deserialize;
+
+use crate::cache::cache::CacheConfig;
+use crate::dnscrypt::server::DnsCryptConfig;
+use crate::filter::captive_portal::{CaptivePortal, CaptivePortalConfig};
+use crate::filter::nxdomain_guard::NxdomainGuardConfig;
+use crate::filter::nxdomain_redirect::{NxdomainRedirect, NxdomainRedirectConfig};
+use crate::filter::rebinding::RebindingConfig;
+use crate::filter::rewrite::{RewriteConfig, Rewrites};
+use crate::filter::safe_search::SafeSearchConfig;
+use crate::local::catalog::CatalogConfig;
+use crate::local::chaos::ChaosConfig;
+use crate::filter::safe_search::SafeSearch;
+use crate::local::denial::Denial;
+use crate::local::dnssd::DnsSdConfig;
+use crate::local::hosts::{HostsConfig, HostsTable};
+use crate::local::pool::{PoolConfig, Pools};
+use crate::local::reverse::{ReverseZones, ReverseZonesConfig};
+use crate::local::zone::{Zone, ZoneConfig};
+use crate::logging::capture::CaptureConfig;
+use crate::logging::query_log::QueryLogConfig;
+use crate::logging::telemetry::TelemetryConfig;
+use crate::odoh::target::OdohConfig;
+use crate::resolver::forward::{Forwarder, ResolverMode, UpstreamConfig};
+use crate::resolver::preset::deserialize_upstream;
+use crate::resolver::self_test::SelfTestConfig;
+use crate::resolver::socks::Socks5Config;
+use crate::server::cookies::CookieConfig;
+use crate::server::handler::RecursionConfig;
+use crate::utils::packet::ParsingConfig;
+use crate::utils::query_type::QueryType;
+use crate::server::http::HttpConfig;
+use crate::server::llmnr::{Llmnr, LlmnrConfig};
+use crate::server::overload::OverloadConfig;
+use crate::server::standby::StandbyConfig;
+use crate::server::tcp::TcpConfig;
+#[cfg(unix)]
+use crate::server::unix::UnixConfig;
+
+pub const CONFIG_PATH: &str = "r_dns.toml";
+const ENV_PREFIX: &str = "RDNS_";
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GeneralConfig {
+    pub listen: String,    // the UDP socket
+    pub log_level: String, // e.g. "debug" or "info,r_dns::cache=debug", RUST_LOG wins when set
+}
+
+impl Default for GeneralConfig {
+    fn default() -> Self {
+        GeneralConfig {
+            listen: "0.0.0.0:2053".to_string(),
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+/**
+Settings read from `r_dns.toml` next to the cache file. Every section is optional, a missing
+file simply gives the defaults.
+*/
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub server: GeneralConfig,
+    pub cache: CacheConfig,
+    pub query_log: QueryLogConfig,
+    pub capture: CaptureConfig,
+    pub hosts: HostsConfig,
+    #[serde(deserialize_with = "deserialize_upstream")]
+    pub upstream: UpstreamConfig,
+    pub http: HttpConfig,
+    pub tcp: TcpConfig,
+    #[cfg(unix)]
+    pub unix: UnixConfig,
+    pub dnscrypt: DnsCryptConfig,
+    pub odoh: OdohConfig,
+    pub rebinding: RebindingConfig,
+    pub chaos: ChaosConfig,
+    pub dnssd: DnsSdConfig,
+    pub llmnr: LlmnrConfig,
+    pub safe_search: SafeSearchConfig,
+    pub captive_portal: CaptivePortalConfig,
+    pub socks5: Socks5Config,
+    pub recursion: RecursionConfig,
+    pub self_test: SelfTestConfig,
+    pub overload: OverloadConfig,
+    pub cookies: CookieConfig,
+    pub parsing: ParsingConfig,
+    pub nxdomain_guard: NxdomainGuardConfig,
+    pub nxdomain_redirect: NxdomainRedirectConfig,
+    pub telemetry: TelemetryConfig,
+    pub standby: StandbyConfig,
+    pub rewrites: Vec<RewriteConfig>,
+    pub pools: Vec<PoolConfig>,
+    pub zones: Vec<ZoneConfig>,
+    pub reverse_zones: ReverseZonesConfig,
+    pub catalogs: Vec<CatalogConfig>,
+}
+
+impl ServerConfig {
+    pub fn parse(toml_string: &str) -> io::Result<ServerConfig> {
+        toml::from_str(toml_string).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // The file with the `RDNS_*` environment variables applied on top
+    pub fn load(path: impl AsRef<Path>) -> io::Result<ServerConfig> {
+        let mut config = match fs::read_to_string(path) {
+            Ok(toml_string) => ServerConfig::parse(&toml_string)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => ServerConfig::default(),
+            Err(e) => return Err(e),
+        };
+        config.apply_env(env::vars())?;
+        Ok(config)
+    }
+
+    /**
+    Overrides settings from `RDNS_*` variables, the way containers are configured: `RDNS_LISTEN`,
+    `RDNS_TCP_LISTEN`, `RDNS_HTTP_LISTEN`, `RDNS_UPSTREAMS` (comma separated, switching from
+    recursion to forwarding), `RDNS_CACHE_SIZE` and `RDNS_LOG_LEVEL`. Any other `RDNS_` variable
+    is an error rather than a typo that silently does nothing.
+    */
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> io::Result<()> {
+        for (name, value) in vars {
+            let Some(setting) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            match setting {
+                "LISTEN" => self.server.listen = value,
+                "TCP_LISTEN" => self.tcp.listen = value,
+                "HTTP_LISTEN" => self.http.listen = value,
+                "UPSTREAMS" => {
+                    self.upstream.servers = value.split(',').map(str::trim).filter(|server| !server.is_empty()).map(String::from).collect();
+                    if self.upstream.mode == ResolverMode::Recursive {
+                        self.upstream.mode = ResolverMode::Forward;
+                    }
+                }
+                "CACHE_SIZE" => {
+                    let size = value.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", name, e)))?;
+                    self.cache.size = Some(size);
+                }
+                "LOG_LEVEL" => self.server.log_level = value,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown setting {}", name))),
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    Everything the server would refuse or skip at startup, for `check-config`: the settings the
+    server validates when it builds its parts, and every error in the zone files and hosts files
+    the config names, each prefixed with its file and line. Nothing is started or queried.
+    */
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Err(e) = LogSpecification::parse(&self.server.log_level) {
+            errors.push(format!("[server] log_level: {}", e));
+        }
+        if self.upstream.mode != ResolverMode::Recursive {
+            if let Err(e) = Forwarder::from_config(&self.upstream) {
+                errors.push(format!("[upstream]: {}", e));
+            }
+        }
+        if let Err(e) = SafeSearch::new(&self.safe_search) {
+            errors.push(format!("[safe_search]: {}", e));
+        }
+        if self.captive_portal.enabled {
+            if let Err(e) = CaptivePortal::new(&self.captive_portal) {
+                errors.push(format!("[captive_portal]: {}", e));
+            }
+        }
+        if self.nxdomain_redirect.enabled {
+            if let Err(e) = NxdomainRedirect::new(&self.nxdomain_redirect) {
+                errors.push(format!("[nxdomain_redirect]: {}", e));
+            }
+        }
+        if let Err(e) = Rewrites::new(&self.rewrites) {
+            errors.push(format!("[[rewrites]]: {}", e));
+        }
+        if let Err(e) = Pools::new(&self.pools) {
+            errors.push(format!("[[pools]]: {}", e));
+        }
+        if let Err(e) = ReverseZones::new(&self.reverse_zones, None, None) {
+            errors.push(format!("[reverse_zones]: {}", e));
+        }
+        if self.llmnr.enabled {
+            if let Err(e) = Llmnr::new(&self.llmnr, None) {
+                errors.push(format!("[llmnr]: {}", e));
+            }
+        }
+        if self.standby.enabled && self.standby.primary.is_empty() {
+            errors.push("[standby]: no primary to copy from".to_string());
+        }
+        if QueryType::from_name(&self.self_test.qtype).is_none() {
+            errors.push(format!("[self_test]: unknown type {}", self.self_test.qtype));
+        }
+        for zone in &self.zones {
+            if let Err(e) = Denial::from_config(zone) {
+                errors.push(format!("[[zones]] {}: {}", zone.name, e));
+            }
+            match fs::read_to_string(&zone.file) {
+                Ok(text) => errors.extend(Zone::validate(&zone.name, &text).into_iter().map(|e| format!("{}: {}", zone.file, e))),
+                Err(e) => errors.push(format!("{}: {}", zone.file, e)),
+            }
+        }
+        if self.hosts.enabled {
+            for file in self.hosts.files() {
+                match fs::read_to_string(&file) {
+                    Ok(text) => errors.extend(HostsTable::validate(&text).into_iter().map(|e| format!("{}: {}", file, e))),
+                    Err(e) => errors.push(format!("{}: {}", file, e)),
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config_is_default() {
+        assert_eq!(ServerConfig::parse("").unwrap(), ServerConfig::default());
+    }
+
+    #[test]
+    fn test_partial_section() {
+        let config = ServerConfig::parse("[query_log]\nenabled = true\ngzip = true\n").unwrap();
+        assert!(config.query_log.enabled);
+        assert!(config.query_log.gzip);
+        assert_eq!(config.query_log.retention, QueryLogConfig::default().retention);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let err = ServerConfig::parse("[query_log]\nmax_size = \"big\"\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut config = ServerConfig::parse("[cache]\nsize = 100\n").unwrap();
+        let vars = [("PATH", "/usr/bin"), ("RDNS_LISTEN", "127.0.0.1:53"), ("RDNS_UPSTREAMS", "192.0.2.1, quad9"), ("RDNS_CACHE_SIZE", "5000")];
+        config.apply_env(vars.iter().map(|(name, value)| (name.to_string(), value.to_string()))).unwrap();
+        assert_eq!(config.server.listen, "127.0.0.1:53");
+        assert_eq!(config.upstream.servers, vec!["192.0.2.1".to_string(), "quad9".to_string()]);
+        assert_eq!(config.upstream.mode, ResolverMode::Forward);
+        assert_eq!(config.cache.size, Some(5000));
+
+        let err = config.apply_env([("RDNS_CACHE_SIZE".to_string(), "big".to_string())]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(config.apply_env([("RDNS_LISTN".to_string(), "[::]:53".to_string())]).unwrap_err().to_string().contains("RDNS_LISTN"));
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(ServerConfig::default().check().is_empty());
+        let config = ServerConfig::parse("[self_test]\nqtype = \"BOGUS\"\n\n[[zones]]\nname = \"example.lan\"\nfile = \"/nonexistent/example.lan.zone\"\n").unwrap();
+        let errors = config.check();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0], "[self_test]: unknown type BOGUS");
+        assert!(errors[1].starts_with("/nonexistent/example.lan.zone: "));
+
+        let config = ServerConfig::parse("[server]\nlog_level = \"info,r_dns=loud\"\n").unwrap();
+        assert_eq!(config.check().len(), 1);
+        assert!(config.check()[0].starts_with("[server] log_level: "));
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crypto_box::{PublicKey, SecretKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+pub const CERT_MAGIC: [u8; 4] = *b"DNSC";
+// X25519-XSalsa20Poly1305, the only construction we implement
+pub const ES_VERSION: u16 = 1;
+pub const PROTOCOL_MINOR_VERSION: u16 = 0;
+pub const CERT_LEN: usize = 124;
+
+/**
+A DNSCrypt v2 resolver certificate, published as a TXT record under the provider name:
+
+cert-magic (4) | es-version (2) | protocol-minor-version (2) | signature (64) |
+resolver-pk (32) | client-magic (8) | serial (4) | ts-start (4) | ts-end (4)
+
+The signature is an Ed25519 signature by the long-term provider key over everything after it.
+Clients pin the provider public key and use the short-term resolver key from the newest
+valid certificate to encrypt their queries.
+*/
+#[derive(Clone)]
+pub struct Certificate {
+    pub resolver_sk: SecretKey,
+    pub client_magic: [u8; 8],
+    pub serial: u32,
+    pub ts_start: u32,
+    pub ts_end: u32,
+    pub signature: [u8; 64],
+}
+
+impl Certificate {
+    pub fn new(provider_key: &SigningKey, resolver_sk: SecretKey, serial: u32, ts_start: u32, ts_end: u32) -> Certificate {
+        let mut client_magic = [0u8; 8];
+        client_magic.copy_from_slice(&resolver_sk.public_key().as_bytes()[..8]);
+
+        let mut cert = Certificate {
+            resolver_sk,
+            client_magic,
+            serial,
+            ts_start,
+            ts_end,
+            signature: [0; 64],
+        };
+        cert.signature = provider_key.sign(&cert.signed_part()).to_bytes();
+        cert
+    }
+
+    pub fn resolver_pk(&self) -> PublicKey {
+        self.resolver_sk.public_key()
+    }
+
+    fn signed_part(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(52);
+        out.extend_from_slice(self.resolver_pk().as_bytes());
+        out.extend_from_slice(&self.client_magic);
+        out.extend_from_slice(&self.serial.to_be_bytes());
+        out.extend_from_slice(&self.ts_start.to_be_bytes());
+        out.extend_from_slice(&self.ts_end.to_be_bytes());
+        out
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(CERT_LEN);
+        out.extend_from_slice(&CERT_MAGIC);
+        out.extend_from_slice(&ES_VERSION.to_be_bytes());
+        out.extend_from_slice(&PROTOCOL_MINOR_VERSION.to_be_bytes());
+        out.extend_from_slice(&self.signature);
+        out.extend_from_slice(&self.signed_part());
+        out
+    }
+
+    pub fn is_valid_at(&self, now: u32) -> bool {
+        self.ts_start <= now && now < self.ts_end
+    }
+}
+
+pub fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32
+}
+
+// What a client takes from a certificate whose signature checks out
+#[derive(Clone, Debug, PartialEq)]
+pub struct Verified {
+    pub resolver_pk: PublicKey,
+    pub client_magic: [u8; 8],
+    pub serial: u32,
+    pub ts_start: u32,
+    pub ts_end: u32,
+}
+
+impl Verified {
+    pub fn is_valid_at(&self, now: u32) -> bool {
+        self.ts_start <= now && now < self.ts_end
+    }
+}
+
+// Checks a serialized certificate the way a client would
+pub fn verify(bytes: &[u8], provider_pk: &VerifyingKey) -> Option<Verified> {
+    if bytes.len() < CERT_LEN || bytes[..4] != CERT_MAGIC || bytes[4..6] != ES_VERSION.to_be_bytes() {
+        return None;
+    }
+    let signature = Signature::from_slice(&bytes[8..72]).ok()?;
+    provider_pk.verify(&bytes[72..], &signature).ok()?;
+
+    let field = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+    Some(Verified {
+        resolver_pk: PublicKey::from_slice(&bytes[72..104]).ok()?,
+        client_magic: bytes[104..112].try_into().unwrap(),
+        serial: field(112),
+        ts_start: field(116),
+        ts_end: field(120),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_round_trip() {
+        let provider_key = SigningKey::from_bytes(&[7; 32]);
+        let cert = Certificate::new(&provider_key, SecretKey::from_bytes([9; 32]), 1, 1000, 2000);
+        let bytes = cert.to_bytes();
+
+        assert_eq!(bytes.len(), CERT_LEN);
+        assert_eq!(&bytes[..4], b"DNSC");
+        let verified = verify(&bytes, &provider_key.verifying_key()).unwrap();
+        assert_eq!(verified.resolver_pk, cert.resolver_pk());
+        assert_eq!(verified.client_magic, cert.client_magic);
+        assert_eq!((verified.serial, verified.ts_start, verified.ts_end), (1, 1000, 2000));
+
+        assert!(cert.is_valid_at(1500));
+        assert!(!cert.is_valid_at(2000));
+    }
+
+    #[test]
+    fn test_tampered_certificate() {
+        let provider_key = SigningKey::from_bytes(&[7; 32]);
+        let mut bytes = Certificate::new(&provider_key, SecretKey::from_bytes([9; 32]), 1, 1000, 2000).to_bytes();
+        bytes[CERT_LEN - 1] ^= 1;
+
+        assert!(verify(&bytes, &provider_key.verifying_key()).is_none());
+        assert!(verify(&bytes[..100], &provider_key.verifying_key()).is_none());
+    }
+}
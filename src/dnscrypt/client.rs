@@ -0,0 +1,188 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+
+use crypto_box::aead::rand_core::RngCore;
+use crypto_box::aead::{Aead, OsRng};
+use crypto_box::{SalsaBox, SecretKey};
+use ed25519_dalek::VerifyingKey;
+use log::{debug, info};
+
+use crate::dnscrypt::cert::{self, Verified};
+use crate::dnscrypt::server::{pad, unpad, HALF_NONCE_LEN, PADDING_BLOCK, RESOLVER_MAGIC};
+use crate::resolver::recursive::{build_query, lookup_via, parse_response};
+use crate::resolver::transport::Connector;
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+
+// UDP queries are padded to a multiple of this, an answer may not be longer than its query
+const MIN_UDP_QUERY_LEN: usize = 256;
+
+/**
+A DNSCrypt v2 resolver to forward to, the way its stamp describes it. The certificate comes
+from a plain TXT query for the provider name and is only taken when it is signed by the
+provider key from the stamp; the newest valid one is used until it expires. Queries are
+encrypted with a key pair of our own, answers that come back truncated over UDP are asked
+for again over TCP.
+*/
+#[derive(Debug)]
+pub struct DnsCryptUpstream {
+    pub server: (Ipv4Addr, u16),
+    provider_name: String,
+    provider_pk: VerifyingKey,
+    client_sk: SecretKey,
+    cert: Mutex<Option<Verified>>,
+}
+
+impl PartialEq for DnsCryptUpstream {
+    fn eq(&self, other: &Self) -> bool {
+        self.server == other.server && self.provider_name == other.provider_name && self.provider_pk == other.provider_pk
+    }
+}
+
+impl DnsCryptUpstream {
+    pub fn new(server: (Ipv4Addr, u16), provider_pk: &[u8], provider_name: &str) -> Result<DnsCryptUpstream, String> {
+        let provider_pk = provider_pk.try_into().ok().and_then(|pk| VerifyingKey::from_bytes(pk).ok())
+            .ok_or_else(|| format!("Invalid DNSCrypt provider key for {}", provider_name))?;
+        Ok(DnsCryptUpstream {
+            server,
+            provider_name: provider_name.trim_end_matches('.').to_string(),
+            provider_pk,
+            client_sk: SecretKey::generate(&mut OsRng),
+            cert: Mutex::new(None),
+        })
+    }
+
+    fn addr(&self) -> SocketAddr {
+        self.server.into()
+    }
+
+    // The newest valid certificate the resolver publishes, fetched again once ours expired
+    fn certificate(&self, connector: &dyn Connector) -> io::Result<Verified> {
+        let now = cert::now();
+        if let Some(cert) = self.cert.lock().unwrap().as_ref().filter(|cert| cert.is_valid_at(now)) {
+            return Ok(cert.clone());
+        }
+        let response = lookup_via(&self.provider_name, QueryType::TXT, self.addr(), false, None, connector)?;
+        let cert = response.answers.iter()
+            .filter_map(|record| match record {
+                DnsRecord::TXT { data, .. } => cert::verify(&data.concat(), &self.provider_pk),
+                _ => None,
+            })
+            .filter(|cert| cert.is_valid_at(now))
+            .max_by_key(|cert| cert.serial)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No valid DNSCrypt certificate from {}", self.provider_name)))?;
+        info!("Using DNSCrypt certificate {} of {}, valid until {}", cert.serial, self.provider_name, cert.ts_end);
+        *self.cert.lock().unwrap() = Some(cert.clone());
+        Ok(cert)
+    }
+
+    fn exchange(&self, query: &[u8], tcp: bool, connector: &dyn Connector) -> io::Result<DnsPacket> {
+        let cert = self.certificate(connector)?;
+        let salsa_box = SalsaBox::new(&cert.resolver_pk, &self.client_sk);
+        let mut nonce = [0u8; 24];
+        OsRng.fill_bytes(&mut nonce[..HALF_NONCE_LEN]);
+        let mut plaintext = query.to_vec();
+        pad(&mut plaintext, if tcp { PADDING_BLOCK } else { MIN_UDP_QUERY_LEN });
+
+        let mut message = cert.client_magic.to_vec();
+        message.extend_from_slice(self.client_sk.public_key().as_bytes());
+        message.extend_from_slice(&nonce[..HALF_NONCE_LEN]);
+        message.extend_from_slice(&salsa_box.encrypt(&nonce.into(), plaintext.as_slice()).map_err(|_| io::Error::other("DNSCrypt encryption failed"))?);
+
+        let mut transport = if tcp { connector.tcp(self.addr())? } else { connector.udp(self.addr())? };
+        transport.send_query(&message)?;
+        let reply = transport.recv_response()?;
+
+        // The resolver's half of the nonce follows ours, anything else isn't an answer to this query
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid DNSCrypt response from {}", self.provider_name));
+        if reply.len() < 8 + 24 || reply[..8] != RESOLVER_MAGIC || reply[8..8 + HALF_NONCE_LEN] != nonce[..HALF_NONCE_LEN] {
+            return Err(invalid());
+        }
+        nonce.copy_from_slice(&reply[8..32]);
+        let plaintext = salsa_box.decrypt(&nonce.into(), &reply[32..]).map_err(|_| invalid())?;
+        parse_response(unpad(&plaintext).ok_or_else(invalid)?, self.addr())
+    }
+
+    pub fn lookup(&self, qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>, connector: &dyn Connector) -> io::Result<DnsPacket> {
+        let query = build_query(qname, qtype, checking_disabled, edns)?;
+        let response = self.exchange(&query, false, connector)?;
+        if !response.header.truncated_message {
+            return Ok(response);
+        }
+        debug!("Truncated DNSCrypt response from {} for {} {:?}, retrying over TCP", self.provider_name, qname, qtype);
+        self.exchange(&query, true, connector)
+    }
+}
+
+// A DNSCrypt resolver on localhost with the provider key [7; 32] answering with `answer`, and an upstream asking it
+#[cfg(test)]
+pub fn fake_upstream(answer: impl Fn(DnsPacket) -> DnsPacket + Send + Sync + 'static) -> DnsCryptUpstream {
+    use std::net::{TcpListener, UdpSocket};
+
+    use ed25519_dalek::SigningKey;
+
+    use crate::dnscrypt::server::{serve, DnsCryptConfig, DnsCryptServer};
+
+    let provider_key = SigningKey::from_bytes(&[7; 32]);
+    let provider_pk = provider_key.verifying_key().to_bytes();
+    let server = DnsCryptServer::new("2.dnscrypt-cert.example.com", provider_key, 24);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let listener = TcpListener::bind(socket.local_addr().unwrap()).unwrap();
+    serve(server, socket, listener, &DnsCryptConfig::default(), move |request, _, _| {
+        let mut response = answer(request.clone());
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions;
+        Ok(response)
+    });
+    DnsCryptUpstream::new((Ipv4Addr::LOCALHOST, port), &provider_pk, "2.dnscrypt-cert.example.com").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ed25519_dalek::SigningKey;
+
+    use crate::resolver::transport::Network;
+    use crate::utils::result_code::ResultCode;
+
+    // Every name gets `count` addresses
+    fn addresses(count: u8) -> impl Fn(DnsPacket) -> DnsPacket {
+        move |request| {
+            let name = &request.questions[0].name;
+            let mut response = DnsPacket::new();
+            response.answers = (0..count).map(|i| DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, i), 300)).collect();
+            response
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let upstream = fake_upstream(addresses(1));
+        let response = upstream.lookup("www.example.com", QueryType::A, false, None, &Network).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.answers, vec![DnsRecord::a("www.example.com", Ipv4Addr::new(192, 0, 2, 0), 300)]);
+        assert!(upstream.cert.lock().unwrap().is_some());
+
+        // Too large for the padded UDP query and the packet buffer, so the answer comes whole over TCP
+        let upstream = fake_upstream(addresses(60));
+        let response = upstream.lookup("www.example.com", QueryType::A, false, None, &Network).unwrap();
+        assert!(!response.header.truncated_message);
+        assert_eq!(response.answers.len(), 60);
+    }
+
+    #[test]
+    fn test_wrong_provider_key() {
+        let server = fake_upstream(addresses(1)).server;
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key().to_bytes();
+        let upstream = DnsCryptUpstream::new(server, &other, "2.dnscrypt-cert.example.com").unwrap();
+        let e = upstream.lookup("www.example.com", QueryType::A, false, None, &Network).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        assert!(DnsCryptUpstream::new(server, &[1, 2, 3], "2.dnscrypt-cert.example.com").is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod cert;
+pub mod client;
+pub mod server;
@@ -0,0 +1,437 @@
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::{Arc, RwLock};
+use std::{io, thread};
+
+use crypto_box::aead::rand_core::RngCore;
+use crypto_box::aead::{Aead, OsRng};
+use crypto_box::{PublicKey, SalsaBox, SecretKey};
+use ed25519_dalek::SigningKey;
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use crate::dnscrypt::cert::{self, Certificate};
+use crate::server::handler::QueryHandler;
+use crate::server::overload::{self, OverloadConfig};
+use crate::server::tcp::{self, TcpConfig};
+use crate::utils::byte_buffer::ByteBuffer;
+use crate::utils::dns_class::DnsClass;
+use crate::utils::key_file::{load_or_create_key, to_hex};
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+use crate::utils::wire::write_large_message;
+
+pub const RESOLVER_MAGIC: [u8; 8] = [0x72, 0x36, 0x66, 0x6e, 0x76, 0x57, 0x6a, 0x38];
+pub const HALF_NONCE_LEN: usize = 12;
+// client-magic, client-pk and client-nonce in front of every encrypted query
+const QUERY_HEADER_LEN: usize = 8 + 32 + HALF_NONCE_LEN;
+const TAG_LEN: usize = 16;
+pub const PADDING_BLOCK: usize = 64;
+// The largest UDP query taken in, clients pad theirs to at least 256 bytes
+const MAX_QUERY_LEN: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DnsCryptConfig {
+    pub enabled: bool,
+    pub listen: String,
+    pub provider_name: String,     // must start with "2.dnscrypt-cert."
+    pub provider_key_file: String, // hex Ed25519 secret key, generated on first start
+    pub cert_lifetime: u32,        // hours a resolver key stays valid
+    pub workers: usize,            // threads answering UDP queries
+    pub queue_size: usize,         // UDP queries waiting for a worker, any more are answered from the cache
+}
+
+impl Default for DnsCryptConfig {
+    fn default() -> Self {
+        DnsCryptConfig {
+            enabled: false,
+            listen: "0.0.0.0:8443".to_string(),
+            provider_name: "2.dnscrypt-cert.r-dns.local".to_string(),
+            provider_key_file: "dnscrypt_provider.key".to_string(),
+            cert_lifetime: 24,
+            workers: 4,
+            queue_size: 512,
+        }
+    }
+}
+
+// ISO/IEC 7816-4 padding: a 0x80 byte followed by zeros up to a multiple of the block size
+pub fn pad(data: &mut Vec<u8>, block: usize) {
+    data.push(0x80);
+    while !data.len().is_multiple_of(block) {
+        data.push(0);
+    }
+}
+
+pub fn unpad(data: &[u8]) -> Option<&[u8]> {
+    let end = data.iter().rposition(|&byte| byte != 0)?;
+    if data[end] != 0x80 {
+        return None;
+    }
+    Some(&data[..end])
+}
+
+/**
+DNSCrypt v2 endpoint. Plain TXT queries for the provider name get the current certificates,
+everything starting with the client magic of one of our certificates is decrypted, answered
+and sent back encrypted. A new resolver key is rolled once the newest certificate has used up
+three quarters of its lifetime; the previous one keeps working until it expires. Shared by the
+UDP workers and the TCP connections, only rolling a key takes the write lock.
+*/
+pub struct DnsCryptServer {
+    provider_name: String,
+    provider_key: SigningKey,
+    lifetime: u32,
+    certs: RwLock<Vec<Certificate>>,
+}
+
+impl DnsCryptServer {
+    pub fn new(provider_name: &str, provider_key: SigningKey, lifetime_hours: u32) -> DnsCryptServer {
+        let server = DnsCryptServer {
+            provider_name: provider_name.trim_end_matches('.').to_ascii_lowercase(),
+            provider_key,
+            lifetime: lifetime_hours.max(1) * 3600,
+            certs: RwLock::new(Vec::new()),
+        };
+        server.rotate(cert::now());
+        server
+    }
+
+    pub fn provider_public_key(&self) -> [u8; 32] {
+        self.provider_key.verifying_key().to_bytes()
+    }
+
+    pub fn certificates(&self) -> Vec<Certificate> {
+        self.certs.read().unwrap().clone()
+    }
+
+    fn needs_new(&self, certs: &[Certificate], now: u32) -> bool {
+        match certs.last() {
+            Some(newest) => newest.ts_end - now < self.lifetime / 4,
+            None => true,
+        }
+    }
+
+    pub fn rotate(&self, now: u32) {
+        {
+            let certs = self.certs.read().unwrap();
+            if certs.iter().all(|cert| cert.ts_end > now) && !self.needs_new(&certs, now) {
+                return;
+            }
+        }
+        let mut certs = self.certs.write().unwrap();
+        certs.retain(|cert| cert.ts_end > now);
+        if self.needs_new(&certs, now) {
+            let serial = certs.last().map(|cert| cert.serial + 1).unwrap_or(1);
+            let resolver_sk = SecretKey::generate(&mut OsRng);
+            certs.push(Certificate::new(&self.provider_key, resolver_sk, serial, now, now + self.lifetime));
+            info!("Issued DNSCrypt certificate with serial {}", serial);
+        }
+    }
+
+    fn cert_response(&self, request: &DnsPacket) -> DnsPacket {
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.header.authoritative_answer = true;
+
+        match request.questions.first() {
+            Some(q) if q.qtype == QueryType::TXT && q.name.eq_ignore_ascii_case(&self.provider_name) => {
+                response.questions.push(q.clone());
+                for cert in self.certs.read().unwrap().iter() {
+                    response.answers.push(DnsRecord::TXT {
+                        domain: q.name.clone(),
+                        data: vec![cert.to_bytes()],
+                        ttl: 3600,
+                        class: DnsClass::IN,
+                    });
+                }
+            }
+            Some(q) => {
+                response.questions.push(q.clone());
+                response.header.rescode = ResultCode::REFUSED;
+            }
+            None => response.header.rescode = ResultCode::FORMERR,
+        }
+        response
+    }
+
+    /**
+    The message to send back for `data`, if any. Over UDP the response may not be longer than the
+    query, larger ones go back truncated so the client asks again over TCP, where the whole
+    answer is sent.
+    */
+    pub fn handle_packet(&self, data: &[u8], tcp: bool, answer: impl Fn(DnsPacket) -> io::Result<DnsPacket>) -> Option<Vec<u8>> {
+        self.rotate(cert::now());
+
+        let cert = self.certs.read().unwrap().iter().find(|cert| data.len() > QUERY_HEADER_LEN && data[..8] == cert.client_magic).cloned();
+        let cert = match cert {
+            Some(cert) => cert,
+            None => {
+                // Unencrypted, only certificate lookups are answered
+                let request = DnsPacket::from_bytes(&data[..data.len().min(512)]).ok()?;
+                let mut buffer = ByteBuffer::new();
+                self.cert_response(&request).write(&mut buffer).ok()?;
+                return Some(buffer.buffer[..buffer.position()].to_vec());
+            }
+        };
+
+        let client_pk = PublicKey::from_slice(&data[8..40]).ok()?;
+        let mut nonce = [0u8; 24];
+        nonce[..HALF_NONCE_LEN].copy_from_slice(&data[40..QUERY_HEADER_LEN]);
+
+        let salsa_box = SalsaBox::new(&client_pk, &cert.resolver_sk);
+        let plaintext = salsa_box.decrypt(&nonce.into(), &data[QUERY_HEADER_LEN..]).ok()?;
+        let query = unpad(&plaintext)?;
+        if query.len() > 512 {
+            return None;
+        }
+        let request = DnsPacket::from_bytes(query).ok()?;
+
+        let response = match answer(request.clone()) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to answer DNSCrypt query: {:?}", e);
+                return None;
+            }
+        };
+
+        OsRng.fill_bytes(&mut nonce[HALF_NONCE_LEN..]);
+        // A UDP response may never be larger than the query, otherwise it could be used for amplification
+        let mut encrypted = encrypt_response(&salsa_box, &nonce, &response)?;
+        if !tcp && encrypted.len() > data.len() {
+            let mut truncated = DnsPacket::new();
+            truncated.header = response.header.clone();
+            truncated.header.truncated_message = true;
+            truncated.questions = response.questions.clone();
+            encrypted = encrypt_response(&salsa_box, &nonce, &truncated)?;
+        }
+        Some(encrypted)
+    }
+}
+
+fn encrypt_response(salsa_box: &SalsaBox, nonce: &[u8; 24], response: &DnsPacket) -> Option<Vec<u8>> {
+    let mut plaintext = write_large_message(response).ok()?;
+    pad(&mut plaintext, PADDING_BLOCK);
+
+    let ciphertext = salsa_box.encrypt(nonce.into(), plaintext.as_slice()).ok()?;
+    let mut out = Vec::with_capacity(8 + 24 + ciphertext.len());
+    out.extend_from_slice(&RESOLVER_MAGIC);
+    out.extend_from_slice(nonce);
+    out.extend_from_slice(&ciphertext);
+    debug_assert_eq!(out.len(), 8 + 24 + TAG_LEN + plaintext.len());
+    Some(out)
+}
+
+/*
+Serves DNSCrypt over UDP and TCP, which the protocol requires for answers that don't fit a UDP
+response. UDP queries are queued for `config.workers` threads like plain ones under
+`[overload]`; `answer` is told whether the queue was full, when it should answer without a lookup.
+*/
+pub fn serve<A>(server: DnsCryptServer, socket: UdpSocket, listener: TcpListener, config: &DnsCryptConfig, answer: A)
+where
+    A: Fn(DnsPacket, IpAddr, bool) -> io::Result<DnsPacket> + Send + Sync + 'static,
+{
+    let (server, answer) = (Arc::new(server), Arc::new(answer));
+    thread::spawn({
+        let (server, answer) = (Arc::clone(&server), Arc::clone(&answer));
+        move || tcp::listen(listener, TcpConfig::default(), move |data, client, _| {
+            server.handle_packet(data, true, |request| answer(request, client, false))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Not a DNSCrypt query for us"))
+        })
+    });
+
+    let queue = OverloadConfig { workers: config.workers, queue_size: config.queue_size, ..OverloadConfig::default() };
+    thread::spawn(move || {
+        let reply = move |socket: &UdpSocket, data: &[u8], src: SocketAddr, shed: bool| {
+            if let Some(reply) = server.handle_packet(data, false, |request| answer(request, src.ip(), shed)) {
+                if let Err(e) = socket.send_to(&reply, src) {
+                    warn!("DNSCrypt send to {} failed: {:?}", src, e);
+                }
+            }
+        };
+        let reply = Arc::new(reply);
+        let shed = Arc::clone(&reply);
+        if let Err(e) = overload::serve_udp(socket, &queue, MAX_QUERY_LEN, move |socket, data, src| reply(socket, data, src, false), move |socket, data, src| shed(socket, data, src, true)) {
+            error!("DNSCrypt UDP server stopped: {:?}", e);
+        }
+    });
+}
+
+pub fn start(config: &DnsCryptConfig, handler: QueryHandler) -> io::Result<()> {
+    let provider_key = SigningKey::from_bytes(&load_or_create_key(&config.provider_key_file)?);
+    let server = DnsCryptServer::new(&config.provider_name, provider_key, config.cert_lifetime);
+    let socket = UdpSocket::bind(&config.listen)?;
+    let listener = TcpListener::bind(socket.local_addr()?)?;
+    info!(
+        "DNSCrypt listening on {} (UDP and TCP) as {} with provider public key {}",
+        config.listen, config.provider_name, to_hex(&server.provider_public_key())
+    );
+    serve(server, socket, listener, config, move |request, client, shed| match shed {
+        true => Ok(handler.shed(request)),
+        false => handler.answer_from(request, Some(client)),
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::recursive::{read_message, write_message};
+    use crate::utils::question::DnsQuestion;
+    use crate::utils::wire::read_large_message;
+    use std::net::{Ipv4Addr, TcpStream};
+    use std::time::Duration;
+
+    fn create_server() -> DnsCryptServer {
+        DnsCryptServer::new("2.dnscrypt-cert.example.com", SigningKey::from_bytes(&[7; 32]), 24)
+    }
+
+    fn create_query(name: &str, qtype: QueryType) -> Vec<u8> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 42;
+        packet.questions.push(DnsQuestion::new(name.to_string(), qtype));
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.position()].to_vec()
+    }
+
+    fn answer(request: DnsPacket) -> io::Result<DnsPacket> {
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.answers.push(DnsRecord::A {
+            domain: request.questions[0].name.clone(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+            class: DnsClass::IN,
+        });
+        response.questions = request.questions;
+        Ok(response)
+    }
+
+    // The query the way dnscrypt-proxy builds it, padded to 256 bytes, and the box to open the reply with
+    fn encrypt_query(server: &DnsCryptServer, name: &str) -> (Vec<u8>, SalsaBox) {
+        let cert = server.certificates()[0].clone();
+        let client_sk = SecretKey::from_bytes([3; 32]);
+        let client_box = SalsaBox::new(&cert.resolver_pk(), &client_sk);
+        let mut nonce = [0u8; 24];
+        nonce[..12].copy_from_slice(&[5; 12]);
+        let mut query = create_query(name, QueryType::A);
+        pad(&mut query, 256);
+
+        let mut packet = cert.client_magic.to_vec();
+        packet.extend_from_slice(client_sk.public_key().as_bytes());
+        packet.extend_from_slice(&nonce[..12]);
+        packet.extend_from_slice(&client_box.encrypt(&nonce.into(), query.as_slice()).unwrap());
+        (packet, client_box)
+    }
+
+    fn decrypt_reply(client_box: &SalsaBox, reply: &[u8]) -> DnsPacket {
+        let reply_nonce: [u8; 24] = reply[8..32].try_into().unwrap();
+        let plaintext = client_box.decrypt(&reply_nonce.into(), &reply[32..]).unwrap();
+        read_large_message(unpad(&plaintext).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_pad_unpad() {
+        let mut data = vec![1, 2, 3];
+        pad(&mut data, 64);
+        assert_eq!(data.len(), 64);
+        assert_eq!(unpad(&data), Some(&[1, 2, 3][..]));
+        assert_eq!(unpad(&[1, 2, 0]), None);
+    }
+
+    #[test]
+    fn test_certificate_query() {
+        let server = create_server();
+        let reply = server.handle_packet(&create_query("2.dnscrypt-cert.example.com", QueryType::TXT), false, answer).unwrap();
+        let response = DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(&reply)).unwrap();
+
+        let cert = match &response.answers[0] {
+            DnsRecord::TXT { data, .. } => data[0].clone(),
+            other => panic!("Unexpected record {:?}", other),
+        };
+        let provider_pk = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        assert!(cert::verify(&cert, &provider_pk).is_some());
+
+        let reply = server.handle_packet(&create_query("example.com", QueryType::A), false, answer).unwrap();
+        let response = DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(&reply)).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::REFUSED);
+    }
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let server = create_server();
+        let (packet, client_box) = encrypt_query(&server, "example.com");
+        let reply = server.handle_packet(&packet, false, answer).unwrap();
+        assert_eq!(reply[..8], RESOLVER_MAGIC);
+        assert_eq!(reply[8..20], [5; 12]);
+        assert!(reply.len() <= packet.len());
+
+        let response = decrypt_reply(&client_box, &reply);
+        assert_eq!(response.header.id, 42);
+        assert_eq!(response.get_random_a(), Some(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let server = create_server();
+        let first = server.certificates()[0].clone();
+
+        server.rotate(first.ts_start + 3600);
+        assert_eq!(server.certificates().len(), 1);
+
+        server.rotate(first.ts_end - 3600);
+        assert_eq!(server.certificates().len(), 2);
+        assert_eq!(server.certificates()[1].serial, 2);
+
+        server.rotate(first.ts_end);
+        assert_eq!(server.certificates().len(), 1);
+        assert_eq!(server.certificates()[0].serial, 2);
+    }
+
+    #[test]
+    fn test_udp_workers_and_tcp() {
+        let server = create_server();
+        let queries: Vec<_> = ["slow.example", "fast.example", "large.example"].iter().map(|name| encrypt_query(&server, name)).collect();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let listener = TcpListener::bind(addr).unwrap();
+        serve(server, socket, listener, &DnsCryptConfig::default(), |request, _, _| {
+            let mut response = answer(request)?;
+            let name = response.questions[0].name.clone();
+            match name.as_str() {
+                "slow.example" => thread::sleep(Duration::from_millis(500)),
+                "large.example" => response.answers = (0..40).map(|i| DnsRecord::a(&name, Ipv4Addr::new(192, 0, 2, i), 300)).collect(),
+                _ => {}
+            }
+            Ok(response)
+        });
+
+        // The slow query holds up one worker, not the fast one behind it
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.send_to(&queries[0].0, addr).unwrap();
+        client.send_to(&queries[1].0, addr).unwrap();
+        let mut data = [0u8; 4096];
+        let len = client.recv(&mut data).unwrap();
+        assert_eq!(decrypt_reply(&queries[1].1, &data[..len]).questions[0].name, "fast.example");
+        let len = client.recv(&mut data).unwrap();
+        assert_eq!(decrypt_reply(&queries[0].1, &data[..len]).questions[0].name, "slow.example");
+
+        // An answer longer than the query is truncated over UDP and whole over TCP
+        client.send_to(&queries[2].0, addr).unwrap();
+        let len = client.recv(&mut data).unwrap();
+        let response = decrypt_reply(&queries[2].1, &data[..len]);
+        assert!(response.header.truncated_message && response.answers.is_empty());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write_message(&mut stream, &queries[2].0).unwrap();
+        let response = decrypt_reply(&queries[2].1, &read_message(&mut stream).unwrap());
+        assert!(!response.header.truncated_message);
+        assert_eq!(response.answers.len(), 40);
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use serde::Deserialize;
+
+use crate::filter::nxdomain_redirect::parse_addr;
+use crate::utils::name::is_subdomain;
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+use crate::utils::subnet::Subnet;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CaptivePortalConfig {
+    pub enabled: bool,
+    pub a: String,            // the portal's addresses, at least one of the two
+    pub aaaa: String,
+    pub ttl: u32,             // kept short, so clients forget the portal soon after they are let through
+    pub allow: Vec<String>,   // domains (and their subdomains) resolved normally, e.g. the portal's own name
+    pub clients: Vec<String>, // addresses or subnets held at the portal, empty for every client
+}
+
+impl Default for CaptivePortalConfig {
+    fn default() -> Self {
+        CaptivePortalConfig {
+            enabled: false,
+            a: String::new(),
+            aaaa: String::new(),
+            ttl: 1,
+            allow: Vec::new(),
+            clients: Vec::new(),
+        }
+    }
+}
+
+/**
+Holds the clients of a guest network at a captive portal: every name outside the allowlist
+resolves to the portal, whatever it is, so the first page a client opens, and the probe its OS
+sends to detect portals, lands there. Other types get an empty answer for those names, an HTTPS
+or SVCB record must not lead around the portal.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptivePortal {
+    a: Option<Ipv4Addr>,
+    aaaa: Option<Ipv6Addr>,
+    ttl: u32,
+    allow: Vec<String>,
+    clients: Vec<Subnet>,
+}
+
+impl CaptivePortal {
+    pub fn new(config: &CaptivePortalConfig) -> io::Result<CaptivePortal> {
+        let (a, aaaa) = (parse_addr(&config.a)?, parse_addr(&config.aaaa)?);
+        if a.is_none() && aaaa.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Neither an a nor an aaaa address for the portal"));
+        }
+        let clients = config.clients.iter()
+            .map(|client| client.parse().map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, e)))
+            .collect::<io::Result<Vec<Subnet>>>()?;
+        Ok(CaptivePortal {
+            a,
+            aaaa,
+            ttl: config.ttl,
+            allow: config.allow.iter().map(|domain| domain.trim_end_matches('.').to_ascii_lowercase()).collect(),
+            clients,
+        })
+    }
+
+    // Transports that don't know the client only count when the portal holds everyone
+    pub fn holds(&self, client: Option<IpAddr>) -> bool {
+        match client {
+            Some(client) => self.clients.is_empty() || self.clients.iter().any(|subnet| subnet.contains(client)),
+            None => self.clients.is_empty(),
+        }
+    }
+
+    // The portal's answer to `question`, None for names on the allowlist
+    pub fn answer(&self, question: &DnsQuestion) -> Option<Vec<DnsRecord>> {
+        if self.allow.iter().any(|domain| is_subdomain(&question.name, domain)) {
+            return None;
+        }
+        let answer = match question.qtype {
+            QueryType::A => self.a.map(|addr| DnsRecord::a(&question.name, addr, self.ttl)),
+            QueryType::AAAA => self.aaaa.map(|addr| DnsRecord::aaaa(&question.name, addr, self.ttl)),
+            _ => None,
+        };
+        Some(answer.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_portal() {
+        let portal = CaptivePortal::new(&CaptivePortalConfig {
+            enabled: true,
+            a: "10.0.0.1".to_string(),
+            allow: vec!["Portal.guest.lan.".to_string()],
+            clients: vec!["10.0.0.0/24".to_string()],
+            ..CaptivePortalConfig::default()
+        }).unwrap();
+
+        assert!(portal.holds(Some("10.0.0.77".parse().unwrap())));
+        assert!(!portal.holds(Some("192.168.1.2".parse().unwrap())));
+        assert!(!portal.holds(None));
+
+        let question = DnsQuestion::new("captive.apple.com".to_string(), QueryType::A);
+        assert_eq!(portal.answer(&question), Some(vec![DnsRecord::a("captive.apple.com", Ipv4Addr::new(10, 0, 0, 1), 1)]));
+        // No IPv6 portal and no way around it
+        assert_eq!(portal.answer(&DnsQuestion::new("example.com".to_string(), QueryType::AAAA)), Some(Vec::new()));
+        assert_eq!(portal.answer(&DnsQuestion::new("example.com".to_string(), QueryType::HTTPS)), Some(Vec::new()));
+        assert_eq!(portal.answer(&DnsQuestion::new("www.portal.guest.lan".to_string(), QueryType::A)), None);
+
+        assert!(CaptivePortal::new(&CaptivePortalConfig { aaaa: "10.0.0.1".to_string(), ..CaptivePortalConfig::default() }).is_err());
+    }
+}
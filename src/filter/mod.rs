@@ -0,0 +1,7 @@
+pub mod captive_portal;
+pub mod nxdomain_guard;
+pub mod nxdomain_redirect;
+pub mod rebinding;
+pub mod rewrite;
+pub mod scrub;
+pub mod safe_search;
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::cache::key::canonical_name;
+use crate::utils::name::is_subdomain;
+use crate::utils::packet::DnsPacket;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+
+// Zones that aren't under attack are forgotten once there are this many
+const MAX_ZONES: usize = 10000;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct NxdomainGuardConfig {
+    pub enabled: bool,
+    pub threshold: u32,      // NXDOMAINs for names under one zone within `window` that mark it as attacked
+    pub window: u64,         // seconds
+    pub recursion_rate: u32, // lookups per second still sent on for an attacked zone, the rest get SERVFAIL
+    pub hold: u64,           // seconds a zone stays marked after the flood stops
+}
+
+impl Default for NxdomainGuardConfig {
+    fn default() -> Self {
+        NxdomainGuardConfig {
+            enabled: false,
+            threshold: 100,
+            window: 10,
+            recursion_rate: 10,
+            hold: 300,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ZoneState {
+    window_start: u64,
+    nxdomains: u32,
+    attacked_until: u64,
+    second: u64,
+    lookups: u32,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// The zone a negative answer came from: the owner of the SOA its authority section carries, when
+// the name is below it. The root and TLDs are left alone, a flood there would hold back every name.
+pub fn zone_of(name: &str, response: &DnsPacket) -> Option<String> {
+    let zone = response.authorities.iter().find_map(|record| match record {
+        DnsRecord::SOA { domain, .. } => Some(canonical_name(domain)),
+        _ => None,
+    })?;
+    (zone.contains('.') && is_subdomain(name, &zone) && !name.trim_end_matches('.').eq_ignore_ascii_case(&zone)).then_some(zone)
+}
+
+// The names above `name`, closest first: "a.b.example.com" gives "b.example.com", "example.com" and "com"
+pub fn ancestors(name: &str) -> impl Iterator<Item = &str> {
+    let name = name.trim_end_matches('.');
+    name.match_indices('.').map(move |(at, _)| &name[at + 1..])
+}
+
+/**
+Detects random subdomain ("water torture") attacks: floods of queries for made-up labels under
+one zone, which miss the cache every time and all end up at the zone's authoritative servers.
+Zones are the ones negative answers name in their SOA, so "x7f3k.co.uk" counts against co.uk
+only when co.uk itself said there is no such name. Once a zone collects too many NXDOMAINs, only a few
+lookups per second for names under it are still sent on; the handler answers the rest from the
+cache or with SERVFAIL, never with an NXDOMAIN it made up. A name is held back only by the
+closest zone above it that the guard knows, so a zone below a flooded one keeps its lookups.
+*/
+#[derive(Clone, Debug)]
+pub struct NxdomainGuard {
+    config: NxdomainGuardConfig,
+    zones: Arc<Mutex<HashMap<String, ZoneState>>>,
+}
+
+impl NxdomainGuard {
+    pub fn new(config: &NxdomainGuardConfig) -> NxdomainGuard {
+        NxdomainGuard { config: config.clone(), zones: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    pub fn allow_lookup(&self, name: &str) -> bool {
+        self.allow_lookup_at(name, now())
+    }
+
+    pub fn allow_lookup_at(&self, name: &str, now: u64) -> bool {
+        let name = canonical_name(name);
+        let mut zones = self.zones.lock().unwrap();
+        let Some(zone) = ancestors(&name).find(|zone| zones.contains_key(*zone)) else {
+            return true;
+        };
+        let Some(state) = zones.get_mut(zone).filter(|state| state.attacked_until > now) else {
+            return true;
+        };
+        if state.second != now {
+            state.second = now;
+            state.lookups = 0;
+        }
+        state.lookups += 1;
+        state.lookups <= self.config.recursion_rate
+    }
+
+    // Called with the response to every lookup that was sent on
+    pub fn record(&self, name: &str, response: &DnsPacket) {
+        self.record_at(name, response, now())
+    }
+
+    pub fn record_at(&self, name: &str, response: &DnsPacket, now: u64) {
+        let Some(zone) = zone_of(name, response) else {
+            return;
+        };
+        let mut zones = self.zones.lock().unwrap();
+        if zones.len() >= MAX_ZONES && !zones.contains_key(&zone) {
+            let window = self.config.window;
+            zones.retain(|_, state| state.attacked_until > now || state.window_start + window > now);
+        }
+
+        let state = zones.entry(zone.clone()).or_default();
+        // Any answer carrying the zone's SOA shows where the zone starts, only NXDOMAINs count against it
+        if response.header.rescode != ResultCode::NXDOMAIN {
+            return;
+        }
+        if state.window_start + self.config.window <= now {
+            state.window_start = now;
+            state.nxdomains = 0;
+        }
+        state.nxdomains += 1;
+        if state.nxdomains < self.config.threshold {
+            return;
+        }
+        if state.attacked_until <= now {
+            warn!("{} NXDOMAINs under {} within {}s, limiting lookups to {}/s", state.nxdomains, zone, self.config.window, self.config.recursion_rate);
+        }
+        state.attacked_until = now + self.config.hold;
+    }
+
+    pub fn attacked_zones(&self) -> Vec<String> {
+        let now = now();
+        let mut zones: Vec<String> = self.zones.lock().unwrap().iter()
+            .filter(|(_, state)| state.attacked_until > now)
+            .map(|(zone, _)| zone.clone())
+            .collect();
+        zones.sort();
+        zones
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::dump::parse_record;
+    use crate::utils::builder::DnsPacketBuilder;
+    use crate::utils::query_type::QueryType;
+
+    fn create_guard() -> NxdomainGuard {
+        NxdomainGuard::new(&NxdomainGuardConfig { enabled: true, threshold: 3, window: 10, recursion_rate: 2, hold: 60 })
+    }
+
+    // What the zone's servers say about a name, with the zone's SOA
+    fn response(name: &str, rescode: ResultCode, zone: &str) -> DnsPacket {
+        let soa = format!("{}. 300 IN SOA ns.{}. admin.{}. 1 3600 600 86400 300", zone, zone, zone);
+        DnsPacketBuilder::query(name, QueryType::A).rcode(rescode).authority(parse_record(&soa).unwrap()).build()
+    }
+
+    #[test]
+    fn test_zone_of() {
+        assert_eq!(zone_of("x7f3k.example.com.", &response("x7f3k.example.com", ResultCode::NXDOMAIN, "Example.com")), Some("example.com".to_string()));
+        // The zone cut is where the SOA says, not the parent of the name
+        assert_eq!(zone_of("a.b.example.com", &response("a.b.example.com", ResultCode::NXDOMAIN, "example.com")), Some("example.com".to_string()));
+        assert_eq!(zone_of("x7f3k.co.uk", &response("x7f3k.co.uk", ResultCode::NXDOMAIN, "co.uk")), Some("co.uk".to_string()));
+        assert_eq!(zone_of("x7f3k.com", &response("x7f3k.com", ResultCode::NXDOMAIN, "com")), None);
+        assert_eq!(zone_of("example.org", &response("example.org", ResultCode::NXDOMAIN, "example.com")), None);
+        assert_eq!(zone_of("a.example.com", &DnsPacketBuilder::query("a.example.com", QueryType::A).build()), None);
+        assert_eq!(ancestors("a.b.example.com.").collect::<Vec<_>>(), vec!["b.example.com", "example.com", "com"]);
+    }
+
+    #[test]
+    fn test_flood_limits_lookups() {
+        let guard = create_guard();
+        for label in ["a1", "b2", "c3"] {
+            let name = format!("{}.victim.com", label);
+            assert!(guard.allow_lookup_at(&name, 100));
+            guard.record_at(&name, &response(&name, ResultCode::NXDOMAIN, "victim.com"), 100);
+        }
+
+        assert!(guard.allow_lookup_at("d4.victim.com", 101));
+        assert!(guard.allow_lookup_at("e5.Victim.com", 101));
+        assert!(!guard.allow_lookup_at("f6.victim.com", 101));
+        assert!(!guard.allow_lookup_at("x.y.victim.com", 101));
+        assert!(guard.allow_lookup_at("www.other.com", 101));
+        // A new second gets a new allowance, the hold running out lifts the limit
+        assert!(guard.allow_lookup_at("g7.victim.com", 102));
+        assert!((0..5).all(|_| guard.allow_lookup_at("h8.victim.com", 160)));
+    }
+
+    #[test]
+    fn test_closer_zones_keep_their_lookups() {
+        let guard = create_guard();
+        for label in ["a1", "b2", "c3"] {
+            let name = format!("{}.co.uk", label);
+            guard.record_at(&name, &response(&name, ResultCode::NXDOMAIN, "co.uk"), 100);
+        }
+        // bbc.co.uk is its own zone, once an answer from it shows that its names are no longer held back
+        assert!(guard.allow_lookup_at("x.co.uk", 101));
+        assert!(guard.allow_lookup_at("y.co.uk", 101));
+        assert!(!guard.allow_lookup_at("www.bbc.co.uk", 101));
+        guard.record_at("mail.bbc.co.uk", &response("mail.bbc.co.uk", ResultCode::NOERROR, "bbc.co.uk"), 101);
+        assert!((0..5).all(|_| guard.allow_lookup_at("www.bbc.co.uk", 101)));
+        assert!(!guard.allow_lookup_at("z.co.uk", 101));
+    }
+
+    #[test]
+    fn test_slow_nxdomains_are_not_an_attack() {
+        let guard = create_guard();
+        for now in [100, 111, 122, 133] {
+            guard.record_at("typo.example.com", &response("typo.example.com", ResultCode::NXDOMAIN, "example.com"), now);
+            guard.record_at("www.example.com", &response("www.example.com", ResultCode::NOERROR, "example.com"), now);
+        }
+        assert!((0..5).all(|_| guard.allow_lookup_at("other.example.com", 133)));
+    }
+}
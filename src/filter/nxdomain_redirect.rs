@@ -0,0 +1,137 @@
+use std::io;
+use std::net::{AddrParseError, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::utils::dns_class::DnsClass;
+use crate::utils::name::is_subdomain;
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct NxdomainRedirectConfig {
+    pub enabled: bool,
+    pub zones: Vec<String>, // names at or below these are redirected, "." for every name
+    pub a: String,          // the landing page's addresses, empty for none
+    pub aaaa: String,
+    pub ttl: u32,
+}
+
+impl Default for NxdomainRedirectConfig {
+    fn default() -> Self {
+        NxdomainRedirectConfig {
+            enabled: false,
+            zones: Vec::new(),
+            a: String::new(),
+            aaaa: String::new(),
+            ttl: 30,
+        }
+    }
+}
+
+// An address from the config, empty for none
+pub fn parse_addr<T: FromStr<Err = AddrParseError>>(addr: &str) -> io::Result<Option<T>> {
+    if addr.is_empty() {
+        return Ok(None);
+    }
+    addr.parse().map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", addr, e)))
+}
+
+/**
+Turns NXDOMAIN for names under the configured zones into an answer pointing at a landing page,
+e.g. a "this site is blocked" or search page. It only touches finished responses, the cache and
+everything before it still see the real NXDOMAIN. A and AAAA questions get the page's address
+when one of that family is set, any other type gets an empty NOERROR, since the name now exists.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct NxdomainRedirect {
+    zones: Vec<String>,
+    a: Option<Ipv4Addr>,
+    aaaa: Option<Ipv6Addr>,
+    ttl: u32,
+}
+
+impl NxdomainRedirect {
+    pub fn new(config: &NxdomainRedirectConfig) -> io::Result<NxdomainRedirect> {
+        let (a, aaaa) = (parse_addr(&config.a)?, parse_addr(&config.aaaa)?);
+        if a.is_none() && aaaa.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Neither an a nor an aaaa address to redirect to"));
+        }
+        Ok(NxdomainRedirect {
+            zones: config.zones.iter().map(|zone| zone.trim_end_matches('.').to_ascii_lowercase()).collect(),
+            a,
+            aaaa,
+            ttl: config.ttl,
+        })
+    }
+
+    // Whether `response` was an NXDOMAIN this rewrote into an answer
+    pub fn redirect(&self, response: &mut DnsPacket) -> bool {
+        let [question] = response.questions.as_slice() else {
+            return false;
+        };
+        if response.header.rescode != ResultCode::NXDOMAIN || question.qclass != DnsClass::IN
+            || !self.zones.iter().any(|zone| is_subdomain(&question.name, zone)) {
+            return false;
+        }
+        let answer = match question.qtype {
+            QueryType::A => self.a.map(|addr| DnsRecord::a(&question.name, addr, self.ttl)),
+            QueryType::AAAA => self.aaaa.map(|addr| DnsRecord::aaaa(&question.name, addr, self.ttl)),
+            _ => None,
+        };
+        // Only the SOA of an empty answer stays, for its negative TTL. Proofs of non-existence would contradict us.
+        let keep_soa = answer.is_none();
+        response.header.rescode = ResultCode::NOERROR;
+        response.header.authoritative_answer = false;
+        response.answers = answer.into_iter().collect();
+        response.authorities.retain(|record| keep_soa && matches!(record, DnsRecord::SOA { .. }));
+        response.set_negative_ttl(self.ttl);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::dump::parse_record;
+    use crate::utils::builder::DnsPacketBuilder;
+
+    fn nxdomain(name: &str, qtype: QueryType) -> DnsPacket {
+        DnsPacketBuilder::query(name, qtype)
+            .rcode(ResultCode::NXDOMAIN)
+            .authority(parse_record("example.com. 300 IN SOA ns.example.com. admin.example.com. 1 3600 600 86400 300").unwrap())
+            .build()
+    }
+
+    #[test]
+    fn test_redirect() {
+        let redirect = NxdomainRedirect::new(&NxdomainRedirectConfig {
+            enabled: true,
+            zones: vec!["Example.com.".to_string()],
+            a: "192.0.2.80".to_string(),
+            ..NxdomainRedirectConfig::default()
+        }).unwrap();
+
+        let mut response = nxdomain("Typo.example.com", QueryType::A);
+        assert!(redirect.redirect(&mut response));
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.answers, vec![DnsRecord::a("Typo.example.com", Ipv4Addr::new(192, 0, 2, 80), 30)]);
+        assert!(response.authorities.is_empty());
+
+        // No IPv6 page: the name exists, it just has no AAAA
+        let mut response = nxdomain("typo.example.com", QueryType::AAAA);
+        assert!(redirect.redirect(&mut response));
+        assert!(response.answers.is_empty());
+        assert_eq!(response.negative_ttl(), Some(30));
+
+        let mut response = nxdomain("typo.example.org", QueryType::A);
+        assert!(!redirect.redirect(&mut response));
+        assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+
+        assert!(NxdomainRedirect::new(&NxdomainRedirectConfig::default()).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::utils::name::is_subdomain;
+use crate::utils::packet::DnsPacket;
+use crate::utils::record::DnsRecord;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RebindingConfig {
+    pub enabled: bool,
+    pub allow: Vec<String>, // domains (and their subdomains) that may resolve to private addresses
+}
+
+impl Default for RebindingConfig {
+    fn default() -> Self {
+        RebindingConfig {
+            enabled: false,
+            allow: vec!["localhost".to_string(), "home.arpa".to_string()],
+        }
+    }
+}
+
+fn is_private_v4(addr: &Ipv4Addr) -> bool {
+    addr.is_private() || addr.is_loopback() || addr.is_link_local() || addr.octets()[0] == 0
+}
+
+fn is_private_v6(addr: &Ipv6Addr) -> bool {
+    if let Some(v4) = addr.to_ipv4_mapped() {
+        return is_private_v4(&v4);
+    }
+    addr.is_loopback() || addr.is_unspecified() || addr.is_unique_local() || addr.is_unicast_link_local()
+}
+
+// RFC 1918, loopback, link-local and "this network" addresses, plus the IPv6 equivalents
+pub fn is_private(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => is_private_v4(&v4),
+        IpAddr::V6(v6) => is_private_v6(&v6),
+    }
+}
+
+fn record_is_private(record: &DnsRecord) -> bool {
+    match record {
+        DnsRecord::A { addr, .. } => is_private_v4(addr),
+        DnsRecord::AAAA { addr, .. } => is_private_v6(addr),
+        _ => false,
+    }
+}
+
+/**
+Protects LAN devices from DNS rebinding: a public name that suddenly resolves to an address
+on the local network lets a web page talk to the router or printer under its own origin.
+Such addresses are dropped from upstream answers unless the name is explicitly allowed.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct RebindingFilter {
+    allow: Vec<String>,
+}
+
+impl RebindingFilter {
+    pub fn new(config: &RebindingConfig) -> RebindingFilter {
+        RebindingFilter { allow: config.allow.clone() }
+    }
+
+    pub fn is_allowed(&self, qname: &str) -> bool {
+        self.allow.iter().any(|domain| is_subdomain(qname, domain))
+    }
+
+    // Returns how many records were removed
+    pub fn filter(&self, qname: &str, packet: &mut DnsPacket) -> usize {
+        if self.is_allowed(qname) {
+            return 0;
+        }
+        let before = packet.answers.len() + packet.resources.len();
+        packet.answers.retain(|record| !record_is_private(record));
+        packet.resources.retain(|record| !record_is_private(record));
+        let removed = before - packet.answers.len() - packet.resources.len();
+        if removed > 0 {
+            warn!("Removed {} private address(es) from the answer for {}", removed, qname);
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+
+    #[test]
+    fn test_is_private() {
+        for addr in ["10.1.2.3", "172.16.0.1", "192.168.1.1", "127.0.0.1", "169.254.0.1", "0.0.0.0", "::1", "fe80::1", "fd00::1", "::ffff:192.168.0.1"] {
+            assert!(is_private(addr.parse().unwrap()), "{}", addr);
+        }
+        for addr in ["192.0.2.1", "172.32.0.1", "8.8.8.8", "2001:db8::1", "::ffff:8.8.8.8"] {
+            assert!(!is_private(addr.parse().unwrap()), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = RebindingFilter::new(&RebindingConfig {
+            enabled: true,
+            allow: vec!["corp.example".to_string()],
+        });
+        let mut packet = DnsPacket::new();
+        packet.answers.push(DnsRecord::A { domain: "evil.example".to_string(), addr: Ipv4Addr::new(192, 168, 0, 1), ttl: 60, class: DnsClass::IN });
+        packet.answers.push(DnsRecord::A { domain: "evil.example".to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 60, class: DnsClass::IN });
+        packet.resources.push(DnsRecord::AAAA { domain: "ns.evil.example".to_string(), addr: "fe80::1".parse().unwrap(), ttl: 60, class: DnsClass::IN });
+
+        let mut allowed = packet.clone();
+        assert_eq!(filter.filter("intranet.corp.example", &mut allowed), 0);
+        assert_eq!(allowed, packet);
+
+        assert_eq!(filter.filter("evil.example", &mut packet), 2);
+        assert_eq!(packet.answers.len(), 1);
+        assert!(packet.resources.is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::io;
+
+use serde::Deserialize;
+
+use crate::utils::name::is_subdomain;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RewriteConfig {
+    pub from: String, // "internal.app", or "*.internal" for every name below it
+    pub to: String,   // "app.prod.cluster.local", or "*.svc.cluster.local" to keep what `*` stood for
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn invalid(rule: &RewriteConfig, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} -> {}: {}", rule.from, rule.to, message))
+}
+
+fn check_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 253 && name.split('.').all(|label| !label.is_empty() && label.len() <= 63 && !label.contains('*'))
+}
+
+/**
+Maps the names clients ask for to the names that are actually resolved, from the `[[rewrites]]`
+tables. An exact rule wins over any `*.zone` rule, and among those the longest zone wins. The
+name a query was rewritten to is never rewritten again, so rules can't loop.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rewrites {
+    exact: HashMap<String, String>,
+    // The zone below which names are rewritten, and the zone they move to or the one name they all become
+    suffixes: Vec<(String, Target)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Target {
+    Name(String),
+    Zone(String),
+}
+
+impl Rewrites {
+    pub fn new(rules: &[RewriteConfig]) -> io::Result<Rewrites> {
+        let mut rewrites = Rewrites::default();
+        for rule in rules {
+            let (from, to) = (normalize(&rule.from), normalize(&rule.to));
+            let target = match to.strip_prefix("*.") {
+                Some(zone) if from.starts_with("*.") => Target::Zone(zone.to_string()),
+                Some(_) => return Err(invalid(rule, "only a *.zone rule can rewrite to a *.zone")),
+                None => Target::Name(to.clone()),
+            };
+            let (Target::Name(name) | Target::Zone(name)) = &target;
+            if !check_name(name) || !check_name(from.strip_prefix("*.").unwrap_or(&from)) {
+                return Err(invalid(rule, "not a valid name"));
+            }
+            match from.strip_prefix("*.") {
+                Some(zone) => rewrites.suffixes.push((zone.to_string(), target)),
+                None => {
+                    if rewrites.exact.insert(from, to).is_some() {
+                        return Err(invalid(rule, "the name is rewritten twice"));
+                    }
+                }
+            }
+        }
+        Ok(rewrites)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.suffixes.is_empty()
+    }
+
+    // The name to resolve instead of `qname`, None when no rule matches
+    pub fn rewrite(&self, qname: &str) -> Option<String> {
+        let qname = normalize(qname);
+        if let Some(to) = self.exact.get(&qname) {
+            return Some(to.clone());
+        }
+        let (zone, target) = self.suffixes.iter()
+            .filter(|(zone, _)| is_subdomain(&qname, zone) && qname != *zone)
+            .max_by_key(|(zone, _)| zone.len())?;
+        match target {
+            Target::Name(name) => Some(name.clone()),
+            Target::Zone(to) => {
+                let rewritten = format!("{}.{}", &qname[..qname.len() - zone.len() - 1], to);
+                // The part `*` stood for may be too long for the other zone
+                (rewritten.len() <= 253).then_some(rewritten)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(from: &str, to: &str) -> RewriteConfig {
+        RewriteConfig { from: from.to_string(), to: to.to_string() }
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let rewrites = Rewrites::new(&[
+            rule("internal.app.", "App.prod.cluster.local"),
+            rule("*.internal", "*.svc.cluster.local"),
+            rule("*.legacy.internal", "gateway.corp.example"),
+        ]).unwrap();
+
+        assert_eq!(rewrites.rewrite("Internal.App"), Some("app.prod.cluster.local".to_string()));
+        assert_eq!(rewrites.rewrite("api.internal."), Some("api.svc.cluster.local".to_string()));
+        assert_eq!(rewrites.rewrite("a.b.internal"), Some("a.b.svc.cluster.local".to_string()));
+        // The longest zone wins, and a zone's rule doesn't cover its apex
+        assert_eq!(rewrites.rewrite("billing.legacy.internal"), Some("gateway.corp.example".to_string()));
+        assert_eq!(rewrites.rewrite("internal"), None);
+        assert_eq!(rewrites.rewrite("example.com"), None);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(Rewrites::new(&[rule("internal.app", "*.cluster.local")]).is_err());
+        assert!(Rewrites::new(&[rule("*.internal", "")]).is_err());
+        assert!(Rewrites::new(&[rule("a..internal", "b.internal")]).is_err());
+        assert!(Rewrites::new(&[rule("a.internal", "b.internal"), rule("A.internal.", "c.internal")]).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use std::io;
+use std::net::IpAddr;
+
+use serde::Deserialize;
+
+use crate::utils::subnet::Subnet;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum YoutubeMode {
+    #[default]
+    Strict,
+    Moderate,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SafeSearchGroup {
+    pub name: String,
+    pub clients: Vec<String>, // addresses or subnets, "192.168.1.0/24"
+    pub enabled: bool,
+}
+
+impl Default for SafeSearchGroup {
+    fn default() -> Self {
+        SafeSearchGroup {
+            name: String::new(),
+            clients: Vec::new(),
+            enabled: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SafeSearchConfig {
+    pub enabled: bool, // for clients that aren't in any group
+    pub youtube: YoutubeMode,
+    pub groups: Vec<SafeSearchGroup>,
+}
+
+impl SafeSearchConfig {
+    pub fn is_used(&self) -> bool {
+        self.enabled || self.groups.iter().any(|group| group.enabled)
+    }
+}
+
+/**
+Forces safe search by answering the search engines' names with a CNAME to the endpoint each
+provider designates for it, followed by that endpoint's addresses. Whether a client gets the
+rewrite is decided by the first group its address falls in, everyone else gets the default.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct SafeSearch {
+    default: bool,
+    youtube: YoutubeMode,
+    groups: Vec<(Vec<Subnet>, bool)>,
+}
+
+impl SafeSearch {
+    pub fn new(config: &SafeSearchConfig) -> io::Result<SafeSearch> {
+        let mut groups = Vec::new();
+        for group in &config.groups {
+            let clients = group.clients.iter()
+                .map(|client| client.parse().map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, format!("Safe search group {:?}: {}", group.name, e))))
+                .collect::<io::Result<Vec<Subnet>>>()?;
+            groups.push((clients, group.enabled));
+        }
+        Ok(SafeSearch {
+            default: config.enabled,
+            youtube: config.youtube,
+            groups,
+        })
+    }
+
+    pub fn is_enforced(&self, client: Option<IpAddr>) -> bool {
+        let client = match client {
+            Some(client) => client,
+            None => return self.default,
+        };
+        self.groups.iter()
+            .find(|(subnets, _)| subnets.iter().any(|subnet| subnet.contains(client)))
+            .map(|(_, enabled)| *enabled)
+            .unwrap_or(self.default)
+    }
+
+    // The provider's safe search endpoint for `qname`, if it is one of theirs
+    pub fn rewrite(&self, qname: &str) -> Option<&'static str> {
+        let qname = qname.trim_end_matches('.').to_ascii_lowercase();
+        let name = qname.strip_prefix("www.").unwrap_or(&qname);
+
+        // Google runs a search front page under most country code domains, google.co.uk and friends
+        if let Some(suffix) = name.strip_prefix("google.") {
+            if !suffix.is_empty() && suffix.split('.').count() <= 2 {
+                return Some("forcesafesearch.google.com");
+            }
+        }
+
+        match (name, qname.as_str()) {
+            ("bing.com", _) => Some("strict.bing.com"),
+            ("duckduckgo.com", _) => Some("safe.duckduckgo.com"),
+            ("youtube.com", _) | (_, "m.youtube.com" | "youtubei.googleapis.com" | "youtube.googleapis.com" | "www.youtube-nocookie.com") => {
+                match self.youtube {
+                    YoutubeMode::Strict => Some("restrict.youtube.com"),
+                    YoutubeMode::Moderate => Some("restrictmoderate.youtube.com"),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_safe_search() -> SafeSearch {
+        SafeSearch::new(&SafeSearchConfig {
+            enabled: false,
+            youtube: YoutubeMode::Moderate,
+            groups: vec![
+                SafeSearchGroup { name: "admin".to_string(), clients: vec!["192.168.1.10".to_string()], enabled: false },
+                SafeSearchGroup { name: "kids".to_string(), clients: vec!["192.168.1.0/24".to_string()], enabled: true },
+            ],
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let safe_search = create_safe_search();
+        assert_eq!(safe_search.rewrite("www.google.com"), Some("forcesafesearch.google.com"));
+        assert_eq!(safe_search.rewrite("WWW.GOOGLE.CO.UK."), Some("forcesafesearch.google.com"));
+        assert_eq!(safe_search.rewrite("bing.com"), Some("strict.bing.com"));
+        assert_eq!(safe_search.rewrite("m.youtube.com"), Some("restrictmoderate.youtube.com"));
+        assert_eq!(safe_search.rewrite("mail.google.com"), None);
+        assert_eq!(safe_search.rewrite("forcesafesearch.google.com"), None);
+        assert_eq!(safe_search.rewrite("google.example.evil.com"), None);
+    }
+
+    #[test]
+    fn test_groups() {
+        let safe_search = create_safe_search();
+        assert!(safe_search.is_enforced(Some("192.168.1.20".parse().unwrap())));
+        assert!(!safe_search.is_enforced(Some("192.168.1.10".parse().unwrap())));
+        assert!(!safe_search.is_enforced(Some("10.0.0.1".parse().unwrap())));
+        assert!(!safe_search.is_enforced(None));
+
+        let config = SafeSearchConfig {
+            groups: vec![SafeSearchGroup { clients: vec!["nonsense".to_string()], ..Default::default() }],
+            ..Default::default()
+        };
+        assert!(SafeSearch::new(&config).is_err());
+    }
+}
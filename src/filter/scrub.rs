@@ -0,0 +1,132 @@
+use log::debug;
+
+use crate::utils::name::{is_subdomain, substitute_dname};
+use crate::utils::packet::DnsPacket;
+use crate::utils::record::DnsRecord;
+
+// The query name plus every name reached by following CNAMEs, or the DNAMEs covering a name
+// without one, in the answer section
+fn chain_names(qname: &str, answers: &[DnsRecord]) -> Vec<String> {
+    let mut names = vec![qname.to_ascii_lowercase()];
+    // Each pass can only add one link, a longer chain than the section is a loop
+    for _ in 0..answers.len() {
+        let last = names.last().unwrap().clone();
+        let next = answers.iter().find_map(|record| match record {
+            DnsRecord::CNAME { domain, cname, .. } if domain.eq_ignore_ascii_case(&last) => Some(cname.to_ascii_lowercase()),
+            _ => None,
+        }).or_else(|| answers.iter().find_map(|record| match record {
+            DnsRecord::DNAME { domain, target, .. } => substitute_dname(&last, domain, target).map(|name| name.to_ascii_lowercase()),
+            _ => None,
+        }));
+        match next {
+            Some(next) if !names.contains(&next) => names.push(next),
+            _ => break,
+        }
+    }
+    names
+}
+
+fn target_name(record: &DnsRecord) -> Option<&str> {
+    match record {
+        DnsRecord::NS { ns, .. } => Some(ns),
+        DnsRecord::MX { exchange, .. } => Some(exchange),
+        DnsRecord::SRV { target, .. } => Some(target),
+        _ => None,
+    }
+}
+
+/**
+Drops records an upstream had no business sending for this question, so a compromised or
+sloppy server can't slip unrelated data into the cache:
+- answers must belong to the query name or the CNAME chain followed from it, DNAMEs must cover
+  one of those names
+- authority records must belong to a zone above one of those names
+- additional records must be an address for a name the kept records point at
+
+Returns how many records were removed.
+*/
+pub fn scrub(qname: &str, packet: &mut DnsPacket) -> usize {
+    let before = packet.answers.len() + packet.authorities.len() + packet.resources.len();
+    let names = chain_names(qname, &packet.answers);
+
+    packet.answers.retain(|record| match record {
+        DnsRecord::DNAME { domain, .. } => names.iter().any(|name| is_subdomain(name, domain) && !name.eq_ignore_ascii_case(domain.trim_end_matches('.'))),
+        _ => names.iter().any(|name| record.domain().eq_ignore_ascii_case(name)),
+    });
+    packet.authorities.retain(|record| names.iter().any(|name| is_subdomain(name, record.domain())));
+
+    let targets: Vec<&str> = packet.answers.iter().chain(&packet.authorities).filter_map(target_name).collect();
+    packet.resources.retain(|record| {
+        matches!(record, DnsRecord::A { .. } | DnsRecord::AAAA { .. })
+            && targets.iter().any(|target| record.domain().eq_ignore_ascii_case(target))
+    });
+
+    let removed = before - packet.answers.len() - packet.authorities.len() - packet.resources.len();
+    if removed > 0 {
+        debug!("Scrubbed {} unrelated record(s) from the answer for {}", removed, qname);
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+    use std::net::Ipv4Addr;
+
+    fn a(domain: &str) -> DnsRecord {
+        DnsRecord::A { domain: domain.to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 60, class: DnsClass::IN }
+    }
+
+    fn cname(domain: &str, target: &str) -> DnsRecord {
+        DnsRecord::CNAME { domain: domain.to_string(), cname: target.to_string(), ttl: 60, class: DnsClass::IN }
+    }
+
+    fn ns(domain: &str, target: &str) -> DnsRecord {
+        DnsRecord::NS { domain: domain.to_string(), ns: target.to_string(), ttl: 60, class: DnsClass::IN }
+    }
+
+    #[test]
+    fn test_scrub_answers() {
+        let mut packet = DnsPacket::new();
+        packet.answers = vec![
+            cname("www.example.com", "cdn.example.net"),
+            a("cdn.example.net"),
+            a("bank.example"),
+            cname("unrelated.example", "www.example.com"),
+        ];
+
+        assert_eq!(scrub("WWW.example.com", &mut packet), 2);
+        assert_eq!(packet.answers, vec![cname("www.example.com", "cdn.example.net"), a("cdn.example.net")]);
+    }
+
+    #[test]
+    fn test_scrub_authority_and_additional() {
+        let mut packet = DnsPacket::new();
+        packet.authorities = vec![ns("example.com", "ns1.example.com"), ns("bank.example", "ns.attacker.example")];
+        packet.resources = vec![a("ns1.example.com"), a("ns.attacker.example"), a("www.bank.example")];
+
+        assert_eq!(scrub("www.example.com", &mut packet), 3);
+        assert_eq!(packet.authorities, vec![ns("example.com", "ns1.example.com")]);
+        assert_eq!(packet.resources, vec![a("ns1.example.com")]);
+    }
+
+    #[test]
+    fn test_scrub_dname() {
+        let dname = DnsRecord::DNAME { domain: "example.org".to_string(), target: "example.net".to_string(), ttl: 60, class: DnsClass::IN };
+        let other = DnsRecord::DNAME { domain: "bank.example".to_string(), target: "attacker.example".to_string(), ttl: 60, class: DnsClass::IN };
+        let mut packet = DnsPacket::new();
+        packet.answers = vec![dname.clone(), other, a("www.example.net")];
+
+        assert_eq!(scrub("www.example.org", &mut packet), 1);
+        assert_eq!(packet.answers, vec![dname, a("www.example.net")]);
+    }
+
+    #[test]
+    fn test_cname_loop() {
+        let mut packet = DnsPacket::new();
+        packet.answers = vec![cname("a.example", "b.example"), cname("b.example", "a.example")];
+
+        assert_eq!(scrub("a.example", &mut packet), 0);
+    }
+}
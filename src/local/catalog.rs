@@ -0,0 +1,169 @@
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::local::transfer::{axfr, soa_serial};
+use crate::local::zone::{Zone, Zones};
+use crate::utils::name::is_subdomain;
+use crate::utils::record::DnsRecord;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CatalogConfig {
+    pub name: String,    // the catalog zone, e.g. "catalog.invalid"
+    pub primary: String, // "ip:port" the catalog and its member zones are transferred from
+    pub refresh: u64,    // seconds between checks for new, removed and changed zones
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        CatalogConfig {
+            name: String::new(),
+            primary: String::new(),
+            refresh: 300,
+        }
+    }
+}
+
+/*
+The member zones listed in a catalog zone (RFC 9432): the PTR targets of `<id>.zones.<catalog>`.
+Only schema version 2 is understood, the version is a TXT record at `version.<catalog>`.
+*/
+pub fn members(catalog: &str, records: &[DnsRecord]) -> Result<BTreeSet<String>, String> {
+    let catalog = catalog.trim_end_matches('.').to_ascii_lowercase();
+    let version = format!("version.{}", catalog);
+    let versions: Vec<&Vec<Vec<u8>>> = records.iter()
+        .filter_map(|record| match record {
+            DnsRecord::TXT { domain, data, .. } if domain.eq_ignore_ascii_case(&version) => Some(data),
+            _ => None,
+        })
+        .collect();
+    if versions.as_slice() != [&vec![b"2".to_vec()]] {
+        return Err(format!("Catalog {} doesn't have schema version 2", catalog));
+    }
+
+    let zones = format!("zones.{}", catalog);
+    Ok(records.iter()
+        .filter_map(|record| match record {
+            DnsRecord::PTR { domain, host, .. } => {
+                let (_, parent) = domain.split_once('.')?;
+                parent.eq_ignore_ascii_case(&zones).then(|| host.trim_end_matches('.').to_ascii_lowercase())
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/**
+Keeps the secondary zones a catalog lists in sync with its primary. The catalog is transferred
+every `refresh` seconds, new member zones are transferred and served, zones that left the
+catalog are dropped and members whose SOA serial changed are transferred again. Only zones the
+catalog brought in are ever replaced or removed, a member with the name of a configured zone is
+skipped. The catalog zone itself isn't served, it only describes the others.
+*/
+struct Catalog {
+    name: String,
+    primary: SocketAddr,
+    zones: Zones,
+    members: HashSet<String>,
+}
+
+impl Catalog {
+    fn transfer(&self, member: &str) -> io::Result<Zone> {
+        // Types this server can't store or generates itself are left out rather than refusing the zone
+        let records = axfr(self.primary, member)?
+            .into_iter()
+            .filter(|record| !matches!(record, DnsRecord::UNKNOWN { .. } | DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. }))
+            .filter(|record| is_subdomain(record.domain(), member))
+            .collect();
+        Zone::from_records(member, records).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        let listed = members(&self.name, &axfr(self.primary, &self.name)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        for removed in self.members.iter().filter(|member| !listed.contains(*member)) {
+            self.zones.remove(removed);
+            info!("Zone {} left catalog {}", removed, self.name);
+        }
+        self.members.retain(|member| listed.contains(member));
+
+        for member in listed {
+            let ours = self.members.contains(&member);
+            let serial = self.zones.serial(&member);
+            if serial.is_some() && !ours {
+                warn!("Zone {} in catalog {} is already configured locally, skipping it", member, self.name);
+                continue;
+            }
+            let result = soa_serial(self.primary, &member).and_then(|primary_serial| {
+                if serial == Some(primary_serial) {
+                    return Ok(None);
+                }
+                self.transfer(&member).map(Some)
+            });
+            match result {
+                Ok(Some(zone)) => {
+                    info!("Transferred zone {} from catalog {}, {} records, serial {}", member, self.name, zone.records.len(), zone.serial());
+                    self.zones.insert(zone);
+                    self.members.insert(member);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to transfer zone {} from {}: {}", member, self.primary, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+// One thread per catalog, member zones show up in `zones` as they are transferred
+pub fn start(configs: &[CatalogConfig], zones: &Zones) -> io::Result<()> {
+    for config in configs {
+        let primary = config.primary.parse::<SocketAddr>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid primary {:?} for catalog {}", config.primary, config.name)))?;
+        let mut catalog = Catalog { name: config.name.clone(), primary, zones: zones.clone(), members: HashSet::new() };
+        let refresh = Duration::from_secs(config.refresh.max(1));
+        thread::spawn(move || loop {
+            if let Err(e) = catalog.sync() {
+                warn!("Failed to refresh catalog {} from {}: {}", catalog.name, catalog.primary, e);
+            }
+            thread::sleep(refresh);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+
+    fn ptr(domain: &str, host: &str) -> DnsRecord {
+        DnsRecord::PTR { domain: domain.to_string(), host: host.to_string(), ttl: 0, class: DnsClass::IN }
+    }
+
+    fn version(version: &str) -> DnsRecord {
+        DnsRecord::TXT { domain: "version.catalog.invalid".to_string(), data: vec![version.as_bytes().to_vec()], ttl: 0, class: DnsClass::IN }
+    }
+
+    #[test]
+    fn test_members() {
+        let records = vec![
+            version("2"),
+            ptr("abc.zones.catalog.invalid", "Example.lan."),
+            ptr("def.zones.catalog.invalid", "example.org"),
+            // A property of a member, not a member
+            ptr("group.abc.zones.catalog.invalid", "ignored.lan"),
+        ];
+        let zones = members("catalog.invalid.", &records).unwrap();
+        assert_eq!(zones.into_iter().collect::<Vec<_>>(), vec!["example.lan", "example.org"]);
+
+        assert!(members("catalog.invalid", &records[1..]).is_err());
+        assert!(members("catalog.invalid", &[version("1"), ptr("abc.zones.catalog.invalid", "example.lan")]).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use serde::Deserialize;
+
+use crate::utils::dns_class::DnsClass;
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    pub version: String, // answer to version.bind / version.server, empty to refuse
+    pub id: String,      // answer to id.server / hostname.bind, empty to refuse
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            enabled: true,
+            version: format!("R_DNS {}", env!("CARGO_PKG_VERSION")),
+            id: String::new(),
+        }
+    }
+}
+
+/**
+Answers the CHAOS-class TXT queries operators use to tell servers apart
+(`dig CH TXT version.bind`). Anything else in class CH is refused by the caller.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chaos {
+    version: String,
+    id: String,
+}
+
+impl Chaos {
+    pub fn new(config: &ChaosConfig) -> Chaos {
+        Chaos {
+            version: config.version.clone(),
+            id: config.id.clone(),
+        }
+    }
+
+    pub fn answer(&self, question: &DnsQuestion) -> Option<Vec<DnsRecord>> {
+        if question.qclass != DnsClass::CH || !matches!(question.qtype, QueryType::TXT | QueryType::ANY) {
+            return None;
+        }
+        let text = match question.name.trim_end_matches('.').to_ascii_lowercase().as_str() {
+            "version.bind" | "version.server" => &self.version,
+            "id.server" | "hostname.bind" => &self.id,
+            _ => return None,
+        };
+        if text.is_empty() {
+            return None;
+        }
+        Some(vec![DnsRecord::TXT {
+            domain: question.name.clone(),
+            data: vec![text.as_bytes().to_vec()],
+            ttl: 0,
+            class: DnsClass::CH,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(name: &str, qclass: DnsClass) -> DnsQuestion {
+        let mut question = DnsQuestion::new(name.to_string(), QueryType::TXT);
+        question.qclass = qclass;
+        question
+    }
+
+    #[test]
+    fn test_answer() {
+        let chaos = Chaos::new(&ChaosConfig {
+            enabled: true,
+            version: "test 1.0".to_string(),
+            id: String::new(),
+        });
+
+        let answers = chaos.answer(&question("VERSION.BIND", DnsClass::CH)).unwrap();
+        assert_eq!(answers, vec![DnsRecord::TXT { domain: "VERSION.BIND".to_string(), data: vec![b"test 1.0".to_vec()], ttl: 0, class: DnsClass::CH }]);
+
+        assert_eq!(chaos.answer(&question("id.server", DnsClass::CH)), None);
+        assert_eq!(chaos.answer(&question("version.bind", DnsClass::IN)), None);
+        assert_eq!(chaos.answer(&question("authors.bind", DnsClass::CH)), None);
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::local::zone::{Zone, ZoneConfig};
+use crate::utils::dns_class::DnsClass;
+use crate::utils::key_file::from_hex;
+use crate::utils::name::{canonical_cmp, is_subdomain};
+use crate::utils::nsec::{nsec3_hash, to_base32hex, NSEC3_SHA1};
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+
+// How a zone proves that names and types don't exist
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DenialMode {
+    #[default]
+    None,
+    Nsec,
+    Nsec3,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Denial {
+    #[default]
+    None,
+    Nsec,
+    Nsec3 { salt: Vec<u8>, iterations: u16 },
+}
+
+// "a.b.example.com" in "example.com" gives "a.b.example.com", "b.example.com" and "example.com"
+fn ancestors<'a>(name: &'a str, origin: &'a str) -> impl Iterator<Item = &'a str> {
+    std::iter::successors(Some(name), |name| name.split_once('.').map(|(_, parent)| parent))
+        .take_while(move |name| is_subdomain(name, origin))
+}
+
+/**
+The names NSEC and NSEC3 chains are built from: every owner name the zone is authoritative for,
+delegation points included but not the glue below them, with the types present at each. A
+delegation point only shows its NS records. Empty non-terminals are listed without types, NSEC
+skips them (the next name proves they exist), NSEC3 needs a record for each (RFC 5155 7.1).
+*/
+fn chain_names(zone: &Zone) -> BTreeMap<String, Vec<QueryType>> {
+    let cuts: Vec<&str> = zone.records.iter()
+        .filter(|record| matches!(record, DnsRecord::NS { .. }) && !is_subdomain(&zone.origin, record.domain()))
+        .map(DnsRecord::domain)
+        .collect();
+    let mut names: BTreeMap<String, Vec<QueryType>> = BTreeMap::new();
+    for record in &zone.records {
+        let name = record.domain().trim_end_matches('.').to_ascii_lowercase();
+        let at_cut = cuts.iter().any(|cut| name.eq_ignore_ascii_case(cut.trim_end_matches('.')));
+        if cuts.iter().any(|cut| is_subdomain(&name, cut)) && !at_cut {
+            continue;
+        }
+        for ancestor in ancestors(&name, &zone.origin).skip(1) {
+            names.entry(ancestor.to_string()).or_default();
+        }
+        let types = names.entry(name).or_default();
+        if !at_cut || matches!(record, DnsRecord::NS { .. }) {
+            types.push(record.query_type());
+        }
+    }
+    // A signed zone has RRSIGs wherever it has records of its own and its key at the apex
+    if zone.signer.is_some() {
+        for (name, types) in names.iter_mut().filter(|(_, types)| !types.is_empty()) {
+            if !cuts.iter().any(|cut| name.eq_ignore_ascii_case(cut.trim_end_matches('.'))) {
+                types.push(QueryType::RRSIG);
+            }
+            if *name == zone.origin {
+                types.push(QueryType::DNSKEY);
+            }
+            types.sort();
+        }
+    }
+    names
+}
+
+impl Denial {
+    pub fn from_config(config: &ZoneConfig) -> Result<Denial, String> {
+        Ok(match config.denial {
+            DenialMode::None => Denial::None,
+            DenialMode::Nsec => Denial::Nsec,
+            DenialMode::Nsec3 => {
+                let salt = match config.nsec3_salt.as_str() {
+                    "" | "-" => Vec::new(),
+                    hex => from_hex(hex).filter(|salt| salt.len() <= 255).ok_or_else(|| format!("Invalid NSEC3 salt {}", hex))?,
+                };
+                Denial::Nsec3 { salt, iterations: config.nsec3_iterations }
+            }
+        })
+    }
+
+    /*
+    The records proving a negative answer for `name`, which doesn't exist if `nxdomain` is set
+    and otherwise has no records of the queried type. `ttl` is the zone's negative TTL.
+    */
+    pub fn prove(&self, zone: &Zone, name: &str, nxdomain: bool, ttl: u32) -> Vec<DnsRecord> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let records = match self {
+            Denial::None => Vec::new(),
+            Denial::Nsec => prove_nsec(zone, &name, nxdomain, ttl),
+            Denial::Nsec3 { salt, iterations } => prove_nsec3(zone, &name, nxdomain, ttl, salt, *iterations),
+        };
+        // One record often proves several things at once
+        let mut unique = Vec::new();
+        for record in records {
+            if !unique.contains(&record) {
+                unique.push(record);
+            }
+        }
+        unique
+    }
+}
+
+// The name that exists closest above a name that doesn't (RFC 5155 section 7.2.1)
+fn closest_encloser<'a>(names: &BTreeMap<String, Vec<QueryType>>, name: &'a str, origin: &'a str) -> (&'a str, Option<&'a str>) {
+    let mut next_closer = None;
+    for ancestor in ancestors(name, origin) {
+        if names.contains_key(ancestor) {
+            return (ancestor, next_closer);
+        }
+        next_closer = Some(ancestor);
+    }
+    (origin, next_closer)
+}
+
+fn prove_nsec(zone: &Zone, name: &str, nxdomain: bool, ttl: u32) -> Vec<DnsRecord> {
+    let names = chain_names(zone);
+    let mut chain: Vec<(&String, &Vec<QueryType>)> = names.iter().filter(|(_, types)| !types.is_empty()).collect();
+    chain.sort_by(|(a, _), (b, _)| canonical_cmp(a, b));
+    if chain.is_empty() {
+        return Vec::new();
+    }
+    // The NSEC at `name`, or the one before it in the chain, whose next name comes after it
+    let nsec = |name: &str| {
+        let i = chain.partition_point(|(owner, _)| canonical_cmp(owner, name).is_le()).max(1) - 1;
+        let (owner, types) = chain[i];
+        let mut types = types.clone();
+        types.push(QueryType::NSEC);
+        types.sort();
+        types.dedup();
+        DnsRecord::NSEC { domain: owner.clone(), next: chain[(i + 1) % chain.len()].0.clone(), types, ttl, class: DnsClass::IN }
+    };
+
+    if !nxdomain {
+        return vec![nsec(name)];
+    }
+    // The name doesn't exist and neither does a wildcard that could have matched it
+    let (encloser, _) = closest_encloser(&names, name, &zone.origin);
+    vec![nsec(name), nsec(&format!("*.{}", encloser))]
+}
+
+fn prove_nsec3(zone: &Zone, name: &str, nxdomain: bool, ttl: u32, salt: &[u8], iterations: u16) -> Vec<DnsRecord> {
+    let names = chain_names(zone);
+    let mut chain: Vec<(Vec<u8>, &Vec<QueryType>)> = names.iter().map(|(name, types)| (nsec3_hash(name, salt, iterations), types)).collect();
+    chain.sort();
+    if chain.is_empty() {
+        return Vec::new();
+    }
+    // The NSEC3 matching the hash of `name`, or covering it if no name has that hash
+    let nsec3 = |name: &str| {
+        let hash = nsec3_hash(name, salt, iterations);
+        let i = match chain.binary_search_by(|(owner, _)| owner.cmp(&hash)) {
+            Ok(i) => i,
+            Err(0) => chain.len() - 1, // before the first hash, the last record wraps around
+            Err(i) => i - 1,
+        };
+        let (owner, types) = &chain[i];
+        DnsRecord::NSEC3 {
+            domain: format!("{}.{}", to_base32hex(owner), zone.origin),
+            algorithm: NSEC3_SHA1,
+            flags: 0,
+            iterations,
+            salt: salt.to_vec(),
+            next: chain[(i + 1) % chain.len()].0.clone(),
+            types: (*types).clone(),
+            ttl,
+            class: DnsClass::IN,
+        }
+    };
+
+    if !nxdomain {
+        return vec![nsec3(name)];
+    }
+    // Closest encloser proof plus the missing wildcard (RFC 5155 section 7.2.2)
+    let (encloser, next_closer) = closest_encloser(&names, name, &zone.origin);
+    let mut records = vec![nsec3(encloser)];
+    records.extend(next_closer.map(&nsec3));
+    records.push(nsec3(&format!("*.{}", encloser)));
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    use crate::local::signer::ZoneSigner;
+    use crate::utils::nsec::from_base32hex;
+    use crate::utils::question::DnsQuestion;
+    use crate::utils::result_code::ResultCode;
+
+    const ZONE: &str = "@\t3600\tIN\tSOA\tns1.example.lan. hostmaster.example.lan. 100 7200 900 1209600 300
+example.lan.\t3600\tIN\tNS\tns1.example.lan.
+ns1.example.lan.\t3600\tIN\tA\t192.0.2.53
+www.example.lan.\t300\tIN\tA\t192.0.2.10
+host.dept.example.lan.\t300\tIN\tA\t192.0.2.20
+sub.example.lan.\t3600\tIN\tNS\tns.sub.example.lan.
+ns.sub.example.lan.\t3600\tIN\tA\t192.0.2.99
+";
+
+    fn zone(denial: Denial) -> Zone {
+        let mut zone = Zone::parse("example.lan", ZONE).unwrap();
+        zone.denial = denial;
+        zone
+    }
+
+    fn nsec(owner: &str, next: &str, types: Vec<QueryType>) -> DnsRecord {
+        DnsRecord::NSEC { domain: owner.to_string(), next: next.to_string(), types, ttl: 300, class: DnsClass::IN }
+    }
+
+    #[test]
+    fn test_nsec_denial() {
+        let zone = zone(Denial::Nsec);
+        let nodata = zone.answer(&DnsQuestion::new("www.example.lan".to_string(), QueryType::MX));
+        assert_eq!(nodata.authorities[1..], [nsec("www.example.lan", "example.lan", vec![QueryType::A, QueryType::NSEC])]);
+
+        // Glue below the delegation and the empty non-terminal dept aren't in the chain
+        let nxdomain = zone.answer(&DnsQuestion::new("missing.example.lan".to_string(), QueryType::A));
+        assert_eq!(nxdomain.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(nxdomain.authorities[1..], [
+            nsec("host.dept.example.lan", "ns1.example.lan", vec![QueryType::A, QueryType::NSEC]),
+            nsec("example.lan", "host.dept.example.lan", vec![QueryType::NS, QueryType::SOA, QueryType::NSEC]),
+        ]);
+        let cut = zone.answer(&DnsQuestion::new("sub.example.lan".to_string(), QueryType::A));
+        assert_eq!(cut.authorities.len(), 1);
+        assert_eq!(chain_names(&zone)["sub.example.lan"], vec![QueryType::NS]);
+    }
+
+    #[test]
+    fn test_nsec3_denial() {
+        let zone = zone(Denial::Nsec3 { salt: vec![0xab, 0xcd], iterations: 1 });
+        let hash = |name: &str| nsec3_hash(name, &[0xab, 0xcd], 1);
+        let covers = |record: &DnsRecord, name: &str| match record {
+            DnsRecord::NSEC3 { domain, next, .. } => {
+                let owner = from_base32hex(domain.split('.').next().unwrap()).unwrap();
+                let hash = hash(name);
+                if owner < *next { owner < hash && hash < *next } else { owner < hash || hash < *next }
+            }
+            _ => false,
+        };
+        let matches = |record: &DnsRecord, name: &str| record.domain() == format!("{}.example.lan", to_base32hex(&hash(name)));
+
+        let nodata = zone.answer(&DnsQuestion::new("dept.example.lan".to_string(), QueryType::A));
+        assert_eq!(nodata.rescode, ResultCode::NOERROR);
+        assert!(matches(&nodata.authorities[1], "dept.example.lan"));
+        assert!(matches!(&nodata.authorities[1], DnsRecord::NSEC3 { types, .. } if types.is_empty()));
+
+        let nxdomain = zone.answer(&DnsQuestion::new("a.missing.dept.example.lan".to_string(), QueryType::A));
+        let proof = &nxdomain.authorities[1..];
+        assert!(matches(&proof[0], "dept.example.lan"));
+        assert!(proof.iter().any(|record| covers(record, "missing.dept.example.lan")));
+        assert!(proof.iter().any(|record| covers(record, "*.dept.example.lan")));
+    }
+
+    #[test]
+    fn test_signed_denial() {
+        let mut zone = zone(Denial::Nsec);
+        zone.signer = Some(ZoneSigner::new(SigningKey::from_bytes(&[1; 32])));
+        let types = chain_names(&zone);
+        assert_eq!(types["example.lan"], [QueryType::NS, QueryType::SOA, QueryType::RRSIG, QueryType::DNSKEY]);
+        assert_eq!(types["sub.example.lan"], [QueryType::NS]);
+
+        // Each RRset of the proof is followed by its signature, the SOA's too
+        let nodata = zone.signed_answer(&DnsQuestion::new("www.example.lan".to_string(), QueryType::MX));
+        let covered: Vec<(QueryType, Option<QueryType>)> = nodata.authorities.iter().map(|record| match record {
+            DnsRecord::RRSIG { type_covered, .. } => (QueryType::RRSIG, Some(*type_covered)),
+            record => (record.query_type(), None),
+        }).collect();
+        assert_eq!(covered, [
+            (QueryType::SOA, None),
+            (QueryType::RRSIG, Some(QueryType::SOA)),
+            (QueryType::NSEC, None),
+            (QueryType::RRSIG, Some(QueryType::NSEC)),
+        ]);
+        assert!(matches!(&nodata.authorities[2], DnsRecord::NSEC { types, .. } if types.contains(&QueryType::RRSIG)));
+
+        let keys = zone.signed_answer(&DnsQuestion::new("example.lan".to_string(), QueryType::DNSKEY));
+        assert!(matches!(keys.answers.as_slice(), [DnsRecord::DNSKEY { .. }, DnsRecord::RRSIG { .. }]));
+
+        // Referrals to a child zone aren't signed
+        let cut = zone.signed_answer(&DnsQuestion::new("www.sub.example.lan".to_string(), QueryType::A));
+        assert!(!cut.authorities.iter().any(|record| matches!(record, DnsRecord::RRSIG { .. })));
+    }
+}
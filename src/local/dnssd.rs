@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::server::mdns::{self, MDNS_ADDR, MDNS_PORT};
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+
+// Lists the service types on the link, for browsers that don't know what to look for (RFC 6763 9)
+pub const SERVICES_NAME: &str = "_services._dns-sd._udp.local";
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DnsSdConfig {
+    pub enabled: bool,
+    pub hostname: String,       // the host's name in .local, empty for the system's hostname
+    pub addresses: Vec<IpAddr>, // of that host, empty for the address of the default route
+    pub ttl: u32,
+    pub services: Vec<Service>,
+}
+
+impl Default for DnsSdConfig {
+    fn default() -> Self {
+        DnsSdConfig {
+            enabled: false,
+            hostname: String::new(),
+            addresses: Vec::new(),
+            ttl: 120,
+            services: Vec::new(),
+        }
+    }
+}
+
+// One advertised service, in the config and in the HTTP API
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Service {
+    pub name: String, // the instance name users see, e.g. "Office Printer"
+    #[serde(rename = "type")]
+    pub service_type: String, // e.g. "_ipp._tcp"
+    pub port: u16,
+    #[serde(default)]
+    pub txt: Vec<String>, // "key=value" pairs
+}
+
+impl Service {
+    pub fn instance(&self) -> String {
+        format!("{}.{}.local", self.name, self.service_type)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() || self.name.len() > 63 || self.name.contains('.') || !self.name.is_ascii() {
+            return Err(format!("Invalid service name {:?}, it has to be 1 to 63 ASCII characters without dots", self.name));
+        }
+        match self.service_type.split('.').collect::<Vec<_>>().as_slice() {
+            [service, "_tcp" | "_udp"] if service.len() > 1 && service.len() <= 16 && service.starts_with('_') => Ok(()),
+            _ => Err(format!("Invalid service type {:?}, expected e.g. \"_ipp._tcp\"", self.service_type)),
+        }
+    }
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+#[cfg(unix)]
+pub fn system_hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return None;
+    }
+    let len = name.iter().position(|&byte| byte == 0)?;
+    String::from_utf8(name[..len].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+pub fn system_hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+// The address multicast goes out from, connecting a UDP socket sends nothing
+pub fn default_address() -> io::Result<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((MDNS_ADDR, MDNS_PORT))?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/**
+DNS-based service discovery (RFC 6763) over multicast DNS: every service is advertised as a PTR
+from its type to the instance, an SRV naming this host and the port, and a TXT with its
+attributes, and the host's name in `.local` resolves to its addresses. Services can be added and
+removed while running, they are announced or withdrawn on the link at once. `mdns::start` answers
+the queries.
+*/
+#[derive(Clone, Debug)]
+pub struct DnsSd {
+    pub host: String, // e.g. "nas.local"
+    addrs: Vec<IpAddr>,
+    ttl: u32,
+    services: Arc<RwLock<Vec<Service>>>,
+    socket: Arc<OnceLock<UdpSocket>>, // set once the responder runs
+}
+
+impl DnsSd {
+    pub fn new(config: &DnsSdConfig) -> io::Result<DnsSd> {
+        let hostname = match config.hostname.is_empty() {
+            true => system_hostname().ok_or_else(|| io::Error::other("Can't get the hostname, set [dnssd] hostname"))?,
+            false => config.hostname.clone(),
+        };
+        let label = hostname.split('.').next().unwrap_or_default();
+        if label.is_empty() || !label.is_ascii() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid DNS-SD hostname {:?}", hostname)));
+        }
+        let addrs = match config.addresses.is_empty() {
+            true => vec![default_address()?],
+            false => config.addresses.clone(),
+        };
+        let dnssd = DnsSd {
+            host: format!("{}.local", label.to_ascii_lowercase()),
+            addrs,
+            ttl: config.ttl,
+            services: Arc::new(RwLock::new(Vec::new())),
+            socket: Arc::new(OnceLock::new()),
+        };
+        for service in &config.services {
+            dnssd.register(service.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        }
+        Ok(dnssd)
+    }
+
+    pub fn services(&self) -> Vec<Service> {
+        self.services.read().unwrap().clone()
+    }
+
+    pub fn register(&self, service: Service) -> Result<(), String> {
+        service.validate()?;
+        let records = self.service_records(&service);
+        {
+            let mut services = self.services.write().unwrap();
+            if services.iter().any(|other| same_name(&other.instance(), &service.instance())) {
+                return Err(format!("{} is already registered", service.instance()));
+            }
+            info!("Advertising {} on port {}", service.instance(), service.port);
+            services.push(service);
+        }
+        self.announce(records);
+        Ok(())
+    }
+
+    // Withdrawn with a goodbye, the records again with a TTL of 0 (RFC 6762 10.1)
+    pub fn unregister(&self, name: &str, service_type: &str) -> Option<Service> {
+        let service = {
+            let mut services = self.services.write().unwrap();
+            let i = services.iter().position(|service| service.name == name && same_name(&service.service_type, service_type))?;
+            services.remove(i)
+        };
+        info!("No longer advertising {}", service.instance());
+        let mut records = self.service_records(&service);
+        records.iter_mut().for_each(|record| record.set_ttl(0));
+        self.announce(records);
+        Some(service)
+    }
+
+    fn host_records(&self) -> Vec<DnsRecord> {
+        self.addrs.iter().map(|addr| match addr {
+            IpAddr::V4(addr) => DnsRecord::a(&self.host, *addr, self.ttl),
+            IpAddr::V6(addr) => DnsRecord::aaaa(&self.host, *addr, self.ttl),
+        }).collect()
+    }
+
+    fn service_records(&self, service: &Service) -> Vec<DnsRecord> {
+        let (service_type, instance) = (format!("{}.local", service.service_type), service.instance());
+        // A TXT record is required even without attributes, then it holds one empty string (RFC 6763 6.1)
+        let mut txt = DnsRecord::txt(&instance, "", self.ttl);
+        if let DnsRecord::TXT { data, .. } = &mut txt {
+            if !service.txt.is_empty() {
+                *data = service.txt.iter().map(|pair| pair.as_bytes().to_vec()).collect();
+            }
+        }
+        vec![
+            DnsRecord::ptr(SERVICES_NAME, &service_type, self.ttl),
+            DnsRecord::ptr(&service_type, &instance, self.ttl),
+            DnsRecord::srv(&instance, 0, 0, service.port, &self.host, self.ttl),
+            txt,
+        ]
+    }
+
+    // Everything advertised, the host's addresses first
+    pub fn records(&self) -> Vec<DnsRecord> {
+        let mut records = self.host_records();
+        for service in self.services.read().unwrap().iter() {
+            for record in self.service_records(service) {
+                if !records.contains(&record) {
+                    records.push(record);
+                }
+            }
+        }
+        records
+    }
+
+    /**
+    The answers to a question and the additional records a querier will ask for next (RFC 6763
+    12): the SRV and TXT of the instances a PTR names, and the addresses of the hosts an SRV
+    names. None when the name isn't one of ours.
+    */
+    pub fn answer(&self, question: &DnsQuestion) -> Option<(Vec<DnsRecord>, Vec<DnsRecord>)> {
+        let records = self.records();
+        let answers: Vec<DnsRecord> = records.iter()
+            .filter(|record| same_name(record.domain(), &question.name))
+            .filter(|record| question.qtype == QueryType::ANY || record.query_type() == question.qtype)
+            .cloned()
+            .collect();
+        if answers.is_empty() {
+            return None;
+        }
+
+        let mut additional: Vec<DnsRecord> = Vec::new();
+        let mut targets: Vec<String> = answers.iter().filter_map(target).collect();
+        while let Some(name) = targets.pop() {
+            for record in records.iter().filter(|record| same_name(record.domain(), &name)) {
+                if matches!(record, DnsRecord::PTR { .. }) || answers.contains(record) || additional.contains(record) {
+                    continue;
+                }
+                targets.extend(target(record));
+                additional.push(record.clone());
+            }
+        }
+        Some((answers, additional))
+    }
+
+    pub fn attach(&self, socket: UdpSocket) {
+        let _ = self.socket.set(socket);
+    }
+
+    pub fn announce(&self, records: Vec<DnsRecord>) {
+        let Some(socket) = self.socket.get() else {
+            return;
+        };
+        if let Err(e) = mdns::send(socket, &mdns::multicast_response(records, Vec::new()), (MDNS_ADDR, MDNS_PORT).into()) {
+            error!("Failed to announce services: {}", e);
+        }
+    }
+}
+
+// The name a record points at that the querier will want the records of
+fn target(record: &DnsRecord) -> Option<String> {
+    match record {
+        DnsRecord::PTR { host, .. } => Some(host.clone()),
+        DnsRecord::SRV { target, .. } => Some(target.clone()),
+        _ => None,
+    }
+}
+
+// A service instance found on the link
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Browsed {
+    pub instance: String,
+    pub host: String,
+    pub port: u16,
+    pub addrs: Vec<IpAddr>,
+    pub txt: Vec<String>,
+}
+
+// The instances the PTR records for `types` name, with what the other records say about them
+fn collect(types: &[String], records: &[DnsRecord]) -> Vec<Browsed> {
+    let mut found: HashMap<String, Browsed> = HashMap::new();
+    for record in records {
+        let DnsRecord::PTR { domain, host, ttl, .. } = record else {
+            continue;
+        };
+        if *ttl == 0 || !types.iter().any(|service_type| same_name(service_type, domain)) {
+            continue;
+        }
+        let instance = host.trim_end_matches('.').to_string();
+        found.entry(instance.to_ascii_lowercase()).or_insert_with(|| Browsed { instance, host: String::new(), port: 0, addrs: Vec::new(), txt: Vec::new() });
+    }
+    for browsed in found.values_mut() {
+        for record in records.iter().filter(|record| same_name(record.domain(), &browsed.instance)) {
+            match record {
+                DnsRecord::SRV { port, target, .. } => (browsed.host, browsed.port) = (target.trim_end_matches('.').to_string(), *port),
+                DnsRecord::TXT { data, .. } => browsed.txt = data.iter().filter(|pair| !pair.is_empty()).map(|pair| String::from_utf8_lossy(pair).into_owned()).collect(),
+                _ => {}
+            }
+        }
+        for record in records.iter().filter(|record| !browsed.host.is_empty() && same_name(record.domain(), &browsed.host)) {
+            let addr = match record {
+                DnsRecord::A { addr, .. } => IpAddr::V4(*addr),
+                DnsRecord::AAAA { addr, .. } => IpAddr::V6(*addr),
+                _ => continue,
+            };
+            if !browsed.addrs.contains(&addr) {
+                browsed.addrs.push(addr);
+            }
+        }
+    }
+    let mut found: Vec<Browsed> = found.into_values().collect();
+    found.sort_by(|a, b| a.instance.cmp(&b.instance));
+    found
+}
+
+/**
+Looks for the instances of a service type on the link, e.g. "_ipp._tcp", or of every type that is
+advertised when none is given. Waits `wait` for the answers to each round of queries: the types,
+their instances, and then the SRV, TXT and addresses the responders didn't already include.
+*/
+pub fn browse(service_type: Option<&str>, wait: Duration) -> io::Result<Vec<Browsed>> {
+    let types: Vec<String> = match service_type {
+        Some(service_type) => vec![format!("{}.local", service_type.trim_end_matches('.').trim_end_matches(".local"))],
+        None => {
+            let records = mdns::query(&[DnsQuestion::new(SERVICES_NAME.to_string(), QueryType::PTR)], wait)?;
+            let mut types: Vec<String> = records.iter().filter_map(|record| match record {
+                DnsRecord::PTR { domain, host, .. } if same_name(domain, SERVICES_NAME) => Some(host.clone()),
+                _ => None,
+            }).collect();
+            types.sort();
+            types.dedup();
+            types
+        }
+    };
+    if types.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let questions: Vec<DnsQuestion> = types.iter().map(|service_type| DnsQuestion::new(service_type.clone(), QueryType::PTR)).collect();
+    let mut records = mdns::query(&questions, wait)?;
+    let missing: Vec<DnsQuestion> = collect(&types, &records).iter()
+        .filter(|browsed| browsed.host.is_empty())
+        .flat_map(|browsed| [QueryType::SRV, QueryType::TXT].map(|qtype| DnsQuestion::new(browsed.instance.clone(), qtype)))
+        .collect();
+    if !missing.is_empty() {
+        records.extend(mdns::query(&missing, wait)?);
+    }
+    let missing: Vec<DnsQuestion> = collect(&types, &records).iter()
+        .filter(|browsed| !browsed.host.is_empty() && browsed.addrs.is_empty())
+        .map(|browsed| DnsQuestion::new(browsed.host.clone(), QueryType::A))
+        .collect();
+    if !missing.is_empty() {
+        records.extend(mdns::query(&missing, wait)?);
+    }
+    Ok(collect(&types, &records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer() -> Service {
+        Service { name: "Office Printer".to_string(), service_type: "_ipp._tcp".to_string(), port: 631, txt: vec!["rp=printers/office".to_string()] }
+    }
+
+    fn dnssd() -> DnsSd {
+        let config = DnsSdConfig { hostname: "nas".to_string(), addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))], services: vec![printer()], ..DnsSdConfig::default() };
+        DnsSd::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_browse_answers() {
+        let dnssd = dnssd();
+        let (answers, additional) = dnssd.answer(&DnsQuestion::new("_ipp._tcp.local".to_string(), QueryType::PTR)).unwrap();
+        assert_eq!(answers, vec![DnsRecord::ptr("_ipp._tcp.local", "Office Printer._ipp._tcp.local", 120)]);
+        assert_eq!(additional.len(), 3);
+        assert!(additional.contains(&DnsRecord::srv("Office Printer._ipp._tcp.local", 0, 0, 631, "nas.local", 120)));
+        assert!(additional.contains(&DnsRecord::a("nas.local", Ipv4Addr::new(192, 168, 1, 20), 120)));
+        assert!(dnssd.answer(&DnsQuestion::new("_http._tcp.local".to_string(), QueryType::PTR)).is_none());
+
+        // What a browser makes of the same records
+        let records: Vec<DnsRecord> = answers.into_iter().chain(additional).collect();
+        let found = collect(&["_ipp._tcp.local".to_string()], &records);
+        assert_eq!(found, vec![Browsed {
+            instance: "Office Printer._ipp._tcp.local".to_string(),
+            host: "nas.local".to_string(),
+            port: 631,
+            addrs: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))],
+            txt: vec!["rp=printers/office".to_string()],
+        }]);
+    }
+
+    #[test]
+    fn test_register_and_unregister() {
+        let dnssd = dnssd();
+        assert!(dnssd.register(printer()).unwrap_err().contains("already registered"));
+        let bad = Service { service_type: "ipp".to_string(), ..printer() };
+        assert!(dnssd.register(bad).is_err());
+
+        let web = Service { name: "NAS".to_string(), service_type: "_http._tcp".to_string(), port: 80, txt: Vec::new() };
+        dnssd.register(web.clone()).unwrap();
+        let (answers, _) = dnssd.answer(&DnsQuestion::new(SERVICES_NAME.to_string(), QueryType::PTR)).unwrap();
+        assert_eq!(answers.len(), 2);
+        assert_eq!(dnssd.unregister("NAS", "_http._tcp"), Some(web));
+        assert!(dnssd.answer(&DnsQuestion::new("_http._tcp.local".to_string(), QueryType::PTR)).is_none());
+    }
+}
@@ -0,0 +1,192 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::local::pool::{Pool, Pools};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckKind {
+    #[default]
+    Tcp,
+    Http,
+    Icmp,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    pub kind: CheckKind,
+    pub port: u16,     // tcp and http
+    pub path: String,  // http, any 2xx or 3xx status is healthy
+    pub interval: u64, // seconds between checks
+    pub timeout: u64,  // seconds
+    pub fall: u32,     // failed checks in a row before a member is taken out
+    pub rise: u32,     // good checks in a row before it is put back
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        HealthCheckConfig {
+            kind: CheckKind::Tcp,
+            port: 80,
+            path: "/".to_string(),
+            interval: 10,
+            timeout: 2,
+            fall: 2,
+            rise: 2,
+        }
+    }
+}
+
+fn http_check(addr: SocketAddr, host: &str, path: &str, timeout: Duration) -> Result<(), String> {
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line).map_err(|e| e.to_string())?;
+    match status_line.split_whitespace().nth(1).and_then(|status| status.parse::<u16>().ok()) {
+        Some(status) if (200..400).contains(&status) => Ok(()),
+        Some(status) => Err(format!("HTTP status {}", status)),
+        None => Err("Not an HTTP response".to_string()),
+    }
+}
+
+// One echo request, waiting `timeout` for the reply. Every system's ping spells that differently.
+#[cfg(windows)]
+fn ping_command(addr: IpAddr, timeout: Duration) -> Command {
+    let mut command = Command::new("ping");
+    command.args(["-n", "1", "-w", &timeout.as_millis().to_string(), &addr.to_string()]);
+    command
+}
+
+// The BSDs take the wait in milliseconds and have a separate ping6
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly"))]
+fn ping_command(addr: IpAddr, timeout: Duration) -> Command {
+    let mut command = Command::new(if addr.is_ipv6() { "ping6" } else { "ping" });
+    command.args(["-c", "1", "-W", &timeout.as_millis().to_string(), &addr.to_string()]);
+    command
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly")))]
+fn ping_command(addr: IpAddr, timeout: Duration) -> Command {
+    let mut command = Command::new("ping");
+    command.args(["-c", "1", "-W", &timeout.as_secs().max(1).to_string(), &addr.to_string()]);
+    command
+}
+
+// Raw ICMP sockets need privileges, the system ping binary already has them
+fn icmp_check(addr: IpAddr, timeout: Duration) -> Result<(), String> {
+    let status = ping_command(addr, timeout)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| format!("Failed to run ping: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err("No ICMP echo reply".to_string())
+    }
+}
+
+pub fn check(config: &HealthCheckConfig, host: &str, addr: IpAddr) -> Result<(), String> {
+    let timeout = Duration::from_secs(config.timeout.max(1));
+    let target = SocketAddr::new(addr, config.port);
+    match config.kind {
+        CheckKind::Tcp => TcpStream::connect_timeout(&target, timeout).map(|_| ()).map_err(|e| e.to_string()),
+        CheckKind::Http => http_check(target, host, &config.path, timeout),
+        CheckKind::Icmp => icmp_check(addr, timeout),
+    }
+}
+
+fn check_pool(pool: &Pool, config: &HealthCheckConfig) {
+    for member in pool.members.iter().filter(|member| !member.disabled) {
+        pool.record_check(member, check(config, &pool.name, member.addr), config.fall, config.rise);
+    }
+}
+
+// One thread per pool with a `check` section, members that fail are left out of answers until they recover
+pub fn start(pools: &Pools) {
+    for pool in pools.pools.values() {
+        let Some(config) = pool.check.clone() else {
+            continue;
+        };
+        let pool = Arc::clone(pool);
+        thread::spawn(move || loop {
+            check_pool(&pool, &config);
+            thread::sleep(Duration::from_secs(config.interval.max(1)));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    fn serve_once(listener: TcpListener, response: &'static str) -> thread::JoinHandle<String> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0u8; 512];
+            while !request.ends_with(b"\r\n\r\n") {
+                let len = stream.read(&mut chunk).unwrap();
+                // The client gave up before finishing its request
+                if len == 0 {
+                    break;
+                }
+                request.extend_from_slice(&chunk[..len]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        })
+    }
+
+    #[test]
+    fn test_ping_command() {
+        let command = ping_command(IpAddr::V4(Ipv4Addr::LOCALHOST), Duration::from_secs(2));
+        let args: Vec<String> = command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        #[cfg(windows)]
+        assert_eq!(args, ["-n", "1", "-w", "2000", "127.0.0.1"]);
+        #[cfg(target_os = "macos")]
+        assert_eq!(args, ["-c", "1", "-W", "2000", "127.0.0.1"]);
+        #[cfg(target_os = "linux")]
+        assert_eq!(args, ["-c", "1", "-W", "2", "127.0.0.1"]);
+    }
+
+    #[test]
+    fn test_tcp_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = HealthCheckConfig { port, ..Default::default() };
+        assert!(check(&config, "app.lan", IpAddr::V4(Ipv4Addr::LOCALHOST)).is_ok());
+
+        drop(listener);
+        assert!(check(&config, "app.lan", IpAddr::V4(Ipv4Addr::LOCALHOST)).is_err());
+    }
+
+    #[test]
+    fn test_http_check() {
+        let config = |port| HealthCheckConfig { kind: CheckKind::Http, port, path: "/healthz".to_string(), ..Default::default() };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = serve_once(listener, "HTTP/1.1 204 No Content\r\n\r\n");
+        assert!(check(&config(port), "app.lan", IpAddr::V4(Ipv4Addr::LOCALHOST)).is_ok());
+        assert!(server.join().unwrap().starts_with("GET /healthz HTTP/1.1\r\nHost: app.lan\r\n"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = serve_once(listener, "HTTP/1.1 503 Service Unavailable\r\n\r\n");
+        assert_eq!(check(&config(port), "app.lan", IpAddr::V4(Ipv4Addr::LOCALHOST)), Err("HTTP status 503".to_string()));
+        server.join().unwrap();
+    }
+}
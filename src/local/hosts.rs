@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use std::{fs, thread};
+
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::utils::dns_class::DnsClass;
+use crate::utils::memory::{map_heap_size, HeapSize, MemoryUsage};
+use crate::utils::name::parse_reverse_name;
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+
+pub const SYSTEM_HOSTS: &str = "/etc/hosts";
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HostsConfig {
+    pub enabled: bool,
+    pub system: bool,         // include /etc/hosts
+    pub paths: Vec<String>,   // additional hosts files, later files add to earlier ones
+    pub ttl: u32,
+    pub reload_interval: u64, // seconds between checks for modified files
+}
+
+impl Default for HostsConfig {
+    fn default() -> Self {
+        HostsConfig {
+            enabled: false,
+            system: true,
+            paths: Vec::new(),
+            ttl: 0,
+            reload_interval: 5,
+        }
+    }
+}
+
+impl HostsConfig {
+    pub fn files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        if self.system {
+            files.push(SYSTEM_HOSTS.to_string());
+        }
+        files.extend(self.paths.iter().cloned());
+        files
+    }
+}
+
+/**
+Names and addresses from one or more hosts files. Lookups are case-insensitive; for PTR
+queries the first name listed for an address wins, like the C library does.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HostsTable {
+    names: HashMap<String, Vec<IpAddr>>,
+    addrs: HashMap<IpAddr, String>,
+}
+
+impl HostsTable {
+    pub fn new() -> HostsTable {
+        HostsTable::default()
+    }
+
+    pub fn load(files: &[String]) -> HostsTable {
+        let mut table = HostsTable::new();
+        for file in files {
+            match fs::read_to_string(file) {
+                Ok(contents) => table.parse(&contents),
+                Err(e) => warn!("Failed to read hosts file {}: {}", file, e),
+            }
+        }
+        table
+    }
+
+    pub fn parse(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+
+            let addr = match fields.next().map(str::parse::<IpAddr>) {
+                Some(Ok(addr)) => addr,
+                _ => continue,
+            };
+
+            for name in fields {
+                let name = name.trim_end_matches('.').to_ascii_lowercase();
+                let addrs = self.names.entry(name.clone()).or_default();
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+                self.addrs.entry(addr).or_insert(name);
+            }
+        }
+    }
+
+    // The lines `parse` skips, each with its line number
+    pub fn validate(contents: &str) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+            match fields.next().map(|addr| (addr, addr.parse::<IpAddr>())) {
+                None => {}
+                Some((addr, Err(_))) => errors.push(format!("Line {}: invalid address {:?}", number + 1, addr)),
+                Some((addr, Ok(_))) if fields.next().is_none() => errors.push(format!("Line {}: no names for {}", number + 1, addr)),
+                Some(_) => {}
+            }
+        }
+        errors
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn memory(&self) -> MemoryUsage {
+        let bytes = map_heap_size(&self.names, HeapSize::heap_size) + map_heap_size(&self.addrs, HeapSize::heap_size);
+        MemoryUsage { entries: self.names.len(), bytes }
+    }
+
+    // None means the hosts files know nothing about the question and it should be resolved
+    // normally. A name that is listed but has no address of the asked family gets an empty answer.
+    pub fn answer(&self, question: &DnsQuestion, ttl: u32) -> Option<Vec<DnsRecord>> {
+        let name = question.name.trim_end_matches('.').to_ascii_lowercase();
+
+        match question.qtype {
+            QueryType::A | QueryType::AAAA => {
+                let addrs = self.names.get(&name)?;
+                Some(addrs.iter().filter_map(|addr| match (addr, question.qtype) {
+                    (IpAddr::V4(addr), QueryType::A) => Some(DnsRecord::A { domain: question.name.clone(), addr: *addr, ttl, class: DnsClass::IN }),
+                    (IpAddr::V6(addr), QueryType::AAAA) => Some(DnsRecord::AAAA { domain: question.name.clone(), addr: *addr, ttl, class: DnsClass::IN }),
+                    _ => None,
+                }).collect())
+            }
+            QueryType::PTR => {
+                let host = self.addrs.get(&parse_reverse_name(&name)?)?;
+                Some(vec![DnsRecord::PTR { domain: question.name.clone(), host: host.clone(), ttl, class: DnsClass::IN }])
+            }
+            _ => None,
+        }
+    }
+}
+
+// Hosts table shared with a thread that reloads it whenever one of the files changes
+#[derive(Clone)]
+pub struct LocalHosts {
+    pub table: Arc<RwLock<HostsTable>>,
+    ttl: u32,
+}
+
+impl LocalHosts {
+    // A fixed table, nothing reloads it
+    pub fn new(table: HostsTable, ttl: u32) -> LocalHosts {
+        LocalHosts { table: Arc::new(RwLock::new(table)), ttl }
+    }
+
+    pub fn start(config: &HostsConfig) -> LocalHosts {
+        let files = config.files();
+        let hosts = LocalHosts::new(HostsTable::load(&files), config.ttl);
+        info!("Loaded {} names from hosts files {:?}", hosts.table.read().unwrap().len(), files);
+
+        let table_clone = Arc::clone(&hosts.table);
+        let reload_interval = Duration::from_secs(config.reload_interval.max(1));
+        thread::spawn(move || {
+            let mut last_modified = modified_times(&files);
+            loop {
+                thread::sleep(reload_interval);
+                let modified = modified_times(&files);
+                if modified != last_modified {
+                    let reloaded = HostsTable::load(&files);
+                    info!("Hosts files changed, reloaded {} names", reloaded.len());
+                    *table_clone.write().unwrap() = reloaded;
+                    last_modified = modified;
+                }
+            }
+        });
+
+        hosts
+    }
+
+    pub fn answer(&self, question: &DnsQuestion) -> Option<Vec<DnsRecord>> {
+        self.table.read().unwrap().answer(question, self.ttl)
+    }
+
+    // Every address with the name its PTR answer gives
+    pub fn addresses(&self) -> Vec<(IpAddr, String)> {
+        self.table.read().unwrap().addrs.iter().map(|(addr, name)| (*addr, name.clone())).collect()
+    }
+}
+
+fn modified_times(files: &[String]) -> Vec<Option<SystemTime>> {
+    files.iter()
+        .map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const HOSTS: &str = "
+# comment line
+127.0.0.1   localhost
+::1         localhost ip6-localhost
+192.168.1.10 NAS.lan nas   # trailing comment
+192.168.1.11 printer.lan.
+not-an-ip   ignored.lan
+";
+
+    fn create_table() -> HostsTable {
+        let mut table = HostsTable::new();
+        table.parse(HOSTS);
+        table
+    }
+
+    #[test]
+    fn test_parse() {
+        let table = create_table();
+        assert_eq!(table.len(), 5);
+        assert!(table.answer(&DnsQuestion::new("ignored.lan".to_string(), QueryType::A), 0).is_none());
+        assert_eq!(HostsTable::validate(&format!("{}10.0.0.1 # no names\n", HOSTS)), vec![
+            "Line 7: invalid address \"not-an-ip\"".to_string(),
+            "Line 8: no names for 10.0.0.1".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_answer_a_and_aaaa() {
+        let table = create_table();
+
+        let answers = table.answer(&DnsQuestion::new("nas.LAN".to_string(), QueryType::A), 0).unwrap();
+        assert_eq!(answers, vec![DnsRecord::A { domain: "nas.LAN".to_string(), addr: Ipv4Addr::new(192, 168, 1, 10), ttl: 0, class: DnsClass::IN }]);
+
+        let answers = table.answer(&DnsQuestion::new("localhost".to_string(), QueryType::AAAA), 0).unwrap();
+        assert_eq!(answers, vec![DnsRecord::AAAA { domain: "localhost".to_string(), addr: Ipv6Addr::LOCALHOST, ttl: 0, class: DnsClass::IN }]);
+
+        // Known name, but no address of that family
+        let answers = table.answer(&DnsQuestion::new("printer.lan".to_string(), QueryType::AAAA), 0).unwrap();
+        assert!(answers.is_empty());
+
+        assert!(table.answer(&DnsQuestion::new("example.com".to_string(), QueryType::A), 0).is_none());
+        assert!(table.answer(&DnsQuestion::new("nas.lan".to_string(), QueryType::MX), 0).is_none());
+    }
+
+    #[test]
+    fn test_answer_ptr() {
+        let table = create_table();
+
+        let answers = table.answer(&DnsQuestion::new("10.1.168.192.in-addr.arpa".to_string(), QueryType::PTR), 0).unwrap();
+        assert_eq!(answers, vec![DnsRecord::PTR {
+            domain: "10.1.168.192.in-addr.arpa".to_string(),
+            host: "nas.lan".to_string(),
+            ttl: 0,
+            class: DnsClass::IN,
+        }]);
+        assert!(table.answer(&DnsQuestion::new("99.1.168.192.in-addr.arpa".to_string(), QueryType::PTR), 0).is_none());
+    }
+}
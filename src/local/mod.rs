@@ -0,0 +1,12 @@
+pub mod hosts;
+pub mod chaos;
+pub mod denial;
+pub mod signer;
+pub mod pool;
+pub mod health;
+pub mod zone;
+pub mod zonemd;
+pub mod reverse;
+pub mod transfer;
+pub mod catalog;
+pub mod dnssd;
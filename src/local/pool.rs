@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::local::health::HealthCheckConfig;
+use crate::utils::dns_class::DnsClass;
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::random::random;
+use crate::utils::record::DnsRecord;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PoolMemberConfig {
+    pub addr: String,
+    pub weight: u32,   // share of the answers relative to the other members of the same priority
+    pub priority: u32, // lower first, higher priorities only take over once every lower member is down
+    pub down: bool,    // taken out of rotation by hand
+}
+
+impl Default for PoolMemberConfig {
+    fn default() -> Self {
+        PoolMemberConfig {
+            addr: String::new(),
+            weight: 1,
+            priority: 0,
+            down: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    pub name: String,
+    pub ttl: u32,
+    pub members: Vec<PoolMemberConfig>,
+    pub check: Option<HealthCheckConfig>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            name: String::new(),
+            ttl: 30,
+            members: Vec::new(),
+            check: None,
+        }
+    }
+}
+
+// What the health checks know about a member, see `record`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MemberHealth {
+    pub up: bool,
+    pub failures: u32,  // consecutive
+    pub successes: u32, // consecutive
+    pub last_error: Option<String>,
+}
+
+impl Default for MemberHealth {
+    fn default() -> Self {
+        MemberHealth { up: true, failures: 0, successes: 0, last_error: None }
+    }
+}
+
+impl MemberHealth {
+    // Goes down after `fall` failed checks in a row and back up after `rise` good ones, so one
+    // lost packet doesn't take a member out of rotation
+    pub fn record(&mut self, result: Result<(), String>, fall: u32, rise: u32) -> bool {
+        let was_up = self.up;
+        match result {
+            Ok(()) => {
+                self.successes += 1;
+                self.failures = 0;
+                if self.successes >= rise {
+                    self.up = true;
+                }
+            }
+            Err(e) => {
+                self.failures += 1;
+                self.successes = 0;
+                self.last_error = Some(e);
+                if self.failures >= fall {
+                    self.up = false;
+                }
+            }
+        }
+        self.up != was_up
+    }
+}
+
+#[derive(Debug)]
+pub struct PoolMember {
+    pub addr: IpAddr,
+    pub weight: u32,
+    pub priority: u32,
+    pub disabled: bool, // marked down in the config, health checks don't bring it back
+    pub health: Mutex<MemberHealth>,
+}
+
+impl PoolMember {
+    pub fn is_up(&self) -> bool {
+        !self.disabled && self.health.lock().unwrap().up
+    }
+}
+
+/**
+A local name answered with one address out of a pool: the members of the best priority that
+are up, picked at random in proportion to their weights. If every member of the asked family is
+down the pool fails open and picks among all of them that aren't disabled, an answer beats none.
+*/
+#[derive(Debug)]
+pub struct Pool {
+    pub name: String,
+    pub ttl: u32,
+    pub members: Vec<PoolMember>,
+    pub check: Option<HealthCheckConfig>,
+}
+
+impl Pool {
+    pub fn new(config: &PoolConfig) -> Result<Pool, String> {
+        let members = config.members.iter().map(|member| {
+            let addr = member.addr.parse().map_err(|_| format!("Invalid address {:?} in pool {}", member.addr, config.name))?;
+            Ok(PoolMember {
+                addr,
+                weight: member.weight,
+                priority: member.priority,
+                disabled: member.down,
+                health: Mutex::new(MemberHealth::default()),
+            })
+        }).collect::<Result<_, String>>()?;
+        Ok(Pool {
+            name: config.name.trim_end_matches('.').to_ascii_lowercase(),
+            ttl: config.ttl,
+            members,
+            check: config.check.clone(),
+        })
+    }
+
+    pub fn set_up(&self, addr: IpAddr, up: bool) {
+        for member in self.members.iter().filter(|member| member.addr == addr) {
+            member.health.lock().unwrap().up = up;
+        }
+    }
+
+    pub fn record_check(&self, member: &PoolMember, result: Result<(), String>, fall: u32, rise: u32) {
+        let mut health = member.health.lock().unwrap();
+        if health.record(result, fall, rise) {
+            match &health.last_error {
+                Some(e) if !health.up => warn!("Pool {} member {} is down: {}", self.name, member.addr, e),
+                _ => info!("Pool {} member {} is up again", self.name, member.addr),
+            }
+        }
+    }
+
+    // `pick` is a random number, passed in so tests can choose
+    pub fn select(&self, ipv6: bool, pick: u64) -> Option<IpAddr> {
+        let family: Vec<&PoolMember> = self.members.iter().filter(|member| member.addr.is_ipv6() == ipv6 && !member.disabled).collect();
+        let mut candidates: Vec<&PoolMember> = family.iter().copied().filter(|member| member.is_up()).collect();
+        if candidates.is_empty() && !family.is_empty() {
+            warn!("Every member of pool {} is down, answering from all of them", self.name);
+            candidates = family;
+        }
+
+        let priority = candidates.iter().map(|member| member.priority).min()?;
+        candidates.retain(|member| member.priority == priority);
+        let total: u64 = candidates.iter().map(|member| member.weight as u64).sum();
+        if total == 0 {
+            return Some(candidates[(pick % candidates.len() as u64) as usize].addr);
+        }
+
+        let mut pick = pick % total;
+        for member in &candidates {
+            if pick < member.weight as u64 {
+                return Some(member.addr);
+            }
+            pick -= member.weight as u64;
+        }
+        None
+    }
+}
+
+// All pools by name, shared with whatever marks members up and down
+#[derive(Clone, Debug, Default)]
+pub struct Pools {
+    pub pools: HashMap<String, Arc<Pool>>,
+}
+
+impl Pools {
+    pub fn new(configs: &[PoolConfig]) -> Result<Pools, String> {
+        let mut pools = HashMap::new();
+        for config in configs {
+            let pool = Pool::new(config)?;
+            pools.insert(pool.name.clone(), Arc::new(pool));
+        }
+        Ok(Pools { pools })
+    }
+
+    // Like the hosts files: None for names that aren't pools, an empty answer for other types
+    pub fn answer(&self, question: &DnsQuestion) -> Option<Vec<DnsRecord>> {
+        let pool = self.pools.get(&question.name.trim_end_matches('.').to_ascii_lowercase())?;
+        let domain = question.name.clone();
+        let record = match (question.qtype, pool.select(question.qtype == QueryType::AAAA, random())) {
+            (QueryType::A, Some(IpAddr::V4(addr))) => Some(DnsRecord::A { domain, addr, ttl: pool.ttl, class: DnsClass::IN }),
+            (QueryType::AAAA, Some(IpAddr::V6(addr))) => Some(DnsRecord::AAAA { domain, addr, ttl: pool.ttl, class: DnsClass::IN }),
+            _ => None,
+        };
+        Some(record.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(addr: &str, weight: u32, priority: u32) -> PoolMemberConfig {
+        PoolMemberConfig { addr: addr.to_string(), weight, priority, down: false }
+    }
+
+    fn create_pool() -> Pool {
+        Pool::new(&PoolConfig {
+            name: "App.Example.lan.".to_string(),
+            ttl: 30,
+            members: vec![
+                member("192.0.2.1", 3, 0),
+                member("192.0.2.2", 1, 0),
+                member("192.0.2.9", 1, 1),
+                member("2001:db8::1", 1, 0),
+                PoolMemberConfig { down: true, ..member("192.0.2.99", 100, 0) },
+            ],
+            check: None,
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_weighted_selection() {
+        let pool = create_pool();
+        let picks: Vec<IpAddr> = (0..4).map(|pick| pool.select(false, pick).unwrap()).collect();
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(picks.iter().filter(|addr| **addr == first).count(), 3);
+        assert_eq!(picks[3], "192.0.2.2".parse::<IpAddr>().unwrap());
+        assert_eq!(pool.select(true, 7), Some("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_failover_and_fail_open() {
+        let pool = create_pool();
+        pool.set_up("192.0.2.1".parse().unwrap(), false);
+        assert!((0..8).all(|pick| pool.select(false, pick) == Some("192.0.2.2".parse().unwrap())));
+
+        pool.set_up("192.0.2.2".parse().unwrap(), false);
+        assert_eq!(pool.select(false, 0), Some("192.0.2.9".parse().unwrap()));
+
+        pool.set_up("192.0.2.9".parse().unwrap(), false);
+        assert!(pool.select(false, 0).is_some());
+        assert!((0..8).all(|pick| pool.select(false, pick) != Some("192.0.2.99".parse().unwrap())));
+    }
+
+    #[test]
+    fn test_health_rise_and_fall() {
+        let mut health = MemberHealth::default();
+        assert!(!health.record(Err("timeout".to_string()), 2, 2));
+        assert!(health.record(Err("timeout".to_string()), 2, 2));
+        assert!(!health.up);
+        assert_eq!(health.last_error.as_deref(), Some("timeout"));
+
+        assert!(!health.record(Ok(()), 2, 2));
+        assert!(health.record(Ok(()), 2, 2));
+        assert!(health.up);
+    }
+
+    #[test]
+    fn test_answer() {
+        let pools = Pools { pools: HashMap::from([("app.example.lan".to_string(), Arc::new(create_pool()))]) };
+        let answer = pools.answer(&DnsQuestion::new("APP.example.lan".to_string(), QueryType::A)).unwrap();
+        assert_eq!(answer.len(), 1);
+        assert_eq!(answer[0].domain(), "APP.example.lan");
+        assert_eq!(pools.answer(&DnsQuestion::new("app.example.lan".to_string(), QueryType::MX)), Some(vec![]));
+        assert!(pools.answer(&DnsQuestion::new("other.lan".to_string(), QueryType::A)).is_none());
+        assert!(Pools::new(&[PoolConfig { members: vec![member("nope", 1, 0)], ..Default::default() }]).is_err());
+    }
+}
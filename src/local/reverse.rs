@@ -0,0 +1,155 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::IpAddr;
+
+use serde::Deserialize;
+
+use crate::local::denial::Denial;
+use crate::local::hosts::LocalHosts;
+use crate::local::zone::{Zone, ZoneAnswer, Zones};
+use crate::utils::dns_class::DnsClass;
+use crate::utils::name::{is_subdomain, reverse_name};
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+use crate::utils::subnet::Subnet;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ReverseZonesConfig {
+    pub enabled: bool,
+    pub networks: Vec<String>, // "192.168.1.0/24" or "fd00::/64", IPv4 prefixes in whole octets and IPv6 ones in whole nibbles
+    pub ttl: u32,
+}
+
+impl Default for ReverseZonesConfig {
+    fn default() -> Self {
+        ReverseZonesConfig {
+            enabled: false,
+            networks: Vec::new(),
+            ttl: 300,
+        }
+    }
+}
+
+// The in-addr.arpa or ip6.arpa zone holding the addresses of `subnet`, "1.168.192.in-addr.arpa" for 192.168.1.0/24
+fn origin(subnet: Subnet) -> Result<String, String> {
+    let (bits, label_bits, label) = if subnet.addr.is_ipv4() { (32, 8, "octet") } else { (128, 4, "nibble") };
+    if u32::from(subnet.prefix) % label_bits != 0 {
+        return Err(format!("{}/{} doesn't end on a whole {}", subnet.addr, subnet.prefix, label));
+    }
+    let skip = ((bits - u32::from(subnet.prefix)) / label_bits) as usize;
+    Ok(reverse_name(subnet.network().addr).splitn(skip + 1, '.').last().unwrap_or_default().to_string())
+}
+
+/**
+Reverse zones generated from the local data: for every address the hosts files and the local
+zones give a name, the matching PTR record in the in-addr.arpa or ip6.arpa zone of the network
+it is in. The zones are built again from the data as it is at each query, so edits to a local
+zone show up at once and nobody maintains a second zone by hand. They are answered
+authoritatively, an address nothing names gets NXDOMAIN with the zone's SOA. A local zone
+containing the name takes precedence.
+*/
+#[derive(Clone)]
+pub struct ReverseZones {
+    networks: Vec<(Subnet, String)>,
+    ttl: u32,
+    zones: Option<Zones>,
+    hosts: Option<LocalHosts>,
+}
+
+impl ReverseZones {
+    pub fn new(config: &ReverseZonesConfig, zones: Option<Zones>, hosts: Option<LocalHosts>) -> io::Result<ReverseZones> {
+        let networks = config.networks.iter().map(|network| {
+            let subnet: Subnet = network.parse().map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let origin = origin(subnet).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            Ok((subnet.network(), origin))
+        }).collect::<io::Result<_>>()?;
+        Ok(ReverseZones { networks, ttl: config.ttl, zones, hosts })
+    }
+
+    pub fn answer(&self, question: &DnsQuestion) -> Option<ZoneAnswer> {
+        let (subnet, origin) = self.networks.iter()
+            .filter(|(_, origin)| is_subdomain(&question.name, origin))
+            .max_by_key(|(_, origin)| origin.len())?;
+        Some(self.zone(*subnet, origin).answer(question))
+    }
+
+    // Hosts files first, an address named there and in a zone gets both PTRs
+    fn addresses(&self) -> Vec<(IpAddr, String)> {
+        let mut addresses = self.hosts.as_ref().map(LocalHosts::addresses).unwrap_or_default();
+        addresses.extend(self.zones.as_ref().map(Zones::addresses).unwrap_or_default());
+        addresses
+    }
+
+    fn zone(&self, subnet: Subnet, origin: &str) -> Zone {
+        let mut names: Vec<(IpAddr, String)> = self.addresses().into_iter()
+            .filter(|(addr, _)| addr.is_ipv4() == subnet.addr.is_ipv4() && subnet.contains(*addr))
+            .map(|(addr, name)| (addr, name.trim_end_matches('.').to_ascii_lowercase()))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        // The serial only moves when the records do, so secondaries and caches see every change
+        let mut hasher = DefaultHasher::new();
+        names.hash(&mut hasher);
+        let mut records = vec![DnsRecord::SOA {
+            domain: origin.to_string(),
+            mname: "localhost".to_string(),
+            rname: format!("hostmaster.{}", origin),
+            serial: hasher.finish() as u32,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: self.ttl,
+            ttl: self.ttl,
+            class: DnsClass::IN,
+        }];
+        records.extend(names.iter().map(|(addr, name)| DnsRecord::ptr(&reverse_name(*addr), name, self.ttl)));
+        Zone { origin: origin.to_string(), path: None, records, denial: Denial::None, signer: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::hosts::HostsTable;
+    use crate::utils::query_type::QueryType;
+    use crate::utils::result_code::ResultCode;
+
+    #[test]
+    fn test_origins() {
+        assert_eq!(origin("192.168.1.0/24".parse().unwrap()), Ok("1.168.192.in-addr.arpa".to_string()));
+        assert_eq!(origin("10.1.2.3/8".parse().unwrap()), Ok("10.in-addr.arpa".to_string()));
+        assert_eq!(origin("2001:db8::/36".parse().unwrap()), Ok("0.8.b.d.0.1.0.0.2.ip6.arpa".to_string()));
+        assert!(origin("192.168.1.0/25".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_generated_zone() {
+        let zone = Zone::parse("example.lan", "@ 3600 IN SOA ns.example.lan. admin.example.lan. 1 3600 600 86400 300
+www.example.lan. 300 IN A 192.168.1.10
+www.example.lan. 300 IN AAAA fd00::10
+outside.example.lan. 300 IN A 192.0.2.1
+").unwrap();
+        let mut table = HostsTable::new();
+        table.parse("192.168.1.20 NAS.lan\n");
+        let hosts = LocalHosts::new(table, 300);
+        let config = ReverseZonesConfig { enabled: true, networks: vec!["192.168.1.0/24".to_string(), "fd00::/64".to_string()], ttl: 60 };
+        let reverse = ReverseZones::new(&config, Some(Zones::from_zones(vec![zone])), Some(hosts)).unwrap();
+
+        let answer = reverse.answer(&DnsQuestion::new("10.1.168.192.in-addr.arpa".to_string(), QueryType::PTR)).unwrap();
+        assert!(answer.authoritative);
+        assert_eq!(answer.answers, vec![DnsRecord::ptr("10.1.168.192.in-addr.arpa", "www.example.lan", 60)]);
+        let answer = reverse.answer(&DnsQuestion::new("20.1.168.192.in-addr.arpa".to_string(), QueryType::PTR)).unwrap();
+        assert_eq!(answer.answers, vec![DnsRecord::ptr("20.1.168.192.in-addr.arpa", "nas.lan", 60)]);
+        let ip6 = reverse_name("fd00::10".parse().unwrap());
+        assert_eq!(reverse.answer(&DnsQuestion::new(ip6.clone(), QueryType::PTR)).unwrap().answers, vec![DnsRecord::ptr(&ip6, "www.example.lan", 60)]);
+
+        let missing = reverse.answer(&DnsQuestion::new("99.1.168.192.in-addr.arpa".to_string(), QueryType::PTR)).unwrap();
+        assert_eq!(missing.rescode, ResultCode::NXDOMAIN);
+        assert!(matches!(missing.authorities[0], DnsRecord::SOA { .. }));
+        // Outside every configured network
+        assert!(reverse.answer(&DnsQuestion::new("1.2.0.192.in-addr.arpa".to_string(), QueryType::PTR)).is_none());
+    }
+}
@@ -0,0 +1,196 @@
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signer, SigningKey};
+
+use crate::local::zonemd::{wire_records, Canonical};
+use crate::utils::dns_class::DnsClass;
+use crate::utils::key_file::load_or_create_key;
+use crate::utils::name::to_wire;
+use crate::utils::record::DnsRecord;
+
+pub const ALGORITHM_ED25519: u8 = 15;
+
+// A zone key that is also the secure entry point, the one key signs everything including itself
+const DNSKEY_FLAGS: u16 = 257;
+
+// Signatures start an hour before the hour they are made in, for clocks running behind, and last a week
+const CLOCK_SKEW: u32 = 3600;
+const VALIDITY: u32 = 7 * 86400;
+
+/**
+Signs the answers of a local zone as they go out (RFC 4034, Ed25519 as RFC 8080 has it), so
+the NSEC and NSEC3 records made up for negative answers carry RRSIGs like everything else. One
+key serves as both key signing and zone signing key; its DNSKEY is answered at the apex and a
+DS for it has to be published in the parent zone for validators to trust the zone. Signatures
+made within the same hour are identical, caches see the same answer until the hour turns.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZoneSigner {
+    key: SigningKey,
+}
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32
+}
+
+// The checksum RFC 4034 appendix B defines over the DNSKEY data
+fn key_tag(rdata: &[u8]) -> u16 {
+    let mut sum: u32 = rdata.iter().enumerate().map(|(i, &byte)| if i % 2 == 0 { (byte as u32) << 8 } else { byte as u32 }).sum();
+    sum += (sum >> 16) & 0xFFFF;
+    sum as u16
+}
+
+// Labels of the owner name, a leading wildcard label and the root not counted (RFC 4034 3.1.3)
+fn label_count(name: &str) -> u8 {
+    let name = name.trim_end_matches('.');
+    let name = name.strip_prefix("*.").unwrap_or(name);
+    name.split('.').filter(|label| !label.is_empty() && *label != "*").count() as u8
+}
+
+impl ZoneSigner {
+    pub fn new(key: SigningKey) -> ZoneSigner {
+        ZoneSigner { key }
+    }
+
+    // The key file holds the hex of the Ed25519 private key, a new one is made if it doesn't exist
+    pub fn load(path: impl AsRef<Path>) -> io::Result<ZoneSigner> {
+        Ok(ZoneSigner::new(SigningKey::from_bytes(&load_or_create_key(path)?)))
+    }
+
+    pub fn dnskey(&self, origin: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::DNSKEY {
+            domain: origin.to_string(),
+            flags: DNSKEY_FLAGS,
+            protocol: 3,
+            algorithm: ALGORITHM_ED25519,
+            public_key: self.key.verifying_key().to_bytes().to_vec(),
+            ttl,
+            class: DnsClass::IN,
+        }
+    }
+
+    pub fn key_tag(&self) -> u16 {
+        let mut rdata = DNSKEY_FLAGS.to_be_bytes().to_vec();
+        rdata.extend_from_slice(&[3, ALGORITHM_ED25519]);
+        rdata.extend_from_slice(self.key.verifying_key().as_bytes());
+        key_tag(&rdata)
+    }
+
+    /**
+    The RRSIG over one RRset, all records of the same name and type, by the zone `signer`. The
+    records are signed in canonical form with the TTL of the first (RFC 4034 3.1.8.1). None for
+    an empty RRset or a record that can't be written.
+    */
+    pub fn sign(&self, rrset: &[DnsRecord], signer: &str, inception: u32, expiration: u32) -> Option<DnsRecord> {
+        let first = rrset.first()?;
+        let original_ttl = first.ttl();
+        let same_ttl: Vec<DnsRecord> = rrset.iter().cloned().map(|mut record| {
+            record.set_ttl(original_ttl);
+            record
+        }).collect();
+        let wire = wire_records(&same_ttl);
+        if wire.len() != rrset.len() {
+            return None;
+        }
+        let mut records = wire.into_iter().map(Canonical::new).collect::<Option<Vec<_>>>()?;
+        records.sort_by(|a, b| a.data().cmp(b.data()));
+        records.dedup_by(|a, b| a.data() == b.data());
+
+        let (labels, key_tag) = (label_count(first.domain()), self.key_tag());
+        let mut data = first.query_type().to_num().to_be_bytes().to_vec();
+        data.extend_from_slice(&[ALGORITHM_ED25519, labels]);
+        for field in [original_ttl, expiration, inception] {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        data.extend_from_slice(&key_tag.to_be_bytes());
+        data.extend_from_slice(&to_wire(signer));
+        for record in &records {
+            data.extend_from_slice(&record.wire);
+        }
+
+        Some(DnsRecord::RRSIG {
+            domain: first.domain().to_string(),
+            type_covered: first.query_type(),
+            algorithm: ALGORITHM_ED25519,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer: signer.trim_end_matches('.').to_string(),
+            signature: self.key.sign(&data).to_bytes().to_vec(),
+            ttl: original_ttl,
+            class: first.class(),
+        })
+    }
+
+    // Adds an RRSIG after each RRset in `records`, signed as of now
+    pub fn sign_section(&self, records: &mut Vec<DnsRecord>, origin: &str) {
+        let now = now();
+        let inception = now - now % 3600 - CLOCK_SKEW;
+        let mut signed = Vec::new();
+        let mut rest = std::mem::take(records);
+        while let Some(first) = rest.first().cloned() {
+            let same_rrset = |record: &DnsRecord| record.query_type() == first.query_type() && record.domain().eq_ignore_ascii_case(first.domain());
+            let (rrset, others): (Vec<_>, Vec<_>) = rest.into_iter().partition(same_rrset);
+            let signature = self.sign(&rrset, origin, inception, inception + VALIDITY);
+            signed.extend(rrset);
+            signed.extend(signature);
+            rest = others;
+        }
+        *records = signed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use ed25519_dalek::{Signature, Verifier};
+
+    use crate::cache::dump::parse_record;
+    use crate::utils::query_type::QueryType;
+
+    // The Ed25519 example of RFC 8080 6.1
+    fn rfc_signer() -> ZoneSigner {
+        let seed = STANDARD.decode("ODIyNjAzODQ2MjgwODAxMjI2NDUxOTAyMDQxNDIyNjI=").unwrap();
+        ZoneSigner::new(SigningKey::from_bytes(&seed.try_into().unwrap()))
+    }
+
+    #[test]
+    fn test_rfc_example() {
+        let signer = rfc_signer();
+        assert_eq!(signer.dnskey("example.com", 3600), parse_record("example.com. 3600 IN DNSKEY 257 3 15 l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=").unwrap());
+        assert_eq!(signer.key_tag(), 3613);
+
+        let mx = parse_record("example.com. 3600 IN MX 10 mail.example.com.").unwrap();
+        let rrsig = signer.sign(&[mx], "example.com", 1438207200, 1440021600).unwrap();
+        assert_eq!(rrsig, parse_record("example.com. 3600 IN RRSIG MX 15 2 3600 1440021600 1438207200 3613 example.com. \
+            oL9krJun7xfBOIWcGHi7mag5/hdZrKWw15jPGrHpjQeRAvTdszaPD+QLs3fx8A4M3e23mRZ9VrbpMngwcrqNAg==").unwrap());
+    }
+
+    #[test]
+    fn test_sign_section() {
+        let signer = rfc_signer();
+        let mut records = vec![
+            parse_record("www.example.com. 300 IN A 192.0.2.2").unwrap(),
+            parse_record("example.com. 300 IN NSEC www.example.com. NS SOA RRSIG NSEC DNSKEY").unwrap(),
+            parse_record("www.example.com. 300 IN A 192.0.2.1").unwrap(),
+        ];
+        signer.sign_section(&mut records, "example.com");
+        let types: Vec<QueryType> = records.iter().map(DnsRecord::query_type).collect();
+        assert_eq!(types, [QueryType::A, QueryType::A, QueryType::RRSIG, QueryType::NSEC, QueryType::RRSIG]);
+
+        // The signature covers both addresses in canonical order, whatever order they came in
+        let DnsRecord::RRSIG { inception, expiration, signature, .. } = &records[2] else { unreachable!() };
+        assert!(*inception <= now() && now() < *expiration);
+        let sorted = [records[1].clone(), records[0].clone()];
+        let DnsRecord::RRSIG { signature: expected, .. } = signer.sign(&sorted, "example.com", *inception, *expiration).unwrap() else { unreachable!() };
+        assert_eq!(signature, &expected);
+        let signature = Signature::from_slice(signature).unwrap();
+        assert!(signer.key.verifying_key().verify(b"something else", &signature).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::local::zonemd;
+use crate::resolver::recursive::{build_query, read_message, write_message};
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+use crate::utils::wire::{decode, invalid, questions, read_u16, section};
+
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The answer records of a message of any size, each on its own without compression
+fn wire_answers(message: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let rcode = message.get(3).ok_or_else(invalid)? & 0x0F;
+    if rcode != 0 {
+        return Err(io::Error::other(format!("Server answered with RCODE {}", rcode)));
+    }
+    let (_, pos) = questions(message)?;
+    Ok(section(message, pos, read_u16(message, 6).ok_or_else(invalid)?)?.0)
+}
+
+/*
+The answer records of a message of any size. Transfers send messages of up to 64 KiB, which
+don't fit the packet buffer, so every record is decoded on its own. Records that can't be
+represented are left out.
+*/
+pub fn answer_records(message: &[u8]) -> io::Result<Vec<DnsRecord>> {
+    Ok(wire_answers(message)?.iter().filter_map(|record| decode(record)).collect())
+}
+
+fn connect(primary: SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&primary, TRANSFER_TIMEOUT)?;
+    stream.set_read_timeout(Some(TRANSFER_TIMEOUT))?;
+    stream.set_write_timeout(Some(TRANSFER_TIMEOUT))?;
+    Ok(stream)
+}
+
+// The SOA serial of `zone` on the primary, checked before transferring the whole zone again
+pub fn soa_serial(primary: SocketAddr, zone: &str) -> io::Result<u32> {
+    let mut stream = connect(primary)?;
+    write_message(&mut stream, &build_query(zone, QueryType::SOA, false, None)?)?;
+    answer_records(&read_message(&mut stream)?)?
+        .into_iter()
+        .find_map(|record| match record {
+            DnsRecord::SOA { serial, .. } => Some(serial),
+            _ => None,
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no SOA for {}", primary, zone)))
+}
+
+/**
+Transfers a whole zone from its primary (AXFR, RFC 5936). The answer is a stream of messages
+that starts and ends with the zone's SOA, so reading stops at the second SOA. The records come
+back in the order the primary sent them with the closing SOA left out. A zone with a ZONEMD
+record is checked against it, over every record sent, and refused when the digest is wrong.
+*/
+pub fn axfr(primary: SocketAddr, zone: &str) -> io::Result<Vec<DnsRecord>> {
+    let mut stream = connect(primary)?;
+    write_message(&mut stream, &build_query(zone, QueryType::AXFR, false, None)?)?;
+
+    let mut records: Vec<DnsRecord> = Vec::new();
+    let mut wire = Vec::new();
+    loop {
+        for raw in wire_answers(&read_message(&mut stream)?)? {
+            let record = decode(&raw);
+            let is_soa = matches!(record, Some(DnsRecord::SOA { .. }));
+            if wire.is_empty() && !is_soa {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Transfer of {} doesn't start with its SOA", zone)));
+            }
+            if is_soa && !wire.is_empty() {
+                zonemd::verify(zone, &records, &wire).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                return Ok(records);
+            }
+            records.extend(record);
+            wire.push(raw);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+    use crate::utils::name::to_wire;
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread;
+
+    // A message in wire format with one answer per entry, each owner name pointing at the question
+    fn message(answers: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut message = vec![0x1a, 0x0a, 0x84, 0x00, 0, 1, 0, answers.len() as u8, 0, 0, 0, 0];
+        message.extend_from_slice(&to_wire("example.lan"));
+        message.extend_from_slice(&[0, 252, 0, 1]);
+        for (rtype, rdata) in answers {
+            message.extend_from_slice(&[0xC0, 12]);
+            message.extend_from_slice(&rtype.to_be_bytes());
+            message.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+            message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            message.extend_from_slice(rdata);
+        }
+        message
+    }
+
+    fn soa(serial: u32) -> Vec<u8> {
+        // mname ns1.example.lan compressed against the question, rname a pointer to the origin
+        let mut rdata = vec![3, b'n', b's', b'1', 0xC0, 12, 0xC0, 12];
+        rdata.extend_from_slice(&serial.to_be_bytes());
+        rdata.extend_from_slice(&[0, 0, 0x1c, 0x20, 0, 0, 0x03, 0x84, 0, 0x12, 0x75, 0, 0, 0, 1, 0x2c]);
+        rdata
+    }
+
+    #[test]
+    fn test_answer_records_expand_names() {
+        let records = answer_records(&message(&[(6, &soa(7)), (15, &[0, 10, 2, b'm', b'x', 0xC0, 12])])).unwrap();
+        assert!(matches!(&records[0], DnsRecord::SOA { domain, mname, rname, serial: 7, .. }
+            if domain == "example.lan" && mname == "ns1.example.lan" && rname == "example.lan"));
+        assert_eq!(records[1], DnsRecord::MX { domain: "example.lan".to_string(), preference: 10, exchange: "mx.example.lan".to_string(), ttl: 3600, class: DnsClass::IN });
+
+        let mut looping = message(&[(2, &[0xC0, 12])]);
+        let len = looping.len();
+        looping[len - 2..].copy_from_slice(&[0xC0, (len - 2) as u8]);
+        assert!(answer_records(&looping).is_err());
+    }
+
+    #[test]
+    fn test_axfr_reads_until_closing_soa() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let primary = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_message(&mut stream).unwrap();
+            write_message(&mut stream, &message(&[(6, &soa(7)), (1, &[192, 0, 2, 1])])).unwrap();
+            write_message(&mut stream, &message(&[(1, &[192, 0, 2, 2]), (6, &soa(7))])).unwrap();
+        });
+        let records = axfr(primary, "example.lan").unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2], DnsRecord::A { domain: "example.lan".to_string(), addr: Ipv4Addr::new(192, 0, 2, 2), ttl: 3600, class: DnsClass::IN });
+    }
+}
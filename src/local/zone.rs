@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::{fs, io};
+
+use log::info;
+use serde::Deserialize;
+
+use crate::cache::dump::parse_record;
+use crate::local::denial::{Denial, DenialMode};
+use crate::local::signer::ZoneSigner;
+use crate::local::zonemd;
+use crate::utils::memory::{map_heap_size, HeapSize, MemoryUsage};
+use crate::utils::name::is_subdomain;
+use crate::utils::presentation::{fqdn, record_data};
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+
+// CNAME chains inside a zone are followed this far
+const MAX_CNAME_HOPS: usize = 8;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ZoneConfig {
+    pub name: String,
+    pub file: String,
+    pub denial: DenialMode,    // NSEC or NSEC3 records in negative answers
+    pub nsec3_salt: String,    // hex, empty for none
+    pub nsec3_iterations: u16, // extra hash iterations, RFC 9276 recommends none
+    pub signing_key: String,   // Ed25519 key file to sign answers with, created if missing, empty for unsigned
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ZoneAnswer {
+    pub rescode: ResultCode,
+    pub authoritative: bool, // false for referrals to a delegated child zone
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub resources: Vec<DnsRecord>,
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+// The records of a zone file with their line numbers, and an error for each line that isn't one
+fn parse_lines(origin: &str, text: &str) -> (Vec<(usize, DnsRecord)>, Vec<String>) {
+    let (mut records, mut errors) = (Vec::new(), Vec::new());
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let line = match line.strip_prefix("@ ").or_else(|| line.strip_prefix("@\t")) {
+            Some(rest) => format!("{}. {}", origin, rest),
+            None => line.to_string(),
+        };
+        match parse_record(&line) {
+            Some(record) => records.push((number + 1, record)),
+            None => errors.push(format!("Line {}: unsupported or malformed record", number + 1)),
+        }
+    }
+    (records, errors)
+}
+
+// Sorts the errors without a line, about the zone as a whole, first
+fn line_number(error: &str) -> usize {
+    error.strip_prefix("Line ").and_then(|rest| rest.split(':').next()).and_then(|n| n.parse().ok()).unwrap_or(0)
+}
+
+/**
+A zone this server is authoritative for, loaded from a zone file in the format `cache export`
+writes: one `name ttl IN type data` record per line with fully qualified names, `@` standing
+for the origin and `;` starting a comment. Exactly one SOA is required, at the apex. NSEC and
+NSEC3 records aren't part of the file, they are generated for negative answers from the records
+as they are at the time, so runtime edits never leave a stale chain behind. Neither are RRSIG
+and DNSKEY records: with a signing key the answers for clients asking for DNSSEC are signed as
+they go out.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zone {
+    pub origin: String,
+    pub path: Option<PathBuf>,
+    pub records: Vec<DnsRecord>, // the SOA comes first
+    pub denial: Denial,
+    pub signer: Option<ZoneSigner>,
+}
+
+impl Zone {
+    pub fn parse(origin: &str, text: &str) -> Result<Zone, String> {
+        let origin = normalize(origin);
+        let (records, errors) = parse_lines(&origin, text);
+        if let Some(error) = errors.into_iter().next() {
+            return Err(error);
+        }
+        let records: Vec<DnsRecord> = records.into_iter().map(|(_, record)| record).collect();
+        zonemd::verify(&origin, &records, &zonemd::wire_records(&records))?;
+        Zone::from_records(&origin, records)
+    }
+
+    /**
+    Every problem with a zone file rather than only the first, each with its line number where
+    there is one: malformed records, the SOA, and records the zone wouldn't accept. Empty when
+    `parse` would load the file.
+    */
+    pub fn validate(origin: &str, text: &str) -> Vec<String> {
+        let origin = normalize(origin);
+        let (records, mut errors) = parse_lines(&origin, text);
+        let (soa, records): (Vec<_>, Vec<_>) = records.into_iter().partition(|(_, record)| matches!(record, DnsRecord::SOA { .. }));
+        let mut zone = Zone { origin: origin.clone(), path: None, records: Vec::new(), denial: Denial::None, signer: None };
+        match soa.as_slice() {
+            [(_, record)] if same_name(record.domain(), &origin) => zone.records.push(record.clone()),
+            [(number, _)] => errors.push(format!("Line {}: the SOA of {} has to be at the apex", number, origin)),
+            [] => errors.push(format!("Zone {} needs an SOA record", origin)),
+            [_, extra @ ..] => errors.extend(extra.iter().map(|(number, _)| format!("Line {}: zone {} needs exactly one SOA record", number, origin))),
+        }
+        for (number, record) in records {
+            match zone.check(&record) {
+                Ok(()) => zone.records.push(record),
+                Err(e) => errors.push(format!("Line {}: {}", number, e)),
+            }
+        }
+        if errors.is_empty() {
+            errors.extend(zonemd::verify(&origin, &zone.records, &zonemd::wire_records(&zone.records)).err());
+        }
+        errors.sort_by_key(|error| line_number(error));
+        errors
+    }
+
+    // A zone from its records in any order, as they come from a file or a zone transfer
+    pub fn from_records(origin: &str, mut records: Vec<DnsRecord>) -> Result<Zone, String> {
+        let origin = normalize(origin);
+        let soa: Vec<usize> = records.iter().enumerate()
+            .filter(|(_, record)| matches!(record, DnsRecord::SOA { .. }))
+            .map(|(i, _)| i)
+            .collect();
+        match soa.as_slice() {
+            [i] if same_name(records[*i].domain(), &origin) => {
+                let soa = records.remove(*i);
+                records.insert(0, soa);
+            }
+            [_] => return Err(format!("The SOA of {} has to be at the apex", origin)),
+            _ => return Err(format!("Zone {} needs exactly one SOA record", origin)),
+        }
+
+        let mut zone = Zone { origin, path: None, records: Vec::new(), denial: Denial::None, signer: None };
+        zone.records.push(records.remove(0));
+        for record in records {
+            zone.check(&record)?;
+            zone.records.push(record);
+        }
+        Ok(zone)
+    }
+
+    pub fn load(config: &ZoneConfig) -> io::Result<Zone> {
+        let text = fs::read_to_string(&config.file)?;
+        let mut zone = Zone::parse(&config.name, &text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", config.file, e)))?;
+        zone.path = Some(PathBuf::from(&config.file));
+        zone.denial = Denial::from_config(config).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", config.name, e)))?;
+        zone.signer = (!config.signing_key.is_empty()).then(|| ZoneSigner::load(&config.signing_key)).transpose()?;
+        info!("Loaded zone {} with {} records, serial {}", zone.origin, zone.records.len(), zone.serial());
+        Ok(zone)
+    }
+
+    pub fn soa(&self) -> &DnsRecord {
+        &self.records[0]
+    }
+
+    pub fn serial(&self) -> u32 {
+        match self.soa() {
+            DnsRecord::SOA { serial, .. } => *serial,
+            _ => 0,
+        }
+    }
+
+    // Serial arithmetic (RFC 1982) wraps, secondaries still see the new serial as newer. A ZONEMD
+    // digest is computed again for the new serial and records.
+    fn bump_serial(&mut self) {
+        if let DnsRecord::SOA { serial, .. } = &mut self.records[0] {
+            *serial = serial.wrapping_add(1);
+        }
+        zonemd::refresh(&self.origin, &mut self.records);
+    }
+
+    // The SOA for negative answers, with the TTL negative answers may be cached for (RFC 2308)
+    fn negative_soa(&self) -> DnsRecord {
+        let mut soa = self.soa().clone();
+        if let DnsRecord::SOA { ttl, minimum, .. } = &mut soa {
+            *ttl = (*ttl).min(*minimum);
+        }
+        soa
+    }
+
+    fn check(&self, record: &DnsRecord) -> Result<(), String> {
+        if !is_subdomain(record.domain(), &self.origin) {
+            return Err(format!("{} is not in zone {}", record.domain(), self.origin));
+        }
+        if matches!(record, DnsRecord::SOA { .. }) {
+            return Err("The SOA record is managed by the server".to_string());
+        }
+        if matches!(record, DnsRecord::UNKNOWN { .. }) {
+            return Err("Unsupported record type".to_string());
+        }
+        if matches!(record, DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. } | DnsRecord::RRSIG { .. } | DnsRecord::DNSKEY { .. }) {
+            return Err("NSEC, NSEC3, RRSIG and DNSKEY records are generated by the server".to_string());
+        }
+        if self.records.contains(record) {
+            return Err(format!("{} {:?} already exists", record.domain(), record.query_type()));
+        }
+        // A CNAME owns its name, nothing else may live next to it (RFC 1034 3.6.2)
+        let at_name = self.records.iter().filter(|other| same_name(other.domain(), record.domain()));
+        let conflict = match record {
+            DnsRecord::CNAME { .. } => at_name.count() > 0,
+            _ => at_name.into_iter().any(|other| matches!(other, DnsRecord::CNAME { .. })),
+        };
+        if conflict {
+            return Err(format!("{} cannot have a CNAME and other records", record.domain()));
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, records: Vec<DnsRecord>) -> Result<(), String> {
+        let mut zone = self.clone();
+        for record in records {
+            zone.check(&record)?;
+            zone.records.push(record);
+        }
+        self.records = zone.records;
+        self.bump_serial();
+        Ok(())
+    }
+
+    // Replaces the records of one name and type with `records`, which may be empty
+    pub fn replace(&mut self, name: &str, qtype: QueryType, records: Vec<DnsRecord>) -> Result<(), String> {
+        if qtype == QueryType::SOA {
+            return Err("The SOA record is managed by the server".to_string());
+        }
+        if let Some(record) = records.iter().find(|record| !same_name(record.domain(), name) || record.query_type() != qtype) {
+            return Err(format!("{} {:?} doesn't belong to {} {:?}", record.domain(), record.query_type(), name, qtype));
+        }
+        let mut zone = self.clone();
+        zone.records.retain(|record| !(same_name(record.domain(), name) && record.query_type() == qtype));
+        zone.add(records)?;
+        self.records = zone.records;
+        Ok(())
+    }
+
+    // Deletes the records of a name, only those of one type if given, and returns how many
+    pub fn delete(&mut self, name: &str, qtype: Option<QueryType>) -> Result<usize, String> {
+        if qtype == Some(QueryType::SOA) || (qtype.is_none() && same_name(name, &self.origin)) {
+            return Err("The SOA record is managed by the server".to_string());
+        }
+        let before = self.records.len();
+        self.records.retain(|record| !(same_name(record.domain(), name) && qtype.is_none_or(|qtype| record.query_type() == qtype)));
+        let deleted = before - self.records.len();
+        if deleted > 0 {
+            self.bump_serial();
+        }
+        Ok(deleted)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("; zone {}, serial {}\n", fqdn(&self.origin), self.serial());
+        for record in &self.records {
+            if let Some(data) = record_data(record) {
+                text.push_str(&format!("{}\t{}\tIN\t{:?}\t{}\n", fqdn(record.domain()), record.ttl(), record.query_type(), data));
+            }
+        }
+        text
+    }
+
+    // Written next to the zone file and renamed over it, a crash never leaves half a zone behind
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.to_text())?;
+        fs::rename(&tmp, path)
+    }
+
+    fn records_at<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a DnsRecord> {
+        self.records.iter().filter(move |record| same_name(record.domain(), name))
+    }
+
+    // The closest delegation between the apex and `name`, NS records below the apex cut the zone
+    fn delegation(&self, name: &str) -> Option<Vec<DnsRecord>> {
+        let mut cut: Option<&str> = None;
+        for record in &self.records {
+            if let DnsRecord::NS { domain, .. } = record {
+                let below_apex = !same_name(domain, &self.origin) && is_subdomain(name, domain);
+                if below_apex && cut.is_none_or(|cut| domain.len() > cut.len()) {
+                    cut = Some(domain);
+                }
+            }
+        }
+        let cut = cut?;
+        Some(self.records_at(cut).filter(|record| matches!(record, DnsRecord::NS { .. })).cloned().collect())
+    }
+
+    pub fn answer(&self, question: &DnsQuestion) -> ZoneAnswer {
+        let mut answer = ZoneAnswer { authoritative: true, ..Default::default() };
+        let mut name = question.name.clone();
+
+        for _ in 0..MAX_CNAME_HOPS {
+            if let Some(ns) = self.delegation(&name) {
+                answer.authoritative = false;
+                answer.resources = ns.iter()
+                    .filter_map(|record| match record {
+                        DnsRecord::NS { ns, .. } => Some(ns),
+                        _ => None,
+                    })
+                    .flat_map(|ns| self.records_at(ns).filter(|record| matches!(record, DnsRecord::A { .. } | DnsRecord::AAAA { .. })))
+                    .cloned()
+                    .collect();
+                answer.authorities = ns;
+                return answer;
+            }
+
+            let mut matching: Vec<DnsRecord> = self.records_at(&name).filter(|record| record.query_type() == question.qtype).cloned().collect();
+            if question.qtype == QueryType::DNSKEY && same_name(&name, &self.origin) {
+                matching.extend(self.signer.as_ref().map(|signer| signer.dnskey(&self.origin, self.soa().ttl())));
+            }
+            if !matching.is_empty() {
+                answer.answers.extend(matching);
+                return answer;
+            }
+
+            let cname = self.records_at(&name).find_map(|record| match record {
+                DnsRecord::CNAME { cname, .. } => Some((record.clone(), cname.clone())),
+                _ => None,
+            });
+            match cname {
+                // Targets outside the zone are left to the client to chase
+                Some((record, target)) => {
+                    answer.answers.push(record);
+                    if !is_subdomain(&target, &self.origin) {
+                        return answer;
+                    }
+                    name = target;
+                }
+                None => break,
+            }
+        }
+
+        // A name exists if it owns records or has names below it (an empty non-terminal)
+        let exists = self.records.iter().any(|record| is_subdomain(record.domain(), &name));
+        if !exists {
+            answer.rescode = ResultCode::NXDOMAIN;
+        }
+        let soa = self.negative_soa();
+        answer.authorities.extend(self.denial.prove(self, &name, !exists, soa.ttl()));
+        answer.authorities.insert(0, soa);
+        answer
+    }
+
+    // The answer with an RRSIG for every RRset the zone is authoritative for, referrals aren't signed
+    pub fn signed_answer(&self, question: &DnsQuestion) -> ZoneAnswer {
+        let mut answer = self.answer(question);
+        if let Some(signer) = &self.signer {
+            signer.sign_section(&mut answer.answers, &self.origin);
+            if answer.authoritative {
+                signer.sign_section(&mut answer.authorities, &self.origin);
+            }
+        }
+        answer
+    }
+}
+
+/**
+The locally authoritative zones, shared between the query handler and the admin API. Changes
+made at runtime bump the zone's SOA serial and are written back to its zone file.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Zones {
+    zones: Arc<RwLock<HashMap<String, Zone>>>,
+}
+
+impl Zones {
+    pub fn new(configs: &[ZoneConfig]) -> io::Result<Zones> {
+        let zones = configs.iter().map(|config| Zone::load(config).map(|zone| (zone.origin.clone(), zone))).collect::<io::Result<_>>()?;
+        Ok(Zones { zones: Arc::new(RwLock::new(zones)) })
+    }
+
+    pub fn from_zones(zones: Vec<Zone>) -> Zones {
+        Zones { zones: Arc::new(RwLock::new(zones.into_iter().map(|zone| (zone.origin.clone(), zone)).collect())) }
+    }
+
+    // Counts records, a zone's memory is almost all in them
+    pub fn memory(&self) -> MemoryUsage {
+        let zones = self.zones.read().unwrap();
+        MemoryUsage {
+            entries: zones.values().map(|zone| zone.records.len()).sum(),
+            bytes: map_heap_size(&zones, |zone| zone.origin.heap_size() + zone.records.heap_size()),
+        }
+    }
+
+    // Adds or replaces a zone loaded at runtime, such as a secondary zone from a catalog
+    pub fn insert(&self, zone: Zone) {
+        self.zones.write().unwrap().insert(zone.origin.clone(), zone);
+    }
+
+    // Serves a copy of another server's zone in place of the one of that name. A zone configured
+    // here keeps its file, denial settings and key, and the file is rewritten to match the copy.
+    pub fn install(&self, mut zone: Zone) -> io::Result<()> {
+        let mut zones = self.zones.write().unwrap();
+        if let Some(existing) = zones.get(&zone.origin) {
+            zone.path = existing.path.clone();
+            zone.denial = existing.denial.clone();
+            zone.signer = existing.signer.clone();
+        }
+        zone.save()?;
+        zones.insert(zone.origin.clone(), zone);
+        Ok(())
+    }
+
+    pub fn remove(&self, origin: &str) -> Option<Zone> {
+        self.zones.write().unwrap().remove(&normalize(origin))
+    }
+
+    pub fn serial(&self, origin: &str) -> Option<u32> {
+        self.zones.read().unwrap().get(&normalize(origin)).map(Zone::serial)
+    }
+
+    // The most specific zone containing `name`
+    fn find<'a>(zones: &'a HashMap<String, Zone>, name: &str) -> Option<&'a Zone> {
+        zones.values().filter(|zone| is_subdomain(name, &zone.origin)).max_by_key(|zone| zone.origin.len())
+    }
+
+    pub fn answer(&self, question: &DnsQuestion) -> Option<ZoneAnswer> {
+        let zones = self.zones.read().unwrap();
+        Zones::find(&zones, &question.name).map(|zone| zone.answer(question))
+    }
+
+    pub fn signed_answer(&self, question: &DnsQuestion) -> Option<ZoneAnswer> {
+        let zones = self.zones.read().unwrap();
+        Zones::find(&zones, &question.name).map(|zone| zone.signed_answer(question))
+    }
+
+    // The address of every A and AAAA record in the zones, with its owner
+    pub fn addresses(&self) -> Vec<(IpAddr, String)> {
+        self.zones.read().unwrap().values().flat_map(|zone| &zone.records).filter_map(|record| match record {
+            DnsRecord::A { domain, addr, .. } => Some((IpAddr::V4(*addr), domain.clone())),
+            DnsRecord::AAAA { domain, addr, .. } => Some((IpAddr::V6(*addr), domain.clone())),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn list(&self) -> Vec<(String, u32)> {
+        let mut zones: Vec<(String, u32)> = self.zones.read().unwrap().values().map(|zone| (zone.origin.clone(), zone.serial())).collect();
+        zones.sort();
+        zones
+    }
+
+    pub fn text(&self, origin: &str) -> Option<String> {
+        self.zones.read().unwrap().get(&normalize(origin)).map(Zone::to_text)
+    }
+
+    // Applies `change` to a copy of the zone, which replaces it only once it is saved
+    pub fn edit<T>(&self, origin: &str, change: impl FnOnce(&mut Zone) -> Result<T, String>) -> Option<io::Result<(T, u32)>> {
+        let mut zones = self.zones.write().unwrap();
+        let zone = zones.get_mut(&normalize(origin))?;
+        let mut edited = zone.clone();
+        let result = match change(&mut edited) {
+            Ok(result) => result,
+            Err(e) => return Some(Err(io::Error::new(io::ErrorKind::InvalidInput, e))),
+        };
+        if let Err(e) = edited.save() {
+            return Some(Err(e));
+        }
+        info!("Zone {} changed, serial {}", edited.origin, edited.serial());
+        let serial = edited.serial();
+        *zone = edited;
+        Some(Ok((result, serial)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+    use std::net::Ipv4Addr;
+
+    const ZONE: &str = "; example zone
+@\t3600\tIN\tSOA\tns1.example.lan. hostmaster.example.lan. 100 7200 900 1209600 300
+example.lan.\t3600\tIN\tNS\tns1.example.lan.
+ns1.example.lan.\t3600\tIN\tA\t192.0.2.53
+www.example.lan.\t300\tIN\tA\t192.0.2.10
+alias.example.lan.\t300\tIN\tCNAME\twww.example.lan.
+host.dept.example.lan.\t300\tIN\tA\t192.0.2.20
+sub.example.lan.\t3600\tIN\tNS\tns.sub.example.lan.
+ns.sub.example.lan.\t3600\tIN\tA\t192.0.2.99
+";
+
+    fn question(name: &str, qtype: QueryType) -> DnsQuestion {
+        DnsQuestion::new(name.to_string(), qtype)
+    }
+
+    fn a(name: &str, addr: [u8; 4]) -> DnsRecord {
+        DnsRecord::A { domain: name.to_string(), addr: Ipv4Addr::from(addr), ttl: 300, class: DnsClass::IN }
+    }
+
+    #[test]
+    fn test_answers() {
+        let zone = Zone::parse("Example.lan.", ZONE).unwrap();
+        assert_eq!(zone.serial(), 100);
+
+        let answer = zone.answer(&question("alias.example.lan", QueryType::A));
+        assert!(answer.authoritative);
+        assert_eq!(answer.answers.len(), 2);
+        assert_eq!(answer.answers[1], a("www.example.lan", [192, 0, 2, 10]));
+
+        let nodata = zone.answer(&question("www.example.lan", QueryType::MX));
+        assert_eq!(nodata.rescode, ResultCode::NOERROR);
+        assert!(matches!(nodata.authorities[0], DnsRecord::SOA { ttl: 300, .. }));
+        assert_eq!(zone.answer(&question("dept.example.lan", QueryType::A)).rescode, ResultCode::NOERROR);
+        assert_eq!(zone.answer(&question("missing.example.lan", QueryType::A)).rescode, ResultCode::NXDOMAIN);
+
+        let referral = zone.answer(&question("www.sub.example.lan", QueryType::A));
+        assert!(!referral.authoritative);
+        assert_eq!(referral.authorities.len(), 1);
+        assert_eq!(referral.resources, vec![DnsRecord::A { domain: "ns.sub.example.lan".to_string(), addr: Ipv4Addr::new(192, 0, 2, 99), ttl: 3600, class: DnsClass::IN }]);
+    }
+
+    #[test]
+    fn test_invalid_zones() {
+        assert!(Zone::parse("example.lan", "www.example.lan. 300 IN A 192.0.2.1\n").is_err());
+        let outside = format!("{}www.example.com. 300 IN A 192.0.2.1\n", ZONE);
+        assert!(Zone::parse("example.lan", &outside).is_err());
+        let conflict = format!("{}alias.example.lan. 300 IN A 192.0.2.1\n", ZONE);
+        assert!(Zone::parse("example.lan", &conflict).is_err());
+    }
+
+    #[test]
+    fn test_validate_reports_every_error() {
+        assert!(Zone::validate("example.lan", ZONE).is_empty());
+        let broken = format!("{}www.example.com. 300 IN A 192.0.2.1\nbogus\nalias.example.lan. 300 IN A 192.0.2.1\n", ZONE);
+        assert_eq!(Zone::validate("example.lan", &broken), vec![
+            "Line 10: www.example.com is not in zone example.lan".to_string(),
+            "Line 11: unsupported or malformed record".to_string(),
+            "Line 12: alias.example.lan cannot have a CNAME and other records".to_string(),
+        ]);
+        assert_eq!(Zone::validate("example.lan", "www.example.lan. 300 IN A 192.0.2.1\n"), vec!["Zone example.lan needs an SOA record".to_string()]);
+    }
+
+    #[test]
+    fn test_edits_bump_serial_and_persist() {
+        let path = std::env::temp_dir().join(format!("r_dns_zone_{}.zone", std::process::id()));
+        fs::write(&path, ZONE).unwrap();
+        let zones = Zones::new(&[ZoneConfig { name: "example.lan".to_string(), file: path.to_string_lossy().into_owned(), ..Default::default() }]).unwrap();
+
+        let (_, serial) = zones.edit("example.lan.", |zone| zone.add(vec![a("new.example.lan", [192, 0, 2, 30])])).unwrap().unwrap();
+        assert_eq!(serial, 101);
+        assert_eq!(zones.answer(&question("new.example.lan", QueryType::A)).unwrap().answers.len(), 1);
+
+        let (_, serial) = zones.edit("example.lan", |zone| zone.replace("www.example.lan", QueryType::A, vec![a("www.example.lan", [192, 0, 2, 11])])).unwrap().unwrap();
+        assert_eq!(serial, 102);
+        let (deleted, serial) = zones.edit("example.lan", |zone| zone.delete("alias.example.lan", None)).unwrap().unwrap();
+        assert_eq!((deleted, serial), (1, 103));
+
+        // Rejected changes leave the zone and its serial alone
+        assert!(zones.edit("example.lan", |zone| zone.add(vec![a("www.example.com", [192, 0, 2, 1])])).unwrap().is_err());
+        assert!(zones.edit("example.com", |zone| zone.delete("www.example.com", None)).is_none());
+
+        let reloaded = Zone::parse("example.lan", &fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.serial(), 103);
+        assert_eq!(reloaded.records, Zones::find(&zones.zones.read().unwrap(), "example.lan").unwrap().records);
+        assert_eq!(zones.answer(&question("alias.example.lan", QueryType::A)).unwrap().rescode, ResultCode::NXDOMAIN);
+    }
+}
@@ -0,0 +1,204 @@
+use sha2::{Digest, Sha384, Sha512};
+
+use crate::utils::byte_buffer::ByteBuffer;
+use crate::utils::name::{canonical_cmp, is_subdomain, push_label};
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+
+pub const SCHEME_SIMPLE: u8 = 1;
+pub const HASH_SHA384: u8 = 1;
+pub const HASH_SHA512: u8 = 2;
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+// The owner of a record in uncompressed wire format, lowercased, and where its type starts
+fn owner(wire: &[u8]) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut pos = 0;
+    loop {
+        let len = *wire.get(pos)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), pos + 1));
+        }
+        if len > 63 {
+            return None;
+        }
+        let mut label = String::new();
+        push_label(&mut label, &wire.get(pos + 1..pos + 1 + len)?.to_ascii_lowercase());
+        labels.push(label);
+        pos += 1 + len;
+    }
+}
+
+// Records we write ourselves, each on its own in wire format
+pub fn wire_records(records: &[DnsRecord]) -> Vec<Vec<u8>> {
+    records.iter().filter_map(|record| {
+        let mut buffer = ByteBuffer::new();
+        record.write(&mut buffer).ok()?;
+        Some(buffer.buffer[..buffer.position()].to_vec())
+    }).collect()
+}
+
+// A record in canonical form (RFC 4034 6.2), sortable by owner, type and data
+pub struct Canonical {
+    pub owner: String,
+    pub rtype: u16,
+    rdata: usize, // where the data starts in `wire`
+    pub wire: Vec<u8>,
+}
+
+impl Canonical {
+    pub fn new(mut wire: Vec<u8>) -> Option<Canonical> {
+        let (owner, pos) = owner(&wire)?;
+        let rtype = u16::from_be_bytes([*wire.get(pos)?, *wire.get(pos + 1)?]);
+        let rdata = pos + 10;
+        wire.get(rdata..)?;
+        wire[..pos].make_ascii_lowercase();
+        // Names in SRV and DNAME data are never compressed, so transfers bring them in the primary's case.
+        // Label lengths are below 64 and aren't touched by lowercasing.
+        match QueryType::from_num(rtype) {
+            QueryType::SRV => wire.get_mut(rdata + 6..)?.make_ascii_lowercase(),
+            QueryType::DNAME => wire[rdata..].make_ascii_lowercase(),
+            _ => {}
+        }
+        Some(Canonical { owner, rtype, rdata, wire })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.wire[self.rdata..]
+    }
+}
+
+fn hash<D: Digest>(records: &[Canonical]) -> Vec<u8> {
+    let mut hasher = D::new();
+    for record in records {
+        hasher.update(&record.wire);
+    }
+    hasher.finalize().to_vec()
+}
+
+/**
+The SIMPLE digest of a zone (RFC 8976 3.3) from its records in uncompressed wire format: every
+record at or below the origin except the apex ZONEMD RRset and the signatures over it, in
+canonical order and without duplicates. None for an algorithm we don't implement or a record
+that isn't valid wire format.
+*/
+pub fn digest(origin: &str, records: &[Vec<u8>], algorithm: u8) -> Option<Vec<u8>> {
+    let mut canonical = Vec::new();
+    for wire in records {
+        let record = Canonical::new(wire.clone())?;
+        let apex = same_name(&record.owner, origin);
+        let zonemd = QueryType::ZONEMD.to_num().to_be_bytes();
+        if !is_subdomain(&record.owner, origin)
+            || (apex && record.rtype == QueryType::ZONEMD.to_num())
+            || (apex && record.rtype == QueryType::RRSIG.to_num() && record.data().starts_with(&zonemd)) {
+            continue;
+        }
+        canonical.push(record);
+    }
+    canonical.sort_by(|a, b| canonical_cmp(&a.owner, &b.owner).then(a.rtype.cmp(&b.rtype)).then_with(|| a.data().cmp(b.data())));
+    canonical.dedup_by(|a, b| a.owner == b.owner && a.rtype == b.rtype && a.data() == b.data());
+
+    match algorithm {
+        HASH_SHA384 => Some(hash::<Sha384>(&canonical)),
+        HASH_SHA512 => Some(hash::<Sha512>(&canonical)),
+        _ => None,
+    }
+}
+
+fn serial(records: &[DnsRecord]) -> Option<u32> {
+    records.iter().find_map(|record| match record {
+        DnsRecord::SOA { serial, .. } => Some(*serial),
+        _ => None,
+    })
+}
+
+/**
+Checks a zone against the ZONEMD records at its apex (RFC 8976 4). `records` are the zone's
+records as we store them, `wire` every record as it arrived, types we don't store included. A
+zone without ZONEMD passes, and so does one whose ZONEMD records all use a scheme or algorithm
+we don't implement. Otherwise one of them has to match, with the SOA's serial.
+*/
+pub fn verify(origin: &str, records: &[DnsRecord], wire: &[Vec<u8>]) -> Result<(), String> {
+    let zonemds: Vec<(u32, u8, u8, &[u8])> = records.iter().filter_map(|record| match record {
+        DnsRecord::ZONEMD { domain, serial, scheme, algorithm, digest, .. } if same_name(domain, origin) => Some((*serial, *scheme, *algorithm, digest.as_slice())),
+        _ => None,
+    }).collect();
+    let supported: Vec<_> = zonemds.iter()
+        .filter(|(_, scheme, algorithm, _)| *scheme == SCHEME_SIMPLE && matches!(*algorithm, HASH_SHA384 | HASH_SHA512))
+        .collect();
+    if supported.is_empty() {
+        return Ok(());
+    }
+
+    let soa_serial = serial(records).ok_or_else(|| format!("Zone {} has no SOA", origin))?;
+    for (i, (serial, scheme, algorithm, expected)) in supported.iter().enumerate() {
+        if supported[..i].iter().any(|other| other.1 == *scheme && other.2 == *algorithm) {
+            return Err(format!("Zone {} has more than one ZONEMD with scheme {} and algorithm {}", origin, scheme, algorithm));
+        }
+        if *serial != soa_serial {
+            return Err(format!("The ZONEMD of {} is for serial {}, the SOA has {}", origin, serial, soa_serial));
+        }
+        if digest(origin, wire, *algorithm).as_deref() == Some(*expected) {
+            return Ok(());
+        }
+    }
+    Err(format!("The ZONEMD digest of {} doesn't match its records", origin))
+}
+
+/**
+Recomputes the apex ZONEMD records after the zone changed, for the serial it has now. Records
+with a scheme or algorithm we don't implement are left as they are.
+*/
+pub fn refresh(origin: &str, records: &mut [DnsRecord]) {
+    let Some(soa_serial) = serial(records) else {
+        return;
+    };
+    let wire = wire_records(records);
+    for record in records.iter_mut() {
+        if let DnsRecord::ZONEMD { domain, serial, scheme: SCHEME_SIMPLE, algorithm, digest: old, .. } = record {
+            if let Some(new) = same_name(domain, origin).then(|| digest(origin, &wire, *algorithm)).flatten() {
+                *serial = soa_serial;
+                *old = new;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::dump::parse_record;
+
+    // The simple example zone of RFC 8976 A.1
+    const EXAMPLE: &str = "example. 86400 IN SOA ns1.example. admin.example. 2018031900 1800 900 604800 86400
+example. 86400 IN NS ns1.example.
+example. 86400 IN NS ns2.example.
+ns1.example. 3600 IN A 203.0.113.63
+ns2.example. 3600 IN AAAA 2001:db8::63
+example. 86400 IN ZONEMD 2018031900 1 1 c68090d90a7aed716bc459f9340e3d7c1370d4d24b7e2fc3a1ddc0b9a87153b9a9713b3c9ae5cc27777f98b8e730044c";
+
+    fn records(text: &str) -> Vec<DnsRecord> {
+        text.lines().map(|line| parse_record(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_rfc_example() {
+        let records = records(EXAMPLE);
+        assert_eq!(verify("example", &records, &wire_records(&records)), Ok(()));
+
+        let mut changed = records.clone();
+        changed[3] = parse_record("ns1.example. 3600 IN A 203.0.113.64").unwrap();
+        assert!(verify("example", &changed, &wire_records(&changed)).unwrap_err().contains("doesn't match"));
+
+        // After an edit the digest is computed again
+        if let DnsRecord::SOA { serial, .. } = &mut changed[0] {
+            *serial += 1;
+        }
+        assert!(verify("example", &changed, &wire_records(&changed)).unwrap_err().contains("serial"));
+        refresh("example", &mut changed);
+        assert_eq!(verify("example", &changed, &wire_records(&changed)), Ok(()));
+    }
+}
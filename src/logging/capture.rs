@@ -0,0 +1,118 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::utils::pcap::{file_header, record, udp_packet};
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub enabled: bool,
+    pub path: String,
+    pub max_size: u64,    // bytes written before the file is rotated
+    pub retention: usize, // rotated files kept around, oldest ones are deleted
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            enabled: false,
+            path: "logs/dns.pcap".to_string(),
+            max_size: 10 * 1024 * 1024,
+            retention: 5,
+        }
+    }
+}
+
+/**
+Writes the queries the server receives and the responses it sends to a pcap file, which Wireshark
+and tcpdump open without the server needing capture permissions. The messages are put in the IP
+and UDP headers they came with. Rotated like the query log: past `max_size` the file becomes
+`<path>.1`, older files shift up by one and anything beyond `retention` is removed.
+*/
+pub struct PacketCapture {
+    config: CaptureConfig,
+    file: File,
+    size: u64,
+}
+
+impl PacketCapture {
+    pub fn open(config: CaptureConfig) -> io::Result<PacketCapture> {
+        if let Some(dir) = Path::new(&config.path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let (file, size) = PacketCapture::create(&config.path)?;
+        Ok(PacketCapture { config, file, size })
+    }
+
+    // Starts a capture file from scratch, appending to an old one would need its link type to match
+    fn create(path: &str) -> io::Result<(File, u64)> {
+        let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+        let header = file_header();
+        file.write_all(&header)?;
+        Ok((file, header.len() as u64))
+    }
+
+    pub fn write(&mut self, src: SocketAddr, dst: SocketAddr, message: &[u8]) -> io::Result<()> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let record = record(time, &udp_packet(src, dst, message));
+        self.file.write_all(&record)?;
+        self.size += record.len() as u64;
+
+        if self.size >= self.config.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.config.path, index))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.config.retention > 0 {
+            let _ = fs::remove_file(self.rotated_path(self.config.retention));
+            for index in (1..self.config.retention).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.config.path, self.rotated_path(1))?;
+        }
+        (self.file, self.size) = PacketCapture::create(&self.config.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::pcap::Capture;
+
+    #[test]
+    fn test_capture_rotates() {
+        let dir = std::env::temp_dir().join(format!("r_dns_capture_{}", std::process::id()));
+        let path = dir.join("dns.pcap").to_string_lossy().into_owned();
+        let config = CaptureConfig { enabled: true, path: path.clone(), max_size: 200, retention: 1 };
+        let mut capture = PacketCapture::open(config).unwrap();
+        let (client, server) = ("192.0.2.1:5300".parse().unwrap(), "192.0.2.53:2053".parse().unwrap());
+
+        capture.write(client, server, &[1; 100]).unwrap();
+        capture.write(server, client, &[2; 100]).unwrap();
+        capture.write(client, server, &[3; 10]).unwrap();
+
+        // Every file is a capture of its own
+        let rotated = fs::read(format!("{}.1", path)).unwrap();
+        let rotated = Capture::parse(&rotated).unwrap();
+        assert_eq!(rotated.dns_messages().iter().map(|(_, message)| message[0]).collect::<Vec<_>>(), vec![1, 2]);
+        let current = fs::read(&path).unwrap();
+        assert_eq!(Capture::parse(&current).unwrap().dns_messages(), vec![(1, &[3u8; 10][..])]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
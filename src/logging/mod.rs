@@ -0,0 +1,5 @@
+pub mod capture;
+pub mod privacy;
+pub mod query_log;
+pub mod trace;
+pub mod telemetry;
@@ -0,0 +1,99 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr};
+
+use serde::Deserialize;
+
+use crate::utils::name::is_subdomain;
+
+/**
+How client addresses show up in the query log.
+`Truncate` keeps the /24 of an IPv4 address and the /48 of an IPv6 address, `Hash` replaces
+the address with a keyed hash whose key is picked at startup, so the same client can be
+followed within one run but not across restarts.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientIpMode {
+    #[default]
+    Full,
+    Truncate,
+    Hash,
+}
+
+pub struct Redactor {
+    mode: ClientIpMode,
+    exclude_suffixes: Vec<String>,
+    key: RandomState,
+}
+
+impl Redactor {
+    pub fn new(mode: ClientIpMode, exclude_suffixes: &[String]) -> Redactor {
+        Redactor {
+            mode,
+            exclude_suffixes: exclude_suffixes.to_vec(),
+            key: RandomState::new(),
+        }
+    }
+
+    // The port is only kept in `Full` mode, it would undo the point of the other two
+    pub fn client(&self, client: SocketAddr) -> String {
+        match self.mode {
+            ClientIpMode::Full => client.to_string(),
+            ClientIpMode::Truncate => truncate_ip(client.ip()).to_string(),
+            ClientIpMode::Hash => format!("{:016x}", self.key.hash_one(client.ip())),
+        }
+    }
+
+    // Queries for names under an excluded suffix are not logged at all
+    pub fn is_excluded(&self, name: &str) -> bool {
+        self.exclude_suffixes.iter().any(|suffix| is_subdomain(name, suffix))
+    }
+}
+
+pub fn truncate_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(v6) => {
+            let mut octets = v6.octets();
+            octets[6..].fill(0);
+            IpAddr::from(octets)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_ip() {
+        assert_eq!(truncate_ip("192.0.2.77".parse().unwrap()), "192.0.2.0".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            truncate_ip("2001:db8:1234:5678::1".parse().unwrap()),
+            "2001:db8:1234::".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hash_is_stable_and_hides_address() {
+        let redactor = Redactor::new(ClientIpMode::Hash, &[]);
+        let client: SocketAddr = "192.0.2.77:5353".parse().unwrap();
+
+        assert_eq!(redactor.client(client), redactor.client("192.0.2.77:5354".parse().unwrap()));
+        assert_eq!(redactor.client(client).len(), 16);
+        assert_ne!(redactor.client(client), redactor.client("192.0.2.78:5353".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_excluded_suffixes() {
+        let redactor = Redactor::new(ClientIpMode::Full, &["internal.example".to_string()]);
+
+        assert!(redactor.is_excluded("internal.example"));
+        assert!(redactor.is_excluded("host.Internal.Example"));
+        assert!(!redactor.is_excluded("notinternal.example"));
+    }
+}
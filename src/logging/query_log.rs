@@ -0,0 +1,202 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+
+use crate::logging::privacy::{ClientIpMode, Redactor};
+use crate::utils::packet::DnsPacket;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct QueryLogConfig {
+    pub enabled: bool,
+    pub path: String,
+    pub max_size: u64,  // bytes written before the file is rotated
+    pub retention: usize, // rotated files kept around, oldest ones are deleted
+    pub gzip: bool,     // compress rotated files
+    pub client_ip: ClientIpMode,
+    pub exclude_suffixes: Vec<String>, // queries below these names are never logged
+}
+
+impl Default for QueryLogConfig {
+    fn default() -> Self {
+        QueryLogConfig {
+            enabled: false,
+            path: "logs/queries.log".to_string(),
+            max_size: 10 * 1024 * 1024,
+            retention: 5,
+            gzip: false,
+            client_ip: ClientIpMode::Full,
+            exclude_suffixes: Vec::new(),
+        }
+    }
+}
+
+/**
+One line per answered query, kept apart from the application log so it can be rotated and
+shipped on its own. Once the file grows past `max_size` it becomes `<path>.1` (or `<path>.1.gz`),
+older files shift up by one and anything beyond `retention` is removed.
+*/
+pub struct QueryLog {
+    config: QueryLogConfig,
+    redactor: Redactor,
+    file: File,
+    size: u64,
+}
+
+impl QueryLog {
+    pub fn open(config: QueryLogConfig) -> io::Result<QueryLog> {
+        if let Some(dir) = Path::new(&config.path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&config.path)?;
+        let size = file.metadata()?.len();
+        let redactor = Redactor::new(config.client_ip, &config.exclude_suffixes);
+
+        Ok(QueryLog { config, redactor, file, size })
+    }
+
+    pub fn log(&mut self, client: SocketAddr, packet: &DnsPacket, elapsed: Duration) -> io::Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let (name, qtype) = match packet.questions.first() {
+            Some(q) => (q.name.as_str(), format!("{:?}", q.qtype)),
+            None => ("-", "-".to_string()),
+        };
+        if self.redactor.is_excluded(name) {
+            return Ok(());
+        }
+
+        let line = format!(
+            "{} {} {} {} {:?} {} {}ms\n",
+            timestamp, self.redactor.client(client), name, qtype, packet.header.rescode, packet.answers.len(), elapsed.as_millis()
+        );
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        if self.size >= self.config.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let suffix = if self.config.gzip { ".gz" } else { "" };
+        PathBuf::from(format!("{}.{}{}", self.config.path, index, suffix))
+    }
+
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.config.retention == 0 {
+            fs::remove_file(&self.config.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.config.retention));
+            for index in (1..self.config.retention).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+
+            if self.config.gzip {
+                let mut encoder = GzEncoder::new(File::create(self.rotated_path(1))?, Compression::default());
+                io::copy(&mut File::open(&self.config.path)?, &mut encoder)?;
+                encoder.finish()?;
+                fs::remove_file(&self.config.path)?;
+            } else {
+                fs::rename(&self.config.path, self.rotated_path(1))?;
+            }
+        }
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.config.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::query_type::QueryType;
+    use crate::utils::question::DnsQuestion;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn test_config(name: &str, gzip: bool) -> QueryLogConfig {
+        let dir = std::env::temp_dir().join(format!("r_dns_query_log_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        QueryLogConfig {
+            enabled: true,
+            path: dir.join("queries.log").to_string_lossy().into_owned(),
+            max_size: 100,
+            retention: 2,
+            gzip,
+            ..Default::default()
+        }
+    }
+
+    fn log_queries(log: &mut QueryLog, count: usize) {
+        let mut packet = DnsPacket::new();
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        for _ in 0..count {
+            log.log("127.0.0.1:5353".parse().unwrap(), &packet, Duration::from_millis(3)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_log_line() {
+        let config = test_config("line", false);
+        let mut log = QueryLog::open(config.clone()).unwrap();
+        log_queries(&mut log, 1);
+
+        let contents = fs::read_to_string(&config.path).unwrap();
+        assert!(contents.ends_with(" 127.0.0.1:5353 example.com A NOERROR 0 3ms\n"));
+    }
+
+    #[test]
+    fn test_privacy_options() {
+        let mut config = test_config("privacy", false);
+        config.client_ip = ClientIpMode::Truncate;
+        config.exclude_suffixes = vec!["example.com".to_string()];
+        let mut log = QueryLog::open(config.clone()).unwrap();
+        log_queries(&mut log, 1);
+
+        let mut packet = DnsPacket::new();
+        packet.questions.push(DnsQuestion::new("example.org".to_string(), QueryType::A));
+        log.log("192.0.2.77:5353".parse().unwrap(), &packet, Duration::from_millis(3)).unwrap();
+
+        let contents = fs::read_to_string(&config.path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.contains(" 192.0.2.0 example.org "));
+    }
+
+    #[test]
+    fn test_rotation_keeps_retention() {
+        let config = test_config("rotation", false);
+        let mut log = QueryLog::open(config.clone()).unwrap();
+        // Each line is roughly 55 bytes, so every second line rotates the file
+        log_queries(&mut log, 8);
+
+        assert!(Path::new(&format!("{}.1", config.path)).exists());
+        assert!(Path::new(&format!("{}.2", config.path)).exists());
+        assert!(!Path::new(&format!("{}.3", config.path)).exists());
+    }
+
+    #[test]
+    fn test_rotation_gzip() {
+        let config = test_config("gzip", true);
+        let mut log = QueryLog::open(config.clone()).unwrap();
+        log_queries(&mut log, 2);
+
+        let mut contents = String::new();
+        GzDecoder::new(File::open(format!("{}.1.gz", config.path)).unwrap()).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(!Path::new(&format!("{}.1", config.path)).exists());
+    }
+}
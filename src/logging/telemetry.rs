@@ -0,0 +1,318 @@
+use std::cell::Cell;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::logging::trace;
+use crate::server::stats::counters;
+use crate::utils::random::random;
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    pub endpoint: String, // host:port of an OTLP/HTTP collector
+    pub path: String,
+    pub service_name: String,
+    pub batch_size: usize,   // spans per export request
+    pub flush_interval: u64, // seconds, a partial batch is sent after this long
+    pub queue_size: usize,   // spans waiting for export, any more are dropped
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            enabled: false,
+            endpoint: "127.0.0.1:4318".to_string(),
+            path: "/v1/traces".to_string(),
+            service_name: "r_dns".to_string(),
+            batch_size: 256,
+            flush_interval: 5,
+            queue_size: 4096,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub parent_id: Option<u64>,
+    pub name: &'static str,
+    pub kind: SpanKind,
+    pub start: u128, // nanoseconds since the epoch
+    pub end: u128,
+    pub attributes: Vec<(&'static str, String)>,
+    pub error: bool,
+}
+
+thread_local! {
+    // The trace and innermost open span of this thread
+    static CONTEXT: Cell<Option<(u128, u64)>> = const { Cell::new(None) };
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos()
+}
+
+// Set once at startup when `[telemetry]` is enabled, see `start`
+fn exporter() -> &'static OnceLock<SyncSender<Span>> {
+    static EXPORTER: OnceLock<SyncSender<Span>> = OnceLock::new();
+    &EXPORTER
+}
+
+/**
+An open span, finished and queued for export when dropped. Spans opened while another one is
+open on the same thread become its children, so a client query, the referrals it follows and
+the upstream queries it sends end up in one trace. Without an exporter this does nothing.
+*/
+pub struct SpanGuard {
+    span: Option<Span>,
+    previous: Option<(u128, u64)>,
+    sender: Option<SyncSender<Span>>,
+}
+
+impl SpanGuard {
+    pub fn new(name: &'static str, kind: SpanKind, sender: Option<SyncSender<Span>>) -> SpanGuard {
+        let Some(sender) = sender else {
+            return SpanGuard { span: None, previous: None, sender: None };
+        };
+        let previous = CONTEXT.with(Cell::get);
+        let trace_id = previous.map(|(trace_id, _)| trace_id).unwrap_or_else(|| (random() as u128) << 64 | random() as u128);
+        let span_id = random().max(1);
+        CONTEXT.with(|context| context.set(Some((trace_id, span_id))));
+
+        let mut attributes = Vec::new();
+        if let Some(id) = trace::current() {
+            attributes.push(("r_dns.trace_id", format!("{:08x}", id)));
+        }
+        let span = Span {
+            trace_id,
+            span_id,
+            parent_id: previous.map(|(_, span_id)| span_id),
+            name,
+            kind,
+            start: unix_nanos(),
+            end: 0,
+            attributes,
+            error: false,
+        };
+        SpanGuard { span: Some(span), previous, sender: Some(sender) }
+    }
+
+    pub fn attr(&mut self, key: &'static str, value: impl ToString) {
+        if let Some(span) = &mut self.span {
+            span.attributes.push((key, value.to_string()));
+        }
+    }
+
+    pub fn error(&mut self, message: impl ToString) {
+        if let Some(span) = &mut self.span {
+            span.error = true;
+            span.attributes.push(("error.message", message.to_string()));
+        }
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let (Some(mut span), Some(sender)) = (self.span.take(), self.sender.take()) else {
+            return;
+        };
+        CONTEXT.with(|context| context.set(self.previous));
+        span.end = unix_nanos();
+        // A collector that can't keep up costs spans, never memory or query time
+        if let Err(TrySendError::Full(_)) = sender.try_send(span) {
+            counters().span_dropped();
+        }
+    }
+}
+
+pub fn span(name: &'static str, kind: SpanKind) -> SpanGuard {
+    SpanGuard::new(name, kind, exporter().get().cloned())
+}
+
+fn hex_id(id: u128, digits: usize) -> String {
+    format!("{:0width$x}", id, width = digits)
+}
+
+// The OTLP/HTTP JSON encoding of an ExportTraceServiceRequest
+pub fn encode(service_name: &str, spans: &[Span]) -> Value {
+    let spans: Vec<Value> = spans.iter().map(|span| {
+        let mut encoded = json!({
+            "traceId": hex_id(span.trace_id, 32),
+            "spanId": hex_id(span.span_id as u128, 16),
+            "name": span.name,
+            "kind": span.kind as u8,
+            "startTimeUnixNano": span.start.to_string(),
+            "endTimeUnixNano": span.end.to_string(),
+            "attributes": span.attributes.iter()
+                .map(|(key, value)| json!({"key": key, "value": {"stringValue": value}}))
+                .collect::<Vec<_>>(),
+            "status": {"code": if span.error { 2 } else { 1 }},
+        });
+        if let Some(parent_id) = span.parent_id {
+            encoded["parentSpanId"] = json!(hex_id(parent_id as u128, 16));
+        }
+        encoded
+    }).collect();
+
+    json!({"resourceSpans": [{
+        "resource": {"attributes": [{"key": "service.name", "value": {"stringValue": service_name}}]},
+        "scopeSpans": [{"scope": {"name": "r_dns"}, "spans": spans}],
+    }]})
+}
+
+fn post(config: &TelemetryConfig, body: &[u8]) -> io::Result<()> {
+    let addr = config.endpoint.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid OTLP endpoint {:?}", config.endpoint)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, EXPORT_TIMEOUT)?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    write!(stream, "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        config.path, config.endpoint, body.len())?;
+    stream.write_all(body)?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!("OTLP collector answered {:?}", status_line.trim()))),
+    }
+}
+
+fn export_loop(config: TelemetryConfig, spans: Receiver<Span>) {
+    let interval = Duration::from_secs(config.flush_interval.max(1));
+    let mut batch = Vec::new();
+    let mut last_flush = Instant::now();
+    loop {
+        let open = match spans.recv_timeout(interval.saturating_sub(last_flush.elapsed())) {
+            Ok(span) => {
+                batch.push(span);
+                true
+            }
+            Err(RecvTimeoutError::Timeout) => true,
+            Err(RecvTimeoutError::Disconnected) => false,
+        };
+        if !batch.is_empty() && (batch.len() >= config.batch_size || last_flush.elapsed() >= interval || !open) {
+            let body = serde_json::to_vec(&encode(&config.service_name, &batch)).unwrap();
+            if let Err(e) = post(&config, &body) {
+                warn!("Failed to export {} spans to {}: {}", batch.len(), config.endpoint, e);
+            }
+            batch.clear();
+        }
+        if last_flush.elapsed() >= interval {
+            last_flush = Instant::now();
+        }
+        if !open {
+            return;
+        }
+    }
+}
+
+// Spans are batched on a background thread, a slow or missing collector never holds up queries
+pub fn start(config: &TelemetryConfig) {
+    let (sender, receiver) = mpsc::sync_channel(config.queue_size.max(1));
+    if exporter().set(sender).is_err() {
+        return;
+    }
+    info!("Exporting traces to {}{}", config.endpoint, config.path);
+    let config = config.clone();
+    thread::spawn(move || export_loop(config, receiver));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_nested_spans() {
+        let (sender, receiver) = mpsc::sync_channel(16);
+        {
+            let _root = SpanGuard::new("handle_query", SpanKind::Server, Some(sender.clone()));
+            {
+                let mut child = SpanGuard::new("upstream.send", SpanKind::Client, Some(sender.clone()));
+                child.attr("server", "192.0.2.53");
+            }
+            let _sibling = SpanGuard::new("cache.insert", SpanKind::Internal, Some(sender.clone()));
+        }
+        let _inert = SpanGuard::new("disabled", SpanKind::Internal, None);
+
+        let spans: Vec<Span> = receiver.try_iter().collect();
+        assert_eq!(spans.iter().map(|span| span.name).collect::<Vec<_>>(), vec!["upstream.send", "cache.insert", "handle_query"]);
+        assert!(spans.iter().all(|span| span.trace_id == spans[2].trace_id));
+        assert_eq!(spans[0].parent_id, Some(spans[2].span_id));
+        assert_eq!(spans[1].parent_id, Some(spans[2].span_id));
+        assert_eq!(spans[2].parent_id, None);
+        assert_eq!(spans[0].attributes, vec![("server", "192.0.2.53".to_string())]);
+        assert_eq!(CONTEXT.with(Cell::get), None);
+    }
+
+    #[test]
+    fn test_full_queue_drops_spans() {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let dropped = counters().totals().spans_dropped;
+        for _ in 0..3 {
+            drop(SpanGuard::new("handle_query", SpanKind::Server, Some(sender.clone())));
+        }
+        assert_eq!(receiver.try_iter().count(), 1);
+        assert!(counters().totals().spans_dropped >= dropped + 2);
+    }
+
+    #[test]
+    fn test_encode_and_export() {
+        let span = Span {
+            trace_id: 0xabc,
+            span_id: 0x12,
+            parent_id: Some(0x34),
+            name: "recursive_lookup",
+            kind: SpanKind::Internal,
+            start: 1_000,
+            end: 2_000,
+            attributes: vec![("qname", "example.com".to_string())],
+            error: true,
+        };
+        let encoded = encode("r_dns", std::slice::from_ref(&span));
+        let exported = &encoded["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(exported["traceId"], "00000000000000000000000000000abc");
+        assert_eq!(exported["parentSpanId"], "0000000000000034");
+        assert_eq!(exported["startTimeUnixNano"], "1000");
+        assert_eq!(exported["status"]["code"], 2);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = TelemetryConfig { endpoint: listener.local_addr().unwrap().to_string(), ..Default::default() };
+        let collector = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0u8; 1024];
+            while !String::from_utf8_lossy(&request).contains("recursive_lookup") {
+                let len = stream.read(&mut chunk).unwrap();
+                request.extend_from_slice(&chunk[..len]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        let (sender, receiver) = mpsc::sync_channel(1);
+        sender.send(span).unwrap();
+        drop(sender);
+        export_loop(config, receiver);
+        assert!(collector.join().unwrap().starts_with("POST /v1/traces HTTP/1.1\r\n"));
+    }
+}
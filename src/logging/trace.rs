@@ -0,0 +1,72 @@
+use std::cell::Cell;
+use std::io::{self, Write};
+
+use flexi_logger::DeferredNow;
+use log::Record;
+
+use crate::utils::random::random;
+
+thread_local! {
+    static TRACE_ID: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+// Clears the trace ID again when the query that set it is answered
+pub struct TraceGuard {
+    owner: bool,
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        if self.owner {
+            TRACE_ID.with(|id| id.set(None));
+        }
+    }
+}
+
+/**
+Tags everything logged on this thread with a fresh trace ID until the guard is dropped, so the
+referrals, retries and cache decisions of one slow lookup can be picked out of a busy log with
+grep. Queries the handler makes for itself while answering (safe search rewrites) keep the ID
+of the client query they belong to.
+*/
+pub fn start() -> TraceGuard {
+    TRACE_ID.with(|id| {
+        if id.get().is_some() {
+            return TraceGuard { owner: false };
+        }
+        id.set(Some(random() as u32));
+        TraceGuard { owner: true }
+    })
+}
+
+pub fn current() -> Option<u32> {
+    TRACE_ID.with(Cell::get)
+}
+
+// flexi_logger's default format with the trace ID in front of the message
+pub fn format(w: &mut dyn Write, _now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+    write!(w, "{} [{}] ", record.level(), record.module_path().unwrap_or("<unnamed>"))?;
+    if let Some(id) = current() {
+        write!(w, "[{:08x}] ", id)?;
+    }
+    write!(w, "{}", record.args())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_queries_share_the_id() {
+        assert_eq!(current(), None);
+        let outer = start();
+        let id = current().unwrap();
+        {
+            let _inner = start();
+            assert_eq!(current(), Some(id));
+        }
+        assert_eq!(current(), Some(id));
+        drop(outer);
+        assert_eq!(current(), None);
+    }
+}
@@ -1,35 +1,109 @@
-use std::borrow::BorrowMut;
-use std::net::{Ipv4Addr, UdpSocket};
-use std::{env, io};
-use cache::cache::{DnsCacheEntry, ThreadSafeDnsCache};
-use log::{info, error};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::io::Read;
+use std::path::Path;
+use std::{env, fs, io};
+use x25519_dalek::StaticSecret;
+use cache::backend::{CacheBackend, ShardedDnsCache};
+use cache::cache::{CacheConfig, DnsCache, ThreadSafeDnsCache};
+use cache::dump;
+use log::{info, error, warn};
 use flexi_logger::{Logger, FileSpec, Duplicate};
+use flexi_logger::writers::LogWriter;
 
 
 use utils::byte_buffer::ByteBuffer;
-use utils::packet::DnsPacket;
+use utils::packet::{set_parse_mode, DnsPacket};
 use utils::query_type::QueryType;
-use utils::question::DnsQuestion;
-use utils::record::DnsRecord;
-use utils::result_code::ResultCode;
+use resolver::benchmark;
+use resolver::compare::Comparison;
+use resolver::cookies::{client_cookies, ClientCookies};
+use resolver::forward::{forwarder, Forwarder, ResolverMode, UpstreamTransport};
+use resolver::recursive::{ip_preference, lookup, QUERY_TIMEOUT};
+use resolver::self_test;
+use resolver::socks::{socks_proxy, SocksProxy};
+use resolver::tcp_pool::{upstream_connections, TcpPools};
+use config::{ServerConfig, CONFIG_PATH};
+use logging::capture::PacketCapture;
+use logging::query_log::QueryLog;
+use logging::telemetry::{self, SpanKind};
+use logging::trace;
+use local::{catalog, dnssd, health};
+use local::hosts::LocalHosts;
+use server::handler::QueryHandler;
+use odoh::target::{odoh_target, OdohTarget};
+use utils::key_file::{from_hex, load_or_create_key};
+use utils::pcap::Capture;
+use utils::replay;
+use utils::wire::write_udp_message;
 
 pub mod utils;
 pub mod cache;
+pub mod resolver;
+pub mod config;
+pub mod logging;
+pub mod local;
+pub mod server;
+pub mod dnscrypt;
+pub mod odoh;
+pub mod filter;
+
+const CACHE_PATH: &str = "dns_cache.toml";
+const DEFAULT_CACHE_SIZE: usize = 16;
+const DEFAULT_UPDATE_INTERVAL_MS: u64 = 20;
+const DEFAULT_CACHE_STORE_INTERVAL: u64 = 120;
+// For `cache benchmark`, operations per thread and a cache too small for all its names
+const BENCHMARK_OPS: usize = 200_000;
+const BENCHMARK_CACHE_SIZE: usize = 2048;
+// How long `browse` waits for the answers to each round of queries
+const BROWSE_WAIT: Duration = Duration::from_secs(2);
 
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let daemon = take_flag(&mut args, "--daemon");
+    let pidfile = take_option(&mut args, "--pidfile")?;
 
-    let mut max_size: usize = 16;
-    let mut update_interval_ms: u64 = 20;
-    let mut cache_store_interval:u64 = 120;
+    let mut max_size: Option<usize> = None;
+    let mut update_interval_ms: u64 = DEFAULT_UPDATE_INTERVAL_MS;
+    let mut cache_store_interval:u64 = DEFAULT_CACHE_STORE_INTERVAL;
     let mut enable_cache = true;
 
-    if args.len() == 1 {}
+    if args.get(1).map(String::as_str) == Some("cache") {
+        return cache_command(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("decode") {
+        return decode_command(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("compare") {
+        return compare_command(&args, &ServerConfig::load(CONFIG_PATH)?);
+    }
+    if args.get(1).map(String::as_str) == Some("browse") {
+        return browse_command(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("check-config") {
+        return check_config_command(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("self-test") {
+        let config = ServerConfig::load(CONFIG_PATH)?;
+        configure_resolver(&config)?;
+        return self_test::run(&config.self_test).map_err(io::Error::other);
+    }
+    if args.get(1).map(String::as_str) == Some("replay") {
+        return replay_command(&args, &ServerConfig::load(CONFIG_PATH)?);
+    }
+    if args.get(1).map(String::as_str) == Some("zone") {
+        return zone_command(&args, &ServerConfig::load(CONFIG_PATH)?);
+    }
+    if args.get(1).map(String::as_str) == Some("--service") {
+        return service_command(&args);
+    }
+
     if args.len() == 2 {
         enable_cache = args[1].parse().expect("Invalid enable_cache");
     }
     else if args.len() == 3 {
-        max_size = args[1].parse().expect("Invalid max_size");
+        max_size = Some(args[1].parse().expect("Invalid max_size"));
         update_interval_ms = args[2].parse().expect("Invalid update_interval_ms");
         cache_store_interval = args[2].parse().expect("Invalid cache_store_interval");
     }
@@ -37,178 +111,435 @@ fn main() -> io::Result<()> {
         eprintln!("Usage: {} <max_size> <update_interval_ms> <cache_store_interval> \n Usage: {} <enable_cache>", args[0], args[0]);
         return Ok(());
     }
-    
-    let socket = UdpSocket::bind(("0.0.0.0", 2053))?;
-    let ts_cache = ThreadSafeDnsCache::new(max_size, std::time::Duration::from_millis(update_interval_ms), std::time::Duration::from_secs(cache_store_interval), "dns_cache.toml");
-    Logger::try_with_str("info").unwrap()
-        .log_to_file(FileSpec::default().directory("logs"))
-        .duplicate_to_stderr(Duplicate::All)
-        .start()
-        .unwrap();
+    let _pidfile = start_daemon(daemon, pidfile.as_deref())?;
+    serve(max_size, update_interval_ms, cache_store_interval, enable_cache, None)
+}
 
-    info!("Server started on port 2053");
-    info!("Cache Status: {:?}", enable_cache);
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != len
+}
 
-    loop {
-        match handle_query(socket.try_clone()?, &ts_cache, enable_cache) {
-            Ok(packet) => {
-                // ts_cache.cache.lock().unwrap().save_to_toml("dns_cache.toml").unwrap();
-                info!("Query {:?} handled successfully", packet.header.id);
-                for rec in packet.answers {
-                    info!("{:?}", rec);
-                }
-                for rec in packet.authorities {
-                    info!("{:?}", rec);
-                }
-                for rec in packet.resources {
-                    info!("{:?}", rec);
-                }
-            }
-            Err(e) => {
-                error!("Error handling query: {:?}", e);
-            }
-        }
+fn take_option(args: &mut Vec<String>, option: &str) -> io::Result<Option<String>> {
+    let Some(i) = args.iter().position(|arg| arg == option) else {
+        return Ok(None);
+    };
+    if i + 1 == args.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} needs a value", option)));
     }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(value))
 }
 
-fn recursive_lookup(qname: &str, qtype: QueryType) -> io::Result<DnsPacket> {
-    let mut root_server = "198.41.0.4".parse::<Ipv4Addr>().unwrap();
-
-    loop {
-        let copy = root_server;
-        let server = (copy, 53);
-
-        let res = lookup(qname, qtype, server)?;
-
-        if res.answers.len() > 0 && res.header.rescode == ResultCode::NOERROR {
-            return Ok(res);
-        }
+// `--daemon` and `--pidfile`, the pidfile is locked before forking so a second instance fails on the terminal
+#[cfg(unix)]
+fn start_daemon(daemon: bool, pidfile: Option<&str>) -> io::Result<Option<server::daemon::Pidfile>> {
+    let mut pidfile = pidfile.map(server::daemon::Pidfile::lock).transpose()?;
+    if daemon {
+        server::daemon::detach()?;
+    }
+    if let Some(pidfile) = pidfile.as_mut() {
+        pidfile.write_pid()?;
+    }
+    Ok(pidfile)
+}
 
-        if res.answers.is_empty() && res.header.rescode == ResultCode::NXDOMAIN {
-            return Ok(res);
-        }
+#[cfg(not(unix))]
+fn start_daemon(daemon: bool, pidfile: Option<&str>) -> io::Result<()> {
+    if daemon || pidfile.is_some() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "--daemon and --pidfile are only supported on Unix, use --service on Windows"));
+    }
+    Ok(())
+}
 
-        if let Some(addr) = res.get_resolved_ns(qname) {
-            root_server = addr;
-            continue;
-        }
+// `browse [type] [seconds]`: the DNS-SD services on the link, of every type when none is given
+fn browse_command(args: &[String]) -> io::Result<()> {
+    let service_type = args.get(2).map(String::as_str).filter(|service_type| *service_type != "all");
+    let wait = match args.get(3) {
+        Some(seconds) => Duration::from_secs_f64(seconds.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid number of seconds"))?),
+        None => BROWSE_WAIT,
+    };
+    for service in dnssd::browse(service_type, wait)? {
+        let addrs: Vec<String> = service.addrs.iter().map(IpAddr::to_string).collect();
+        println!("{}\t{}:{}\t{}\t{}", service.instance, service.host, service.port, addrs.join(","), service.txt.join(" "));
+    }
+    Ok(())
+}
 
-        let new_qname = match res.get_unresolved_ns(qname) {
-            Some(cname) => cname,
-            None => return Ok(res),
-        };
+// `check-config [file]`: reports every problem with the config and the files it names, without starting anything
+fn check_config_command(args: &[String]) -> io::Result<()> {
+    let path = args.get(2).map(String::as_str).unwrap_or(CONFIG_PATH);
+    let errors = match ServerConfig::parse(&fs::read_to_string(path)?) {
+        Ok(config) => config.check(),
+        Err(e) => vec![format!("{}: {}", path, e)],
+    };
+    if errors.is_empty() {
+        println!("{} is valid", path);
+        return Ok(());
+    }
+    for error in &errors {
+        eprintln!("{}", error);
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} error(s) in {}", errors.len(), path)))
+}
 
-        let rec = recursive_lookup(&new_qname, QueryType::A)?;
-        if let Some(ns) = rec.get_random_a() {
-            root_server = ns;
-            continue;
+// Sets up the parser and the resolver the way the config asks, everything `resolver::resolve` relies on
+fn configure_resolver(config: &ServerConfig) -> io::Result<()> {
+    set_parse_mode(config.parsing.mode);
+    let _ = ip_preference().set(config.recursion.ip_preference);
+    if config.upstream.mode != ResolverMode::Recursive {
+        let _ = forwarder().set(Forwarder::from_config(&config.upstream)?);
+    }
+    if config.cookies.upstream {
+        let _ = client_cookies().set(ClientCookies::new());
+    }
+    if config.socks5.enabled {
+        let _ = socks_proxy().set(SocksProxy::from_config(&config.socks5, QUERY_TIMEOUT)?);
+    }
+    if forwarder().get().is_some() && config.upstream.transport == UpstreamTransport::Tcp {
+        // Pooled connections go straight to the upstreams, which would leak around the proxy
+        if config.socks5.enabled {
+            warn!("Ignoring the tcp upstream transport, queries go through the SOCKS5 proxy");
         } else {
-            return Ok(res);
+            let _ = upstream_connections().set(TcpPools::new(config.upstream.tcp_connections));
         }
     }
+    Ok(())
 }
 
-fn lookup(qname: &str, qtype: QueryType, server: (Ipv4Addr, u16)) -> io::Result<DnsPacket> {
+// Runs the server until the process ends, a service sends warnings and errors to `log_writer`
+fn serve(max_size: Option<usize>, update_interval_ms: u64, cache_store_interval: u64, enable_cache: bool, log_writer: Option<Box<dyn LogWriter>>) -> io::Result<()> {
+    let config = ServerConfig::load(CONFIG_PATH)?;
+    configure_resolver(&config)?;
+    if config.telemetry.enabled {
+        telemetry::start(&config.telemetry);
+    }
+    if let (Some(forwarder), true) = (forwarder().get(), config.upstream.benchmark) {
+        benchmark::start(forwarder, config.upstream.benchmark_name.clone(), Duration::from_secs(config.upstream.benchmark_interval));
+    }
+    let socket = UdpSocket::bind(&config.server.listen)?;
+    let max_size = max_size.or(config.cache.size).unwrap_or(DEFAULT_CACHE_SIZE);
+    let ts_cache = ThreadSafeDnsCache::new(max_size, std::time::Duration::from_millis(update_interval_ms), std::time::Duration::from_secs(cache_store_interval), CACHE_PATH, &config.cache);
+    let logger = Logger::try_with_env_or_str(&config.server.log_level)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid log_level {}: {}", config.server.log_level, e)))?
+        .format(trace::format);
+    let logger = match log_writer {
+        Some(log_writer) => logger.log_to_file_and_writer(FileSpec::default().directory("logs"), log_writer),
+        None => logger.log_to_file(FileSpec::default().directory("logs")).duplicate_to_stderr(Duplicate::All),
+    };
+    let _logger = logger.start().map_err(io::Error::other)?;
 
-    let socket = match UdpSocket::bind(("0.0.0.0", 43210)) {
-        Ok(s) => s,
-        Err(e) => {
-            return Err(e);
+    info!("Server started on {}", config.server.listen);
+    info!("Cache Status: {:?}", enable_cache);
+    info!("Resolver mode: {:?}", config.upstream.mode);
+    if config.self_test.enabled {
+        if let Err(diagnostic) = self_test::run(&config.self_test) {
+            error!("Self test failed: {}", diagnostic);
+            if config.self_test.exit_on_failure {
+                return Err(io::Error::other(format!("Self test failed: {}", diagnostic)));
+            }
         }
-    };
+    }
 
-    let mut packet = DnsPacket::new();
-    packet.header.id = 6666;
-    packet.header.questions = 1;
-    packet.header.recursion_desired = true;
-    packet.questions.push(DnsQuestion::new(qname.to_string(), qtype));
+    let query_log = if config.query_log.enabled {
+        info!("Logging queries to {}", config.query_log.path);
+        Some(QueryLog::open(config.query_log.clone())?)
+    } else {
+        None
+    };
 
-    let mut req_buffer = ByteBuffer::new();
-    packet.write(&mut req_buffer).unwrap();
+    let capture = if config.capture.enabled {
+        info!("Capturing queries and responses to {}", config.capture.path);
+        Some(PacketCapture::open(config.capture.clone())?)
+    } else {
+        None
+    };
 
-    socket.send_to(&req_buffer.buffer[0..req_buffer.position], server).unwrap();
+    let hosts = if config.hosts.enabled {
+        Some(LocalHosts::start(&config.hosts))
+    } else {
+        None
+    };
 
-    let mut res_buffer = ByteBuffer::new();
-    socket.recv_from(&mut res_buffer.buffer).unwrap();
+    let handler = QueryHandler::new(ts_cache, enable_cache, hosts, &config)?;
+    if let Some(pools) = &handler.pools {
+        health::start(pools);
+    }
+    if let Some(zones) = &handler.zones {
+        catalog::start(&config.catalogs, zones)?;
+    }
+    if let (true, Some(zones)) = (config.standby.enabled, &handler.zones) {
+        server::standby::start(&config.standby, handler.cache.clone(), zones.clone());
+    }
+    if let Some(dnssd) = &handler.dnssd {
+        server::mdns::start(dnssd)?;
+    }
+    if config.llmnr.enabled {
+        server::llmnr::start(&config.llmnr, handler.hosts.clone())?;
+    }
+    if config.odoh.enabled {
+        if !config.http.enabled {
+            warn!("ODoH is served on the HTTP API, enable [http] to use it");
+        }
+        let target = OdohTarget::new(StaticSecret::from(load_or_create_key(&config.odoh.key_file)?));
+        let _ = odoh_target().set(target);
+    }
+    if config.http.enabled {
+        server::http::start(&config.http, handler.clone())?;
+    }
+    if config.tcp.enabled {
+        server::tcp::start(&config.tcp, handler.clone())?;
+    }
+    if config.dnscrypt.enabled {
+        dnscrypt::server::start(&config.dnscrypt, handler.clone())?;
+    }
+    #[cfg(unix)]
+    if config.unix.enabled {
+        server::unix::start(&config.unix, handler.clone())?;
+    }
 
-    let res_packet = DnsPacket::from_buffer(&mut res_buffer).unwrap();
+    // Shared with the UDP workers when there are any
+    let logs = Arc::new((Mutex::new(query_log), Mutex::new(capture)));
+    if config.overload.enabled {
+        let (answer_handler, answer_logs) = (handler.clone(), Arc::clone(&logs));
+        return server::overload::serve_udp(socket, &config.overload, 512, move |socket, query, src| {
+            report(answer_datagram(socket, &answer_handler, query, src, &answer_logs.0, &answer_logs.1, false));
+        }, |socket, query, src| {
+            report(answer_datagram(socket, &handler, query, src, &logs.0, &logs.1, true));
+        });
+    }
+    loop {
+        report(handle_query(&socket, &handler, &logs.0, &logs.1));
+    }
+}
 
-    Ok(res_packet)
+fn report(result: io::Result<DnsPacket>) {
+    match result {
+        Ok(packet) => {
+            // ts_cache.cache.lock().unwrap().save_to_toml("dns_cache.toml").unwrap();
+            info!("Query {:?} handled successfully", packet.header.id);
+            for rec in packet.answers {
+                info!("{:?}", rec);
+            }
+            for rec in packet.authorities {
+                info!("{:?}", rec);
+            }
+            for rec in packet.resources {
+                info!("{:?}", rec);
+            }
+        }
+        Err(e) => {
+            error!("Error handling query: {:?}", e);
+        }
+    }
+}
 
+// `cache export` prints the saved cache, `cache import <file>` adds a dump to it. Run these
+// with the server stopped, it would overwrite the file with its own copy on the next save.
+fn cache_command(args: &[String]) -> io::Result<()> {
+    let mut cache = DnsCache::load_from_toml(CACHE_PATH).unwrap_or_else(|_| DnsCache::new(DEFAULT_CACHE_SIZE));
+    match (args.get(2).map(String::as_str), args.get(3)) {
+        (Some("export"), None) => {
+            let count = dump::export(&cache, &mut io::stdout().lock())?;
+            eprintln!("Exported {} entries", count);
+        }
+        (Some("import"), Some(path)) => {
+            let count = dump::import(&mut cache, &fs::read_to_string(path)?)?;
+            cache.save_to_toml(CACHE_PATH)?;
+            eprintln!("Imported {} entries", count);
+        }
+        (Some("benchmark"), threads) => {
+            let threads = match threads {
+                Some(threads) => threads.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+                None => 4,
+            };
+            let config = CacheConfig::default();
+            let backends: [(&str, Box<dyn CacheBackend>); 2] = [
+                ("mutex", Box::new(Mutex::new(DnsCache::new(BENCHMARK_CACHE_SIZE)))),
+                ("sharded", Box::new(ShardedDnsCache::new(BENCHMARK_CACHE_SIZE, config.shards, &config))),
+            ];
+            for (name, backend) in backends {
+                let elapsed = cache::backend::benchmark(&*backend, threads, BENCHMARK_OPS);
+                let total = threads * BENCHMARK_OPS;
+                println!("{:<8} {} operations on {} threads in {:?}, {:.0} per second", name, total, threads, elapsed, total as f64 / elapsed.as_secs_f64());
+            }
+        }
+        _ => eprintln!("Usage: {} cache export\n Usage: {} cache import <file>\n Usage: {} cache benchmark [threads]", args[0], args[0], args[0]),
+    }
+    Ok(())
 }
 
-fn handle_query(socket: UdpSocket, cache: &ThreadSafeDnsCache, enable_cache: bool) -> io::Result<DnsPacket> {
-    info!("Handling query");
-    let mut req_buffer = ByteBuffer::new();
-    let (_, src) = socket.recv_from(&mut req_buffer.buffer).unwrap();
-    let mut request = DnsPacket::from_buffer(&mut req_buffer).unwrap();
+// Prints the byte by byte breakdown of one message, read from a file of hex digits (whitespace is
+// ignored) or of the raw message as it was in a UDP payload, `-` reads it from stdin
+fn decode_command(args: &[String]) -> io::Result<()> {
+    let data = match args.get(2).map(String::as_str) {
+        Some("-") => {
+            let mut data = Vec::new();
+            io::stdin().lock().read_to_end(&mut data)?;
+            data
+        }
+        Some(path) => fs::read(path)?,
+        None => {
+            eprintln!("Usage: {} decode <hexfile|payload|->", args[0]);
+            return Ok(());
+        }
+    };
+    let hex: Option<String> = std::str::from_utf8(&data).ok().map(|text| text.split_whitespace().collect());
+    let message = match hex.as_deref().and_then(from_hex) {
+        Some(message) if !message.is_empty() => message,
+        _ => data,
+    };
+    print!("{}", DnsPacket::explain(&message));
+    Ok(())
+}
 
-    let mut response = DnsPacket::new();
-    response.header.id = request.header.id;
-    response.header.recursion_desired = true;
-    response.header.recursion_available = true;
-    response.header.response = true;
+// Resolves a name with the configured resolver and asks a reference server the same, then lists
+// where the answers differ. Fails when the rcodes or the records do, TTLs are only shown.
+fn compare_command(args: &[String], config: &ServerConfig) -> io::Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (name, qtype, server) = match &args[2..] {
+        [name, qtype, "--against", server] => (name.trim_end_matches('.').to_ascii_lowercase(), *qtype, *server),
+        _ => {
+            eprintln!("Usage: {} compare <name> <type> --against <server[:port]>", args[0]);
+            return Ok(());
+        }
+    };
+    let qtype = QueryType::from_name(qtype).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown type {}", qtype)))?;
+    let server: SocketAddr = server.parse()
+        .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid server {}", server)))?;
+
+    configure_resolver(config)?;
+    let local = resolver::resolve(&name, qtype)?;
+    let reference = lookup(&name, qtype, server)?;
+    let comparison = Comparison::new(&local, &reference);
+    print!("{}", comparison);
+    if !comparison.is_match() {
+        return Err(io::Error::other(format!("The answers for {} {} differ from {}", name, qtype.name(), server)));
+    }
+    println!("{} {} answers the same as {}", name, qtype.name(), server);
+    Ok(())
+}
 
-    if let Some(q) = request.questions.pop() {
+// Checks every DNS message in a pcap capture parses and re-encodes cleanly, with the parse mode
+// from the config. Problem messages are saved to the corpus directory when one is given.
+fn replay_command(args: &[String], config: &ServerConfig) -> io::Result<()> {
+    let Some(path) = args.get(2) else {
+        eprintln!("Usage: {} replay <capture.pcap> [corpus-dir]", args[0]);
+        return Ok(());
+    };
+    set_parse_mode(config.parsing.mode);
+    let data = fs::read(path)?;
+    let capture = Capture::parse(&data)?;
+    let report = replay::replay(&capture, args.get(3).map(Path::new), &mut io::stdout().lock())?;
+    eprintln!("{} DNS messages, {} parse failures, {} re-encode mismatches", report.messages, report.parse_failures, report.mismatches);
+    Ok(())
+}
 
-        let key = format!("{}-{:?}", q.name, q.qtype.to_num());
-        if enable_cache {
-            if let Some(entry) = cache.get(&key) {
-                let mut response = entry.get_packet().unwrap();
+// `--service` is how the Service Control Manager starts the server, `--service install` and
+// `--service uninstall` register it with Windows and remove it again
+#[cfg(windows)]
+fn service_command(args: &[String]) -> io::Result<()> {
+    match args.get(2).map(String::as_str) {
+        None => server::service::run(|| {
+            let event_log = server::service::EventLog::register()?;
+            serve(None, DEFAULT_UPDATE_INTERVAL_MS, DEFAULT_CACHE_STORE_INTERVAL, true, Some(Box::new(event_log)))
+        }),
+        Some("install") => server::service::install(),
+        Some("uninstall") => server::service::uninstall(),
+        Some(_) => {
+            eprintln!("Usage: {0} --service\n Usage: {0} --service install\n Usage: {0} --service uninstall", args[0]);
+            Ok(())
+        }
+    }
+}
 
-                response.header.id = request.header.id;
+#[cfg(not(windows))]
+fn service_command(_args: &[String]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Running as a service is only supported on Windows"))
+}
 
-                let mut res_buffer = ByteBuffer::new();
-                response.write(&mut res_buffer).unwrap();
-                socket.send_to(&res_buffer.buffer[0..res_buffer.position], src).unwrap();
-                return Ok(response);
-            }
+// Edits zones on the running server through the HTTP API, records are quoted zone file lines
+fn zone_command(args: &[String], config: &ServerConfig) -> io::Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (method, target, body) = match &args[2..] {
+        ["list"] => ("GET", "/zones".to_string(), String::new()),
+        ["show", zone] => ("GET", format!("/zones/{}", zone), String::new()),
+        ["add", zone, records @ ..] if !records.is_empty() => ("POST", format!("/zones/{}/records", zone), records.join("\n")),
+        ["replace", zone, name, qtype, records @ ..] => ("PUT", format!("/zones/{}/records?name={}&type={}", zone, name, qtype), records.join("\n")),
+        ["delete", zone, name] => ("DELETE", format!("/zones/{}/records?name={}", zone, name), String::new()),
+        ["delete", zone, name, qtype] => ("DELETE", format!("/zones/{}/records?name={}&type={}", zone, name, qtype), String::new()),
+        _ => {
+            eprintln!("Usage: {0} zone list\n Usage: {0} zone show <zone>\n Usage: {0} zone add <zone> <record>...\n Usage: {0} zone replace <zone> <name> <type> [record]...\n Usage: {0} zone delete <zone> <name> [type]", args[0]);
+            return Ok(());
         }
+    };
 
-        if let Ok(result) = recursive_lookup(&q.name, q.qtype) {
-            response.questions.push(q);
-            response.header.rescode = result.header.rescode;
+    let (status, body) = server::admin::request(&config.http.listen, &config.http.admin_token, method, &target, &body)?;
+    if status == 200 {
+        print!("{}", body);
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("Server answered {}: {}", status, body.trim())))
+    }
+}
 
-            for rec in result.answers {
-                // println!("Answer: {:?}", rec);
-                response.answers.push(rec);
-            }
-            for rec in result.authorities {
-                // println!("Authority: {:?}", rec);
-                response.authorities.push(rec);
-            }
-            for rec in result.resources {
-                // println!("Resource: {:?}", rec);
-                response.resources.push(rec);
-            }
-        } else {
-            response.header.rescode = ResultCode::SERVFAIL;
+fn log_query(query_log: &Mutex<Option<QueryLog>>, src: SocketAddr, packet: &DnsPacket, start: Instant) {
+    if let Some(query_log) = query_log.lock().unwrap().as_mut() {
+        if let Err(e) = query_log.log(src, packet, start.elapsed()) {
+            error!("Failed to write query log: {:?}", e);
         }
-    } else {
-        response.header.rescode = ResultCode::FORMERR;
-    }
-
-    let mut res_buffer = ByteBuffer::new();
-    response.write(&mut res_buffer).unwrap();
-    socket.send_to(&res_buffer.buffer[0..res_buffer.position], src).unwrap();
-
-    let ttl = match response.answers.get(0) {
-        Some(rec) => {
-            match rec {
-                DnsRecord::A { ttl, .. } => *ttl,
-                DnsRecord::AAAA { ttl, .. } => *ttl,
-                DnsRecord::CNAME { ttl, .. } => *ttl,
-                DnsRecord::NS { ttl, .. } => *ttl,
-                DnsRecord::MX { ttl, .. } => *ttl,
-                DnsRecord::UNKNOWN { ttl, .. } => *ttl,
-            }
+    }
+}
+
+fn capture_message(capture: &Mutex<Option<PacketCapture>>, from: SocketAddr, to: SocketAddr, message: &[u8]) {
+    if let Some(capture) = capture.lock().unwrap().as_mut() {
+        if let Err(e) = capture.write(from, to, message) {
+            error!("Failed to write packet capture: {:?}", e);
         }
-        None => 60,
-    } as u32;
+    }
+}
 
-    let entry = DnsCacheEntry::from_packet(&response, ttl)?;
-    cache.insert(format!("{}-{:?}", response.questions[0].name, response.questions[0].qtype.to_num()), entry).unwrap();
+fn handle_query(socket: &UdpSocket, handler: &QueryHandler, query_log: &Mutex<Option<QueryLog>>, capture: &Mutex<Option<PacketCapture>>) -> io::Result<DnsPacket> {
+    let mut req_buffer = ByteBuffer::new();
+    let (len, src) = socket.recv_from(&mut req_buffer.buffer)?;
+    answer_datagram(socket, handler, &req_buffer.buffer[..len], src, query_log, capture, false)
+}
 
-    return Ok(response)
+// A shed query is answered without a lookup, see `QueryHandler::shed`
+fn answer_datagram(socket: &UdpSocket, handler: &QueryHandler, query: &[u8], src: SocketAddr, query_log: &Mutex<Option<QueryLog>>, capture: &Mutex<Option<PacketCapture>>, shed: bool) -> io::Result<DnsPacket> {
+    let local = socket.local_addr()?;
+    capture_message(capture, src, local, query);
+    let start = Instant::now();
+    let mut span = telemetry::span("handle_query", SpanKind::Server);
+    span.attr("client.address", src.ip());
+    let request = DnsPacket::from_bytes(query);
+    if let Some(question) = request.as_ref().ok().and_then(|request| request.questions.first()) {
+        span.attr("dns.qname", &question.name);
+        span.attr("dns.qtype", format!("{:?}", question.qtype));
+    }
 
-}
\ No newline at end of file
+    let response = if handler.transparent && !shed {
+        let response = handler.relay(query, Some(src.ip()))?;
+        socket.send_to(&response, src)?;
+        capture_message(capture, local, src, &response);
+        // Decoded for the logs only, the client already has the answer as it came
+        DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(&response[..response.len().min(512)])).unwrap_or_else(|_| DnsPacket::new())
+    } else {
+        let response = match request {
+            Ok(request) if shed => handler.shed(request),
+            Ok(request) => handler.answer_udp(request, src.ip())?,
+            Err(e) => DnsPacket::format_error(query).ok_or(e)?,
+        };
+        let message = write_udp_message(&response)?;
+        socket.send_to(&message, src)?;
+        capture_message(capture, local, src, &message);
+        response
+    };
+    log_query(query_log, src, &response, start);
+    span.attr("dns.rcode", format!("{:?}", response.header.rescode));
+
+    Ok(response)
+}
@@ -0,0 +1,159 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::Aes128Gcm;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/*
+The single HPKE (RFC 9180) suite ODoH deployments use, base mode only:
+DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and AES-128-GCM.
+*/
+pub const KEM_ID: u16 = 0x0020;
+pub const KDF_ID: u16 = 0x0001;
+pub const AEAD_ID: u16 = 0x0001;
+pub const N_ENC: usize = 32;
+pub const N_K: usize = 16;
+pub const N_N: usize = 12;
+pub const N_H: usize = 32;
+
+const MODE_BASE: u8 = 0;
+
+fn kem_suite_id() -> Vec<u8> {
+    let mut suite_id = b"KEM".to_vec();
+    suite_id.extend_from_slice(&KEM_ID.to_be_bytes());
+    suite_id
+}
+
+fn hpke_suite_id() -> Vec<u8> {
+    let mut suite_id = b"HPKE".to_vec();
+    suite_id.extend_from_slice(&KEM_ID.to_be_bytes());
+    suite_id.extend_from_slice(&KDF_ID.to_be_bytes());
+    suite_id.extend_from_slice(&AEAD_ID.to_be_bytes());
+    suite_id
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Vec<u8> {
+    let labeled_ikm = [b"HPKE-v1", suite_id, label, ikm].concat();
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), &labeled_ikm);
+    prk.to_vec()
+}
+
+fn labeled_expand(prk: &[u8], suite_id: &[u8], label: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let labeled_info = [&(len as u16).to_be_bytes()[..], b"HPKE-v1", suite_id, label, info].concat();
+    let mut out = vec![0; len];
+    let hkdf = Hkdf::<Sha256>::from_prk(prk).expect("PRKs are always Nh bytes");
+    hkdf.expand(&labeled_info, &mut out).expect("output lengths are fixed by the suite");
+    out
+}
+
+fn extract_and_expand(dh: &[u8], kem_context: &[u8]) -> Vec<u8> {
+    let suite_id = kem_suite_id();
+    let prk = labeled_extract(&suite_id, b"", b"eae_prk", dh);
+    labeled_expand(&prk, &suite_id, b"shared_secret", kem_context, N_H)
+}
+
+pub struct Context {
+    key: Vec<u8>,
+    base_nonce: Vec<u8>,
+    exporter_secret: Vec<u8>,
+    seq: u64,
+}
+
+impl Context {
+    fn key_schedule(shared_secret: &[u8], info: &[u8]) -> Context {
+        let suite_id = hpke_suite_id();
+        let mut context = vec![MODE_BASE];
+        context.extend_from_slice(&labeled_extract(&suite_id, b"", b"psk_id_hash", b""));
+        context.extend_from_slice(&labeled_extract(&suite_id, b"", b"info_hash", info));
+
+        let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+        Context {
+            key: labeled_expand(&secret, &suite_id, b"key", &context, N_K),
+            base_nonce: labeled_expand(&secret, &suite_id, b"base_nonce", &context, N_N),
+            exporter_secret: labeled_expand(&secret, &suite_id, b"exp", &context, N_H),
+            seq: 0,
+        }
+    }
+
+    fn nonce(&self) -> Vec<u8> {
+        let mut nonce = self.base_nonce.clone();
+        for (byte, seq) in nonce.iter_mut().rev().zip(self.seq.to_le_bytes()) {
+            *byte ^= seq;
+        }
+        nonce
+    }
+
+    pub fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
+        let cipher = Aes128Gcm::new_from_slice(&self.key).ok()?;
+        let ciphertext = cipher.encrypt(self.nonce().as_slice().into(), Payload { msg: plaintext, aad }).ok()?;
+        self.seq += 1;
+        Some(ciphertext)
+    }
+
+    pub fn open(&mut self, aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let cipher = Aes128Gcm::new_from_slice(&self.key).ok()?;
+        let plaintext = cipher.decrypt(self.nonce().as_slice().into(), Payload { msg: ciphertext, aad }).ok()?;
+        self.seq += 1;
+        Some(plaintext)
+    }
+
+    pub fn export(&self, exporter_context: &[u8], len: usize) -> Vec<u8> {
+        labeled_expand(&self.exporter_secret, &hpke_suite_id(), b"sec", exporter_context, len)
+    }
+}
+
+// Sender side, the encapsulated key goes in front of the ciphertext
+pub fn setup_base_s(pk_r: &PublicKey, sk_e: &StaticSecret, info: &[u8]) -> ([u8; N_ENC], Context) {
+    let enc = PublicKey::from(sk_e).to_bytes();
+    let dh = sk_e.diffie_hellman(pk_r);
+    let kem_context = [&enc[..], pk_r.as_bytes()].concat();
+    let shared_secret = extract_and_expand(dh.as_bytes(), &kem_context);
+    (enc, Context::key_schedule(&shared_secret, info))
+}
+
+pub fn setup_base_r(enc: &[u8], sk_r: &StaticSecret, info: &[u8]) -> Option<Context> {
+    let enc: [u8; N_ENC] = enc.try_into().ok()?;
+    let dh = sk_r.diffie_hellman(&PublicKey::from(enc));
+    if !dh.was_contributory() {
+        return None;
+    }
+    let kem_context = [&enc[..], PublicKey::from(sk_r).as_bytes()].concat();
+    let shared_secret = extract_and_expand(dh.as_bytes(), &kem_context);
+    Some(Context::key_schedule(&shared_secret, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // RFC 9180 appendix A.1.1
+    #[test]
+    fn test_rfc9180_base_vector() {
+        let sk_e: [u8; 32] = from_hex("52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736").try_into().unwrap();
+        let sk_r: [u8; 32] = from_hex("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8").try_into().unwrap();
+        let sk_e = StaticSecret::from(sk_e);
+        let sk_r = StaticSecret::from(sk_r);
+        let info = from_hex("4f6465206f6e2061204772656369616e2055726e");
+
+        let (enc, mut sender) = setup_base_s(&PublicKey::from(&sk_r), &sk_e, &info);
+        assert_eq!(enc.to_vec(), from_hex("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431"));
+        assert_eq!(sender.key, from_hex("4531685d41d65f03dc48f6b8302c05b0"));
+        assert_eq!(sender.base_nonce, from_hex("56d890e5accaaf011cff4b7d"));
+
+        let plaintext = from_hex("4265617574792069732074727574682c20747275746820626561757479");
+        let aad = from_hex("436f756e742d30");
+        let ciphertext = sender.seal(&aad, &plaintext).unwrap();
+        assert_eq!(
+            ciphertext,
+            from_hex("f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a96d8770ac83d07bea87e13c512a")
+        );
+
+        let mut receiver = setup_base_r(&enc, &sk_r, &info).unwrap();
+        assert_eq!(receiver.open(&aad, &ciphertext).unwrap(), plaintext);
+        assert_eq!(receiver.export(b"test", 16), sender.export(b"test", 16));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod hpke;
+pub mod target;
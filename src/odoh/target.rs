@@ -0,0 +1,318 @@
+use std::io;
+use std::sync::OnceLock;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::Aes128Gcm;
+use hkdf::Hkdf;
+use log::warn;
+use serde::Deserialize;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::odoh::hpke::{self, Context};
+use crate::server::handler::QueryHandler;
+use crate::server::http::{HttpRequest, HttpResponse};
+use crate::utils::packet::DnsPacket;
+use crate::utils::wire::write_large_message;
+
+pub const CONTENT_TYPE: &str = "application/oblivious-dns-message";
+pub const CONFIGS_PATH: &str = "/.well-known/odohconfigs";
+pub const ODOH_VERSION: u16 = 0x0001;
+const QUERY: u8 = 0x01;
+const RESPONSE: u8 = 0x02;
+// max(Nn, Nk) for AES-128-GCM
+const RESPONSE_NONCE_LEN: usize = 16;
+const PADDING_BLOCK: usize = 128;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OdohConfig {
+    pub enabled: bool,   // served on the HTTP API, which has to be enabled too
+    pub key_file: String, // hex X25519 secret key, generated on first start
+}
+
+impl Default for OdohConfig {
+    fn default() -> Self {
+        OdohConfig {
+            enabled: false,
+            key_file: "odoh.key".to_string(),
+        }
+    }
+}
+
+fn write_vec16(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn read_vec16<'a>(data: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len = u16::from_be_bytes([*data.get(*pos)?, *data.get(*pos + 1)?]) as usize;
+    let value = data.get(*pos + 2..*pos + 2 + len)?;
+    *pos += 2 + len;
+    Some(value)
+}
+
+fn padded_plaintext(dns_message: &[u8]) -> Vec<u8> {
+    let padding = (PADDING_BLOCK - dns_message.len() % PADDING_BLOCK) % PADDING_BLOCK;
+    let mut out = Vec::with_capacity(dns_message.len() + padding + 4);
+    write_vec16(&mut out, dns_message);
+    write_vec16(&mut out, &vec![0; padding]);
+    out
+}
+
+fn dns_message(plaintext: &[u8]) -> Option<&[u8]> {
+    let mut pos = 0;
+    let message = read_vec16(plaintext, &mut pos)?;
+    let padding = read_vec16(plaintext, &mut pos)?;
+    if pos != plaintext.len() || padding.iter().any(|&byte| byte != 0) {
+        return None;
+    }
+    Some(message)
+}
+
+// What the target needs to keep from a query to encrypt the matching response
+pub struct QueryContext {
+    context: Context,
+    plaintext: Vec<u8>,
+}
+
+/**
+Oblivious DoH target (RFC 9230). Clients fetch our HPKE public key from the well-known
+configs path, encrypt their queries to it and hand them to an oblivious proxy, so the proxy
+sees who is asking but not what, and we see what is asked but not by whom.
+*/
+pub struct OdohTarget {
+    secret: StaticSecret,
+    config_contents: Vec<u8>,
+    key_id: Vec<u8>,
+}
+
+impl OdohTarget {
+    pub fn new(secret: StaticSecret) -> OdohTarget {
+        let mut config_contents = Vec::new();
+        config_contents.extend_from_slice(&hpke::KEM_ID.to_be_bytes());
+        config_contents.extend_from_slice(&hpke::KDF_ID.to_be_bytes());
+        config_contents.extend_from_slice(&hpke::AEAD_ID.to_be_bytes());
+        write_vec16(&mut config_contents, PublicKey::from(&secret).as_bytes());
+
+        let (_, hkdf) = Hkdf::<Sha256>::extract(Some(b""), &config_contents);
+        let mut key_id = vec![0; hpke::N_H];
+        hkdf.expand(b"odoh key id", &mut key_id).expect("Nh is a valid HKDF output length");
+
+        OdohTarget { secret, config_contents, key_id }
+    }
+
+    pub fn key_id(&self) -> &[u8] {
+        &self.key_id
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.secret)
+    }
+
+    // ObliviousDoHConfigs with our single configuration
+    pub fn configs(&self) -> Vec<u8> {
+        let mut config = ODOH_VERSION.to_be_bytes().to_vec();
+        write_vec16(&mut config, &self.config_contents);
+        let mut configs = Vec::new();
+        write_vec16(&mut configs, &config);
+        configs
+    }
+
+    pub fn decrypt_query(&self, message: &[u8]) -> Result<(Vec<u8>, QueryContext), u16> {
+        let mut pos = 1;
+        if message.first() != Some(&QUERY) {
+            return Err(400);
+        }
+        let key_id = read_vec16(message, &mut pos).ok_or(400u16)?;
+        let encrypted = read_vec16(message, &mut pos).ok_or(400u16)?;
+        if key_id != self.key_id.as_slice() {
+            return Err(401);
+        }
+        if encrypted.len() < hpke::N_ENC {
+            return Err(400);
+        }
+
+        let mut aad = vec![QUERY];
+        write_vec16(&mut aad, key_id);
+        let (enc, ciphertext) = encrypted.split_at(hpke::N_ENC);
+        let mut context = hpke::setup_base_r(enc, &self.secret, b"odoh query").ok_or(400u16)?;
+        let plaintext = context.open(&aad, ciphertext).ok_or(400u16)?;
+        let query = dns_message(&plaintext).ok_or(400u16)?.to_vec();
+
+        Ok((query, QueryContext { context, plaintext }))
+    }
+
+    pub fn encrypt_response(query: &QueryContext, response: &[u8]) -> Option<Vec<u8>> {
+        let mut response_nonce = [0u8; RESPONSE_NONCE_LEN];
+        OsRng.fill_bytes(&mut response_nonce);
+        encrypt_response_with_nonce(query, response, &response_nonce)
+    }
+
+    pub fn configs_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::new(200, "application/octet-stream", self.configs());
+        response.headers.push(("Cache-Control".to_string(), "max-age=86400".to_string()));
+        response
+    }
+
+    pub fn answer(&self, message: &[u8], answer: impl Fn(DnsPacket) -> io::Result<DnsPacket>) -> Result<Vec<u8>, u16> {
+        let (query, context) = self.decrypt_query(message)?;
+        if query.len() > 512 {
+            return Err(400);
+        }
+        let request = DnsPacket::from_bytes(&query).map_err(|_| 400u16)?;
+
+        let response = answer(request).map_err(|e| {
+            warn!("Failed to answer oblivious query: {:?}", e);
+            500u16
+        })?;
+        let message = write_large_message(&response).map_err(|_| 500u16)?;
+        OdohTarget::encrypt_response(&context, &message).ok_or(500)
+    }
+
+    pub fn handle(&self, handler: &QueryHandler, request: &HttpRequest) -> HttpResponse {
+        if request.header("content-type") != Some(CONTENT_TYPE) {
+            return HttpResponse::new(415, "text/plain", b"Expected an oblivious DNS message\n".to_vec());
+        }
+        match self.answer(&request.body, |query| handler.answer(query)) {
+            Ok(body) => {
+                let mut response = HttpResponse::new(200, CONTENT_TYPE, body);
+                // Responses are bound to one query, caching them at the proxy is pointless
+                response.headers.push(("Cache-Control".to_string(), "no-cache, no-store".to_string()));
+                response
+            }
+            Err(status) => HttpResponse::new(status, "text/plain", Vec::new()),
+        }
+    }
+}
+
+fn response_secrets(query: &QueryContext, response_nonce: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let secret = query.context.export(b"odoh response", hpke::N_K);
+    let mut salt = query.plaintext.clone();
+    write_vec16(&mut salt, response_nonce);
+
+    let (_, hkdf) = Hkdf::<Sha256>::extract(Some(&salt), &secret);
+    let mut key = vec![0; hpke::N_K];
+    let mut nonce = vec![0; hpke::N_N];
+    hkdf.expand(b"odoh key", &mut key).expect("Nk is a valid HKDF output length");
+    hkdf.expand(b"odoh nonce", &mut nonce).expect("Nn is a valid HKDF output length");
+    (key, nonce)
+}
+
+fn encrypt_response_with_nonce(query: &QueryContext, response: &[u8], response_nonce: &[u8]) -> Option<Vec<u8>> {
+    let (key, nonce) = response_secrets(query, response_nonce);
+    let mut aad = vec![RESPONSE];
+    write_vec16(&mut aad, response_nonce);
+
+    let cipher = Aes128Gcm::new_from_slice(&key).ok()?;
+    let plaintext = padded_plaintext(response);
+    let ciphertext = cipher.encrypt(nonce.as_slice().into(), Payload { msg: &plaintext, aad: &aad }).ok()?;
+
+    let mut out = vec![RESPONSE];
+    write_vec16(&mut out, response_nonce);
+    write_vec16(&mut out, &ciphertext);
+    Some(out)
+}
+
+// Set once at startup when ODoH is enabled, see `server::http::route`
+pub fn odoh_target() -> &'static OnceLock<OdohTarget> {
+    static TARGET: OnceLock<OdohTarget> = OnceLock::new();
+    &TARGET
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::byte_buffer::ByteBuffer;
+    use crate::utils::dns_class::DnsClass;
+    use crate::utils::query_type::QueryType;
+    use crate::utils::question::DnsQuestion;
+    use crate::utils::record::DnsRecord;
+    use std::net::Ipv4Addr;
+
+    fn answer(request: DnsPacket) -> io::Result<DnsPacket> {
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.answers.push(DnsRecord::A {
+            domain: request.questions[0].name.clone(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+            class: DnsClass::IN,
+        });
+        response.questions = request.questions;
+        Ok(response)
+    }
+
+    // Encrypts a query the way an ODoH client does, returning the message and the client's context
+    fn client_query(target: &OdohTarget, name: &str) -> (Vec<u8>, QueryContext) {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 7;
+        packet.questions.push(DnsQuestion::new(name.to_string(), QueryType::A));
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        let plaintext = padded_plaintext(&buffer.buffer[..buffer.position()]);
+
+        let mut aad = vec![QUERY];
+        write_vec16(&mut aad, target.key_id());
+        let (enc, mut context) = hpke::setup_base_s(&target.public_key(), &StaticSecret::from([4; 32]), b"odoh query");
+        let encrypted = [&enc[..], &context.seal(&aad, &plaintext).unwrap()].concat();
+
+        let mut message = vec![QUERY];
+        write_vec16(&mut message, target.key_id());
+        write_vec16(&mut message, &encrypted);
+        (message, QueryContext { context, plaintext })
+    }
+
+    #[test]
+    fn test_configs() {
+        let target = OdohTarget::new(StaticSecret::from([9; 32]));
+        let configs = target.configs();
+
+        assert_eq!(configs.len(), 2 + 4 + 8 + 32);
+        assert_eq!(&configs[..6], &[0, 44, 0, 1, 0, 40]);
+        assert_eq!(&configs[6..12], &[0, 0x20, 0, 1, 0, 1]);
+        assert_eq!(&configs[14..], target.public_key().as_bytes());
+        assert_eq!(target.key_id().len(), 32);
+    }
+
+    #[test]
+    fn test_query_round_trip() {
+        let target = OdohTarget::new(StaticSecret::from([9; 32]));
+        let (message, client) = client_query(&target, "example.com");
+
+        let reply = target.answer(&message, answer).unwrap();
+        assert_eq!(reply[0], RESPONSE);
+
+        // Decrypt the response with the client's copy of the secrets
+        let mut pos = 1;
+        let response_nonce = read_vec16(&reply, &mut pos).unwrap();
+        let ciphertext = read_vec16(&reply, &mut pos).unwrap();
+        let (key, nonce) = response_secrets(&client, response_nonce);
+        let mut aad = vec![RESPONSE];
+        write_vec16(&mut aad, response_nonce);
+        let plaintext = Aes128Gcm::new_from_slice(&key).unwrap()
+            .decrypt(nonce.as_slice().into(), Payload { msg: ciphertext, aad: &aad })
+            .unwrap();
+        assert_eq!(plaintext.len() % PADDING_BLOCK, 4);
+
+        let response = DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(dns_message(&plaintext).unwrap())).unwrap();
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.get_random_a(), Some(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[test]
+    fn test_rejected_queries() {
+        let target = OdohTarget::new(StaticSecret::from([9; 32]));
+        let (mut message, _) = client_query(&target, "example.com");
+
+        let other = OdohTarget::new(StaticSecret::from([8; 32]));
+        assert_eq!(other.answer(&message, answer), Err(401));
+
+        let last = message.len() - 1;
+        message[last] ^= 1;
+        assert_eq!(target.answer(&message, answer), Err(400));
+        assert_eq!(target.answer(&[RESPONSE], answer), Err(400));
+    }
+}
@@ -0,0 +1,136 @@
+use std::net::Ipv4Addr;
+use std::sync::{OnceLock, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::resolver::forward::Forwarder;
+use crate::resolver::transport::Network;
+use crate::utils::query_type::QueryType;
+use crate::utils::result_code::ResultCode;
+
+// Queries per upstream in one benchmark, the median is what counts
+pub const BENCHMARK_ROUNDS: usize = 3;
+
+/**
+How an upstream did in a benchmark: the median round trip time of its answers, or None when
+it failed to answer or gave a wrong answer (no address for a name that has one) even once.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpstreamScore {
+    pub server: (Ipv4Addr, u16),
+    pub rtt: Option<Duration>,
+}
+
+// Asks the way forwarded queries do, so DNSCrypt upstreams are timed encrypted
+pub fn probe(forwarder: &Forwarder, server: (Ipv4Addr, u16), name: &str, rounds: usize) -> UpstreamScore {
+    let mut samples = Vec::new();
+    for _ in 0..rounds {
+        let start = Instant::now();
+        match forwarder.ask(server, name, QueryType::A, false, None, &Network) {
+            Ok(res) if res.header.rescode == ResultCode::NOERROR && res.get_random_a().is_some() => {
+                samples.push(start.elapsed());
+            }
+            Ok(res) => {
+                warn!("Upstream {:?} gave a wrong answer ({:?}) for benchmark name {}", server, res.header.rescode, name);
+                return UpstreamScore { server, rtt: None };
+            }
+            Err(e) => {
+                warn!("Upstream {:?} failed the benchmark: {}", server, e);
+                return UpstreamScore { server, rtt: None };
+            }
+        }
+    }
+    samples.sort();
+    UpstreamScore { server, rtt: samples.get(samples.len() / 2).copied() }
+}
+
+// Working upstreams fastest first, the failed ones keep their configured order at the end
+pub fn rank(scores: &[UpstreamScore]) -> Vec<(Ipv4Addr, u16)> {
+    let mut working: Vec<&UpstreamScore> = scores.iter().filter(|score| score.rtt.is_some()).collect();
+    working.sort_by_key(|score| score.rtt);
+    working.into_iter()
+        .chain(scores.iter().filter(|score| score.rtt.is_none()))
+        .map(|score| score.server)
+        .collect()
+}
+
+pub type Ranking = Option<Vec<(Ipv4Addr, u16)>>;
+
+// The benchmarked order of the forwarder's upstreams, unset until the first benchmark finishes
+pub fn upstream_ranking() -> &'static RwLock<Ranking> {
+    static RANKING: OnceLock<RwLock<Ranking>> = OnceLock::new();
+    RANKING.get_or_init(|| RwLock::new(None))
+}
+
+// Benchmarks in the background so startup isn't held up by slow upstreams. An interval of 0 only benchmarks once.
+pub fn start(forwarder: &'static Forwarder, name: String, interval: Duration) {
+    thread::spawn(move || loop {
+        let scores: Vec<UpstreamScore> = forwarder.servers.iter().map(|server| probe(forwarder, *server, &name, BENCHMARK_ROUNDS)).collect();
+        info!("Upstream benchmark: {:?}", scores);
+        *upstream_ranking().write().unwrap() = Some(rank(&scores));
+
+        if interval.is_zero() {
+            return;
+        }
+        thread::sleep(interval);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+    use crate::utils::byte_buffer::ByteBuffer;
+    use crate::utils::packet::DnsPacket;
+    use crate::utils::record::DnsRecord;
+    use std::net::UdpSocket;
+
+    fn score(last_octet: u8, rtt: Option<u64>) -> UpstreamScore {
+        UpstreamScore { server: (Ipv4Addr::new(192, 0, 2, last_octet), 53), rtt: rtt.map(Duration::from_millis) }
+    }
+
+    #[test]
+    fn test_rank() {
+        let scores = [score(1, None), score(2, Some(80)), score(3, Some(10)), score(4, None)];
+        let ranked: Vec<u8> = rank(&scores).iter().map(|(addr, _)| addr.octets()[3]).collect();
+        assert_eq!(ranked, vec![3, 2, 1, 4]);
+    }
+
+    // Answers every query it gets, with an address only when `correct` is set
+    fn fake_upstream(correct: bool, queries: usize) -> (Ipv4Addr, u16) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for _ in 0..queries {
+                let mut buffer = ByteBuffer::new();
+                let (_, src) = socket.recv_from(&mut buffer.buffer).unwrap();
+                let mut packet = DnsPacket::from_buffer(&mut buffer).unwrap();
+                packet.header.response = true;
+                if correct {
+                    packet.answers.push(DnsRecord::A {
+                        domain: packet.questions[0].name.clone(),
+                        addr: Ipv4Addr::new(192, 0, 2, 80),
+                        ttl: 60,
+                        class: DnsClass::IN,
+                    });
+                }
+                let mut buffer = ByteBuffer::new();
+                packet.write(&mut buffer).unwrap();
+                socket.send_to(&buffer.buffer[..buffer.position], src).unwrap();
+            }
+        });
+        (Ipv4Addr::LOCALHOST, port)
+    }
+
+    #[test]
+    fn test_probe() {
+        let forwarder = Forwarder { servers: Vec::new(), search: Vec::new(), dnscrypt: Vec::new() };
+        let good = fake_upstream(true, BENCHMARK_ROUNDS);
+        assert!(probe(&forwarder, good, "example.com", BENCHMARK_ROUNDS).rtt.is_some());
+
+        let wrong = fake_upstream(false, 1);
+        assert_eq!(probe(&forwarder, wrong, "example.com", BENCHMARK_ROUNDS).rtt, None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::utils::packet::DnsPacket;
+use crate::utils::presentation::{fqdn, record_data};
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+
+/**
+How the answer of the local resolver differs from that of a reference server to the same question.
+Answers are compared as sets of records (owner, type and data), so order doesn't matter; a CNAME
+chain the local side lost shows up as records only the reference has. TTLs are listed for records
+both have, a caching reference counts them down so they differ without anything being wrong.
+*/
+#[derive(Debug, Default, PartialEq)]
+pub struct Comparison {
+    pub rcode: Option<(ResultCode, ResultCode)>, // local and reference, when they differ
+    pub only_local: Vec<String>,
+    pub only_reference: Vec<String>,
+    pub ttls: Vec<(String, u32, u32)>, // record, local TTL, reference TTL
+}
+
+// The answer section by record, in zone file format without the TTL
+fn answers(packet: &DnsPacket) -> BTreeMap<String, u32> {
+    packet.answers.iter().filter_map(|record: &DnsRecord| {
+        let data = record_data(record)?;
+        let line = format!("{}\t{}\t{}", fqdn(&record.domain().to_ascii_lowercase()), record.query_type().name(), data);
+        Some((line, record.ttl()))
+    }).collect()
+}
+
+impl Comparison {
+    pub fn new(local: &DnsPacket, reference: &DnsPacket) -> Comparison {
+        let (local_answers, reference_answers) = (answers(local), answers(reference));
+        let mut comparison = Comparison::default();
+        if local.header.rescode != reference.header.rescode {
+            comparison.rcode = Some((local.header.rescode, reference.header.rescode));
+        }
+        for (record, &ttl) in &local_answers {
+            match reference_answers.get(record) {
+                Some(&reference_ttl) if reference_ttl != ttl => comparison.ttls.push((record.clone(), ttl, reference_ttl)),
+                Some(_) => {}
+                None => comparison.only_local.push(record.clone()),
+            }
+        }
+        comparison.only_reference = reference_answers.into_keys().filter(|record| !local_answers.contains_key(record)).collect();
+        comparison
+    }
+
+    // Same rcode and same records, TTLs aside
+    pub fn is_match(&self) -> bool {
+        self.rcode.is_none() && self.only_local.is_empty() && self.only_reference.is_empty()
+    }
+}
+
+// Like a diff from the reference to the local answer: `-` only the reference has, `+` only the local one
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((local, reference)) = self.rcode {
+            writeln!(f, "rcode: {:?} locally, {:?} from the reference", local, reference)?;
+        }
+        for record in &self.only_reference {
+            writeln!(f, "- {}", record)?;
+        }
+        for record in &self.only_local {
+            writeln!(f, "+ {}", record)?;
+        }
+        for (record, local, reference) in &self.ttls {
+            writeln!(f, "  {}\tTTL {} locally, {} from the reference", record, local, reference)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+    use std::net::Ipv4Addr;
+
+    fn a(domain: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::A { domain: domain.to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl, class: DnsClass::IN }
+    }
+
+    #[test]
+    fn test_lost_cname_chain() {
+        let cname = DnsRecord::CNAME { domain: "www.example.com".to_string(), cname: "cdn.example.net".to_string(), ttl: 300, class: DnsClass::IN };
+        let mut reference = DnsPacket::new();
+        reference.answers = vec![cname.clone(), a("cdn.example.net", 60)];
+        let mut local = DnsPacket::new();
+        local.answers = vec![cname];
+
+        let comparison = Comparison::new(&local, &reference);
+        assert!(!comparison.is_match());
+        assert_eq!(comparison.only_reference, vec!["cdn.example.net.\tA\t192.0.2.1"]);
+        assert!(comparison.only_local.is_empty());
+        assert_eq!(comparison.to_string(), "- cdn.example.net.\tA\t192.0.2.1\n");
+    }
+
+    #[test]
+    fn test_ttls_and_rcode() {
+        let mut local = DnsPacket::new();
+        local.answers = vec![a("Example.com", 300)];
+        let mut reference = DnsPacket::new();
+        reference.answers = vec![a("example.com", 212)];
+        let comparison = Comparison::new(&local, &reference);
+        assert!(comparison.is_match());
+        assert_eq!(comparison.ttls, vec![("example.com.\tA\t192.0.2.1".to_string(), 300, 212)]);
+
+        reference.header.rescode = ResultCode::SERVFAIL;
+        reference.answers.clear();
+        let comparison = Comparison::new(&local, &reference);
+        assert_eq!(comparison.rcode, Some((ResultCode::NOERROR, ResultCode::SERVFAIL)));
+        assert_eq!(comparison.only_local.len(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+
+use crypto_box::aead::rand_core::RngCore;
+use crypto_box::aead::OsRng;
+use log::debug;
+
+use crate::utils::edns::{EdnsOption, OPTION_COOKIE, UDP_PAYLOAD_SIZE};
+use crate::utils::packet::DnsPacket;
+use crate::utils::record::DnsRecord;
+
+#[derive(Clone, Debug, Default)]
+struct ServerState {
+    client: [u8; 8],
+    server: Vec<u8>, // empty until the server has sent one
+}
+
+/**
+Client side DNS cookies (RFC 7873). Every upstream server gets its own random client cookie and
+the server cookie it hands back goes along with the following queries. An answer carrying a
+cookie that doesn't start with ours wasn't sent in reply to our query and is dropped, which
+takes an off-path spoofer a lot more than guessing the ID and port. Servers that don't support
+cookies simply don't send one back and are trusted as before. The cookie of a client that sent
+its own is replaced, that one is for us and not for the upstream.
+*/
+#[derive(Debug, Default)]
+pub struct ClientCookies {
+    servers: Mutex<HashMap<SocketAddr, ServerState>>,
+}
+
+impl ClientCookies {
+    pub fn new() -> ClientCookies {
+        ClientCookies::default()
+    }
+
+    fn option(&self, server: SocketAddr) -> EdnsOption {
+        let mut servers = self.servers.lock().unwrap();
+        let state = servers.entry(server).or_insert_with(|| {
+            let mut client = [0u8; 8];
+            OsRng.fill_bytes(&mut client);
+            ServerState { client, server: Vec::new() }
+        });
+        let mut data = state.client.to_vec();
+        data.extend_from_slice(&state.server);
+        EdnsOption::new(OPTION_COOKIE, data)
+    }
+
+    // The OPT record of a query to `server`, the client's options if it sent any, with our cookie
+    pub fn query_opt(&self, server: SocketAddr, edns: Option<&DnsRecord>) -> DnsRecord {
+        let (flags, mut options) = match edns {
+            Some(DnsRecord::OPT { flags, options, .. }) => (*flags, options.clone()),
+            _ => (0, Vec::new()),
+        };
+        options.retain(|option| option.code != OPTION_COOKIE);
+        options.push(self.option(server));
+        DnsRecord::OPT { udp_size: UDP_PAYLOAD_SIZE, flags, options }
+    }
+
+    /*
+    Checks and takes the cookie out of a response, so it never reaches a client or the cache.
+    Returns whether the server refused the query with an extended RCODE and a new cookie
+    (BADCOOKIE, RFC 7873 section 5.3), in which case the query is worth one more try.
+    */
+    pub fn accept(&self, server: SocketAddr, response: &mut DnsPacket) -> io::Result<bool> {
+        let Some(cookie) = response.take_option(OPTION_COOKIE) else {
+            return Ok(false);
+        };
+        let mut servers = self.servers.lock().unwrap();
+        let state = servers.entry(server).or_default();
+        if cookie.len() < 16 || cookie.len() > 40 || cookie[..8] != state.client {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Response from {} doesn't echo our cookie", server)));
+        }
+        state.server = cookie[8..].to_vec();
+        let extended_rcode = match response.opt() {
+            Some(DnsRecord::OPT { flags, .. }) => flags >> 24,
+            _ => 0,
+        };
+        Ok(extended_rcode != 0)
+    }
+
+    // Sends a query with our cookie through `send`, once more if the server wants a fresh one
+    pub fn exchange<F>(&self, server: SocketAddr, edns: Option<&DnsRecord>, send: F) -> io::Result<DnsPacket>
+    where
+        F: Fn(Option<&DnsRecord>) -> io::Result<DnsPacket>,
+    {
+        for _ in 0..2 {
+            let mut response = send(Some(&self.query_opt(server, edns)))?;
+            if !self.accept(server, &mut response)? {
+                return Ok(response);
+            }
+            debug!("{} rejected our cookie, retrying with the one it sent", server);
+        }
+        Err(io::Error::other(format!("{} keeps rejecting our cookie", server)))
+    }
+}
+
+// Set once at startup when `[cookies] upstream` is on, see `recursive::lookup_with`
+pub fn client_cookies() -> &'static OnceLock<ClientCookies> {
+    static COOKIES: OnceLock<ClientCookies> = OnceLock::new();
+    &COOKIES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(cookie: Option<Vec<u8>>, flags: u32) -> DnsPacket {
+        let mut response = DnsPacket::new();
+        let options = cookie.map(|cookie| vec![EdnsOption::new(OPTION_COOKIE, cookie)]).unwrap_or_default();
+        response.resources.push(DnsRecord::OPT { udp_size: 1232, flags, options });
+        response
+    }
+
+    #[test]
+    fn test_client_cookies() {
+        let cookies = ClientCookies::new();
+        let server = SocketAddr::from(([192, 0, 2, 53], 53));
+        let client_opt = DnsRecord::OPT { udp_size: 4096, flags: 0, options: vec![EdnsOption::new(OPTION_COOKIE, vec![9; 8])] };
+        let DnsRecord::OPT { options, .. } = cookies.query_opt(server, Some(&client_opt)) else { unreachable!() };
+        assert_eq!(options.len(), 1);
+        let client = options[0].data.clone();
+        assert_eq!(client.len(), 8);
+        assert_ne!(client, vec![9; 8]);
+
+        // The server cookie is learned and sent from then on, the option never reaches the caller
+        let mut answer = response(Some([client.clone(), vec![1; 16]].concat()), 0);
+        assert!(!cookies.accept(server, &mut answer).unwrap());
+        assert_eq!(answer.option(OPTION_COOKIE), None);
+        let DnsRecord::OPT { options, .. } = cookies.query_opt(server, None) else { unreachable!() };
+        assert_eq!(options[0].data, [client.clone(), vec![1; 16]].concat());
+
+        assert!(cookies.accept(server, &mut response(Some([client.clone(), vec![2; 16]].concat()), 1 << 24)).unwrap());
+        assert!(cookies.accept(server, &mut response(Some([vec![0; 8], vec![2; 16]].concat()), 0)).is_err());
+        assert!(!cookies.accept(server, &mut response(None, 0)).unwrap());
+    }
+}
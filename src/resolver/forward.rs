@@ -0,0 +1,304 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, OnceLock};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::cache::key::Provenance;
+use crate::dnscrypt::client::DnsCryptUpstream;
+use crate::logging::telemetry::{self, SpanKind};
+use crate::resolver::recursive::lookup_via;
+use crate::resolver::benchmark::upstream_ranking;
+use crate::resolver::preset::preset;
+use crate::resolver::stamp::DnsStamp;
+use crate::resolver::tcp_pool::upstream_connections;
+use crate::resolver::transport::{Connector, Network};
+use crate::resolver::resolv_conf::{ResolvConf, RESOLV_CONF};
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolverMode {
+    #[default]
+    Recursive,
+    Forward,
+    Proxy, // relay raw queries to the upstreams, see `proxy::relay`
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamTransport {
+    #[default]
+    Udp,
+    Tcp, // persistent pipelined connections, see `tcp_pool`
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct UpstreamConfig {
+    pub mode: ResolverMode,
+    pub servers: Vec<String>, // "192.0.2.1", "192.0.2.1:5353", a plain DNS or DNSCrypt "sdns://" stamp or a preset name
+    pub resolv_conf: bool,    // bootstrap from resolv.conf when `servers` is empty
+    pub resolv_conf_path: String,
+    pub benchmark: bool,          // measure the upstreams and use the fastest working one first
+    pub benchmark_name: String,   // has to resolve to an address
+    pub benchmark_interval: u64,  // seconds between benchmarks, 0 for startup only
+    pub transport: UpstreamTransport,
+    pub tcp_connections: usize,   // per upstream
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            mode: ResolverMode::Recursive,
+            servers: Vec::new(),
+            resolv_conf: true,
+            resolv_conf_path: RESOLV_CONF.to_string(),
+            benchmark: false,
+            benchmark_name: "example.com".to_string(),
+            benchmark_interval: 3600,
+            transport: UpstreamTransport::Udp,
+            tcp_connections: 2,
+        }
+    }
+}
+
+pub fn parse_server(server: &str) -> Option<(Ipv4Addr, u16)> {
+    if let Ok(addr) = server.parse::<SocketAddrV4>() {
+        return Some((*addr.ip(), addr.port()));
+    }
+    server.parse::<Ipv4Addr>().ok().map(|addr| (addr, 53))
+}
+
+/**
+Sends every query to a fixed list of upstream resolvers instead of walking the tree from the
+root. Upstreams are tried in order, or fastest first once benchmarked, until one gives a
+usable answer. Single label names are expanded with the search domains first, the way a stub
+resolver would. Upstreams from DNSCrypt stamps are asked encrypted, every other one in plain DNS.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Forwarder {
+    pub servers: Vec<(Ipv4Addr, u16)>,
+    pub search: Vec<String>,
+    pub dnscrypt: Vec<Arc<DnsCryptUpstream>>, // the servers that are DNSCrypt resolvers
+}
+
+impl Forwarder {
+    pub fn from_config(config: &UpstreamConfig) -> io::Result<Forwarder> {
+        let mut forwarder = Forwarder { servers: Vec::new(), search: Vec::new(), dnscrypt: Vec::new() };
+
+        for server in &config.servers {
+            if server.starts_with("sdns://") {
+                let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+                let stamp: DnsStamp = server.parse().map_err(invalid)?;
+                let server = stamp.server().map_err(invalid)?;
+                if let DnsStamp::DnsCrypt { provider_pk, provider_name, .. } = &stamp {
+                    // Proxy mode relays the client's bytes as they are, there is nothing to encrypt them with
+                    if config.mode == ResolverMode::Proxy {
+                        return Err(invalid("DNSCrypt upstreams don't work in proxy mode".to_string()));
+                    }
+                    forwarder.dnscrypt.push(Arc::new(DnsCryptUpstream::new(server, provider_pk, provider_name).map_err(invalid)?));
+                }
+                forwarder.servers.push(server);
+                continue;
+            }
+            if let Some(preset) = preset(server) {
+                info!("Upstream preset {}: plain DNS to {:?} on port 53", preset.name, preset.addrs);
+                forwarder.servers.extend(preset.addrs.iter().map(|addr| (*addr, 53)));
+                continue;
+            }
+            match parse_server(server) {
+                Some(server) => forwarder.servers.push(server),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid upstream server {:?}", server))),
+            }
+        }
+
+        if forwarder.servers.is_empty() && config.resolv_conf {
+            let conf = ResolvConf::load(&config.resolv_conf_path)?;
+            info!("Using upstreams from {}: {:?}", config.resolv_conf_path, conf.nameservers);
+            forwarder.apply_resolv_conf(conf);
+        }
+
+        if forwarder.servers.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Forward mode needs at least one upstream server"));
+        }
+        Ok(forwarder)
+    }
+
+    pub fn apply_resolv_conf(&mut self, conf: ResolvConf) {
+        for addr in conf.nameservers {
+            match addr {
+                IpAddr::V4(addr) => self.servers.push((addr, 53)),
+                IpAddr::V6(addr) => warn!("Skipping IPv6 upstream {} from resolv.conf", addr),
+            }
+        }
+        self.search = conf.search;
+    }
+
+    pub fn candidate_names(&self, qname: &str) -> Vec<String> {
+        if qname.contains('.') || self.search.is_empty() {
+            return vec![qname.to_string()];
+        }
+        let mut names: Vec<String> = self.search.iter().map(|domain| format!("{}.{}", qname, domain)).collect();
+        names.push(qname.to_string());
+        names
+    }
+
+    // One query to one of the upstreams, over the transport it speaks
+    pub fn ask(&self, server: (Ipv4Addr, u16), qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>, connector: &dyn Connector) -> io::Result<DnsPacket> {
+        if let Some(upstream) = self.dnscrypt.iter().find(|upstream| upstream.server == server) {
+            return upstream.lookup(qname, qtype, checking_disabled, edns, connector);
+        }
+        match upstream_connections().get() {
+            Some(connections) => connections.lookup(qname, qtype, server, checking_disabled, edns),
+            None => lookup_via(qname, qtype, server.into(), checking_disabled, edns, connector),
+        }
+    }
+
+    // How answers from `server` travel: encrypted from a DNSCrypt resolver, in the clear otherwise
+    pub fn provenance_of(&self, server: (Ipv4Addr, u16)) -> Provenance {
+        match self.dnscrypt.iter().any(|upstream| upstream.server == server) {
+            true => Provenance::Encrypted,
+            false => Provenance::Plain,
+        }
+    }
+
+    // The best of the upstreams
+    pub fn provenance(&self) -> Provenance {
+        self.servers.iter().map(|server| self.provenance_of(*server)).max().unwrap_or_default()
+    }
+
+    // The client's EDNS options go upstream untouched, and the upstream's come back in the answer,
+    // along with how the upstream that gave it was asked
+    pub fn lookup(&self, qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>) -> io::Result<(DnsPacket, Provenance)> {
+        self.lookup_via(qname, qtype, checking_disabled, edns, &Network)
+    }
+
+    pub fn lookup_via(&self, qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>, connector: &dyn Connector) -> io::Result<(DnsPacket, Provenance)> {
+        let mut span = telemetry::span("forward_lookup", SpanKind::Internal);
+        span.attr("dns.qname", qname);
+        span.attr("dns.qtype", format!("{:?}", qtype));
+        let names = self.candidate_names(qname);
+        let mut last = None;
+
+        let ranking = upstream_ranking().read().unwrap().clone();
+        let servers = ranking.as_ref().unwrap_or(&self.servers);
+
+        for name in &names {
+            for server in servers {
+                debug!("Forwarding {} {:?} to {:?}", name, qtype, server);
+                match self.ask(*server, name, qtype, checking_disabled, edns, connector) {
+                    Ok(res) if matches!(res.header.rescode, ResultCode::SERVFAIL | ResultCode::REFUSED) => {
+                        warn!("Upstream {:?} answered {:?} for {}", server, res.header.rescode, name);
+                    }
+                    Ok(res) => {
+                        if res.header.rescode != ResultCode::NXDOMAIN {
+                            return Ok((res, self.provenance_of(*server)));
+                        }
+                        // Try the next search domain, the last NXDOMAIN is what the client gets
+                        last = Some((res, self.provenance_of(*server)));
+                        break;
+                    }
+                    Err(e) => warn!("Upstream {:?} failed for {}: {}", server, name, e),
+                }
+            }
+        }
+
+        last.ok_or_else(|| io::Error::other(format!("No upstream answered for {}", qname)))
+    }
+}
+
+// Set once at startup when running in forward mode, see `resolver::resolve`
+pub fn forwarder() -> &'static OnceLock<Forwarder> {
+    static FORWARDER: OnceLock<Forwarder> = OnceLock::new();
+    &FORWARDER
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_server() {
+        assert_eq!(parse_server("192.0.2.1"), Some((Ipv4Addr::new(192, 0, 2, 1), 53)));
+        assert_eq!(parse_server("192.0.2.1:5353"), Some((Ipv4Addr::new(192, 0, 2, 1), 5353)));
+        assert_eq!(parse_server("dns.example"), None);
+    }
+
+    #[test]
+    fn test_from_config_resolv_conf() {
+        let path = std::env::temp_dir().join(format!("r_dns_resolv_conf_{}", std::process::id()));
+        std::fs::write(&path, "search lan\nnameserver 192.0.2.53\nnameserver ::1\n").unwrap();
+        let mut config = UpstreamConfig {
+            mode: ResolverMode::Forward,
+            resolv_conf_path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let forwarder = Forwarder::from_config(&config).unwrap();
+        assert_eq!(forwarder.servers, vec![(Ipv4Addr::new(192, 0, 2, 53), 53)]);
+        assert_eq!(forwarder.search, vec!["lan".to_string()]);
+
+        // Explicit servers win, and resolv.conf can be switched off entirely
+        config.servers = vec!["192.0.2.1".to_string()];
+        assert_eq!(Forwarder::from_config(&config).unwrap().servers, vec![(Ipv4Addr::new(192, 0, 2, 1), 53)]);
+        config.servers = vec!["sdns://AAYAAAAAAAAABzkuOS45Ljk".to_string()];
+        assert_eq!(Forwarder::from_config(&config).unwrap().servers, vec![(Ipv4Addr::new(9, 9, 9, 9), 53)]);
+        config.servers.clear();
+        config.resolv_conf = false;
+        assert!(Forwarder::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_from_config_stamps() {
+        let dnscrypt = DnsStamp::DnsCrypt {
+            props: 0,
+            addr: "192.0.2.1:8443".to_string(),
+            provider_pk: vec![0; 32],
+            provider_name: "2.dnscrypt-cert.example.com".to_string(),
+        };
+        let mut config = UpstreamConfig {
+            mode: ResolverMode::Forward,
+            servers: vec![dnscrypt.to_string(), "192.0.2.53".to_string()],
+            ..Default::default()
+        };
+        let forwarder = Forwarder::from_config(&config).unwrap();
+        assert_eq!(forwarder.servers, vec![(Ipv4Addr::new(192, 0, 2, 1), 8443), (Ipv4Addr::new(192, 0, 2, 53), 53)]);
+        assert_eq!(forwarder.dnscrypt.iter().map(|upstream| upstream.server).collect::<Vec<_>>(), vec![(Ipv4Addr::new(192, 0, 2, 1), 8443)]);
+
+        config.mode = ResolverMode::Proxy;
+        assert!(Forwarder::from_config(&config).is_err());
+
+        // Transports the forwarder doesn't speak are refused rather than asked in plain DNS
+        let dot = DnsStamp::DoT {
+            props: 0,
+            addr: "192.0.2.2".to_string(),
+            hashes: Vec::new(),
+            hostname: "dot.example.com".to_string(),
+            bootstrap: Vec::new(),
+        };
+        config.mode = ResolverMode::Forward;
+        config.servers = vec![dot.to_string()];
+        let e = Forwarder::from_config(&config).unwrap_err();
+        assert!(e.to_string().contains("DoT upstreams are not supported"));
+    }
+
+    #[test]
+    fn test_candidate_names() {
+        let forwarder = Forwarder {
+            servers: vec![(Ipv4Addr::new(192, 0, 2, 53), 53)],
+            search: vec!["corp.example".to_string(), "lan".to_string()],
+            dnscrypt: Vec::new(),
+        };
+
+        assert_eq!(forwarder.candidate_names("www.example.com"), vec!["www.example.com".to_string()]);
+        assert_eq!(
+            forwarder.candidate_names("nas"),
+            vec!["nas.corp.example".to_string(), "nas.lan".to_string(), "nas".to_string()]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::io;
+use std::net::IpAddr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long a nameserver's first address has to itself before its next one is asked too (RFC 8305 5)
+pub const HEAD_START: Duration = Duration::from_millis(250);
+// Failures in a row after which a family counts as unreachable, and for how long
+const FAILURES_TO_UNREACHABLE: u32 = 3;
+pub const UNREACHABLE_FOR: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Default)]
+struct Family {
+    failures: u32,
+    unreachable_until: Option<Instant>,
+}
+
+/**
+Whether IPv4 and IPv6 nameservers answer at all, from the outcome of every query. A family whose
+queries keep timing out or hitting unreachable networks, e.g. IPv6 with a broken upstream route,
+is tried last for a while instead of making every recursion wait for it first.
+*/
+#[derive(Debug, Default)]
+pub struct Reachability {
+    v4: Family,
+    v6: Family,
+}
+
+impl Reachability {
+    fn family(&mut self, addr: IpAddr) -> &mut Family {
+        match addr {
+            IpAddr::V4(_) => &mut self.v4,
+            IpAddr::V6(_) => &mut self.v6,
+        }
+    }
+
+    pub fn success(&mut self, addr: IpAddr) {
+        *self.family(addr) = Family::default();
+    }
+
+    pub fn failure(&mut self, addr: IpAddr) {
+        let family = self.family(addr);
+        family.failures += 1;
+        if family.failures >= FAILURES_TO_UNREACHABLE {
+            family.unreachable_until = Some(Instant::now() + UNREACHABLE_FOR);
+        }
+    }
+
+    // Unreachable families get another chance once their time is up
+    pub fn is_reachable(&mut self, addr: IpAddr) -> bool {
+        let family = self.family(addr);
+        match family.unreachable_until {
+            Some(until) if until > Instant::now() => false,
+            Some(_) => {
+                *family = Family::default();
+                true
+            }
+            None => true,
+        }
+    }
+
+    // The addresses with the unreachable family moved to the back, otherwise in the order given
+    pub fn order(&mut self, mut addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+        addrs.sort_by_key(|addr| !self.is_reachable(*addr));
+        addrs
+    }
+
+    // What a query's outcome says about its family: only silence and unreachable networks count against it
+    pub fn record(&mut self, addr: IpAddr, result: &io::Result<impl Sized>) {
+        match result {
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                | io::ErrorKind::NetworkUnreachable | io::ErrorKind::HostUnreachable) => self.failure(addr),
+            _ => self.success(addr),
+        }
+    }
+}
+
+// Shared by every recursion, like the RTT and lameness records
+pub fn reachability() -> &'static Mutex<Reachability> {
+    static REACHABILITY: OnceLock<Mutex<Reachability>> = OnceLock::new();
+    REACHABILITY.get_or_init(|| Mutex::new(Reachability::default()))
+}
+
+/**
+Asks one nameserver at its addresses Happy Eyeballs style (RFC 8305): the first address gets a
+head start, then the next one is asked alongside it, and so on. An attempt that fails starts the
+next address at once. Every outcome goes to `outcome` as it arrives, until it returns Some, or
+until `deadline` passes. Attempts still running then finish in the background, they only update
+the reachability.
+*/
+pub fn race<T: Send + 'static, R>(
+    addrs: &[IpAddr],
+    deadline: Option<Instant>,
+    attempt: impl Fn(IpAddr) -> io::Result<T> + Send + Sync + 'static,
+    mut outcome: impl FnMut(IpAddr, io::Result<T>) -> Option<R>,
+) -> Option<R> {
+    let attempt = move |addr: IpAddr| {
+        let result = attempt(addr);
+        reachability().lock().unwrap().record(addr, &result);
+        result
+    };
+    // A single address has nothing to race against, unless the wait for it has to end in time
+    if let ([addr], None) = (addrs, deadline) {
+        return outcome(*addr, attempt(*addr));
+    }
+
+    let attempt = Arc::new(attempt);
+    let (sender, receiver) = mpsc::channel();
+    let mut waiting = addrs.iter().copied();
+    let mut running = 0;
+    let mut start_next = |running: &mut usize| match waiting.next() {
+        Some(addr) => {
+            let (attempt, sender) = (attempt.clone(), sender.clone());
+            thread::spawn(move || {
+                let _ = sender.send((addr, attempt(addr)));
+            });
+            *running += 1;
+            true
+        }
+        None => false,
+    };
+
+    start_next(&mut running);
+    loop {
+        let wait = deadline.map_or(HEAD_START, |deadline| HEAD_START.min(deadline.saturating_duration_since(Instant::now())));
+        let received = match receiver.recv_timeout(wait) {
+            Ok(received) => received,
+            Err(RecvTimeoutError::Timeout) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => return None,
+            Err(RecvTimeoutError::Timeout) => {
+                start_next(&mut running);
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return None,
+        };
+        running -= 1;
+        let (addr, result) = received;
+        if let Some(found) = outcome(addr, result) {
+            return Some(found);
+        }
+        if running == 0 && !start_next(&mut running) {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+
+    #[test]
+    fn test_unreachable_family_goes_last() {
+        let mut reachability = Reachability::default();
+        for _ in 0..FAILURES_TO_UNREACHABLE - 1 {
+            reachability.record(V6, &Err::<(), _>(io::Error::from(io::ErrorKind::TimedOut)));
+        }
+        assert_eq!(reachability.order(vec![V6, V4]), vec![V6, V4]);
+
+        reachability.record(V6, &Err::<(), _>(io::Error::from(io::ErrorKind::NetworkUnreachable)));
+        assert_eq!(reachability.order(vec![V6, V4]), vec![V4, V6]);
+
+        // Any answer, even an unusable one, shows the path works
+        reachability.record(V6, &Err::<(), _>(io::Error::from(io::ErrorKind::InvalidData)));
+        assert_eq!(reachability.order(vec![V6, V4]), vec![V6, V4]);
+    }
+
+    #[test]
+    fn test_race_gives_head_start() {
+        // The first address never answers in time, the second is asked after the head start and wins
+        let start = Instant::now();
+        let winner = race(&[V6, V4], None, |addr| {
+            if addr == V6 {
+                thread::sleep(HEAD_START * 4);
+            }
+            Ok(addr)
+        }, |_, result| result.ok());
+        assert_eq!(winner, Some(V4));
+        assert!(start.elapsed() >= HEAD_START && start.elapsed() < HEAD_START * 4);
+
+        // A quick failure doesn't wait for the head start, and None comes back when nothing answers
+        let start = Instant::now();
+        let tried = Mutex::new(Vec::new());
+        let result: Option<()> = race(&[V6, V4], None, |_| Err::<(), _>(io::Error::from(io::ErrorKind::ConnectionRefused)), |addr, _| {
+            tried.lock().unwrap().push(addr);
+            None
+        });
+        assert_eq!((result, tried.into_inner().unwrap()), (None, vec![V6, V4]));
+        assert!(start.elapsed() < HEAD_START);
+
+        // Nothing is waited for past the deadline, not even a single address
+        let start = Instant::now();
+        let result = race(&[V4], Some(start + HEAD_START / 2), |addr| {
+            thread::sleep(HEAD_START * 2);
+            Ok(addr)
+        }, |_, result| result.ok());
+        assert_eq!(result, None);
+        assert!(start.elapsed() >= HEAD_START / 2 && start.elapsed() < HEAD_START);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::resolver::lame::{LameCache, LAME_TTL};
+use crate::resolver::rtt::RttTracker;
+use crate::resolver::transport::TcpOnly;
+
+// Delegations and nameserver addresses are kept for their TTL, but never longer than this
+pub const MAX_INFRA_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// How long what a server showed about its EDNS support is believed
+pub const EDNS_TTL: Duration = Duration::from_secs(60 * 60);
+// Zones and nameserver names each, beyond that only expired entries make room
+pub const MAX_INFRA_ENTRIES: usize = 10_000;
+
+/**
+How much EDNS a server copes with, from the most to the least. Servers behind firewalls that drop
+fragments lose answers larger than a packet, older ones drop or reject any query with an OPT
+record (RFC 6891 7).
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdnsSupport {
+    Full,  // an OPT record with our full payload size
+    Small, // an OPT record advertising 512 bytes, answers that big never fragment
+    Off,   // no OPT record at all
+}
+
+impl EdnsSupport {
+    // What to try when a query at this level goes unanswered
+    pub fn lower(self) -> EdnsSupport {
+        match self {
+            EdnsSupport::Full => EdnsSupport::Small,
+            _ => EdnsSupport::Off,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Delegation {
+    nameservers: Vec<String>,
+    until: Instant,
+}
+
+#[derive(Debug)]
+struct Addresses {
+    addrs: Vec<IpAddr>,
+    until: Instant,
+}
+
+fn until(ttl: u32) -> Instant {
+    Instant::now() + Duration::from_secs(ttl.into()).min(MAX_INFRA_TTL)
+}
+
+// Drops the expired entries of a full table, and tells whether there is room now
+fn make_room<V>(table: &mut HashMap<String, V>, until: impl Fn(&V) -> Instant) -> bool {
+    if table.len() >= MAX_INFRA_ENTRIES {
+        let now = Instant::now();
+        table.retain(|_, value| until(value) > now);
+    }
+    table.len() < MAX_INFRA_ENTRIES
+}
+
+/**
+What recursion has learned about the nameservers it talks to, kept apart from the answer cache:
+per address the round trip time, lameness per zone, whether UDP gets through and whether EDNS
+works, and per name the addresses of nameservers and the nameservers of zones. The next query
+for a name in a known zone starts at that zone's servers instead of the root, and a nameserver
+without glue that was looked up once isn't looked up again until its addresses expire.
+*/
+#[derive(Debug)]
+pub struct InfraCache {
+    pub rtt: RttTracker,
+    pub lame: LameCache,
+    pub tcp_only: TcpOnly,
+    edns: HashMap<IpAddr, (EdnsSupport, Instant)>,
+    nameservers: HashMap<String, Addresses>,
+    delegations: HashMap<String, Delegation>,
+}
+
+impl Default for InfraCache {
+    fn default() -> Self {
+        InfraCache {
+            rtt: RttTracker::new(),
+            lame: LameCache::new(LAME_TTL),
+            tcp_only: TcpOnly::default(),
+            edns: HashMap::new(),
+            nameservers: HashMap::new(),
+            delegations: HashMap::new(),
+        }
+    }
+}
+
+impl InfraCache {
+    // How much EDNS the server answered to last, None when it hasn't shown yet
+    pub fn edns(&mut self, addr: IpAddr) -> Option<EdnsSupport> {
+        match self.edns.get(&addr) {
+            Some((supported, until)) if *until > Instant::now() => Some(*supported),
+            Some(_) => {
+                self.edns.remove(&addr);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn set_edns(&mut self, addr: IpAddr, supported: EdnsSupport) {
+        self.edns.insert(addr, (supported, Instant::now() + EDNS_TTL));
+    }
+
+    pub fn addresses(&mut self, ns: &str) -> Option<Vec<IpAddr>> {
+        let ns = ns.trim_end_matches('.').to_ascii_lowercase();
+        match self.nameservers.get(&ns) {
+            Some(entry) if entry.until > Instant::now() => Some(entry.addrs.clone()),
+            Some(_) => {
+                self.nameservers.remove(&ns);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn add_addresses(&mut self, ns: &str, addrs: Vec<IpAddr>, ttl: u32) {
+        if addrs.is_empty() || !make_room(&mut self.nameservers, |entry| entry.until) {
+            return;
+        }
+        self.nameservers.insert(ns.trim_end_matches('.').to_ascii_lowercase(), Addresses { addrs, until: until(ttl) });
+    }
+
+    pub fn add_delegation(&mut self, zone: &str, nameservers: Vec<String>, ttl: u32) {
+        if nameservers.is_empty() || !make_room(&mut self.delegations, |delegation| delegation.until) {
+            return;
+        }
+        let nameservers = nameservers.iter().map(|ns| ns.trim_end_matches('.').to_ascii_lowercase()).collect();
+        self.delegations.insert(zone.trim_end_matches('.').to_ascii_lowercase(), Delegation { nameservers, until: until(ttl) });
+    }
+
+    pub fn forget_delegation(&mut self, zone: &str) {
+        self.delegations.remove(&zone.trim_end_matches('.').to_ascii_lowercase());
+    }
+
+    /**
+    The closest zone above `qname` (or `qname` itself) that recursion already knows the servers
+    of, with the addresses of every one of them that has some. The root is left to the hints, a
+    zone none of whose nameservers has known addresses is passed over for its parent.
+    */
+    pub fn closest_delegation(&mut self, qname: &str) -> Option<(String, Vec<Vec<IpAddr>>)> {
+        let mut zone = qname.trim_end_matches('.').to_ascii_lowercase();
+        while !zone.is_empty() {
+            let delegation = match self.delegations.get(&zone) {
+                Some(delegation) if delegation.until > Instant::now() => Some(delegation.nameservers.clone()),
+                Some(_) => {
+                    self.delegations.remove(&zone);
+                    None
+                }
+                None => None,
+            };
+            if let Some(nameservers) = delegation {
+                let servers: Vec<Vec<IpAddr>> = nameservers.iter().filter_map(|ns| self.addresses(ns)).collect();
+                if !servers.is_empty() {
+                    return Some((zone, servers));
+                }
+            }
+            zone = zone.split_once('.').map(|(_, parent)| parent.to_string()).unwrap_or_default();
+        }
+        None
+    }
+}
+
+// Shared by every recursion over the real network, including the cache refresh thread
+pub fn infra_cache() -> &'static Mutex<InfraCache> {
+    static INFRA_CACHE: OnceLock<Mutex<InfraCache>> = OnceLock::new();
+    INFRA_CACHE.get_or_init(|| Mutex::new(InfraCache::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest_delegation() {
+        let mut infra = InfraCache::default();
+        let ns = IpAddr::from([192, 0, 2, 10]);
+        infra.add_delegation("com", vec!["ns.nic.com".to_string()], 3600);
+        infra.add_addresses("NS.nic.com.", vec![ns], 3600);
+        // A zone whose nameservers have no known addresses doesn't help
+        infra.add_delegation("example.com", vec!["ns.example.net".to_string()], 3600);
+
+        assert_eq!(infra.closest_delegation("www.Example.com"), Some(("com".to_string(), vec![vec![ns]])));
+        assert_eq!(infra.closest_delegation("example.org"), None);
+
+        infra.add_addresses("ns.example.net", vec![IpAddr::from([192, 0, 2, 21])], 0);
+        assert_eq!(infra.closest_delegation("www.example.com").unwrap().0, "com");
+        infra.add_addresses("ns.example.net", vec![IpAddr::from([192, 0, 2, 21])], 3600);
+        assert_eq!(infra.closest_delegation("www.example.com").unwrap().0, "example.com");
+    }
+
+    #[test]
+    fn test_edns_support_expires() {
+        let mut infra = InfraCache::default();
+        let addr = IpAddr::from([192, 0, 2, 1]);
+        assert_eq!(infra.edns(addr), None);
+        infra.set_edns(addr, EdnsSupport::Off);
+        assert_eq!(infra.edns(addr), Some(EdnsSupport::Off));
+
+        infra.edns.insert(addr, (EdnsSupport::Small, Instant::now()));
+        assert_eq!(infra.edns(addr), None);
+    }
+}
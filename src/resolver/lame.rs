@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// How long a server stays marked lame for a zone before we give it another chance
+pub const LAME_TTL: Duration = Duration::from_secs(15 * 60);
+
+/**
+Remembers nameservers that answered badly (non-authoritatively, with REFUSED, or with
+garbage) for a given zone, so recursion can skip them instead of timing out on them
+again for every query.
+*/
+#[derive(Debug)]
+pub struct LameCache {
+    entries: HashMap<(IpAddr, String), Instant>,
+    ttl: Duration,
+}
+
+impl LameCache {
+    pub fn new(ttl: Duration) -> LameCache {
+        LameCache {
+            entries: HashMap::new(),
+            ttl,
+        }
+    }
+
+    pub fn mark(&mut self, addr: IpAddr, zone: &str) {
+        self.entries.insert((addr, zone.to_ascii_lowercase()), Instant::now() + self.ttl);
+    }
+
+    pub fn is_lame(&mut self, addr: IpAddr, zone: &str) -> bool {
+        let key = (addr, zone.to_ascii_lowercase());
+        match self.entries.get(&key) {
+            Some(expiry) if *expiry > Instant::now() => true,
+            Some(_) => {
+                self.entries.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mark_is_per_zone() {
+        let mut cache = LameCache::new(LAME_TTL);
+        let addr = IpAddr::from([192, 0, 2, 1]);
+        cache.mark(addr, "Example.com");
+
+        assert!(cache.is_lame(addr, "example.com"));
+        assert!(!cache.is_lame(addr, "example.org"));
+        assert!(!cache.is_lame(IpAddr::from([192, 0, 2, 2]), "example.com"));
+    }
+
+    #[test]
+    fn test_lameness_expires() {
+        let mut cache = LameCache::new(Duration::from_millis(10));
+        let addr = IpAddr::from([192, 0, 2, 1]);
+        cache.mark(addr, "example.com");
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!cache.is_lame(addr, "example.com"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::io;
+use std::time::Instant;
+
+use crate::cache::key::Provenance;
+use crate::resolver::forward::Forwarder;
+use crate::resolver::recursive::Progress;
+use crate::resolver::transport::{Connector, Network};
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+
+pub mod benchmark;
+pub mod compare;
+pub mod cookies;
+pub mod forward;
+pub mod happy_eyeballs;
+pub mod infra;
+pub mod lame;
+pub mod preset;
+pub mod proxy;
+pub mod recursive;
+pub mod resolv_conf;
+pub mod rtt;
+pub mod self_test;
+pub mod socks;
+pub mod stamp;
+pub mod tcp_pool;
+pub mod transport;
+
+// Entry point for everything that needs an answer from the outside world: forwards to the
+// configured upstreams in forward mode, otherwise recurses from the root.
+pub fn resolve(qname: &str, qtype: QueryType) -> io::Result<DnsPacket> {
+    resolve_with(qname, qtype, false, None).map(|(packet, _)| packet)
+}
+
+// The client's CD bit and EDNS options only mean something to the upstream it picked, so they
+// are passed on in forward mode. Recursion talks to authoritative servers, which get neither, only
+// an OPT record of our own. The answer comes with the path it was learned on.
+pub fn resolve_with(qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>) -> io::Result<(DnsPacket, Provenance)> {
+    match forward::forwarder().get() {
+        Some(forwarder) => forwarder.lookup(qname, qtype, checking_disabled, edns),
+        None => Ok((recursive::recursive_lookup(qname, qtype)?, Provenance::Plain)),
+    }
+}
+
+/**
+Where a handler's lookups go: to the forwarder's upstreams in forward mode, otherwise down from
+the root, either way over `connector`. The server takes what was set up at startup, tests bring
+their own forwarder and a `MockNetwork`.
+*/
+#[derive(Clone)]
+pub struct Upstream {
+    pub forwarder: Option<Forwarder>,
+    pub connector: &'static dyn Connector,
+}
+
+impl Upstream {
+    pub fn configured() -> Upstream {
+        Upstream { forwarder: forward::forwarder().get().cloned(), connector: &Network }
+    }
+
+    // The best path lookups can learn answers on. Recursion talks to authoritative servers in the
+    // clear and nothing is validated here, only DNSCrypt upstreams answer encrypted.
+    pub fn provenance(&self) -> Provenance {
+        self.forwarder.as_ref().map_or(Provenance::Plain, Forwarder::provenance)
+    }
+
+    // A client waiting until `deadline` gets what recursion had assembled by then in `progress`.
+    // Forwarding is bounded by the upstreams' own timeouts.
+    pub fn resolve(&self, qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>, deadline: Option<Instant>, progress: &Progress) -> io::Result<(DnsPacket, Provenance)> {
+        let packet = match (&self.forwarder, deadline) {
+            (Some(forwarder), _) => return forwarder.lookup_via(qname, qtype, checking_disabled, edns, self.connector),
+            (None, Some(deadline)) => recursive::recursive_lookup_until(qname, qtype, self.connector, deadline, progress)?,
+            (None, None) => recursive::recursive_lookup_via(qname, qtype, self.connector)?,
+        };
+        Ok((packet, Provenance::Plain))
+    }
+}
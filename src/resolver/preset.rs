@@ -0,0 +1,99 @@
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::resolver::forward::{ResolverMode, UpstreamConfig};
+
+/**
+A well known public resolver that can be named in the config instead of spelling out its
+addresses. Presets only use plain DNS: the forwarder asks `addrs` on port 53, never the
+service's DoT or DoH endpoints, so only services that answer there are listed.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub addrs: &'static [Ipv4Addr],
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "cloudflare",
+        addrs: &[Ipv4Addr::new(1, 1, 1, 1), Ipv4Addr::new(1, 0, 0, 1)],
+    },
+    Preset {
+        name: "cloudflare-malware",
+        addrs: &[Ipv4Addr::new(1, 1, 1, 2), Ipv4Addr::new(1, 0, 0, 2)],
+    },
+    Preset {
+        name: "cloudflare-family",
+        addrs: &[Ipv4Addr::new(1, 1, 1, 3), Ipv4Addr::new(1, 0, 0, 3)],
+    },
+    // Quad9 blocks malicious domains on its main addresses
+    Preset {
+        name: "quad9",
+        addrs: &[Ipv4Addr::new(9, 9, 9, 9), Ipv4Addr::new(149, 112, 112, 112)],
+    },
+    Preset {
+        name: "quad9-unfiltered",
+        addrs: &[Ipv4Addr::new(9, 9, 9, 10), Ipv4Addr::new(149, 112, 112, 10)],
+    },
+    Preset {
+        name: "google",
+        addrs: &[Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(8, 8, 4, 4)],
+    },
+];
+
+pub fn preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UpstreamSetting {
+    Preset(String),
+    Config(UpstreamConfig),
+}
+
+// `upstream = "quad9"` is shorthand for forwarding to that preset, the table form is unchanged
+pub fn deserialize_upstream<'de, D: Deserializer<'de>>(deserializer: D) -> Result<UpstreamConfig, D::Error> {
+    match UpstreamSetting::deserialize(deserializer)? {
+        UpstreamSetting::Preset(name) => Ok(UpstreamConfig {
+            mode: ResolverMode::Forward,
+            servers: vec![name],
+            ..Default::default()
+        }),
+        UpstreamSetting::Config(config) => Ok(config),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::resolver::forward::Forwarder;
+
+    #[test]
+    fn test_preset_lookup() {
+        assert_eq!(preset("Quad9").unwrap().addrs[0], Ipv4Addr::new(9, 9, 9, 9));
+        assert_eq!(preset("cloudflare-malware").unwrap().addrs[0], Ipv4Addr::new(1, 1, 1, 2));
+        assert!(preset("opendns-typo").is_none());
+        // Every preset has to be reachable in plain DNS, the only transport it is used over
+        assert!(PRESETS.iter().all(|preset| !preset.addrs.is_empty()));
+    }
+
+    #[test]
+    fn test_preset_shorthand() {
+        let config = ServerConfig::parse("upstream = \"cloudflare\"\n").unwrap();
+        assert_eq!(config.upstream.mode, ResolverMode::Forward);
+        let forwarder = Forwarder::from_config(&config.upstream).unwrap();
+        assert_eq!(forwarder.servers, vec![(Ipv4Addr::new(1, 1, 1, 1), 53), (Ipv4Addr::new(1, 0, 0, 1), 53)]);
+
+        // Presets mix with plain addresses in the table form
+        let config = ServerConfig::parse("[upstream]\nmode = \"forward\"\nservers = [\"google\", \"192.0.2.1\"]\n").unwrap();
+        let forwarder = Forwarder::from_config(&config.upstream).unwrap();
+        assert_eq!(forwarder.servers.len(), 3);
+        assert_eq!(forwarder.servers[2], (Ipv4Addr::new(192, 0, 2, 1), 53));
+
+        assert!(Forwarder::from_config(&ServerConfig::parse("upstream = \"nope\"\n").unwrap().upstream).is_err());
+    }
+}
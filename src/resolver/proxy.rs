@@ -0,0 +1,253 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+
+use log::{debug, info};
+
+use crate::resolver::recursive::{exchange_tcp, QUERY_TIMEOUT};
+use crate::resolver::socks::socks_proxy;
+use crate::utils::random::random;
+
+const HEADER_LEN: usize = 12;
+const MAX_UDP_RESPONSE: usize = 4096;
+const TYPE_OPT: u16 = 41;
+
+fn read_u16(message: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*message.get(pos)?, *message.get(pos + 1)?]))
+}
+
+// Position right after the (possibly compressed) name at `pos`
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            _ if len & 0xC0 == 0xC0 => return Some(pos + 2),
+            _ => pos += 1 + len,
+        }
+    }
+}
+
+// Where the question section ends, the answers start there
+fn question_end(message: &[u8]) -> Option<usize> {
+    let questions = read_u16(message, 4)?;
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        pos = skip_name(message, pos)? + 4;
+    }
+    (pos <= message.len()).then_some(pos)
+}
+
+// Calls `f` with the type and TTL field of every record, returns where the last one ends
+fn for_each_record(message: &mut [u8], mut f: impl FnMut(u16, &mut [u8])) -> Option<usize> {
+    let records: u16 = [6, 8, 10].iter().map(|&pos| read_u16(message, pos)).sum::<Option<u16>>()?;
+    let mut pos = question_end(message)?;
+    for _ in 0..records {
+        pos = skip_name(message, pos)?;
+        let rtype = read_u16(message, pos)?;
+        f(rtype, message.get_mut(pos + 4..pos + 8)?);
+        pos += 10 + read_u16(message, pos + 8)? as usize;
+    }
+    (pos <= message.len()).then_some(pos)
+}
+
+/**
+Counts down every TTL in a raw message by `elapsed` seconds without decoding anything else,
+so cached answers go back to clients exactly as the upstream sent them apart from their age.
+The OPT pseudo record keeps its TTL field, it holds EDNS flags rather than a TTL. Returns the
+length of the message, or None for one that doesn't parse and shouldn't be served.
+*/
+pub fn age_ttls(message: &mut [u8], elapsed: u32) -> Option<usize> {
+    for_each_record(message, |rtype, ttl_field| {
+        if rtype != TYPE_OPT {
+            let ttl = u32::from_be_bytes([ttl_field[0], ttl_field[1], ttl_field[2], ttl_field[3]]);
+            ttl_field.copy_from_slice(&ttl.saturating_sub(elapsed).to_be_bytes());
+        }
+    })
+}
+
+// The smallest TTL in a raw message, what it can be cached for
+pub fn min_ttl(message: &[u8]) -> Option<u32> {
+    let mut min = None;
+    for_each_record(&mut message.to_vec(), |rtype, ttl_field| {
+        if rtype != TYPE_OPT {
+            let ttl = u32::from_be_bytes([ttl_field[0], ttl_field[1], ttl_field[2], ttl_field[3]]);
+            min = Some(min.map_or(ttl, |min: u32| min.min(ttl)));
+        }
+    })?;
+    min
+}
+
+pub fn has_opt(message: &[u8]) -> bool {
+    let mut opt = false;
+    for_each_record(&mut message.to_vec(), |rtype, _| opt |= rtype == TYPE_OPT);
+    opt
+}
+
+// The client's own spelling of the name goes back in the question of a cached answer
+pub fn copy_question(query: &[u8], response: &mut [u8]) {
+    if let (Some(end), Some(response_end)) = (question_end(query), question_end(response)) {
+        if end == response_end && query[HEADER_LEN..end].eq_ignore_ascii_case(&response[HEADER_LEN..end]) {
+            response[HEADER_LEN..end].copy_from_slice(&query[HEADER_LEN..end]);
+        }
+    }
+}
+
+// A response belongs to a query if it has the same ID and question, names compared ignoring case
+fn matches(query: &[u8], response: &[u8]) -> bool {
+    let (Some(query_end), Some(response_end)) = (question_end(query), question_end(response)) else {
+        return false;
+    };
+    response[0..2] == query[0..2]
+        && response[2] & 0x80 != 0
+        && query[4..6] == response[4..6]
+        && query[HEADER_LEN..query_end].eq_ignore_ascii_case(&response[HEADER_LEN..response_end])
+}
+
+fn exchange_udp(query: &[u8], server: (Ipv4Addr, u16)) -> io::Result<Vec<u8>> {
+    if let Some(proxy) = socks_proxy().get() {
+        let response = proxy.exchange(query, SocketAddr::from(server))?;
+        return match matches(query, &response) {
+            true => Ok(response),
+            false => Err(io::Error::new(io::ErrorKind::InvalidData, "Response doesn't match the query")),
+        };
+    }
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(query)?;
+    let mut response = vec![0u8; MAX_UDP_RESPONSE];
+    loop {
+        let len = socket.recv(&mut response)?;
+        // Strays and spoofing attempts are ignored, the real answer may still come
+        if matches(query, &response[..len]) {
+            response.truncate(len);
+            return Ok(response);
+        }
+        debug!("Ignoring a response from {:?} that doesn't match the query", server);
+    }
+}
+
+fn exchange_over_tcp(query: &[u8], server: (Ipv4Addr, u16)) -> io::Result<Vec<u8>> {
+    if let Some(proxy) = socks_proxy().get() {
+        return proxy.exchange_over_tcp(query, SocketAddr::from(server));
+    }
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::from(server), QUERY_TIMEOUT)?;
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+    exchange_tcp(&mut stream, query)
+}
+
+/**
+Sends a client's query to an upstream byte for byte and returns the upstream's response the
+same way, records and EDNS options the server doesn't understand included. Only the ID is
+changed on the wire, so clients that happen to pick the same ID can't get each other's answers.
+Truncated answers are fetched again over TCP, the upstreams are tried in order.
+*/
+pub fn relay(query: &[u8], servers: &[(Ipv4Addr, u16)]) -> io::Result<Vec<u8>> {
+    if question_end(query).is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Malformed query"));
+    }
+    let mut message = query.to_vec();
+    message[0..2].copy_from_slice(&(random() as u16).to_be_bytes());
+
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "No upstream servers");
+    for &server in servers {
+        let result = exchange_udp(&message, server).and_then(|response| {
+            if response[2] & 0x02 == 0 {
+                return Ok(response);
+            }
+            info!("Truncated response from {:?}, relaying over TCP", server);
+            let response = exchange_over_tcp(&message, server)?;
+            if matches(&message, &response) {
+                Ok(response)
+            } else {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "TCP response doesn't match the query"))
+            }
+        });
+        match result {
+            Ok(mut response) => {
+                response[0..2].copy_from_slice(&query[0..2]);
+                return Ok(response);
+            }
+            Err(e) => {
+                debug!("Relaying to {:?} failed: {}", server, e);
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+    use crate::utils::byte_buffer::ByteBuffer;
+    use crate::utils::packet::DnsPacket;
+    use crate::utils::query_type::QueryType;
+    use crate::utils::question::DnsQuestion;
+    use crate::utils::record::DnsRecord;
+    use std::thread;
+
+    fn encode(packet: &DnsPacket) -> Vec<u8> {
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.position()].to_vec()
+    }
+
+    fn create_response() -> Vec<u8> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 7;
+        packet.header.response = true;
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300, class: DnsClass::IN });
+        packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 2), ttl: 60, class: DnsClass::IN });
+        let mut message = encode(&packet);
+        // An OPT record the packet types don't know, with DO set in its TTL field
+        message[11] = 1;
+        message.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0x80, 0, 0, 4, 0xde, 0xad, 0xbe, 0xef]);
+        message
+    }
+
+    #[test]
+    fn test_age_ttls() {
+        let mut message = create_response();
+        assert_eq!(min_ttl(&message), Some(60));
+        assert!(has_opt(&message));
+        assert_eq!(age_ttls(&mut message, 100), Some(message.len()));
+        assert_eq!(min_ttl(&message), Some(0));
+
+        let packet = DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(&message)).unwrap();
+        assert_eq!(packet.answers[0].ttl(), 200);
+        assert_eq!(packet.answers[1].ttl(), 0);
+        assert_eq!(message[message.len() - 10..message.len() - 6], [0, 0, 0x80, 0]);
+
+        let truncated = &mut message[..40].to_vec();
+        assert_eq!(age_ttls(truncated, 1), None);
+    }
+
+    #[test]
+    fn test_relay_untouched() {
+        let response = create_response();
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        let sent = response.clone();
+        let server = thread::spawn(move || {
+            let mut data = [0u8; 512];
+            let (len, src) = upstream.recv_from(&mut data).unwrap();
+            // A stray answer to some other query comes first
+            upstream.send_to(b"\x12\x34\x81\x80\x00\x00\x00\x00\x00\x00\x00\x00", src).unwrap();
+            let mut answer = sent.clone();
+            answer[0..2].copy_from_slice(&data[0..2]);
+            upstream.send_to(&answer, src).unwrap();
+            data[..len].to_vec()
+        });
+
+        let mut query = DnsPacket::new();
+        query.header.id = 7;
+        query.questions.push(DnsQuestion::new("Example.COM".to_string(), QueryType::A));
+        let relayed = relay(&encode(&query), &[(Ipv4Addr::LOCALHOST, addr.port())]).unwrap();
+        assert_eq!(relayed, response);
+        assert_eq!(server.join().unwrap()[2..], encode(&query)[2..]);
+    }
+}
@@ -0,0 +1,1048 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::logging::telemetry::{self, SpanKind};
+use crate::resolver::cookies::client_cookies;
+use crate::resolver::happy_eyeballs::{race, reachability};
+use crate::resolver::infra::EdnsSupport;
+use crate::resolver::transport::{Connector, Network};
+use crate::utils::builder::DnsPacketBuilder;
+use crate::utils::byte_buffer::ByteBuffer;
+use crate::utils::dns_class::DnsClass;
+use crate::utils::edns::UDP_PAYLOAD_SIZE;
+use crate::utils::name::{is_subdomain, substitute_dname};
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+use crate::utils::wire::read_large_message;
+
+// a.root-servers.net, b.root-servers.net and c.root-servers.net
+const ROOT_SERVERS: [Ipv4Addr; 3] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+];
+const ROOT_SERVERS_V6: [Ipv6Addr; 3] = [
+    Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30),
+    Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb),
+    Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc),
+];
+
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+// The payload size queries to other servers advertise, the one DNS Flag Day 2020 settled on:
+// answers that size fit a packet on practically every path, so they don't fragment
+pub const UPSTREAM_PAYLOAD_SIZE: u16 = 1232;
+const ROOT_TTL: u32 = 518400;
+// CNAMEs and DNAMEs followed out of the zone that answered, per query
+const MAX_ALIAS_HOPS: usize = 8;
+// Nameservers without glue looked up per referral, more are ignored (NXNSAttack)
+const MAX_GLUELESS: usize = 4;
+// Lookups of glueless nameservers nested in each other, for delegation chains that never end
+const MAX_GLUELESS_DEPTH: usize = 4;
+
+// Which nameserver addresses recursion uses, hosts without IPv4 (or IPv6) can leave the other out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpPreference {
+    #[default]
+    Ipv4,     // both, IPv4 first
+    Ipv6,     // both, IPv6 first
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl IpPreference {
+    fn allows(self, addr: &IpAddr) -> bool {
+        match self {
+            IpPreference::Ipv4Only => addr.is_ipv4(),
+            IpPreference::Ipv6Only => addr.is_ipv6(),
+            _ => true,
+        }
+    }
+
+    fn prefers_ipv6(self) -> bool {
+        matches!(self, IpPreference::Ipv6 | IpPreference::Ipv6Only)
+    }
+
+    // The usable addresses, the preferred family first and otherwise in the order given
+    pub fn order(self, addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+        let mut addrs: Vec<IpAddr> = addrs.into_iter().filter(|addr| self.allows(addr)).collect();
+        addrs.sort_by_key(|addr| addr.is_ipv6() != self.prefers_ipv6());
+        addrs
+    }
+
+    // The address types a nameserver without glue is resolved with, in the order they are tried
+    fn address_types(self) -> Vec<QueryType> {
+        match self {
+            IpPreference::Ipv4 => vec![QueryType::A, QueryType::AAAA],
+            IpPreference::Ipv6 => vec![QueryType::AAAA, QueryType::A],
+            IpPreference::Ipv4Only => vec![QueryType::A],
+            IpPreference::Ipv6Only => vec![QueryType::AAAA],
+        }
+    }
+}
+
+// Set once at startup from `[recursion] ip_preference`
+pub fn ip_preference() -> &'static OnceLock<IpPreference> {
+    static PREFERENCE: OnceLock<IpPreference> = OnceLock::new();
+    &PREFERENCE
+}
+
+// The root servers one at a time, each with its IPv4 and IPv6 address
+fn root_nameservers() -> Vec<Vec<IpAddr>> {
+    ROOT_SERVERS.into_iter().zip(ROOT_SERVERS_V6).map(|(v4, v6)| vec![IpAddr::V4(v4), IpAddr::V6(v6)]).collect()
+}
+
+// The root hints as a referral, the best a client that doesn't want recursion can get from us
+pub fn add_root_referral(packet: &mut DnsPacket) {
+    for ((letter, addr), addr_v6) in ['a', 'b', 'c'].into_iter().zip(ROOT_SERVERS).zip(ROOT_SERVERS_V6) {
+        let ns = format!("{}.root-servers.net", letter);
+        packet.authorities.push(DnsRecord::NS { domain: String::new(), ns: ns.clone(), ttl: ROOT_TTL, class: DnsClass::IN });
+        packet.resources.push(DnsRecord::A { domain: ns.clone(), addr, ttl: ROOT_TTL, class: DnsClass::IN });
+        packet.resources.push(DnsRecord::AAAA { domain: ns, addr: addr_v6, ttl: ROOT_TTL, class: DnsClass::IN });
+    }
+}
+
+pub fn recursive_lookup(qname: &str, qtype: QueryType) -> io::Result<DnsPacket> {
+    recursive_lookup_via(qname, qtype, &Network)
+}
+
+/**
+Follows the delegations from the root down, every query goes out through `connector`. An answer
+that is an alias into another zone, a CNAME or a DNAME with the CNAME it implies, is followed
+from the root again and the answers are joined into one response, like any resolver does. The
+connector is shared with the queries a nameserver's other addresses race in the background.
+*/
+pub fn recursive_lookup_via(qname: &str, qtype: QueryType, connector: &'static dyn Connector) -> io::Result<DnsPacket> {
+    recurse(qname, qtype, Recursion { connector, deadline: None, progress: None, depth: 0 })
+}
+
+/**
+`recursive_lookup_via` for a client that needs its answer by `deadline`: past it no more queries
+go out and the lookup fails with `TimedOut`. The answer assembled so far, the aliases followed
+before the last of them went unanswered, is kept in `progress` all along.
+*/
+pub fn recursive_lookup_until(qname: &str, qtype: QueryType, connector: &'static dyn Connector, deadline: Instant, progress: &Progress) -> io::Result<DnsPacket> {
+    recurse(qname, qtype, Recursion { connector, deadline: Some(deadline), progress: Some(progress), depth: 0 })
+}
+
+// Where a lookup with a deadline leaves the answer it had when the time ran out
+pub type Progress = Mutex<Option<DnsPacket>>;
+
+// What every step of one lookup shares
+#[derive(Clone, Copy)]
+struct Recursion<'a> {
+    connector: &'static dyn Connector,
+    deadline: Option<Instant>,
+    progress: Option<&'a Progress>,
+    depth: usize, // the glueless nameservers whose lookups this one is nested in
+}
+
+impl Recursion<'_> {
+    fn in_time(&self, qname: &str) -> io::Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(io::Error::new(io::ErrorKind::TimedOut, format!("Ran out of time resolving {}", qname))),
+            _ => Ok(()),
+        }
+    }
+
+    fn publish(&self, res: &DnsPacket) {
+        if let Some(progress) = self.progress {
+            *progress.lock().unwrap() = Some(res.clone());
+        }
+    }
+
+    // The lookup of a glueless nameserver, whose answer is no part of the client's
+    fn nested(&self) -> Self {
+        Recursion { depth: self.depth + 1, progress: None, ..*self }
+    }
+}
+
+fn recurse(qname: &str, qtype: QueryType, rec: Recursion) -> io::Result<DnsPacket> {
+    let mut span = telemetry::span("recursive_lookup", SpanKind::Internal);
+    span.attr("dns.qname", qname);
+    span.attr("dns.qtype", format!("{:?}", qtype));
+    let mut res = resolve(qname, qtype, rec)?;
+    rec.publish(&res);
+    if matches!(qtype, QueryType::CNAME | QueryType::DNAME) {
+        return Ok(res);
+    }
+
+    let mut followed = vec![qname.to_ascii_lowercase()];
+    for _ in 0..MAX_ALIAS_HOPS {
+        let target = synthesize_cnames(qname, &mut res);
+        let answered = res.answers.iter().any(|record| record.query_type() == qtype && record.domain().eq_ignore_ascii_case(&target));
+        if res.header.rescode != ResultCode::NOERROR || answered || followed.contains(&target.to_ascii_lowercase()) {
+            break;
+        }
+        debug!("Following the alias of {} to {}", qname, target);
+        let next = resolve(&target, qtype, rec)?;
+        res.answers.extend(next.answers);
+        res.authorities = next.authorities;
+        res.resources = next.resources;
+        res.header.rescode = next.header.rescode;
+        rec.publish(&res);
+        followed.push(target.to_ascii_lowercase());
+    }
+    Ok(res)
+}
+
+/**
+Adds the CNAME a DNAME in the answer implies for the name it covers (RFC 6672 3.2), right after
+the DNAME and with its TTL, unless the server already sent that CNAME. Returns the name the alias
+chain from `qname` ends at, `qname` itself when there is none.
+*/
+pub fn synthesize_cnames(qname: &str, packet: &mut DnsPacket) -> String {
+    let mut name = qname.to_string();
+    for _ in 0..MAX_ALIAS_HOPS {
+        let cname = packet.answers.iter().find_map(|record| match record {
+            DnsRecord::CNAME { domain, cname, .. } if domain.eq_ignore_ascii_case(&name) => Some(cname.clone()),
+            _ => None,
+        });
+        if let Some(cname) = cname {
+            name = cname;
+            continue;
+        }
+        let synthesized = packet.answers.iter().enumerate().find_map(|(i, record)| match record {
+            DnsRecord::DNAME { domain, target, ttl, class } => substitute_dname(&name, domain, target)
+                .map(|cname| (i, DnsRecord::CNAME { domain: name.clone(), cname, ttl: *ttl, class: *class })),
+            _ => None,
+        });
+        let Some((i, record)) = synthesized else {
+            break;
+        };
+        if let DnsRecord::CNAME { cname, .. } = &record {
+            name = cname.clone();
+        }
+        packet.answers.insert(i + 1, record);
+    }
+    name
+}
+
+/*
+One name from the closest zone whose servers are known down to the servers that answer for it,
+from the root when no zone above it is known. Every referral on the way is remembered, with the
+addresses of its nameservers, for the queries that come after.
+*/
+fn resolve(qname: &str, qtype: QueryType, rec: Recursion) -> io::Result<DnsPacket> {
+    let connector = rec.connector;
+    let closest = connector.infra().lock().unwrap().closest_delegation(qname);
+    let mut from_cache = closest.is_some();
+    // The zone the current servers are authoritative for, used to decide which glue to trust
+    let (mut zone, mut servers) = closest.unwrap_or_else(|| (String::new(), root_nameservers()));
+    let mut names: Vec<String> = Vec::new();
+
+    loop {
+        let res = match query_zone(qname, qtype, &zone, &servers, &names, rec) {
+            Ok(res) => res,
+            // The servers of a remembered zone may have moved on since, the root knows where to
+            Err(e) if from_cache && rec.in_time(qname).is_ok() => {
+                debug!("Known servers for zone {:?} failed ({}), starting from the root", zone, e);
+                connector.infra().lock().unwrap().forget_delegation(&zone);
+                return resolve(qname, qtype, rec);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if !res.answers.is_empty() && res.header.rescode == ResultCode::NOERROR {
+            return Ok(res);
+        }
+
+        if res.answers.is_empty() && res.header.rescode == ResultCode::NXDOMAIN {
+            return Ok(res);
+        }
+
+        // Only follow referrals further down the tree, anything else would loop forever
+        let referral_zone = match res.get_referral_zone(qname) {
+            Some(referral_zone) if is_subdomain(referral_zone, &zone) && !is_subdomain(&zone, referral_zone) => {
+                referral_zone.to_string()
+            }
+            _ => return Ok(res),
+        };
+
+        let (glue, unresolved) = referral_servers(&res, &zone, &referral_zone, connector);
+        if glue.is_empty() && unresolved.is_empty() {
+            return Err(io::Error::other(format!("The referral from zone {:?} to {:?} has no usable nameserver", zone, referral_zone)));
+        }
+
+        debug!("Referred from {:?} to {:?}: {:?}, without glue {:?}", zone, referral_zone, glue, unresolved);
+        servers = glue;
+        names = unresolved;
+        zone = referral_zone;
+        from_cache = false;
+    }
+}
+
+fn has_glue(packet: &DnsPacket, ns: &str) -> bool {
+    packet.resources.iter().any(|rec| matches!(rec, DnsRecord::A { .. } | DnsRecord::AAAA { .. }) && rec.domain().eq_ignore_ascii_case(ns))
+}
+
+// Addresses nothing on the internet can answer from: a referral there is a mistake, or an attempt
+// to have us query ourselves or flood a multicast group
+fn is_usable_address(addr: &IpAddr) -> bool {
+    let special = match addr {
+        IpAddr::V4(addr) => addr.is_broadcast(),
+        IpAddr::V6(addr) => addr.to_ipv4_mapped().is_some(),
+    };
+    !(addr.is_unspecified() || addr.is_loopback() || addr.is_multicast() || special)
+}
+
+// The usable addresses of a name among the records and the lowest TTL of those it has
+fn addresses_of(records: &[DnsRecord], name: &str) -> (Vec<IpAddr>, u32) {
+    let found: Vec<(IpAddr, u32)> = records.iter().filter(|record| record.domain().eq_ignore_ascii_case(name)).filter_map(|record| match record {
+        DnsRecord::A { addr, ttl, .. } => Some((IpAddr::V4(*addr), *ttl)),
+        DnsRecord::AAAA { addr, ttl, .. } => Some((IpAddr::V6(*addr), *ttl)),
+        _ => None,
+    }).filter(|(addr, _)| {
+        if !is_usable_address(addr) {
+            warn!("Ignoring address {} of nameserver {}", addr, name);
+        }
+        is_usable_address(addr)
+    }).collect();
+    (found.iter().map(|(addr, _)| *addr).collect(), found.iter().map(|(_, ttl)| *ttl).min().unwrap_or(0))
+}
+
+/*
+The servers a referral from `zone` to `referral_zone` leads to: the addresses of the nameservers
+with glue we can trust or addresses we know already, then the names of the others. Glue is only
+trusted inside `zone`, the servers sending it can't speak for anything else. A nameserver inside
+the zone it serves can't be looked up without glue, that would need the zone itself, and no more
+than `MAX_GLUELESS` others are. The delegation and its glue are remembered for later queries.
+*/
+fn referral_servers(res: &DnsPacket, zone: &str, referral_zone: &str, connector: &dyn Connector) -> (Vec<Vec<IpAddr>>, Vec<String>) {
+    let nameservers: Vec<(String, u32)> = res.authorities.iter().filter_map(|record| match record {
+        DnsRecord::NS { domain, ns, ttl, .. } if domain.eq_ignore_ascii_case(referral_zone) => Some((ns.clone(), *ttl)),
+        _ => None,
+    }).collect();
+    let mut infra = connector.infra().lock().unwrap();
+    let (mut glue, mut unresolved) = (Vec::new(), Vec::new());
+    for (ns, _) in &nameservers {
+        if is_subdomain(ns, zone) {
+            let (addrs, ttl) = addresses_of(&res.resources, ns);
+            if !addrs.is_empty() {
+                infra.add_addresses(ns, addrs.clone(), ttl);
+                glue.push(addrs);
+                continue;
+            }
+        } else if has_glue(res, ns) {
+            warn!("Ignoring out-of-bailiwick glue for {} from zone {:?}", ns, zone);
+        }
+        if let Some(addrs) = infra.addresses(ns) {
+            glue.push(addrs);
+        } else if is_subdomain(ns, referral_zone) {
+            warn!("Nameserver {} of zone {:?} has no glue, it can't be looked up", ns, referral_zone);
+        } else if unresolved.len() < MAX_GLUELESS {
+            unresolved.push(ns.clone());
+        } else {
+            debug!("Not looking up nameserver {} of zone {:?}, enough others are", ns, referral_zone);
+        }
+    }
+    let ttl = nameservers.iter().map(|(_, ttl)| *ttl).min().unwrap_or(0);
+    infra.add_delegation(referral_zone, nameservers.into_iter().map(|(ns, _)| ns).collect(), ttl);
+    (glue, unresolved)
+}
+
+// Each nameserver's usable addresses, the preferred family first unless it has been unreachable
+// lately, and the nameservers fastest first
+fn order_nameservers(servers: &[Vec<IpAddr>], connector: &dyn Connector) -> Vec<Vec<IpAddr>> {
+    let mut servers: Vec<Vec<IpAddr>> = servers.iter()
+        .map(|addrs| reachability().lock().unwrap().order(preference().order(addrs.clone())))
+        .filter(|addrs| !addrs.is_empty())
+        .collect();
+    let firsts: Vec<IpAddr> = servers.iter().map(|addrs| addrs[0]).collect();
+    connector.infra().lock().unwrap().rtt.order(&firsts).into_iter()
+        .filter_map(|first| Some(servers.swap_remove(servers.iter().position(|addrs| addrs[0] == first)?)))
+        .collect()
+}
+
+/*
+Asks the nameservers for `zone` in turn, fastest first, until one of them gives a usable response. A
+nameserver with an IPv4 and an IPv6 address is asked at the second one too when the first doesn't
+answer within the head start. Servers that answer lamely are remembered and skipped by later
+queries for the same zone.
+*/
+fn query_zone(qname: &str, qtype: QueryType, zone: &str, servers: &[Vec<IpAddr>], names: &[String], rec: Recursion) -> io::Result<DnsPacket> {
+    let connector = rec.connector;
+    let candidates = order_nameservers(servers, connector).into_iter().chain(names.iter().filter_map(|ns| resolve_ns(ns, rec)).map(|addr| vec![addr]));
+
+    for addrs in candidates {
+        rec.in_time(qname)?;
+        let addrs: Vec<IpAddr> = addrs.into_iter().filter(|server| {
+            let lame = connector.infra().lock().unwrap().lame.is_lame(*server, zone);
+            if lame {
+                debug!("Skipping {}, it is lame for zone {:?}", server, zone);
+            }
+            !lame
+        }).collect();
+        if addrs.is_empty() {
+            continue;
+        }
+
+        let name = qname.to_string();
+        let attempt = move |server: IpAddr| {
+            let start = Instant::now();
+            let opt = DnsRecord::OPT { udp_size: UPSTREAM_PAYLOAD_SIZE, flags: 0, options: Vec::new() };
+            let res = lookup_via(&name, qtype, (server, 53).into(), false, Some(&opt), connector);
+            connector.infra().lock().unwrap().rtt.record(server, start.elapsed());
+            // The server's OPT record is about our exchange with it, not about the answer
+            res.map(|mut res| {
+                res.take_opt();
+                (res, start.elapsed())
+            })
+        };
+        let found = race(&addrs, rec.deadline, attempt, |server, res| {
+            debug!("Asked {} for {} {:?} in zone {:?}, {:?}", server, qname, qtype, zone,
+                res.as_ref().map(|(res, elapsed)| (res.header.rescode, elapsed)));
+            match res {
+                Ok((res, _)) if !is_lame_response(&res, qname, zone) => return Some(res),
+                Ok((res, _)) => warn!("Lame response from {} for zone {:?} ({:?})", server, zone, res.header.rescode),
+                // A timeout says more about the network than about the server, so don't hold it against it
+                Err(e) if timed_out(&e) => {
+                    warn!("Timed out waiting for {} for zone {:?}", server, zone);
+                    return None;
+                }
+                Err(e) => warn!("Unparseable response from {} for zone {:?}: {}", server, zone, e),
+            }
+            connector.infra().lock().unwrap().lame.mark(server, zone);
+            None
+        });
+        if let Some(res) = found {
+            return Ok(res);
+        }
+    }
+
+    rec.in_time(qname)?;
+    Err(io::Error::other(format!("No working nameserver for zone {:?}", zone)))
+}
+
+fn preference() -> IpPreference {
+    ip_preference().get().copied().unwrap_or_default()
+}
+
+// The first address of the nameserver in the preferred family, the other one if it has none
+fn resolve_ns(ns: &str, rec: Recursion) -> Option<IpAddr> {
+    if rec.depth >= MAX_GLUELESS_DEPTH {
+        warn!("Not looking up nameserver {}, {} glueless delegations deep already", ns, rec.depth);
+        return None;
+    }
+    for qtype in preference().address_types() {
+        match recurse(ns, qtype, rec.nested()) {
+            Ok(res) => {
+                let (addrs, ttl) = addresses_of(&res.answers, ns);
+                if let Some(addr) = addrs.first().copied() {
+                    rec.connector.infra().lock().unwrap().add_addresses(ns, addrs, ttl);
+                    return Some(addr);
+                }
+            }
+            Err(e) => warn!("Failed to resolve nameserver {} {:?}: {}", ns, qtype, e),
+        }
+    }
+    None
+}
+
+// A server is lame for `zone` when it refuses us, or when it answers without authority and
+// without referring us to a zone below the one it is supposed to serve. A referral naming the
+// nameservers of more than one zone is bogus, whichever of them we picked.
+pub fn is_lame_response(res: &DnsPacket, qname: &str, zone: &str) -> bool {
+    if res.header.rescode == ResultCode::REFUSED {
+        return true;
+    }
+    if res.header.authoritative_answer {
+        return false;
+    }
+    let mut owners = res.authorities.iter().filter_map(|record| match record {
+        DnsRecord::NS { domain, .. } => Some(domain.trim_end_matches('.')),
+        _ => None,
+    });
+    if let Some(first) = owners.next() {
+        if owners.any(|owner| !owner.eq_ignore_ascii_case(first)) {
+            return true;
+        }
+    }
+
+    match res.get_referral_zone(qname) {
+        // Upward or sideways referrals are as useless as no answer at all
+        Some(referral_zone) => !is_subdomain(referral_zone, zone) || is_subdomain(zone, referral_zone),
+        None => true,
+    }
+}
+
+pub fn lookup(qname: &str, qtype: QueryType, server: impl Into<SocketAddr>) -> io::Result<DnsPacket> {
+    lookup_with(qname, qtype, server, false, None)
+}
+
+// `edns` is a client's OPT record to pass on, only its payload size is replaced with ours
+pub fn build_query(qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>) -> io::Result<Vec<u8>> {
+    build_query_sized(qname, qtype, checking_disabled, edns, UDP_PAYLOAD_SIZE)
+}
+
+fn build_query_sized(qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>, udp_size: u16) -> io::Result<Vec<u8>> {
+    let mut packet = DnsPacketBuilder::query(qname, qtype).id(6666).checking_disabled(checking_disabled).build();
+    if let Some(DnsRecord::OPT { flags, options, .. }) = edns {
+        packet.resources.push(DnsRecord::OPT { udp_size, flags: *flags, options: options.clone() });
+    }
+
+    let mut req_buffer = ByteBuffer::new();
+    packet.write(&mut req_buffer)?;
+    Ok(req_buffer.buffer[0..req_buffer.position].to_vec())
+}
+
+// `checking_disabled` asks a validating upstream to hand over data even if it fails validation
+pub fn lookup_with(qname: &str, qtype: QueryType, server: impl Into<SocketAddr>, checking_disabled: bool, edns: Option<&DnsRecord>) -> io::Result<DnsPacket> {
+    lookup_via(qname, qtype, server.into(), checking_disabled, edns, &Network)
+}
+
+pub fn lookup_via(qname: &str, qtype: QueryType, server: SocketAddr, checking_disabled: bool, edns: Option<&DnsRecord>, connector: &dyn Connector) -> io::Result<DnsPacket> {
+    let mut span = telemetry::span("upstream.send", SpanKind::Client);
+    span.attr("server.address", server.ip());
+    span.attr("server.port", server.port());
+    span.attr("dns.qname", qname);
+    span.attr("dns.qtype", format!("{:?}", qtype));
+    let result = send_query(qname, qtype, server, checking_disabled, edns, connector);
+    match &result {
+        Ok(packet) => span.attr("dns.rcode", format!("{:?}", packet.header.rescode)),
+        Err(e) => span.error(e),
+    }
+    result
+}
+
+fn send_query(qname: &str, qtype: QueryType, server: SocketAddr, checking_disabled: bool, edns: Option<&DnsRecord>, connector: &dyn Connector) -> io::Result<DnsPacket> {
+    match client_cookies().get() {
+        Some(cookies) => cookies.exchange(server, edns, |edns| exchange_query(qname, qtype, server, checking_disabled, edns, connector)),
+        None => exchange_query(qname, qtype, server, checking_disabled, edns, connector),
+    }
+}
+
+fn timed_out(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+fn exchange_udp(query: &[u8], server: SocketAddr, connector: &dyn Connector) -> io::Result<DnsPacket> {
+    let mut udp = connector.udp(server)?;
+    udp.send_query(query)?;
+    parse_response(&udp.recv_response()?, server)
+}
+
+fn exchange_tcp_via(query: &[u8], server: SocketAddr, connector: &dyn Connector) -> io::Result<DnsPacket> {
+    let mut tcp = connector.tcp(server)?;
+    tcp.send_query(query)?;
+    parse_response(&tcp.recv_response()?, server)
+}
+
+// The query at an EDNS level: `edns` with our full payload size, advertising 512 bytes, or left out
+fn edns_query(qname: &str, qtype: QueryType, checking_disabled: bool, edns: Option<&DnsRecord>, level: EdnsSupport) -> io::Result<Vec<u8>> {
+    match level {
+        EdnsSupport::Full => build_query_sized(qname, qtype, checking_disabled, edns, UPSTREAM_PAYLOAD_SIZE),
+        EdnsSupport::Small => build_query(qname, qtype, checking_disabled, edns),
+        EdnsSupport::Off => build_query(qname, qtype, checking_disabled, None),
+    }
+}
+
+/*
+UDP first, then TCP when the answer was truncated or UDP got no answer at all. A server that only
+answers over TCP is remembered, so the next queries to it don't wait for UDP to time out again.
+
+A query with an OPT record goes out with as much EDNS as the server last answered to, the full
+payload size when we don't know yet. A server that leaves it unanswered over UDP is asked once
+more: a level down when it has answered EDNS before, without EDNS when it never has, as old
+servers and firewalls drop any query with an OPT record. One that rejects the OPT record with
+FORMERR or NOTIMP is asked again without it. The level that got an answer is remembered for the
+next queries.
+*/
+fn exchange_query(qname: &str, qtype: QueryType, server: SocketAddr, checking_disabled: bool, edns: Option<&DnsRecord>, connector: &dyn Connector) -> io::Result<DnsPacket> {
+    let known = edns.and_then(|_| connector.infra().lock().unwrap().edns(server.ip()));
+    let mut level = match edns {
+        Some(_) => known.unwrap_or(EdnsSupport::Full),
+        None => EdnsSupport::Off,
+    };
+    let query = |level| edns_query(qname, qtype, checking_disabled, edns, level);
+    if connector.infra().lock().unwrap().tcp_only.contains(server.ip()) {
+        return exchange_tcp_via(&query(level)?, server, connector);
+    }
+
+    let mut result = exchange_udp(&query(level)?, server, connector);
+    let retry = match &result {
+        Err(e) if timed_out(e) && level != EdnsSupport::Off => Some(known.map_or(EdnsSupport::Off, EdnsSupport::lower)),
+        Ok(packet) if level != EdnsSupport::Off && packet.opt().is_none() && matches!(packet.header.rescode, ResultCode::FORMERR | ResultCode::NOTIMP) => Some(EdnsSupport::Off),
+        _ => None,
+    };
+    if let Some(lower) = retry {
+        debug!("No usable answer from {} to EDNS {:?} for {} {:?}, trying {:?}", server, level, qname, qtype, lower);
+        match exchange_udp(&query(lower)?, server, connector) {
+            // Silence at both levels says the server or UDP is down rather than EDNS, that's for TCP to find out
+            Err(e) if timed_out(&e) && result.is_err() => {}
+            retried => {
+                level = lower;
+                result = retried;
+            }
+        }
+    }
+
+    let packet = match result {
+        Ok(packet) if !packet.header.truncated_message => packet,
+        Ok(_) => {
+            // Caching the part that fit would serve clients an incomplete answer, so ask again over TCP
+            info!("Truncated response from {} for {} {:?}, retrying over TCP", server, qname, qtype);
+            exchange_tcp_via(&query(level)?, server, connector)?
+        }
+        Err(e) if timed_out(&e) => {
+            // When TCP fails too the server is down, which is what the UDP timeout already says
+            debug!("No answer over UDP from {} for {} {:?}, trying TCP", server, qname, qtype);
+            let packet = exchange_tcp_via(&query(level)?, server, connector).map_err(|_| e)?;
+            info!("{} only answers over TCP, asking it over TCP from now on", server);
+            connector.infra().lock().unwrap().tcp_only.mark(server.ip());
+            packet
+        }
+        Err(e) => return Err(e),
+    };
+    // A server that answers an OPT record without one of its own ignores EDNS, it gets none from now on
+    if edns.is_some() {
+        let level = if packet.opt().is_some() { level } else { EdnsSupport::Off };
+        connector.infra().lock().unwrap().set_edns(server.ip(), level);
+    }
+    Ok(packet)
+}
+
+// DNS over TCP prefixes every message with its length
+pub fn write_message(stream: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)
+}
+
+pub fn read_message(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+pub fn exchange_tcp<S: Read + Write>(stream: &mut S, query: &[u8]) -> io::Result<Vec<u8>> {
+    write_message(stream, query)?;
+    read_message(stream)
+}
+
+/*
+Without EDNS a UDP answer never exceeds 512 bytes, but one over TCP can: referrals with many
+nameservers, answers with DNSSEC records. Those are read record by record and kept whole, they
+go back to clients over TCP as they are and as a truncated answer over UDP.
+*/
+pub fn parse_response(response: &[u8], server: SocketAddr) -> io::Result<DnsPacket> {
+    if response.len() <= 512 {
+        return Ok(DnsPacket::from_bytes(response)?);
+    }
+    let packet = read_large_message(response)?;
+    debug!("Read a {} byte response from {} with {} answer, {} authority and {} additional records", response.len(), server,
+        packet.answers.len(), packet.authorities.len(), packet.resources.len());
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::infra::InfraCache;
+    use crate::resolver::transport::MockNetwork;
+    use crate::utils::wire::write_large_message;
+    use std::net::{TcpListener, UdpSocket};
+    use std::thread;
+
+    fn root_servers() -> Vec<IpAddr> {
+        root_nameservers().concat()
+    }
+
+    fn create_referral(zone: &str) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.authorities.push(DnsRecord::NS {
+            domain: zone.to_string(),
+            ns: format!("ns1.{}", zone),
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+        packet
+    }
+
+    #[test]
+    fn test_downward_referral_is_not_lame() {
+        let packet = create_referral("example.com");
+        assert!(!is_lame_response(&packet, "www.example.com", "com"));
+        assert!(!is_lame_response(&create_referral("com"), "www.example.com", ""));
+    }
+
+    #[test]
+    fn test_upward_referral_is_lame() {
+        assert!(is_lame_response(&create_referral("com"), "www.example.com", "example.com"));
+        assert!(is_lame_response(&create_referral("example.com"), "www.example.com", "example.com"));
+    }
+
+    #[test]
+    fn test_refused_and_non_authoritative_answers_are_lame() {
+        let mut refused = create_referral("example.com");
+        refused.header.rescode = ResultCode::REFUSED;
+        assert!(is_lame_response(&refused, "www.example.com", "com"));
+
+        let mut answer = DnsPacket::new();
+        answer.answers.push(DnsRecord::A {
+            domain: "www.example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 3600,
+            class: DnsClass::IN,
+        });
+        assert!(is_lame_response(&answer, "www.example.com", "example.com"));
+
+        answer.header.authoritative_answer = true;
+        assert!(!is_lame_response(&answer, "www.example.com", "example.com"));
+    }
+
+    #[test]
+    fn test_query_carries_client_options() {
+        use crate::utils::edns::{EdnsOption, FLAG_DO, OPTION_COOKIE};
+        let opt = DnsRecord::OPT { udp_size: 4096, flags: FLAG_DO, options: vec![EdnsOption::new(OPTION_COOKIE, vec![7; 8])] };
+        let query = build_query("example.com", QueryType::A, false, Some(&opt)).unwrap();
+        let packet = DnsPacket::from_bytes(&query).unwrap();
+        assert_eq!(packet.opt(), Some(&DnsRecord::OPT { udp_size: UDP_PAYLOAD_SIZE, flags: FLAG_DO, options: vec![EdnsOption::new(OPTION_COOKIE, vec![7; 8])] }));
+
+        let query = build_query("example.com", QueryType::A, false, None).unwrap();
+        assert!(DnsPacket::from_bytes(&query).unwrap().resources.is_empty());
+    }
+
+    #[test]
+    fn test_root_referral() {
+        let mut packet = DnsPacket::new();
+        add_root_referral(&mut packet);
+
+        assert_eq!(packet.get_referral_zone("www.example.com"), Some(""));
+        assert_eq!(packet.get_glue_addrs("www.example.com", ""), root_servers());
+
+        // The root owner name has to survive a round trip through the wire format
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.seek(0).unwrap();
+        let read = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(read.authorities, packet.authorities);
+        assert_eq!(read.resources, packet.resources);
+    }
+
+    #[test]
+    fn test_ip_preference_order() {
+        let v4 = IpAddr::from([192, 0, 2, 1]);
+        let v6 = IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        let v4b = IpAddr::from([192, 0, 2, 2]);
+        let addrs = vec![v4, v6, v4b];
+
+        assert_eq!(IpPreference::Ipv4.order(addrs.clone()), vec![v4, v4b, v6]);
+        assert_eq!(IpPreference::Ipv6.order(addrs.clone()), vec![v6, v4, v4b]);
+        assert_eq!(IpPreference::Ipv4Only.order(addrs.clone()), vec![v4, v4b]);
+        assert_eq!(IpPreference::Ipv6Only.order(addrs), vec![v6]);
+    }
+
+    fn response_to(query: &[u8], truncated: bool, addrs: u8) -> Vec<u8> {
+        let mut packet = DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(query)).unwrap();
+        packet.header.response = true;
+        packet.header.truncated_message = truncated;
+        for i in 0..addrs {
+            packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, i), ttl: 300, class: DnsClass::IN });
+        }
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.position].to_vec()
+    }
+
+    // `response_to` with `glue` more additional records than the packet buffer holds, the way a TCP answer can come
+    fn large_response_to(query: &[u8], addrs: u8, glue: u8) -> Vec<u8> {
+        let mut response = response_to(query, false, addrs);
+        response[10..12].copy_from_slice(&(glue as u16).to_be_bytes());
+        for i in 0..glue {
+            // Owned by the question's name, 16 bytes each
+            response.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 198, 51, 100, i]);
+        }
+        response
+    }
+
+    fn serve_truncated_then_tcp(response: impl FnOnce(&[u8]) -> Vec<u8> + Send + 'static) -> (u16, thread::JoinHandle<()>) {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = udp.local_addr().unwrap().port();
+        let tcp = TcpListener::bind(("127.0.0.1", port)).unwrap();
+
+        let server = thread::spawn(move || {
+            let mut query = [0u8; 512];
+            let (len, src) = udp.recv_from(&mut query).unwrap();
+            udp.send_to(&response_to(&query[..len], true, 1), src).unwrap();
+
+            let (mut stream, _) = tcp.accept().unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut query).unwrap();
+            let response = response(&query);
+            stream.write_all(&(response.len() as u16).to_be_bytes()).unwrap();
+            stream.write_all(&response).unwrap();
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn test_truncated_response_retried_over_tcp() {
+        let (port, server) = serve_truncated_then_tcp(|query| response_to(query, false, 3));
+        let packet = lookup("example.com", QueryType::A, (Ipv4Addr::LOCALHOST, port)).unwrap();
+        server.join().unwrap();
+        assert!(!packet.header.truncated_message);
+        assert_eq!(packet.answers.len(), 3);
+    }
+
+    #[test]
+    fn test_large_tcp_response_kept() {
+        let (port, server) = serve_truncated_then_tcp(|query| {
+            let response = large_response_to(query, 3, 40);
+            assert!(response.len() > 512);
+            response
+        });
+        let packet = lookup("example.com", QueryType::A, (Ipv4Addr::LOCALHOST, port)).unwrap();
+        server.join().unwrap();
+        // Nothing is left out, the whole answer only goes out again over TCP
+        assert_eq!((packet.answers.len(), packet.resources.len()), (3, 40));
+        assert!(packet.write(&mut ByteBuffer::new()).is_err());
+        assert!(write_large_message(&packet).unwrap().len() > 512);
+    }
+
+    fn referral_to(zone: &str, ns: &str, glue: Option<[u8; 4]>) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.authorities.push(DnsRecord::NS { domain: zone.to_string(), ns: ns.to_string(), ttl: 3600, class: DnsClass::IN });
+        if let Some(addr) = glue {
+            packet.resources.push(DnsRecord::A { domain: ns.to_string(), addr: addr.into(), ttl: 3600, class: DnsClass::IN });
+        }
+        packet
+    }
+
+    fn answer(name: &str, addr: [u8; 4]) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.authoritative_answer = true;
+        packet.answers.push(DnsRecord::A { domain: name.to_string(), addr: addr.into(), ttl: 300, class: DnsClass::IN });
+        packet
+    }
+
+    #[test]
+    fn test_udp_blocked_falls_back_to_tcp() {
+        let network = MockNetwork::without_udp(|_, query| Some(answer(&query.questions[0].name, [192, 0, 2, 80])));
+        let server = SocketAddr::from(([192, 0, 2, 53], 53));
+
+        let res = lookup_via("www.example.com", QueryType::A, server, false, None, &network).unwrap();
+        assert_eq!(res.answers.len(), 1);
+        assert!(network.infra().lock().unwrap().tcp_only.contains(server.ip()));
+    }
+
+    #[test]
+    fn test_edns_fallback() {
+        // .53 drops every query with an OPT record, .54 rejects it, .55 only loses answers too large for a packet
+        let network = MockNetwork::new(|server, query| {
+            let size = match query.opt() {
+                Some(DnsRecord::OPT { udp_size, .. }) => Some(*udp_size),
+                _ => None,
+            };
+            match (server, size) {
+                (_, None) => Some(answer(&query.questions[0].name, [192, 0, 2, 80])),
+                (IpAddr::V4(addr), Some(_)) if addr.octets()[3] == 54 => {
+                    let mut packet = DnsPacket::new();
+                    packet.header.rescode = ResultCode::FORMERR;
+                    Some(packet)
+                }
+                (IpAddr::V4(addr), Some(size)) if addr.octets()[3] == 55 && size <= UDP_PAYLOAD_SIZE => {
+                    let mut packet = answer(&query.questions[0].name, [192, 0, 2, 80]);
+                    packet.resources.push(DnsRecord::OPT { udp_size: size, flags: 0, options: Vec::new() });
+                    Some(packet)
+                }
+                _ => None,
+            }
+        });
+        let opt = DnsRecord::OPT { udp_size: UPSTREAM_PAYLOAD_SIZE, flags: 0, options: Vec::new() };
+
+        for (last, supported) in [(53, EdnsSupport::Off), (54, EdnsSupport::Off)] {
+            let server = SocketAddr::from(([192, 0, 2, last], 53));
+            assert_eq!(lookup_via("www.example.com", QueryType::A, server, false, Some(&opt), &network).unwrap().answers.len(), 1);
+            assert_eq!(network.infra().lock().unwrap().edns(server.ip()), Some(supported));
+            assert!(!network.infra().lock().unwrap().tcp_only.contains(server.ip()));
+        }
+
+        // A server known to do EDNS only steps down to the small payload size
+        let server = SocketAddr::from(([192, 0, 2, 55], 53));
+        network.infra().lock().unwrap().set_edns(server.ip(), EdnsSupport::Full);
+        let res = lookup_via("www.example.com", QueryType::A, server, false, Some(&opt), &network).unwrap();
+        assert!(res.opt().is_some());
+        assert_eq!(network.infra().lock().unwrap().edns(server.ip()), Some(EdnsSupport::Small));
+
+        // Without an OPT record from the caller nothing is probed
+        network.asked.lock().unwrap().clear();
+        lookup_via("www.example.com", QueryType::A, SocketAddr::from(([192, 0, 2, 56], 53)), false, None, &network).unwrap();
+        assert_eq!(network.asked.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_follows_glueless_delegation() {
+        let network: &'static MockNetwork = Box::leak(Box::new(MockNetwork::new(|server, query| {
+            let name = query.questions[0].name.as_str();
+            match server {
+                server if root_servers().contains(&server) && name.ends_with(".net") => Some(referral_to("net", "ns.nic.net", Some([192, 0, 2, 30]))),
+                server if root_servers().contains(&server) => Some(referral_to("com", "ns.nic.com", Some([192, 0, 2, 10]))),
+                // example.com's nameserver lives in another TLD, the resolver has to look it up first
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 10] => Some(referral_to("example.com", "ns.example.net", None)),
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 30] => Some(answer(name, [192, 0, 2, 21])),
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 21] => Some(answer(name, [192, 0, 2, 80])),
+                _ => None,
+            }
+        })));
+
+        let res = recursive_lookup_via("www.example.com", QueryType::A, network).unwrap();
+        assert_eq!(res.answers, vec![DnsRecord::A { domain: "www.example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 80), ttl: 300, class: DnsClass::IN }]);
+        // root, com, root again for ns.example.net, net, and finally example.com
+        let asked = network.asked.lock().unwrap().clone();
+        assert_eq!(asked.len(), 5);
+        assert!(root_servers().contains(&asked[0]) && root_servers().contains(&asked[2]));
+        assert_eq!([asked[1], asked[3], asked[4]], [[192, 0, 2, 10], [192, 0, 2, 30], [192, 0, 2, 21]].map(IpAddr::from));
+
+        // The next name in the zone goes straight to its nameserver, whose address is known by now
+        network.asked.lock().unwrap().clear();
+        recursive_lookup_via("mail.example.com", QueryType::A, network).unwrap();
+        assert_eq!(*network.asked.lock().unwrap(), vec![IpAddr::from([192, 0, 2, 21])]);
+    }
+
+    #[test]
+    fn test_malicious_referrals() {
+        let network: &'static MockNetwork = Box::leak(Box::new(MockNetwork::new(|server, query| {
+            let name = query.questions[0].name.as_str();
+            let site = name.trim_end_matches(".com").rsplit('.').next().unwrap_or_default().to_string();
+            let zone = format!("{}.com", site);
+            match server {
+                server if root_servers().contains(&server) && name.ends_with(".net") => Some(referral_to("net", "ns.nic.net", Some([192, 0, 2, 30]))),
+                server if root_servers().contains(&server) => Some(referral_to("com", "ns.nic.com", Some([192, 0, 2, 10]))),
+                // Nothing under net exists, every glueless nameserver there is a dead end
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 30] => {
+                    let mut packet = DnsPacket::new();
+                    packet.header.authoritative_answer = true;
+                    packet.header.rescode = ResultCode::NXDOMAIN;
+                    Some(packet)
+                }
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 10] => Some(match site.as_str() {
+                    // Back up to the root, and to the zone the server is supposed to serve itself
+                    "upward" => referral_to("", "a.root-servers.net", Some([198, 41, 0, 4])),
+                    "same" => referral_to("com", "ns.nic.com", Some([192, 0, 2, 10])),
+                    // Sideways, to a zone the query name isn't in
+                    "sideways" => referral_to("org", "ns.nic.org", Some([192, 0, 2, 20])),
+                    // The right zone, and the parent's too to steal it
+                    "mixed" => {
+                        let mut packet = referral_to(&zone, &format!("ns.{}", zone), Some([192, 0, 2, 40]));
+                        packet.authorities.push(DnsRecord::NS { domain: "com".to_string(), ns: "ns.evil.net".to_string(), ttl: 3600, class: DnsClass::IN });
+                        packet
+                    }
+                    // Glue pointing at ourselves, or at everyone
+                    "loopback" => referral_to(&zone, &format!("ns.{}", zone), Some([127, 0, 0, 1])),
+                    "broadcast" => referral_to(&zone, &format!("ns.{}", zone), Some([255, 255, 255, 255])),
+                    // A nameserver inside its own zone without glue, looking it up would need the zone
+                    "loop" => referral_to(&zone, &format!("ns.{}", zone), None),
+                    // Every nameserver glueless in the next zone, which is just the same again
+                    chain if chain.starts_with("chain") => referral_to(&zone, &format!("ns.{}x.com", chain), None),
+                    // Many glueless nameservers nobody can find
+                    _ => {
+                        let mut packet = DnsPacket::new();
+                        packet.authorities = (0..10).map(|i| DnsRecord::NS { domain: zone.clone(), ns: format!("ns{}.{}.net", i, site), ttl: 3600, class: DnsClass::IN }).collect();
+                        packet
+                    }
+                }),
+                _ => None,
+            }
+        })));
+
+        let com = IpAddr::from([192, 0, 2, 10]);
+        for site in ["upward", "same", "sideways", "mixed", "loopback", "broadcast", "loop", "chain", "flood"] {
+            *network.infra().lock().unwrap() = InfraCache::default();
+            network.asked.lock().unwrap().clear();
+            let name = format!("www.{}.com", site);
+            assert!(recursive_lookup_via(&name, QueryType::A, network).is_err(), "{}", site);
+            let asked = network.asked.lock().unwrap().clone();
+            // Nobody was asked who wasn't in the referrals we trust, and not too often
+            assert!(asked.iter().all(|addr| root_servers().contains(addr) || [com, IpAddr::from([192, 0, 2, 30])].contains(addr)), "{}: {:?}", site, asked);
+            assert!(asked.len() <= 2 + MAX_GLUELESS * 4 * (MAX_GLUELESS_DEPTH + 1), "{}: {}", site, asked.len());
+            // Referrals that don't lead down make the server lame, the others are bad delegations of a good server
+            let lame = network.infra().lock().unwrap().lame.is_lame(com, "com");
+            assert_eq!(lame, ["upward", "same", "sideways", "mixed"].contains(&site), "{}", site);
+        }
+        // The glueless nameservers of the flood asked for both address types, no more of them than allowed
+        let flooded = network.asked.lock().unwrap().iter().filter(|addr| **addr == IpAddr::from([192, 0, 2, 30])).count();
+        assert_eq!(flooded, MAX_GLUELESS * 2);
+    }
+
+    #[test]
+    fn test_deadline_keeps_partial_answer() {
+        let network: &'static MockNetwork = Box::leak(Box::new(MockNetwork::new(|server, query| {
+            let name = query.questions[0].name.as_str();
+            match server {
+                server if root_servers().contains(&server) && name.ends_with(".net") => Some(referral_to("net", "ns.nic.net", Some([192, 0, 2, 30]))),
+                server if root_servers().contains(&server) => Some(referral_to("com", "ns.nic.com", Some([192, 0, 2, 10]))),
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 10] => {
+                    let mut packet = DnsPacket::new();
+                    packet.header.authoritative_answer = true;
+                    packet.answers.push(DnsRecord::cname("www.example.com", "www.example.net", 300));
+                    Some(packet)
+                }
+                // The alias's zone answers, but much too late
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 30] => {
+                    thread::sleep(Duration::from_secs(2));
+                    Some(answer(name, [192, 0, 2, 80]))
+                }
+                _ => None,
+            }
+        })));
+
+        let start = Instant::now();
+        let progress = Progress::default();
+        let err = recursive_lookup_until("www.example.com", QueryType::A, network, start + Duration::from_millis(300), &progress).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let partial = progress.into_inner().unwrap().unwrap();
+        assert_eq!(partial.answers, vec![DnsRecord::cname("www.example.com", "www.example.net", 300)]);
+
+        // Past the deadline nothing is asked at all
+        network.asked.lock().unwrap().clear();
+        let err = recursive_lookup_until("mail.example.com", QueryType::A, network, Instant::now(), &Progress::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(network.asked.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_synthesizes_and_follows_dname() {
+        let network: &'static MockNetwork = Box::leak(Box::new(MockNetwork::new(|server, query| {
+            let name = query.questions[0].name.as_str();
+            match server {
+                server if root_servers().contains(&server) && name.ends_with("example.org") => Some(referral_to("example.org", "ns.example.org", Some([192, 0, 2, 40]))),
+                server if root_servers().contains(&server) => Some(referral_to("example.net", "ns.example.net", Some([192, 0, 2, 50]))),
+                // The old server only sends the DNAME, without the CNAME it implies
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 40] => {
+                    let mut packet = DnsPacket::new();
+                    packet.header.authoritative_answer = true;
+                    packet.answers.push(DnsRecord::dname("example.org", "example.net", 3600));
+                    Some(packet)
+                }
+                IpAddr::V4(addr) if addr.octets() == [192, 0, 2, 50] => Some(answer(name, [192, 0, 2, 80])),
+                _ => None,
+            }
+        })));
+
+        let res = recursive_lookup_via("www.example.org", QueryType::A, network).unwrap();
+        assert_eq!(res.answers, vec![
+            DnsRecord::dname("example.org", "example.net", 3600),
+            DnsRecord::cname("www.example.org", "www.example.net", 3600),
+            DnsRecord::a("www.example.net", Ipv4Addr::new(192, 0, 2, 80), 300),
+        ]);
+
+        // A CNAME the server did send isn't added again
+        let mut packet = DnsPacket::new();
+        packet.answers = res.answers[..2].to_vec();
+        assert_eq!(synthesize_cnames("WWW.example.org", &mut packet), "www.example.net");
+        assert_eq!(packet.answers.len(), 2);
+    }
+}
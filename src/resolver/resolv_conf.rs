@@ -0,0 +1,70 @@
+use std::net::IpAddr;
+use std::path::Path;
+use std::{fs, io};
+
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/**
+The parts of resolv.conf(5) a forwarder cares about. `domain` is treated as a one entry
+search list and, as in glibc, whichever of `domain`/`search` comes last wins.
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<IpAddr>,
+    pub search: Vec<String>,
+}
+
+impl ResolvConf {
+    pub fn parse(contents: &str) -> ResolvConf {
+        let mut conf = ResolvConf::default();
+
+        for line in contents.lines() {
+            let line = line.split(['#', ';']).next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+
+            match fields.next() {
+                Some("nameserver") => {
+                    // Scoped addresses ("fe80::1%eth0") are not something we can use
+                    if let Some(Ok(addr)) = fields.next().map(str::parse::<IpAddr>) {
+                        conf.nameservers.push(addr);
+                    }
+                }
+                Some("search") | Some("domain") => {
+                    conf.search = fields.map(|domain| domain.trim_end_matches('.').to_ascii_lowercase()).collect();
+                }
+                _ => {}
+            }
+        }
+
+        conf
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<ResolvConf> {
+        Ok(ResolvConf::parse(&fs::read_to_string(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let conf = ResolvConf::parse("
+# Generated by NetworkManager
+domain lan
+search corp.example lan.
+nameserver 192.168.1.1
+nameserver  ::1 ; trailing comment
+nameserver fe80::1%eth0
+options edns0
+");
+        assert_eq!(conf.nameservers, vec!["192.168.1.1".parse::<IpAddr>().unwrap(), "::1".parse().unwrap()]);
+        assert_eq!(conf.search, vec!["corp.example".to_string(), "lan".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(ResolvConf::parse(""), ResolvConf::default());
+    }
+}
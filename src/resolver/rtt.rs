@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::utils::random::random;
+
+// Servers we have never talked to are assumed to be fairly quick so they get a chance
+pub const INITIAL_RTT: Duration = Duration::from_millis(100);
+// Roughly one query in EXPLORE_ONE_IN goes to a random server instead of the fastest one
+pub const EXPLORE_ONE_IN: u64 = 20;
+
+/**
+Smoothed round trip time per nameserver address, updated the same way TCP does it
+(srtt = 7/8 srtt + 1/8 sample). Used to pick the fastest server of a zone first.
+*/
+#[derive(Debug, Default)]
+pub struct RttTracker {
+    srtt: HashMap<IpAddr, Duration>,
+}
+
+impl RttTracker {
+    pub fn new() -> RttTracker {
+        RttTracker {
+            srtt: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, addr: IpAddr, sample: Duration) {
+        let srtt = match self.srtt.get(&addr) {
+            Some(srtt) => (*srtt * 7 + sample) / 8,
+            None => sample,
+        };
+        self.srtt.insert(addr, srtt);
+    }
+
+    pub fn get(&self, addr: IpAddr) -> Duration {
+        self.srtt.get(&addr).copied().unwrap_or(INITIAL_RTT)
+    }
+
+    // Fastest server first. Ties keep their original (referral) order.
+    pub fn sort(&self, servers: &mut [IpAddr]) {
+        servers.sort_by_key(|addr| self.get(*addr));
+    }
+
+    // Like `sort`, but every so often moves a random server to the front so that servers which
+    // were slow once get re-measured instead of being avoided forever.
+    pub fn order(&self, servers: &[IpAddr]) -> Vec<IpAddr> {
+        let mut ordered = servers.to_vec();
+        self.sort(&mut ordered);
+
+        if ordered.len() > 1 && random().is_multiple_of(EXPLORE_ONE_IN) {
+            let pick = (random() % ordered.len() as u64) as usize;
+            let server = ordered.remove(pick);
+            ordered.insert(0, server);
+        }
+        ordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_smooths_samples() {
+        let mut tracker = RttTracker::new();
+        let addr = IpAddr::from([192, 0, 2, 1]);
+
+        assert_eq!(tracker.get(addr), INITIAL_RTT);
+        tracker.record(addr, Duration::from_millis(80));
+        assert_eq!(tracker.get(addr), Duration::from_millis(80));
+        tracker.record(addr, Duration::from_millis(160));
+        assert_eq!(tracker.get(addr), Duration::from_millis(90));
+    }
+
+    #[test]
+    fn test_sort_prefers_fastest() {
+        let mut tracker = RttTracker::new();
+        let slow = IpAddr::from([192, 0, 2, 1]);
+        let fast = IpAddr::from([192, 0, 2, 2]);
+        let unknown = IpAddr::from([192, 0, 2, 3]);
+        tracker.record(slow, Duration::from_millis(400));
+        tracker.record(fast, Duration::from_millis(20));
+
+        let mut servers = vec![slow, unknown, fast];
+        tracker.sort(&mut servers);
+        assert_eq!(servers, vec![fast, unknown, slow]);
+    }
+
+    #[test]
+    fn test_order_keeps_every_server() {
+        let tracker = RttTracker::new();
+        let servers: Vec<IpAddr> = (1..=4).map(|i| IpAddr::from([192, 0, 2, i])).collect();
+
+        for _ in 0..100 {
+            let mut ordered = tracker.order(&servers);
+            ordered.sort();
+            assert_eq!(ordered, servers);
+        }
+    }
+}
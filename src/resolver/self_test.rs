@@ -0,0 +1,121 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::Instant;
+
+use log::info;
+use serde::Deserialize;
+
+use crate::resolver::forward::forwarder;
+use crate::resolver::resolve;
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SelfTestConfig {
+    pub enabled: bool,
+    pub name: String,         // the canary, a name that always resolves
+    pub qtype: String,
+    pub expect: Vec<IpAddr>,  // one of these has to be in the answer, empty for any
+    pub exit_on_failure: bool, // refuse to start rather than only logging the failure
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        SelfTestConfig {
+            enabled: false,
+            name: "example.com".to_string(),
+            qtype: "A".to_string(),
+            expect: Vec::new(),
+            exit_on_failure: true,
+        }
+    }
+}
+
+// Who the lookup went to, for the diagnostics
+fn upstreams() -> String {
+    match forwarder().get() {
+        Some(forwarder) => forwarder.servers.iter().map(|(ip, port)| format!("{}:{}", ip, port)).collect::<Vec<_>>().join(", "),
+        None => "the root servers".to_string(),
+    }
+}
+
+fn diagnose_error(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+            format!("no answer from {} in time, check the network, the firewall and the upstream addresses", upstreams())
+        }
+        io::ErrorKind::ConnectionRefused => format!("{} refused the connection, nothing is listening there", upstreams()),
+        _ => format!("the lookup through {} failed: {}", upstreams(), e),
+    }
+}
+
+// Whether `response` is a real answer to the canary, and what looks wrong if it isn't
+fn verify(config: &SelfTestConfig, qtype: QueryType, response: &DnsPacket) -> Result<(), String> {
+    let name = &config.name;
+    match response.header.rescode {
+        ResultCode::NOERROR => {}
+        ResultCode::SERVFAIL => return Err(format!("SERVFAIL for {}, the upstream can't resolve it either, check its connectivity and DNSSEC", name)),
+        ResultCode::REFUSED => return Err(format!("REFUSED for {}, the upstream doesn't serve this host, check its access rules", name)),
+        ResultCode::NXDOMAIN => return Err(format!("NXDOMAIN for {}, the canary is misspelled or filtered upstream", name)),
+        rcode => return Err(format!("{:?} for {}", rcode, name)),
+    }
+    let records: Vec<&DnsRecord> = response.answers.iter().filter(|record| record.query_type() == qtype).collect();
+    if records.is_empty() {
+        return Err(format!("no {} record for {} in the answer", qtype, name));
+    }
+    let addrs: Vec<IpAddr> = records.iter().filter_map(|record| match record {
+        DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+        DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+        _ => None,
+    }).collect();
+    if !config.expect.is_empty() && !addrs.iter().any(|addr| config.expect.contains(addr)) {
+        return Err(format!("{} resolved to {:?} instead of one of {:?}, something on the way may be rewriting answers", name, addrs, config.expect));
+    }
+    Ok(())
+}
+
+/**
+Resolves the canary through the configured pipeline, the upstreams in forward mode or the root
+servers otherwise, and checks that a real answer comes back. Meant for startup, so a broken
+upstream or network setup shows up in the log at once rather than in the clients' failures. The
+error is a diagnostic that says what went wrong and where to look.
+*/
+pub fn run(config: &SelfTestConfig) -> Result<(), String> {
+    let qtype = QueryType::from_name(&config.qtype).ok_or_else(|| format!("unknown self test type {}", config.qtype))?;
+    let start = Instant::now();
+    let response = resolve(&config.name, qtype).map_err(|e| diagnose_error(&e))?;
+    verify(config, qtype, &response)?;
+    info!("Self test passed, {} {} resolved through {} in {:?}", config.name, qtype, upstreams(), start.elapsed());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn answer(rescode: ResultCode, records: Vec<DnsRecord>) -> DnsPacket {
+        let mut packet = DnsPacket::builder().rcode(rescode).answers(records).build();
+        packet.header.response = true;
+        packet
+    }
+
+    #[test]
+    fn test_verify() {
+        let config = SelfTestConfig::default();
+        let addr = Ipv4Addr::new(192, 0, 2, 1);
+        let cname = DnsRecord::cname("example.com", "cdn.example.net", 300);
+        assert!(verify(&config, QueryType::A, &answer(ResultCode::NOERROR, vec![cname.clone(), DnsRecord::a("cdn.example.net", addr, 60)])).is_ok());
+
+        let error = verify(&config, QueryType::A, &answer(ResultCode::NOERROR, vec![cname])).unwrap_err();
+        assert_eq!(error, "no A record for example.com in the answer");
+        assert!(verify(&config, QueryType::A, &answer(ResultCode::SERVFAIL, vec![])).unwrap_err().starts_with("SERVFAIL"));
+
+        // A captive portal or hijacking resolver answers, but with the wrong address
+        let config = SelfTestConfig { expect: vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7))], ..SelfTestConfig::default() };
+        assert!(verify(&config, QueryType::A, &answer(ResultCode::NOERROR, vec![DnsRecord::a("example.com", addr, 60)])).unwrap_err().contains("rewriting"));
+    }
+}
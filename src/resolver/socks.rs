@@ -0,0 +1,266 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::resolver::recursive::exchange_tcp;
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+const NO_ACCEPTABLE_METHOD: u8 = 0xFF;
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Socks5Config {
+    pub enabled: bool,
+    pub proxy: String,
+    pub username: String, // empty for no authentication
+    pub password: String,
+    pub udp: bool, // UDP ASSOCIATE instead of DNS over TCP, Tor only does the latter
+}
+
+impl Default for Socks5Config {
+    fn default() -> Self {
+        Socks5Config {
+            enabled: false,
+            proxy: "127.0.0.1:9050".to_string(),
+            username: String::new(),
+            password: String::new(),
+            udp: false,
+        }
+    }
+}
+
+fn socks_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, msg)
+}
+
+fn encode_addr(out: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(ATYP_IPV4);
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(ATYP_IPV6);
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+fn read_addr(stream: &mut impl Read) -> io::Result<SocketAddr> {
+    let mut atyp = [0u8; 1];
+    stream.read_exact(&mut atyp)?;
+    let ip = match atyp[0] {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        ATYP_DOMAIN => {
+            // Only proxies bound to a name do this, there is nothing sensible to connect to
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            let mut name = vec![0u8; len[0] as usize + 2];
+            stream.read_exact(&mut name)?;
+            return Err(socks_error("SOCKS5 proxy answered with a domain name address".to_string()));
+        }
+        atyp => return Err(socks_error(format!("Unknown SOCKS5 address type {}", atyp))),
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port)?;
+    Ok(SocketAddr::new(ip, u16::from_be_bytes(port)))
+}
+
+// Every datagram to and from the UDP relay carries the real peer's address in front
+pub fn wrap_datagram(target: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0, 0, 0];
+    encode_addr(&mut out, target);
+    out.extend_from_slice(data);
+    out
+}
+
+pub fn unwrap_datagram(datagram: &[u8]) -> io::Result<(SocketAddr, &[u8])> {
+    if datagram.len() < 4 || datagram[2] != 0 {
+        return Err(socks_error("Fragmented or short SOCKS5 datagram".to_string()));
+    }
+    let mut rest = &datagram[3..];
+    let from = read_addr(&mut rest)?;
+    Ok((from, rest))
+}
+
+/**
+Sends upstream queries through a SOCKS5 proxy (RFC 1928), for networks where the resolver
+can't reach the internet directly or shouldn't be seen doing so. Queries go over TCP through a
+CONNECT by default, which works with Tor; `udp` switches to a UDP ASSOCIATE relay.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct SocksProxy {
+    pub proxy: SocketAddr,
+    pub auth: Option<(String, String)>,
+    pub udp: bool,
+    pub timeout: Duration,
+}
+
+impl SocksProxy {
+    pub fn from_config(config: &Socks5Config, timeout: Duration) -> io::Result<SocksProxy> {
+        let proxy = config.proxy.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid SOCKS5 proxy {:?}", config.proxy)))?;
+        let auth = (!config.username.is_empty()).then(|| (config.username.clone(), config.password.clone()));
+        Ok(SocksProxy { proxy, auth, udp: config.udp, timeout })
+    }
+
+    fn open(&self, command: u8, target: SocketAddr) -> io::Result<(TcpStream, SocketAddr)> {
+        let mut stream = TcpStream::connect_timeout(&self.proxy, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let method = if self.auth.is_some() { USER_PASS } else { NO_AUTH };
+        stream.write_all(&[SOCKS_VERSION, 1, method])?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != SOCKS_VERSION || reply[1] == NO_ACCEPTABLE_METHOD || reply[1] != method {
+            return Err(socks_error("SOCKS5 proxy rejected the authentication method".to_string()));
+        }
+
+        if let Some((username, password)) = &self.auth {
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request)?;
+            stream.read_exact(&mut reply)?;
+            if reply[1] != 0 {
+                return Err(socks_error("SOCKS5 authentication failed".to_string()));
+            }
+        }
+
+        let mut request = vec![SOCKS_VERSION, command, 0];
+        encode_addr(&mut request, target);
+        stream.write_all(&request)?;
+        let mut reply = [0u8; 3];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != SOCKS_VERSION || reply[1] != 0 {
+            return Err(socks_error(format!("SOCKS5 request failed with code {}", reply[1])));
+        }
+        let bound = read_addr(&mut stream)?;
+        Ok((stream, bound))
+    }
+
+    fn exchange_tcp(&self, query: &[u8], server: SocketAddr) -> io::Result<Vec<u8>> {
+        let (mut stream, _) = self.open(CMD_CONNECT, server)?;
+        exchange_tcp(&mut stream, query)
+    }
+
+    fn exchange_udp(&self, query: &[u8], server: SocketAddr) -> io::Result<Vec<u8>> {
+        // The association lives as long as this TCP connection
+        let (_control, mut relay) = self.open(CMD_UDP_ASSOCIATE, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))?;
+        if relay.ip().is_unspecified() {
+            relay.set_ip(self.proxy.ip());
+        }
+
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.send_to(&wrap_datagram(server, query), relay)?;
+
+        let mut buffer = [0u8; 4096];
+        loop {
+            let (len, _) = socket.recv_from(&mut buffer)?;
+            let (from, data) = unwrap_datagram(&buffer[..len])?;
+            if from == server {
+                return Ok(data.to_vec());
+            }
+        }
+    }
+
+    pub fn exchange(&self, query: &[u8], server: SocketAddr) -> io::Result<Vec<u8>> {
+        if self.udp {
+            self.exchange_udp(query, server)
+        } else {
+            self.exchange_tcp(query, server)
+        }
+    }
+
+    // For retrying truncated answers, even when queries normally go over UDP
+    pub fn exchange_over_tcp(&self, query: &[u8], server: SocketAddr) -> io::Result<Vec<u8>> {
+        self.exchange_tcp(query, server)
+    }
+}
+
+// Set once at startup when a proxy is configured, see `recursive::lookup`
+pub fn socks_proxy() -> &'static OnceLock<SocksProxy> {
+    static PROXY: OnceLock<SocksProxy> = OnceLock::new();
+    &PROXY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_datagram_round_trip() {
+        let server = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 53), 53));
+        let datagram = wrap_datagram(server, b"query");
+        assert_eq!(&datagram[..4], &[0, 0, 0, ATYP_IPV4]);
+
+        let (from, data) = unwrap_datagram(&datagram).unwrap();
+        assert_eq!(from, server);
+        let server: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+        assert_eq!(unwrap_datagram(&wrap_datagram(server, b"query")).unwrap(), (server, &b"query"[..]));
+        assert_eq!(data, b"query");
+        assert!(unwrap_datagram(&[0, 0, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn test_tcp_exchange_with_auth() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = SocksProxy {
+            proxy: listener.local_addr().unwrap(),
+            auth: Some(("user".to_string(), "secret".to_string())),
+            udp: false,
+            timeout: Duration::from_secs(5),
+        };
+
+        // A proxy that checks the handshake and plays the DNS server itself
+        let fake = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0u8; 64];
+            stream.read_exact(&mut buffer[..3]).unwrap();
+            assert_eq!(&buffer[..3], &[5, 1, USER_PASS]);
+            stream.write_all(&[5, USER_PASS]).unwrap();
+
+            stream.read_exact(&mut buffer[..13]).unwrap();
+            assert_eq!(&buffer[..13], b"\x01\x04user\x06secret");
+            stream.write_all(&[1, 0]).unwrap();
+
+            stream.read_exact(&mut buffer[..10]).unwrap();
+            assert_eq!(&buffer[..10], &[5, CMD_CONNECT, 0, ATYP_IPV4, 192, 0, 2, 53, 0, 53]);
+            stream.write_all(&[5, 0, 0, ATYP_IPV4, 127, 0, 0, 1, 0x10, 0x00]).unwrap();
+
+            stream.read_exact(&mut buffer[..7]).unwrap();
+            assert_eq!(&buffer[..7], b"\x00\x05query");
+            stream.write_all(b"\x00\x06answer").unwrap();
+        });
+
+        let response = proxy.exchange(b"query", SocketAddr::from((Ipv4Addr::new(192, 0, 2, 53), 53))).unwrap();
+        assert_eq!(response, b"answer");
+        fake.join().unwrap();
+    }
+}
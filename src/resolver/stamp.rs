@@ -0,0 +1,351 @@
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::str::FromStr;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+const PROTO_PLAIN: u8 = 0x00;
+const PROTO_DNSCRYPT: u8 = 0x01;
+const PROTO_DOH: u8 = 0x02;
+const PROTO_DOT: u8 = 0x03;
+const PROTO_DOQ: u8 = 0x04;
+const PROTO_ODOH_TARGET: u8 = 0x05;
+
+// Informational properties, a bit field in the stamp
+pub const PROP_DNSSEC: u64 = 1;
+pub const PROP_NO_LOGS: u64 = 2;
+pub const PROP_NO_FILTER: u64 = 4;
+
+/**
+A DNS stamp ("sdns://..."), the format public resolver lists publish server configurations
+in: the transport, its address, and whatever the transport needs to authenticate the server.
+Addresses keep the "ip:port" form of the stamp, the port is optional.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DnsStamp {
+    Plain {
+        props: u64,
+        addr: String,
+    },
+    DnsCrypt {
+        props: u64,
+        addr: String,
+        provider_pk: Vec<u8>,
+        provider_name: String,
+    },
+    DoH {
+        props: u64,
+        addr: String,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        path: String,
+        bootstrap: Vec<String>,
+    },
+    DoT {
+        props: u64,
+        addr: String,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        bootstrap: Vec<String>,
+    },
+    DoQ {
+        props: u64,
+        addr: String,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        bootstrap: Vec<String>,
+    },
+    ODoHTarget {
+        props: u64,
+        hostname: String,
+        path: String,
+    },
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or("Stamp is truncated")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn props(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // Length-prefixed bytes
+    fn lp(&mut self) -> Result<&'a [u8], String> {
+        let len = self.take(1)?[0] as usize;
+        self.take(len)
+    }
+
+    fn lp_string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.lp()?.to_vec()).map_err(|_| "Stamp contains invalid UTF-8".to_string())
+    }
+
+    // A set of length-prefixed items where the high bit of the length means "more follow"
+    fn vlp(&mut self) -> Result<Vec<&'a [u8]>, String> {
+        let mut items = Vec::new();
+        loop {
+            let len = self.take(1)?[0];
+            items.push(self.take((len & 0x7F) as usize)?);
+            if len & 0x80 == 0 {
+                return Ok(items);
+            }
+        }
+    }
+
+    fn optional_vlp_strings(&mut self) -> Result<Vec<String>, String> {
+        if self.pos == self.data.len() {
+            return Ok(Vec::new());
+        }
+        self.vlp()?.into_iter()
+            .map(|item| String::from_utf8(item.to_vec()).map_err(|_| "Stamp contains invalid UTF-8".to_string()))
+            .collect()
+    }
+
+    fn hashes(&mut self) -> Result<Vec<Vec<u8>>, String> {
+        Ok(self.vlp()?.into_iter().filter(|hash| !hash.is_empty()).map(|hash| hash.to_vec()).collect())
+    }
+}
+
+fn write_lp(out: &mut Vec<u8>, data: &[u8]) {
+    out.push(data.len() as u8);
+    out.extend_from_slice(data);
+}
+
+fn write_vlp<T: AsRef<[u8]>>(out: &mut Vec<u8>, items: &[T]) {
+    if items.is_empty() {
+        out.push(0);
+    }
+    for (i, item) in items.iter().enumerate() {
+        let more = if i + 1 < items.len() { 0x80 } else { 0 };
+        out.push(item.as_ref().len() as u8 | more);
+        out.extend_from_slice(item.as_ref());
+    }
+}
+
+impl DnsStamp {
+    pub fn props(&self) -> u64 {
+        match self {
+            DnsStamp::Plain { props, .. }
+            | DnsStamp::DnsCrypt { props, .. }
+            | DnsStamp::DoH { props, .. }
+            | DnsStamp::DoT { props, .. }
+            | DnsStamp::DoQ { props, .. }
+            | DnsStamp::ODoHTarget { props, .. } => *props,
+        }
+    }
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            DnsStamp::Plain { .. } => 53,
+            DnsStamp::DoT { .. } | DnsStamp::DoQ { .. } => 853,
+            _ => 443,
+        }
+    }
+
+    pub fn transport(&self) -> &'static str {
+        match self {
+            DnsStamp::Plain { .. } => "Do53",
+            DnsStamp::DnsCrypt { .. } => "DNSCrypt",
+            DnsStamp::DoH { .. } => "DoH",
+            DnsStamp::DoT { .. } => "DoT",
+            DnsStamp::DoQ { .. } => "DoQ",
+            DnsStamp::ODoHTarget { .. } => "ODoH",
+        }
+    }
+
+    // The address to forward to, the forwarder speaks plain DNS and DNSCrypt but no other transport
+    pub fn server(&self) -> Result<(Ipv4Addr, u16), String> {
+        let (DnsStamp::Plain { addr, .. } | DnsStamp::DnsCrypt { addr, .. }) = self else {
+            return Err(format!("{} upstreams are not supported, only plain DNS and DNSCrypt stamps are", self.transport()));
+        };
+        if let Ok(server) = addr.parse::<SocketAddrV4>() {
+            return Ok((*server.ip(), server.port()));
+        }
+        match addr.parse::<Ipv4Addr>() {
+            Ok(ip) => Ok((ip, self.default_port())),
+            Err(_) => Err(format!("Stamp address {:?} is not an IPv4 address", addr)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            DnsStamp::Plain { props, addr } => {
+                out.push(PROTO_PLAIN);
+                out.extend_from_slice(&props.to_le_bytes());
+                write_lp(&mut out, addr.as_bytes());
+            }
+            DnsStamp::DnsCrypt { props, addr, provider_pk, provider_name } => {
+                out.push(PROTO_DNSCRYPT);
+                out.extend_from_slice(&props.to_le_bytes());
+                write_lp(&mut out, addr.as_bytes());
+                write_lp(&mut out, provider_pk);
+                write_lp(&mut out, provider_name.as_bytes());
+            }
+            DnsStamp::DoH { props, addr, hashes, hostname, path, bootstrap } => {
+                out.push(PROTO_DOH);
+                out.extend_from_slice(&props.to_le_bytes());
+                write_lp(&mut out, addr.as_bytes());
+                write_vlp(&mut out, hashes);
+                write_lp(&mut out, hostname.as_bytes());
+                write_lp(&mut out, path.as_bytes());
+                if !bootstrap.is_empty() {
+                    write_vlp(&mut out, bootstrap);
+                }
+            }
+            DnsStamp::DoT { props, addr, hashes, hostname, bootstrap } | DnsStamp::DoQ { props, addr, hashes, hostname, bootstrap } => {
+                out.push(if matches!(self, DnsStamp::DoT { .. }) { PROTO_DOT } else { PROTO_DOQ });
+                out.extend_from_slice(&props.to_le_bytes());
+                write_lp(&mut out, addr.as_bytes());
+                write_vlp(&mut out, hashes);
+                write_lp(&mut out, hostname.as_bytes());
+                if !bootstrap.is_empty() {
+                    write_vlp(&mut out, bootstrap);
+                }
+            }
+            DnsStamp::ODoHTarget { props, hostname, path } => {
+                out.push(PROTO_ODOH_TARGET);
+                out.extend_from_slice(&props.to_le_bytes());
+                write_lp(&mut out, hostname.as_bytes());
+                write_lp(&mut out, path.as_bytes());
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<DnsStamp, String> {
+        let mut reader = Reader { data, pos: 1 };
+        let stamp = match data.first() {
+            Some(&PROTO_PLAIN) => DnsStamp::Plain {
+                props: reader.props()?,
+                addr: reader.lp_string()?,
+            },
+            Some(&PROTO_DNSCRYPT) => DnsStamp::DnsCrypt {
+                props: reader.props()?,
+                addr: reader.lp_string()?,
+                provider_pk: reader.lp()?.to_vec(),
+                provider_name: reader.lp_string()?,
+            },
+            Some(&PROTO_DOH) => DnsStamp::DoH {
+                props: reader.props()?,
+                addr: reader.lp_string()?,
+                hashes: reader.hashes()?,
+                hostname: reader.lp_string()?,
+                path: reader.lp_string()?,
+                bootstrap: reader.optional_vlp_strings()?,
+            },
+            Some(&proto @ (PROTO_DOT | PROTO_DOQ)) => {
+                let (props, addr, hashes, hostname) = (reader.props()?, reader.lp_string()?, reader.hashes()?, reader.lp_string()?);
+                let bootstrap = reader.optional_vlp_strings()?;
+                if proto == PROTO_DOT {
+                    DnsStamp::DoT { props, addr, hashes, hostname, bootstrap }
+                } else {
+                    DnsStamp::DoQ { props, addr, hashes, hostname, bootstrap }
+                }
+            }
+            Some(&PROTO_ODOH_TARGET) => DnsStamp::ODoHTarget {
+                props: reader.props()?,
+                hostname: reader.lp_string()?,
+                path: reader.lp_string()?,
+            },
+            Some(proto) => return Err(format!("Unsupported stamp protocol 0x{:02x}", proto)),
+            None => return Err("Empty stamp".to_string()),
+        };
+        Ok(stamp)
+    }
+}
+
+impl FromStr for DnsStamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DnsStamp, String> {
+        let encoded = s.trim().strip_prefix("sdns://").ok_or("A DNS stamp starts with sdns://")?;
+        let data = URL_SAFE_NO_PAD.decode(encoded.trim_end_matches('=')).map_err(|e| format!("Invalid stamp encoding: {}", e))?;
+        DnsStamp::from_bytes(&data)
+    }
+}
+
+impl fmt::Display for DnsStamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sdns://{}", URL_SAFE_NO_PAD.encode(self.to_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_stamp() {
+        // Quad9 from the public-resolvers list
+        let stamp: DnsStamp = "sdns://AAYAAAAAAAAABzkuOS45Ljk".parse().unwrap();
+        assert_eq!(stamp, DnsStamp::Plain { props: 6, addr: "9.9.9.9".to_string() });
+        assert_eq!(stamp.to_string(), "sdns://AAYAAAAAAAAABzkuOS45Ljk");
+        assert_eq!(stamp.props() & PROP_NO_LOGS, PROP_NO_LOGS);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let stamps = [
+            DnsStamp::DnsCrypt {
+                props: PROP_DNSSEC,
+                addr: "192.0.2.1:8443".to_string(),
+                provider_pk: vec![7; 32],
+                provider_name: "2.dnscrypt-cert.example.com".to_string(),
+            },
+            DnsStamp::DoH {
+                props: PROP_DNSSEC | PROP_NO_FILTER,
+                addr: "192.0.2.2".to_string(),
+                hashes: vec![vec![1; 32], vec![2; 32]],
+                hostname: "doh.example.com".to_string(),
+                path: "/dns-query".to_string(),
+                bootstrap: vec!["192.0.2.53".to_string()],
+            },
+            DnsStamp::DoT {
+                props: 0,
+                addr: "[2001:db8::1]".to_string(),
+                hashes: Vec::new(),
+                hostname: "dot.example.com".to_string(),
+                bootstrap: Vec::new(),
+            },
+            DnsStamp::ODoHTarget { props: 0, hostname: "odoh.example.com".to_string(), path: "/dns-query".to_string() },
+        ];
+        for stamp in stamps {
+            assert_eq!(stamp.to_string().parse::<DnsStamp>(), Ok(stamp));
+        }
+    }
+
+    #[test]
+    fn test_server() {
+        let stamp = DnsStamp::Plain { props: 0, addr: "192.0.2.1:5353".to_string() };
+        assert_eq!(stamp.server(), Ok((Ipv4Addr::new(192, 0, 2, 1), 5353)));
+        let stamp = DnsStamp::DnsCrypt {
+            props: 0,
+            addr: "192.0.2.1".to_string(),
+            provider_pk: vec![7; 32],
+            provider_name: "2.dnscrypt-cert.example.com".to_string(),
+        };
+        assert_eq!(stamp.server(), Ok((Ipv4Addr::new(192, 0, 2, 1), 443)));
+
+        let stamp = DnsStamp::ODoHTarget { props: 0, hostname: "odoh.example.com".to_string(), path: "/dns-query".to_string() };
+        assert!(stamp.server().unwrap_err().contains("ODoH"));
+        assert!(DnsStamp::Plain { props: 0, addr: "[2001:db8::1]".to_string() }.server().is_err());
+    }
+
+    #[test]
+    fn test_invalid_stamps() {
+        assert!("https://dns.example".parse::<DnsStamp>().is_err());
+        assert!("sdns://!!!".parse::<DnsStamp>().is_err());
+        assert!("sdns://AAYAAAAAAAAABzkuOS45".parse::<DnsStamp>().is_err());
+        assert!("sdns://gQ".parse::<DnsStamp>().is_err());
+    }
+}
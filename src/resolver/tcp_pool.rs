@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::logging::telemetry::{self, SpanKind};
+use crate::resolver::cookies::client_cookies;
+use crate::resolver::recursive::{build_query, parse_response, read_message, write_message, QUERY_TIMEOUT};
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::random::random;
+use crate::utils::record::DnsRecord;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+type Pending = Arc<Mutex<HashMap<u16, Sender<Vec<u8>>>>>;
+
+// One long-lived connection, any number of queries can be outstanding on it at once
+struct Connection {
+    writer: Mutex<TcpStream>,
+    pending: Pending,
+    alive: Arc<AtomicBool>,
+}
+
+impl Connection {
+    fn open(server: SocketAddr) -> io::Result<Connection> {
+        let stream = TcpStream::connect_timeout(&server, QUERY_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let alive = Arc::new(AtomicBool::new(true));
+        let mut reader = stream.try_clone()?;
+        let (reader_pending, reader_alive) = (Arc::clone(&pending), Arc::clone(&alive));
+
+        // Answers can come back in any order, the reader hands each one to whoever sent its ID
+        thread::spawn(move || {
+            while let Ok(response) = read_message(&mut reader) {
+                let id = match response.get(0..2) {
+                    Some(id) => u16::from_be_bytes([id[0], id[1]]),
+                    None => continue,
+                };
+                if let Some(sender) = reader_pending.lock().unwrap().remove(&id) {
+                    let _ = sender.send(response);
+                }
+            }
+            // Dropping the senders wakes every query still waiting on this connection
+            reader_alive.store(false, Ordering::SeqCst);
+            reader_pending.lock().unwrap().clear();
+        });
+
+        Ok(Connection { writer: Mutex::new(stream), pending, alive })
+    }
+
+    fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    fn close(&self) {
+        self.alive.store(false, Ordering::SeqCst);
+        let _ = self.writer.lock().unwrap().shutdown(Shutdown::Both);
+    }
+
+    fn exchange(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        if query.len() < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Query too short"));
+        }
+        let (sender, receiver) = mpsc::channel();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            let id = std::iter::repeat_with(|| random() as u16).find(|id| !pending.contains_key(id)).unwrap();
+            pending.insert(id, sender);
+            id
+        };
+
+        let mut message = query.to_vec();
+        message[0..2].copy_from_slice(&id.to_be_bytes());
+        if let Err(e) = write_message(&mut *self.writer.lock().unwrap(), &message) {
+            self.close();
+            return Err(e);
+        }
+
+        match receiver.recv_timeout(QUERY_TIMEOUT) {
+            Ok(mut response) => {
+                response[0..2].copy_from_slice(&query[0..2]);
+                Ok(response)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out waiting for a pipelined answer"))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "Upstream closed the connection")),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Backoff {
+    failures: u32,
+    retry_at: Instant,
+}
+
+/**
+A few persistent TCP connections to one upstream, used in turn. Queries are pipelined: each
+gets a fresh ID on the wire and waits for the answer with that ID, so a slow answer doesn't hold
+up the ones behind it. Dead connections are reopened on the next query, backing off
+exponentially while the upstream keeps refusing them.
+*/
+pub struct TcpPool {
+    server: SocketAddr,
+    slots: Vec<Mutex<Option<Arc<Connection>>>>,
+    next: AtomicUsize,
+    backoff: Mutex<Backoff>,
+}
+
+impl TcpPool {
+    pub fn new(server: SocketAddr, size: usize) -> TcpPool {
+        TcpPool {
+            server,
+            slots: (0..size.max(1)).map(|_| Mutex::new(None)).collect(),
+            next: AtomicUsize::new(0),
+            backoff: Mutex::new(Backoff { failures: 0, retry_at: Instant::now() }),
+        }
+    }
+
+    fn connection(&self) -> io::Result<Arc<Connection>> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        let mut slot = self.slots[index].lock().unwrap();
+        if let Some(connection) = slot.as_ref().filter(|connection| connection.is_alive()) {
+            return Ok(Arc::clone(connection));
+        }
+
+        let mut backoff = self.backoff.lock().unwrap();
+        let now = Instant::now();
+        if now < backoff.retry_at {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("Waiting {:?} before reconnecting to {}", backoff.retry_at - now, self.server)));
+        }
+        match Connection::open(self.server) {
+            Ok(connection) => {
+                if backoff.failures > 0 {
+                    info!("Reconnected to upstream {} after {} failures", self.server, backoff.failures);
+                }
+                backoff.failures = 0;
+                let connection = Arc::new(connection);
+                *slot = Some(Arc::clone(&connection));
+                Ok(connection)
+            }
+            Err(e) => {
+                let delay = MIN_BACKOFF.saturating_mul(1 << backoff.failures.min(16)).min(MAX_BACKOFF);
+                backoff.failures += 1;
+                backoff.retry_at = now + delay;
+                warn!("Failed to connect to upstream {} ({}), retrying in {:?}", self.server, e, delay);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn exchange(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        self.connection()?.exchange(query)
+    }
+}
+
+// A pool per upstream, opened on first use
+pub struct TcpPools {
+    size: usize,
+    pools: Mutex<HashMap<(Ipv4Addr, u16), Arc<TcpPool>>>,
+}
+
+impl TcpPools {
+    pub fn new(size: usize) -> TcpPools {
+        TcpPools { size, pools: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn pool(&self, server: (Ipv4Addr, u16)) -> Arc<TcpPool> {
+        let mut pools = self.pools.lock().unwrap();
+        Arc::clone(pools.entry(server).or_insert_with(|| Arc::new(TcpPool::new(SocketAddr::from(server), self.size))))
+    }
+
+    pub fn lookup(&self, qname: &str, qtype: QueryType, server: (Ipv4Addr, u16), checking_disabled: bool, edns: Option<&DnsRecord>) -> io::Result<DnsPacket> {
+        let mut span = telemetry::span("upstream.send", SpanKind::Client);
+        span.attr("server.address", server.0);
+        span.attr("server.port", server.1);
+        span.attr("network.transport", "tcp");
+        let exchange = |edns: Option<&DnsRecord>| {
+            let query = build_query(qname, qtype, checking_disabled, edns)?;
+            parse_response(&self.pool(server).exchange(&query)?, server.into())
+        };
+        match client_cookies().get() {
+            Some(cookies) => cookies.exchange(server.into(), edns, exchange),
+            None => exchange(edns),
+        }
+    }
+}
+
+// Set once at startup when forwarding over TCP, see `Forwarder::lookup`
+pub fn upstream_connections() -> &'static OnceLock<TcpPools> {
+    static CONNECTIONS: OnceLock<TcpPools> = OnceLock::new();
+    &CONNECTIONS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_pipelined_answers_out_of_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        let upstream = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let first = read_message(&mut stream).unwrap();
+            let second = read_message(&mut stream).unwrap();
+            write_message(&mut stream, &second).unwrap();
+            write_message(&mut stream, &first).unwrap();
+            listener
+        });
+
+        let pool = Arc::new(TcpPool::new(server, 1));
+        let queries: Vec<_> = [b"\x00\x01first".to_vec(), b"\x00\x02second".to_vec()].into_iter().map(|query| {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || (pool.exchange(&query).unwrap(), query))
+        }).collect();
+        for query in queries {
+            let (response, query) = query.join().unwrap();
+            assert_eq!(response, query);
+        }
+        drop(upstream.join().unwrap());
+    }
+
+    #[test]
+    fn test_reconnect_with_backoff() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        let pool = TcpPool::new(server, 1);
+
+        let upstream = thread::spawn(move || {
+            // Answers one query per connection, then hangs up
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let query = read_message(&mut stream).unwrap();
+                write_message(&mut stream, &query).unwrap();
+            }
+        });
+        assert_eq!(pool.exchange(b"\x00\x01one").unwrap(), b"\x00\x01one");
+        // The reader notices the closed connection shortly after
+        while pool.slots[0].lock().unwrap().as_ref().unwrap().is_alive() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(pool.exchange(b"\x00\x02two").unwrap(), b"\x00\x02two");
+        upstream.join().unwrap();
+
+        // Nothing listens any more, the second attempt doesn't even try to connect
+        while pool.slots[0].lock().unwrap().as_ref().unwrap().is_alive() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(pool.exchange(b"\x00\x03").unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+        let err = pool.exchange(b"\x00\x03").unwrap_err();
+        assert!(err.to_string().starts_with("Waiting"));
+    }
+}
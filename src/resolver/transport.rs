@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::resolver::infra::{infra_cache, InfraCache};
+use crate::resolver::recursive::{read_message, write_message, QUERY_TIMEOUT, UPSTREAM_PAYLOAD_SIZE};
+use crate::resolver::socks::{socks_proxy, SocksProxy};
+#[cfg(test)]
+use crate::utils::packet::DnsPacket;
+#[cfg(test)]
+use crate::utils::wire::{write_large_message, write_udp_message};
+
+// How long a server that only answered over TCP is asked over TCP straight away
+pub const TCP_ONLY_TTL: Duration = Duration::from_secs(15 * 60);
+
+/**
+Nameservers UDP doesn't get through to, because a firewall on either side drops it, that did
+answer over TCP. Queries to them skip the UDP timeout until the mark expires and UDP gets
+another try.
+*/
+#[derive(Debug, Default)]
+pub struct TcpOnly {
+    until: HashMap<IpAddr, Instant>,
+}
+
+impl TcpOnly {
+    pub fn mark(&mut self, addr: IpAddr) {
+        self.until.insert(addr, Instant::now() + TCP_ONLY_TTL);
+    }
+
+    pub fn contains(&mut self, addr: IpAddr) -> bool {
+        match self.until.get(&addr) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                self.until.remove(&addr);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+// One exchange with a nameserver: the query goes out with `send_query`, the answer to it comes back from `recv_response`
+pub trait Transport {
+    fn send_query(&mut self, query: &[u8]) -> io::Result<()>;
+    fn recv_response(&mut self) -> io::Result<Vec<u8>>;
+}
+
+/**
+Opens transports to nameservers. `lookup` asks for UDP first and for TCP when the answer was
+truncated or never came. `Network` is the real thing (through the SOCKS5 proxy when one is set), a new transport
+or a test plugs in here without the resolver knowing. What recursion learns about the servers
+behind a connector is kept in its `infra` cache.
+*/
+pub trait Connector: Sync {
+    fn udp(&self, server: SocketAddr) -> io::Result<Box<dyn Transport + '_>>;
+    fn tcp(&self, server: SocketAddr) -> io::Result<Box<dyn Transport + '_>>;
+    fn infra(&self) -> &Mutex<InfraCache>;
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    server: SocketAddr,
+}
+
+impl UdpTransport {
+    pub fn open(server: SocketAddr) -> io::Result<UdpTransport> {
+        // Let the OS pick a random source port, several lookups can be in flight at once
+        let local: IpAddr = if server.is_ipv4() { Ipv4Addr::UNSPECIFIED.into() } else { Ipv6Addr::UNSPECIFIED.into() };
+        let socket = UdpSocket::bind((local, 0))?;
+        socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+        Ok(UdpTransport { socket, server })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_query(&mut self, query: &[u8]) -> io::Result<()> {
+        self.socket.send_to(query, self.server)?;
+        Ok(())
+    }
+
+    fn recv_response(&mut self) -> io::Result<Vec<u8>> {
+        let mut buffer = [0u8; UPSTREAM_PAYLOAD_SIZE as usize];
+        let (len, _) = self.socket.recv_from(&mut buffer)?;
+        Ok(buffer[..len].to_vec())
+    }
+}
+
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn connect(server: SocketAddr) -> io::Result<TcpTransport> {
+        let stream = TcpStream::connect_timeout(&server, QUERY_TIMEOUT)?;
+        stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+        stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+        Ok(TcpTransport { stream })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_query(&mut self, query: &[u8]) -> io::Result<()> {
+        write_message(&mut self.stream, query)
+    }
+
+    fn recv_response(&mut self) -> io::Result<Vec<u8>> {
+        read_message(&mut self.stream)
+    }
+}
+
+// The proxy does the whole exchange in one go, so the query waits until the response is asked for
+struct Proxied {
+    proxy: &'static SocksProxy,
+    server: SocketAddr,
+    tcp: bool,
+    query: Vec<u8>,
+}
+
+impl Transport for Proxied {
+    fn send_query(&mut self, query: &[u8]) -> io::Result<()> {
+        self.query = query.to_vec();
+        Ok(())
+    }
+
+    fn recv_response(&mut self) -> io::Result<Vec<u8>> {
+        match self.tcp {
+            true => self.proxy.exchange_over_tcp(&self.query, self.server),
+            false => self.proxy.exchange(&self.query, self.server),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Network;
+
+impl Connector for Network {
+    fn udp(&self, server: SocketAddr) -> io::Result<Box<dyn Transport + '_>> {
+        match socks_proxy().get() {
+            Some(proxy) => Ok(Box::new(Proxied { proxy, server, tcp: false, query: Vec::new() })),
+            None => Ok(Box::new(UdpTransport::open(server)?)),
+        }
+    }
+
+    fn tcp(&self, server: SocketAddr) -> io::Result<Box<dyn Transport + '_>> {
+        match socks_proxy().get() {
+            Some(proxy) => Ok(Box::new(Proxied { proxy, server, tcp: true, query: Vec::new() })),
+            None => Ok(Box::new(TcpTransport::connect(server)?)),
+        }
+    }
+
+    fn infra(&self) -> &Mutex<InfraCache> {
+        infra_cache()
+    }
+}
+
+// Nameservers made of a function from (server, query) to response, `None` where nobody answers
+#[cfg(test)]
+type Answer = dyn Fn(IpAddr, &DnsPacket) -> Option<DnsPacket> + Sync;
+
+#[cfg(test)]
+pub struct MockNetwork {
+    answer: Box<Answer>,
+    udp: bool,
+    pub asked: Mutex<Vec<IpAddr>>,
+    infra: Mutex<InfraCache>,
+}
+
+#[cfg(test)]
+impl MockNetwork {
+    pub fn new(answer: impl Fn(IpAddr, &DnsPacket) -> Option<DnsPacket> + Sync + 'static) -> MockNetwork {
+        MockNetwork { answer: Box::new(answer), udp: true, asked: Mutex::new(Vec::new()), infra: Mutex::new(InfraCache::default()) }
+    }
+
+    // A network that drops every UDP query, only TCP gets answers
+    pub fn without_udp(answer: impl Fn(IpAddr, &DnsPacket) -> Option<DnsPacket> + Sync + 'static) -> MockNetwork {
+        MockNetwork { udp: false, ..MockNetwork::new(answer) }
+    }
+
+    fn open(&self, server: SocketAddr, tcp: bool, dropped: bool) -> io::Result<Box<dyn Transport + '_>> {
+        Ok(Box::new(MockTransport { network: self, server: server.ip(), tcp, dropped, response: None }))
+    }
+}
+
+#[cfg(test)]
+impl Connector for MockNetwork {
+    fn udp(&self, server: SocketAddr) -> io::Result<Box<dyn Transport + '_>> {
+        self.open(server, false, !self.udp)
+    }
+
+    fn tcp(&self, server: SocketAddr) -> io::Result<Box<dyn Transport + '_>> {
+        self.open(server, true, false)
+    }
+
+    fn infra(&self) -> &Mutex<InfraCache> {
+        &self.infra
+    }
+}
+
+#[cfg(test)]
+struct MockTransport<'a> {
+    network: &'a MockNetwork,
+    server: IpAddr,
+    tcp: bool,
+    dropped: bool,
+    response: Option<DnsPacket>,
+}
+
+#[cfg(test)]
+impl Transport for MockTransport<'_> {
+    fn send_query(&mut self, query: &[u8]) -> io::Result<()> {
+        if self.dropped {
+            return Ok(());
+        }
+        let query = DnsPacket::from_bytes(query)?;
+        self.network.asked.lock().unwrap().push(self.server);
+        self.response = (self.network.answer)(self.server, &query).map(|mut response| {
+            response.header.id = query.header.id;
+            response.header.response = true;
+            response.questions = query.questions.clone();
+            response
+        });
+        Ok(())
+    }
+
+    fn recv_response(&mut self) -> io::Result<Vec<u8>> {
+        // Answers too large for a datagram come truncated, as a real server sends them
+        let response = self.response.take().ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))?;
+        if self.tcp { write_large_message(&response) } else { write_udp_message(&response) }
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::cache::dump::{self, parse_record};
+use crate::local::dnssd::{DnsSd, Service};
+use crate::local::zone::Zones;
+use crate::server::handler::QueryHandler;
+use crate::server::http::{HttpRequest, HttpResponse};
+use crate::utils::query_type::QueryType;
+use crate::utils::record::DnsRecord;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/*
+Zone management, only served when `admin_token` is set and requests carry it as
+`Authorization: Bearer <token>`. Records are sent in zone file format, one per line:
+
+GET    /zones                                      zones and their serials
+GET    /zones/example.lan                          the zone file
+POST   /zones/example.lan/records                   add the records in the body
+PUT    /zones/example.lan/records?name=www.example.lan&type=A      replace that record set with the body
+DELETE /zones/example.lan/records?name=www.example.lan[&type=A]    delete records
+
+The cache in `cache export` format, for a standby to copy (see `standby`). The first line is
+`;; at <time>`, the primary's clock to pass as `since` next time for only what changed since:
+
+GET    /cache[?since=<unix time>]                  the entries stored or refreshed since then
+
+The DNS-SD services advertised over mDNS, as JSON like the `[[dnssd.services]]` config:
+
+GET    /services                                   the advertised services
+POST   /services                                   advertise the service in the body
+DELETE /services?type=_ipp._tcp&name=Office%20Printer   withdraw a service
+*/
+
+fn text(status: u16, body: &str) -> HttpResponse {
+    HttpResponse::new(status, "text/plain", format!("{}\n", body).into_bytes())
+}
+
+fn parse_records(body: &[u8]) -> Result<Vec<DnsRecord>, String> {
+    let body = std::str::from_utf8(body).map_err(|_| "Records must be UTF-8".to_string())?;
+    body.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .map(|line| parse_record(line).ok_or_else(|| format!("Unsupported or malformed record: {}", line)))
+        .collect()
+}
+
+fn record_set(request: &HttpRequest) -> Result<(String, Option<QueryType>), String> {
+    let name = request.param("name").filter(|name| !name.is_empty()).ok_or("Missing name parameter")?;
+    let qtype = match request.param("type") {
+        Some(qtype) => Some(QueryType::from_name(qtype).ok_or("Invalid type parameter")?),
+        None => None,
+    };
+    Ok((name.trim_end_matches('.').to_ascii_lowercase(), qtype))
+}
+
+fn edit(zones: &Zones, request: &HttpRequest, zone: &str) -> HttpResponse {
+    let result = match request.method.as_str() {
+        "POST" => parse_records(&request.body).map(|records| zones.edit(zone, |zone| zone.add(records).map(|_| 0))),
+        "PUT" => record_set(request).and_then(|(name, qtype)| {
+            let qtype = qtype.ok_or("Missing type parameter")?;
+            let records = parse_records(&request.body)?;
+            Ok(zones.edit(zone, |zone| zone.replace(&name, qtype, records).map(|_| 0)))
+        }),
+        "DELETE" => record_set(request).map(|(name, qtype)| zones.edit(zone, |zone| zone.delete(&name, qtype))),
+        _ => return text(405, "Method not allowed"),
+    };
+    match result {
+        Err(e) => text(400, &e),
+        Ok(None) => text(404, "No such zone"),
+        Ok(Some(Err(e))) if e.kind() == io::ErrorKind::InvalidInput => text(400, &e.to_string()),
+        Ok(Some(Err(e))) => text(500, &format!("Failed to save the zone: {}", e)),
+        Ok(Some(Ok((deleted, serial)))) => {
+            let body = json!({"zone": zone, "serial": serial, "deleted": deleted});
+            HttpResponse::new(200, "application/json", body.to_string().into_bytes())
+        }
+    }
+}
+
+fn services(dnssd: &DnsSd, request: &HttpRequest) -> HttpResponse {
+    match request.method.as_str() {
+        "GET" => HttpResponse::new(200, "application/json", serde_json::to_vec(&dnssd.services()).unwrap()),
+        "POST" => {
+            let service: Service = match serde_json::from_slice(&request.body) {
+                Ok(service) => service,
+                Err(e) => return text(400, &format!("Invalid service: {}", e)),
+            };
+            let instance = service.instance();
+            match dnssd.register(service) {
+                Ok(()) => HttpResponse::new(200, "application/json", json!({"instance": instance}).to_string().into_bytes()),
+                Err(e) => text(400, &e),
+            }
+        }
+        "DELETE" => match (request.param("name"), request.param("type")) {
+            (Some(name), Some(service_type)) => match dnssd.unregister(name, service_type) {
+                Some(service) => HttpResponse::new(200, "application/json", json!({"instance": service.instance()}).to_string().into_bytes()),
+                None => text(404, "No such service"),
+            },
+            _ => text(400, "Missing name or type parameter"),
+        },
+        _ => text(405, "Method not allowed"),
+    }
+}
+
+fn cache(handler: &QueryHandler, request: &HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return text(405, "Method not allowed");
+    }
+    let Ok(since) = request.param("since").unwrap_or("0").parse::<u64>() else {
+        return text(400, "Invalid since parameter");
+    };
+    let snapshot = handler.cache.backend.snapshot();
+    let mut out = format!("{}{}\n", dump::TIME_PREFIX, snapshot.clock().now()).into_bytes();
+    match dump::export_since(&snapshot, since, &mut out) {
+        Ok(_) => HttpResponse::new(200, "text/plain", out),
+        Err(e) => text(500, &format!("Failed to export the cache: {}", e)),
+    }
+}
+
+pub fn handle(handler: &QueryHandler, token: &str, request: &HttpRequest) -> HttpResponse {
+    if token.is_empty() {
+        return text(404, "Not found");
+    }
+    if request.header("authorization").and_then(|value| value.strip_prefix("Bearer ")) != Some(token) {
+        return text(401, "Unauthorized");
+    }
+    if request.path.trim_matches('/') == "cache" {
+        return cache(handler, request);
+    }
+    if request.path.trim_matches('/') == "services" {
+        return match &handler.dnssd {
+            Some(dnssd) => services(dnssd, request),
+            None => text(404, "DNS-SD is not enabled"),
+        };
+    }
+    let Some(zones) = &handler.zones else {
+        return text(404, "No zones are configured");
+    };
+
+    let path: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), path.as_slice()) {
+        ("GET", ["zones"]) => {
+            let list: Vec<_> = zones.list().into_iter().map(|(zone, serial)| json!({"zone": zone, "serial": serial})).collect();
+            HttpResponse::new(200, "application/json", serde_json::to_vec(&list).unwrap())
+        }
+        ("GET", ["zones", zone]) => match zones.text(zone) {
+            Some(zone) => HttpResponse::new(200, "text/plain", zone.into_bytes()),
+            None => text(404, "No such zone"),
+        },
+        (_, ["zones", zone, "records"]) => edit(zones, request, zone),
+        _ => text(404, "Not found"),
+    }
+}
+
+// What the `zone` command uses to talk to a running server, returns the status and body
+pub fn request(listen: &str, token: &str, method: &str, target: &str, body: &str) -> io::Result<(u16, String)> {
+    let addr = listen.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid HTTP listen address {:?}", listen)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, CLIENT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method, target, listen, token, body.len(), body
+    );
+    stream.write_all(request.as_bytes())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line.split_whitespace().nth(1).and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Not an HTTP response"))?;
+    let mut response = String::new();
+    reader.read_to_string(&mut response)?;
+    let body = response.split_once("\r\n\r\n").map(|(_, body)| body.to_string()).unwrap_or_default();
+    Ok((status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, target: &str, token: &str, body: &str) -> HttpRequest {
+        let mut request = HttpRequest { method: method.to_string(), body: body.as_bytes().to_vec(), ..Default::default() };
+        request.parse_target(target);
+        request.headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+        request
+    }
+
+    #[test]
+    fn test_record_set() {
+        assert_eq!(record_set(&request("DELETE", "/zones/x/records?name=WWW.example.lan.&type=aaaa", "", "")), Ok(("www.example.lan".to_string(), Some(QueryType::AAAA))));
+        assert!(record_set(&request("DELETE", "/zones/x/records?type=A", "", "")).is_err());
+        assert_eq!(parse_records(b"; comment\nwww.example.lan. 60 IN A 192.0.2.1\n\n").unwrap().len(), 1);
+        assert!(parse_records(b"www.example.lan. 60 IN A nope\n").is_err());
+    }
+}
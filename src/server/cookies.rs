@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crypto_box::aead::rand_core::RngCore;
+use crypto_box::aead::OsRng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::utils::edns::{EdnsOption, OPTION_COOKIE};
+
+// Server cookies following RFC 9018: version, three reserved bytes, timestamp and hash
+const VERSION: u8 = 1;
+const SERVER_COOKIE_LEN: usize = 16;
+const LIFETIME: u32 = 3600; // seconds a server cookie is accepted
+const REFRESH: u32 = 1800;  // older cookies are replaced in the next answer
+const CLOCK_SKEW: u32 = 300; // how far in the future a timestamp may be
+
+// Clients with no valid cookie are forgotten once there are this many
+const MAX_CLIENTS: usize = 10000;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CookieConfig {
+    pub enabled: bool,        // answer client cookies with server cookies
+    pub upstream: bool,       // send our own cookies to upstreams and drop answers that don't echo them
+    pub unverified_rate: u32, // UDP queries per second from one address without a valid server cookie, 0 for no limit
+}
+
+// What a query's COOKIE option turned out to be
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cookie {
+    Missing,
+    Malformed,
+    // Only a client cookie, or a server cookie that isn't ours or has expired
+    Unverified([u8; 8]),
+    // The whole option, client and server cookie
+    Valid(Vec<u8>),
+}
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32
+}
+
+/**
+Server side DNS cookies (RFC 7873). A client that sends a client cookie gets a server cookie
+back, a hash over its client cookie, its address and a timestamp keyed with a secret that only
+lives as long as the process. Once it echoes that cookie the client has shown it can receive
+our answers, so it can't be an off-path attacker spoofing someone else's address. Over UDP,
+clients without a valid cookie can be held to `unverified_rate` queries per second, queries
+beyond that get an empty truncated answer (with a fresh cookie if they sent one), which sends
+real clients to TCP or back with the cookie.
+*/
+#[derive(Clone)]
+pub struct ServerCookies {
+    secret: [u8; 16],
+    unverified_rate: u32,
+    clients: Arc<Mutex<HashMap<IpAddr, (u64, u32)>>>, // second and queries within it
+}
+
+impl ServerCookies {
+    pub fn new(config: &CookieConfig) -> ServerCookies {
+        let mut secret = [0u8; 16];
+        OsRng.fill_bytes(&mut secret);
+        ServerCookies { secret, unverified_rate: config.unverified_rate, clients: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    fn hash(&self, client_cookie: &[u8], timestamp: u32, client: IpAddr) -> [u8; 8] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.secret);
+        hasher.update(client_cookie);
+        hasher.update([VERSION, 0, 0, 0]);
+        hasher.update(timestamp.to_be_bytes());
+        match client {
+            IpAddr::V4(addr) => hasher.update(addr.octets()),
+            IpAddr::V6(addr) => hasher.update(addr.octets()),
+        }
+        let mut hash = [0u8; 8];
+        hash.copy_from_slice(&hasher.finalize()[..8]);
+        hash
+    }
+
+    fn cookie(&self, client_cookie: &[u8; 8], client: IpAddr, now: u32) -> Vec<u8> {
+        let mut cookie = client_cookie.to_vec();
+        cookie.extend_from_slice(&[VERSION, 0, 0, 0]);
+        cookie.extend_from_slice(&now.to_be_bytes());
+        cookie.extend_from_slice(&self.hash(client_cookie, now, client));
+        cookie
+    }
+
+    pub fn verify(&self, option: Option<&[u8]>, client: IpAddr) -> Cookie {
+        self.verify_at(option, client, now())
+    }
+
+    pub fn verify_at(&self, option: Option<&[u8]>, client: IpAddr, now: u32) -> Cookie {
+        let Some(option) = option else {
+            return Cookie::Missing;
+        };
+        // A client cookie alone, or with a server cookie of 8 to 32 bytes (RFC 7873 section 5.2.2)
+        if option.len() != 8 && !(16..=40).contains(&option.len()) {
+            return Cookie::Malformed;
+        }
+        let client_cookie: [u8; 8] = option[..8].try_into().unwrap();
+        if option.len() != 8 + SERVER_COOKIE_LEN || option[8] != VERSION {
+            return Cookie::Unverified(client_cookie);
+        }
+        let timestamp = u32::from_be_bytes(option[12..16].try_into().unwrap());
+        let fresh = timestamp <= now.saturating_add(CLOCK_SKEW) && now.saturating_sub(timestamp) < LIFETIME;
+        if fresh && option[16..] == self.hash(&client_cookie, timestamp, client) {
+            Cookie::Valid(option.to_vec())
+        } else {
+            Cookie::Unverified(client_cookie)
+        }
+    }
+
+    // The COOKIE option for the answer, a valid cookie is echoed until it is due for a refresh
+    pub fn reply(&self, cookie: &Cookie, client: IpAddr) -> Option<EdnsOption> {
+        self.reply_at(cookie, client, now())
+    }
+
+    pub fn reply_at(&self, cookie: &Cookie, client: IpAddr, now: u32) -> Option<EdnsOption> {
+        let data = match cookie {
+            Cookie::Missing | Cookie::Malformed => return None,
+            Cookie::Unverified(client_cookie) => self.cookie(client_cookie, client, now),
+            Cookie::Valid(option) => {
+                let timestamp = u32::from_be_bytes(option[12..16].try_into().unwrap());
+                if now.saturating_sub(timestamp) < REFRESH {
+                    option.clone()
+                } else {
+                    self.cookie(option[..8].try_into().unwrap(), client, now)
+                }
+            }
+        };
+        Some(EdnsOption::new(OPTION_COOKIE, data))
+    }
+
+    // Whether a UDP query is answered, a valid cookie always is
+    pub fn allow(&self, cookie: &Cookie, client: IpAddr) -> bool {
+        self.allow_at(cookie, client, now() as u64)
+    }
+
+    pub fn allow_at(&self, cookie: &Cookie, client: IpAddr, now: u64) -> bool {
+        if self.unverified_rate == 0 || matches!(cookie, Cookie::Valid(_)) {
+            return true;
+        }
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_CLIENTS && !clients.contains_key(&client) {
+            clients.retain(|_, (second, _)| *second == now);
+        }
+        let (second, queries) = clients.entry(client).or_insert((now, 0));
+        if *second != now {
+            *second = now;
+            *queries = 0;
+        }
+        *queries += 1;
+        *queries <= self.unverified_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn cookies(unverified_rate: u32) -> ServerCookies {
+        ServerCookies::new(&CookieConfig { enabled: true, unverified_rate, ..Default::default() })
+    }
+
+    #[test]
+    fn test_server_cookie_round_trip() {
+        let cookies = cookies(0);
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let client_cookie = [1, 2, 3, 4, 5, 6, 7, 8];
+        let now = 1_700_000_000;
+
+        let first = cookies.verify_at(Some(&client_cookie), client, now);
+        assert_eq!(first, Cookie::Unverified(client_cookie));
+        let option = cookies.reply_at(&first, client, now).unwrap();
+        assert_eq!(option.data.len(), 24);
+
+        let echoed = cookies.verify_at(Some(&option.data), client, now + 10);
+        assert_eq!(echoed, Cookie::Valid(option.data.clone()));
+        assert_eq!(cookies.reply_at(&echoed, client, now + 10).unwrap().data, option.data);
+        assert_ne!(cookies.reply_at(&echoed, client, now + REFRESH).unwrap().data, option.data);
+
+        // Another address, an expired timestamp or a different secret don't verify
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert_eq!(cookies.verify_at(Some(&option.data), other, now), Cookie::Unverified(client_cookie));
+        assert_eq!(cookies.verify_at(Some(&option.data), client, now + LIFETIME), Cookie::Unverified(client_cookie));
+        assert_eq!(self::cookies(0).verify_at(Some(&option.data), client, now), Cookie::Unverified(client_cookie));
+
+        assert_eq!(cookies.verify_at(Some(&[0; 12]), client, now), Cookie::Malformed);
+        assert_eq!(cookies.verify_at(None, client, now), Cookie::Missing);
+    }
+
+    #[test]
+    fn test_unverified_rate() {
+        let cookies = cookies(2);
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert!(cookies.allow_at(&Cookie::Missing, client, 100));
+        assert!(cookies.allow_at(&Cookie::Unverified([0; 8]), client, 100));
+        assert!(!cookies.allow_at(&Cookie::Missing, client, 100));
+        assert!(cookies.allow_at(&Cookie::Valid(vec![0; 24]), client, 100));
+        assert!(cookies.allow_at(&Cookie::Missing, client, 101));
+    }
+}
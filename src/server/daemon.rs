@@ -0,0 +1,102 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/**
+A pidfile holding the server's process id, locked for as long as the server runs so a second
+instance started with the same file refuses to start instead of fighting over the ports. The
+lock goes away with the process however it ends, a file left behind by a killed server is
+simply taken over by the next one. The file is removed when the server stops on an error.
+*/
+#[derive(Debug)]
+pub struct Pidfile {
+    file: File,
+    path: PathBuf,
+}
+
+impl Pidfile {
+    pub fn lock(path: impl AsRef<Path>) -> io::Result<Pidfile> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Pidfile { file, path: path.to_path_buf() }),
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                file.read_to_string(&mut pid)?;
+                Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is locked, r_dns is already running as pid {}", path.display(), pid.trim())))
+            }
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    // Called again after detaching, the process id changes with each fork
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", process::id())?;
+        self.file.sync_all()
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn fork() -> io::Result<bool> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+/**
+Moves the process into the background the classic way: fork so the shell gets its prompt back,
+start a new session without a controlling terminal, fork again so the session leader is gone
+and no terminal can ever be acquired, then point stdin, stdout and stderr at `/dev/null`. The
+working directory is kept, the config, cache and logs are found relative to it. Has to be called
+before any thread is started, only the calling thread survives a fork.
+*/
+pub fn detach() -> io::Result<()> {
+    if !fork()? {
+        unsafe { libc::_exit(0) };
+    }
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if !fork()? {
+        unsafe { libc::_exit(0) };
+    }
+    let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    for fd in 0..3 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_pidfile_lock() {
+        let path = env::temp_dir().join(format!("r_dns_test_{}.pid", process::id()));
+        let mut pidfile = Pidfile::lock(&path).unwrap();
+        pidfile.write_pid().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", process::id()));
+
+        let err = Pidfile::lock(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(err.to_string().ends_with(&format!("already running as pid {}", process::id())));
+
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,149 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use log::warn;
+
+use crate::server::handler::QueryHandler;
+use crate::server::http::{HttpRequest, HttpResponse};
+use crate::utils::packet::DnsPacket;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+use crate::utils::wire::write_large_message;
+
+pub const CONTENT_TYPE: &str = "application/dns-message";
+
+fn error(status: u16, message: &str) -> HttpResponse {
+    HttpResponse::new(status, "text/plain", format!("{}\n", message).into_bytes())
+}
+
+// The wire format query from `?dns=` (base64url, padding optional) or a POST body
+fn query_message(request: &HttpRequest) -> Result<Vec<u8>, HttpResponse> {
+    let message = match request.method.as_str() {
+        "GET" => {
+            let dns = request.param("dns").ok_or_else(|| error(400, "Missing dns parameter"))?;
+            URL_SAFE_NO_PAD.decode(dns.trim_end_matches('=')).map_err(|_| error(400, "Invalid base64url in dns parameter"))?
+        }
+        _ if request.header("content-type") != Some(CONTENT_TYPE) => return Err(error(415, "Expected a DNS message")),
+        _ => request.body.clone(),
+    };
+    if message.len() > 512 {
+        return Err(error(413, "Query too large"));
+    }
+    Ok(message)
+}
+
+/*
+How long HTTP caches may keep an answer (RFC 8484 section 5.1): the smallest TTL in the
+response, or for negative answers the SOA's negative caching time. TTLs coming from our cache
+have already been counted down, so the response is always fresh as of now and `Age` is 0.
+Anything that isn't a real answer (SERVFAIL, REFUSED, a bare NODATA) isn't cached at all.
+*/
+pub fn max_age(response: &DnsPacket) -> Option<u32> {
+    if !matches!(response.header.rescode, ResultCode::NOERROR | ResultCode::NXDOMAIN) || response.header.truncated_message {
+        return None;
+    }
+    if response.answers.is_empty() {
+        return response.negative_ttl();
+    }
+    response.answers.iter().chain(&response.authorities).chain(&response.resources).map(DnsRecord::ttl).min()
+}
+
+fn cache_headers(response: &DnsPacket) -> Vec<(String, String)> {
+    match max_age(response) {
+        Some(max_age) => vec![
+            ("Cache-Control".to_string(), format!("max-age={}", max_age)),
+            ("Age".to_string(), "0".to_string()),
+        ],
+        None => vec![("Cache-Control".to_string(), "no-store".to_string())],
+    }
+}
+
+// RFC 8484 DNS over HTTPS, `GET /dns-query?dns=...` or `POST /dns-query` with a DNS message body
+pub fn handle(handler: &QueryHandler, request: &HttpRequest) -> HttpResponse {
+    let message = match query_message(request) {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+    let query = match DnsPacket::from_bytes(&message) {
+        Ok(query) => query,
+        Err(_) => return error(400, "Malformed DNS message"),
+    };
+
+    let answer = handler.answer_from(query, request.peer.map(|peer| peer.ip()));
+    let response = match answer {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Failed to answer DoH query: {:?}", e);
+            return error(500, &e.to_string());
+        }
+    };
+    let message = match write_large_message(&response) {
+        Ok(message) => message,
+        Err(e) => return error(500, &e.to_string()),
+    };
+
+    let mut http_response = HttpResponse::new(200, CONTENT_TYPE, message);
+    http_response.headers.extend(cache_headers(&response));
+    http_response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::byte_buffer::ByteBuffer;
+    use crate::utils::dns_class::DnsClass;
+    use std::net::Ipv4Addr;
+
+    fn soa(ttl: u32, minimum: u32) -> DnsRecord {
+        DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 1,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum,
+            ttl,
+            class: DnsClass::IN,
+        }
+    }
+
+    #[test]
+    fn test_max_age() {
+        let mut response = DnsPacket::new();
+        for ttl in [300, 60] {
+            response.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl, class: DnsClass::IN });
+        }
+        assert_eq!(max_age(&response), Some(60));
+
+        let mut negative = DnsPacket::new();
+        negative.header.rescode = ResultCode::NXDOMAIN;
+        negative.authorities.push(soa(3600, 900));
+        assert_eq!(max_age(&negative), Some(900));
+        negative.authorities.clear();
+        assert_eq!(max_age(&negative), None);
+
+        response.header.rescode = ResultCode::SERVFAIL;
+        assert_eq!(max_age(&response), None);
+        assert_eq!(cache_headers(&response), vec![("Cache-Control".to_string(), "no-store".to_string())]);
+    }
+
+    #[test]
+    fn test_query_message() {
+        // The example query from RFC 8484 section 4.1.1, www.example.com A
+        let mut request = HttpRequest { method: "GET".to_string(), ..Default::default() };
+        request.parse_target("/dns-query?dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB");
+        let message = query_message(&request).unwrap();
+        let query = DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(&message)).unwrap();
+        assert_eq!(query.questions[0].name, "www.example.com");
+
+        request.parse_target("/dns-query?dns=not*base64");
+        assert_eq!(query_message(&request).unwrap_err().status, 400);
+
+        let post = HttpRequest { method: "POST".to_string(), body: message.clone(), ..Default::default() };
+        assert_eq!(query_message(&post).unwrap_err().status, 415);
+        let mut post = post;
+        post.headers.push(("Content-Type".to_string(), CONTENT_TYPE.to_string()));
+        assert_eq!(query_message(&post).unwrap(), message);
+    }
+}
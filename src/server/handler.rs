@@ -0,0 +1,1051 @@
+use std::io;
+use std::iter;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::cache::cache::{DnsCacheEntry, ThreadSafeDnsCache};
+use crate::cache::key::{CacheKey, Provenance};
+use crate::cache::policy::STALE_TTL;
+use crate::config::ServerConfig;
+use crate::filter::captive_portal::CaptivePortal;
+use crate::filter::nxdomain_guard::{ancestors, NxdomainGuard};
+use crate::filter::nxdomain_redirect::NxdomainRedirect;
+use crate::filter::rebinding::RebindingFilter;
+use crate::filter::rewrite::Rewrites;
+use crate::filter::safe_search::SafeSearch;
+use crate::filter::scrub::scrub;
+use crate::local::chaos::Chaos;
+use crate::local::dnssd::DnsSd;
+use crate::local::hosts::LocalHosts;
+use crate::local::pool::Pools;
+use crate::local::reverse::ReverseZones;
+use crate::local::zone::Zones;
+use crate::logging::trace;
+use crate::resolver::forward::{forwarder, ResolverMode};
+use crate::resolver::proxy;
+use crate::resolver::recursive::{add_root_referral, IpPreference, Progress};
+use crate::resolver::Upstream;
+use crate::server::cookies::{Cookie, ServerCookies};
+use crate::server::overload::{Overload, ShedPolicy};
+use crate::server::stats::counters;
+use crate::utils::builder::DnsPacketBuilder;
+use crate::utils::dns_class::DnsClass;
+use crate::utils::edns::{self, OPTION_COOKIE, OPTION_ECS};
+use crate::utils::header::Opcode;
+use crate::utils::packet::DnsPacket;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+use crate::utils::result_code::ResultCode;
+use crate::utils::subnet::Subnet;
+use crate::utils::wire::write_udp_message;
+
+// What to do with queries that have RD cleared
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NonRecursivePolicy {
+    // Answer from local data and the cache, refer to the root otherwise
+    #[default]
+    Cache,
+    // Only local data (hosts files, CHAOS), everything else is REFUSED
+    Refuse,
+}
+
+// What a client gets when its lookup runs out of time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeadlinePolicy {
+    #[default]
+    Servfail,
+    // The cached answer however long expired, SERVFAIL without one
+    Stale,
+    // The aliases followed so far, SERVFAIL when recursion hadn't got that far
+    Partial,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RecursionConfig {
+    pub non_recursive: NonRecursivePolicy,
+    pub ip_preference: IpPreference,
+    pub deadline: u64, // milliseconds a client's lookup may take in all, 0 for no limit
+    pub on_deadline: DeadlinePolicy,
+}
+
+/**
+Turns a request into a response independently of the transport it arrived on: hosts files and
+record pools first, then the local zones, then the cache, then the resolver. Only class IN is
+resolved, CHAOS has its own handful of names. Upstream answers are scrubbed of unrelated records
+and pass the rebinding filter before they are cached. Cheap to clone, so every listener can own one.
+*/
+#[derive(Clone)]
+pub struct QueryHandler {
+    pub cache: ThreadSafeDnsCache,
+    pub enable_cache: bool,
+    pub hosts: Option<LocalHosts>,
+    pub rebinding: Option<RebindingFilter>,
+    pub chaos: Option<Chaos>,
+    pub safe_search: Option<SafeSearch>,
+    pub captive_portal: Option<CaptivePortal>,
+    pub rewrites: Option<Rewrites>,
+    pub pools: Option<Pools>,
+    pub zones: Option<Zones>,
+    pub reverse_zones: Option<ReverseZones>,
+    pub dnssd: Option<DnsSd>,
+    pub nxdomain_guard: Option<NxdomainGuard>,
+    pub nxdomain_redirect: Option<NxdomainRedirect>,
+    pub cookies: Option<ServerCookies>,
+    pub non_recursive: NonRecursivePolicy,
+    pub deadline: Option<(Duration, DeadlinePolicy)>,
+    pub partition: bool, // see `refuses_plaintext`
+    pub overload: Option<Overload>,
+    pub transparent: bool, // relay raw queries, see `relay`
+    pub upstream: Upstream,
+}
+
+const SAFE_SEARCH_TTL: u32 = 300;
+
+// An empty response to `request` with its question
+fn reply_to(request: &DnsPacket, rescode: ResultCode) -> DnsPacket {
+    DnsPacketBuilder::response_to(request).recursion_available(true).rcode(rescode).build()
+}
+
+impl QueryHandler {
+    // The hosts files come in already loaded since they own a reload thread
+    pub fn new(cache: ThreadSafeDnsCache, enable_cache: bool, hosts: Option<LocalHosts>, config: &ServerConfig) -> io::Result<QueryHandler> {
+        let safe_search = if config.safe_search.is_used() {
+            Some(SafeSearch::new(&config.safe_search)?)
+        } else {
+            None
+        };
+        let pools = if config.pools.is_empty() {
+            None
+        } else {
+            Some(Pools::new(&config.pools).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?)
+        };
+        // A standby may be sent zones it has none of
+        let zones = if config.zones.is_empty() && config.catalogs.is_empty() && !config.standby.enabled {
+            None
+        } else {
+            Some(Zones::new(&config.zones)?)
+        };
+        let reverse_zones = if config.reverse_zones.enabled {
+            Some(ReverseZones::new(&config.reverse_zones, zones.clone(), hosts.clone())?)
+        } else {
+            None
+        };
+        Ok(QueryHandler {
+            cache,
+            enable_cache,
+            hosts,
+            rebinding: config.rebinding.enabled.then(|| RebindingFilter::new(&config.rebinding)),
+            chaos: config.chaos.enabled.then(|| Chaos::new(&config.chaos)),
+            safe_search,
+            captive_portal: config.captive_portal.enabled.then(|| CaptivePortal::new(&config.captive_portal)).transpose()?,
+            rewrites: Some(Rewrites::new(&config.rewrites)?).filter(|rewrites| !rewrites.is_empty()),
+            pools,
+            zones,
+            reverse_zones,
+            dnssd: config.dnssd.enabled.then(|| DnsSd::new(&config.dnssd)).transpose()?,
+            nxdomain_guard: config.nxdomain_guard.enabled.then(|| NxdomainGuard::new(&config.nxdomain_guard)),
+            nxdomain_redirect: config.nxdomain_redirect.enabled.then(|| NxdomainRedirect::new(&config.nxdomain_redirect)).transpose()?,
+            cookies: config.cookies.enabled.then(|| ServerCookies::new(&config.cookies)),
+            non_recursive: config.recursion.non_recursive,
+            partition: config.cache.partition,
+            deadline: (config.recursion.deadline > 0).then(|| (Duration::from_millis(config.recursion.deadline), config.recursion.on_deadline)),
+            overload: config.overload.enabled.then(|| Overload::new(&config.overload)),
+            transparent: config.upstream.mode == ResolverMode::Proxy,
+            upstream: Upstream::configured(),
+        })
+    }
+
+    // For transports that don't know who is asking, they get the default policies
+    pub fn answer(&self, request: DnsPacket) -> io::Result<DnsPacket> {
+        self.answer_from(request, None)
+    }
+
+    pub fn answer_from(&self, mut request: DnsPacket, client: Option<IpAddr>) -> io::Result<DnsPacket> {
+        let _trace = trace::start();
+        counters().query();
+        let (edns, dnssec_ok) = (request.opt().is_some(), request.dnssec_ok());
+        // A client's cookie is for us, it is taken out before the query goes anywhere else
+        let cookie = match (&self.cookies, client) {
+            (Some(cookies), Some(client)) => Some((cookies, client, cookies.verify(request.take_option(OPTION_COOKIE).as_deref(), client))),
+            _ => None,
+        };
+        let mut response = match cookie {
+            Some((_, _, Cookie::Malformed)) => reply_to(&request, ResultCode::FORMERR),
+            _ => self.route(request, client)?,
+        };
+        // EDNS clients get an OPT back (RFC 6891 section 7) even when there were no options to relay
+        if edns && response.opt().is_none() {
+            response.resources.push(edns::opt_record(dnssec_ok));
+        }
+        if let Some(option) = cookie.and_then(|(cookies, client, cookie)| cookies.reply(&cookie, client)) {
+            response.set_option(option);
+        }
+        // AD may only be set for data a local validator has verified (RFC 4035 3.2.3). Nothing
+        // is validated here, so an AD copied from an upstream or an old cache entry is cleared.
+        response.header.authed_data = false;
+        Ok(response)
+    }
+
+    // Hands a message to the handler for its OPCODE, only QUERY has one so far
+    fn route(&self, request: DnsPacket, client: Option<IpAddr>) -> io::Result<DnsPacket> {
+        match request.header.opcode {
+            Opcode::Query => {
+                let dnssec_ok = request.dnssec_ok();
+                let mut response = self.build_response(request, client, true)?;
+                // Kept apart from resolution: only the client's copy changes, and DNSSEC aware clients would reject it anyway
+                if let Some(redirect) = self.nxdomain_redirect.as_ref().filter(|_| !dnssec_ok) {
+                    if redirect.redirect(&mut response) {
+                        debug!("Redirected NXDOMAIN for {}", response.questions[0].name);
+                    }
+                }
+                Ok(response)
+            }
+            // IQUERY is obsolete (RFC 3425) and STATUS never got specified
+            Opcode::IQuery | Opcode::Status | Opcode::Notify | Opcode::Update | Opcode::Dso | Opcode::Unassigned(_) => {
+                debug!("No handler for opcode {}", request.header.opcode);
+                Ok(reply_to(&request, ResultCode::NOTIMP))
+            }
+        }
+    }
+
+    // UDP queries from clients without a valid cookie may be over their rate, see `ServerCookies`
+    pub fn answer_udp(&self, request: DnsPacket, client: IpAddr) -> io::Result<DnsPacket> {
+        if let Some(cookies) = &self.cookies {
+            let cookie = cookies.verify(request.option(OPTION_COOKIE), client);
+            if !cookies.allow(&cookie, client) {
+                debug!("{} is over the rate for queries without a cookie, answering truncated", client);
+                counters().query();
+                counters().blocked();
+                let mut response = reply_to(&request, ResultCode::NOERROR);
+                response.header.truncated_message = true;
+                if let (Some(_), Some(option)) = (request.opt(), cookies.reply(&cookie, client)) {
+                    response.resources.push(edns::opt_record(request.dnssec_ok()));
+                    response.set_option(option);
+                }
+                return Ok(response);
+            }
+        }
+        self.answer_from(request, Some(client))
+    }
+
+    /**
+    An answer that costs no lookup, for UDP queries shed because the queue is full: from the cache,
+    stale entries included, or else the empty SERVFAIL or truncated answer `udp_shed` asks for.
+    */
+    pub fn shed(&self, request: DnsPacket) -> DnsPacket {
+        counters().query();
+        counters().shed();
+        let (edns, dnssec_ok) = (request.opt().is_some(), request.dnssec_ok());
+        let cached = match request.questions.as_slice() {
+            [q] if self.enable_cache && request.header.opcode == Opcode::Query && q.qclass == DnsClass::IN => self.cached(&request, q),
+            _ => None,
+        };
+        let mut response = cached.unwrap_or_else(|| match self.overload.as_ref().map(|overload| overload.udp_shed) {
+            Some(ShedPolicy::Truncate) => DnsPacketBuilder::response_to(&request).recursion_available(true).truncated(true).build(),
+            _ => reply_to(&request, ResultCode::SERVFAIL),
+        });
+        if edns && response.opt().is_none() {
+            response.resources.push(edns::opt_record(dnssec_ok));
+        }
+        response.header.authed_data = false;
+        response
+    }
+
+    // Whether the client asked for authenticated data (AD in the query, RFC 6840 5.7) while the cache is partitioned
+    fn wants_authenticated(&self, request: &DnsPacket) -> bool {
+        self.partition && request.header.authed_data
+    }
+
+    /**
+    The paths a cached answer for `request` may have been learned on, best first. Answers are
+    cached apart by the path they were learned on, see `Provenance`, and a client that wants
+    authenticated data only takes those that didn't cross the network in the clear.
+    */
+    fn provenances(&self, request: &DnsPacket) -> impl Iterator<Item = Provenance> {
+        let floor = if self.wants_authenticated(request) { Provenance::Encrypted } else { Provenance::Plain };
+        Provenance::BEST_FIRST.into_iter().filter(move |provenance| *provenance >= floor)
+    }
+
+    /**
+    Whether the client wants authenticated data while lookups can only learn answers in the
+    clear, with no DNSCrypt upstream to ask. Such clients get SERVFAIL for anything local data
+    doesn't answer, rather than data a spoofer could have planted.
+    */
+    fn refuses_plaintext(&self, request: &DnsPacket) -> bool {
+        self.wants_authenticated(request) && self.upstream.provenance() < Provenance::Encrypted
+    }
+
+    // The cached answer to `question`, stale or not, with the request's ID and flags
+    fn cached(&self, request: &DnsPacket, question: &DnsQuestion) -> Option<DnsPacket> {
+        let key = CacheKey::for_question(question);
+        let (key, entry) = self.provenances(request).find_map(|provenance| self.lookup(&key.clone().learned(provenance), client_subnet(request)))?;
+        Some(self.serve_entry(request, question, &key, entry))
+    }
+
+    /**
+    The entry answering `key` for a client whose ECS option speaks for `subnet`: one an upstream
+    scoped to a subnet holding the client's (RFC 7871 section 7.3.2), the longest scope first,
+    then one that holds for every client. Clients without ECS only ever get the latter.
+    */
+    fn lookup(&self, key: &CacheKey, subnet: Option<Subnet>) -> Option<(CacheKey, DnsCacheEntry)> {
+        let scoped = subnet.into_iter()
+            .flat_map(|subnet| (1..=subnet.prefix).rev().map(move |prefix| key.clone().with_scope(Subnet { prefix, ..subnet })));
+        scoped.chain(iter::once(key.clone())).find_map(|key| {
+            let entry = self.cache.get(&key)?;
+            Some((key, entry))
+        })
+    }
+
+    fn serve_entry(&self, request: &DnsPacket, question: &DnsQuestion, key: &CacheKey, entry: DnsCacheEntry) -> DnsPacket {
+        let clock = self.cache.clock();
+        let mut response = DnsPacket::clone(&entry.packet);
+        counters().cache_hit();
+        if entry.is_expired(&*clock) {
+            debug!("Serving stale cache entry {}", key);
+            response.set_ttl(STALE_TTL);
+            self.cache.refresh_stale(key);
+        } else {
+            debug!("Cache hit for {}", key);
+            if response.answers.is_empty() {
+                response.set_negative_ttl(entry.remaining_ttl(&*clock));
+            }
+        }
+        response.header.id = request.header.id;
+        response.header.recursion_desired = request.header.recursion_desired;
+        response.header.checking_disabled = request.header.checking_disabled;
+        response.questions = vec![question.clone()];
+        if !request.dnssec_ok() {
+            response.remove_dnssec_records(question.qtype);
+        }
+        response
+    }
+
+    /**
+    An unexpired NXDOMAIN the cache holds for a name above `question` (RFC 8020: nothing exists
+    below a name that doesn't), its negative TTL counted down to what is left of it. The
+    question's own name was a cache miss already.
+    */
+    fn cached_nxdomain(&self, request: &DnsPacket, question: &DnsQuestion) -> Option<DnsPacket> {
+        let clock = self.cache.clock();
+        ancestors(&question.name).find_map(|name| {
+            let key = CacheKey::for_question(&DnsQuestion { name: name.to_string(), ..question.clone() });
+            let entry = self.provenances(request).find_map(|provenance| self.cache.peek(&key.clone().learned(provenance)))
+                .filter(|entry| !entry.is_expired(&*clock) && entry.packet.header.rescode == ResultCode::NXDOMAIN)?;
+            let mut packet = DnsPacket::clone(&entry.packet);
+            packet.set_negative_ttl(entry.remaining_ttl(&*clock));
+            Some(packet)
+        })
+    }
+
+    // Whether hosts files, pools, zones or safe search have something to say about the query
+    fn intercepts(&self, request: &DnsPacket, client: Option<IpAddr>) -> bool {
+        let [question] = request.questions.as_slice() else {
+            return false;
+        };
+        if request.header.opcode != Opcode::Query || question.qclass != DnsClass::IN {
+            return false;
+        }
+        let mut q = question.clone();
+        q.name = q.name.to_ascii_lowercase();
+        self.captive_portal.as_ref().is_some_and(|portal| portal.holds(client) && portal.answer(&q).is_some())
+            || self.hosts.as_ref().is_some_and(|hosts| hosts.answer(&q).is_some())
+            || self.pools.as_ref().is_some_and(|pools| pools.answer(&q).is_some())
+            || self.zones.as_ref().is_some_and(|zones| zones.answer(&q).is_some())
+            || self.reverse_zones.as_ref().is_some_and(|reverse| reverse.answer(&q).is_some())
+            || self.safe_search.as_ref()
+                .filter(|safe_search| safe_search.is_enforced(client))
+                .is_some_and(|safe_search| safe_search.rewrite(&q.name).is_some())
+            || self.rewrites.as_ref().is_some_and(|rewrites| rewrites.rewrite(&q.name).is_some())
+    }
+
+    /**
+    Transparent proxy mode: the raw query goes to the upstreams and their raw response comes
+    back, so records and EDNS options the server doesn't know survive. Local data still answers
+    the names it has, and answers are cached as received, only their TTLs are counted down.
+    Cached answers carrying an OPT record only go to clients that sent one themselves.
+    */
+    pub fn relay(&self, query: &[u8], client: Option<IpAddr>) -> io::Result<Vec<u8>> {
+        let _trace = trace::start();
+        let servers = forwarder().get().map(|forwarder| forwarder.servers.as_slice()).unwrap_or_default();
+        let Ok(request) = DnsPacket::from_bytes(query) else {
+            counters().query();
+            return proxy::relay(query, servers);
+        };
+        // answer_from counts the queries it answers itself
+        if self.intercepts(&request, client) || self.refuses_plaintext(&request) {
+            return write_udp_message(&self.answer_from(request, client)?);
+        }
+        counters().query();
+
+        let key = match request.questions.as_slice() {
+            [q] if request.header.opcode == Opcode::Query && !request.header.checking_disabled => {
+                // Proxy mode only has plain upstreams
+                Some(CacheKey::for_question(q))
+            }
+            _ => None,
+        };
+        let cacheable = key.filter(|_| self.enable_cache);
+        let clock = self.cache.clock();
+        let subnet = client_subnet(&request);
+        if let Some((key, entry)) = cacheable.as_ref().and_then(|key| self.lookup(key, subnet)).filter(|(_, entry)| !entry.is_expired(&*clock)) {
+            let mut response = write_udp_message(&entry.packet)?;
+            let age = entry.ttl.saturating_sub(entry.remaining_ttl(&*clock));
+            if let Some(len) = proxy::age_ttls(&mut response, age) {
+                response.truncate(len);
+                if !proxy::has_opt(&response) || proxy::has_opt(query) {
+                    debug!("Cache hit for {}", key);
+                    counters().cache_hit();
+                    response[0..2].copy_from_slice(&query[0..2]);
+                    proxy::copy_question(query, &mut response);
+                    return Ok(response);
+                }
+            }
+        }
+
+        let response = proxy::relay(query, servers)?;
+        let rcode = ResultCode::from_num((response[3] & 0x0F) as u16);
+        let truncated = response[2] & 0x02 != 0;
+        if let (Some(key), Some(ttl)) = (cacheable, proxy::min_ttl(&response)) {
+            if !truncated && matches!(rcode, ResultCode::NOERROR | ResultCode::NXDOMAIN) {
+                // Anything over 512 bytes fails to parse and isn't cached
+                if let Ok(packet) = DnsPacket::from_bytes(&response) {
+                    let key = scoped(key, subnet, &packet);
+                    self.cache.insert(key, DnsCacheEntry::new(packet, clock.now() + ttl as u64, ttl as u64))?;
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    // `rewrite` is false for the query a rule already rewrote, so rules never chain
+    fn build_response(&self, mut request: DnsPacket, client: Option<IpAddr>, rewrite: bool) -> io::Result<DnsPacket> {
+        info!("Handling query");
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.recursion_desired = request.header.recursion_desired;
+        response.header.recursion_available = true;
+        response.header.response = true;
+        response.header.opcode = request.header.opcode;
+        response.header.checking_disabled = request.header.checking_disabled;
+
+        // RFC 9619: a QUERY carries exactly one question. Nobody agrees on what several
+        // would mean, so they get a FORMERR rather than silently losing all but one.
+        if request.questions.len() != 1 {
+            response.header.rescode = ResultCode::FORMERR;
+            response.questions = request.questions;
+            return Ok(response);
+        }
+        // Everything internal works on the lowercased name, the client gets its own spelling back
+        let original = request.questions.remove(0);
+        let mut q = original.clone();
+        q.name = q.name.to_ascii_lowercase();
+
+        if q.qclass != DnsClass::IN {
+            match self.chaos.as_ref().and_then(|chaos| chaos.answer(&original)) {
+                Some(answers) => {
+                    response.header.authoritative_answer = true;
+                    response.answers = answers;
+                }
+                None => response.header.rescode = ResultCode::REFUSED,
+            }
+            response.questions.push(original);
+            return Ok(response);
+        }
+
+        // A client held at the portal gets nothing else for names outside its allowlist, local data included
+        if let Some(answers) = self.captive_portal.as_ref().filter(|portal| portal.holds(client)).and_then(|portal| portal.answer(&q)) {
+            response.questions.push(original);
+            response.answers = answers;
+            return Ok(response);
+        }
+
+        // Resolved (and cached) under the name the rules give, answered under the one the client asked for
+        if let Some(target) = self.rewrites.as_ref().filter(|_| rewrite).and_then(|rewrites| rewrites.rewrite(&q.name)) {
+            debug!("Rewriting {} to {}", q.name, target);
+            request.questions.push(DnsQuestion::new(target.clone(), q.qtype));
+            let mut response = self.build_response(request, client, false)?;
+            for record in &mut response.answers {
+                if record.domain().trim_end_matches('.').eq_ignore_ascii_case(&target) {
+                    record.set_domain(original.name.clone());
+                }
+            }
+            response.questions = vec![original];
+            return Ok(response);
+        }
+
+        let safe_search = self.safe_search.as_ref().filter(|safe_search| safe_search.is_enforced(client));
+        if let Some(target) = safe_search.and_then(|safe_search| safe_search.rewrite(&q.name)) {
+            let mut query = DnsPacket::new();
+            query.header.id = request.header.id;
+            query.header.recursion_desired = request.header.recursion_desired;
+            query.header.checking_disabled = request.header.checking_disabled;
+            query.questions.push(DnsQuestion::new(target.to_string(), q.qtype));
+            query.resources = request.resources.clone();
+
+            // Still the client's one query, answer_from finishes it (and counts it) once
+            let mut response = self.build_response(query, client, false)?;
+            response.answers.insert(0, DnsRecord::CNAME {
+                domain: original.name.clone(),
+                cname: target.to_string(),
+                ttl: SAFE_SEARCH_TTL,
+                class: DnsClass::IN,
+            });
+            response.questions = vec![original];
+            return Ok(response);
+        }
+
+        let local = self.hosts.as_ref().and_then(|hosts| hosts.answer(&q))
+            .or_else(|| self.pools.as_ref().and_then(|pools| pools.answer(&q)));
+        if let Some(answers) = local {
+            response.header.authoritative_answer = true;
+            response.questions.push(original);
+            response.answers = answers;
+            return Ok(response);
+        }
+
+        let zone_answer = self.zones.as_ref()
+            .and_then(|zones| if request.dnssec_ok() { zones.signed_answer(&q) } else { zones.answer(&q) })
+            .or_else(|| self.reverse_zones.as_ref().and_then(|reverse| reverse.answer(&q)));
+        if let Some(answer) = zone_answer {
+            response.header.authoritative_answer = answer.authoritative;
+            response.header.rescode = answer.rescode;
+            response.questions.push(original);
+            response.answers = answer.answers;
+            response.authorities = answer.authorities;
+            response.resources = answer.resources;
+            if !request.dnssec_ok() {
+                response.remove_dnssec_records(q.qtype);
+            }
+            return Ok(response);
+        }
+
+        // Nothing is validated locally: CD is passed upstream and DO only decides whether
+        // DNSSEC records that happen to be in an answer are kept
+        let checking_disabled = request.header.checking_disabled;
+        let dnssec_ok = request.dnssec_ok();
+        let qtype = q.qtype;
+
+        let recursion_desired = request.header.recursion_desired;
+        if !recursion_desired && self.non_recursive == NonRecursivePolicy::Refuse {
+            response.header.rescode = ResultCode::REFUSED;
+            response.questions.push(original);
+            return Ok(response);
+        }
+
+        if self.refuses_plaintext(&request) {
+            debug!("Answering SERVFAIL for {}, the client wants authenticated data", q.name);
+            response.header.rescode = ResultCode::SERVFAIL;
+            response.questions.push(original);
+            return Ok(response);
+        }
+
+        let mut key = CacheKey::for_question(&q);
+        // Looked at first, a cache hit would drop an entry this old
+        let stale = match self.deadline {
+            Some((_, DeadlinePolicy::Stale)) if self.enable_cache => self.provenances(&request).find_map(|provenance| {
+                let key = key.clone().learned(provenance);
+                Some((self.cache.peek(&key)?, key))
+            }),
+            _ => None,
+        };
+        if let Some(response) = self.enable_cache.then(|| self.cached(&request, &original)).flatten() {
+            return Ok(response);
+        }
+
+        debug!("Cache miss for {}", key);
+        if !recursion_desired {
+            add_root_referral(&mut response);
+            response.questions.push(original);
+            return Ok(response);
+        }
+
+        // Made-up names under a flooded zone never repeat, caching them would only push real entries out
+        if self.nxdomain_guard.as_ref().is_some_and(|guard| !guard.allow_lookup(&q.name)) {
+            counters().limited();
+            match self.cached_nxdomain(&request, &q) {
+                Some(nxdomain) => {
+                    debug!("Answering NXDOMAIN for {} without a lookup, its zone is flooded and a name above it is gone", q.name);
+                    response.header.rescode = ResultCode::NXDOMAIN;
+                    response.authorities = nxdomain.authorities.into_iter().filter(|record| matches!(record, DnsRecord::SOA { .. })).collect();
+                }
+                None => {
+                    debug!("Answering SERVFAIL for {} without a lookup, its zone is flooded", q.name);
+                    response.header.rescode = ResultCode::SERVFAIL;
+                }
+            }
+            response.questions.push(original);
+            return Ok(response);
+        }
+
+        let _permit = match self.overload.as_ref().map(Overload::try_recursion) {
+            Some(None) => {
+                debug!("Answering SERVFAIL for {}, too many lookups in flight", q.name);
+                counters().shed();
+                response.header.rescode = ResultCode::SERVFAIL;
+                response.questions.push(original);
+                return Ok(response);
+            }
+            permit => permit.flatten(),
+        };
+        let start = Instant::now();
+        let progress = Progress::default();
+        let deadline = self.deadline.map(|(deadline, _)| start + deadline);
+        let mut result = self.upstream.resolve(&q.name, q.qtype, checking_disabled, request.opt(), deadline, &progress);
+        debug!("Lookup of {} {:?} took {:?}", q.name, q.qtype, start.elapsed());
+        // What a lookup that ran out of time leaves is the client's to take, never the cache's
+        let out_of_time = result.is_err() && self.deadline.is_some_and(|(deadline, _)| start.elapsed() >= deadline);
+        if out_of_time {
+            if let (Some((_, DeadlinePolicy::Stale)), Some((entry, key))) = (self.deadline, stale) {
+                debug!("Out of time for {}, answering from the expired cache entry", q.name);
+                return Ok(self.serve_entry(&request, &original, &key, entry));
+            }
+            if let (Some((_, DeadlinePolicy::Partial)), Some(partial)) = (self.deadline, progress.into_inner().unwrap()) {
+                debug!("Out of time for {}, answering with the aliases followed so far", q.name);
+                result = Ok((partial, Provenance::Plain));
+            }
+        }
+        // The upstream's OPT answers this client's options, it is kept out of the cache
+        let mut upstream_opt = None;
+        match result {
+            Ok((mut result, provenance)) => {
+                key = scoped(key.learned(provenance), client_subnet(&request), &result);
+                upstream_opt = result.take_opt();
+                response.header.rescode = result.header.rescode;
+                response.answers = result.answers;
+                response.authorities = result.authorities;
+                response.resources = result.resources;
+                scrub(&q.name, &mut response);
+                if let Some(guard) = &self.nxdomain_guard {
+                    guard.record(&q.name, &response);
+                }
+                if let Some(rebinding) = &self.rebinding {
+                    if rebinding.filter(&q.name, &mut response) > 0 {
+                        counters().blocked();
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Lookup of {} {:?} failed: {}", q.name, q.qtype, e);
+                response.header.rescode = ResultCode::SERVFAIL;
+            }
+        }
+        let ttl = match self.cache.override_ttl(&q.name, &mut response) {
+            Some(ttl) => ttl,
+            None => response.cache_ttl().unwrap_or(60),
+        };
+        // The SOA's TTL becomes the negative TTL, cache hits count it down from there
+        if response.negative_ttl().is_some() {
+            response.set_negative_ttl(ttl);
+        }
+        response.questions.push(q);
+
+        // Data fetched with CD may have failed validation upstream, it must not reach other clients
+        if !checking_disabled && !out_of_time {
+            match DnsCacheEntry::from_packet(&response, ttl, &*self.cache.clock()) {
+                Ok(entry) => self.cache.insert(key.clone(), entry)?,
+                Err(e) => debug!("Not caching {} {:?}: {}", original.name, original.qtype, e),
+            }
+        }
+        // Cached for the clients that take it, an upstream that answered in the clear fails this one
+        if self.wants_authenticated(&request) && key.provenance < Provenance::Encrypted {
+            debug!("Answering SERVFAIL for {}, the answer was learned in the clear", original.name);
+            response.header.rescode = ResultCode::SERVFAIL;
+            response.answers.clear();
+            response.authorities.clear();
+            response.resources.clear();
+            response.questions = vec![original];
+            return Ok(response);
+        }
+        response.questions = vec![original];
+        if !dnssec_ok {
+            response.remove_dnssec_records(qtype);
+        }
+        // The payload size the client sees is ours, not the upstream's
+        if let (Some(DnsRecord::OPT { flags, options, .. }), Some(_)) = (upstream_opt, request.opt()) {
+            response.resources.push(DnsRecord::OPT { udp_size: edns::UDP_PAYLOAD_SIZE, flags, options });
+        }
+
+        Ok(response)
+    }
+}
+
+// The subnet a client's ECS option speaks for, masked to its source prefix
+fn client_subnet(request: &DnsPacket) -> Option<Subnet> {
+    request.option(OPTION_ECS).and_then(Subnet::from_ecs).map(|(subnet, _)| subnet)
+}
+
+// Where an upstream answer to a client in `subnet` is cached: under the client's subnet cut to
+// the scope the upstream gave, when the answer depends on it, never longer than what was sent
+fn scoped(key: CacheKey, subnet: Option<Subnet>, response: &DnsPacket) -> CacheKey {
+    let scope = response.option(OPTION_ECS).and_then(Subnet::from_ecs).map(|(_, scope)| scope);
+    match (subnet, scope) {
+        (Some(subnet), Some(scope)) if scope > 0 => key.with_scope(Subnet { prefix: scope.min(subnet.prefix), ..subnet }),
+        _ => key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::cache::cache::DnsCache;
+    use crate::cache::clock::ManualClock;
+    use crate::cache::dump::parse_record;
+    use crate::dnscrypt::client::fake_upstream;
+    use crate::utils::byte_buffer::ByteBuffer;
+    use crate::filter::nxdomain_guard::NxdomainGuardConfig;
+    use crate::resolver::forward::Forwarder;
+    use crate::resolver::transport::{MockNetwork, Network};
+    use crate::utils::edns::EdnsOption;
+    use crate::utils::query_type::QueryType;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn new_handler(config: &ServerConfig) -> QueryHandler {
+        handler_at(config, Arc::new(ManualClock::new(NOW)))
+    }
+
+    fn handler_at(config: &ServerConfig, clock: Arc<ManualClock>) -> QueryHandler {
+        let cache = ThreadSafeDnsCache::in_memory(DnsCache::with_clock(64, clock));
+        QueryHandler::new(cache, true, None, config).unwrap()
+    }
+
+    #[test]
+    fn test_formerr_unless_one_question() {
+        let handler = new_handler(&ServerConfig::default());
+
+        let response = handler.answer(DnsPacketBuilder::new().id(7).build()).unwrap();
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.header.rescode, ResultCode::FORMERR);
+        assert!(response.questions.is_empty());
+
+        let request = DnsPacketBuilder::query("example.com", QueryType::A)
+            .question(DnsQuestion::new("example.org".to_string(), QueryType::AAAA))
+            .build();
+        let response = handler.answer(request).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::FORMERR);
+        assert!(response.answers.is_empty());
+        // Both questions are echoed, so the client sees which query this answers
+        assert_eq!(response.questions.len(), 2);
+    }
+
+    // Caches `answer` the way a lookup would have
+    fn cache(handler: &QueryHandler, answer: &DnsPacket, ttl: u32) {
+        let key = CacheKey::for_question(&answer.questions[0]);
+        handler.cache.insert(key, DnsCacheEntry::from_packet(answer, ttl, &*handler.cache.clock()).unwrap()).unwrap();
+    }
+
+    fn query(name: &str, qtype: QueryType) -> DnsPacketBuilder {
+        DnsPacketBuilder::query(name, qtype).recursion_desired(true)
+    }
+
+    #[test]
+    fn test_authed_data_cleared() {
+        let handler = new_handler(&ServerConfig::default());
+        // An upstream that set AD, cached as it came
+        let answer = DnsPacketBuilder::query("example.com", QueryType::A)
+            .authed_data(true)
+            .answer(DnsRecord::a("example.com", Ipv4Addr::new(192, 0, 2, 1), 300))
+            .build();
+        cache(&handler, &answer, 300);
+
+        let response = handler.answer(query("example.com", QueryType::A).authed_data(true).build()).unwrap();
+        assert_eq!(response.answers, answer.answers);
+        assert!(!response.header.authed_data);
+    }
+
+    #[test]
+    fn test_non_recursive_queries() {
+        let mut config = ServerConfig::default();
+        let handler = new_handler(&config);
+        let answer = DnsPacketBuilder::query("cached.example", QueryType::A)
+            .answer(DnsRecord::a("cached.example", Ipv4Addr::new(192, 0, 2, 1), 300))
+            .build();
+        cache(&handler, &answer, 300);
+
+        // RD=0 gets what the cache has, and the root hints instead of a lookup for the rest
+        let response = handler.answer(query("cached.example", QueryType::A).recursion_desired(false).build()).unwrap();
+        assert_eq!(response.answers, answer.answers);
+        let response = handler.answer(query("uncached.example", QueryType::A).recursion_desired(false).build()).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities.len(), 3);
+        assert!(response.authorities.iter().all(|record| matches!(record, DnsRecord::NS { domain, .. } if domain.is_empty())));
+        assert_eq!(response.resources.len(), 6);
+        assert!(handler.cache.get(&CacheKey::new("uncached.example", QueryType::A)).is_none());
+
+        config.recursion.non_recursive = NonRecursivePolicy::Refuse;
+        let response = new_handler(&config).answer(query("uncached.example", QueryType::A).recursion_desired(false).build()).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::REFUSED);
+    }
+
+    #[test]
+    fn test_negative_ttl_counts_down() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let handler = handler_at(&ServerConfig::default(), clock.clone());
+        let nxdomain = DnsPacketBuilder::query("gone.example", QueryType::A)
+            .rcode(ResultCode::NXDOMAIN)
+            .authority(parse_record("example. 3600 IN SOA ns.example. admin.example. 1 3600 600 86400 300").unwrap())
+            .build();
+        cache(&handler, &nxdomain, 300);
+
+        clock.advance(120);
+        let response = handler.answer(query("gone.example", QueryType::A).build()).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(response.negative_ttl(), Some(180));
+        // The cached copy keeps its own TTL
+        assert_eq!(handler.cache.get(&CacheKey::new("gone.example", QueryType::A)).unwrap().packet.negative_ttl(), Some(300));
+    }
+
+    /*
+    A network where every server answers for every name: "www.example.net" only after two
+    seconds, "alias.example" with a CNAME to it, anything else with an address right away.
+    */
+    fn slow_network() -> &'static MockNetwork {
+        Box::leak(Box::new(MockNetwork::new(|_, query| {
+            let name = query.questions[0].name.as_str();
+            let mut packet = DnsPacket::new();
+            packet.header.authoritative_answer = true;
+            match name {
+                "alias.example" => packet.answers.push(DnsRecord::cname(name, "www.example.net", 300)),
+                "www.example.net" => {
+                    thread::sleep(Duration::from_secs(2));
+                    packet.answers.push(DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, 80), 300));
+                }
+                _ => packet.answers.push(DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, 1), 300)),
+            }
+            Some(packet)
+        })))
+    }
+
+    fn deadline_handler(policy: DeadlinePolicy, clock: Arc<ManualClock>) -> QueryHandler {
+        let mut config = ServerConfig::default();
+        config.recursion.deadline = 300;
+        config.recursion.on_deadline = policy;
+        let mut handler = handler_at(&config, clock);
+        handler.upstream = Upstream { forwarder: None, connector: slow_network() };
+        handler
+    }
+
+    #[test]
+    fn test_answer_larger_than_a_datagram() {
+        let network: &'static MockNetwork = Box::leak(Box::new(MockNetwork::new(|_, query| {
+            let name = query.questions[0].name.as_str();
+            let mut packet = DnsPacket::new();
+            packet.header.authoritative_answer = true;
+            packet.answers = (0..60).map(|i| DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, i), 300)).collect();
+            Some(packet)
+        })));
+        let mut handler = new_handler(&ServerConfig::default());
+        handler.upstream = Upstream { forwarder: None, connector: network };
+
+        // Learned over TCP and kept whole, UDP clients are told to ask again over TCP
+        let response = handler.answer_udp(query("many.example", QueryType::A).build(), IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap();
+        assert_eq!(response.answers.len(), 60);
+        assert_eq!(handler.cache.peek(&CacheKey::new("many.example", QueryType::A)).unwrap().packet.answers.len(), 60);
+        let truncated = DnsPacket::from_bytes(&write_udp_message(&response).unwrap()).unwrap();
+        assert!(truncated.header.truncated_message && truncated.answers.is_empty());
+        assert_eq!(truncated.questions, response.questions);
+    }
+
+    #[test]
+    fn test_deadline_servfail() {
+        let handler = deadline_handler(DeadlinePolicy::Servfail, Arc::new(ManualClock::new(NOW)));
+        let start = Instant::now();
+        let response = handler.answer(query("www.example.net", QueryType::A).build()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+        assert!(handler.cache.peek(&CacheKey::new("www.example.net", QueryType::A)).is_none());
+
+        // Lookups in time are answered and cached as usual
+        let response = handler.answer(query("www.example.com", QueryType::A).build()).unwrap();
+        assert_eq!(response.answers, vec![DnsRecord::a("www.example.com", Ipv4Addr::new(192, 0, 2, 1), 300)]);
+        assert!(handler.cache.peek(&CacheKey::new("www.example.com", QueryType::A)).is_some());
+    }
+
+    #[test]
+    fn test_deadline_partial() {
+        let handler = deadline_handler(DeadlinePolicy::Partial, Arc::new(ManualClock::new(NOW)));
+        let response = handler.answer(query("alias.example", QueryType::A).build()).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.answers, vec![DnsRecord::cname("alias.example", "www.example.net", 300)]);
+        assert!(handler.cache.peek(&CacheKey::new("alias.example", QueryType::A)).is_none());
+
+        // Nothing assembled, nothing to give
+        let response = handler.answer(query("www.example.net", QueryType::A).build()).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    }
+
+    #[test]
+    fn test_deadline_stale() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let handler = deadline_handler(DeadlinePolicy::Stale, clock.clone());
+        let old = DnsPacketBuilder::query("www.example.net", QueryType::A)
+            .answer(DnsRecord::a("www.example.net", Ipv4Addr::new(192, 0, 2, 8), 60))
+            .build();
+        cache(&handler, &old, 60);
+        // Long past any serve-stale window, only the deadline brings it back
+        clock.advance(7 * 86400);
+        let key = CacheKey::new("www.example.net", QueryType::A);
+
+        let response = handler.answer(query("www.example.net", QueryType::A).build()).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(response.answers, vec![DnsRecord::a("www.example.net", Ipv4Addr::new(192, 0, 2, 8), STALE_TTL)]);
+        // The cache had dropped the old answer, the lookup that ran out of time put nothing back
+        assert!(handler.cache.peek(&key).is_none());
+
+        // Without an old answer it is SERVFAIL after all
+        let response = handler.answer(query("www.example.net", QueryType::AAAA).build()).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    }
+
+    // ECS data for `subnet`, with the scope an upstream would give
+    fn ecs(subnet: &str, scope: u8) -> EdnsOption {
+        let subnet: Subnet = subnet.parse().unwrap();
+        let IpAddr::V4(addr) = subnet.addr else { unreachable!() };
+        let mut data = vec![0, 1, subnet.prefix, scope];
+        data.extend_from_slice(&addr.octets()[..(subnet.prefix as usize).div_ceil(8)]);
+        EdnsOption::new(OPTION_ECS, data)
+    }
+
+    #[test]
+    fn test_answers_scoped_to_client_subnets() {
+        // An upstream that tailors its answer to the /24 in the query's ECS option
+        let network: &'static MockNetwork = Box::leak(Box::new(MockNetwork::new(|_, query| {
+            let mut packet = DnsPacket::new();
+            let name = query.questions[0].name.as_str();
+            match query.option(OPTION_ECS).and_then(Subnet::from_ecs) {
+                Some((subnet, _)) => {
+                    let IpAddr::V4(addr) = subnet.addr else { unreachable!() };
+                    packet.answers.push(DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, addr.octets()[2]), 300));
+                    let options = vec![ecs(&format!("{}/{}", subnet.addr, subnet.prefix), 24)];
+                    packet.resources.push(DnsRecord::OPT { udp_size: 1232, flags: 0, options });
+                }
+                None => packet.answers.push(DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, 1), 300)),
+            }
+            Some(packet)
+        })));
+        let mut handler = new_handler(&ServerConfig::default());
+        let forwarder = Forwarder { servers: vec![(Ipv4Addr::new(192, 0, 2, 53), 53)], search: Vec::new(), dnscrypt: Vec::new() };
+        handler.upstream = Upstream { forwarder: Some(forwarder), connector: network };
+
+        let ask = |subnet: Option<&str>| {
+            let mut request = query("www.example", QueryType::A).build();
+            let options = subnet.map(|subnet| vec![ecs(subnet, 0)]).unwrap_or_default();
+            request.resources.push(DnsRecord::OPT { udp_size: 1232, flags: 0, options });
+            handler.answer(request).unwrap().answers
+        };
+        let a = |last| vec![DnsRecord::a("www.example", Ipv4Addr::new(192, 0, 2, last), 300)];
+        assert_eq!(ask(Some("198.51.100.0/24")), a(100));
+        assert_eq!(ask(Some("203.0.113.0/24")), a(113));
+        assert_eq!(network.asked.lock().unwrap().len(), 2);
+
+        // Another client of the first subnet gets the cached answer for it, clients without ECS
+        // never get an answer tailored to somebody else
+        assert_eq!(ask(Some("198.51.100.77/32")), a(100));
+        assert_eq!(network.asked.lock().unwrap().len(), 2);
+        assert_eq!(ask(None), a(1));
+        assert_eq!(network.asked.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_flooded_zone_gets_servfail() {
+        let network: &'static MockNetwork = Box::leak(Box::new(MockNetwork::new(|_, query| {
+            let name = query.questions[0].name.as_str();
+            let mut packet = DnsPacket::new();
+            match name {
+                "www.victim.example" => packet.answers.push(DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, 1), 300)),
+                _ => {
+                    packet.header.rescode = ResultCode::NXDOMAIN;
+                    packet.authorities.push(parse_record("victim.example. 300 IN SOA ns.victim.example. admin.victim.example. 1 3600 600 86400 300").unwrap());
+                }
+            }
+            Some(packet)
+        })));
+        let nxdomain_guard = NxdomainGuardConfig { enabled: true, threshold: 2, recursion_rate: 0, ..NxdomainGuardConfig::default() };
+        let config = ServerConfig { nxdomain_guard, ..ServerConfig::default() };
+        let mut handler = new_handler(&config);
+        let forwarder = Forwarder { servers: vec![(Ipv4Addr::new(192, 0, 2, 53), 53)], search: Vec::new(), dnscrypt: Vec::new() };
+        handler.upstream = Upstream { forwarder: Some(forwarder), connector: network };
+        let ask = |name: &str| handler.answer(query(name, QueryType::A).build()).unwrap();
+
+        assert_eq!(ask("gone.victim.example").header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(ask("x7f3k.victim.example").header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(network.asked.lock().unwrap().len(), 2);
+
+        // The zone is flooded now: a name that exists isn't denied, it fails
+        assert_eq!(ask("www.victim.example").header.rescode, ResultCode::SERVFAIL);
+        assert_eq!(ask("q9z2m.victim.example").header.rescode, ResultCode::SERVFAIL);
+        // Names below one the cache knows is gone still get their NXDOMAIN
+        let response = ask("a.gone.victim.example");
+        assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(response.negative_ttl(), Some(300));
+        assert_eq!(network.asked.lock().unwrap().len(), 2);
+        assert!(counters().totals().limited >= 3);
+    }
+
+    // A plain DNS upstream on localhost giving every name 192.0.2.2
+    fn plain_upstream() -> (Ipv4Addr, u16) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || loop {
+            let mut data = [0u8; 512];
+            let (len, src) = socket.recv_from(&mut data).unwrap();
+            let request = DnsPacket::from_bytes(&data[..len]).unwrap();
+            let name = request.questions[0].name.clone();
+            let response = DnsPacketBuilder::response_to(&request).answer(DnsRecord::a(&name, Ipv4Addr::new(192, 0, 2, 2), 300)).build();
+            let mut buffer = ByteBuffer::new();
+            response.write(&mut buffer).unwrap();
+            socket.send_to(&buffer.buffer[..buffer.position()], src).unwrap();
+        });
+        (Ipv4Addr::LOCALHOST, port)
+    }
+
+    #[test]
+    fn test_partition_by_provenance() {
+        // The DNSCrypt upstream only knows secure.example, everything else ends up at the plain one
+        let dnscrypt = fake_upstream(|request| {
+            let name = &request.questions[0].name;
+            match name.as_str() {
+                "secure.example" => DnsPacketBuilder::response_to(&request).answer(DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, 1), 300)).build(),
+                _ => DnsPacketBuilder::response_to(&request).rcode(ResultCode::SERVFAIL).build(),
+            }
+        });
+        let plain = plain_upstream();
+        let mut handler = new_handler(&ServerConfig::default());
+        handler.partition = true;
+        let forwarder = Forwarder { servers: vec![dnscrypt.server, plain], search: Vec::new(), dnscrypt: vec![Arc::new(dnscrypt)] };
+        handler.upstream = Upstream { forwarder: Some(forwarder), connector: &Network };
+        let ask = |handler: &QueryHandler, name: &str, authed_data: bool| handler.answer(query(name, QueryType::A).authed_data(authed_data).build()).unwrap();
+        let a = |name: &str, last| vec![DnsRecord::a(name, Ipv4Addr::new(192, 0, 2, last), 300)];
+
+        // Learned encrypted: a client that wants authenticated data gets it, and so do the others
+        assert_eq!(ask(&handler, "secure.example", true).answers, a("secure.example", 1));
+        assert!(handler.cache.peek(&CacheKey::new("secure.example", QueryType::A).learned(Provenance::Encrypted)).is_some());
+        assert_eq!(ask(&handler, "secure.example", false).answers, a("secure.example", 1));
+
+        // Learned in the clear: cached for clients that don't care, never served to one that does
+        let response = ask(&handler, "plain.example", true);
+        assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+        assert!(response.answers.is_empty());
+        assert!(handler.cache.peek(&CacheKey::new("plain.example", QueryType::A)).is_some());
+        assert_eq!(ask(&handler, "plain.example", false).answers, a("plain.example", 2));
+        assert_eq!(ask(&handler, "plain.example", true).header.rescode, ResultCode::SERVFAIL);
+
+        // Without a DNSCrypt upstream such a client isn't even looked up for
+        let forwarder = Forwarder { servers: vec![plain], search: Vec::new(), dnscrypt: Vec::new() };
+        handler.upstream = Upstream { forwarder: Some(forwarder), connector: &Network };
+        assert_eq!(ask(&handler, "other.example", true).header.rescode, ResultCode::SERVFAIL);
+        assert!(handler.cache.peek(&CacheKey::new("other.example", QueryType::A)).is_none());
+        assert_eq!(ask(&handler, "other.example", false).answers, a("other.example", 2));
+    }
+}
@@ -0,0 +1,274 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use crate::server::handler::QueryHandler;
+use crate::odoh::target::{self, odoh_target};
+use crate::server::{admin, doh, json, stats};
+
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+const MAX_BODY_BYTES: usize = 64 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub listen: String,
+    pub admin_token: String, // empty disables the zone and service management endpoints
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            enabled: false,
+            listen: "127.0.0.1:8053".to_string(),
+            admin_token: String::new(),
+        }
+    }
+}
+
+/**
+Just enough of an HTTP/1.1 request to route DNS API calls: the method, the path, the
+percent-decoded query parameters and a `Content-Length` delimited body. Every connection is
+closed after one response.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub params: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub peer: Option<SocketAddr>,
+}
+
+impl HttpRequest {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn parse_target(&mut self, target: &str) {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        self.path = path.to_string();
+        self.params = query.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key), percent_decode(value))
+            })
+            .collect();
+    }
+
+    pub fn read(stream: &mut impl BufRead) -> io::Result<HttpRequest> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut request = HttpRequest::default();
+        let mut total = 0;
+
+        let mut line = String::new();
+        total += stream.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        request.method = parts.next().ok_or_else(|| invalid("Empty request line"))?.to_string();
+        let target = parts.next().ok_or_else(|| invalid("Missing request target"))?.to_string();
+        request.parse_target(&target);
+
+        loop {
+            line.clear();
+            let read = stream.read_line(&mut line)?;
+            total += read;
+            if total > MAX_HEADER_BYTES {
+                return Err(invalid("Request headers too large"));
+            }
+            if read == 0 || line.trim_end().is_empty() {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                request.headers.push((key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        if let Some(length) = request.header("content-length") {
+            let length: usize = length.parse().map_err(|_| invalid("Invalid Content-Length"))?;
+            if length > MAX_BODY_BYTES {
+                return Err(invalid("Request body too large"));
+            }
+            request.body = vec![0; length];
+            stream.read_exact(&mut request.body)?;
+        }
+
+        Ok(request)
+    }
+}
+
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    pub fn write(&self, stream: &mut impl Write) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            415 => "Unsupported Media Type",
+            _ => "Internal Server Error",
+        };
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status, reason, self.content_type, self.body.len()
+        );
+        for (key, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+pub fn route(handler: &QueryHandler, config: &HttpConfig, request: &HttpRequest) -> HttpResponse {
+    match (request.method.as_str(), request.path.as_str(), odoh_target().get()) {
+        ("GET", "/dns-query", _) if request.param("dns").is_some() => doh::handle(handler, request),
+        ("POST", "/dns-query", _) if request.header("content-type") == Some(doh::CONTENT_TYPE) => doh::handle(handler, request),
+        ("GET", "/resolve" | "/dns-query", _) => json::handle(handler, request),
+        ("GET", "/stats", _) => stats::handle(handler),
+        (_, path, _) if path == "/zones" || path.starts_with("/zones/") || path == "/services" || path == "/cache" => admin::handle(handler, &config.admin_token, request),
+        ("GET", target::CONFIGS_PATH, Some(odoh)) => odoh.configs_response(),
+        ("POST", "/dns-query", Some(odoh)) => odoh.handle(handler, request),
+        ("GET", _, _) => HttpResponse::new(404, "text/plain", b"Not found\n".to_vec()),
+        _ => HttpResponse::new(405, "text/plain", b"Method not allowed\n".to_vec()),
+    }
+}
+
+fn handle_connection(handler: &QueryHandler, config: &HttpConfig, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let peer = stream.peer_addr().ok();
+    let mut request = HttpRequest::read(&mut BufReader::new(stream))?;
+    request.peer = peer;
+    route(handler, config, &request).write(&mut writer)
+}
+
+// Serves the HTTP API on its own thread, one short-lived thread per connection
+pub fn start(config: &HttpConfig, handler: QueryHandler) -> io::Result<()> {
+    let listener = TcpListener::bind(&config.listen)?;
+    info!("HTTP API listening on {}", config.listen);
+
+    // Shared through an Arc rather than cloned, dropping a cache handle writes the cache to disk
+    let handler = Arc::new(handler);
+    let config = Arc::new(config.clone());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let (handler, config) = (Arc::clone(&handler), Arc::clone(&config));
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(&handler, &config, stream) {
+                            warn!("HTTP request failed: {:?}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Failed to accept HTTP connection: {:?}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "GET /resolve?name=example.com&type=AAAA&cd=1 HTTP/1.1\r\nHost: localhost\r\nAccept: application/dns-json\r\n\r\n";
+        let request = HttpRequest::read(&mut raw.as_bytes()).unwrap();
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/resolve");
+        assert_eq!(request.param("name"), Some("example.com"));
+        assert_eq!(request.param("type"), Some("AAAA"));
+        assert_eq!(request.param("do"), None);
+        assert_eq!(request.header("accept"), Some("application/dns-json"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn test_read_body() {
+        let raw = "POST /dns-query HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcdef";
+        assert_eq!(HttpRequest::read(&mut raw.as_bytes()).unwrap().body, b"abc");
+
+        let raw = "POST /dns-query HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n";
+        assert!(HttpRequest::read(&mut raw.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("www%2Eexample.com"), "www.example.com");
+        assert_eq!(percent_decode("a+b"), "a b");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut out = Vec::new();
+        HttpResponse::new(200, "application/dns-json", b"{}".to_vec()).write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("Content-Length: 2\r\n"));
+        assert!(out.ends_with("\r\n\r\n{}"));
+    }
+}
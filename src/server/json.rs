@@ -0,0 +1,160 @@
+use serde::Serialize;
+
+use crate::server::handler::QueryHandler;
+use crate::server::http::{HttpRequest, HttpResponse};
+use crate::utils::packet::DnsPacket;
+use crate::utils::presentation::{fqdn, record_data};
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+
+/*
+The JSON schema served by dns.google/resolve and cloudflare-dns.com/dns-query:
+
+{"Status": 0, "TC": false, "RD": true, "RA": true, "AD": false, "CD": false,
+ "Question": [{"name": "example.com.", "type": 1}],
+ "Answer": [{"name": "example.com.", "type": 1, "TTL": 300, "data": "93.184.216.34"}]}
+*/
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct JsonResponse {
+    pub status: u16,
+    #[serde(rename = "TC")]
+    pub tc: bool,
+    #[serde(rename = "RD")]
+    pub rd: bool,
+    #[serde(rename = "RA")]
+    pub ra: bool,
+    #[serde(rename = "AD")]
+    pub ad: bool,
+    #[serde(rename = "CD")]
+    pub cd: bool,
+    pub question: Vec<JsonQuestion>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub answer: Vec<JsonRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authority: Vec<JsonRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub additional: Vec<JsonRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonQuestion {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub qtype: u16,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRecord {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub qtype: u16,
+    #[serde(rename = "TTL")]
+    pub ttl: u32,
+    pub data: String,
+}
+
+fn json_records(records: &[DnsRecord]) -> Vec<JsonRecord> {
+    records.iter().filter_map(|record| {
+        Some(JsonRecord {
+            name: fqdn(record.domain()),
+            qtype: record.query_type().to_num(),
+            ttl: record.ttl(),
+            data: record_data(record)?,
+        })
+    }).collect()
+}
+
+impl JsonResponse {
+    pub fn from_packet(packet: &DnsPacket) -> JsonResponse {
+        JsonResponse {
+            status: packet.header.rescode.to_num(),
+            tc: packet.header.truncated_message,
+            rd: packet.header.recursion_desired,
+            ra: packet.header.recursion_available,
+            ad: packet.header.authed_data,
+            cd: packet.header.checking_disabled,
+            question: packet.questions.iter().map(|q| JsonQuestion {
+                name: fqdn(&q.name),
+                qtype: q.qtype.to_num(),
+            }).collect(),
+            answer: json_records(&packet.answers),
+            authority: json_records(&packet.authorities),
+            additional: json_records(&packet.resources),
+        }
+    }
+}
+
+fn error(status: u16, message: &str) -> HttpResponse {
+    let body = serde_json::json!({ "error": message }).to_string();
+    HttpResponse::new(status, "application/json", body.into_bytes())
+}
+
+// Answers `?name=example.com&type=AAAA`. `type` defaults to A and takes mnemonics or numbers.
+pub fn handle(handler: &QueryHandler, request: &HttpRequest) -> HttpResponse {
+    let name = match request.param("name") {
+        Some(name) if !name.is_empty() => name.trim_end_matches('.').to_ascii_lowercase(),
+        _ => return error(400, "Missing name parameter"),
+    };
+    let qtype = match request.param("type").map(QueryType::from_name).unwrap_or(Some(QueryType::A)) {
+        Some(qtype) => qtype,
+        None => return error(400, "Invalid type parameter"),
+    };
+
+    let mut query = DnsPacket::new();
+    query.header.recursion_desired = true;
+    query.questions.push(DnsQuestion::new(name, qtype));
+
+    let response = match handler.answer_from(query, request.peer.map(|peer| peer.ip())) {
+        Ok(response) => response,
+        Err(e) => return error(500, &e.to_string()),
+    };
+
+    // Cloudflare insists on its own media type, everyone else gets plain JSON
+    let content_type = match request.header("accept") {
+        Some(accept) if accept.contains("application/dns-json") => "application/dns-json",
+        _ => "application/json",
+    };
+    let body = serde_json::to_vec(&JsonResponse::from_packet(&response)).unwrap();
+    HttpResponse::new(200, content_type, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dns_class::DnsClass;
+    use crate::utils::result_code::ResultCode;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_from_packet() {
+        let mut packet = DnsPacket::new();
+        packet.header.recursion_desired = true;
+        packet.header.recursion_available = true;
+        packet.header.rescode = ResultCode::NOERROR;
+        packet.questions.push(DnsQuestion::new("www.example.com".to_string(), QueryType::A));
+        packet.answers.push(DnsRecord::CNAME {
+            domain: "www.example.com".to_string(),
+            cname: "example.com".to_string(),
+            ttl: 60,
+            class: DnsClass::IN,
+        });
+        packet.answers.push(DnsRecord::A {
+            domain: "example.com".to_string(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+            class: DnsClass::IN,
+        });
+
+        let json = serde_json::to_value(JsonResponse::from_packet(&packet)).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "Status": 0, "TC": false, "RD": true, "RA": true, "AD": false, "CD": false,
+            "Question": [{"name": "www.example.com.", "type": 1}],
+            "Answer": [
+                {"name": "www.example.com.", "type": 5, "TTL": 60, "data": "example.com."},
+                {"name": "example.com.", "type": 1, "TTL": 300, "data": "192.0.2.1"}
+            ]
+        }));
+    }
+}
@@ -0,0 +1,197 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::thread;
+
+use log::{debug, error, info};
+use serde::Deserialize;
+
+use crate::local::dnssd::{default_address, system_hostname};
+use crate::local::hosts::LocalHosts;
+use crate::server::mdns::{multicast_socket, send};
+use crate::utils::builder::DnsPacketBuilder;
+use crate::utils::dns_class::DnsClass;
+use crate::utils::header::Opcode;
+use crate::utils::name::parse_reverse_name;
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+use crate::utils::subnet::Subnet;
+
+pub const LLMNR_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+pub const LLMNR_PORT: u16 = 5355;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LlmnrConfig {
+    pub enabled: bool,
+    pub hostname: String,       // the name answered for, empty for the system's hostname
+    pub addresses: Vec<IpAddr>, // its addresses, empty for the address of the default route
+    pub hosts: bool,            // also answer for the names in the hosts files
+    pub ttl: u32,
+    pub clients: Vec<String>,   // subnets whose queries are answered
+}
+
+impl Default for LlmnrConfig {
+    fn default() -> Self {
+        LlmnrConfig {
+            enabled: false,
+            hostname: String::new(),
+            addresses: Vec::new(),
+            hosts: true,
+            ttl: 30,
+            clients: ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "169.254.0.0/16"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/**
+A Link-Local Multicast Name Resolution responder (RFC 4795), for Windows machines that look up
+bare names on the LAN when DNS has no answer. It only answers for names configured here, the
+host's own and optionally those in the hosts files, and never for queries from outside `clients`.
+LLMNR is a favourite of spoofing tools on Windows networks, which is why it is off by default.
+*/
+#[derive(Clone)]
+pub struct Llmnr {
+    pub hostname: String, // a single label, lowercase
+    addrs: Vec<IpAddr>,
+    hosts: Option<LocalHosts>,
+    ttl: u32,
+    clients: Vec<Subnet>,
+}
+
+impl Llmnr {
+    pub fn new(config: &LlmnrConfig, hosts: Option<LocalHosts>) -> io::Result<Llmnr> {
+        let hostname = match config.hostname.is_empty() {
+            true => system_hostname().ok_or_else(|| io::Error::other("Can't get the hostname, set [llmnr] hostname"))?,
+            false => config.hostname.clone(),
+        };
+        let label = hostname.split('.').next().unwrap_or_default();
+        if label.is_empty() || !label.is_ascii() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid LLMNR hostname {:?}", hostname)));
+        }
+        let clients = config.clients.iter()
+            .map(|client| client.parse().map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, format!("LLMNR clients: {}", e))))
+            .collect::<io::Result<Vec<Subnet>>>()?;
+        let addrs = match config.addresses.is_empty() {
+            true => vec![default_address()?],
+            false => config.addresses.clone(),
+        };
+        Ok(Llmnr {
+            hostname: label.to_ascii_lowercase(),
+            addrs,
+            hosts: hosts.filter(|_| config.hosts),
+            ttl: config.ttl,
+            clients,
+        })
+    }
+
+    // The host's own records, None when the name isn't ours
+    fn own(&self, question: &DnsQuestion) -> Option<Vec<DnsRecord>> {
+        let name = question.name.trim_end_matches('.');
+        if question.qtype == QueryType::PTR {
+            let addr = parse_reverse_name(&name.to_ascii_lowercase())?;
+            return self.addrs.contains(&addr).then(|| vec![DnsRecord::ptr(name, &self.hostname, self.ttl)]);
+        }
+        if !name.eq_ignore_ascii_case(&self.hostname) {
+            return None;
+        }
+        Some(self.addrs.iter().filter_map(|addr| match (addr, question.qtype) {
+            (IpAddr::V4(addr), QueryType::A | QueryType::ANY) => Some(DnsRecord::a(name, *addr, self.ttl)),
+            (IpAddr::V6(addr), QueryType::AAAA | QueryType::ANY) => Some(DnsRecord::aaaa(name, *addr, self.ttl)),
+            _ => None,
+        }).collect())
+    }
+
+    /**
+    The unicast response to an LLMNR query, None when it isn't ours to answer. A name we know
+    without records of the asked type gets an empty answer, so the querier can stop waiting. The
+    header bits LLMNR gives other meanings to (C, T and the rest of Z) are left clear.
+    */
+    pub fn respond(&self, query: &DnsPacket, src: SocketAddr) -> Option<DnsPacket> {
+        if !self.clients.iter().any(|client| client.contains(src.ip())) {
+            return None;
+        }
+        if query.header.response || query.header.opcode != Opcode::Query || query.questions.len() != 1 {
+            return None;
+        }
+        let question = &query.questions[0];
+        if !matches!(question.qclass, DnsClass::IN | DnsClass::ANY) {
+            return None;
+        }
+        let answers = self.own(question).or_else(|| self.hosts.as_ref()?.answer(question))?;
+        let mut response = DnsPacketBuilder::response_to(query).recursion_desired(false).answers(answers).build();
+        response.header.checking_disabled = false;
+        Some(response)
+    }
+}
+
+// Answers LLMNR queries sent to 224.0.0.252:5355 and to this host's port 5355, IPv4 only
+pub fn start(config: &LlmnrConfig, hosts: Option<LocalHosts>) -> io::Result<()> {
+    let llmnr = Llmnr::new(config, hosts)?;
+    let socket = multicast_socket(LLMNR_ADDR, LLMNR_PORT)?;
+    info!("Answering LLMNR for {}", llmnr.hostname);
+    thread::spawn(move || {
+        let mut data = [0u8; 512];
+        loop {
+            let (len, src) = match socket.recv_from(&mut data) {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("LLMNR receive failed: {:?}", e);
+                    continue;
+                }
+            };
+            let query = match DnsPacket::from_bytes(&data[..len]) {
+                Ok(query) => query,
+                Err(e) => {
+                    debug!("Ignoring malformed LLMNR message from {}: {}", src, e);
+                    continue;
+                }
+            };
+            if let Some(response) = llmnr.respond(&query, src) {
+                if let Err(e) = send(&socket, &response, src) {
+                    error!("Failed to send the LLMNR response to {}: {}", src, e);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::hosts::HostsConfig;
+    use std::{env, fs, process};
+
+    fn llmnr() -> Llmnr {
+        let path = env::temp_dir().join(format!("r_dns_llmnr_hosts_{}", process::id()));
+        fs::write(&path, "192.168.1.10 nas\n").unwrap();
+        let hosts = LocalHosts::start(&HostsConfig { enabled: true, system: false, paths: vec![path.display().to_string()], ..HostsConfig::default() });
+        fs::remove_file(&path).unwrap();
+
+        let config = LlmnrConfig { hostname: "FileServer".to_string(), addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5))], ..LlmnrConfig::default() };
+        Llmnr::new(&config, Some(hosts)).unwrap()
+    }
+
+    fn query(name: &str, qtype: QueryType) -> DnsPacket {
+        DnsPacketBuilder::new().id(77).question(DnsQuestion::new(name.to_string(), qtype)).build()
+    }
+
+    #[test]
+    fn test_respond() {
+        let llmnr = llmnr();
+        let peer: SocketAddr = "192.168.1.30:51000".parse().unwrap();
+
+        let response = llmnr.respond(&query("FILESERVER", QueryType::A), peer).unwrap();
+        assert_eq!((response.header.id, response.questions.len()), (77, 1));
+        assert_eq!(response.answers, vec![DnsRecord::a("FILESERVER", Ipv4Addr::new(192, 168, 1, 5), 30)]);
+        assert!(llmnr.respond(&query("fileserver", QueryType::AAAA), peer).unwrap().answers.is_empty());
+        assert_eq!(llmnr.respond(&query("nas", QueryType::A), peer).unwrap().answers.len(), 1);
+        assert_eq!(llmnr.respond(&query("5.1.168.192.in-addr.arpa", QueryType::PTR), peer).unwrap().answers.len(), 1);
+
+        // Names that aren't ours and clients outside the allowed subnets get nothing
+        assert!(llmnr.respond(&query("printer", QueryType::A), peer).is_none());
+        assert!(llmnr.respond(&query("nas", QueryType::A), "203.0.113.9:51000".parse().unwrap()).is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::local::dnssd::DnsSd;
+use crate::utils::builder::DnsPacketBuilder;
+use crate::utils::byte_buffer::ByteBuffer;
+use crate::utils::dns_class::DnsClass;
+use crate::utils::header::Opcode;
+use crate::utils::packet::DnsPacket;
+use crate::utils::presentation::record_data;
+use crate::utils::question::DnsQuestion;
+use crate::utils::random::random;
+use crate::utils::record::DnsRecord;
+
+pub const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_PORT: u16 = 5353;
+
+// The top bit of the class: "unicast response" in questions, "cache flush" in records (RFC 6762 18.12, 10.2)
+const TOP_BIT: u16 = 0x8000;
+// Queries not from port 5353 come from plain resolvers, which get short TTLs (RFC 6762 6.7)
+const LEGACY_TTL: u32 = 10;
+const ANNOUNCEMENTS: usize = 2;
+
+// The port is shared with any other responder on the host, e.g. Avahi on 5353
+pub fn multicast_socket(group: Ipv4Addr, port: u16) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+    socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_loop_v4(true)?;
+    Ok(socket.into())
+}
+
+pub fn send(socket: &UdpSocket, packet: &DnsPacket, dest: SocketAddr) -> io::Result<()> {
+    let mut buffer = ByteBuffer::new();
+    packet.write(&mut buffer)?;
+    socket.send_to(&buffer.buffer[..buffer.position()], dest)?;
+    Ok(())
+}
+
+// Records only we have, so caches drop whatever else they hold for the name and type
+fn cache_flush(mut record: DnsRecord) -> DnsRecord {
+    if !matches!(record, DnsRecord::PTR { .. }) {
+        record.set_class(DnsClass::from_num(DnsClass::IN.to_num() | TOP_BIT));
+    }
+    record
+}
+
+// A response for the multicast group: ID 0, no questions, authoritative (RFC 6762 18)
+pub fn multicast_response(answers: Vec<DnsRecord>, additional: Vec<DnsRecord>) -> DnsPacket {
+    let mut packet = DnsPacketBuilder::new().authoritative(true).answers(answers.into_iter().map(cache_flush)).build();
+    packet.header.response = true;
+    packet.resources = additional.into_iter().map(cache_flush).collect();
+    packet
+}
+
+// Whether the querier's known answer is the record, with at least half its TTL left (RFC 6762 7.1)
+fn known(known: &DnsRecord, record: &DnsRecord) -> bool {
+    known.query_type() == record.query_type()
+        && known.domain().eq_ignore_ascii_case(record.domain())
+        && record_data(known).map(|data| data.to_ascii_lowercase()) == record_data(record).map(|data| data.to_ascii_lowercase())
+        && known.ttl() >= record.ttl() / 2
+}
+
+/**
+The response to an mDNS query and where it goes, None when there is nothing to say. Answers go
+to the group unless a question asks for a unicast response. Queries from a port other than 5353
+come from plain resolvers, they get a conventional unicast DNS response with the ID and question.
+*/
+pub fn respond(dnssd: &DnsSd, query: &DnsPacket, src: SocketAddr) -> Option<(DnsPacket, SocketAddr)> {
+    if query.header.response || query.header.opcode != Opcode::Query {
+        return None;
+    }
+    let legacy = src.port() != MDNS_PORT;
+    let mut unicast = legacy;
+    let (mut answers, mut additional) = (Vec::new(), Vec::new());
+    for question in &query.questions {
+        let class = question.qclass.to_num();
+        if !matches!(DnsClass::from_num(class & !TOP_BIT), DnsClass::IN | DnsClass::ANY) {
+            continue;
+        }
+        unicast |= class & TOP_BIT != 0;
+        let question = DnsQuestion { qclass: DnsClass::IN, ..question.clone() };
+        if let Some((more_answers, more_additional)) = dnssd.answer(&question) {
+            answers.extend(more_answers);
+            additional.extend(more_additional);
+        }
+    }
+    answers.retain(|record| !query.answers.iter().any(|other| known(other, record)));
+    if answers.is_empty() {
+        return None;
+    }
+    additional.retain(|record| !answers.contains(record));
+
+    if legacy {
+        answers.iter_mut().chain(additional.iter_mut()).for_each(|record| record.set_ttl(record.ttl().min(LEGACY_TTL)));
+        let mut response = DnsPacketBuilder::response_to(query).authoritative(true).answers(answers).build();
+        response.resources = additional;
+        return Some((response, src));
+    }
+    let dest = if unicast { src } else { SocketAddr::from((MDNS_ADDR, MDNS_PORT)) };
+    Some((multicast_response(answers, additional), dest))
+}
+
+/**
+Answers mDNS queries for the DNS-SD services on 224.0.0.251:5353, after announcing them. There is
+no probing for name conflicts, the hostname and instance names are expected to be unique on the
+link. IPv4 only.
+*/
+pub fn start(dnssd: &DnsSd) -> io::Result<()> {
+    let socket = multicast_socket(MDNS_ADDR, MDNS_PORT)?;
+    dnssd.attach(socket.try_clone()?);
+
+    // Announced twice, a second apart (RFC 6762 8.3)
+    let announcer = dnssd.clone();
+    thread::spawn(move || {
+        for _ in 0..ANNOUNCEMENTS {
+            announcer.announce(announcer.records());
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+
+    let dnssd = dnssd.clone();
+    info!("Answering mDNS for {} with {} services", dnssd.host, dnssd.services().len());
+    thread::spawn(move || {
+        let mut data = [0u8; 512];
+        loop {
+            let (len, src) = match socket.recv_from(&mut data) {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("mDNS receive failed: {:?}", e);
+                    continue;
+                }
+            };
+            let query = match DnsPacket::from_bytes(&data[..len]) {
+                Ok(query) => query,
+                Err(e) => {
+                    debug!("Ignoring malformed mDNS message from {}: {}", src, e);
+                    continue;
+                }
+            };
+            if let Some((response, dest)) = respond(&dnssd, &query, src) {
+                if let Err(e) = send(&socket, &response, dest) {
+                    error!("Failed to send the mDNS response to {}: {}", dest, e);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/**
+A one-shot multicast query (RFC 6762 5.1) from a port of its own, so responders answer it
+directly and nothing has to listen on 5353. Returns every record of every response that arrives
+within `wait`, answers and additional records alike.
+*/
+pub fn query(questions: &[DnsQuestion], wait: Duration) -> io::Result<Vec<DnsRecord>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_loop_v4(true)?;
+    let id = random() as u16;
+    let mut packet = DnsPacketBuilder::new().id(id).build();
+    packet.questions = questions.to_vec();
+    send(&socket, &packet, SocketAddr::from((MDNS_ADDR, MDNS_PORT)))?;
+
+    let deadline = Instant::now() + wait;
+    let mut records = Vec::new();
+    let mut data = [0u8; 512];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+        socket.set_read_timeout(Some(left))?;
+        let len = match socket.recv_from(&mut data) {
+            Ok((len, _)) => len,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        };
+        let Ok(response) = DnsPacket::from_bytes(&data[..len]) else {
+            continue;
+        };
+        if response.header.response && (response.header.id == id || response.header.id == 0) {
+            records.extend(response.answers.into_iter().chain(response.authorities).chain(response.resources));
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::dnssd::{DnsSdConfig, Service};
+    use crate::utils::query_type::QueryType;
+    use std::net::IpAddr;
+
+    fn dnssd() -> DnsSd {
+        let service = Service { name: "NAS".to_string(), service_type: "_http._tcp".to_string(), port: 80, txt: Vec::new() };
+        let config = DnsSdConfig { hostname: "nas".to_string(), addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))], services: vec![service], ..DnsSdConfig::default() };
+        DnsSd::new(&config).unwrap()
+    }
+
+    fn query(qclass: u16, known: Vec<DnsRecord>) -> DnsPacket {
+        let mut query = DnsPacketBuilder::new().question(DnsQuestion::new("_http._tcp.local".to_string(), QueryType::PTR)).answers(known).build();
+        query.questions[0].qclass = DnsClass::from_num(qclass);
+        query
+    }
+
+    #[test]
+    fn test_respond() {
+        let dnssd = dnssd();
+        let peer: SocketAddr = "192.168.1.30:5353".parse().unwrap();
+        let (response, dest) = respond(&dnssd, &query(1, Vec::new()), peer).unwrap();
+        assert_eq!(dest, SocketAddr::from((MDNS_ADDR, MDNS_PORT)));
+        assert_eq!((response.header.id, response.questions.len(), response.answers.len()), (0, 0, 1));
+        assert!(response.resources.iter().all(|record| record.class() == DnsClass::UNKNOWN(0x8001)));
+
+        // The QU bit asks for a unicast answer, known answers are left out
+        assert_eq!(respond(&dnssd, &query(0x8001, Vec::new()), peer).unwrap().1, peer);
+        let known = vec![DnsRecord::ptr("_http._tcp.local", "nas._http._tcp.local", 100)];
+        assert!(respond(&dnssd, &query(1, known), peer).is_none());
+
+        // A plain resolver gets a normal response with short TTLs
+        let resolver: SocketAddr = "192.168.1.30:40000".parse().unwrap();
+        let (response, dest) = respond(&dnssd, &query(1, Vec::new()), resolver).unwrap();
+        assert_eq!((dest, response.questions.len(), response.answers[0].ttl()), (resolver, 1, LEGACY_TTL));
+    }
+}
@@ -0,0 +1,18 @@
+pub mod admin;
+pub mod cookies;
+#[cfg(unix)]
+pub mod daemon;
+pub mod doh;
+pub mod handler;
+pub mod http;
+pub mod json;
+pub mod llmnr;
+pub mod mdns;
+pub mod overload;
+#[cfg(windows)]
+pub mod service;
+pub mod standby;
+pub mod stats;
+pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
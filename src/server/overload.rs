@@ -0,0 +1,182 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use log::{debug, error, info};
+use serde::Deserialize;
+
+// What a UDP query that finds the queue full gets when the cache has nothing for it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShedPolicy {
+    #[default]
+    Servfail,
+    // An empty truncated answer, the client retries over TCP where the connection limit applies
+    Truncate,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OverloadConfig {
+    pub enabled: bool,
+    pub queue_size: usize,     // UDP queries waiting for a worker, any more are shed
+    pub workers: usize,        // threads answering the queued UDP queries
+    pub max_recursions: usize, // lookups in flight at once over all transports
+    pub udp_shed: ShedPolicy,
+}
+
+impl Default for OverloadConfig {
+    fn default() -> Self {
+        OverloadConfig {
+            enabled: false,
+            queue_size: 512,
+            workers: 4,
+            max_recursions: 200,
+            udp_shed: ShedPolicy::Servfail,
+        }
+    }
+}
+
+/**
+Load shedding: rather than letting queries pile up behind slow upstreams, the ones over the limits
+are answered at once from the cache, stale entries included, or else with a SERVFAIL. Recursions
+are counted over all transports, a query that would start one more than `max_recursions` gets the
+SERVFAIL. UDP queries go through a bounded queue first, see `serve_udp`.
+*/
+#[derive(Clone, Debug)]
+pub struct Overload {
+    in_flight: Arc<AtomicUsize>,
+    pub max_recursions: usize,
+    pub udp_shed: ShedPolicy,
+}
+
+// A recursion in flight, it stops counting when dropped
+#[derive(Debug)]
+pub struct RecursionPermit(Arc<AtomicUsize>);
+
+impl Drop for RecursionPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Overload {
+    pub fn new(config: &OverloadConfig) -> Overload {
+        Overload { in_flight: Arc::new(AtomicUsize::new(0)), max_recursions: config.max_recursions, udp_shed: config.udp_shed }
+    }
+
+    pub fn try_recursion(&self) -> Option<RecursionPermit> {
+        let permit = RecursionPermit(Arc::clone(&self.in_flight));
+        match self.in_flight.fetch_add(1, Ordering::SeqCst) < self.max_recursions {
+            true => Some(permit),
+            false => None,
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/**
+Answers UDP queries on `config.workers` threads fed from a queue of at most `config.queue_size`
+datagrams, each cut off at `max_len` bytes. When the queue is full the receiving thread hands the query to `shed` instead, which
+has to answer without a lookup, so a burst is turned away right away instead of going stale in a
+backlog. Doesn't return unless the socket fails.
+*/
+pub fn serve_udp<A, S>(socket: UdpSocket, config: &OverloadConfig, max_len: usize, answer: A, shed: S) -> io::Result<()>
+where
+    A: Fn(&UdpSocket, &[u8], SocketAddr) + Send + Sync + 'static,
+    S: Fn(&UdpSocket, &[u8], SocketAddr),
+{
+    let (sender, receiver) = mpsc::sync_channel::<(Vec<u8>, SocketAddr)>(config.queue_size);
+    let (receiver, answer) = (Arc::new(Mutex::new(receiver)), Arc::new(answer));
+    for _ in 0..config.workers.max(1) {
+        let (socket, receiver, answer) = (socket.try_clone()?, Arc::clone(&receiver), Arc::clone(&answer));
+        thread::spawn(move || loop {
+            let next = receiver.lock().unwrap().recv();
+            match next {
+                Ok((query, src)) => answer(&socket, &query, src),
+                Err(_) => return,
+            }
+        });
+    }
+    info!("Answering UDP queries on {} workers, shedding over {} queued", config.workers.max(1), config.queue_size);
+
+    let mut data = vec![0u8; max_len];
+    loop {
+        let (len, src) = match socket.recv_from(&mut data) {
+            Ok(received) => received,
+            Err(e) => {
+                error!("UDP receive failed: {:?}", e);
+                continue;
+            }
+        };
+        match sender.try_send((data[..len].to_vec(), src)) {
+            Ok(()) => {}
+            Err(TrySendError::Full((query, src))) => {
+                debug!("Query queue is full, shedding the query from {}", src);
+                shed(&socket, &query, src);
+            }
+            Err(TrySendError::Disconnected(_)) => return Err(io::Error::other("UDP workers have stopped")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_recursion_limit() {
+        let overload = Overload::new(&OverloadConfig { max_recursions: 2, ..OverloadConfig::default() });
+        let first = overload.try_recursion().unwrap();
+        let _second = overload.try_recursion().unwrap();
+        assert!(overload.try_recursion().is_none());
+        assert_eq!(overload.in_flight(), 2);
+        drop(first);
+        assert!(overload.try_recursion().is_some());
+        assert_eq!(overload.in_flight(), 1);
+    }
+
+    #[test]
+    fn test_full_queue_is_shed() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let config = OverloadConfig { queue_size: 1, workers: 1, ..OverloadConfig::default() };
+        // The one worker blocks on its first query, so the queue fills up behind it
+        let (release, blocked) = channel::<()>();
+        let blocked = Mutex::new(blocked);
+        thread::spawn(move || {
+            serve_udp(server, &config, 512, move |socket, query, src| {
+                let _ = blocked.lock().unwrap().recv();
+                socket.send_to(&[b'a', query[0]], src).unwrap();
+            }, |socket, query, src| {
+                socket.send_to(&[b's', query[0]], src).unwrap();
+            })
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.send_to(&[1], addr).unwrap();
+        // Give the worker time to take the first query off the queue
+        thread::sleep(Duration::from_millis(100));
+        client.send_to(&[2], addr).unwrap();
+        client.send_to(&[3], addr).unwrap();
+
+        let mut data = [0u8; 2];
+        client.recv(&mut data).unwrap();
+        assert_eq!(data, [b's', 3]);
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        client.recv(&mut data).unwrap();
+        assert_eq!(data, [b'a', 1]);
+        client.recv(&mut data).unwrap();
+        assert_eq!(data, [b'a', 2]);
+    }
+}
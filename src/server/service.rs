@@ -0,0 +1,191 @@
+use std::ffi::OsString;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+use std::sync::{mpsc, OnceLock};
+use std::time::Duration;
+use std::{env, thread};
+
+use flexi_logger::writers::LogWriter;
+use flexi_logger::DeferredNow;
+use log::{Level, LevelFilter, Record};
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo, ServiceStartType,
+    ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+    EVENTLOG_WARNING_TYPE,
+};
+
+const SERVICE_NAME: &str = "r_dns";
+const DISPLAY_NAME: &str = "R_DNS DNS server";
+
+fn to_io(e: windows_service::Error) -> io::Error {
+    match e {
+        windows_service::Error::Winapi(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+fn wide(text: &str) -> Vec<u16> {
+    std::ffi::OsStr::new(text).encode_wide().chain(Some(0)).collect()
+}
+
+/**
+Warnings and errors go to the Windows event log under the service's name, where a service
+without a console is expected to report them. Everything else stays in the log files, a line
+per query would bury the events that matter.
+*/
+pub struct EventLog {
+    source: HANDLE,
+}
+
+impl EventLog {
+    pub fn register() -> io::Result<EventLog> {
+        let source = unsafe { RegisterEventSourceW(ptr::null(), wide(SERVICE_NAME).as_ptr()) };
+        if source == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EventLog { source })
+    }
+
+    fn report(&self, level: Level, message: &str) {
+        let kind = match level {
+            Level::Error => EVENTLOG_ERROR_TYPE,
+            Level::Warn => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let message = wide(message);
+        let strings = [message.as_ptr()];
+        unsafe {
+            ReportEventW(self.source, kind, 0, 0, ptr::null_mut(), 1, 0, strings.as_ptr(), ptr::null());
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.source);
+        }
+    }
+}
+
+impl LogWriter for EventLog {
+    fn write(&self, _now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+        self.report(record.level(), &format!("{}: {}", record.target(), record.args()));
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn max_log_level(&self) -> LevelFilter {
+        LevelFilter::Warn
+    }
+}
+
+// What the service runs, the same server `main` starts from a console
+static SERVE: OnceLock<fn() -> io::Result<()>> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        if let Ok(event_log) = EventLog::register() {
+            event_log.report(Level::Error, &format!("{} stopped: {}", DISPLAY_NAME, e));
+        }
+    }
+}
+
+fn set_state(status: &ServiceStatusHandle, state: ServiceState, exit_code: u32) -> io::Result<()> {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+    status.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::ServiceSpecific(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(5),
+        process_id: None,
+    }).map_err(to_io)
+}
+
+/*
+Runs the server on its own thread until the Service Control Manager asks it to stop or it fails.
+The server has nothing to flush on the way out, the process ends as soon as the service is
+reported stopped and the dispatcher returns.
+*/
+fn run_service() -> io::Result<()> {
+    let (done, stopped) = mpsc::channel();
+    let control = done.clone();
+    let status = service_control_handler::register(SERVICE_NAME, move |event| match event {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = control.send(Ok(()));
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    }).map_err(to_io)?;
+
+    // Services start in System32, the config, cache and logs are next to the executable
+    if let Some(dir) = env::current_exe()?.parent() {
+        env::set_current_dir(dir)?;
+    }
+    let serve = *SERVE.get().ok_or_else(|| io::Error::other("Service started without a server"))?;
+    set_state(&status, ServiceState::Running, 0)?;
+    thread::spawn(move || {
+        let _ = done.send(serve());
+    });
+
+    let result = stopped.recv().unwrap_or(Ok(()));
+    set_state(&status, ServiceState::StopPending, 0)?;
+    set_state(&status, ServiceState::Stopped, result.is_err() as u32)?;
+    result
+}
+
+// Hands the process over to the Service Control Manager, only works when it started us
+pub fn run(serve: fn() -> io::Result<()>) -> io::Result<()> {
+    let _ = SERVE.set(serve);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(to_io)
+}
+
+// Registers this executable to start with Windows, `--service` is how it knows it runs as one
+pub fn install() -> io::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)
+        .map_err(to_io)?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(DISPLAY_NAME),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: env::current_exe()?,
+        launch_arguments: vec![OsString::from("--service")],
+        dependencies: vec![],
+        account_name: None, // LocalSystem
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG).map_err(to_io)?;
+    service.set_description("Caching DNS resolver and authoritative server").map_err(to_io)
+}
+
+pub fn uninstall() -> io::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT).map_err(to_io)?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)
+        .map_err(to_io)?;
+    if service.query_status().map_err(to_io)?.current_state != ServiceState::Stopped {
+        service.stop().map_err(to_io)?;
+    }
+    // The service is gone once every handle to it is closed
+    service.delete().map_err(to_io)
+}
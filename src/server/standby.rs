@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::cache::cache::{DnsCache, ThreadSafeDnsCache};
+use crate::cache::dump;
+use crate::local::zone::{Zone, Zones};
+use crate::server::admin;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct StandbyConfig {
+    pub enabled: bool,
+    pub primary: String, // the primary's [http] listen address, e.g. "10.0.0.1:8053"
+    pub token: String,   // the primary's admin_token
+    pub interval: u64,   // seconds between copies
+}
+
+impl Default for StandbyConfig {
+    fn default() -> Self {
+        StandbyConfig {
+            enabled: false,
+            primary: String::new(),
+            token: String::new(),
+            interval: 5,
+        }
+    }
+}
+
+/**
+The standby half of a primary/standby pair: every `interval` seconds it copies what changed in
+the primary's cache since the last copy, and every zone whose serial differs from ours, over the
+primary's admin API. The standby answers queries all along, so taking over is only a matter of
+sending clients to it, by moving the service address or listing it as their second server, and
+it starts with the primary's cache rather than an empty one. Zones the primary dropped are
+dropped here too, unless they are configured here as well.
+*/
+struct Standby {
+    config: StandbyConfig,
+    cache: ThreadSafeDnsCache,
+    zones: Zones,
+    since: u64,              // the primary's clock at the last copy of its cache
+    copied: HashSet<String>, // zones served only because the primary has them
+    reachable: bool,
+}
+
+impl Standby {
+    // The body of a successful GET, None for a 404
+    fn get(&self, target: &str) -> io::Result<Option<String>> {
+        match admin::request(&self.config.primary, &self.config.token, "GET", target, "")? {
+            (200, body) => Ok(Some(body)),
+            (404, _) => Ok(None),
+            (status, body) => Err(io::Error::other(format!("{} answered {} {}", target, status, body.trim()))),
+        }
+    }
+
+    // Returns how many entries were copied. Newer answers replace the ones we have.
+    fn copy_cache(&mut self) -> io::Result<usize> {
+        let Some(text) = self.get(&format!("/cache?since={}", self.since))? else {
+            return Ok(0);
+        };
+        let at = text.lines().next().and_then(|line| line.strip_prefix(dump::TIME_PREFIX)).and_then(|at| at.trim().parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The cache dump doesn't say when it was taken"))?;
+        let mut received = DnsCache::with_clock(usize::MAX, self.cache.clock());
+        let count = dump::import(&mut received, &text)?;
+        for (key, entry) in received.cache {
+            if self.cache.peek(&key).is_some() {
+                self.cache.update(&key, &entry.packet, entry.ttl)?;
+            } else {
+                self.cache.insert(key, entry)?;
+            }
+        }
+        self.since = at;
+        Ok(count)
+    }
+
+    fn copy_zones(&mut self) -> io::Result<()> {
+        // A primary without zones has nothing at /zones
+        let listed: HashMap<String, u32> = match self.get("/zones")? {
+            Some(list) => serde_json::from_str::<Vec<serde_json::Value>>(&list)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+                .iter()
+                .filter_map(|zone| Some((zone["zone"].as_str()?.to_string(), u32::try_from(zone["serial"].as_u64()?).ok()?)))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        for removed in self.copied.iter().filter(|zone| !listed.contains_key(*zone)) {
+            self.zones.remove(removed);
+            info!("Zone {} is gone from the primary, no longer serving it", removed);
+        }
+        self.copied.retain(|zone| listed.contains_key(zone));
+
+        for (zone, serial) in listed {
+            let ours = self.zones.serial(&zone);
+            if ours == Some(serial) {
+                continue;
+            }
+            let copy = match self.get(&format!("/zones/{}", zone))? {
+                Some(text) => Zone::parse(&zone, &text),
+                None => continue,
+            };
+            match copy.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)).and_then(|copy| self.zones.install(copy)) {
+                Ok(()) => {
+                    info!("Copied zone {} from the primary, serial {}", zone, serial);
+                    if ours.is_none() {
+                        self.copied.insert(zone);
+                    }
+                }
+                Err(e) => warn!("Failed to copy zone {} from the primary: {}", zone, e),
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) {
+        let result = self.copy_cache().and_then(|count| {
+            debug!("Copied {} cache entries from the primary", count);
+            self.copy_zones()
+        });
+        // Said once rather than every interval while the primary is down
+        match result {
+            Ok(()) if !self.reachable => {
+                info!("Copying from the primary {} again", self.config.primary);
+                self.reachable = true;
+            }
+            Err(e) if self.reachable => {
+                warn!("Failed to copy from the primary {}: {}", self.config.primary, e);
+                self.reachable = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn start(config: &StandbyConfig, cache: ThreadSafeDnsCache, zones: Zones) {
+    let mut standby = Standby { config: config.clone(), cache, zones, since: 0, copied: HashSet::new(), reachable: true };
+    let interval = Duration::from_secs(config.interval.max(1));
+    info!("Standby for {}, copying its cache and zones every {:?}", config.primary, interval);
+    thread::spawn(move || loop {
+        standby.sync();
+        thread::sleep(interval);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    use crate::cache::clock::ManualClock;
+    use crate::cache::key::CacheKey;
+    use crate::server::http::{HttpRequest, HttpResponse};
+    use crate::utils::query_type::QueryType;
+    use crate::utils::record::DnsRecord;
+
+    const NOW: u64 = 1_700_000_000;
+
+    // The bodies a primary's admin API serves, by target; targets without one get a 404
+    type Pages = Arc<Mutex<HashMap<String, String>>>;
+
+    fn fake_primary() -> (String, Pages) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let pages = Pages::default();
+        thread::spawn({
+            let pages = Arc::clone(&pages);
+            move || {
+                for mut stream in listener.incoming().map(Result::unwrap) {
+                    let request = HttpRequest::read(&mut BufReader::new(&stream)).unwrap();
+                    let target = match request.param("since") {
+                        Some(since) => format!("{}?since={}", request.path, since),
+                        None => request.path.clone(),
+                    };
+                    let response = match pages.lock().unwrap().get(&target) {
+                        Some(body) => HttpResponse::new(200, "text/plain", body.clone().into_bytes()),
+                        None => HttpResponse::new(404, "text/plain", Vec::new()),
+                    };
+                    response.write(&mut stream).unwrap();
+                }
+            }
+        });
+        (addr, pages)
+    }
+
+    fn zone(origin: &str, serial: u32, addr: u8) -> String {
+        format!("{0}. 3600 IN SOA ns.{0}. admin.{0}. {1} 3600 600 86400 300\nwww.{0}. 300 IN A 192.0.2.{2}\n", origin, serial, addr)
+    }
+
+    fn cached(standby: &Standby, name: &str) -> Vec<DnsRecord> {
+        standby.cache.peek(&CacheKey::new(name, QueryType::A)).map(|entry| entry.packet.answers.clone()).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_sync_from_primary() {
+        let (primary, pages) = fake_primary();
+        let clock = Arc::new(ManualClock::new(NOW));
+        let cache = ThreadSafeDnsCache::in_memory(DnsCache::with_clock(64, clock));
+        let zones = Zones::from_zones(vec![Zone::parse("own.lan", &zone("own.lan", 1, 1)).unwrap()]);
+        let config = StandbyConfig { enabled: true, primary, ..StandbyConfig::default() };
+        let mut standby = Standby { config, cache, zones, since: 0, copied: HashSet::new(), reachable: true };
+
+        let dump = |at: u64, addr: u8| format!("{}{}\n;www.example.com.\tIN\tA\t; NOERROR\nwww.example.com.\t300\tIN\tA\t192.0.2.{}\n\n", dump::TIME_PREFIX, at, addr);
+        pages.lock().unwrap().extend([
+            ("/cache?since=0".to_string(), dump(NOW, 1)),
+            ("/zones".to_string(), r#"[{"zone": "copied.lan", "serial": 1}]"#.to_string()),
+            ("/zones/copied.lan".to_string(), zone("copied.lan", 1, 1)),
+        ]);
+        standby.sync();
+        assert!(standby.reachable);
+        assert_eq!(standby.since, NOW);
+        assert_eq!(cached(&standby, "www.example.com"), vec![DnsRecord::a("www.example.com", Ipv4Addr::new(192, 0, 2, 1), 300)]);
+        assert_eq!(standby.zones.serial("copied.lan"), Some(1));
+        assert_eq!(standby.zones.serial("own.lan"), Some(1));
+
+        // Only what changed since the last copy is asked for, a newer answer replaces ours and a
+        // zone with another serial is copied again
+        pages.lock().unwrap().extend([
+            (format!("/cache?since={}", NOW), dump(NOW + 5, 2)),
+            ("/zones".to_string(), r#"[{"zone": "copied.lan", "serial": 2}]"#.to_string()),
+            ("/zones/copied.lan".to_string(), zone("copied.lan", 2, 2)),
+        ]);
+        standby.sync();
+        assert_eq!(standby.since, NOW + 5);
+        assert_eq!(cached(&standby, "www.example.com"), vec![DnsRecord::a("www.example.com", Ipv4Addr::new(192, 0, 2, 2), 300)]);
+        assert_eq!(standby.zones.serial("copied.lan"), Some(2));
+        assert!(standby.zones.text("copied.lan").unwrap().contains("192.0.2.2"));
+
+        // A zone the primary dropped goes, ours stays even though the primary never had it
+        pages.lock().unwrap().insert("/zones".to_string(), "[]".to_string());
+        standby.sync();
+        assert_eq!(standby.zones.serial("copied.lan"), None);
+        assert_eq!(standby.zones.serial("own.lan"), Some(1));
+        assert!(standby.copied.is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_primary() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let primary = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let cache = ThreadSafeDnsCache::in_memory(DnsCache::with_clock(64, Arc::new(ManualClock::new(NOW))));
+        let config = StandbyConfig { enabled: true, primary, ..StandbyConfig::default() };
+        let mut standby = Standby { config, cache, zones: Zones::default(), since: 0, copied: HashSet::new(), reachable: true };
+        standby.sync();
+        assert!(!standby.reachable);
+        assert_eq!(standby.since, 0);
+    }
+}
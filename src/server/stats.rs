@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::filter::nxdomain_guard::NxdomainGuard;
+use crate::local::pool::{MemberHealth, Pools};
+use crate::server::handler::QueryHandler;
+use crate::server::http::HttpResponse;
+use crate::utils::memory::MemoryUsage;
+
+// Saved next to the cache by its save thread
+pub const STATS_PATH: &str = "dns_stats.toml";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Totals {
+    pub queries: u64,
+    pub blocked: u64,       // answered by policy instead: rebinding filter, cookie rate limit
+    pub cache_hits: u64,
+    pub shed: u64,          // answered without a lookup because the server was overloaded
+    pub limited: u64,       // answered without a lookup because the NXDOMAIN guard held it back
+    pub spans_dropped: u64, // telemetry spans left out because the export queue was full
+}
+
+/**
+Cumulative query counters. The totals are saved with the cache and added back at startup, so
+they count from the first run rather than from the last restart.
+*/
+#[derive(Debug, Default)]
+pub struct Counters {
+    queries: AtomicU64,
+    blocked: AtomicU64,
+    cache_hits: AtomicU64,
+    shed: AtomicU64,
+    limited: AtomicU64,
+    spans_dropped: AtomicU64,
+}
+
+impl Counters {
+    pub fn query(&self) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn blocked(&self) {
+        self.blocked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn shed(&self) {
+        self.shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn limited(&self) {
+        self.limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn span_dropped(&self) {
+        self.spans_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> Totals {
+        Totals {
+            queries: self.queries.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            shed: self.shed.load(Ordering::Relaxed),
+            limited: self.limited.load(Ordering::Relaxed),
+            spans_dropped: self.spans_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    // Added rather than set, queries answered before the file was read still count
+    pub fn restore(&self, totals: Totals) {
+        self.queries.fetch_add(totals.queries, Ordering::Relaxed);
+        self.blocked.fetch_add(totals.blocked, Ordering::Relaxed);
+        self.cache_hits.fetch_add(totals.cache_hits, Ordering::Relaxed);
+        self.shed.fetch_add(totals.shed, Ordering::Relaxed);
+        self.limited.fetch_add(totals.limited, Ordering::Relaxed);
+        self.spans_dropped.fetch_add(totals.spans_dropped, Ordering::Relaxed);
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, toml::to_string(&self.totals()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+    }
+
+    pub fn load(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let totals = toml::from_str(&fs::read_to_string(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.restore(totals);
+        Ok(())
+    }
+}
+
+pub fn counters() -> &'static Counters {
+    static COUNTERS: OnceLock<Counters> = OnceLock::new();
+    COUNTERS.get_or_init(Counters::default)
+}
+
+/*
+Served on GET /stats:
+
+{"pools": {"app.example.lan": [{"addr": "192.0.2.1", "disabled": false, "up": true,
+ "failures": 0, "successes": 12, "last_error": null}]},
+ "attacked_zones": ["victim.example"], "memory": {"cache": {"entries": 850, "bytes": 412000},
+ "hosts": {"entries": 120000, "bytes": 9600000}, "zones": {"entries": 0, "bytes": 0}, "total_bytes": 10012000},
+ "queries": 1200, "blocked": 3, "cache_hits": 870, "shed": 0, "limited": 14}
+*/
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub pools: BTreeMap<String, Vec<MemberStats>>,
+    pub attacked_zones: Vec<String>, // zones the NXDOMAIN guard is limiting lookups for
+    pub memory: MemoryStats,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+// Estimated heap use of what the configuration makes the server hold, see `HeapSize`
+#[derive(Debug, Default, Serialize)]
+pub struct MemoryStats {
+    pub cache: MemoryUsage, // entries are cached answers
+    pub hosts: MemoryUsage, // names from the hosts files, blocklists included
+    pub zones: MemoryUsage, // records of the local zones
+    pub total_bytes: usize,
+}
+
+impl MemoryStats {
+    pub fn collect(handler: &QueryHandler) -> MemoryStats {
+        let mut stats = MemoryStats {
+            cache: handler.cache.memory(),
+            hosts: handler.hosts.as_ref().map(|hosts| hosts.table.read().unwrap().memory()).unwrap_or_default(),
+            zones: handler.zones.as_ref().map(|zones| zones.memory()).unwrap_or_default(),
+            total_bytes: 0,
+        };
+        stats.total_bytes = stats.cache.bytes + stats.hosts.bytes + stats.zones.bytes;
+        stats
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberStats {
+    pub addr: String,
+    pub disabled: bool,
+    #[serde(flatten)]
+    pub health: MemberHealth,
+}
+
+impl Stats {
+    pub fn collect(pools: Option<&Pools>, guard: Option<&NxdomainGuard>) -> Stats {
+        let pools = pools.iter().flat_map(|pools| pools.pools.values()).map(|pool| {
+            let members = pool.members.iter().map(|member| MemberStats {
+                addr: member.addr.to_string(),
+                disabled: member.disabled,
+                health: member.health.lock().unwrap().clone(),
+            }).collect();
+            (pool.name.clone(), members)
+        }).collect();
+        Stats { pools, attacked_zones: guard.map(NxdomainGuard::attacked_zones).unwrap_or_default(), memory: MemoryStats::default(), totals: counters().totals() }
+    }
+}
+
+pub fn handle(handler: &QueryHandler) -> HttpResponse {
+    let mut stats = Stats::collect(handler.pools.as_ref(), handler.nxdomain_guard.as_ref());
+    stats.memory = MemoryStats::collect(handler);
+    let body = serde_json::to_vec(&stats).unwrap();
+    HttpResponse::new(200, "application/json", body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::pool::{PoolConfig, PoolMemberConfig};
+
+    #[test]
+    fn test_pool_stats() {
+        let pools = Pools::new(&[PoolConfig {
+            name: "app.lan".to_string(),
+            members: vec![PoolMemberConfig { addr: "192.0.2.1".to_string(), ..Default::default() }],
+            ..Default::default()
+        }]).unwrap();
+        let pool = &pools.pools["app.lan"];
+        pool.record_check(&pool.members[0], Err("connection refused".to_string()), 1, 1);
+
+        let json = serde_json::to_value(Stats::collect(Some(&pools), None)).unwrap();
+        let member = &json["pools"]["app.lan"][0];
+        assert_eq!(member["addr"], "192.0.2.1");
+        assert_eq!(member["up"], false);
+        assert_eq!(member["last_error"], "connection refused");
+        let json = serde_json::to_value(Stats::collect(None, None)).unwrap();
+        assert_eq!((&json["pools"], &json["attacked_zones"]), (&serde_json::json!({}), &serde_json::json!([])));
+        assert!(json["queries"].is_u64());
+    }
+
+    #[test]
+    fn test_counters_persist() {
+        let saved = Counters::default();
+        saved.query();
+        saved.query();
+        saved.cache_hit();
+        let path = std::env::temp_dir().join(format!("r_dns_stats_{}.toml", std::process::id()));
+        saved.save(&path).unwrap();
+
+        let restarted = Counters::default();
+        restarted.query();
+        restarted.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(restarted.totals(), Totals { queries: 3, cache_hits: 1, ..Totals::default() });
+    }
+}
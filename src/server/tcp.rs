@@ -0,0 +1,296 @@
+use std::io::{self, Read};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+
+use crate::resolver::recursive::write_message;
+use crate::server::handler::QueryHandler;
+use crate::utils::edns::{EdnsOption, OPTION_KEEPALIVE};
+use crate::utils::packet::DnsPacket;
+use crate::utils::wire::write_large_message;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TcpConfig {
+    pub enabled: bool,
+    pub listen: String,
+    pub max_connections: usize, // open at once, connections over it are closed right away
+    pub max_queries: usize,     // per connection before we close it, 0 for no limit
+    pub idle_timeout: u64,      // milliseconds a client has to send its next whole query
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            enabled: false,
+            listen: "0.0.0.0:2053".to_string(),
+            max_connections: 150,
+            max_queries: 100,
+            idle_timeout: 10_000,
+        }
+    }
+}
+
+impl TcpConfig {
+    // The idle timeout as edns-tcp-keepalive carries it, in units of 100 milliseconds (RFC 7828)
+    fn keepalive_timeout(&self) -> u16 {
+        (self.idle_timeout / 100).min(u16::MAX as u64) as u16
+    }
+}
+
+// Frees a connection's place when its thread ends, however it ends
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Fills `buffer` unless the deadline passes first. The read timeout is shortened to what is left
+// before every read, so a client trickling in a byte at a time can't keep the connection forever.
+fn read_before(stream: &mut TcpStream, buffer: &mut [u8], deadline: Instant) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"));
+        }
+        stream.set_read_timeout(Some(left))?;
+        match stream.read(&mut buffer[filled..]) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(len) => filled += len,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"));
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn read_message_before(stream: &mut TcpStream, deadline: Instant) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    read_before(stream, &mut len, deadline)?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    read_before(stream, &mut message, deadline)?;
+    Ok(message)
+}
+
+// A client that asked with edns-tcp-keepalive is told how long it may keep the connection idle,
+// the option is between it and us so it isn't passed on with the query
+pub fn answer_message<F>(message: &[u8], client: IpAddr, keepalive: u16, answer: &F) -> io::Result<Vec<u8>>
+where
+    F: Fn(DnsPacket, IpAddr) -> io::Result<DnsPacket>,
+{
+    let response = match DnsPacket::from_bytes(message) {
+        Ok(mut request) => {
+            let asked = request.take_option(OPTION_KEEPALIVE).is_some();
+            let mut response = answer(request, client)?;
+            if asked {
+                response.set_option(EdnsOption::new(OPTION_KEEPALIVE, keepalive.to_be_bytes().to_vec()));
+            }
+            response
+        }
+        Err(e) => DnsPacket::format_error(message).ok_or(e)?,
+    };
+    // Unlike over UDP, the whole answer goes out, however large
+    write_large_message(&response)
+}
+
+fn serve_connection<F>(mut stream: TcpStream, config: &TcpConfig, answer: &F) -> io::Result<()>
+where
+    F: Fn(&[u8], IpAddr, u16) -> io::Result<Vec<u8>>,
+{
+    let client = stream.peer_addr()?.ip();
+    let idle_timeout = Duration::from_millis(config.idle_timeout);
+    // A client that doesn't read its answers is given as long as an idle one
+    stream.set_write_timeout(Some(idle_timeout))?;
+    let mut queries = 0;
+    while config.max_queries == 0 || queries < config.max_queries {
+        let message = match read_message_before(&mut stream, Instant::now() + idle_timeout) {
+            Ok(message) => message,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                debug!("Closed idle TCP connection from {}", client);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        queries += 1;
+        // A keepalive of 0 on the last answer tells the client we are about to close (RFC 7828 section 3.3.2)
+        let keepalive = if queries == config.max_queries { 0 } else { config.keepalive_timeout() };
+        write_message(&mut stream, &answer(&message, client, keepalive)?)?;
+    }
+    debug!("Closed TCP connection from {} after {} queries", client, queries);
+    Ok(())
+}
+
+/**
+Serves connections from `listener` under the limits of `config`. `answer` turns each message a
+client sends into the one that goes back, given the keepalive timeout to tell the client; an
+error closes the connection.
+*/
+pub fn listen<F>(listener: TcpListener, config: TcpConfig, answer: F)
+where
+    F: Fn(&[u8], IpAddr, u16) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+{
+    let (config, answer) = (Arc::new(config), Arc::new(answer));
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Failed to accept TCP connection: {:?}", e);
+                continue;
+            }
+        };
+        let slot = ConnectionSlot(Arc::clone(&open));
+        if open.fetch_add(1, Ordering::SeqCst) >= config.max_connections {
+            debug!("Refused TCP connection from {:?}, {} already open", stream.peer_addr(), config.max_connections);
+            continue;
+        }
+        let (config, answer) = (Arc::clone(&config), Arc::clone(&answer));
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(e) = serve_connection(stream, &config, &*answer) {
+                warn!("TCP connection failed: {:?}", e);
+            }
+        });
+    }
+}
+
+/**
+DNS over TCP (RFC 7766), for answers too large for UDP and clients that prefer a connection. Each
+connection gets its own thread, so their number is capped; connections over the cap are closed as
+soon as they are accepted. A connection is closed once it has sent `max_queries` queries or when a
+whole query hasn't arrived within the idle timeout, which is what keeps slowloris-style clients from
+holding on to their threads. Clients sending edns-tcp-keepalive (RFC 7828) are told that timeout.
+*/
+pub fn start(config: &TcpConfig, handler: QueryHandler) -> io::Result<()> {
+    let listener = TcpListener::bind(&config.listen)?;
+    info!("Listening for TCP queries on {}", config.listen);
+    thread::spawn({
+        let config = config.clone();
+        let answer = move |request, client| handler.answer_from(request, Some(client));
+        move || listen(listener, config, move |message, client, keepalive| answer_message(message, client, keepalive, &answer))
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::recursive::read_message;
+    use crate::utils::byte_buffer::ByteBuffer;
+    use crate::utils::wire::read_large_message;
+    use crate::utils::dns_class::DnsClass;
+    use crate::utils::query_type::QueryType;
+    use crate::utils::question::DnsQuestion;
+    use crate::utils::record::DnsRecord;
+    use std::io::Write;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    fn answer(request: DnsPacket, _client: IpAddr) -> io::Result<DnsPacket> {
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        response.answers.push(DnsRecord::A {
+            domain: request.questions[0].name.clone(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+            class: DnsClass::IN,
+        });
+        // Like the handler, an answer to an EDNS query has an OPT record
+        if request.opt().is_some() {
+            response.resources.push(DnsRecord::OPT { udp_size: 512, flags: 0, options: Vec::new() });
+        }
+        Ok(response)
+    }
+
+    fn create_query(id: u16, keepalive: bool) -> Vec<u8> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = id;
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        if keepalive {
+            packet.resources.push(DnsRecord::OPT { udp_size: 512, flags: 0, options: vec![EdnsOption::new(OPTION_KEEPALIVE, Vec::new())] });
+        }
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.position()].to_vec()
+    }
+
+    fn start_server(config: TcpConfig) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || listen(listener, config, |message, client, keepalive| answer_message(message, client, keepalive, &answer)));
+        addr
+    }
+
+    // Whether the server has closed the connection, rather than left it waiting
+    fn is_closed(stream: &mut TcpStream) -> bool {
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        match stream.read(&mut [0u8; 1]) {
+            Ok(len) => len == 0,
+            Err(e) => !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut),
+        }
+    }
+
+    #[test]
+    fn test_query_limit_and_keepalive() {
+        let addr = start_server(TcpConfig { max_queries: 2, idle_timeout: 2_500, ..TcpConfig::default() });
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        write_message(&mut stream, &create_query(1, true)).unwrap();
+        let response = DnsPacket::from_bytes(&read_message(&mut stream).unwrap()).unwrap();
+        assert_eq!(response.header.id, 1);
+        assert_eq!(response.option(OPTION_KEEPALIVE), Some(&25u16.to_be_bytes()[..]));
+
+        // The last query allowed is answered with a timeout of 0, then the connection is closed
+        write_message(&mut stream, &create_query(2, true)).unwrap();
+        let response = DnsPacket::from_bytes(&read_message(&mut stream).unwrap()).unwrap();
+        assert_eq!(response.option(OPTION_KEEPALIVE), Some(&[0u8, 0][..]));
+        assert!(is_closed(&mut stream));
+    }
+
+    #[test]
+    fn test_connection_limit_and_idle_timeout() {
+        let addr = start_server(TcpConfig { max_connections: 1, idle_timeout: 300, ..TcpConfig::default() });
+        let mut first = TcpStream::connect(addr).unwrap();
+        write_message(&mut first, &create_query(1, false)).unwrap();
+        let response = DnsPacket::from_bytes(&read_message(&mut first).unwrap()).unwrap();
+        assert!(response.opt().is_none());
+
+        let mut second = TcpStream::connect(addr).unwrap();
+        assert!(is_closed(&mut second));
+
+        // Half a length prefix doesn't count as activity, the query has to be whole in time
+        first.write_all(&[0]).unwrap();
+        assert!(is_closed(&mut first));
+    }
+
+    #[test]
+    fn test_large_answer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let large = |request, client| {
+            let mut response = answer(request, client)?;
+            let name = response.questions[0].name.clone();
+            response.answers = (0..100).map(|i| DnsRecord::a(&name, Ipv4Addr::new(192, 0, 2, i), 300)).collect();
+            Ok(response)
+        };
+        thread::spawn(move || listen(listener, TcpConfig::default(), move |message, client, keepalive| answer_message(message, client, keepalive, &large)));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write_message(&mut stream, &create_query(1, false)).unwrap();
+        let message = read_message(&mut stream).unwrap();
+        assert!(message.len() > 512);
+        assert_eq!(read_large_message(&message).unwrap().answers.len(), 100);
+    }
+}
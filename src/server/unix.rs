@@ -0,0 +1,217 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+
+use crate::resolver::recursive::{read_message, write_message};
+use crate::server::handler::QueryHandler;
+use crate::utils::packet::DnsPacket;
+use crate::utils::wire::write_large_message;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct UnixConfig {
+    pub enabled: bool,
+    pub datagram: String, // one query per datagram, like UDP, empty to disable
+    pub stream: String,   // length prefixed messages, like TCP, empty to disable
+}
+
+impl Default for UnixConfig {
+    fn default() -> Self {
+        UnixConfig {
+            enabled: false,
+            datagram: "r_dns.sock".to_string(),
+            stream: "r_dns.stream.sock".to_string(),
+        }
+    }
+}
+
+fn answer_message(message: &[u8], answer: &impl Fn(DnsPacket) -> io::Result<DnsPacket>) -> io::Result<Vec<u8>> {
+    let response = match DnsPacket::from_bytes(message) {
+        Ok(request) => answer(request)?,
+        Err(e) => DnsPacket::format_error(message).ok_or(e)?,
+    };
+    write_large_message(&response)
+}
+
+// A socket left behind by an earlier run would make the bind fail, anything else at the path is kept
+fn remove_stale_socket(path: &str) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn serve_datagram<F>(socket: UnixDatagram, answer: F)
+where
+    F: Fn(DnsPacket) -> io::Result<DnsPacket>,
+{
+    let mut data = [0u8; 4096];
+    loop {
+        let (len, src) = match socket.recv_from(&mut data) {
+            Ok(received) => received,
+            Err(e) => {
+                error!("Unix socket receive failed: {:?}", e);
+                continue;
+            }
+        };
+        // An unbound client socket has no address to send the answer back to
+        let Some(path) = src.as_pathname() else {
+            debug!("Dropped a query from an unbound Unix socket");
+            continue;
+        };
+        match answer_message(&data[..len], &answer) {
+            Ok(reply) => {
+                if let Err(e) = socket.send_to(&reply, path) {
+                    warn!("Unix socket send to {:?} failed: {:?}", path, e);
+                }
+            }
+            Err(e) => warn!("Failed to answer query on the Unix socket: {:?}", e),
+        }
+    }
+}
+
+fn serve_stream<F>(mut stream: UnixStream, answer: &F) -> io::Result<()>
+where
+    F: Fn(DnsPacket) -> io::Result<DnsPacket>,
+{
+    loop {
+        let message = match read_message(&mut stream) {
+            Ok(message) => message,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        write_message(&mut stream, &answer_message(&message, answer)?)?;
+    }
+}
+
+fn listen_stream<F>(listener: UnixListener, answer: F)
+where
+    F: Fn(DnsPacket) -> io::Result<DnsPacket> + Send + Sync + 'static,
+{
+    let answer = Arc::new(answer);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let answer = Arc::clone(&answer);
+                thread::spawn(move || {
+                    if let Err(e) = serve_stream(stream, &*answer) {
+                        warn!("Unix stream connection failed: {:?}", e);
+                    }
+                });
+            }
+            Err(e) => error!("Failed to accept Unix stream connection: {:?}", e),
+        }
+    }
+}
+
+/**
+Queries over Unix domain sockets, for local stubs and sandboxed processes that can't open
+network sockets. The datagram socket works like UDP, datagram clients have to bind their own
+socket to a path to get an answer. The stream socket uses TCP's two byte length framing and
+takes any number of queries per connection. Clients have no IP address, so per-client policies
+such as safe search groups treat them as unknown.
+*/
+pub fn start(config: &UnixConfig, handler: QueryHandler) -> io::Result<()> {
+    let handler = Arc::new(handler);
+    if !config.datagram.is_empty() {
+        remove_stale_socket(&config.datagram)?;
+        let socket = UnixDatagram::bind(&config.datagram)?;
+        info!("Listening on Unix datagram socket {}", config.datagram);
+        let handler = Arc::clone(&handler);
+        thread::spawn(move || serve_datagram(socket, |request| handler.answer(request)));
+    }
+    if !config.stream.is_empty() {
+        remove_stale_socket(&config.stream)?;
+        let listener = UnixListener::bind(Path::new(&config.stream))?;
+        info!("Listening on Unix stream socket {}", config.stream);
+        thread::spawn(move || listen_stream(listener, move |request| handler.answer(request)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::byte_buffer::ByteBuffer;
+    use crate::utils::dns_class::DnsClass;
+    use crate::utils::query_type::QueryType;
+    use crate::utils::question::DnsQuestion;
+    use crate::utils::record::DnsRecord;
+    use std::net::Ipv4Addr;
+    use std::path::PathBuf;
+
+    fn answer(request: DnsPacket) -> io::Result<DnsPacket> {
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        response.questions = request.questions.clone();
+        response.answers.push(DnsRecord::A {
+            domain: request.questions[0].name.clone(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+            class: DnsClass::IN,
+        });
+        Ok(response)
+    }
+
+    fn create_query(id: u16) -> Vec<u8> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = id;
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.position()].to_vec()
+    }
+
+    fn parse(message: &[u8]) -> DnsPacket {
+        DnsPacket::from_buffer(&mut ByteBuffer::from_buffer(message)).unwrap()
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("r_dns_{}_{}.sock", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_stream_queries() {
+        let path = socket_path("stream");
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || listen_stream(listener, answer));
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        for id in [1, 2] {
+            write_message(&mut stream, &create_query(id)).unwrap();
+            let response = parse(&read_message(&mut stream).unwrap());
+            assert_eq!(response.header.id, id);
+            assert_eq!(response.answers.len(), 1);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_datagram_query() {
+        let (server_path, client_path) = (socket_path("datagram"), socket_path("client"));
+        let server = UnixDatagram::bind(&server_path).unwrap();
+        thread::spawn(move || serve_datagram(server, answer));
+
+        let client = UnixDatagram::bind(&client_path).unwrap();
+        client.send_to(&create_query(7), &server_path).unwrap();
+        let mut data = [0u8; 512];
+        let len = client.recv(&mut data).unwrap();
+        assert_eq!(parse(&data[..len]).header.id, 7);
+
+        assert!(remove_stale_socket(server_path.to_str().unwrap()).is_ok());
+        assert!(!server_path.exists());
+        fs::remove_file(&client_path).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+use std::path::Path;
+use std::{fs, io};
+
+use crypto_box::aead::rand_core::RngCore;
+use crypto_box::aead::OsRng;
+use log::info;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// Reads a hex encoded 32 byte secret key, generating and saving a fresh one if the file doesn't exist yet
+pub fn load_or_create_key(path: impl AsRef<Path>) -> io::Result<[u8; 32]> {
+    let path = path.as_ref();
+    match fs::read_to_string(path) {
+        Ok(hex) => from_hex(&hex)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid key in {}", path.display()))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut bytes = [0u8; 32];
+            OsRng.fill_bytes(&mut bytes);
+            fs::write(path, to_hex(&bytes))?;
+            info!("Generated a new key in {}", path.display());
+            Ok(bytes)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10\n"), Some(vec![0, 0xab, 0x10]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_load_or_create_key() {
+        let path = std::env::temp_dir().join(format!("r_dns_key_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let key = load_or_create_key(&path).unwrap();
+        assert_eq!(load_or_create_key(&path).unwrap(), key);
+
+        fs::write(&path, "not a key").unwrap();
+        assert!(load_or_create_key(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::net::IpAddr;
+use std::ops::AddAssign;
+
+use serde::Serialize;
+
+use crate::utils::edns::EdnsOption;
+use crate::utils::packet::DnsPacket;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+use crate::utils::svcb::SvcParam;
+
+/**
+Heap memory owned by a value, not counting the value itself. Allocations are taken at their
+capacity but the allocator's own overhead isn't known, so this is an estimate, good for seeing
+which part of the server grows rather than for matching the process's resident size.
+*/
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for u8 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for IpAddr {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl HeapSize for EdnsOption {
+    fn heap_size(&self) -> usize {
+        self.data.capacity()
+    }
+}
+
+impl HeapSize for SvcParam {
+    fn heap_size(&self) -> usize {
+        self.value.capacity()
+    }
+}
+
+impl HeapSize for DnsQuestion {
+    fn heap_size(&self) -> usize {
+        self.name.heap_size()
+    }
+}
+
+impl HeapSize for DnsRecord {
+    fn heap_size(&self) -> usize {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. } | DnsRecord::A { domain, .. } | DnsRecord::AAAA { domain, .. } => domain.heap_size(),
+            DnsRecord::NS { domain, ns: name, .. }
+            | DnsRecord::CNAME { domain, cname: name, .. }
+            | DnsRecord::DNAME { domain, target: name, .. }
+            | DnsRecord::PTR { domain, host: name, .. }
+            | DnsRecord::MX { domain, exchange: name, .. }
+            | DnsRecord::SRV { domain, target: name, .. } => domain.heap_size() + name.heap_size(),
+            DnsRecord::SOA { domain, mname, rname, .. } => domain.heap_size() + mname.heap_size() + rname.heap_size(),
+            DnsRecord::TXT { domain, data, .. } => domain.heap_size() + data.heap_size(),
+            DnsRecord::HINFO { domain, cpu, os, .. } => domain.heap_size() + cpu.heap_size() + os.heap_size(),
+            DnsRecord::LOC { domain, .. } | DnsRecord::EUI48 { domain, .. } | DnsRecord::EUI64 { domain, .. } => domain.heap_size(),
+            DnsRecord::DHCID { domain, data, .. } => domain.heap_size() + data.heap_size(),
+            DnsRecord::URI { domain, target, .. } => domain.heap_size() + target.heap_size(),
+            DnsRecord::ZONEMD { domain, digest, .. } => domain.heap_size() + digest.heap_size(),
+            DnsRecord::SVCB { domain, target, params, .. } | DnsRecord::HTTPS { domain, target, params, .. } => {
+                domain.heap_size() + target.heap_size() + params.heap_size()
+            }
+            DnsRecord::OPT { options, .. } => options.heap_size(),
+            DnsRecord::DNSKEY { domain, public_key, .. } => domain.heap_size() + public_key.heap_size(),
+            DnsRecord::RRSIG { domain, signer, signature, .. } => domain.heap_size() + signer.heap_size() + signature.heap_size(),
+            DnsRecord::NSEC { domain, next, types, .. } => domain.heap_size() + next.heap_size() + types.capacity() * size_of::<u16>(),
+            DnsRecord::NSEC3 { domain, salt, next, types, .. } => {
+                domain.heap_size() + salt.capacity() + next.capacity() + types.capacity() * size_of::<u16>()
+            }
+        }
+    }
+}
+
+impl HeapSize for DnsPacket {
+    fn heap_size(&self) -> usize {
+        self.questions.heap_size() + self.answers.heap_size() + self.authorities.heap_size() + self.resources.heap_size()
+    }
+}
+
+// A hash map's table, estimated at one control byte per slot, plus what its keys and values own
+pub fn map_heap_size<K: HeapSize, V>(map: &HashMap<K, V>, value_size: impl Fn(&V) -> usize) -> usize {
+    map.capacity() * (size_of::<K>() + size_of::<V>() + 1)
+        + map.iter().map(|(key, value)| key.heap_size() + value_size(value)).sum::<usize>()
+}
+
+// What a part of the server holds, reported by `GET /stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryUsage {
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+impl AddAssign for MemoryUsage {
+    fn add_assign(&mut self, other: MemoryUsage) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::query_type::QueryType;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_record_and_packet_sizes() {
+        let a = DnsRecord::a("example.com", Ipv4Addr::new(192, 0, 2, 1), 300);
+        assert_eq!(a.heap_size(), "example.com".len());
+        let txt = DnsRecord::txt("example.com", &"x".repeat(300), 300);
+        assert_eq!(txt.heap_size(), 11 + 2 * size_of::<Vec<u8>>() + 300);
+
+        let packet = DnsPacket::builder().question(DnsQuestion::new("example.com".to_string(), QueryType::A)).answer(a).build();
+        let slots = packet.questions.capacity() * size_of::<DnsQuestion>() + packet.answers.capacity() * size_of::<DnsRecord>();
+        assert_eq!(packet.heap_size(), slots + 11 + 11);
+
+        let map: HashMap<String, u32> = [("example.com".to_string(), 1)].into_iter().collect();
+        assert!(map_heap_size(&map, |_| 0) >= size_of::<String>() + size_of::<u32>() + 11);
+    }
+}
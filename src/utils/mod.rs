@@ -1,7 +1,8 @@
-pub mod byte_buffer;
-pub mod result_code;
-pub mod record;
-pub mod header;
-pub mod question;
-pub mod query_type;
-pub mod packet;
\ No newline at end of file
+pub use r_dns_core::{builder, byte_buffer, dns_class, edns, error, header, name, nsec, packet, presentation, query_type, question, record, result_code, svcb};
+pub mod key_file;
+pub mod memory;
+pub mod pcap;
+pub mod replay;
+pub mod subnet;
+pub mod random;
+pub mod wire;
@@ -0,0 +1,316 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+// Link types (the LINKTYPE_ values in the file header) of the captures DNS can be taken out of
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+
+// Where DNS is found in a capture, 2053 is where this server listens
+const DNS_PORTS: [u16; 2] = [53, 2053];
+
+const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+const MAGIC_PCAPNG: u32 = 0x0a0d0d0a;
+
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+
+/**
+The frames of a capture in the classic pcap format (what `tcpdump -w` writes), either byte order
+and microsecond or nanosecond timestamps. pcapng files have to be saved as pcap first, e.g. with
+`editcap -F pcap`.
+*/
+#[derive(Debug)]
+pub struct Capture<'a> {
+    pub link_type: u32,
+    pub frames: Vec<&'a [u8]>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<'a> Capture<'a> {
+    pub fn parse(data: &'a [u8]) -> io::Result<Capture<'a>> {
+        let header = data.get(..24).ok_or_else(|| invalid("File is too short for a pcap header"))?;
+        let magic = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let u32_at: fn([u8; 4]) -> u32 = match magic {
+            MAGIC_MICROS | MAGIC_NANOS => u32::from_le_bytes,
+            _ if magic.swap_bytes() == MAGIC_MICROS || magic.swap_bytes() == MAGIC_NANOS => u32::from_be_bytes,
+            MAGIC_PCAPNG => return Err(invalid("pcapng files aren't supported, save the capture as pcap")),
+            _ => return Err(invalid("Not a pcap file")),
+        };
+        let read_u32 = |pos: usize| data.get(pos..pos + 4).map(|bytes| u32_at(bytes.try_into().unwrap()));
+
+        let link_type = read_u32(20).unwrap() & 0xFFFF;
+        let mut frames = Vec::new();
+        let mut pos = 24;
+        while pos < data.len() {
+            let len = read_u32(pos + 8).ok_or_else(|| invalid("Capture ends inside a record header"))? as usize;
+            let frame = data.get(pos + 16..pos + 16 + len).ok_or_else(|| invalid("Capture ends inside a frame"))?;
+            frames.push(frame);
+            pos += 16 + len;
+        }
+        Ok(Capture { link_type, frames })
+    }
+
+    // The DNS messages in the capture, with the number of the frame each came in (from 1, like Wireshark)
+    pub fn dns_messages(&self) -> Vec<(usize, &'a [u8])> {
+        self.frames.iter().enumerate()
+            .filter_map(|(i, frame)| Some((i + 1, dns_payload(self.link_type, frame)?)))
+            .collect()
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..pos + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// The IP packet inside a link layer frame
+fn ip_packet(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+    match link_type {
+        LINKTYPE_RAW => Some(frame),
+        LINKTYPE_NULL => frame.get(4..),
+        LINKTYPE_LINUX_SLL => frame.get(16..),
+        LINKTYPE_ETHERNET => {
+            let mut pos = 12;
+            // 802.1Q tags sit between the addresses and the EtherType
+            while u16_at(frame, pos)? == 0x8100 {
+                pos += 4;
+            }
+            match u16_at(frame, pos)? {
+                0x0800 | 0x86DD => frame.get(pos + 2..),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// The DNS message in a frame: UDP to or from a DNS port, or a TCP segment holding one whole message.
+// Fragments and messages split over several segments are skipped.
+pub fn dns_payload(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+    let packet = ip_packet(link_type, frame)?;
+    let (protocol, transport) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = (packet[0] & 0x0F) as usize * 4;
+            let total_len = u16_at(packet, 2)? as usize;
+            let fragment = u16_at(packet, 6)?;
+            if fragment & 0x3FFF != 0 {
+                return None;
+            }
+            (*packet.get(9)?, packet.get(header_len..total_len.min(packet.len()))?)
+        }
+        6 => {
+            let payload_len = u16_at(packet, 4)? as usize;
+            (*packet.get(6)?, packet.get(40..(40 + payload_len).min(packet.len()))?)
+        }
+        _ => return None,
+    };
+    let ports = [u16_at(transport, 0)?, u16_at(transport, 2)?];
+    if !ports.iter().any(|port| DNS_PORTS.contains(port)) {
+        return None;
+    }
+    match protocol {
+        PROTO_UDP => transport.get(8..),
+        PROTO_TCP => {
+            let data = transport.get((*transport.get(12)? >> 4) as usize * 4..)?;
+            let len = u16_at(data, 0)? as usize;
+            (len > 0 && data.len() == len + 2).then(|| &data[2..])
+        }
+        _ => None,
+    }
+}
+
+// The global header of a capture written by us: little endian, microseconds, raw IP packets
+pub fn file_header() -> [u8; 24] {
+    let mut header = [0u8; 24];
+    header[..4].copy_from_slice(&MAGIC_MICROS.to_le_bytes());
+    header[4..8].copy_from_slice(&[2, 0, 4, 0]); // version 2.4
+    header[16..20].copy_from_slice(&65535u32.to_le_bytes()); // snapshot length
+    header[20..24].copy_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    header
+}
+
+// One record of a capture, `time` since the Unix epoch
+pub fn record(time: Duration, packet: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(16 + packet.len());
+    record.extend((time.as_secs() as u32).to_le_bytes());
+    record.extend(time.subsec_micros().to_le_bytes());
+    record.extend((packet.len() as u32).to_le_bytes());
+    record.extend((packet.len() as u32).to_le_bytes());
+    record.extend(packet);
+    record
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32).sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/**
+An IP packet with a UDP datagram from `src` to `dst`, as it would have been on the wire. When the
+two aren't of the same family (a v4 client of a socket bound to `[::]`), the server side is
+written as the unspecified address of the client's family.
+*/
+pub fn udp_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend(src.port().to_be_bytes());
+    udp.extend(dst.port().to_be_bytes());
+    udp.extend(udp_len.to_be_bytes());
+    udp.extend([0, 0]);
+    udp.extend(payload);
+
+    let unspecified = |ip: IpAddr| match ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let (src_ip, dst_ip) = match (src.ip(), dst.ip()) {
+        (src @ IpAddr::V4(_), dst @ IpAddr::V4(_)) | (src @ IpAddr::V6(_), dst @ IpAddr::V6(_)) => (src, dst),
+        (src, dst) if src.is_unspecified() => (unspecified(dst), dst),
+        (src, _) => (src, unspecified(src)),
+    };
+
+    // The UDP checksum covers a pseudo header of addresses, protocol and length (RFC 768, RFC 8200 section 8.1)
+    let mut pseudo = Vec::new();
+    match (src_ip, dst_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            pseudo.extend(src.octets());
+            pseudo.extend(dst.octets());
+            pseudo.extend([0, PROTO_UDP]);
+            pseudo.extend(udp_len.to_be_bytes());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            pseudo.extend(src.octets());
+            pseudo.extend(dst.octets());
+            pseudo.extend((udp_len as u32).to_be_bytes());
+            pseudo.extend([0, 0, 0, PROTO_UDP]);
+        }
+        _ => unreachable!(),
+    }
+    pseudo.extend(&udp);
+    let sum = match checksum(&pseudo) {
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    udp[6..8].copy_from_slice(&sum.to_be_bytes());
+
+    let mut packet = Vec::with_capacity(40 + udp.len());
+    match (src_ip, dst_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let total_len = (20 + udp.len()) as u16;
+            packet.extend([0x45, 0]);
+            packet.extend(total_len.to_be_bytes());
+            packet.extend([0, 0, 0x40, 0, 64, PROTO_UDP, 0, 0]); // don't fragment, TTL 64
+            packet.extend(src.octets());
+            packet.extend(dst.octets());
+            let sum = checksum(&packet);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            packet.extend([0x60, 0, 0, 0]);
+            packet.extend((udp.len() as u16).to_be_bytes());
+            packet.extend([PROTO_UDP, 64]);
+            packet.extend(src.octets());
+            packet.extend(dst.octets());
+        }
+        _ => unreachable!(),
+    }
+    packet.extend(udp);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An Ethernet frame with an IPv4 UDP datagram from 192.0.2.1:5300 to 192.0.2.53:53
+    fn udp_frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend([0x08, 0x00]);
+        let total_len = (20 + 8 + payload.len()) as u16;
+        frame.extend([0x45, 0, (total_len >> 8) as u8, total_len as u8, 0, 0, 0x40, 0, 64, PROTO_UDP, 0, 0]);
+        frame.extend([192, 0, 2, 1, 192, 0, 2, 53]);
+        let udp_len = (8 + payload.len()) as u16;
+        frame.extend([0x14, 0xb4, 0, 53, (udp_len >> 8) as u8, udp_len as u8, 0, 0]);
+        frame.extend(payload);
+        frame
+    }
+
+    fn capture(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(MAGIC_MICROS.to_le_bytes());
+        data.extend([2, 0, 4, 0]);
+        data.extend([0u8; 8]);
+        data.extend(65535u32.to_le_bytes());
+        data.extend(LINKTYPE_ETHERNET.to_le_bytes());
+        for frame in frames {
+            data.extend([0u8; 8]);
+            data.extend((frame.len() as u32).to_le_bytes());
+            data.extend((frame.len() as u32).to_le_bytes());
+            data.extend(frame);
+        }
+        data
+    }
+
+    #[test]
+    fn test_dns_messages() {
+        let mut other = udp_frame(b"not dns");
+        other[36..38].copy_from_slice(&[0, 123]); // to the NTP port instead
+        let data = capture(&[udp_frame(b"first"), other, udp_frame(b"second")]);
+
+        let capture = Capture::parse(&data).unwrap();
+        assert_eq!(capture.frames.len(), 3);
+        assert_eq!(capture.dns_messages(), vec![(1, &b"first"[..]), (3, &b"second"[..])]);
+    }
+
+    #[test]
+    fn test_tcp_payload() {
+        let mut frame = udp_frame(&[]);
+        frame[23] = PROTO_TCP;
+        frame.truncate(34);
+        frame.extend([0xd4, 0x31, 0, 53, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0, 0, 0, 0, 0, 0]);
+        frame.extend([0, 3, 1, 2, 3]);
+        let total_len = (frame.len() - 14) as u16;
+        frame[16..18].copy_from_slice(&total_len.to_be_bytes());
+        assert_eq!(dns_payload(LINKTYPE_ETHERNET, &frame), Some(&[1, 2, 3][..]));
+
+        // Half a message, the rest comes in the next segment
+        frame[55] = 9;
+        assert_eq!(dns_payload(LINKTYPE_ETHERNET, &frame), None);
+    }
+
+    #[test]
+    fn test_written_capture_reads_back() {
+        let client: SocketAddr = "192.0.2.1:5300".parse().unwrap();
+        let server: SocketAddr = "192.0.2.53:53".parse().unwrap();
+        let mut data = file_header().to_vec();
+        let query = udp_packet(client, server, b"query");
+        data.extend(record(Duration::from_secs(1_700_000_000), &query));
+        data.extend(record(Duration::from_secs(1_700_000_001), &udp_packet("[2001:db8::1]:5300".parse().unwrap(), "[2001:db8::53]:53".parse().unwrap(), b"v6")));
+        // The server side of a dual-stack socket, written in the client's family
+        data.extend(record(Duration::from_secs(1_700_000_002), &udp_packet("[::]:53".parse().unwrap(), client, b"reply")));
+
+        let capture = Capture::parse(&data).unwrap();
+        assert_eq!(capture.link_type, LINKTYPE_RAW);
+        assert_eq!(capture.dns_messages(), vec![(1, &b"query"[..]), (2, &b"v6"[..]), (3, &b"reply"[..])]);
+        // A header with a correct checksum sums to zero
+        assert_eq!(checksum(&query[..20]), 0);
+        assert_eq!(&capture.frames[2][12..16], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_not_pcap() {
+        assert!(Capture::parse(b"not a capture at all, just text").is_err());
+        let mut pcapng = capture(&[]);
+        pcapng[..4].copy_from_slice(&MAGIC_PCAPNG.to_le_bytes());
+        assert!(Capture::parse(&pcapng).unwrap_err().to_string().contains("pcapng"));
+    }
+}
@@ -0,0 +1,7 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// Not cryptographic, but every RandomState is seeded differently, which is plenty for picking servers
+pub fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
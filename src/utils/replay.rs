@@ -0,0 +1,94 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::utils::byte_buffer::ByteBuffer;
+use crate::utils::key_file::to_hex;
+use crate::utils::packet::DnsPacket;
+use crate::utils::pcap::Capture;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub messages: usize,
+    pub parse_failures: usize,
+    pub mismatches: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Problem {
+    Parse(String),
+    Mismatch(String),
+}
+
+// Parses `message` the way the server would, then writes it out again and parses that: anything
+// lost or changed on the way is a bug in one direction or the other
+pub fn check(message: &[u8]) -> Result<(), Problem> {
+    let packet = DnsPacket::from_bytes(message).map_err(|e| Problem::Parse(e.to_string()))?;
+    let mut buffer = ByteBuffer::new();
+    packet.write(&mut buffer).map_err(|e| Problem::Mismatch(format!("re-encoding failed: {}", e)))?;
+    let reparsed = DnsPacket::from_bytes(&buffer.buffer[..buffer.position()])
+        .map_err(|e| Problem::Mismatch(format!("the re-encoded message doesn't parse: {}", e)))?;
+    if reparsed != packet {
+        return Err(Problem::Mismatch("the re-encoded message parses differently".to_string()));
+    }
+    Ok(())
+}
+
+/**
+Runs every DNS message in a pcap capture through `check`, printing one line per problem to `out`.
+With a `corpus` directory, each problem message is also saved there as `frame-<n>.hex`, the input
+`r_dns decode` takes, so real traffic that broke the parser can become a regression test.
+*/
+pub fn replay(capture: &Capture, corpus: Option<&Path>, out: &mut impl Write) -> io::Result<Report> {
+    if let Some(corpus) = corpus {
+        fs::create_dir_all(corpus)?;
+    }
+    let mut report = Report::default();
+    for (frame, message) in capture.dns_messages() {
+        report.messages += 1;
+        let problem = match check(message) {
+            Ok(()) => continue,
+            Err(Problem::Parse(e)) => {
+                report.parse_failures += 1;
+                format!("parse failed: {}", e)
+            }
+            Err(Problem::Mismatch(e)) => {
+                report.mismatches += 1;
+                e
+            }
+        };
+        writeln!(out, "frame {}: {}", frame, problem)?;
+        if let Some(corpus) = corpus {
+            fs::write(corpus.join(format!("frame-{}.hex", frame)), to_hex(message) + "\n")?;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::query_type::QueryType;
+    use crate::utils::question::DnsQuestion;
+
+    fn query() -> Vec<u8> {
+        let mut packet = DnsPacket::new();
+        packet.questions.push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.position()].to_vec()
+    }
+
+    #[test]
+    fn test_check() {
+        let message = query();
+        assert_eq!(check(&message), Ok(()));
+        assert!(matches!(check(&message[..5]), Err(Problem::Parse(_))));
+
+        // The writer leaves out records of types it doesn't know
+        let mut message = query();
+        message[7] = 1;
+        message.extend([0xC0, 0x0C, 0, 99, 0, 1, 0, 0, 0, 60, 0, 2, 0xAB, 0xCD]);
+        assert!(matches!(check(&message), Err(Problem::Mismatch(_))));
+    }
+}
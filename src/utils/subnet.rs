@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+// An address range in CIDR notation ("192.0.2.0/24", "2001:db8::/32"). A bare address is a single host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Subnet {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl Subnet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip.to_ipv4_mapped().is_some_and(|ip| self.contains(IpAddr::V4(ip))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+
+    // The same subnet with the host bits of its address cleared
+    pub fn network(&self) -> Subnet {
+        let addr = match self.addr {
+            IpAddr::V4(addr) => IpAddr::V4((u32::from(addr) & u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)).into()),
+            IpAddr::V6(addr) => IpAddr::V6((u128::from(addr) & u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0)).into()),
+        };
+        Subnet { addr, prefix: self.prefix }
+    }
+
+    /**
+    The data of an ECS option (RFC 7871 section 6): the client subnet it speaks for, masked to
+    its source prefix, and its scope prefix, which is 0 in queries and, in responses, how much
+    of the subnet the answer depends on.
+    */
+    pub fn from_ecs(data: &[u8]) -> Option<(Subnet, u8)> {
+        let (family, source, scope) = (u16::from_be_bytes([*data.first()?, *data.get(1)?]), *data.get(2)?, *data.get(3)?);
+        let address = data.get(4..)?;
+        if address.len() != (source as usize).div_ceil(8) {
+            return None;
+        }
+        let addr = match family {
+            1 if source <= 32 => {
+                let mut octets = [0; 4];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::from(octets)
+            }
+            2 if source <= 128 => {
+                let mut octets = [0; 16];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::from(octets)
+            }
+            _ => return None,
+        };
+        Some((Subnet { addr, prefix: source }.network(), scope))
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Subnet, String> {
+        let (addr, prefix) = s.split_once('/').map(|(addr, prefix)| (addr, Some(prefix))).unwrap_or((s, None));
+        let addr: IpAddr = addr.trim().parse().map_err(|_| format!("Invalid address in {:?}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|&prefix| prefix <= max).ok_or_else(|| format!("Invalid prefix in {:?}", s))?,
+            None => max,
+        };
+        Ok(Subnet { addr, prefix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains() {
+        let net: Subnet = "192.168.1.0/24".parse().unwrap();
+        assert!(net.contains("192.168.1.77".parse().unwrap()));
+        assert!(net.contains("::ffff:192.168.1.77".parse().unwrap()));
+        assert!(!net.contains("192.168.2.1".parse().unwrap()));
+
+        let host: Subnet = "2001:db8::1".parse().unwrap();
+        assert_eq!(host.prefix, 128);
+        assert!(host.contains("2001:db8::1".parse().unwrap()));
+        assert!(!host.contains("2001:db8::2".parse().unwrap()));
+
+        let all: Subnet = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_from_ecs() {
+        assert_eq!(Subnet::from_ecs(&[0, 1, 24, 0, 198, 51, 100]), Some(("198.51.100.0/24".parse().unwrap(), 0)));
+        assert_eq!(Subnet::from_ecs(&[0, 2, 56, 48, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0xff]), Some(("2001:db8:0:ff00::/56".parse().unwrap(), 48)));
+        // The address must be as long as the source prefix, no more
+        assert_eq!(Subnet::from_ecs(&[0, 1, 24, 0, 198, 51, 100, 7]), None);
+        assert_eq!(Subnet::from_ecs(&[0, 1, 40, 0, 1, 2, 3, 4, 5]), None);
+        assert_eq!(Subnet::from_ecs(&[0, 1]), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("192.168.1.0/33".parse::<Subnet>().is_err());
+        assert!("example.com/24".parse::<Subnet>().is_err());
+    }
+}
@@ -0,0 +1,241 @@
+/*!
+Messages of any size. The packet buffer holds 512 bytes, but a message over TCP can have up to
+64 KiB, so the records of those are rewritten without compression and decoded one by one, and
+written one by one into buffers of their own.
+*/
+
+use std::io;
+
+use crate::utils::byte_buffer::ByteBuffer;
+use crate::utils::dns_class::DnsClass;
+use crate::utils::error::DnsError;
+use crate::utils::name::{push_label, to_wire};
+use crate::utils::packet::DnsPacket;
+use crate::utils::query_type::QueryType;
+use crate::utils::question::DnsQuestion;
+use crate::utils::record::DnsRecord;
+
+pub const HEADER_LEN: usize = 12;
+
+pub fn read_u16(message: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*message.get(pos)?, *message.get(pos + 1)?]))
+}
+
+// The name at `pos` with compression pointers followed, and the position right after it
+fn read_name(message: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+    loop {
+        let len = *message.get(pos)? as usize;
+        if len & 0xC0 == 0xC0 {
+            // Pointers only go backwards, a limit on their number keeps loops out
+            let target = (read_u16(message, pos)? & 0x3FFF) as usize;
+            jumps += 1;
+            if target >= pos || jumps > 64 {
+                return None;
+            }
+            end.get_or_insert(pos + 2);
+            pos = target;
+        } else if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(pos + 1)));
+        } else {
+            let mut label = String::new();
+            push_label(&mut label, &message.get(pos + 1..pos + 1 + len)?.to_ascii_lowercase());
+            labels.push(label);
+            pos += 1 + len;
+        }
+    }
+}
+
+/*
+The record at `pos` rewritten without compression, so it can be read on its own, and where the
+next record starts. Only the types RFC 3597 allows to be compressed have names in their data
+expanded, everything else is copied as it is.
+*/
+fn standalone_record(message: &[u8], pos: usize) -> Option<(Vec<u8>, usize)> {
+    let (owner, pos) = read_name(message, pos)?;
+    let rtype = read_u16(message, pos)?;
+    let start = pos + 10;
+    let end = start + read_u16(message, pos + 8)? as usize;
+    let raw = message.get(start..end)?;
+
+    let rdata = match QueryType::from_num(rtype) {
+        QueryType::NS | QueryType::CNAME | QueryType::PTR => to_wire(&read_name(message, start)?.0),
+        QueryType::MX => [&raw[..2.min(raw.len())], &to_wire(&read_name(message, start + 2)?.0)].concat(),
+        QueryType::SOA => {
+            let (mname, next) = read_name(message, start)?;
+            let (rname, next) = read_name(message, next)?;
+            [to_wire(&mname), to_wire(&rname), message.get(next..next + 20)?.to_vec()].concat()
+        }
+        _ => raw.to_vec(),
+    };
+    let mut record = to_wire(&owner);
+    record.extend_from_slice(message.get(pos..pos + 8)?);
+    record.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    record.extend_from_slice(&rdata);
+    Some((record, end))
+}
+
+pub fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Malformed message")
+}
+
+// The questions of a message, and where the answer section starts
+pub fn questions(message: &[u8]) -> io::Result<(Vec<DnsQuestion>, usize)> {
+    let mut questions = Vec::new();
+    let mut pos = HEADER_LEN;
+    for _ in 0..read_u16(message, 4).ok_or_else(invalid)? {
+        let (name, next) = read_name(message, pos).ok_or_else(invalid)?;
+        let (qtype, qclass) = read_u16(message, next).zip(read_u16(message, next + 2)).ok_or_else(invalid)?;
+        questions.push(DnsQuestion { name, qtype: QueryType::from_num(qtype), qclass: DnsClass::from_num(qclass) });
+        pos = next + 4;
+    }
+    Ok((questions, pos))
+}
+
+// The `count` records from `pos` on, each on its own without compression, and where the next section starts
+pub fn section(message: &[u8], mut pos: usize, count: u16) -> io::Result<(Vec<Vec<u8>>, usize)> {
+    let mut records = Vec::new();
+    for _ in 0..count {
+        let (record, next) = standalone_record(message, pos).ok_or_else(invalid)?;
+        pos = next;
+        records.push(record);
+    }
+    Ok((records, pos))
+}
+
+// A record `section` split off, None when it can't be represented, such as TXT data over 512 bytes
+pub fn decode(record: &[u8]) -> Option<DnsRecord> {
+    (record.len() <= 512).then(|| DnsRecord::read(&mut ByteBuffer::from_buffer(record)).ok()).flatten()
+}
+
+/*
+A whole message of any size as a packet, e.g. a large answer that came over TCP. Records that
+can't be represented are left out, and the packet may still be too large to write into the
+packet buffer.
+*/
+pub fn read_large_message(message: &[u8]) -> io::Result<DnsPacket> {
+    let mut packet = DnsPacket::new();
+    packet.header.read(&mut ByteBuffer::from_buffer(message.get(..HEADER_LEN).ok_or_else(invalid)?))?;
+    let (questions, pos) = questions(message)?;
+    let (answers, pos) = section(message, pos, packet.header.answers)?;
+    let (authorities, pos) = section(message, pos, packet.header.authoritative_entries)?;
+    let (resources, _) = section(message, pos, packet.header.resource_entries)?;
+    packet.questions = questions;
+    packet.answers = answers.iter().filter_map(|record| decode(record)).collect();
+    packet.authorities = authorities.iter().filter_map(|record| decode(record)).collect();
+    packet.resources = resources.iter().filter_map(|record| decode(record)).collect();
+    Ok(packet)
+}
+
+// What one part of a message, a header, question or record, comes to on its own
+fn part(write: impl FnOnce(&mut ByteBuffer) -> Result<(), DnsError>) -> io::Result<Vec<u8>> {
+    let mut buffer = ByteBuffer::new();
+    write(&mut buffer)?;
+    Ok(buffer.buffer[..buffer.position()].to_vec())
+}
+
+fn overflows(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| matches!(e.downcast_ref(), Some(DnsError::BufferOverflow)))
+}
+
+/*
+A packet as a UDP message. One that doesn't fit the packet buffer goes out as its header and
+questions with TC set, so the client asks again over TCP.
+*/
+pub fn write_udp_message(packet: &DnsPacket) -> io::Result<Vec<u8>> {
+    match part(|buffer| packet.write(buffer)) {
+        Err(e) if overflows(&e) => {}
+        written => return written,
+    }
+    let mut truncated = DnsPacket::new();
+    truncated.header = packet.header.clone();
+    truncated.header.truncated_message = true;
+    truncated.questions = packet.questions.clone();
+    part(|buffer| truncated.write(buffer))
+}
+
+/*
+A packet as a message of up to 64 KiB, for answers over TCP too large for the packet buffer.
+Nothing is compressed, so the header, questions and records are written each on their own and
+put together. Messages that fit the buffer come out as `DnsPacket::write` makes them.
+*/
+pub fn write_large_message(packet: &DnsPacket) -> io::Result<Vec<u8>> {
+    match part(|buffer| packet.write(buffer)) {
+        Err(e) if overflows(&e) => {}
+        written => return written,
+    }
+    let mut header = packet.header.clone();
+    header.questions = packet.questions.len() as u16;
+    header.answers = packet.answers.len() as u16;
+    header.authoritative_entries = packet.authorities.len() as u16;
+    header.resource_entries = packet.resources.len() as u16;
+    let mut message = part(|buffer| header.write(buffer))?;
+    for question in &packet.questions {
+        message.extend_from_slice(&part(|buffer| question.write(buffer))?);
+    }
+    // As in `DnsPacket::write`, the upper bits of an extended RCODE go in the OPT record
+    let extended = packet.header.rescode.extended_bits();
+    for record in packet.answers.iter().chain(&packet.authorities).chain(&packet.resources) {
+        let record = match record {
+            DnsRecord::OPT { udp_size, flags, options } => part(|buffer| DnsRecord::OPT {
+                udp_size: *udp_size,
+                flags: (flags & 0x00FF_FFFF) | (extended as u32) << 24,
+                options: options.clone(),
+            }.write(buffer))?,
+            record => part(|buffer| record.write(buffer))?,
+        };
+        message.extend_from_slice(&record);
+    }
+    if message.len() > u16::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Message longer than 65535 bytes"));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_write_large_message() {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 7;
+        packet.header.response = true;
+        packet.questions.push(DnsQuestion::new("many.example.com".to_string(), QueryType::A));
+        packet.answers = (0..60).map(|i| DnsRecord::a("many.example.com", Ipv4Addr::new(192, 0, 2, i), 300)).collect();
+        packet.resources.push(DnsRecord::OPT { udp_size: 1232, flags: 0, options: Vec::new() });
+
+        let message = write_large_message(&packet).unwrap();
+        assert!(message.len() > 512);
+        let read = read_large_message(&message).unwrap();
+        assert_eq!((read.header.id, read.header.answers), (7, 60));
+        assert_eq!((read.answers, read.resources), (packet.answers.clone(), packet.resources.clone()));
+
+        // Small ones are what the packet buffer holds, byte for byte
+        packet.answers.truncate(2);
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        assert_eq!(write_large_message(&packet).unwrap(), &buffer.buffer[..buffer.position()]);
+    }
+
+    #[test]
+    fn test_write_udp_message() {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 7;
+        packet.header.response = true;
+        packet.questions.push(DnsQuestion::new("many.example.com".to_string(), QueryType::A));
+        packet.answers = (0..2).map(|i| DnsRecord::a("many.example.com", Ipv4Addr::new(192, 0, 2, i), 300)).collect();
+        assert_eq!(write_udp_message(&packet).unwrap(), write_large_message(&packet).unwrap());
+
+        // Too large for a datagram, the client gets the question back with TC set
+        packet.answers = (0..60).map(|i| DnsRecord::a("many.example.com", Ipv4Addr::new(192, 0, 2, i), 300)).collect();
+        let read = DnsPacket::from_bytes(&write_udp_message(&packet).unwrap()).unwrap();
+        assert!(read.header.truncated_message);
+        assert_eq!(read.header.id, 7);
+        assert_eq!(read.questions, packet.questions);
+        assert!(read.answers.is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+use js_sys::{Promise, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, Response, Window, WorkerGlobalScope};
+
+use r_dns_core::byte_buffer::ByteBuffer;
+use r_dns_core::packet::DnsPacket;
+use r_dns_core::presentation::{fqdn, record_data};
+use r_dns_core::query_type::QueryType;
+use r_dns_core::question::DnsQuestion;
+use r_dns_core::record::DnsRecord;
+
+const MEDIA_TYPE: &str = "application/dns-message";
+
+/**
+A DNS message handed to JavaScript. `Message.query` builds one and `Message.decode` reads one off
+the wire. The header fields are getters and the sections come back as lines in zone file format,
+the way `/cache/dump` writes them.
+*/
+#[wasm_bindgen]
+pub struct Message {
+    packet: DnsPacket,
+}
+
+#[wasm_bindgen]
+impl Message {
+    // A recursive query with ID 0, as RFC 8484 asks of DoH clients so HTTP caches can share answers
+    pub fn query(name: &str, qtype: &str) -> Result<Message, JsError> {
+        let qtype = QueryType::from_name(qtype).ok_or_else(|| JsError::new(&format!("Unknown type {}", qtype)))?;
+        let mut packet = DnsPacket::new();
+        packet.header.recursion_desired = true;
+        packet.questions.push(DnsQuestion::new(name.trim_end_matches('.').to_ascii_lowercase(), qtype));
+        Ok(Message { packet })
+    }
+
+    pub fn decode(message: &[u8]) -> Result<Message, JsError> {
+        Ok(Message { packet: DnsPacket::from_bytes(message)? })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, JsError> {
+        let mut buffer = ByteBuffer::new();
+        self.packet.write(&mut buffer)?;
+        Ok(buffer.buffer[..buffer.position()].to_vec())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> u16 {
+        self.packet.header.id
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn rcode(&self) -> u16 {
+        self.packet.header.rescode.to_num()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn truncated(&self) -> bool {
+        self.packet.header.truncated_message
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn authentic(&self) -> bool {
+        self.packet.header.authed_data
+    }
+
+    pub fn questions(&self) -> Vec<String> {
+        self.packet.questions.iter().map(|question| format!(";{}\t{}\t{}", fqdn(&question.name), question.qclass.name(), question.qtype.name())).collect()
+    }
+
+    pub fn answers(&self) -> Vec<String> {
+        lines(&self.packet.answers)
+    }
+
+    pub fn authorities(&self) -> Vec<String> {
+        lines(&self.packet.authorities)
+    }
+
+    pub fn additionals(&self) -> Vec<String> {
+        lines(&self.packet.resources)
+    }
+}
+
+fn lines(records: &[DnsRecord]) -> Vec<String> {
+    records.iter().filter_map(|record| {
+        let data = record_data(record)?;
+        Some(format!("{}\t{}\t{}\t{}\t{}", fqdn(record.domain()), record.ttl(), record.class().name(), record.query_type().name(), data))
+    }).collect()
+}
+
+// Pages have a `window`, web and service workers only their own global scope
+fn fetch(request: &Request) -> Result<Promise, JsValue> {
+    let global = js_sys::global();
+    match global.dyn_ref::<Window>() {
+        Some(window) => Ok(window.fetch_with_request(request)),
+        None => Ok(global.dyn_into::<WorkerGlobalScope>()?.fetch_with_request(request)),
+    }
+}
+
+// Looks `name` up through the DoH server at `url` (RFC 8484), the query goes in the body of a POST
+#[wasm_bindgen]
+pub async fn resolve(url: String, name: String, qtype: String) -> Result<Message, JsValue> {
+    let query = Message::query(&name, &qtype)?.encode()?;
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_body(&Uint8Array::from(query.as_slice()));
+    let request = Request::new_with_str_and_init(&url, &init)?;
+    request.headers().set("Content-Type", MEDIA_TYPE)?;
+    request.headers().set("Accept", MEDIA_TYPE)?;
+
+    let response: Response = JsFuture::from(fetch(&request)?).await?.dyn_into()?;
+    if !response.ok() {
+        return Err(JsError::new(&format!("{} answered with HTTP {}", url, response.status())).into());
+    }
+    let body = JsFuture::from(response.array_buffer()?).await?;
+    Ok(Message::decode(&Uint8Array::new(&body).to_vec())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r_dns_core::dns_class::DnsClass;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_query_round_trip() {
+        let query = Message::query("Example.COM.", "aaaa").unwrap();
+        let decoded = Message::decode(&query.encode().unwrap()).unwrap();
+        assert_eq!(decoded.id(), 0);
+        assert!(decoded.packet.header.recursion_desired);
+        assert_eq!(decoded.questions(), vec![";example.com.\tIN\tAAAA"]);
+    }
+
+    #[test]
+    fn test_answer_lines() {
+        let mut packet = DnsPacket::new();
+        packet.header.response = true;
+        packet.answers.push(DnsRecord::A { domain: "example.com".to_string(), addr: Ipv4Addr::new(192, 0, 2, 1), ttl: 300, class: DnsClass::IN });
+        let mut buffer = ByteBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        let message = Message::decode(&buffer.buffer[..buffer.position()]).unwrap();
+        assert_eq!(message.rcode(), 0);
+        assert_eq!(message.answers(), vec!["example.com.\t300\tIN\tA\t192.0.2.1"]);
+        assert!(message.authorities().is_empty());
+    }
+}